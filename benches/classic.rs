@@ -0,0 +1,45 @@
+//! Classic BASIC benchmarks, run on both execution backends
+//!
+//! Each program in `benches/programs` is entered line by line and run from
+//! scratch in every iteration, so the timings cover tokenising, parsing and
+//! running. Run with `cargo bench`; `cargo bench -- sieve` runs one program.
+
+use bbc_basic_interpreter::config::{Backend, Config};
+use bbc_basic_interpreter::Interpreter;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::path::Path;
+
+/// The benchmark programs, in the order they are reported
+const PROGRAMS: &[&str] = &[
+    "pcw1", "pcw2", "pcw3", "pcw4", "pcw5", "pcw6", "pcw7", "pcw8", "sieve", "noise",
+];
+
+/// Enter and run a program
+fn run(source: &str, backend: Backend) {
+    let mut interpreter = Interpreter::with_config(Config {
+        backend,
+        deterministic: true,
+        ..Default::default()
+    });
+    for line in source.lines().filter(|line| !line.trim().is_empty()) {
+        interpreter.process_line(line).unwrap();
+    }
+    interpreter.run().unwrap();
+}
+
+fn classic(c: &mut Criterion) {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/programs");
+    for name in PROGRAMS {
+        let source = std::fs::read_to_string(directory.join(format!("{}.bas", name))).unwrap();
+        let mut group = c.benchmark_group(*name);
+        for (label, backend) in [("tree", Backend::Tree), ("bytecode", Backend::Bytecode)] {
+            group.bench_with_input(BenchmarkId::from_parameter(label), &source, |b, source| {
+                b.iter(|| run(source, backend))
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, classic);
+criterion_main!(benches);
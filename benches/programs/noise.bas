@@ -0,0 +1,7 @@
+10 REM Noise loop: random numbers as fast as BASIC can make them
+20 T=0
+30 FOR I%=1 TO 2000
+40 T=T+RND(6)
+50 NEXT I%
+60 PRINT T
+70 END
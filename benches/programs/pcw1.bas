@@ -0,0 +1,6 @@
+10 REM PCW Benchmark 1: an empty FOR loop
+100 PRINT "S"
+200 FOR K=1 TO 1000
+300 NEXT K
+400 PRINT "E";K
+500 END
@@ -0,0 +1,8 @@
+10 REM PCW Benchmark 2: a REPEAT loop counting to 1000
+100 PRINT "S"
+200 K=0
+250 REPEAT
+300 K=K+1
+400 UNTIL K>=1000
+500 PRINT "E";K
+600 END
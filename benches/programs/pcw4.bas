@@ -0,0 +1,9 @@
+10 REM PCW Benchmark 4: arithmetic on constants
+100 PRINT "S"
+200 K=0
+250 REPEAT
+300 K=K+1
+310 A=K/2*3+4-5
+400 UNTIL K>=1000
+500 PRINT "E";K
+600 END
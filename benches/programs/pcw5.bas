@@ -0,0 +1,11 @@
+10 REM PCW Benchmark 5: adds a subroutine call
+100 PRINT "S"
+200 K=0
+250 REPEAT
+300 K=K+1
+310 A=K/2*3+4-5
+320 GOSUB 820
+400 UNTIL K>=1000
+500 PRINT "E";K
+600 END
+820 RETURN
@@ -0,0 +1,14 @@
+10 REM PCW Benchmark 6: adds an inner FOR loop
+100 PRINT "S"
+200 K=0
+210 DIM M(6)
+250 REPEAT
+300 K=K+1
+310 A=K/2*3+4-5
+320 GOSUB 820
+330 FOR L=1 TO 5
+340 NEXT L
+400 UNTIL K>=1000
+500 PRINT "E";K
+600 END
+820 RETURN
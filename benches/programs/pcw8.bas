@@ -0,0 +1,11 @@
+10 REM PCW Benchmark 8: power, logarithm and sine
+100 PRINT "S"
+200 K=0
+250 REPEAT
+300 K=K+1
+330 A=K^2
+340 B=LOG(K)
+350 C=SIN(K)
+400 UNTIL K>=1000
+500 PRINT "E";K
+600 END
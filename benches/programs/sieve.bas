@@ -0,0 +1,20 @@
+10 REM BYTE Sieve of Eratosthenes (1981): the odd primes below 8194
+20 S%=4095
+30 DIM F%(S%+1)
+40 C%=0
+50 FOR I%=0 TO S%
+60 F%(I%)=1
+70 NEXT I%
+80 FOR I%=0 TO S%
+90 IF F%(I%) THEN
+100 P%=I%+I%+3
+110 K%=I%+P%
+120 WHILE K%<=S%
+130 F%(K%)=0
+140 K%=K%+P%
+150 ENDWHILE
+160 C%=C%+1
+170 ENDIF
+180 NEXT I%
+190 PRINT C%;" primes"
+200 END
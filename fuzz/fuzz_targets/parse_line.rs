@@ -0,0 +1,45 @@
+//! Fuzz the tokenizer and parser with arbitrary lines
+//!
+//! Run with `cargo +nightly fuzz run parse_line` from the repository root.
+//! Any panic, stack overflow or hang is a bug: malformed lines must come
+//! back as errors.
+
+#![no_main]
+
+use bbc_basic_interpreter::parser::{
+    parse_expression, parse_statement_with_dialect, unparse, Dialect,
+};
+use bbc_basic_interpreter::tokenizer::{
+    classify, classify_with_options, detokenize, detokenize_compact, tokenize,
+    tokenize_with_options, KeywordCase, TokenizerOptions,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    // Uppercase keywords only, as the BBC Micro reads them
+    let strict = TokenizerOptions {
+        case_insensitive_keywords: false,
+        keyword_case: KeywordCase::Lower,
+    };
+    classify(text);
+    classify_with_options(text, &strict);
+    let _ = tokenize_with_options(text, &strict);
+
+    let Ok(line) = tokenize(text) else {
+        return;
+    };
+    let _ = detokenize(&line);
+    let _ = detokenize_compact(&line, &strict);
+    let _ = parse_expression(&line.tokens);
+    for dialect in [Dialect::BasicII, Dialect::BasicV] {
+        if let Ok(statement) = parse_statement_with_dialect(&line, dialect) {
+            // What was parsed must read back in without panicking too
+            if let Ok(again) = tokenize(&unparse(&statement)) {
+                let _ = parse_statement_with_dialect(&again, dialect);
+            }
+        }
+    }
+});
@@ -0,0 +1,368 @@
+//! Warnings about a program's flow (CHECK)
+//!
+//! Finds lines that can never run, loops with nothing in them, loop ends
+//! with no loop open before them in the listing, and GOTO patterns that
+//! *STRUCTURE would rewrite. None of these stop a program running, so they
+//! are warnings: CHECK lists them, and the language server shows them
+//! alongside its errors.
+
+use crate::parser::{Dialect, Statement};
+use crate::program::ProgramStore;
+use crate::structure::{structure_program, Rewrite};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Something in a program that is probably not what was meant
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// Lines nothing jumps to, after a line that does not run on into them
+    Unreachable { start: u16, end: u16 },
+    /// A FOR, REPEAT or WHILE closed on the very next line
+    EmptyLoop {
+        line_number: u16,
+        keyword: &'static str,
+    },
+    /// A NEXT, UNTIL or ENDWHILE with no loop open before it in the listing
+    Unopened {
+        line_number: u16,
+        keyword: &'static str,
+        opener: &'static str,
+    },
+    /// A GOTO or GOSUB pattern *STRUCTURE would rewrite
+    Structurable(Rewrite),
+}
+
+impl Warning {
+    /// The first and last lines the warning is about
+    pub fn lines(&self) -> (u16, u16) {
+        match self {
+            Warning::Unreachable { start, end } => (*start, *end),
+            Warning::EmptyLoop { line_number, .. } | Warning::Unopened { line_number, .. } => {
+                (*line_number, *line_number)
+            }
+            Warning::Structurable(
+                Rewrite::RepeatUntil { start, end }
+                | Rewrite::WhileEndWhile { start, end }
+                | Rewrite::Procedure { start, end, .. },
+            ) => (*start, *end),
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::Unreachable { start, end } if start == end => {
+                write!(f, "Line {}: never runs", start)
+            }
+            Warning::Unreachable { start, end } => {
+                write!(f, "Lines {}-{}: never run", start, end)
+            }
+            Warning::EmptyLoop {
+                line_number,
+                keyword,
+            } => write!(f, "Line {}: empty {} loop", line_number, keyword),
+            Warning::Unopened {
+                line_number,
+                keyword,
+                opener,
+            } => write!(f, "Line {}: {} without {}", line_number, keyword, opener),
+            Warning::Structurable(rewrite) => write!(f, "{} (*STRUCTURE)", rewrite),
+        }
+    }
+}
+
+/// Check a program, returning its warnings in line order
+///
+/// A line that does not parse is taken to run on and to be jumped to, so
+/// it hides nothing after it.
+pub fn check_program(program: &ProgramStore, dialect: Dialect) -> Vec<Warning> {
+    let lines: Vec<(u16, Option<Statement>)> = program
+        .statements(dialect)
+        .map(|(line_number, statement)| (line_number, statement.ok()))
+        .collect();
+
+    let mut warnings = unreachable(&lines);
+    warnings.extend(loops(&lines));
+    if let Ok((_, rewrites)) = structure_program(program, dialect) {
+        warnings.extend(rewrites.into_iter().map(Warning::Structurable));
+    }
+    warnings.sort_by_key(Warning::lines);
+    warnings
+}
+
+/// Runs of lines that can never run
+///
+/// Flow starts at the first line and at each DEF PROC and DEF FN, and
+/// follows every jump and every line that runs on into the next.
+fn unreachable(lines: &[(u16, Option<Statement>)]) -> Vec<Warning> {
+    let index: HashMap<u16, usize> = lines
+        .iter()
+        .enumerate()
+        .map(|(i, (line_number, _))| (*line_number, i))
+        .collect();
+    let blocks = match_blocks(lines);
+
+    let mut reached = vec![false; lines.len()];
+    let mut pending: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(i, (_, statement))| {
+            *i == 0
+                || matches!(
+                    statement,
+                    None | Some(Statement::DefProc { .. } | Statement::DefFn { .. })
+                )
+        })
+        .map(|(i, _)| i)
+        .collect();
+    while let Some(i) = pending.pop() {
+        if i >= lines.len() || reached[i] {
+            continue;
+        }
+        reached[i] = true;
+        let Some(statement) = &lines[i].1 else {
+            pending.push(i + 1);
+            continue;
+        };
+        if !matches!(statement, Statement::Restore { .. }) {
+            pending.extend(statement.jump_targets().iter().filter_map(|t| index.get(t)));
+        }
+        match (statement, blocks.get(&i)) {
+            // The ELSE part, or what follows the loop, when the condition fails
+            (Statement::IfBlock { .. } | Statement::While { .. }, Some(&end)) => {
+                pending.extend([i + 1, end + 1])
+            }
+            // The end of the THEN part goes on after the ENDIF
+            (Statement::Else, Some(&end)) => pending.push(end),
+            _ if runs_on(statement) => pending.push(i + 1),
+            _ => {}
+        }
+    }
+
+    let mut warnings: Vec<Warning> = Vec::new();
+    let mut previous = None;
+    for (i, (line_number, statement)) in lines.iter().enumerate() {
+        // DATA and REM after END are usual
+        let quiet = matches!(
+            statement,
+            Some(Statement::Data { .. } | Statement::Rem { .. } | Statement::Empty)
+        );
+        if reached[i] || quiet {
+            continue;
+        }
+        match warnings.last_mut() {
+            Some(Warning::Unreachable { end, .. }) if previous == Some(i - 1) => {
+                *end = *line_number
+            }
+            _ => warnings.push(Warning::Unreachable {
+                start: *line_number,
+                end: *line_number,
+            }),
+        }
+        previous = Some(i);
+    }
+    warnings
+}
+
+/// Whether a statement can go on to the next line
+fn runs_on(statement: &Statement) -> bool {
+    match statement {
+        Statement::Goto { .. }
+        | Statement::Return { .. }
+        | Statement::End
+        | Statement::Stop
+        | Statement::Quit
+        | Statement::EndProc
+        | Statement::Chain { .. } => false,
+        Statement::If {
+            then_part,
+            else_part: Some(else_part),
+            ..
+        } => {
+            !(then_part.last().is_some_and(|last| !runs_on(last))
+                && else_part.last().is_some_and(|last| !runs_on(last)))
+        }
+        _ => true,
+    }
+}
+
+/// Where the condition of each block IF and WHILE sends control when it
+/// fails, and where each ELSE goes on from, by index: the ELSE or ENDIF
+/// for an IF (whose next line is the one after), the ENDWHILE for a WHILE,
+/// and the ENDIF for an ELSE
+fn match_blocks(lines: &[(u16, Option<Statement>)]) -> HashMap<usize, usize> {
+    let mut blocks = HashMap::new();
+    let mut ifs: Vec<(usize, Option<usize>)> = Vec::new();
+    let mut whiles = Vec::new();
+    for (i, (_, statement)) in lines.iter().enumerate() {
+        match statement {
+            Some(Statement::IfBlock { .. }) => ifs.push((i, None)),
+            Some(Statement::Else) => {
+                if let Some((_, otherwise)) = ifs.last_mut() {
+                    *otherwise = Some(i);
+                }
+            }
+            Some(Statement::EndIf) => {
+                if let Some((start, otherwise)) = ifs.pop() {
+                    match otherwise {
+                        Some(otherwise) => {
+                            blocks.insert(start, otherwise);
+                            blocks.insert(otherwise, i);
+                        }
+                        // Without an ELSE the line after ENDIF is reached
+                        // through the ENDIF itself
+                        None => {
+                            blocks.insert(start, i - 1);
+                        }
+                    }
+                }
+            }
+            Some(Statement::While { .. }) => whiles.push(i),
+            Some(Statement::EndWhile) => {
+                if let Some(start) = whiles.pop() {
+                    blocks.insert(start, i);
+                }
+            }
+            _ => {}
+        }
+    }
+    blocks
+}
+
+/// Empty loops, and loop ends with no loop open before them in the listing
+fn loops(lines: &[(u16, Option<Statement>)]) -> Vec<Warning> {
+    const KINDS: [(&str, &str); 3] = [("FOR", "NEXT"), ("REPEAT", "UNTIL"), ("WHILE", "ENDWHILE")];
+    let kind = |statement: &Option<Statement>| match statement {
+        Some(Statement::For { .. }) => Some((0, true)),
+        Some(Statement::Next { .. }) => Some((0, false)),
+        Some(Statement::Repeat) => Some((1, true)),
+        Some(Statement::Until { .. }) => Some((1, false)),
+        Some(Statement::While { .. }) => Some((2, true)),
+        Some(Statement::EndWhile) => Some((2, false)),
+        _ => None,
+    };
+
+    let mut warnings = Vec::new();
+    let mut open = [0usize; 3];
+    for (i, (line_number, statement)) in lines.iter().enumerate() {
+        let Some((loop_kind, opens)) = kind(statement) else {
+            continue;
+        };
+        let (opener, keyword) = KINDS[loop_kind];
+        if opens {
+            open[loop_kind] += 1;
+            if lines.get(i + 1).and_then(|(_, next)| kind(next)) == Some((loop_kind, false)) {
+                warnings.push(Warning::EmptyLoop {
+                    line_number: *line_number,
+                    keyword: opener,
+                });
+            }
+            continue;
+        }
+        // NEXT J%, I% closes two loops
+        let closes = match statement {
+            Some(Statement::Next { variables }) => variables.len().max(1),
+            _ => 1,
+        };
+        if open[loop_kind] == 0 {
+            warnings.push(Warning::Unopened {
+                line_number: *line_number,
+                keyword,
+                opener,
+            });
+        }
+        open[loop_kind] = open[loop_kind].saturating_sub(closes);
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(lines: &[&str]) -> Vec<String> {
+        let program = ProgramStore::from_source(&lines.join("\n")).unwrap();
+        check_program(&program, Dialect::BasicV)
+            .iter()
+            .map(Warning::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_unreachable_lines() {
+        let warnings = check(&[
+            "10 GOSUB 100",
+            "20 PROCgreet",
+            "30 END",
+            "40 PRINT \"LOST\"",
+            "50 X = 1",
+            "60 DATA 1, 2",
+            "70 PRINT \"LOST TOO\"",
+            "100 PRINT \"SUB\"",
+            "110 RETURN",
+            "120 PRINT \"AFTER RETURN\"",
+            "200 DEF PROCgreet",
+            "210 IF X THEN",
+            "220 PRINT \"YES\"",
+            "230 ELSE",
+            "240 PRINT \"NO\"",
+            "250 ENDIF",
+            "260 ENDPROC",
+        ]);
+        assert_eq!(
+            warnings,
+            [
+                "Lines 40-50: never run",
+                "Line 70: never runs",
+                "Line 120: never runs"
+            ]
+        );
+
+        // Lines reached by a jump are fine, as is what follows a WHILE
+        let warnings = check(&[
+            "10 ON ERROR GOTO 100",
+            "20 WHILE FALSE",
+            "30 PRINT",
+            "40 ENDWHILE",
+            "50 IF X THEN GOTO 70 ELSE GOTO 80",
+            "60 PRINT \"LOST\"",
+            "70 END",
+            "80 END",
+            "100 REPORT",
+        ]);
+        assert_eq!(warnings, ["Line 60: never runs"]);
+    }
+
+    #[test]
+    fn test_loop_warnings() {
+        let warnings = check(&[
+            "10 NEXT",
+            "20 FOR I% = 1 TO 10",
+            "30 NEXT I%",
+            "40 REPEAT",
+            "50 PRINT 1",
+            "60 UNTIL TRUE",
+            "70 UNTIL TRUE",
+            "80 ENDWHILE",
+        ]);
+        assert_eq!(
+            warnings,
+            [
+                "Line 10: NEXT without FOR",
+                "Line 20: empty FOR loop",
+                "Line 70: UNTIL without REPEAT",
+                "Line 80: ENDWHILE without WHILE"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_structurable_goto() {
+        let warnings = check(&["10 I% = 0", "20 I% = I% + 1", "30 IF I% < 10 THEN GOTO 20"]);
+        assert_eq!(
+            warnings,
+            ["Lines 20-30: GOTO loop -> REPEAT...UNTIL (*STRUCTURE)"]
+        );
+    }
+}
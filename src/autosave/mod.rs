@@ -0,0 +1,255 @@
+//! Autosave and crash recovery for the program buffer
+//!
+//! While the REPL is in use the typed-in program, and optionally the
+//! variables, are written to a recovery file every so often. The file is
+//! removed when the interpreter exits cleanly, so finding one at startup
+//! means the last session ended abnormally and its work can be restored.
+
+use crate::config::Config;
+use crate::interpreter::Interpreter;
+use crate::tokenizer::tokenize_with_options;
+use crate::variables::Variable;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Name of the recovery file
+pub const RECOVERY_FILE_NAME: &str = "bbcbasic-recovery.toml";
+
+/// Saved state of the program buffer
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Snapshot {
+    /// Program listing, one numbered line per entry
+    pub program: Vec<String>,
+    /// Variables and arrays by name (empty unless variables are saved)
+    pub variables: BTreeMap<String, Variable>,
+}
+
+impl Snapshot {
+    /// Take a snapshot of the interpreter's program, and its variables if
+    /// `include_variables` is set
+    pub fn capture(interpreter: &Interpreter, include_variables: bool) -> Self {
+        let variables = if include_variables {
+            interpreter
+                .executor()
+                .variables()
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect()
+        } else {
+            BTreeMap::new()
+        };
+        Self {
+            program: interpreter.list(),
+            variables,
+        }
+    }
+
+    /// Whether there is nothing worth recovering
+    pub fn is_empty(&self) -> bool {
+        self.program.is_empty() && self.variables.is_empty()
+    }
+
+    /// Replace the interpreter's program with the snapshot's, and set the
+    /// saved variables
+    pub fn restore(&self, interpreter: &mut Interpreter) -> Result<(), String> {
+        let options = interpreter.config().tokenizer_options();
+        let program = interpreter.program_mut();
+        program.begin_edit();
+        program.clear();
+        let restored = self.program.iter().try_for_each(|line| {
+            let tokenized = tokenize_with_options(line, &options)
+                .map_err(|e| format!("Cannot restore line {}: {:?}", line, e))?;
+            program.store_line(tokenized);
+            Ok::<_, String>(())
+        });
+        program.end_edit();
+        restored?;
+
+        let variables = interpreter.executor_mut().variables_mut();
+        for (name, value) in &self.variables {
+            variables.insert_variable(name.clone(), value.clone());
+        }
+        Ok(())
+    }
+
+    /// Serialise the snapshot as TOML text
+    pub fn to_toml(&self) -> Result<String, String> {
+        toml::to_string(self).map_err(|e| e.to_string())
+    }
+
+    /// Parse a snapshot from TOML text
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+}
+
+/// Periodic autosave of the program buffer to a recovery file
+#[derive(Debug)]
+pub struct Autosave {
+    path: PathBuf,
+    interval: Duration,
+    include_variables: bool,
+    /// When the buffer was last checked for changes
+    last_check: Instant,
+    /// What the recovery file holds now (None = no file written yet)
+    saved: Option<Snapshot>,
+}
+
+impl Autosave {
+    /// Autosave to `path` at most once per `interval`
+    pub fn new(path: PathBuf, interval: Duration, include_variables: bool) -> Self {
+        Self {
+            path,
+            interval,
+            include_variables,
+            last_check: Instant::now(),
+            saved: None,
+        }
+    }
+
+    /// Autosave as configured, or None if autosave is turned off
+    pub fn from_config(config: &Config) -> Option<Self> {
+        (config.autosave > 0).then(|| {
+            Self::new(
+                default_recovery_path(),
+                Duration::from_secs(config.autosave.into()),
+                config.autosave_variables,
+            )
+        })
+    }
+
+    /// The recovery file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Snapshot left behind by a session that did not exit cleanly
+    pub fn recover(&self) -> Result<Option<Snapshot>, String> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let text = std::fs::read_to_string(&self.path)
+            .map_err(|e| format!("Cannot read {}: {}", self.path.display(), e))?;
+        let snapshot =
+            Snapshot::from_toml(&text).map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        Ok((!snapshot.is_empty()).then_some(snapshot))
+    }
+
+    /// Save if the interval has passed and the buffer has changed since the
+    /// last save, returning whether the recovery file was written
+    pub fn tick(&mut self, interpreter: &Interpreter) -> Result<bool, String> {
+        if self.last_check.elapsed() < self.interval {
+            return Ok(false);
+        }
+        self.save(interpreter)
+    }
+
+    /// Save now if the buffer has changed since the last save, returning
+    /// whether the recovery file was written
+    pub fn save(&mut self, interpreter: &Interpreter) -> Result<bool, String> {
+        self.last_check = Instant::now();
+        let snapshot = Snapshot::capture(interpreter, self.include_variables);
+        if self.saved.as_ref() == Some(&snapshot) {
+            return Ok(false);
+        }
+
+        if snapshot.is_empty() {
+            // Nothing to recover, e.g. after NEW
+            self.remove()?;
+        } else {
+            if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| format!("Cannot create {}: {}", parent.display(), e))?;
+            }
+            // Write a new file and rename it over the old one, so a crash
+            // part way through leaves the previous snapshot intact
+            let partial = self.path.with_extension("tmp");
+            std::fs::write(&partial, snapshot.to_toml()?)
+                .and_then(|()| std::fs::rename(&partial, &self.path))
+                .map_err(|e| format!("Cannot write {}: {}", self.path.display(), e))?;
+        }
+        self.saved = Some(snapshot);
+        Ok(true)
+    }
+
+    /// Remove the recovery file on a clean exit
+    pub fn finish(mut self) -> Result<(), String> {
+        self.remove()
+    }
+
+    fn remove(&mut self) -> Result<(), String> {
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(format!("Cannot remove {}: {}", self.path.display(), e))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Where the recovery file is kept: ~/.config/bbc-basic, or the current
+/// directory if there is no home directory
+pub fn default_recovery_path() -> PathBuf {
+    match std::env::var_os("HOME") {
+        Some(home) => Path::new(&home)
+            .join(".config")
+            .join("bbc-basic")
+            .join(RECOVERY_FILE_NAME),
+        None => PathBuf::from(RECOVERY_FILE_NAME),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut interpreter = Interpreter::new();
+        interpreter.process_line("10 PRINT \"HELLO\"").unwrap();
+        interpreter.process_line("20 GOTO 10").unwrap();
+        interpreter.process_line("A% = 42").unwrap();
+        interpreter.process_line("DIM B(2)").unwrap();
+
+        let snapshot = Snapshot::capture(&interpreter, true);
+        let text = snapshot.to_toml().unwrap();
+        assert_eq!(Snapshot::from_toml(&text).unwrap(), snapshot);
+
+        let mut restored = Interpreter::new();
+        snapshot.restore(&mut restored).unwrap();
+        assert_eq!(restored.list(), interpreter.list());
+        let variables = restored.executor().variables();
+        assert_eq!(variables.get_integer_var("A%"), Some(42));
+        assert!(variables.get_variable("B").is_some_and(Variable::is_array));
+
+        assert!(Snapshot::capture(&interpreter, false).variables.is_empty());
+    }
+
+    #[test]
+    fn test_autosave_and_recover() {
+        let path = std::env::temp_dir().join("bbc_basic_autosave_test.toml");
+        let _ = std::fs::remove_file(&path);
+        let mut interpreter = Interpreter::new();
+        let mut autosave = Autosave::new(path.clone(), Duration::from_secs(3600), false);
+
+        // Nothing is written until there is a program, or before the interval
+        assert!(autosave.save(&interpreter).unwrap());
+        assert!(!path.exists());
+        interpreter.process_line("10 PRINT 1").unwrap();
+        assert!(!autosave.tick(&interpreter).unwrap());
+        assert!(autosave.save(&interpreter).unwrap());
+        assert!(!autosave.save(&interpreter).unwrap());
+
+        // A new session finds the snapshot
+        let next = Autosave::new(path.clone(), Duration::from_secs(3600), false);
+        let snapshot = next.recover().unwrap().unwrap();
+        assert_eq!(snapshot.program, vec!["10 PRINT 1"]);
+
+        // A clean exit leaves nothing to recover
+        autosave.finish().unwrap();
+        assert_eq!(next.recover().unwrap(), None);
+    }
+}
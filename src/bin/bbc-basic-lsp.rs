@@ -0,0 +1,242 @@
+//! bbc-basic-lsp: a Language Server Protocol server for BBC BASIC
+//!
+//! Speaks JSON-RPC over standard input and output, as editors expect, and
+//! answers with the analysis in `bbc_basic_interpreter::lsp`: diagnostics
+//! when a document is opened or changed, go-to-definition for PROC, FN and
+//! line numbers, and completion of keywords and the program's names.
+//!
+//! Build with `cargo build --features lsp --bin bbc-basic-lsp`.
+
+use bbc_basic_interpreter::config::Config;
+use bbc_basic_interpreter::lsp::{CompletionKind, Document, Position, Range, Severity};
+use bbc_basic_interpreter::parser::Dialect;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+/// JSON-RPC error code for a method the server does not implement
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Open documents by URI
+struct Server {
+    documents: HashMap<String, Document>,
+    dialect: Dialect,
+    shutdown: bool,
+}
+
+impl Server {
+    /// Handle a message, returning the messages to send back
+    fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let id = message.get("id").cloned();
+
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    // Whole documents are sent on every change
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "completionProvider": {},
+                },
+                "serverInfo": {
+                    "name": "bbc-basic-lsp",
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+            }),
+            "textDocument/didOpen" => {
+                let document = &params["textDocument"];
+                return self.update(document["uri"].as_str(), document["text"].as_str());
+            }
+            "textDocument/didChange" => {
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str());
+                return self.update(params["textDocument"]["uri"].as_str(), text);
+            }
+            "textDocument/didClose" => {
+                if let Some(uri) = params["textDocument"]["uri"].as_str() {
+                    self.documents.remove(uri);
+                }
+                return Vec::new();
+            }
+            "textDocument/definition" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                let position = position(&params["position"]);
+                match (self.documents.get(uri), position) {
+                    (Some(document), Some(position)) => document.definition(position).map_or(
+                        Value::Null,
+                        |range| json!({ "uri": uri, "range": range_json(range) }),
+                    ),
+                    _ => Value::Null,
+                }
+            }
+            "textDocument/completion" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                let items: Vec<Value> = self
+                    .documents
+                    .get(uri)
+                    .map(Document::completions)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|completion| {
+                        json!({
+                            "label": completion.label,
+                            "kind": completion_kind(completion.kind),
+                        })
+                    })
+                    .collect();
+                json!(items)
+            }
+            "shutdown" => {
+                self.shutdown = true;
+                Value::Null
+            }
+            _ => {
+                // Unknown notifications are ignored; unknown requests get an
+                // error
+                return id
+                    .map(|id| {
+                        json!({
+                            "jsonrpc": "2.0",
+                            "id": id,
+                            "error": {
+                                "code": METHOD_NOT_FOUND,
+                                "message": format!("Unknown method {}", method),
+                            },
+                        })
+                    })
+                    .into_iter()
+                    .collect();
+            }
+        };
+        id.map(|id| json!({ "jsonrpc": "2.0", "id": id, "result": result }))
+            .into_iter()
+            .collect()
+    }
+
+    /// Analyse a document's new text and publish its diagnostics
+    fn update(&mut self, uri: Option<&str>, text: Option<&str>) -> Vec<Value> {
+        let (Some(uri), Some(text)) = (uri, text) else {
+            return Vec::new();
+        };
+        let document = Document::new(text, self.dialect);
+        let diagnostics: Vec<Value> = document
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| {
+                json!({
+                    "range": range_json(diagnostic.range),
+                    "severity": match diagnostic.severity {
+                        Severity::Error => 1,
+                        Severity::Warning => 2,
+                    },
+                    "source": "bbc-basic",
+                    "message": diagnostic.message,
+                })
+            })
+            .collect();
+        self.documents.insert(uri.to_string(), document);
+        vec![json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        })]
+    }
+}
+
+fn position(value: &Value) -> Option<Position> {
+    Some(Position {
+        line: value["line"].as_u64()? as u32,
+        character: value["character"].as_u64()? as u32,
+    })
+}
+
+fn range_json(range: Range) -> Value {
+    json!({
+        "start": { "line": range.start.line, "character": range.start.character },
+        "end": { "line": range.end.line, "character": range.end.character },
+    })
+}
+
+/// The protocol's CompletionItemKind numbers
+fn completion_kind(kind: CompletionKind) -> u8 {
+    match kind {
+        CompletionKind::Function | CompletionKind::Procedure => 3,
+        CompletionKind::Variable => 6,
+        CompletionKind::Keyword => 14,
+    }
+}
+
+/// Read one message, framed by a Content-Length header (None at the end of
+/// input)
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let Some(length) = length else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Message has no Content-Length",
+        ));
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn main() {
+    let mut server = Server {
+        documents: HashMap::new(),
+        // The dialect comes from the same configuration file as the REPL's
+        dialect: Config::default_path()
+            .and_then(|path| Config::load(path).ok())
+            .map(|config| config.dialect)
+            .unwrap_or_default(),
+        shutdown: false,
+    };
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut output = io::stdout().lock();
+    loop {
+        let message = match read_message(&mut input) {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("bbc-basic-lsp: {}", e);
+                continue;
+            }
+        };
+        if message["method"] == "exit" {
+            std::process::exit(if server.shutdown { 0 } else { 1 });
+        }
+        for reply in server.handle(&message) {
+            if let Err(e) = write_message(&mut output, &reply) {
+                eprintln!("bbc-basic-lsp: {}", e);
+                return;
+            }
+        }
+    }
+}
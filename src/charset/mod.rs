@@ -0,0 +1,105 @@
+//! BBC character set
+//!
+//! BBC BASIC strings are strings of bytes in the BBC Micro's 8-bit character
+//! set. The interpreter keeps them in Rust strings with one char per byte
+//! (U+0000 to U+00FF), so LEN, MID$, ASC, CHR$ and INSTR count bytes as the
+//! original does. The functions here convert at the edges: text typed at the
+//! keyboard or read from host files comes in through `from_unicode` or
+//! `from_bytes`, and output goes back through `to_unicode` or `to_bytes`.
+//!
+//! Character 96 (0x60) is the pound sign. Codes 128 to 255 are shown as they
+//! appear in MODE 7: teletext control codes as spaces, the block graphics
+//! ranges as sextant mosaics and the rest as the matching letters.
+
+/// Character code of the pound sign
+pub const POUND: u8 = 0x60;
+
+/// Byte for a Unicode character: '£' is the pound sign, and anything outside
+/// the 8-bit set becomes '?'
+pub fn byte(c: char) -> u8 {
+    match c {
+        '£' => POUND,
+        _ => u8::try_from(c as u32).unwrap_or(b'?'),
+    }
+}
+
+/// Convert Unicode text (typed or read from the host) to a BBC string
+pub fn from_unicode(text: &str) -> String {
+    text.chars().map(|c| char::from(byte(c))).collect()
+}
+
+/// Convert host file bytes to a BBC string
+pub fn from_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| char::from(b)).collect()
+}
+
+/// Convert a BBC string to bytes for a host file
+pub fn to_bytes(text: &str) -> Vec<u8> {
+    text.chars().map(code).collect()
+}
+
+/// Character code of a char in a BBC string
+pub fn code(c: char) -> u8 {
+    u8::try_from(c as u32).unwrap_or(b'?')
+}
+
+/// Unicode character for displaying a BBC character code
+pub fn display_char(code: u8) -> char {
+    match code {
+        POUND => '£',
+        0..=127 => char::from(code),
+        // Teletext control codes show as spaces
+        0x80..=0x9F => ' ',
+        // Block graphics: bits 0-4 and 6 light the six cells of a 2x3 mosaic
+        0xA0..=0xBF | 0xE0..=0xFF => sextant((code & 0x1F) | ((code & 0x40) >> 1)),
+        // Capitals and symbols blast through in graphics mode
+        _ => display_char(code & 0x7F),
+    }
+}
+
+/// Convert a BBC string to Unicode for display
+pub fn to_unicode(text: &str) -> String {
+    text.chars().map(|c| display_char(code(c))).collect()
+}
+
+/// Unicode block sextant with the given cells lit (bit 0 top left to bit 5
+/// bottom right)
+fn sextant(cells: u8) -> char {
+    let code = match cells {
+        0 => return ' ',
+        21 => return '\u{258C}',
+        42 => return '\u{2590}',
+        63 => return '\u{2588}',
+        // The sextant block leaves out the two half blocks above
+        1..=20 => 0x1FB00 + cells as u32 - 1,
+        22..=41 => 0x1FB00 + cells as u32 - 2,
+        _ => 0x1FB00 + cells as u32 - 3,
+    };
+    char::from_u32(code).unwrap_or('?')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pound_sign() {
+        let text = from_unicode("£5 ✓");
+        assert_eq!(text, "`5 ?");
+        assert_eq!(code('\u{A3}'), 0xA3);
+        assert_eq!(to_bytes(&text), vec![0x60, b'5', b' ', b'?']);
+        assert_eq!(to_unicode(&text), "£5 ?");
+    }
+
+    #[test]
+    fn test_high_codes() {
+        assert_eq!(from_bytes(&[0xC1, 0x81]).chars().count(), 2);
+        assert_eq!(display_char(0xC1), 'A');
+        assert_eq!(display_char(0x81), ' ');
+        assert_eq!(display_char(0xA0), ' ');
+        assert_eq!(display_char(0xFF), '\u{2588}');
+        assert_eq!(display_char(0xA1), '\u{1FB00}');
+        assert_eq!(display_char(0xB5), '\u{258C}');
+        assert_eq!(display_char(0xFE), '\u{1FB3B}');
+    }
+}
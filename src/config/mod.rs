@@ -0,0 +1,948 @@
+//! Interpreter configuration for BBC BASIC
+//!
+//! Options are read from a TOML file at startup and can be changed for the
+//! current session with *CONFIGURE, in the spirit of the Master's CMOS settings.
+
+use crate::filesystem::FilenameTranslator;
+use crate::keymap::KeyMap;
+use crate::numbering::Numbering;
+use crate::parser::Dialect;
+use crate::tokenizer::{KeywordCase, TokenizerOptions};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Name of the configuration file looked for in the current directory
+pub const CONFIG_FILE_NAME: &str = "bbcbasic.toml";
+/// Environment variable that overrides the configuration file location
+pub const CONFIG_ENV_VAR: &str = "BBC_BASIC_CONFIG";
+
+/// Interpreter configuration
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Screen MODE selected at startup (0-7)
+    pub mode: u8,
+    /// BASIC dialect: basic2 (Model B, strict) or basic5 (with extensions)
+    pub dialect: Dialect,
+    /// Accept keywords typed in lowercase
+    pub case_insensitive_keywords: bool,
+    /// Case of keywords in LIST output
+    pub list_case: KeywordCase,
+    /// First line number given to source files loaded without line numbers
+    pub number_start: u16,
+    /// Gap between the line numbers given to unnumbered source files
+    pub number_step: u16,
+    /// Directory that program and data files are read from and written to
+    pub filesystem_root: Option<PathBuf>,
+    /// Maximum statements executed per second when running (0 = unthrottled)
+    pub speed: u32,
+    /// Frames a second that WAIT and *FX 19 wait for (50 = a PAL BBC Micro)
+    pub refresh_rate: u32,
+    /// How RUN executes the program
+    pub backend: Backend,
+    /// How reals are held: as doubles, or as the Model B's five byte floats
+    pub floats: FloatFormat,
+    /// When INPUT prints its "?" prompt
+    pub prompts: Prompts,
+    /// Read cassettes at real 1200 baud speed rather than instantly
+    pub tape_realtime: bool,
+    /// Load from cassettes at real speed with the loading tones played,
+    /// Escape skipping the wait (the interactive interpreter)
+    pub tape_authentic: bool,
+    /// Close open files when a program ends or stops with an error, and on NEW
+    pub close_files: bool,
+    /// Seconds between autosaves of the program buffer (0 = off)
+    pub autosave: u32,
+    /// Include variables in autosaves
+    pub autosave_variables: bool,
+    /// Print the PROC, GOSUB and loop state when an error stops a program
+    pub post_mortem: bool,
+    /// Changes to variables kept for *HISTORY (0 = none recorded)
+    pub history: usize,
+    /// Seconds a program may go round the same few lines getting nowhere
+    /// before the watchdog asks what to do (0 = off)
+    pub watchdog: u32,
+    /// Report running programs through the `tracing` crate (needs the
+    /// `tracing` feature)
+    pub trace: bool,
+    /// Make runs exactly reproducible: RND is seeded from `seed` and TIME
+    /// runs on a virtual clock, for tests and CI
+    pub deterministic: bool,
+    /// Seed for RND in deterministic mode
+    pub seed: u64,
+    /// Colour scheme for the terminal
+    pub colour_scheme: ColourScheme,
+    /// How text drawn at the graphics cursor (VDU 5) is shown
+    pub font: Font,
+    /// Printed when the interpreter starts (empty = nothing)
+    pub banner: String,
+    /// Message of the day, printed after the banner (empty = nothing)
+    pub motd: String,
+    /// What to do with `boot_file` at start-up, as *OPT 4 sets for a disc
+    pub boot: BootOption,
+    /// The file acted on at start-up: a host file, or one in a disc image
+    /// (`GAMES.SSD#!BOOT`); LOAD and RUN add `.bbas` to a host file name
+    /// as LOAD does
+    pub boot_file: String,
+    /// Keys remapped for INKEY with a negative number, as scancode = BBC key
+    /// (`ArrowLeft = "Z"`), on top of the standard layout
+    pub keys: BTreeMap<String, String>,
+    /// Strictness flags
+    pub strict: StrictFlags,
+    /// What programs from people who cannot be trusted may do
+    pub safe: SafeMode,
+}
+
+/// Colour schemes for the terminal and the *INSPECT panel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColourScheme {
+    /// Leave the terminal's own colours alone
+    Default,
+    /// White text on black, like a BBC Micro on a TV
+    Classic,
+    /// Bold white on black, with the colours lightened to stand out on it
+    Contrast,
+    /// Green phosphor monitor
+    Green,
+    /// Amber phosphor monitor
+    Amber,
+}
+
+/// Fonts that text drawn at the graphics cursor (VDU 5) is shown in
+///
+/// The canvas always holds the BBC's own character shapes, as POINT reads
+/// them back; the font says how renderers show them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Font {
+    /// The embedded 8x8 BBC font, drawn pixel by pixel
+    #[default]
+    Beeb,
+    /// The host's own monospace font: each character is shown as itself in
+    /// the cell it starts in
+    System,
+}
+
+/// What is done with the boot file at start-up, as on pressing SHIFT-BREAK
+/// with a disc in the drive (*OPT 4,n sets it)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BootOption {
+    /// Nothing (*OPT 4,0)
+    #[default]
+    Off,
+    /// LOAD it as a program (*OPT 4,1)
+    Load,
+    /// CHAIN it (*OPT 4,2)
+    Run,
+    /// Type its lines at the prompt, as *EXEC does (*OPT 4,3)
+    Exec,
+}
+
+/// Execution backends for RUN
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Parse and walk each line's syntax tree as it is reached
+    #[default]
+    Tree,
+    /// Compile the program to bytecode once, then run it on the VM
+    #[serde(alias = "vm")]
+    Bytecode,
+}
+
+/// How reals are computed and printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FloatFormat {
+    /// 64 bit doubles, printed as precisely as they need
+    #[default]
+    Double,
+    /// The Model B's five byte floats (32 bit mantissa, 8 bit exponent),
+    /// each result rounded to them and printed to nine figures as with the
+    /// default @%, so results match a real machine
+    Bbc,
+}
+
+/// When INPUT prompts for its answers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Prompts {
+    /// Only when someone is typing the answers, not when they are piped in
+    /// (`bbc-basic-interpreter run prog.bbas < data.txt`)
+    #[default]
+    Auto,
+    /// Always
+    On,
+    /// Never
+    Off,
+}
+
+/// Flags controlling how closely the interpreter follows the real machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StrictFlags {
+    /// Reading an unset variable is an error (otherwise it reads as 0 or "")
+    pub undefined_variables: bool,
+    /// Strings are limited to 255 characters
+    pub string_length: bool,
+    /// BBC file names must fit the DFS (7) and ADFS (10) length limits
+    pub filenames: bool,
+    /// RUN refuses a program that jumps to lines it does not have (otherwise
+    /// only the jump itself fails, with "No such line")
+    pub line_numbers: bool,
+}
+
+/// Limits on programs that cannot be trusted, such as snippets sent to a
+/// hosted service
+///
+/// With `enabled` set, programs cannot write files (OPENOUT and OPENUP),
+/// run star commands (FNoscli$), CALL or USR the OS, or load programs and
+/// libraries from URLs: each gives error 49, "Not allowed", which ON ERROR
+/// can trap. Strings are held to 255 characters whatever `strict` says, and
+/// `filesystem_root` should be set to keep file reads in one directory. The
+/// limits apply to every run whether or not `enabled` is set, and stop the
+/// program whatever ON ERROR says.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SafeMode {
+    /// Refuse file writes, star commands, OS calls and URLs
+    pub enabled: bool,
+    /// Most statements a run may execute (0 = no limit)
+    pub max_statements: u64,
+    /// Most seconds a run may take (0 = no limit)
+    pub max_seconds: u32,
+    /// Deepest PROC and FN calls may nest before "No room" (0 = no limit)
+    pub max_depth: usize,
+}
+
+impl SafeMode {
+    /// Settings for running programs sent to a hosted service: everything
+    /// refused, a million statements, ten seconds and 200 nested calls
+    pub fn hosted() -> Self {
+        Self {
+            enabled: true,
+            max_statements: 1_000_000,
+            max_seconds: 10,
+            max_depth: 200,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            mode: 7,
+            dialect: Dialect::BasicV,
+            case_insensitive_keywords: true,
+            list_case: KeywordCase::Upper,
+            number_start: 10,
+            number_step: 10,
+            filesystem_root: None,
+            speed: 0,
+            refresh_rate: 50,
+            backend: Backend::Tree,
+            floats: FloatFormat::Double,
+            prompts: Prompts::Auto,
+            tape_realtime: false,
+            tape_authentic: false,
+            close_files: true,
+            autosave: 0,
+            autosave_variables: false,
+            post_mortem: false,
+            history: 0,
+            watchdog: 10,
+            trace: false,
+            deterministic: false,
+            seed: 0,
+            colour_scheme: ColourScheme::Default,
+            font: Font::Beeb,
+            banner: "BBC BASIC Interpreter v0.1.0".to_string(),
+            motd: String::new(),
+            boot: BootOption::Off,
+            boot_file: "!BOOT".to_string(),
+            keys: BTreeMap::new(),
+            strict: StrictFlags::default(),
+            safe: SafeMode::default(),
+        }
+    }
+}
+
+impl Default for StrictFlags {
+    fn default() -> Self {
+        Self {
+            undefined_variables: true,
+            string_length: true,
+            filenames: true,
+            line_numbers: false,
+        }
+    }
+}
+
+impl ColourScheme {
+    /// ANSI escape sequence that selects this scheme (empty for Default)
+    pub fn ansi_prefix(&self) -> &'static str {
+        match self {
+            ColourScheme::Default => "",
+            ColourScheme::Classic => "\x1b[97;40m",
+            ColourScheme::Contrast => "\x1b[1;97;40m",
+            ColourScheme::Green => "\x1b[92;40m",
+            ColourScheme::Amber => "\x1b[38;5;214;40m",
+        }
+    }
+
+    /// ANSI escape sequence that restores the terminal's colours
+    pub fn ansi_reset(&self) -> &'static str {
+        match self {
+            ColourScheme::Default => "",
+            _ => "\x1b[0m",
+        }
+    }
+
+    /// How one of the BBC's physical colours (0-15) shows, as red, green
+    /// and blue; the flashing colours 8-15 show in their first colour
+    ///
+    /// The TV colours are the BBC's pure primaries, which the default
+    /// scheme leaves as they are. Phosphor monitors show each colour's
+    /// brightness in their own colour, as a monochrome monitor plugged into
+    /// a BBC did.
+    pub fn rgb(&self, colour: u8) -> [u8; 3] {
+        let colour = colour & 7;
+        // Bits 0, 1 and 2 turn on the red, green and blue guns
+        let tv = [1, 2, 4].map(|gun| if colour & gun != 0 { 255 } else { 0 });
+        let brightness = tv_brightness(tv);
+        let phosphor =
+            |shade: [u8; 3]| shade.map(|level| (f64::from(level) * brightness).round() as u8);
+        match self {
+            ColourScheme::Default | ColourScheme::Classic => tv,
+            // Black and white stay, and the other colours are lightened
+            ColourScheme::Contrast if colour != 0 && colour != 7 => tv.map(|level| level.max(85)),
+            ColourScheme::Contrast => tv,
+            ColourScheme::Green => phosphor([51, 255, 102]),
+            ColourScheme::Amber => phosphor([255, 176, 0]),
+        }
+    }
+}
+
+/// Brightness of a colour from 0 to 1, weighting red, green and blue as the
+/// eye (and a monochrome monitor's luminance signal) does
+fn tv_brightness([r, g, b]: [u8; 3]) -> f64 {
+    (0.299 * f64::from(r) + 0.587 * f64::from(g) + 0.114 * f64::from(b)) / 255.0
+}
+
+impl fmt::Display for ColourScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ColourScheme::Default => "default",
+            ColourScheme::Classic => "classic",
+            ColourScheme::Contrast => "contrast",
+            ColourScheme::Green => "green",
+            ColourScheme::Amber => "amber",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Font {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Font::Beeb => "beeb",
+            Font::System => "system",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Backend::Tree => "tree",
+            Backend::Bytecode => "bytecode",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for FloatFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FloatFormat::Double => "double",
+            FloatFormat::Bbc => "bbc",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for BootOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BootOption::Off => "off",
+            BootOption::Load => "load",
+            BootOption::Run => "run",
+            BootOption::Exec => "exec",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Prompts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Prompts::Auto => "auto",
+            Prompts::On => "on",
+            Prompts::Off => "off",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Config {
+    /// Parse a configuration from TOML text
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        let config: Config = toml::from_str(text).map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Serialise the configuration as TOML text
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap_or_default()
+    }
+
+    /// Load a configuration file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        Self::from_toml(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Save the configuration to a file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_toml())
+            .map_err(|e| format!("Cannot write {}: {}", path.display(), e))
+    }
+
+    /// Find the configuration file to use at startup, if any
+    ///
+    /// Checks $BBC_BASIC_CONFIG, then ./bbcbasic.toml, then
+    /// ~/.config/bbc-basic/bbcbasic.toml.
+    pub fn default_path() -> Option<PathBuf> {
+        if let Some(path) = std::env::var_os(CONFIG_ENV_VAR) {
+            return Some(PathBuf::from(path));
+        }
+
+        let local = PathBuf::from(CONFIG_FILE_NAME);
+        if local.exists() {
+            return Some(local);
+        }
+
+        let home = std::env::var_os("HOME")?;
+        let user = Path::new(&home)
+            .join(".config")
+            .join("bbc-basic")
+            .join(CONFIG_FILE_NAME);
+        user.exists().then_some(user)
+    }
+
+    /// Check that all values are in range
+    pub fn validate(&self) -> Result<(), String> {
+        if self.mode > 7 {
+            return Err(format!("mode must be 0-7, got {}", self.mode));
+        }
+        if self.number_start > 32767 || self.number_step == 0 {
+            return Err("number_start must be 0-32767 and number_step at least 1".to_string());
+        }
+        if !(1..=1000).contains(&self.refresh_rate) {
+            return Err(format!(
+                "refresh_rate must be 1-1000, got {}",
+                self.refresh_rate
+            ));
+        }
+        self.keymap()?;
+        Ok(())
+    }
+
+    /// Set a single option by name (*CONFIGURE name value)
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let mut updated = self.clone();
+        match key.to_ascii_lowercase().as_str() {
+            "mode" => updated.mode = parse_number(key, value)?,
+            "dialect" => {
+                updated.dialect = match value.to_ascii_lowercase().as_str() {
+                    "basic2" | "ii" | "2" | "strict" => Dialect::BasicII,
+                    "basic5" | "v" | "5" | "compat" => Dialect::BasicV,
+                    _ => return Err(format!("dialect expects BASIC2 or BASIC5, got {}", value)),
+                }
+            }
+            "case_insensitive_keywords" | "case" => {
+                updated.case_insensitive_keywords = parse_flag(key, value)?
+            }
+            "list_case" => {
+                updated.list_case = match value.to_ascii_lowercase().as_str() {
+                    "upper" => KeywordCase::Upper,
+                    "lower" => KeywordCase::Lower,
+                    _ => return Err(format!("list_case expects UPPER or LOWER, got {}", value)),
+                }
+            }
+            "number_start" => updated.number_start = parse_number(key, value)?,
+            "number_step" => updated.number_step = parse_number(key, value)?,
+            "filesystem_root" | "root" => {
+                updated.filesystem_root = if value.is_empty() {
+                    None
+                } else {
+                    Some(PathBuf::from(value))
+                }
+            }
+            "speed" => updated.speed = parse_number(key, value)?,
+            "refresh_rate" | "vsync" => updated.refresh_rate = parse_number(key, value)?,
+            "backend" => {
+                updated.backend = match value.to_ascii_lowercase().as_str() {
+                    "tree" => Backend::Tree,
+                    "bytecode" | "vm" => Backend::Bytecode,
+                    _ => return Err(format!("backend expects TREE or BYTECODE, got {}", value)),
+                }
+            }
+            "floats" => {
+                updated.floats = match value.to_ascii_lowercase().as_str() {
+                    "double" | "ieee" => FloatFormat::Double,
+                    "bbc" | "five_byte" => FloatFormat::Bbc,
+                    _ => return Err(format!("floats expects DOUBLE or BBC, got {}", value)),
+                }
+            }
+            "prompts" => {
+                updated.prompts = match value.to_ascii_lowercase().as_str() {
+                    "auto" => Prompts::Auto,
+                    "on" | "yes" | "true" => Prompts::On,
+                    "off" | "no" | "false" => Prompts::Off,
+                    _ => return Err(format!("prompts expects AUTO, ON or OFF, got {}", value)),
+                }
+            }
+            "tape_realtime" | "tape" => updated.tape_realtime = parse_flag(key, value)?,
+            "tape_authentic" | "authentic" => updated.tape_authentic = parse_flag(key, value)?,
+            "close_files" => updated.close_files = parse_flag(key, value)?,
+            "autosave" => updated.autosave = parse_number(key, value)?,
+            "autosave_variables" => updated.autosave_variables = parse_flag(key, value)?,
+            "post_mortem" => updated.post_mortem = parse_flag(key, value)?,
+            "history" => updated.history = parse_number(key, value)?,
+            "watchdog" => updated.watchdog = parse_number(key, value)?,
+            "trace" => updated.trace = parse_flag(key, value)?,
+            "deterministic" => updated.deterministic = parse_flag(key, value)?,
+            "seed" => updated.seed = parse_number(key, value)?,
+            "banner" => updated.banner = value.to_string(),
+            "motd" => updated.motd = value.to_string(),
+            "boot" => {
+                updated.boot = match value.to_ascii_lowercase().as_str() {
+                    "off" | "0" => BootOption::Off,
+                    "load" | "1" => BootOption::Load,
+                    "run" | "2" => BootOption::Run,
+                    "exec" | "3" => BootOption::Exec,
+                    _ => return Err(format!("boot expects OFF/LOAD/RUN/EXEC, got {}", value)),
+                }
+            }
+            "boot_file" => updated.boot_file = value.to_string(),
+            "colour_scheme" | "colour" | "color" => {
+                updated.colour_scheme = match value.to_ascii_lowercase().as_str() {
+                    "default" => ColourScheme::Default,
+                    "classic" | "tv" => ColourScheme::Classic,
+                    "contrast" | "high_contrast" => ColourScheme::Contrast,
+                    "green" => ColourScheme::Green,
+                    "amber" => ColourScheme::Amber,
+                    _ => return Err(format!("Unknown colour scheme: {}", value)),
+                }
+            }
+            "font" => {
+                updated.font = match value.to_ascii_lowercase().as_str() {
+                    "beeb" | "bbc" => Font::Beeb,
+                    "system" | "monospace" => Font::System,
+                    _ => return Err(format!("font expects BEEB or SYSTEM, got {}", value)),
+                }
+            }
+            option if option.starts_with("key.") => {
+                // Scancodes keep their case (key.ArrowLeft)
+                let scancode = &key["key.".len()..];
+                if value.is_empty() {
+                    updated.keys.remove(scancode);
+                } else {
+                    updated.keys.insert(scancode.to_string(), value.to_string());
+                }
+            }
+            "strict.undefined_variables" => {
+                updated.strict.undefined_variables = parse_flag(key, value)?
+            }
+            "strict.string_length" => updated.strict.string_length = parse_flag(key, value)?,
+            "strict.filenames" => updated.strict.filenames = parse_flag(key, value)?,
+            "strict.line_numbers" => updated.strict.line_numbers = parse_flag(key, value)?,
+            "safe" | "safe.enabled" => updated.safe.enabled = parse_flag(key, value)?,
+            "safe.max_statements" => updated.safe.max_statements = parse_number(key, value)?,
+            "safe.max_seconds" => updated.safe.max_seconds = parse_number(key, value)?,
+            "safe.max_depth" => updated.safe.max_depth = parse_number(key, value)?,
+            _ => return Err(format!("Unknown option: {}", key)),
+        }
+        updated.validate()?;
+        *self = updated;
+        Ok(())
+    }
+
+    /// Describe the current settings, one per line (*CONFIGURE with no arguments)
+    pub fn describe(&self) -> String {
+        let root = self.filesystem_root.as_ref().map_or_else(
+            || "(current directory)".to_string(),
+            |p| p.display().to_string(),
+        );
+        let list_case = match self.list_case {
+            KeywordCase::Upper => "upper",
+            KeywordCase::Lower => "lower",
+        };
+        let speed = if self.speed == 0 {
+            "unthrottled".to_string()
+        } else {
+            format!("{} statements/s", self.speed)
+        };
+        let keys = if self.keys.is_empty() {
+            "standard".to_string()
+        } else {
+            self.keys
+                .iter()
+                .map(|(scancode, key)| format!("{}={}", scancode, key))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let autosave = if self.autosave == 0 {
+            "off".to_string()
+        } else {
+            format!("every {}s", self.autosave)
+        };
+        let history = if self.history == 0 {
+            "off".to_string()
+        } else {
+            format!("last {} changes", self.history)
+        };
+        let watchdog = if self.watchdog == 0 {
+            "off".to_string()
+        } else {
+            format!("after {}s", self.watchdog)
+        };
+
+        [
+            format!("mode                       {}", self.mode),
+            format!("dialect                    {}", self.dialect),
+            format!(
+                "case_insensitive_keywords  {}",
+                on_off(self.case_insensitive_keywords)
+            ),
+            format!("list_case                  {}", list_case),
+            format!("number_start               {}", self.number_start),
+            format!("number_step                {}", self.number_step),
+            format!("filesystem_root            {}", root),
+            format!("speed                      {}", speed),
+            format!("refresh_rate               {} Hz", self.refresh_rate),
+            format!("backend                    {}", self.backend),
+            format!("floats                     {}", self.floats),
+            format!("prompts                    {}", self.prompts),
+            format!("tape_realtime              {}", on_off(self.tape_realtime)),
+            format!("tape_authentic             {}", on_off(self.tape_authentic)),
+            format!("close_files                {}", on_off(self.close_files)),
+            format!("autosave                   {}", autosave),
+            format!(
+                "autosave_variables         {}",
+                on_off(self.autosave_variables)
+            ),
+            format!("post_mortem                {}", on_off(self.post_mortem)),
+            format!("history                    {}", history),
+            format!("watchdog                   {}", watchdog),
+            format!("trace                      {}", on_off(self.trace)),
+            format!("deterministic              {}", on_off(self.deterministic)),
+            format!("seed                       {}", self.seed),
+            format!("colour_scheme              {}", self.colour_scheme),
+            format!("font                       {}", self.font),
+            format!("banner                     {}", self.banner),
+            format!("motd                       {}", self.motd),
+            format!("boot                       {}", self.boot),
+            format!("boot_file                  {}", self.boot_file),
+            format!("keys                       {}", keys),
+            format!(
+                "strict.undefined_variables {}",
+                on_off(self.strict.undefined_variables)
+            ),
+            format!(
+                "strict.string_length       {}",
+                on_off(self.strict.string_length)
+            ),
+            format!(
+                "strict.filenames           {}",
+                on_off(self.strict.filenames)
+            ),
+            format!(
+                "strict.line_numbers        {}",
+                on_off(self.strict.line_numbers)
+            ),
+            format!("safe.enabled               {}", on_off(self.safe.enabled)),
+            format!(
+                "safe.max_statements        {}",
+                limit(self.safe.max_statements)
+            ),
+            format!(
+                "safe.max_seconds           {}",
+                limit(self.safe.max_seconds)
+            ),
+            format!("safe.max_depth             {}", limit(self.safe.max_depth)),
+        ]
+        .join("\n")
+    }
+
+    /// Statements per second to pace runs at (0 = unthrottled); deterministic
+    /// runs do not depend on the real clock, so they are never paced
+    pub fn pacing(&self) -> u32 {
+        if self.deterministic {
+            0
+        } else {
+            self.speed
+        }
+    }
+
+    /// Tokenizer options for entering and listing programs
+    pub fn tokenizer_options(&self) -> TokenizerOptions {
+        TokenizerOptions {
+            case_insensitive_keywords: self.case_insensitive_keywords,
+            keyword_case: self.list_case,
+        }
+    }
+
+    /// Line numbering for source files loaded without line numbers
+    pub fn numbering(&self) -> Numbering {
+        Numbering {
+            start: self.number_start,
+            step: self.number_step,
+        }
+    }
+
+    /// Which BBC key each scancode stands for, for INKEY with a negative
+    /// number
+    pub fn keymap(&self) -> Result<KeyMap, String> {
+        KeyMap::with_overrides(&self.keys)
+    }
+
+    /// Translator from file names to paths under the filesystem root
+    pub fn filenames(&self) -> FilenameTranslator {
+        FilenameTranslator::new(self.filesystem_root.clone(), self.strict.filenames)
+    }
+
+    /// Resolve a filename against the configured filesystem root
+    pub fn resolve_path(&self, filename: &str) -> Result<PathBuf, String> {
+        self.filenames().translate(filename)
+    }
+}
+
+/// Format a flag for display
+fn on_off(flag: bool) -> &'static str {
+    if flag {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+/// A limit for *CONFIGURE to show, where 0 means there is none
+fn limit<T: PartialEq + Default + fmt::Display>(value: T) -> String {
+    if value == T::default() {
+        "none".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Parse an on/off style flag value
+fn parse_flag(key: &str, value: &str) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "on" | "true" | "yes" | "1" => Ok(true),
+        "off" | "false" | "no" | "0" => Ok(false),
+        _ => Err(format!("{} expects ON or OFF, got {}", key, value)),
+    }
+}
+
+/// Parse a numeric option value
+fn parse_number<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} expects a number, got {}", key, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_toml() {
+        let config = Config::from_toml(
+            r#"
+            mode = 1
+            speed = 500
+            colour_scheme = "green"
+            dialect = "basic2"
+            backend = "bytecode"
+            filesystem_root = "/tmp/bbc"
+
+            [keys]
+            ArrowLeft = "Z"
+
+            [strict]
+            undefined_variables = false
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.mode, 1);
+        assert_eq!(config.speed, 500);
+        assert_eq!(config.colour_scheme, ColourScheme::Green);
+        assert_eq!(config.dialect, Dialect::BasicII);
+        assert_eq!(config.backend, Backend::Bytecode);
+        assert_eq!(config.filesystem_root, Some(PathBuf::from("/tmp/bbc")));
+        assert_eq!(config.keymap().unwrap().inkey("ArrowLeft"), Some(-98));
+        assert!(!config.strict.undefined_variables);
+        // Unspecified options keep their defaults
+        assert!(config.strict.string_length);
+        assert!(config.case_insensitive_keywords);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Config::from_toml("mode = 9").is_err());
+        assert!(Config::from_toml("unknown = 1").is_err());
+        assert!(Config::from_toml("colour_scheme = \"purple\"").is_err());
+        assert!(Config::from_toml("dialect = \"basic4\"").is_err());
+        assert!(Config::from_toml("[keys]\nKeyA = \"HOME\"").is_err());
+    }
+
+    #[test]
+    fn test_backend_spellings() {
+        // The TOML file takes the same names as *CONFIGURE
+        for name in ["tree", "bytecode", "vm"] {
+            let from_toml = Config::from_toml(&format!("backend = \"{}\"", name)).unwrap();
+            let mut configured = Config::default();
+            configured.set("backend", name).unwrap();
+            assert_eq!(from_toml.backend, configured.backend, "{}", name);
+        }
+        assert!(Config::from_toml("backend = \"jit\"").is_err());
+    }
+
+    #[test]
+    fn test_toml_round_trip() {
+        let mut config = Config::default();
+        config.set("colour", "amber").unwrap();
+        config.set("strict.string_length", "off").unwrap();
+        config.set("strict.line_numbers", "on").unwrap();
+        config.set("key.ArrowRight", "X").unwrap();
+        config.set("boot", "exec").unwrap();
+        config.set("banner", "").unwrap();
+        config.set("safe", "on").unwrap();
+        config.set("safe.max_seconds", "5").unwrap();
+        config.set("font", "system").unwrap();
+        assert_eq!(Config::from_toml(&config.to_toml()).unwrap(), config);
+    }
+
+    #[test]
+    fn test_set_option() {
+        let mut config = Config::default();
+        config.set("MODE", "2").unwrap();
+        config.set("speed", "100").unwrap();
+        config.set("root", "programs").unwrap();
+        config.set("list_case", "LOWER").unwrap();
+        config.set("dialect", "strict").unwrap();
+        config.set("backend", "VM").unwrap();
+        config.set("tape_realtime", "on").unwrap();
+        assert!(config.tape_realtime);
+        config.set("authentic", "on").unwrap();
+        assert!(config.tape_authentic);
+        config.set("floats", "BBC").unwrap();
+        assert_eq!(config.floats, FloatFormat::Bbc);
+        assert!(config.set("floats", "single").is_err());
+        config.set("boot", "3").unwrap();
+        assert_eq!(config.boot, BootOption::Exec);
+        config.set("boot", "run").unwrap();
+        assert_eq!(config.boot, BootOption::Run);
+        assert!(config.set("boot", "4").is_err());
+        config.set("motd", "Welcome back").unwrap();
+        assert_eq!(config.motd, "Welcome back");
+        config.set("prompts", "OFF").unwrap();
+        assert_eq!(config.prompts, Prompts::Off);
+        assert!(config.set("prompts", "sometimes").is_err());
+        config.set("close_files", "off").unwrap();
+        assert!(!config.close_files);
+        config.set("autosave", "30").unwrap();
+        assert_eq!(config.autosave, 30);
+        config.set("watchdog", "0").unwrap();
+        assert_eq!(config.watchdog, 0);
+        config.set("history", "500").unwrap();
+        assert_eq!(config.history, 500);
+        config.set("trace", "on").unwrap();
+        assert!(config.trace);
+        config.set("vsync", "60").unwrap();
+        assert_eq!(config.refresh_rate, 60);
+        assert!(config.set("refresh_rate", "0").is_err());
+        config.set("colour", "TV").unwrap();
+        assert_eq!(config.colour_scheme, ColourScheme::Classic);
+        config.set("colour", "high_contrast").unwrap();
+        assert_eq!(config.colour_scheme, ColourScheme::Contrast);
+        config.set("font", "monospace").unwrap();
+        assert_eq!(config.font, Font::System);
+        assert!(config.set("font", "teletext").is_err());
+        config.set("number_start", "1000").unwrap();
+        config.set("number_step", "5").unwrap();
+        assert_eq!(
+            config.numbering(),
+            Numbering {
+                start: 1000,
+                step: 5
+            }
+        );
+        assert_eq!(config.dialect, Dialect::BasicII);
+        assert_eq!(config.backend, Backend::Bytecode);
+        assert_eq!(config.tokenizer_options().keyword_case, KeywordCase::Lower);
+        assert_eq!(config.mode, 2);
+        assert_eq!(config.speed, 100);
+        #[cfg(feature = "filesystem-host")]
+        {
+            assert_eq!(
+                config.resolve_path("GAME.bbas").unwrap(),
+                PathBuf::from("programs/GAME.bbas")
+            );
+            assert_eq!(
+                config.resolve_path("$.GAME").unwrap(),
+                PathBuf::from("programs/GAME")
+            );
+            assert!(config.resolve_path("../GAME").is_err());
+        }
+
+        // Invalid values leave the configuration unchanged
+        assert!(config.set("mode", "8").is_err());
+        assert!(config.set("number_step", "0").is_err());
+        assert!(config.set("case", "maybe").is_err());
+        assert!(config.set("nonsense", "1").is_err());
+        assert!(config.set("key.KeyA", "HOME").is_err());
+        config.set("key.ArrowUp", ":").unwrap();
+        assert_eq!(config.keymap().unwrap().inkey("ArrowUp"), Some(-73));
+        config.set("key.ArrowUp", "").unwrap();
+        assert!(config.keys.is_empty());
+        assert_eq!(config.mode, 2);
+    }
+
+    #[test]
+    fn test_scheme_colours() {
+        assert_eq!(ColourScheme::Classic.rgb(1), [255, 0, 0]);
+        assert_eq!(ColourScheme::Classic.rgb(14), [0, 255, 255]);
+        assert_eq!(ColourScheme::Contrast.rgb(0), [0, 0, 0]);
+        assert_eq!(ColourScheme::Contrast.rgb(4), [85, 85, 255]);
+        assert_eq!(ColourScheme::Contrast.rgb(7), [255, 255, 255]);
+        // Monochrome monitors show each colour as a shade of their phosphor
+        assert_eq!(ColourScheme::Green.rgb(7), [51, 255, 102]);
+        assert_eq!(ColourScheme::Amber.rgb(0), [0, 0, 0]);
+        let [red, ..] = ColourScheme::Amber.rgb(1);
+        let [yellow, ..] = ColourScheme::Amber.rgb(3);
+        assert!(0 < red && red < yellow && yellow < 255);
+    }
+}
@@ -0,0 +1,130 @@
+//! Breakpoints and stepping for the program debugger
+//!
+//! A program run under the debugger stops at breakpoints and can then be
+//! stepped. Steps are measured against the depth of the GOSUB/PROC return
+//! stack rather than single lines, so a structured program can be debugged a
+//! procedure at a time: stepping over a PROC call runs the whole procedure,
+//! and stepping out runs to the end of the current one.
+
+use std::collections::BTreeSet;
+
+/// How far to run before stopping again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// Stop at the next line, following PROC calls and GOSUBs into their
+    /// bodies
+    Into,
+    /// Stop at the next line at this call depth or shallower, running PROC
+    /// calls and GOSUBs to completion
+    Over,
+    /// Stop once the current PROC or subroutine has returned
+    Out,
+    /// Run until a breakpoint or the end of the program
+    Continue,
+}
+
+/// Why a debugged or sliced program gave control back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pause {
+    /// Stopped before running this line, at a breakpoint or after a step
+    Break(u16),
+    /// Ran its share of statements and has more to run
+    Yielded,
+    /// INPUT or GET is waiting for keys to be inserted into the keyboard
+    /// buffer
+    WaitingForInput,
+    /// The program finished
+    Ended,
+}
+
+/// Breakpoints and the state of a program stopped under the debugger
+#[derive(Debug, Clone, Default)]
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    /// Line the program is stopped at (None = not stopped in the debugger)
+    paused_at: Option<u16>,
+}
+
+impl Debugger {
+    /// Create a debugger with no breakpoints
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop before running `line_number`
+    pub fn set_breakpoint(&mut self, line_number: u16) {
+        self.breakpoints.insert(line_number);
+    }
+
+    /// Remove a breakpoint, returning whether there was one
+    pub fn clear_breakpoint(&mut self, line_number: u16) -> bool {
+        self.breakpoints.remove(&line_number)
+    }
+
+    /// Remove all breakpoints
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Lines with breakpoints, in order
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Line the program is stopped at, if it is stopped in the debugger
+    pub fn paused_at(&self) -> Option<u16> {
+        self.paused_at
+    }
+
+    pub(crate) fn set_paused_at(&mut self, line_number: Option<u16>) {
+        self.paused_at = line_number;
+    }
+
+    /// Whether to stop before running `line_number` at call `depth`, when
+    /// running `step` from `start_depth`
+    ///
+    /// The line the program is resumed from (`resuming`) is always run, so
+    /// that a step or continue makes progress past a breakpoint.
+    pub(crate) fn should_stop(
+        &self,
+        line_number: u16,
+        depth: usize,
+        step: Step,
+        start_depth: usize,
+        resuming: bool,
+    ) -> bool {
+        if resuming {
+            return false;
+        }
+        self.breakpoints.contains(&line_number)
+            || match step {
+                Step::Into => true,
+                Step::Over => depth <= start_depth,
+                Step::Out => depth < start_depth,
+                Step::Continue => false,
+            }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stop_conditions() {
+        let mut debugger = Debugger::new();
+        debugger.set_breakpoint(100);
+        assert!(debugger.should_stop(100, 3, Step::Continue, 0, false));
+        assert!(!debugger.should_stop(100, 3, Step::Continue, 0, true));
+        assert!(!debugger.should_stop(20, 0, Step::Continue, 0, false));
+
+        assert!(debugger.should_stop(20, 2, Step::Into, 1, false));
+        assert!(!debugger.should_stop(20, 2, Step::Over, 1, false));
+        assert!(debugger.should_stop(20, 1, Step::Over, 1, false));
+        assert!(!debugger.should_stop(20, 1, Step::Out, 1, false));
+        assert!(debugger.should_stop(20, 0, Step::Out, 1, false));
+
+        assert!(debugger.clear_breakpoint(100));
+        assert_eq!(debugger.breakpoints().count(), 0);
+    }
+}
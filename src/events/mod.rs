@@ -0,0 +1,240 @@
+//! Structured output events for front ends
+//!
+//! Everything a running program does to the screen and sound system is
+//! reported as an [`OutputEvent`]: text printed, the screen cleared, a MODE
+//! change, a note queued or a graphics operation. A front end subscribes an
+//! [`OutputListener`] to the interpreter and draws the events however it
+//! likes; the REPL's [`TerminalRenderer`] is one such listener, writing text
+//! to an ANSI terminal.
+//!
+//! Beneath the events, an [`Oswrch`] hook sees the raw byte stream that a BBC
+//! Micro would send through OSWRCH to its VDU drivers: printed characters,
+//! with a new line as 10 and 13, and the VDU sequences of statements such as
+//! CLS, MODE, GCOL, PLOT, MOVE and DRAW, or of VDU itself.
+
+use crate::charset;
+use std::fmt;
+use std::io::Write;
+
+/// Something a program did to the screen or sound system
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputEvent {
+    /// Text written to the screen, in the BBC character set ("\n" starts a
+    /// new line)
+    Print(String),
+    /// The text screen was cleared (CLS, or MODE)
+    Cls,
+    /// The screen mode changed (MODE)
+    ModeChange(u8),
+    /// A note was queued (SOUND)
+    SoundQueued(QueuedSound),
+    /// A graphics operation
+    GraphicsOp(GraphicsOp),
+    /// The program waited for the next frame (WAIT, *FX 19), so what it
+    /// has drawn is ready to show
+    Frame,
+}
+
+/// A SOUND statement's parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueuedSound {
+    pub channel: i32,
+    /// Volume (0 to -15), or an envelope number (1-16)
+    pub amplitude: i32,
+    pub pitch: i32,
+    /// Length in twentieths of a second
+    pub duration: i32,
+}
+
+/// A graphics statement, with coordinates in BBC graphics units
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphicsOp {
+    Plot {
+        mode: u8,
+        x: i32,
+        y: i32,
+    },
+    Move {
+        x: i32,
+        y: i32,
+    },
+    Draw {
+        x: i32,
+        y: i32,
+    },
+    Circle {
+        x: i32,
+        y: i32,
+        radius: i32,
+        filled: bool,
+    },
+    Ellipse {
+        x: i32,
+        y: i32,
+        major: i32,
+        minor: i32,
+        filled: bool,
+    },
+    Rectangle {
+        x1: i32,
+        y1: i32,
+        x2: i32,
+        y2: i32,
+        filled: bool,
+    },
+    /// RECTANGLE ... TO: the block between two corners copied to (x, y),
+    /// or moved there if `moved` (RECTANGLE FILL ... TO)
+    CopyRectangle {
+        x1: i32,
+        y1: i32,
+        x2: i32,
+        y2: i32,
+        x: i32,
+        y: i32,
+        moved: bool,
+    },
+    Fill {
+        x: i32,
+        y: i32,
+    },
+    /// A character printed at the graphics cursor (VDU 5), with its top
+    /// left corner at (x, y)
+    Char {
+        x: i32,
+        y: i32,
+        code: u8,
+    },
+    Origin {
+        x: i32,
+        y: i32,
+    },
+    Gcol {
+        mode: u8,
+        colour: u8,
+    },
+    Clg,
+}
+
+/// Receives output events as a program runs
+///
+/// Listeners are `Send` so that an interpreter can be moved to a worker
+/// thread along with them.
+pub trait OutputListener: Send {
+    fn event(&mut self, event: &OutputEvent);
+}
+
+impl<F: FnMut(&OutputEvent) + Send> OutputListener for F {
+    fn event(&mut self, event: &OutputEvent) {
+        self(event)
+    }
+}
+
+/// Receives each byte written to the VDU drivers, as OSWRCH would
+pub trait Oswrch: Send {
+    fn oswrch(&mut self, byte: u8);
+}
+
+impl<F: FnMut(u8) + Send> Oswrch for F {
+    fn oswrch(&mut self, byte: u8) {
+        self(byte)
+    }
+}
+
+/// The listeners subscribed to an executor's output
+#[derive(Default)]
+pub struct OutputEvents {
+    listeners: Vec<Box<dyn OutputListener>>,
+    writers: Vec<Box<dyn Oswrch>>,
+}
+
+impl OutputEvents {
+    /// Send future events to `listener` as well
+    pub fn subscribe(&mut self, listener: Box<dyn OutputListener>) {
+        self.listeners.push(listener);
+    }
+
+    /// Pass an event to every listener
+    pub fn emit(&mut self, event: OutputEvent) {
+        for listener in &mut self.listeners {
+            listener.event(&event);
+        }
+    }
+
+    /// Send future VDU bytes to `writer` as well
+    pub fn subscribe_oswrch(&mut self, writer: Box<dyn Oswrch>) {
+        self.writers.push(writer);
+    }
+
+    /// Pass bytes to every OSWRCH hook
+    pub fn write(&mut self, bytes: &[u8]) {
+        for writer in &mut self.writers {
+            for &byte in bytes {
+                writer.oswrch(byte);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for OutputEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "OutputEvents({} listeners, {} OSWRCH hooks)",
+            self.listeners.len(),
+            self.writers.len()
+        )
+    }
+}
+
+/// Writes printed text to standard output, converting it to Unicode, and
+/// clears the terminal with ANSI escapes
+///
+/// Graphics and sound have no terminal form, so they are left to other
+/// listeners.
+#[derive(Debug, Default)]
+pub struct TerminalRenderer;
+
+impl OutputListener for TerminalRenderer {
+    fn event(&mut self, event: &OutputEvent) {
+        let mut stdout = std::io::stdout();
+        // A terminal that has gone away has nowhere to report to
+        let _ = match event {
+            OutputEvent::Print(text) => write!(stdout, "{}", charset::to_unicode(text)),
+            // ESC[2J clears the screen and ESC[H homes the cursor
+            OutputEvent::Cls => write!(stdout, "\x1b[2J\x1b[H"),
+            _ => Ok(()),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_listeners_see_every_event() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut events = OutputEvents::default();
+        for _ in 0..2 {
+            let seen = Arc::clone(&seen);
+            events.subscribe(Box::new(move |event: &OutputEvent| {
+                seen.lock().unwrap().push(event.clone())
+            }));
+        }
+        events.emit(OutputEvent::ModeChange(1));
+        assert_eq!(*seen.lock().unwrap(), vec![OutputEvent::ModeChange(1); 2]);
+    }
+
+    #[test]
+    fn test_oswrch_hooks_see_every_byte() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut events = OutputEvents::default();
+        events.write(&[1]);
+        let hook = Arc::clone(&seen);
+        events.subscribe_oswrch(Box::new(move |byte: u8| hook.lock().unwrap().push(byte)));
+        events.write(&[22, 7]);
+        events.write(&[65]);
+        assert_eq!(*seen.lock().unwrap(), vec![22, 7, 65]);
+    }
+}
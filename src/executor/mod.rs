@@ -2,16 +2,39 @@
 //!
 //! Executes parsed BBC BASIC statements with proper control flow handling.
 
+use crate::config::{FloatFormat, Prompts, SafeMode, StrictFlags};
 use crate::error::{BBCBasicError, Result};
-use crate::graphics::GraphicsSystem;
-use crate::memory::MemoryManager;
-use crate::parser::{DataValue, Expression, Statement};
-use crate::variables::{Variable, VariableStore};
-use rand::Rng;
-use std::cell::RefCell;
-use std::collections::HashMap;
+use crate::events::{GraphicsOp, Oswrch, OutputEvent, OutputEvents, OutputListener, QueuedSound};
+use crate::filesystem::FilenameTranslator;
+use crate::graphics::{Canvas, Graphics};
+use crate::hooks::Hooks;
+use crate::memory::{
+    screen_start, AllocationType, MemoryManager, MemoryStatus, MEMORY_SIZE, RESIDENT_START,
+};
+use crate::os::{keys_from_terminal, LineEditor, OSInterface};
+use crate::palette::Colours;
+use crate::parser::{
+    split_items, BinaryOperator, DataValue, Expression, Indirection, Statement, UnaryOperator,
+    VduItem,
+};
+use crate::program::Position;
+use crate::screen::TextScreen;
+use crate::sound::SoundSystem;
+use crate::speech::Speech;
+use crate::trace;
+use crate::turtle::Turtle;
+use crate::variables::{
+    resident_integer_name, Target, Variable, VariableStore, DEFAULT_PRINT_FORMAT, RESIDENT_INTEGERS,
+};
+use crate::vm::CompiledProgram;
+use crate::watchdog::{Watchdog, WatchdogAction};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::io::{BufRead, BufReader, BufWriter, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 /// File handle for file I/O operations
 #[derive(Debug)]
@@ -20,19 +43,73 @@ enum FileHandle {
     Input(BufReader<File>),
     /// File opened for writing (OPENOUT)
     Output(BufWriter<File>),
+    /// File opened for reading and writing (OPENUP)
+    Update(File),
+}
+
+impl FileHandle {
+    /// The open file, for reading and moving its pointer
+    fn seekable(&mut self) -> &mut dyn Seek {
+        match self {
+            FileHandle::Input(reader) => reader,
+            FileHandle::Output(writer) => writer,
+            FileHandle::Update(file) => file,
+        }
+    }
+}
+
+/// Handles the filing system gives out, lowest free first (as DFS, which
+/// allows five files open at once)
+const FIRST_FILE_HANDLE: i32 = 0x11;
+const LAST_FILE_HANDLE: i32 = 0x15;
+
+/// Variables that read machine state rather than the variable store; all of
+/// them are integers
+pub(crate) const PSEUDO_VARIABLES: &[&str] = &[
+    "TIME", "HIMEM", "LOMEM", "PAGE", "TOP", "ERR", "ERL", "GET", "TRUE", "FALSE",
+];
+
+/// Create the directory a file is to be written in, if it is missing
+fn create_parent_directory(path: &std::path::Path) -> Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => std::fs::create_dir_all(parent)
+            .map_err(|e| BBCBasicError::DiskError(format!("Cannot create directory: {}", e))),
+        _ => Ok(()),
+    }
+}
+
+/// Close a file, writing out anything still buffered
+fn close_file(file_handle: FileHandle) -> Result<()> {
+    match file_handle {
+        FileHandle::Output(mut writer) => writer
+            .flush()
+            .map_err(|e| BBCBasicError::DiskError(format!("Flush error: {}", e))),
+        FileHandle::Input(_) | FileHandle::Update(_) => Ok(()),
+    }
 }
 
 /// Local variable frame for procedure/function scoping
 #[derive(Debug, Clone)]
 struct LocalFrame {
-    /// Saved variable values (variable name -> saved value)
+    /// Saved variable values (variable name -> saved value); arrays are
+    /// saved whole under their name followed by "()"
     saved_variables: HashMap<String, Option<Variable>>,
+    /// DATA pointer saved by LOCAL DATA
+    data_pointer: Option<usize>,
+    /// Depth of the LOCAL ERROR stack before the procedure's first LOCAL
+    /// ERROR
+    error_depth: Option<usize>,
+    /// Array parameters passed by reference (parameter, caller's array)
+    array_arguments: Vec<(String, String)>,
 }
 
 impl LocalFrame {
     fn new() -> Self {
         Self {
             saved_variables: HashMap::new(),
+            data_pointer: None,
+            error_depth: None,
+            array_arguments: Vec::new(),
         }
     }
 }
@@ -48,7 +125,10 @@ pub struct ProcedureDefinition {
 #[derive(Debug, Clone)]
 pub struct FunctionDefinition {
     pub params: Vec<String>,
-    pub expression: Expression,
+    /// The result of a single-line function; None for a multi-line one
+    pub expression: Option<Expression>,
+    /// Line of the DEF, after which a multi-line function's body starts
+    pub line_number: Option<u16>,
 }
 
 /// Error information for ON ERROR handling
@@ -62,20 +142,98 @@ pub struct ErrorInfo {
     pub message: String,
 }
 
+/// What an expression gives
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Integer(i32),
+    Real(f64),
+    String(String),
+}
+
+impl std::fmt::Display for Value {
+    /// The value as PRINT shows it before a program sets @%
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Integer(value) => write!(f, "{}", value),
+            Value::Real(value) => {
+                write!(f, "{}", formatted_real_text(*value, DEFAULT_PRINT_FORMAT))
+            }
+            Value::String(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// A FOR loop in progress
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForLoop {
+    /// Variable or array element counting
+    pub variable: Target,
+    /// Value it stops after
+    pub limit: f64,
+    /// Added by each NEXT
+    pub step: f64,
+    /// The FOR statement
+    pub position: Position,
+}
+
+/// Error handling saved by LOCAL ERROR
+#[derive(Debug, Clone)]
+struct SavedErrorState {
+    handler: Option<u16>,
+    last_error: Option<ErrorInfo>,
+}
+
+/// An INPUT statement part way through being answered
+#[derive(Debug, Default)]
+struct PendingInput {
+    /// Lines typed so far
+    lines: Vec<String>,
+    /// The line being typed
+    editor: LineEditor,
+    /// Whether the "?" prompt for the line being typed has been printed
+    prompted: bool,
+}
+
+/// Where PRINT sends its text, chosen by OUTPUT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputTarget {
+    Screen,
+    /// An open file, and the column PRINT has reached on its current line
+    File {
+        handle: i32,
+        column: usize,
+    },
+}
+
 /// BBC BASIC statement executor
 #[derive(Debug)]
 pub struct Executor {
     variables: VariableStore,
     memory: MemoryManager,
-    graphics: GraphicsSystem,
-    // Control flow stack for GOSUB/RETURN
-    return_stack: Vec<u16>,
-    // FOR loop state: (variable, end_value, step_value, loop_line)
-    for_loops: Vec<(String, i32, i32, u16)>,
-    // REPEAT loop stack: stores line numbers of REPEAT statements
-    repeat_stack: Vec<u16>,
-    // WHILE loop stack: stores line numbers of WHILE statements
-    while_stack: Vec<u16>,
+    graphics: Graphics,
+    // Colours and palette set by COLOUR, GCOL and VDU 19
+    colours: Colours,
+    // Graphics origin set by ORIGIN and VDU 29
+    origin: (i32, i32),
+    // Heading and pen of the turtle FORWARD walks
+    turtle: Turtle,
+    sound: SoundSystem,
+    // Phrases queued by *SAY
+    speech: Speech,
+    // OS calls and the keyboard buffer read by GET, INKEY and INPUT
+    os: OSInterface,
+    // Text screen contents, read back by COPY key editing
+    screen: TextScreen,
+    // Control flow stack for GOSUB/RETURN: the GOSUB or PROC statements
+    return_stack: Vec<Position>,
+    // FOR loops in progress, innermost last
+    for_loops: Vec<ForLoop>,
+    // FOR statement the last NEXT goes back to (None if its loop ended)
+    next_loop: Option<Position>,
+    // REPEAT loop stack: stores the positions of REPEAT statements
+    repeat_stack: Vec<Position>,
+    // WHILE loop stack: stores the positions of WHILE statements
+    while_stack: Vec<Position>,
     // DATA storage: stores all DATA values in program order
     data_values: Vec<DataValue>,
     // DATA line numbers: tracks which line each DATA value came from (parallel to data_values)
@@ -84,24 +242,80 @@ pub struct Executor {
     data_pointer: usize,
     // Current line number being executed (for DATA tracking)
     current_line: Option<u16>,
-    // Random number generator for RND function (wrapped in RefCell for interior mutability)
-    rng: RefCell<rand::rngs::ThreadRng>,
+    // Random number generator for RND function
+    rng: StdRng,
     // Procedure definitions: name -> (line_number, params)
     procedures: HashMap<String, ProcedureDefinition>,
     // Function definitions (DEF FN): name -> (params, expression)
     functions: HashMap<String, FunctionDefinition>,
+    // The program compiled for the VM, which runs multi-line function bodies
+    compiled: Option<Arc<CompiledProgram>>,
     // Local variable stack for PROC/FN scoping
     local_stack: Vec<LocalFrame>,
     // Error handler: line number to jump to on error (None = no handler)
     error_handler: Option<u16>,
     // Last error information (for ERL and ERR functions)
     last_error: Option<ErrorInfo>,
+    // Error handling saved by LOCAL ERROR, for RESTORE ERROR
+    error_stack: Vec<SavedErrorState>,
     // Open file handles: handle number -> FileHandle
     open_files: HashMap<i32, FileHandle>,
-    // Next file handle number to allocate
-    next_file_handle: i32,
     // Output buffer (for testing)
     output: String,
+    // Where PRINT output has been sent by OUTPUT, most recent last (empty =
+    // the screen)
+    output_targets: Vec<OutputTarget>,
+    // Listeners for screen, graphics and sound output
+    events: OutputEvents,
+    // Set while the interpreter runs a program in slices: INPUT and GET stop
+    // with WaitingForInput instead of reading standard input
+    yield_for_input: bool,
+    // The answers an INPUT statement has been typed so far, kept while it
+    // waits for the rest
+    pending_input: Option<PendingInput>,
+    // Instant the executor was created (TIME counts centiseconds from here)
+    start_time: std::time::Instant,
+    // Centiseconds on the virtual clock, which stands in for the real one in
+    // deterministic mode (None = use the real clock)
+    virtual_time: Option<u64>,
+    // Frames a second, the ticks WAIT and *FX 19 wait for
+    refresh_rate: u32,
+    // Current screen MODE (0-7)
+    screen_mode: u8,
+    // Strictness flags from the interpreter configuration
+    strict: StrictFlags,
+    // Bytes reserved by DIM name size, from LOMEM up
+    dim_space: usize,
+    // Whether reals are rounded to five byte floats and printed to nine figures
+    floats: FloatFormat,
+    // When INPUT prints "?"
+    prompts: Prompts,
+    // Maps OPENIN/OPENOUT/OPENUP file names to host paths
+    filenames: FilenameTranslator,
+    // Printed text goes to the graphics cursor (VDU 5) rather than the text
+    // cursor (VDU 4)
+    vdu5: bool,
+    // A VDU control code waiting for its parameters, and those sent so far
+    vdu_queue: Vec<u8>,
+    // Variables made read-only by CONST
+    constants: HashSet<String>,
+    // Report statements, calls, files and errors through `tracing`
+    trace: bool,
+    // Printing, drawing, sound, keyboard, clock, file and memory operations
+    // so far, which show the watchdog that a program is getting somewhere
+    io: u64,
+    // Watches for a program stuck in a loop
+    watchdog: Option<Watchdog>,
+    // Called around each program statement
+    hooks: Hooks,
+    // Print each line number in square brackets as it runs
+    trace_lines: bool,
+    // What programs may do, and how far they may run
+    safe: SafeMode,
+    // Statements run since the run started, and when it started, for the
+    // safe mode limits
+    run_statements: u64,
+    run_started: std::time::Instant,
 }
 
 impl Executor {
@@ -109,31 +323,185 @@ impl Executor {
     pub fn new() -> Self {
         Self {
             variables: VariableStore::new(),
-            memory: MemoryManager::new(),
-            graphics: GraphicsSystem::new(),
+            memory: MemoryManager::for_mode(7),
+            graphics: Graphics::new(),
+            colours: Colours::default(),
+            origin: (0, 0),
+            turtle: Turtle::default(),
+            sound: SoundSystem::new(),
+            speech: Speech::new(),
+            os: OSInterface::new(),
+            screen: TextScreen::default(),
             return_stack: Vec::new(),
             for_loops: Vec::new(),
+            next_loop: None,
             repeat_stack: Vec::new(),
             while_stack: Vec::new(),
             data_values: Vec::new(),
             data_line_numbers: Vec::new(),
             data_pointer: 0,
             current_line: None,
-            rng: RefCell::new(rand::thread_rng()),
+            rng: StdRng::from_entropy(),
             procedures: HashMap::new(),
             functions: HashMap::new(),
+            compiled: None,
             local_stack: Vec::new(),
             error_handler: None,
             last_error: None,
+            error_stack: Vec::new(),
             open_files: HashMap::new(),
-            next_file_handle: 1,
             output: String::new(),
+            output_targets: Vec::new(),
+            events: OutputEvents::default(),
+            yield_for_input: false,
+            pending_input: None,
+            start_time: std::time::Instant::now(),
+            virtual_time: None,
+            refresh_rate: 50,
+            screen_mode: 7,
+            strict: StrictFlags::default(),
+            dim_space: 0,
+            floats: FloatFormat::Double,
+            prompts: Prompts::Auto,
+            filenames: FilenameTranslator::default(),
+            vdu5: false,
+            vdu_queue: Vec::new(),
+            constants: HashSet::new(),
+            trace: false,
+            io: 0,
+            watchdog: None,
+            hooks: Hooks::default(),
+            trace_lines: false,
+            safe: SafeMode::default(),
+            run_statements: 0,
+            run_started: std::time::Instant::now(),
         }
     }
 
     /// Set the current line number (for tests and program execution tracking)
     pub fn set_line_number(&mut self, line_number: Option<u16>) {
         self.current_line = line_number;
+        self.variables.history_mut().set_line(line_number);
+        if let (true, Some(line_number)) = (self.trace, line_number) {
+            trace::statement(line_number);
+        }
+        if let (true, Some(line_number)) = (self.trace_lines, line_number) {
+            // Not progress, or the watchdog would never see a stuck loop again
+            self.write_text(&format!("[{}] ", line_number));
+        }
+    }
+
+    /// Watch for the program getting stuck, from now on (`None` stops
+    /// watching)
+    pub fn set_watchdog(&mut self, watchdog: Option<Watchdog>) {
+        self.watchdog = watchdog;
+    }
+
+    /// Change how many frames a second WAIT and *FX 19 wait for (50, as on
+    /// a PAL BBC Micro, unless set; at least one)
+    pub fn set_refresh_rate(&mut self, frames_per_second: u32) {
+        self.refresh_rate = frames_per_second.max(1);
+    }
+
+    /// Change how long the watchdog waits before asking about a stuck
+    /// program, if there is one (zero stops it asking)
+    pub fn set_watchdog_interval(&mut self, interval: std::time::Duration) {
+        if let Some(watchdog) = &mut self.watchdog {
+            watchdog.set_interval(interval);
+        }
+    }
+
+    /// The hooks called around each program statement
+    pub fn hooks_mut(&mut self) -> &mut Hooks {
+        &mut self.hooks
+    }
+
+    /// Ask the hooks about the statement about to run, returning the one to
+    /// run in its place, if any
+    pub(crate) fn hook_before(
+        &mut self,
+        line_number: u16,
+        statement: &Statement,
+    ) -> Result<Option<Statement>> {
+        if self.hooks.is_empty() {
+            return Ok(None);
+        }
+        self.hooks.before(line_number, statement)
+    }
+
+    /// Tell the hooks about a statement that has run
+    pub(crate) fn hook_after(
+        &mut self,
+        line_number: u16,
+        statement: &Statement,
+        result: &Result<()>,
+    ) {
+        if !self.hooks.is_empty() {
+            self.hooks.after(line_number, statement, result);
+        }
+    }
+
+    /// Print line numbers as they run, or stop
+    pub fn set_trace_lines(&mut self, enabled: bool) {
+        self.trace_lines = enabled;
+    }
+
+    /// A count that goes up whenever the program changes a variable or does
+    /// any input or output
+    pub fn activity(&self) -> u64 {
+        self.io + self.variables.changes()
+    }
+
+    /// Set what programs may do and how far they may run
+    pub fn set_safe_mode(&mut self, safe: SafeMode) {
+        self.safe = safe;
+    }
+
+    /// Start counting a run's statements and time against the safe mode
+    /// limits
+    pub fn start_limits(&mut self) {
+        self.run_statements = 0;
+        self.run_started = std::time::Instant::now();
+    }
+
+    /// Refuse something safe mode does not allow
+    fn check_allowed(&self, what: &str) -> Result<()> {
+        if self.safe.enabled {
+            return Err(BBCBasicError::NotAllowed(what.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Let the watchdog look at the line about to run, giving Escape if it
+    /// is told to break, after checking the run is within its limits
+    pub(crate) fn watch(&mut self) -> Result<()> {
+        self.run_statements += 1;
+        let SafeMode {
+            max_statements,
+            max_seconds,
+            ..
+        } = self.safe;
+        if max_statements != 0 && self.run_statements > max_statements {
+            return Err(BBCBasicError::LimitReached("Statement".to_string()));
+        }
+        if max_seconds != 0 && self.run_started.elapsed().as_secs() >= u64::from(max_seconds) {
+            return Err(BBCBasicError::LimitReached("Time".to_string()));
+        }
+        let activity = self.activity();
+        let (Some(watchdog), Some(line_number)) = (&mut self.watchdog, self.current_line) else {
+            return Ok(());
+        };
+        match watchdog.check(line_number, activity) {
+            WatchdogAction::Break => return Err(BBCBasicError::Escape),
+            WatchdogAction::Trace => self.trace_lines = true,
+            WatchdogAction::Continue => {}
+        }
+        Ok(())
+    }
+
+    /// Line number of the statement being executed
+    pub fn line_number(&self) -> Option<u16> {
+        self.current_line
     }
 
     /// Execute a statement
@@ -145,6 +513,11 @@ impl Executor {
             Statement::ArrayAssignment { name, indices, expression } => {
                 self.execute_array_assignment(name, indices, expression)
             }
+            Statement::IndirectAssignment {
+                op,
+                address,
+                expression,
+            } => self.execute_indirect_assignment(*op, address, expression),
             Statement::Print { items } => self.execute_print(items),
             Statement::End | Statement::Stop | Statement::Quit => {
                 // END, STOP, and QUIT all stop execution
@@ -155,22 +528,20 @@ impl Executor {
                 // Comments do nothing during execution
                 Ok(())
             }
+            Statement::StarCommand { command } => {
+                let output = self.oscli(command)?;
+                self.print_output(&output);
+                Ok(())
+            }
             Statement::Goto { line_number } => self.execute_goto(*line_number),
             Statement::Gosub { .. } => {
                 // GOSUB is handled as control flow in main.rs
                 Ok(())
             }
-            Statement::Return { value } => {
-                // RETURN is handled as control flow in main.rs
-                // TODO: Implement return value storage for multi-line functions
-                // Currently, single-line DEF FN uses the expression directly
-                // Multi-line functions with RETURN expression will need:
-                // 1. Function context stack to store return values
-                // 2. Return value retrieval in function call handler
-                if value.is_some() {
-                    // Expression is present but not yet evaluated/stored
-                    // This will be implemented when adding multi-line function support
-                }
+            Statement::Return { .. } => {
+                // RETURN is handled as control flow in main.rs, and RETURN
+                // with a value ends a multi-line function's body (see
+                // `function_result`)
                 Ok(())
             }
             Statement::For {
@@ -182,6 +553,7 @@ impl Executor {
             Statement::Next { variables } => self.execute_next(variables),
             Statement::Input { variables } => self.execute_input(variables),
             Statement::Dim { arrays } => self.execute_dim(arrays),
+            Statement::DimSpace { name, size } => self.execute_dim_space(name, size),
             Statement::If {
                 condition,
                 then_part,
@@ -190,6 +562,10 @@ impl Executor {
             Statement::Data { values } => self.execute_data(values),
             Statement::Read { variables } => self.execute_read(variables),
             Statement::Restore { line_number } => self.execute_restore(*line_number),
+            Statement::RestoreLine { line } => self.execute_restore_line(line),
+            Statement::RestoreRelative { offset } => self.execute_restore_relative(offset),
+            Statement::RestoreData => self.execute_restore_data(),
+            Statement::RestoreError => self.execute_restore_error(),
             Statement::Repeat => {
                 // REPEAT is handled as control flow in main.rs
                 Ok(())
@@ -206,24 +582,94 @@ impl Executor {
                 // ENDWHILE is handled as control flow in main.rs
                 Ok(())
             }
+            Statement::IfBlock { .. } | Statement::Else | Statement::EndIf => {
+                // Block IF is handled as control flow by the interpreter
+                Ok(())
+            }
             Statement::Cls => self.execute_cls(),
+            Statement::Clear => {
+                self.clear_variables();
+                Ok(())
+            }
+            Statement::Report => {
+                let message = self.report();
+                self.print_output(&message);
+                Ok(())
+            }
+            Statement::Vdu { items } => self.execute_vdu(items),
             // Graphics statements
             Statement::Plot { mode, x, y } => self.execute_plot(mode, x, y),
-            Statement::Move { x, y } => self.execute_move(x, y),
-            Statement::Draw { x, y } => self.execute_draw(x, y),
-            Statement::Circle { x, y, radius } => self.execute_circle(x, y, radius),
+            Statement::Move { x, y, relative } => self.execute_move(x, y, *relative),
+            Statement::Draw { x, y, relative } => self.execute_draw(x, y, *relative),
+            Statement::Circle {
+                x,
+                y,
+                radius,
+                filled,
+            } => self.execute_circle(x, y, radius, *filled),
             Statement::Gcol { mode, color } => self.execute_gcol(mode, color),
+            Statement::Forward { distance } => self.execute_forward(distance),
+            Statement::Turn { degrees } => {
+                let degrees = self.eval_real(degrees)?;
+                self.turtle.turn(degrees);
+                Ok(())
+            }
+            Statement::PenUp => {
+                self.turtle.set_pen(false);
+                Ok(())
+            }
+            Statement::PenDown { colour } => {
+                self.turtle.set_pen(true);
+                match colour {
+                    Some(colour) => self.execute_gcol(&Expression::Integer(0), colour),
+                    None => Ok(()),
+                }
+            }
+            Statement::Colour { colour } => {
+                let colour = self.eval_integer(colour)?;
+                self.vdu(&[17, colour as u8])
+            }
+            Statement::Call { address } => {
+                self.check_allowed("CALL")?;
+                let address = self.eval_integer(address)?;
+                self.os_call(address).map(|_| ())
+            }
             Statement::Clg => self.execute_clg(),
-            Statement::Ellipse { x, y, major, minor } => self.execute_ellipse(x, y, major, minor),
+            Statement::Mode { mode } => self.execute_mode(mode),
+            Statement::Ellipse {
+                x,
+                y,
+                major,
+                minor,
+                filled,
+            } => self.execute_ellipse(x, y, major, minor, *filled),
             Statement::Rectangle {
                 x1,
                 y1,
                 width,
                 height,
                 filled,
-            } => self.execute_rectangle(x1, y1, width, height, *filled),
+                target,
+            } => self.execute_rectangle(x1, y1, width, height, *filled, target.as_ref()),
             Statement::Fill { x, y } => self.execute_fill(x, y),
             Statement::Origin { x, y } => self.execute_origin(x, y),
+            Statement::Sound {
+                channel,
+                amplitude,
+                pitch,
+                duration,
+            } => self.execute_sound(channel, amplitude, pitch, duration),
+            Statement::Envelope { params } => self.execute_envelope(params),
+            Statement::Wait { centiseconds } => self.execute_wait(centiseconds.as_ref()),
+            Statement::Mouse { x, y, buttons } => self.execute_mouse(x, y, buttons),
+            Statement::MousePointer { visible } => {
+                self.os.set_pointer_visible(*visible);
+                Ok(())
+            }
+            Statement::Const { name, expression } => self.execute_const(name, expression),
+            Statement::Assert { condition, message } => {
+                self.execute_assert(condition, message.as_ref())
+            }
             Statement::DefProc { .. } => {
                 // DEF PROC is handled during procedure collection in main.rs
                 Ok(())
@@ -232,13 +678,24 @@ impl Executor {
                 name,
                 params,
                 expression,
-            } => self.execute_def_fn(name, params, expression),
+            } => self.execute_def_fn(name, params, expression.as_ref()),
             Statement::EndProc => {
                 // ENDPROC is handled as control flow in main.rs
                 Ok(())
             }
             Statement::Local { variables } => self.execute_local(variables),
-            Statement::ProcCall { .. } => {
+            Statement::LocalData => {
+                let data_pointer = self.data_pointer;
+                self.current_local_frame()?
+                    .data_pointer
+                    .get_or_insert(data_pointer);
+                Ok(())
+            }
+            Statement::LocalError => {
+                self.execute_local_error();
+                Ok(())
+            }
+            Statement::ProcCall { .. } | Statement::OnProc { .. } => {
                 // PROC calls are handled as control flow in main.rs
                 Ok(())
             }
@@ -255,6 +712,22 @@ impl Executor {
                 self.execute_input_file(handle, variables)
             }
             Statement::CloseFile { handle } => self.execute_close_file(handle),
+            Statement::Output { handle } => self.execute_output(handle.as_ref()),
+            Statement::BputFile {
+                handle,
+                value,
+                newline,
+            } => self.execute_bput_file(handle, value, *newline),
+            Statement::PtrFile { handle, value } => {
+                let handle = self.eval_integer(handle)?;
+                let position = self.eval_integer(value)?;
+                self.set_ptr(handle, position)
+            }
+            Statement::ExtFile { handle, value } => {
+                let handle = self.eval_integer(handle)?;
+                let length = self.eval_integer(value)?;
+                self.set_ext(handle, length)
+            }
             _ => {
                 // Other statements not implemented yet
                 Ok(())
@@ -264,6 +737,7 @@ impl Executor {
 
     /// Execute an assignment statement
     fn execute_assignment(&mut self, target: &str, expression: &Expression) -> Result<()> {
+        self.check_constant(target)?;
         // Determine variable type from suffix
         if target.ends_with('%') {
             let value = self.eval_integer(expression)?;
@@ -289,15 +763,7 @@ impl Executor {
     ) -> Result<()> {
         use crate::variables::Variable;
 
-        // Evaluate all indices to integers
-        let mut index_values = Vec::with_capacity(indices.len());
-        for index_expr in indices {
-            let idx = self.eval_integer(index_expr)?;
-            if idx < 0 {
-                return Err(BBCBasicError::SubscriptOutOfRange);
-            }
-            index_values.push(idx as usize);
-        }
+        let index_values = self.eval_indices(indices)?;
 
         // Determine the array type and evaluate the expression accordingly
         let value = if name.ends_with('%') {
@@ -312,34 +778,85 @@ impl Executor {
         self.variables.set_array_element(name, &index_values, value)
     }
 
+    /// Evaluate the subscripts of an array element
+    fn eval_indices(&mut self, indices: &[Expression]) -> Result<Vec<usize>> {
+        let mut index_values = Vec::with_capacity(indices.len());
+        for index_expr in indices {
+            let idx = self.eval_integer(index_expr)?;
+            if idx < 0 {
+                return Err(BBCBasicError::SubscriptOutOfRange);
+            }
+            index_values.push(idx as usize);
+        }
+        Ok(index_values)
+    }
+
+    /// The variable or array element a READ, INPUT, FOR or NEXT names,
+    /// working out an element's subscripts now
+    fn resolve_target(&mut self, target: &Expression) -> Result<Target> {
+        match target {
+            Expression::Variable(name) => Ok(Target::Variable(name.clone())),
+            Expression::ArrayAccess { name, indices } => Ok(Target::Element {
+                name: name.clone(),
+                indices: self.eval_indices(indices)?,
+            }),
+            _ => Err(BBCBasicError::SyntaxError {
+                message: "Expected variable name".to_string(),
+                line: None,
+            }),
+        }
+    }
+
+    /// Give a variable or array element set by READ or INPUT its value,
+    /// already of the right type, working out the element's subscripts now
+    /// so that they can use variables set earlier in the same statement
+    fn assign_target(&mut self, target: &Expression, value: Variable) -> Result<()> {
+        let target = self.resolve_target(target)?;
+        self.variables.set_target(&target, value)
+    }
+
     /// Execute a PRINT statement
     fn execute_print(&mut self, items: &[crate::parser::PrintItem]) -> Result<()> {
         use crate::parser::PrintItem;
 
+        // Numbers are right-justified in a field as wide as the low byte of
+        // @%, except from a semicolon up to the next comma
+        let width = (self.variables.get_integer_var("@%").unwrap_or_default() & 0xFF) as usize;
+        let mut justify = true;
         for item in items {
             match item {
                 PrintItem::Expression(expr) => {
                     // Evaluate expression and print it
-                    let output = self.format_expression(expr)?;
-                    self.print_output(&output);
+                    let output = match self.evaluate(expr)? {
+                        Value::String(text) => text,
+                        value => justified(self.format_value(value), width, justify),
+                    };
+                    self.print_text(&output)?;
+                }
+                PrintItem::Hex(expr) => {
+                    // Negative numbers print as their 32 bit two's complement
+                    let value = match self.eval_integer(expr) {
+                        Ok(value) => value,
+                        Err(_) => real_to_integer(self.eval_real(expr)?)?,
+                    };
+                    let output = justified(format!("{:X}", value as u32), width, justify);
+                    self.print_text(&output)?;
                 }
                 PrintItem::Semicolon => {
-                    // Semicolon suppresses newline (do nothing)
+                    // Semicolon suppresses newline and the field numbers are
+                    // printed in
+                    justify = false;
                 }
                 PrintItem::Comma => {
-                    // Comma moves to next tab position (TAB(10) intervals)
-                    #[cfg(test)]
-                    {
-                        let current_len = self.output.len();
-                        let next_tab = ((current_len / 10) + 1) * 10;
-                        let spaces = next_tab - current_len;
-                        self.output.push_str(&" ".repeat(spaces));
-                    }
-                    #[cfg(not(test))]
-                    {
-                        print!("\t");
+                    // Comma moves to the start of the next field, unless the
+                    // cursor is at one already
+                    let column = self.print_column();
+                    if width > 0 {
+                        self.print_text(&" ".repeat((width - column % width) % width))?;
                     }
+                    justify = true;
                 }
+                PrintItem::Apostrophe => self.print_text("\n")?,
                 PrintItem::Tab(expr) => {
                     // TAB accepts both integer and real, truncating real to integer
                     let pos = if let Ok(int_val) = self.eval_integer(expr) {
@@ -348,18 +865,8 @@ impl Executor {
                         let real_val = self.eval_real(expr)?;
                         real_val.floor().max(0.0) as usize
                     };
-                    #[cfg(test)]
-                    {
-                        let current_len = self.output.len();
-                        if pos > current_len {
-                            self.output.push_str(&" ".repeat(pos - current_len));
-                        }
-                    }
-                    #[cfg(not(test))]
-                    {
-                        // Move to absolute position (simplified)
-                        print!("{}", " ".repeat(pos));
-                    }
+                    let column = self.print_column();
+                    self.print_text(&" ".repeat(pos.saturating_sub(column)))?;
                 }
                 PrintItem::Spc(expr) => {
                     // SPC accepts both integer and real, truncating real to integer
@@ -369,49 +876,104 @@ impl Executor {
                         let real_val = self.eval_real(expr)?;
                         real_val.floor().max(0.0) as usize
                     };
-                    self.print_output(&" ".repeat(count));
+                    self.print_text(&" ".repeat(count))?;
                 }
             }
         }
 
-        // Add newline unless last item was semicolon
-        if items.is_empty() || !matches!(items.last(), Some(PrintItem::Semicolon)) {
-            #[cfg(test)]
-            {
-                self.output.push('\n');
-            }
-            #[cfg(not(test))]
-            {
-                println!();
-            }
+        // Add newline unless the last item was a semicolon or comma, which
+        // leave the cursor where they put it
+        if !matches!(items.last(), Some(PrintItem::Semicolon | PrintItem::Comma)) {
+            self.print_text("\n")?;
+        }
+
+        Ok(())
+    }
+
+    /// Send PRINT's text to the screen, or to the file OUTPUT chose
+    fn print_text(&mut self, text: &str) -> Result<()> {
+        let Some(OutputTarget::File { handle, column }) = self.output_targets.last_mut() else {
+            self.print_output(text);
+            return Ok(());
+        };
+        *column = match text.rfind('\n') {
+            Some(newline) => text[newline + 1..].chars().count(),
+            None => *column + text.chars().count(),
+        };
+        let handle = *handle;
+        self.write_file_bytes(handle, &crate::charset::to_bytes(text))
+    }
+
+    /// Column PRINT has reached, for commas and TAB
+    fn print_column(&self) -> usize {
+        match self.output_targets.last() {
+            Some(OutputTarget::File { column, .. }) => *column,
+            _ => self.screen.cursor().0,
         }
+    }
 
+    /// Execute OUTPUT statement - send PRINT output to a file (`OUTPUT #ch`)
+    /// or the screen (`OUTPUT #0`) until the next OUTPUT, or go back to
+    /// where it went before that (`OUTPUT`)
+    fn execute_output(&mut self, handle: Option<&Expression>) -> Result<()> {
+        let Some(handle) = handle else {
+            self.output_targets.pop();
+            return Ok(());
+        };
+        let target = match self.eval_integer(handle)? {
+            0 => OutputTarget::Screen,
+            handle if self.open_files.contains_key(&handle) => {
+                OutputTarget::File { handle, column: 0 }
+            }
+            handle => return Err(BBCBasicError::ChannelNotOpen(handle)),
+        };
+        self.output_targets.push(target);
         Ok(())
     }
 
-    /// Format an expression for printing
-    fn format_expression(&mut self, expr: &Expression) -> Result<String> {
+    /// Send PRINT output to the screen again, forgetting every OUTPUT
+    pub fn reset_output(&mut self) {
+        self.output_targets.clear();
+    }
+
+    /// Format a value for printing
+    fn format_value(&self, value: Value) -> String {
+        match value {
+            Value::Integer(value) => value.to_string(),
+            Value::Real(value) => self.real_text(value),
+            Value::String(value) => value,
+        }
+    }
+
+    /// Evaluate an expression of any type, as PRINT does: by the type of a
+    /// literal or variable, or else as the first of integer, real and string
+    /// that it gives
+    pub fn evaluate(&mut self, expr: &Expression) -> Result<Value> {
         match expr {
-            Expression::Integer(_) => Ok(self.eval_integer(expr)?.to_string()),
-            Expression::Real(_) => Ok(self.eval_real(expr)?.to_string()),
-            Expression::String(_) => self.eval_string(expr),
+            Expression::Integer(_) => self.eval_integer(expr).map(Value::Integer),
+            Expression::Real(_) => self.eval_real(expr).map(Value::Real),
+            Expression::String(_) => self.eval_string(expr).map(Value::String),
             Expression::Variable(name) => {
-                if name.ends_with('%') {
-                    Ok(self.eval_integer(expr)?.to_string())
+                if name.ends_with('%') || PSEUDO_VARIABLES.contains(&name.as_str()) {
+                    self.eval_integer(expr).map(Value::Integer)
                 } else if name.ends_with('$') {
-                    self.eval_string(expr)
+                    self.eval_string(expr).map(Value::String)
                 } else {
-                    Ok(self.eval_real(expr)?.to_string())
+                    self.eval_real(expr).map(Value::Real)
                 }
             }
+            // A function's body runs once, whatever its result is
+            Expression::FunctionCall { name, args } if self.functions.contains_key(name) => {
+                self.call_function(name, args, Self::evaluate)
+            }
             _ => {
                 // Try to evaluate as different types
                 if let Ok(val) = self.eval_integer(expr) {
-                    Ok(val.to_string())
+                    Ok(Value::Integer(val))
                 } else if let Ok(val) = self.eval_real(expr) {
-                    Ok(val.to_string())
+                    Ok(Value::Real(val))
                 } else if let Ok(val) = self.eval_string(expr) {
-                    Ok(val)
+                    Ok(Value::String(val))
                 } else {
                     Err(BBCBasicError::TypeMismatch)
                 }
@@ -419,13 +981,142 @@ impl Executor {
         }
     }
 
-    /// Print output (to buffer in test mode, to stdout in production)
+    /// Print output: to the OSWRCH hooks, then the screen, the output buffer
+    /// and any listeners
     fn print_output(&mut self, text: &str) {
+        self.io += 1;
+        self.write_text(text);
+    }
+
+    /// Send text to the screen and output listeners
+    fn write_text(&mut self, text: &str) {
+        let mut bytes = Vec::with_capacity(text.len());
+        for c in text.chars() {
+            match c {
+                '\n' => bytes.extend([10, 13]),
+                _ => bytes.push(crate::charset::code(c)),
+            }
+        }
+        self.events.write(&bytes);
+        self.display(text);
+    }
+
+    /// Show printed text, at the text or graphics cursor
+    fn display(&mut self, text: &str) {
+        if self.vdu5 {
+            self.print_graphics_text(text);
+            return;
+        }
+        self.screen.write_str(text);
         self.output.push_str(text);
-        #[cfg(not(test))]
-        {
-            print!("{}", text);
+        self.events.emit(OutputEvent::Print(text.to_string()));
+    }
+
+    /// Draw printed text at the graphics cursor in the system font (VDU 5)
+    ///
+    /// Characters are the size of the mode's text cells and are plotted in
+    /// the GCOL colour; the cursor is their top-left corner.
+    fn print_graphics_text(&mut self, text: &str) {
+        let width = (self.graphics.width() / self.screen.width()) as i32;
+        let height = (self.graphics.height() / self.screen.height()) as i32;
+        for c in text.chars() {
+            let (x, y) = self.graphics.get_position();
+            match c {
+                '\n' => self.graphics.move_to(0, y - height),
+                _ => match crate::charset::code(c) {
+                    8 => self.graphics.move_by(-width, 0),
+                    10 => self.graphics.move_by(0, -height),
+                    11 => self.graphics.move_by(0, height),
+                    13 => self.graphics.move_to(0, y),
+                    0..=31 | 127 => {}
+                    code => {
+                        self.graphics.draw_char(code, width, height);
+                        self.emit_graphics(GraphicsOp::Char { x, y, code });
+                    }
+                },
+            }
+        }
+    }
+
+    /// Execute VDU statement - send its bytes to the VDU driver
+    fn execute_vdu(&mut self, items: &[VduItem]) -> Result<()> {
+        let mut bytes = Vec::new();
+        for item in items {
+            match item {
+                VduItem::Byte(expression) => bytes.push(self.eval_integer(expression)? as u8),
+                VduItem::Word(expression) => {
+                    let word = self.eval_integer(expression)? as u16;
+                    bytes.extend(word.to_le_bytes());
+                }
+            }
+        }
+        self.vdu(&bytes)
+    }
+
+    /// Send bytes to the VDU driver
+    ///
+    /// Control codes take their parameters from the bytes that follow, which
+    /// may come in later calls. VDU 4 and 5 choose whether text goes to the
+    /// text or graphics cursor; CLS, CLG, COLOUR, GCOL, MODE, PLOT and
+    /// ORIGIN (12, 16, 17, 18, 22, 25 and 29) act as those statements do, 19
+    /// changes the palette and 20 restores the default colours, and other
+    /// control codes are ignored along with their parameters. Everything else is
+    /// printed. The OSWRCH hooks see every byte as it was sent.
+    pub fn vdu(&mut self, bytes: &[u8]) -> Result<()> {
+        self.io += 1;
+        self.events.write(bytes);
+        for &byte in bytes {
+            self.vdu_queue.push(byte);
+            if self.vdu_queue.len() <= vdu_parameter_count(self.vdu_queue[0]) {
+                continue;
+            }
+            let sequence = std::mem::take(&mut self.vdu_queue);
+            let word = |at: usize| i32::from(i16::from_le_bytes([sequence[at], sequence[at + 1]]));
+            match sequence[0] {
+                4 => self.vdu5 = false,
+                5 => self.vdu5 = true,
+                12 => self.clear_screen(),
+                16 => self.clear_graphics(),
+                17 => self.colours.set_text(sequence[1]),
+                18 => {
+                    let (mode, colour) = (sequence[1], sequence[2]);
+                    self.colours.set_graphics(mode, colour);
+                    self.graphics.set_color(mode, colour);
+                    self.emit_graphics(GraphicsOp::Gcol { mode, colour });
+                }
+                19 => self.colours.set_palette(sequence[1], sequence[2]),
+                20 => self.colours.reset(),
+                22 => {
+                    self.clear_screen();
+                    self.set_mode(i32::from(sequence[1]))?;
+                }
+                25 => {
+                    let (mode, x, y) = (sequence[1], word(2), word(4));
+                    self.graphics.plot(mode, x, y);
+                    self.emit_graphics(GraphicsOp::Plot { mode, x, y });
+                }
+                29 => {
+                    let (x, y) = (word(1), word(3));
+                    self.origin = (x, y);
+                    self.graphics.set_origin(x, y);
+                    self.emit_graphics(GraphicsOp::Origin { x, y });
+                }
+                // Bell, cursor movement and printable characters
+                code @ (7 | 8 | 10 | 11 | 13 | 32..) => self.display(&char::from(code).to_string()),
+                _ => {}
+            }
         }
+        Ok(())
+    }
+
+    /// Send future output events to `listener` as well
+    pub fn subscribe(&mut self, listener: Box<dyn OutputListener>) {
+        self.events.subscribe(listener);
+    }
+
+    /// Send every future byte written to the VDU drivers to `writer` as well
+    pub fn subscribe_oswrch(&mut self, writer: Box<dyn Oswrch>) {
+        self.events.subscribe_oswrch(writer);
     }
 
     /// Get output buffer (for testing)
@@ -447,15 +1138,17 @@ impl Executor {
     }
 
     /// Execute GOSUB statement
+    #[allow(dead_code)]
     fn execute_gosub(&mut self, line_number: u16) -> Result<()> {
         // Push return address to stack
         // In a real implementation, we'd push the NEXT line after this GOSUB
         // For now, we push the target line (simplified)
-        self.return_stack.push(line_number);
+        self.return_stack.push(Position::from(line_number));
         Ok(())
     }
 
     /// Execute RETURN statement
+    #[allow(dead_code)]
     fn execute_return(&mut self) -> Result<()> {
         // Pop return address from stack
         if self.return_stack.is_empty() {
@@ -467,128 +1160,233 @@ impl Executor {
     }
 
     /// Execute FOR statement
+    ///
+    /// The loop counts with a variable or an array element (whose
+    /// subscripts are worked out once, here): in whole numbers if it is an
+    /// integer, else in reals.
     fn execute_for(
         &mut self,
-        variable: &str,
+        variable: &Expression,
         start: &Expression,
         end: &Expression,
         step: Option<&Expression>,
     ) -> Result<()> {
-        // Evaluate start, end, and step values
-        let start_val = self.eval_integer(start)?;
-        let end_val = self.eval_integer(end)?;
-        let step_val = if let Some(step_expr) = step {
-            self.eval_integer(step_expr)?
-        } else {
-            1 // Default step is 1
+        let variable = self.resolve_target(variable)?;
+        self.check_constant(variable.name())?;
+
+        // Evaluate start, end, and step values (the default step is 1)
+        let (start, limit, step) = match variable.name().chars().last() {
+            Some('$') => return Err(BBCBasicError::TypeMismatch),
+            Some('%') => {
+                let start = self.eval_integer(start)?;
+                let limit = self.eval_integer(end)?;
+                let step = step.map_or(Ok(1), |step| self.eval_integer(step))?;
+                (Variable::Integer(start), f64::from(limit), f64::from(step))
+            }
+            _ => {
+                let start = self.eval_real(start)?;
+                let limit = self.eval_real(end)?;
+                let step = step.map_or(Ok(1.0), |step| self.eval_real(step))?;
+                (Variable::Real(start), limit, step)
+            }
         };
 
         // Set loop variable to start value
-        self.variables
-            .set_integer_var(variable.to_string(), start_val);
-
-        // Store loop state: (variable, end_value, step_value, loop_line)
-        // loop_line would be the line number in a real program
-        self.for_loops
-            .push((variable.to_string(), end_val, step_val, 0));
+        self.variables.set_target(&variable, start)?;
+
+        // The FOR position is filled in by set_for_loop_position
+        self.for_loops.push(ForLoop {
+            variable,
+            limit,
+            step,
+            position: Position::default(),
+        });
 
         Ok(())
     }
 
     /// Execute NEXT statement
-    fn execute_next(&mut self, variables: &[String]) -> Result<()> {
-        // If no variables specified, use the most recent FOR loop
-        let var_name = if variables.is_empty() {
-            if let Some((name, _, _, _)) = self.for_loops.last() {
-                name.clone()
-            } else {
-                return Err(BBCBasicError::BadCall);
+    ///
+    /// NEXT J%,I% is NEXT J% and then, once that loop is done, NEXT I%.
+    fn execute_next(&mut self, variables: &[Expression]) -> Result<()> {
+        if variables.is_empty() {
+            return self.next_iteration(None);
+        }
+        for variable in variables {
+            self.next_iteration(Some(variable))?;
+            if self.next_loop.is_some() {
+                break;
             }
-        } else {
-            variables[0].clone()
-        };
-
-        // Find the matching FOR loop
-        let loop_index = self
-            .for_loops
-            .iter()
-            .rposition(|(name, _, _, _)| name == &var_name)
-            .ok_or(BBCBasicError::BadCall)?;
+        }
+        Ok(())
+    }
 
-        let (_, end_val, step_val, _) = self.for_loops[loop_index];
+    /// Step the FOR loop of a NEXT variable, or the innermost loop, setting
+    /// where to loop back to if it has not finished
+    ///
+    /// Loops inside the one named are left, as when a NEXT I% skips the
+    /// NEXT J% of an inner loop.
+    fn next_iteration(&mut self, variable: Option<&Expression>) -> Result<()> {
+        // If no variable is specified, use the most recent FOR loop
+        let loop_index = match variable {
+            None => self
+                .for_loops
+                .len()
+                .checked_sub(1)
+                .ok_or(BBCBasicError::BadCall)?,
+            Some(variable) => {
+                let variable = self.resolve_target(variable)?;
+                self.for_loops
+                    .iter()
+                    .rposition(|active| active.variable == variable)
+                    .ok_or(BBCBasicError::BadCall)?
+            }
+        };
+        self.for_loops.truncate(loop_index + 1);
 
-        // Get current loop variable value
-        let current_val = self
-            .variables
-            .get_integer_var(&var_name)
-            .ok_or_else(|| BBCBasicError::NoSuchVariable(var_name.clone()))?;
+        let active = &self.for_loops[loop_index];
+        let (step, limit) = (active.step, active.limit);
 
         // Increment the loop variable
-        let next_val = current_val + step_val;
-        self.variables.set_integer_var(var_name.clone(), next_val);
+        let variable = active.variable.clone();
+        let (next_val, next) = match self.variables.get_target(&variable)? {
+            Variable::Integer(value) => {
+                let next_val = f64::from(value) + step;
+                (next_val, Variable::Integer(next_val as i32))
+            }
+            Variable::Real(value) => (value + step, Variable::Real(value + step)),
+            _ => return Err(BBCBasicError::TypeMismatch),
+        };
+        self.variables.set_target(&variable, next)?;
 
         // Check if loop is complete
-        let loop_complete = if step_val > 0 {
-            next_val > end_val
+        let loop_complete = if step > 0.0 {
+            next_val > limit
         } else {
-            next_val < end_val
+            next_val < limit
         };
 
-        if loop_complete {
+        self.next_loop = if loop_complete {
             // Remove the loop from the stack
-            self.for_loops.remove(loop_index);
-        }
-        // In a real program, we'd jump back to the FOR statement line if not complete
+            self.for_loops.pop();
+            None
+        } else {
+            Some(self.for_loops[loop_index].position)
+        };
 
         Ok(())
     }
 
     /// Execute INPUT statement
-    fn execute_input(&mut self, _variables: &[String]) -> Result<()> {
-        // In a real implementation, this would read from stdin
-        // For now, we'll just set default values for testing
-        // Full implementation requires I/O handling
-        #[cfg(test)]
-        {
-            // In test mode, set variables to test values
-            for var in _variables {
-                if var.ends_with('%') {
-                    self.variables.set_integer_var(var.clone(), 0);
-                } else if var.ends_with('$') {
-                    self.variables.set_string_var(var.clone(), String::new())?;
-                } else {
-                    self.variables.set_real_var(var.clone(), 0.0);
+    ///
+    /// Answers are separated by commas, and lines are read until every
+    /// variable has one; see [`input_answers`].
+    fn execute_input(&mut self, variables: &[Expression]) -> Result<()> {
+        variables
+            .iter()
+            .try_for_each(|var| self.check_constant(target_name(var)))?;
+        let lines = self
+            .read_input_lines(|lines| input_answers(variables, lines).len() == variables.len())?;
+        for (var, answer) in variables.iter().zip(input_answers(variables, &lines)) {
+            self.assign_target(var, answer)?;
+        }
+        Ok(())
+    }
+
+    /// Read lines for an INPUT statement until `complete` says there are
+    /// enough
+    ///
+    /// When yielding for input, keys are taken only from the keyboard
+    /// buffer. If it runs out before the last line is finished, the lines so
+    /// far are kept and the statement stops with
+    /// [`BBCBasicError::WaitingForInput`]; running it again once more keys
+    /// have been inserted carries on where it left off.
+    fn read_input_lines(&mut self, complete: impl Fn(&[String]) -> bool) -> Result<Vec<String>> {
+        if !self.yield_for_input {
+            let mut lines = Vec::new();
+            while !complete(&lines) {
+                lines.push(self.read_input_line()?);
+            }
+            return Ok(lines);
+        }
+        let mut pending = self.pending_input.take().unwrap_or_default();
+        while !complete(&pending.lines) {
+            let Some(key) = self.os.keyboard_mut().read() else {
+                if !pending.prompted {
+                    self.print_output("? ");
+                    pending.prompted = true;
                 }
+                self.pending_input = Some(pending);
+                return Err(BBCBasicError::WaitingForInput);
+            };
+            if let Some(line) = pending.editor.key(key, &mut self.screen) {
+                pending.lines.push(line);
+                pending.editor = LineEditor::new();
+                pending.prompted = false;
             }
         }
-        #[cfg(not(test))]
-        {
-            // Production mode: read from stdin
-            use std::io::{self, Write};
+        Ok(pending.lines)
+    }
 
-            for var in _variables {
-                print!("? ");
-                io::stdout().flush().unwrap();
+    /// Make INPUT and GET stop with [`BBCBasicError::WaitingForInput`] when
+    /// the keyboard buffer is empty, rather than reading standard input
+    pub fn set_yield_for_input(&mut self, enabled: bool) {
+        self.yield_for_input = enabled;
+    }
 
-                let mut input = String::new();
-                io::stdin().read_line(&mut input).unwrap();
-                let input = input.trim();
+    /// Forget the answers typed to an INPUT statement that was waiting for
+    /// more (when the program is run again)
+    pub fn cancel_input(&mut self) {
+        self.pending_input = None;
+    }
 
-                if var.ends_with('%') {
-                    if let Ok(val) = input.parse::<i32>() {
-                        self.variables.set_integer_var(var.clone(), val);
-                    }
-                } else if var.ends_with('$') {
-                    self.variables
-                        .set_string_var(var.clone(), input.to_string())?;
-                } else {
-                    if let Ok(val) = input.parse::<f64>() {
-                        self.variables.set_real_var(var.clone(), val);
-                    }
-                }
+    /// Read a line typed in answer to INPUT through the line editor
+    ///
+    /// Keys already in the keyboard buffer are typed first, so that inserted
+    /// keystrokes (including COPY editing) can answer INPUT. When they run
+    /// out, the rest of the line is read from the OS's line input; if nothing
+    /// more will be typed, INPUT stops with Eof, which ON ERROR can trap.
+    fn read_input_line(&mut self) -> Result<String> {
+        let mut editor = LineEditor::new();
+        while let Some(key) = self.os.keyboard_mut().read() {
+            if let Some(line) = editor.key(key, &mut self.screen) {
+                return Ok(line);
             }
         }
-        Ok(())
+
+        let prompt = match self.prompts {
+            Prompts::Auto => self.os.is_interactive(),
+            Prompts::On => true,
+            Prompts::Off => false,
+        };
+        if prompt {
+            self.print_output("? ");
+        }
+        let typed = self.os.read_line().ok_or(BBCBasicError::Eof)?;
+        for key in keys_from_terminal(&typed) {
+            editor.key(key, &mut self.screen);
+        }
+        Ok(editor.key(13, &mut self.screen).unwrap_or_default())
+    }
+
+    /// Edit a line typed at a terminal against the text screen, so that the
+    /// cursor keys and COPY can re-enter text already on the screen
+    pub fn edit_line(&mut self, typed: &str) -> String {
+        let mut editor = LineEditor::new();
+        for key in keys_from_terminal(typed) {
+            editor.key(key, &mut self.screen);
+        }
+        editor.key(13, &mut self.screen).unwrap_or_default()
+    }
+
+    /// Get the text screen (for COPY key editing and display)
+    pub fn screen(&self) -> &TextScreen {
+        &self.screen
+    }
+
+    /// Get the text screen mutably (for echoing typed command lines)
+    pub fn screen_mut(&mut self) -> &mut TextScreen {
+        &mut self.screen
     }
 
     /// Execute DIM statement
@@ -614,6 +1412,19 @@ impl Executor {
                 VarType::Real
             };
 
+            // Claim the heap space first, so an array that cannot fit below
+            // HIMEM fails with "No room" before it is allocated
+            let element_size = if var_type == VarType::Real { 5 } else { 4 };
+            let array_size = dim_sizes
+                .iter()
+                .try_fold(element_size, |size: usize, &d| size.checked_mul(d))
+                .ok_or(BBCBasicError::NoRoom)?;
+            let heap = self.heap_size() + 2 + name.len() + 1 + 2 * dim_sizes.len();
+            self.memory.set_layout(
+                self.memory.allocated(AllocationType::Program),
+                heap.saturating_add(array_size),
+            )?;
+
             // Create array in variable store
             self.variables
                 .dim_array(name.clone(), dim_sizes, var_type)?;
@@ -621,9 +1432,157 @@ impl Executor {
         Ok(())
     }
 
-    /// Execute an IF statement
-    fn execute_if(
-        &mut self,
+    /// Execute DIM name size, reserving size + 1 bytes of memory and setting
+    /// the variable to their address
+    fn execute_dim_space(&mut self, name: &str, size: &Expression) -> Result<()> {
+        self.check_constant(name)?;
+        if name.ends_with('$') {
+            return Err(BBCBasicError::TypeMismatch);
+        }
+        let size = self.eval_integer(size)?;
+        if size < -1 {
+            return Err(BBCBasicError::SubscriptOutOfRange);
+        }
+        let address = self.memory.get_lomem() as usize + self.dim_space;
+        let bytes = size as usize + 1;
+        self.memory.set_layout(
+            self.memory.allocated(AllocationType::Program),
+            self.heap_size().saturating_add(bytes),
+        )?;
+        self.dim_space += bytes;
+        if name.ends_with('%') {
+            self.variables
+                .set_integer_var(name.to_string(), address as i32);
+        } else {
+            self.variables
+                .set_real_var(name.to_string(), address as f64);
+        }
+        Ok(())
+    }
+
+    /// Bytes the variables and DIMmed blocks of memory take on the heap
+    fn heap_size(&self) -> usize {
+        self.variables.heap_size() + self.dim_space
+    }
+
+    /// Execute an assignment to memory (?A% = 5, A%!4 = X%, $A% = "TEXT")
+    fn execute_indirect_assignment(
+        &mut self,
+        op: Indirection,
+        address: &Expression,
+        expression: &Expression,
+    ) -> Result<()> {
+        self.io += 1;
+        match op {
+            Indirection::Byte => {
+                let value = self.eval_integer(expression)?;
+                let address = self.indirect_address(address, 1)?;
+                self.write_memory(address, &[value as u8])
+            }
+            Indirection::Word => {
+                let value = self.eval_integer(expression)?;
+                let address = self.indirect_address(address, 4)?;
+                self.write_memory(address, &value.to_le_bytes())
+            }
+            Indirection::String => {
+                let mut bytes = crate::charset::to_bytes(&self.eval_string(expression)?);
+                bytes.push(b'\r');
+                let address = self.indirect_address(address, bytes.len())?;
+                self.write_memory(address, &bytes)
+            }
+        }
+    }
+
+    /// Read bytes of memory, with the resident integers in their workspace
+    /// at &400 as they stand
+    pub fn read_memory(&self, address: u16, length: usize) -> Result<Vec<u8>> {
+        let mut bytes = self.memory.block(address, length)?.to_vec();
+        let range = usize::from(address)..usize::from(address) + length;
+        for (at, value) in self.resident_integers() {
+            for (offset, byte) in value.to_le_bytes().into_iter().enumerate() {
+                if range.contains(&(at + offset)) {
+                    bytes[at + offset - range.start] = byte;
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Write bytes to memory, setting any resident integers they fall on
+    pub fn write_memory(&mut self, address: u16, bytes: &[u8]) -> Result<()> {
+        self.memory.load_block(address, bytes)?;
+        let range = usize::from(address)..usize::from(address) + bytes.len();
+        let residents: Vec<(usize, i32)> = self.resident_integers().collect();
+        for (index, (at, value)) in residents.into_iter().enumerate() {
+            let mut value = value.to_le_bytes();
+            let mut written = false;
+            for (offset, byte) in value.iter_mut().enumerate() {
+                if range.contains(&(at + offset)) {
+                    *byte = bytes[at + offset - range.start];
+                    written = true;
+                }
+            }
+            if written {
+                self.variables
+                    .set_integer_var(resident_integer_name(index), i32::from_le_bytes(value));
+            }
+        }
+        Ok(())
+    }
+
+    /// The address and value of each resident integer, from @% at &400
+    fn resident_integers(&self) -> impl Iterator<Item = (usize, i32)> + '_ {
+        (0..RESIDENT_INTEGERS).map(|index| {
+            let name = resident_integer_name(index);
+            let value = self.variables.get_integer_var(&name).unwrap_or_default();
+            (usize::from(RESIDENT_START) + 4 * index, value)
+        })
+    }
+
+    /// The address of `length` bytes of memory an indirection operator
+    /// reads or writes
+    fn indirect_address(&mut self, address: &Expression, length: usize) -> Result<u16> {
+        let address = self.eval_integer(address)?;
+        match usize::try_from(address) {
+            Ok(start) if start + length <= MEMORY_SIZE => Ok(start as u16),
+            _ => Err(BBCBasicError::InvalidAddress(address as u16)),
+        }
+    }
+
+    /// Read memory through an indirection operator: ? and ! give integers
+    /// and $ a string
+    fn read_indirect(&mut self, op: Indirection, address: &Expression) -> Result<Variable> {
+        match op {
+            Indirection::Byte => {
+                let address = self.indirect_address(address, 1)?;
+                Ok(Variable::Integer(self.read_memory(address, 1)?[0] as i32))
+            }
+            Indirection::Word => {
+                let address = self.indirect_address(address, 4)?;
+                let bytes = self.read_memory(address, 4)?;
+                Ok(Variable::Integer(i32::from_le_bytes([
+                    bytes[0], bytes[1], bytes[2], bytes[3],
+                ])))
+            }
+            Indirection::String => {
+                // Up to the carriage return, or 255 characters
+                let start = self.indirect_address(address, 0)? as usize;
+                let end = (start + 256).min(MEMORY_SIZE);
+                let bytes = self.read_memory(start as u16, end - start)?;
+                let length = bytes
+                    .iter()
+                    .position(|&b| b == b'\r')
+                    .unwrap_or(bytes.len().min(255));
+                Ok(Variable::String(crate::charset::from_bytes(
+                    &bytes[..length],
+                )))
+            }
+        }
+    }
+
+    /// Execute an IF statement
+    fn execute_if(
+        &mut self,
         condition: &Expression,
         then_part: &[Statement],
         else_part: Option<&Vec<Statement>>,
@@ -659,11 +1618,11 @@ impl Executor {
 
     /// Collect DATA statement values without executing (for program pre-processing)
     /// This is used to collect all DATA statements before program execution begins
-    pub fn collect_data(&mut self, statement: &Statement) -> Result<()> {
+    pub fn collect_data(&mut self, statement: &Statement, line_number: u16) -> Result<()> {
         if let Statement::Data { values } = statement {
             for value in values {
                 self.data_values.push(value.clone());
-                self.data_line_numbers.push(self.current_line);
+                self.data_line_numbers.push(Some(line_number));
             }
         }
         Ok(())
@@ -678,8 +1637,10 @@ impl Executor {
     }
 
     /// Execute READ statement - reads data into variables
-    fn execute_read(&mut self, variables: &[String]) -> Result<()> {
-        for var_name in variables {
+    fn execute_read(&mut self, variables: &[Expression]) -> Result<()> {
+        for target in variables {
+            let var_name = target_name(target);
+            self.check_constant(var_name)?;
             // Check if we've run out of data
             if self.data_pointer >= self.data_values.len() {
                 return Err(BBCBasicError::SyntaxError {
@@ -698,9 +1659,9 @@ impl Executor {
                 let int_val = match data_value {
                     DataValue::Integer(v) => *v,
                     DataValue::Real(v) => *v as i32,
-                    DataValue::String(_) => 0, // BBC BASIC: string to number = 0
+                    DataValue::String(s) => number_prefix(s) as i32,
                 };
-                self.variables.set_integer_var(var_name.clone(), int_val);
+                self.assign_target(target, Variable::Integer(int_val))?;
             } else if var_name.ends_with('$') {
                 // String variable
                 let str_val = match data_value {
@@ -708,15 +1669,15 @@ impl Executor {
                     DataValue::Integer(v) => v.to_string(),
                     DataValue::Real(v) => v.to_string(),
                 };
-                self.variables.set_string_var(var_name.clone(), str_val)?;
+                self.assign_target(target, Variable::String(str_val))?;
             } else {
                 // Real variable
                 let real_val = match data_value {
                     DataValue::Real(v) => *v,
                     DataValue::Integer(v) => *v as f64,
-                    DataValue::String(_) => 0.0, // BBC BASIC: string to number = 0
+                    DataValue::String(s) => number_prefix(s),
                 };
-                self.variables.set_real_var(var_name.clone(), real_val);
+                self.assign_target(target, Variable::Real(real_val))?;
             }
         }
         Ok(())
@@ -746,62 +1707,212 @@ impl Executor {
         Ok(())
     }
 
+    /// Execute RESTORE to a line given by an expression
+    fn execute_restore_line(&mut self, line: &Expression) -> Result<()> {
+        let line_number = self.eval_integer(line)?;
+        let line_number = u16::try_from(line_number).map_err(|_| BBCBasicError::SyntaxError {
+            message: format!("No DATA at line {}", line_number),
+            line: None,
+        })?;
+        self.execute_restore(Some(line_number))
+    }
+
+    /// Execute RESTORE DATA - back to where LOCAL DATA saved the pointer
+    fn execute_restore_data(&mut self) -> Result<()> {
+        self.data_pointer = self
+            .local_stack
+            .last()
+            .and_then(|frame| frame.data_pointer)
+            .ok_or_else(|| BBCBasicError::SyntaxError {
+                message: "RESTORE DATA without LOCAL DATA".to_string(),
+                line: None,
+            })?;
+        Ok(())
+    }
+
+    /// Execute LOCAL ERROR - save the error handling, to be restored by
+    /// RESTORE ERROR or, in a procedure, when it returns
+    fn execute_local_error(&mut self) {
+        let depth = self.error_stack.len();
+        self.error_stack.push(SavedErrorState {
+            handler: self.error_handler,
+            last_error: self.last_error.clone(),
+        });
+        if let Some(frame) = self.local_stack.last_mut() {
+            frame.error_depth.get_or_insert(depth);
+        }
+    }
+
+    /// Execute RESTORE ERROR - back to the error handling saved by the last
+    /// LOCAL ERROR
+    fn execute_restore_error(&mut self) -> Result<()> {
+        let state = self
+            .error_stack
+            .pop()
+            .ok_or_else(|| BBCBasicError::SyntaxError {
+                message: "RESTORE ERROR without LOCAL ERROR".to_string(),
+                line: None,
+            })?;
+        self.restore_error_state(state);
+        Ok(())
+    }
+
+    fn restore_error_state(&mut self, state: SavedErrorState) {
+        self.error_handler = state.handler;
+        self.last_error = state.last_error;
+    }
+
+    /// Forget error handling saved by LOCAL ERROR, so a new run starts
+    /// without it
+    pub fn clear_saved_errors(&mut self) {
+        self.error_stack.clear();
+    }
+
+    /// Execute RESTORE +offset - to the offset'th DATA line after the
+    /// current line (+0 and +1 both give the next one)
+    fn execute_restore_relative(&mut self, offset: &Expression) -> Result<()> {
+        let offset = self.eval_integer(offset)?;
+        let current = self.current_line.unwrap_or(0);
+        let mut lines: Vec<u16> = self
+            .data_line_numbers
+            .iter()
+            .flatten()
+            .copied()
+            .filter(|&line| line > current)
+            .collect();
+        lines.dedup();
+        let target = usize::try_from(offset)
+            .ok()
+            .and_then(|offset| lines.get(offset.saturating_sub(1)))
+            .ok_or_else(|| BBCBasicError::SyntaxError {
+                message: format!("No DATA at line {} +{}", current, offset),
+                line: None,
+            })?;
+        self.execute_restore(Some(*target))
+    }
+
     /// Execute CLS statement - clear screen
     fn execute_cls(&mut self) -> Result<()> {
-        // Output ANSI escape sequences to clear screen and move cursor to home
-        // ESC[2J clears the entire screen
-        // ESC[H moves cursor to home position (0,0)
-        #[cfg(test)]
-        {
-            self.output.push_str("\x1b[2J\x1b[H");
-        }
-        #[cfg(not(test))]
-        {
-            print!("\x1b[2J\x1b[H");
-        }
+        self.events.write(&[12]);
+        self.clear_screen();
         Ok(())
     }
 
+    /// Clear the text screen (VDU 12)
+    fn clear_screen(&mut self) {
+        self.screen.clear();
+        self.events.emit(OutputEvent::Cls);
+    }
+
     /// Execute PLOT statement - plot with mode code
     fn execute_plot(&mut self, mode: &Expression, x: &Expression, y: &Expression) -> Result<()> {
         let mode_val = self.eval_integer(mode)?;
         let x_val = self.eval_integer(x)?;
         let y_val = self.eval_integer(y)?;
 
+        self.write_plot(mode_val as u8, x_val, y_val);
         self.graphics.plot(mode_val as u8, x_val, y_val);
+        self.emit_graphics(GraphicsOp::Plot {
+            mode: mode_val as u8,
+            x: x_val,
+            y: y_val,
+        });
         Ok(())
     }
 
     /// Execute MOVE statement - move graphics cursor
-    fn execute_move(&mut self, x: &Expression, y: &Expression) -> Result<()> {
-        let x_val = self.eval_integer(x)?;
-        let y_val = self.eval_integer(y)?;
-
-        self.graphics.move_to(x_val, y_val);
+    fn execute_move(&mut self, x: &Expression, y: &Expression, relative: bool) -> Result<()> {
+        let (x_val, y_val) = self.graphics_target(x, y, relative)?;
+        self.move_or_draw(if relative { 0 } else { 4 }, x_val, y_val);
         Ok(())
     }
 
     /// Execute DRAW statement - draw line to coordinates
-    fn execute_draw(&mut self, x: &Expression, y: &Expression) -> Result<()> {
-        let x_val = self.eval_integer(x)?;
-        let y_val = self.eval_integer(y)?;
+    fn execute_draw(&mut self, x: &Expression, y: &Expression, relative: bool) -> Result<()> {
+        let (x_val, y_val) = self.graphics_target(x, y, relative)?;
+        self.move_or_draw(if relative { 1 } else { 5 }, x_val, y_val);
+        Ok(())
+    }
 
-        self.graphics.draw_line_to(x_val, y_val);
+    /// Execute FORWARD statement - walk the turtle, as DRAW or, with its
+    /// pen up, as MOVE
+    fn execute_forward(&mut self, distance: &Expression) -> Result<()> {
+        let distance = self.eval_real(distance)?;
+        let (x, y) = self.turtle.forward(distance, self.graphics.get_position());
+        self.move_or_draw(if self.turtle.is_pen_down() { 5 } else { 4 }, x, y);
         Ok(())
     }
 
+    /// Take the graphics cursor to (x, y), drawing if the plot mode is a
+    /// DRAW's (1 or 5) and moving if it is a MOVE's (0 or 4)
+    fn move_or_draw(&mut self, mode: u8, x: i32, y: i32) {
+        self.write_plot_to(mode, x, y);
+        if mode & 1 == 0 {
+            self.graphics.move_to(x, y);
+            self.emit_graphics(GraphicsOp::Move { x, y });
+        } else {
+            self.graphics.draw_line_to(x, y);
+            self.emit_graphics(GraphicsOp::Draw { x, y });
+        }
+    }
+
+    /// Write the VDU 25 sequence for a PLOT
+    fn write_plot(&mut self, mode: u8, x: i32, y: i32) {
+        let (x, y) = ((x as i16).to_le_bytes(), (y as i16).to_le_bytes());
+        self.events.write(&[25, mode, x[0], x[1], y[0], y[1]]);
+    }
+
+    /// Write the PLOT that takes the graphics cursor to (x, y): a relative
+    /// plot mode (0 to 3) is given the offset from the cursor
+    fn write_plot_to(&mut self, mode: u8, x: i32, y: i32) {
+        if mode & 4 == 0 {
+            let (current_x, current_y) = self.graphics.get_position();
+            self.write_plot(mode, x - current_x, y - current_y);
+        } else {
+            self.write_plot(mode, x, y);
+        }
+    }
+
+    /// The point MOVE or DRAW goes to; listeners are always given absolute
+    /// coordinates, so MOVE BY and DRAW BY are resolved here
+    fn graphics_target(
+        &mut self,
+        x: &Expression,
+        y: &Expression,
+        relative: bool,
+    ) -> Result<(i32, i32)> {
+        let x_val = self.eval_integer(x)?;
+        let y_val = self.eval_integer(y)?;
+        if !relative {
+            return Ok((x_val, y_val));
+        }
+        let (current_x, current_y) = self.graphics.get_position();
+        Ok((current_x + x_val, current_y + y_val))
+    }
+
     /// Execute CIRCLE statement - draw a circle
     fn execute_circle(
         &mut self,
         x: &Expression,
         y: &Expression,
         radius: &Expression,
+        filled: bool,
     ) -> Result<()> {
         let x_val = self.eval_integer(x)?;
         let y_val = self.eval_integer(y)?;
         let radius_val = self.eval_integer(radius)?;
 
-        self.graphics.draw_circle(x_val, y_val, radius_val);
+        if filled {
+            self.graphics.fill_circle(x_val, y_val, radius_val);
+        } else {
+            self.graphics.draw_circle(x_val, y_val, radius_val);
+        }
+        self.emit_graphics(GraphicsOp::Circle {
+            x: x_val,
+            y: y_val,
+            radius: radius_val,
+            filled,
+        });
         Ok(())
     }
 
@@ -810,16 +1921,356 @@ impl Executor {
         let mode_val = self.eval_integer(mode)?;
         let color_val = self.eval_integer(color)?;
 
+        self.events.write(&[18, mode_val as u8, color_val as u8]);
+        self.colours.set_graphics(mode_val as u8, color_val as u8);
         self.graphics.set_color(mode_val as u8, color_val as u8);
+        self.emit_graphics(GraphicsOp::Gcol {
+            mode: mode_val as u8,
+            colour: color_val as u8,
+        });
         Ok(())
     }
 
     /// Execute CLG statement - clear graphics screen
     fn execute_clg(&mut self) -> Result<()> {
+        self.events.write(&[16]);
+        self.clear_graphics();
+        Ok(())
+    }
+
+    /// Clear the graphics screen (VDU 16)
+    fn clear_graphics(&mut self) {
+        self.graphics.clear();
+        self.emit_graphics(GraphicsOp::Clg);
+    }
+
+    /// Execute MODE statement - change screen mode
+    fn execute_mode(&mut self, mode: &Expression) -> Result<()> {
+        let mode_val = self.eval_integer(mode)?;
+        self.events.write(&[22, mode_val as u8]);
+        self.clear_screen();
+        self.set_mode(mode_val)
+    }
+
+    /// Select a screen mode, clearing the graphics screen and resetting its origin
+    pub fn set_mode(&mut self, mode: i32) -> Result<()> {
+        if !(0..=7).contains(&mode) {
+            return Err(BBCBasicError::IllegalFunction);
+        }
+        // Screen memory starts at HIMEM, so it must not overlap the program
+        // or variables
+        self.memory.set_himem(screen_start(mode as u8))?;
+        self.screen_mode = mode as u8;
+        self.screen = TextScreen::for_mode(self.screen_mode);
+        self.vdu5 = false;
+        self.colours = Colours::for_mode(self.screen_mode);
+        self.origin = (0, 0);
+        self.graphics.set_origin(0, 0);
+        self.graphics.move_to(0, 0);
         self.graphics.clear();
+        // MODE clears the screen on show too
+        self.graphics.flip();
+        self.turtle = Turtle::default();
+        self.events.emit(OutputEvent::ModeChange(self.screen_mode));
         Ok(())
     }
 
+    /// Get the current screen mode
+    pub fn screen_mode(&self) -> u8 {
+        self.screen_mode
+    }
+
+    /// Apply strictness flags from the interpreter configuration
+    pub fn set_strict_flags(&mut self, flags: StrictFlags) {
+        self.strict = flags;
+        self.variables.set_string_limit(flags.string_length);
+    }
+
+    /// Compute and print reals as doubles or as five byte BBC floats
+    pub fn set_float_format(&mut self, floats: FloatFormat) {
+        self.floats = floats;
+    }
+
+    /// Choose when INPUT prints its "?" prompt
+    pub fn set_prompts(&mut self, prompts: Prompts) {
+        self.prompts = prompts;
+    }
+
+    /// A real result in the current float format: rounded to the 32 bit
+    /// mantissa of a five byte float, and "Too big" beyond its range
+    pub(crate) fn round_real(&self, value: f64) -> Result<f64> {
+        match self.floats {
+            FloatFormat::Double => Ok(value),
+            FloatFormat::Bbc => Ok(decode_real(encode_real(value)?)),
+        }
+    }
+
+    /// A real as PRINT shows it: as @% says, which starts as
+    /// [`DEFAULT_PRINT_FORMAT`]
+    fn real_text(&self, value: f64) -> String {
+        let format = self
+            .variables
+            .get_integer_var("@%")
+            .unwrap_or(DEFAULT_PRINT_FORMAT);
+        formatted_real_text(value, format)
+    }
+
+    /// A real as STR$ gives it: as @% says only if its top byte is set
+    /// (`@% = &1000000 + format`), and in the default format otherwise
+    fn str_text(&self, value: f64) -> String {
+        match self.variables.get_integer_var("@%") {
+            Some(format) if format >> 24 != 0 => formatted_real_text(value, format),
+            _ => formatted_real_text(value, DEFAULT_PRINT_FORMAT),
+        }
+    }
+
+    /// Report the running program through `tracing` (see [`crate::trace`])
+    pub fn set_tracing(&mut self, on: bool) {
+        self.trace = on;
+    }
+
+    /// Whether the running program is reported through `tracing`
+    pub fn tracing(&self) -> bool {
+        self.trace
+    }
+
+    /// Set how file names map to host paths
+    pub fn set_filenames(&mut self, filenames: FilenameTranslator) {
+        self.filenames = filenames;
+    }
+
+    /// Translate a file name to a host path
+    fn resolve_path(&self, filename: &str) -> Result<PathBuf> {
+        self.filenames
+            .translate(filename)
+            .map_err(BBCBasicError::BadName)
+    }
+
+    /// Run a star command, returning the text it would print
+    ///
+    /// This is what FNoscli$ calls, so that programs can read a *CAT
+    /// listing and manage files: *CAT (or *.), *DELETE name, *RENAME old new,
+    /// *FX A,X,Y and *SHADOW [0|1] are understood, and *SAY text with the
+    /// `speech` feature.
+    pub fn oscli(&mut self, command: &str) -> Result<String> {
+        let command = command.trim().trim_start_matches('*').trim_start();
+        let name = command.split_whitespace().next().unwrap_or_default();
+        self.check_allowed(&format!("*{}", name))?;
+        let (name, arguments) = match command.find(|c: char| c.is_whitespace()) {
+            Some(split) => (&command[..split], command[split..].trim()),
+            // *. needs no space before its arguments
+            None if command.starts_with('.') => (".", command[1..].trim()),
+            None => (command, ""),
+        };
+        match name.to_ascii_uppercase().as_str() {
+            "CAT" | "." => {
+                let root = self.filenames.root().unwrap_or(Path::new("."));
+                crate::filesystem::catalogue(root).map_err(BBCBasicError::DiskError)
+            }
+            "DELETE" if !arguments.is_empty() => {
+                std::fs::remove_file(self.resolve_path(arguments)?)
+                    .map_err(|_| BBCBasicError::FileNotFound(arguments.to_string()))?;
+                Ok(String::new())
+            }
+            "RENAME" => {
+                let [from, to] = arguments.split_whitespace().collect::<Vec<_>>()[..] else {
+                    return Err(BBCBasicError::SyntaxError {
+                        message: "Syntax: *RENAME old new".to_string(),
+                        line: None,
+                    });
+                };
+                let target = self.resolve_path(to)?;
+                if target.exists() {
+                    return Err(BBCBasicError::DiskError(format!("{} already exists", to)));
+                }
+                std::fs::rename(self.resolve_path(from)?, target)
+                    .map_err(|_| BBCBasicError::FileNotFound(from.to_string()))?;
+                Ok(String::new())
+            }
+            "FX" => self.fx(arguments).map(|()| String::new()),
+            "SHADOW" => {
+                let on = match arguments {
+                    "" | "1" => true,
+                    "0" => false,
+                    _ => {
+                        return Err(BBCBasicError::SyntaxError {
+                            message: "Syntax: *SHADOW [0|1]".to_string(),
+                            line: None,
+                        })
+                    }
+                };
+                self.graphics.set_double_buffered(on);
+                Ok(String::new())
+            }
+            #[cfg(feature = "speech")]
+            "SAY" => {
+                // The real Speech System gives "Bad call" if it is missing
+                self.speech
+                    .say(arguments)
+                    .map_err(|_| BBCBasicError::BadCall)?;
+                Ok(String::new())
+            }
+            _ => Err(BBCBasicError::BadCommand),
+        }
+    }
+
+    /// Call an OS routine (CALL and USR) with the low bytes of A%, X% and
+    /// Y% in the registers, giving back the registers it returns
+    ///
+    /// Only the OS entry points can be called: OSASCI (&FFE3), OSNEWL
+    /// (&FFE7), OSWRCH (&FFEE), OSWORD (&FFF1) and OSBYTE (&FFF4). Anything
+    /// else is a Bad call, as there is no 6502 to run machine code.
+    fn os_call(&mut self, address: i32) -> Result<(u8, u8, u8)> {
+        let register = |name: &str| self.variables.get_integer_var(name).unwrap_or(0) as u8;
+        let (a, x, y) = (register("A%"), register("X%"), register("Y%"));
+        match address & 0xFFFF {
+            0xFFE3 if a == 13 => self.vdu(&[10, 13])?,
+            0xFFE3 | 0xFFEE => self.vdu(&[a])?,
+            0xFFE7 => self.vdu(&[10, 13])?,
+            0xFFF1 => self.osword(a, u16::from_le_bytes([x, y]))?,
+            0xFFF4 => {
+                let (x, y) = self.osbyte(a, x, y)?;
+                return Ok((a, x, y));
+            }
+            _ => return Err(BBCBasicError::BadCall),
+        }
+        Ok((a, x, y))
+    }
+
+    /// Make an OSWORD call with its parameter block at `block`
+    ///
+    /// 7 and 8 go to the sound system; &0B reads the physical colour of the
+    /// logical colour in the block's first byte into the next, and &0C
+    /// changes the palette as VDU 19 does with the block's five bytes.
+    fn osword(&mut self, call: u8, block: u16) -> Result<()> {
+        match call {
+            7 | 8 => {
+                let parameters = self.read_memory(block, if call == 7 { 8 } else { 14 })?;
+                self.sound.osword(call, &parameters)
+            }
+            0x0B => {
+                let logical = self.read_memory(block, 1)?[0];
+                let physical = self.colours.physical(logical);
+                self.write_memory(block, &[logical, physical, 0, 0, 0])
+            }
+            0x0C => {
+                let parameters = self.read_memory(block, 5)?;
+                self.vdu(&[19])?;
+                self.vdu(&parameters)
+            }
+            _ => Err(BBCBasicError::BadCall),
+        }
+    }
+
+    /// Run a *FX command: the arguments after *FX, as `A[,X[,Y]]`
+    pub fn fx(&mut self, arguments: &str) -> Result<()> {
+        let (a, x, y) = OSInterface::parse_fx(arguments)?;
+        self.osbyte(a, x, y).map(|_| ())
+    }
+
+    /// Make an OSBYTE call: 19 waits for the next frame, 114 turns the
+    /// shadow screen on (X = 0) or off, &86 reads the text cursor's column
+    /// and row, &87 the character under it and the MODE, and &A0 VDU
+    /// variable X (and the one after it); the rest go to the OS
+    fn osbyte(&mut self, a: u8, x: u8, y: u8) -> Result<(u8, u8)> {
+        let (column, row) = self.screen.cursor();
+        match a {
+            19 => {
+                self.wait_for_frame();
+                Ok((x, y))
+            }
+            // As on the Master, 0 selects the shadow screen and 1 the
+            // normal one
+            114 => {
+                let shadow = self.graphics.is_double_buffered();
+                self.graphics.set_double_buffered(x == 0);
+                Ok((u8::from(!shadow), y))
+            }
+            0x86 => Ok((column as u8, row as u8)),
+            0x87 => Ok((self.screen.char_at(column, row), self.screen_mode)),
+            0xA0 => {
+                let variables = self.vdu_variables();
+                let byte = |n: usize| variables.get(n).copied().unwrap_or(0);
+                Ok((byte(usize::from(x)), byte(usize::from(x) + 1)))
+            }
+            _ => self.os.osbyte(a, x, y),
+        }
+    }
+
+    /// The VDU variables, as OSBYTE &A0 numbers them
+    fn vdu_variables(&self) -> [u8; 0x61] {
+        let mut variables = [0; 0x61];
+        let mut words = |at: usize, values: &[i32]| {
+            for (i, value) in values.iter().enumerate() {
+                variables[at + i * 2..at + i * 2 + 2]
+                    .copy_from_slice(&(*value as i16).to_le_bytes());
+            }
+        };
+        let (x, y) = self.graphics.get_position();
+        words(0x00, &[0, 0, 1279, 1023]);
+        words(0x0C, &[self.origin.0, self.origin.1, x, y]);
+        let (column, row) = self.screen.cursor();
+        variables[0x08..0x0C].copy_from_slice(&[
+            0,
+            self.screen.height() as u8 - 1,
+            self.screen.width() as u8 - 1,
+            0,
+        ]);
+        variables[0x18] = column as u8;
+        variables[0x19] = row as u8;
+        variables[0x55] = self.screen_mode;
+        let colours = &self.colours;
+        variables[0x57..0x5D].copy_from_slice(&[
+            colours.text_foreground,
+            colours.text_background,
+            colours.graphics_foreground,
+            colours.graphics_background,
+            colours.foreground_action,
+            colours.background_action,
+        ]);
+        variables[0x60] = colours.count() - 1;
+        variables
+    }
+
+    /// Read VDU variable `number`, as OSBYTE &A0 and FNvdu number them
+    ///
+    /// &00-&07 are the graphics window and &08-&0B the text window (left,
+    /// bottom, right, top), &0C and &0E the graphics origin, &10 and &12 the
+    /// graphics cursor, and &18 and &19 the text cursor's column and row.
+    /// &55 is the MODE, &57-&5A the text foreground and background and the
+    /// graphics foreground and background colours, &5B and &5C their GCOL
+    /// actions, and &60 one less than the number of colours. Coordinates
+    /// take two bytes, low byte first, and are read as one signed number
+    /// from their first; any number past &60 is a Bad call.
+    pub fn vdu_variable(&self, number: i32) -> Result<i32> {
+        let variables = self.vdu_variables();
+        let number = usize::try_from(number)
+            .ok()
+            .filter(|&number| number < variables.len())
+            .ok_or(BBCBasicError::BadCall)?;
+        let coordinate = number < 0x08 || (0x0C..0x14).contains(&number);
+        Ok(if coordinate && number % 2 == 0 {
+            i32::from(i16::from_le_bytes([
+                variables[number],
+                variables[number + 1],
+            ]))
+        } else {
+            i32::from(variables[number])
+        })
+    }
+
+    /// Value of a variable that has never been assigned
+    ///
+    /// Real BBC BASIC reports "No such variable"; with the strict flag off
+    /// it reads as zero or the empty string instead.
+    fn undefined_variable<T: Default>(&self, name: &str) -> Result<T> {
+        if self.strict.undefined_variables {
+            Err(BBCBasicError::NoSuchVariable(name.to_string()))
+        } else {
+            Ok(T::default())
+        }
+    }
+
     /// Execute ELLIPSE statement - draw an ellipse
     fn execute_ellipse(
         &mut self,
@@ -827,17 +2278,32 @@ impl Executor {
         y: &Expression,
         major: &Expression,
         minor: &Expression,
+        filled: bool,
     ) -> Result<()> {
         let x_val = self.eval_integer(x)?;
         let y_val = self.eval_integer(y)?;
         let major_val = self.eval_integer(major)?;
         let minor_val = self.eval_integer(minor)?;
 
-        self.graphics.draw_ellipse(x_val, y_val, major_val, minor_val);
+        if filled {
+            self.graphics
+                .fill_ellipse(x_val, y_val, major_val, minor_val);
+        } else {
+            self.graphics
+                .draw_ellipse(x_val, y_val, major_val, minor_val);
+        }
+        self.emit_graphics(GraphicsOp::Ellipse {
+            x: x_val,
+            y: y_val,
+            major: major_val,
+            minor: minor_val,
+            filled,
+        });
         Ok(())
     }
 
-    /// Execute RECTANGLE statement - draw a rectangle
+    /// Execute RECTANGLE statement - draw a rectangle, or copy or move one
+    /// to a target point
     fn execute_rectangle(
         &mut self,
         x1: &Expression,
@@ -845,6 +2311,7 @@ impl Executor {
         width: &Expression,
         height: &Expression,
         filled: bool,
+        target: Option<&(Expression, Expression)>,
     ) -> Result<()> {
         let x1_val = self.eval_integer(x1)?;
         let y1_val = self.eval_integer(y1)?;
@@ -854,8 +2321,32 @@ impl Executor {
         let x2_val = x1_val + width_val;
         let y2_val = y1_val + height_val;
 
+        if let Some((x, y)) = target {
+            let x_val = self.eval_integer(x)?;
+            let y_val = self.eval_integer(y)?;
+            self.graphics
+                .copy_rectangle(x1_val, y1_val, x2_val, y2_val, (x_val, y_val), filled);
+            self.emit_graphics(GraphicsOp::CopyRectangle {
+                x1: x1_val,
+                y1: y1_val,
+                x2: x2_val,
+                y2: y2_val,
+                x: x_val,
+                y: y_val,
+                moved: filled,
+            });
+            return Ok(());
+        }
+
         self.graphics
             .draw_rectangle(x1_val, y1_val, x2_val, y2_val, filled);
+        self.emit_graphics(GraphicsOp::Rectangle {
+            x1: x1_val,
+            y1: y1_val,
+            x2: x2_val,
+            y2: y2_val,
+            filled,
+        });
         Ok(())
     }
 
@@ -865,6 +2356,7 @@ impl Executor {
         let y_val = self.eval_integer(y)?;
 
         self.graphics.flood_fill(x_val, y_val);
+        self.emit_graphics(GraphicsOp::Fill { x: x_val, y: y_val });
         Ok(())
     }
 
@@ -873,59 +2365,359 @@ impl Executor {
         let x_val = self.eval_integer(x)?;
         let y_val = self.eval_integer(y)?;
 
+        let (x, y) = ((x_val as i16).to_le_bytes(), (y_val as i16).to_le_bytes());
+        self.events.write(&[29, x[0], x[1], y[0], y[1]]);
+        self.origin = (x_val, y_val);
         self.graphics.set_origin(x_val, y_val);
+        self.emit_graphics(GraphicsOp::Origin { x: x_val, y: y_val });
         Ok(())
     }
 
+    /// Report a graphics statement to the output listeners
+    fn emit_graphics(&mut self, op: GraphicsOp) {
+        self.io += 1;
+        self.events.emit(OutputEvent::GraphicsOp(op));
+    }
+
+    /// Execute SOUND statement - queue a note
+    fn execute_sound(
+        &mut self,
+        channel: &Expression,
+        amplitude: &Expression,
+        pitch: &Expression,
+        duration: &Expression,
+    ) -> Result<()> {
+        let channel_val = self.eval_integer(channel)?;
+        let amplitude_val = self.eval_integer(amplitude)?;
+        let pitch_val = self.eval_integer(pitch)?;
+        let duration_val = self.eval_integer(duration)?;
+        self.io += 1;
+
+        // A full queue makes SOUND wait until the channel has room, unless the
+        // note flushes the channel anyway
+        let queue = (channel_val & 0x03) as usize;
+        if channel_val & 0x10 == 0 {
+            self.update_sound_clock();
+            if let Some(wait) = self.sound.wait_for_space(queue).filter(|&wait| wait > 0) {
+                self.wait(wait as u64 * 5);
+            }
+        }
+        self.update_sound_clock();
+        self.sound
+            .sound(channel_val, amplitude_val, pitch_val, duration_val)?;
+        self.events.emit(OutputEvent::SoundQueued(QueuedSound {
+            channel: channel_val,
+            amplitude: amplitude_val,
+            pitch: pitch_val,
+            duration: duration_val,
+        }));
+        Ok(())
+    }
+
+    /// Bring the sound clock up to date (it runs in twentieths of a second
+    /// from when the executor was created)
+    fn update_sound_clock(&mut self) {
+        let twentieths = self.centiseconds() / 5;
+        self.sound.set_clock(twentieths as u32);
+    }
+
+    /// Centiseconds since the executor was started, on the virtual clock in
+    /// deterministic mode
+    fn centiseconds(&self) -> u64 {
+        self.virtual_time
+            .unwrap_or_else(|| (self.start_time.elapsed().as_millis() / 10) as u64)
+    }
+
+    /// The value TIME would read now, for tools watching a program (reading
+    /// it here does not count as the program doing I/O)
+    pub fn time(&self) -> i32 {
+        self.centiseconds() as i32
+    }
+
+    /// Let `centiseconds` pass: sleep, or step the virtual clock in
+    /// deterministic mode
+    fn wait(&mut self, centiseconds: u64) {
+        self.io += 1;
+        match &mut self.virtual_time {
+            Some(time) => *time += centiseconds,
+            None => std::thread::sleep(std::time::Duration::from_millis(centiseconds * 10)),
+        }
+    }
+
+    /// Wait for the next frame to start (WAIT alone, and *FX 19), so a loop
+    /// that draws one frame a time round runs at the refresh rate however
+    /// quickly it draws; a double buffered picture is flipped on to the
+    /// screen, and front ends see [`OutputEvent::Frame`]
+    fn wait_for_frame(&mut self) {
+        let rate = u64::from(self.refresh_rate);
+        match self.virtual_time {
+            Some(time) => {
+                let frame = time * rate / 100 + 1;
+                self.wait((frame * 100).div_ceil(rate) - time);
+            }
+            None => {
+                self.io += 1;
+                let elapsed = self.start_time.elapsed().as_nanos();
+                let period = 1_000_000_000 / u128::from(rate);
+                let frame = elapsed / period + 1;
+                let remaining = (frame * period - elapsed) as u64;
+                std::thread::sleep(std::time::Duration::from_nanos(remaining));
+            }
+        }
+        self.graphics.flip();
+        self.events.emit(OutputEvent::Frame);
+    }
+
+    /// Make runs reproducible (`Some(seed)`) or go back to the real clock and
+    /// unpredictable random numbers (`None`)
+    ///
+    /// With a seed, RND is seeded from it, and TIME restarts at zero and only
+    /// moves on when the program waits (WAIT, INKEY, or SOUND waiting for
+    /// room in a queue) by the time it waited, without really waiting.
+    pub fn set_deterministic(&mut self, seed: Option<u64>) {
+        match seed {
+            Some(seed) => {
+                self.rng = StdRng::seed_from_u64(seed);
+                self.virtual_time = Some(0);
+            }
+            None => {
+                self.rng = StdRng::from_entropy();
+                self.virtual_time = None;
+            }
+        }
+    }
+
+    /// Execute CONST statement - assign the variable, then make it read-only
+    fn execute_const(&mut self, name: &str, expression: &Expression) -> Result<()> {
+        self.execute_assignment(name, expression)?;
+        self.constants.insert(name.to_string());
+        Ok(())
+    }
+
+    /// Fail with [`BBCBasicError::Constant`] if `name` was made read-only
+    /// by CONST
+    pub fn check_constant(&self, name: &str) -> Result<()> {
+        if self.constants.contains(name) {
+            return Err(BBCBasicError::Constant(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Forget the constants, so the next run can define them again
+    pub fn clear_constants(&mut self) {
+        self.constants.clear();
+    }
+
+    /// Execute ASSERT statement - a FALSE (zero) condition stops with the
+    /// message, or "Assertion failed"
+    fn execute_assert(
+        &mut self,
+        condition: &Expression,
+        message: Option<&Expression>,
+    ) -> Result<()> {
+        if self.eval_integer(condition)? != 0 {
+            return Ok(());
+        }
+        let message = match message {
+            Some(message) => self.eval_string(message)?,
+            None => "Assertion failed".to_string(),
+        };
+        Err(BBCBasicError::AssertionFailed(message))
+    }
+
+    /// Execute WAIT statement - pause for a number of centiseconds, or until
+    /// the next frame if none is given
+    fn execute_wait(&mut self, centiseconds: Option<&Expression>) -> Result<()> {
+        match centiseconds {
+            Some(expr) => {
+                let centiseconds = self.eval_integer(expr)?.max(0) as u64;
+                self.wait(centiseconds);
+            }
+            None => self.wait_for_frame(),
+        }
+        Ok(())
+    }
+
+    /// Execute MOUSE statement - set three variables to the pointer's
+    /// position, from the graphics origin, and its buttons
+    fn execute_mouse(
+        &mut self,
+        x: &Expression,
+        y: &Expression,
+        buttons: &Expression,
+    ) -> Result<()> {
+        let pointer = self.os.pointer();
+        let values = [
+            (x, pointer.x - self.origin.0),
+            (y, pointer.y - self.origin.1),
+            (buttons, i32::from(pointer.buttons)),
+        ];
+        for (target, value) in values {
+            let name = target_name(target);
+            self.check_constant(name)?;
+            let value = if name.ends_with('%') {
+                Variable::Integer(value)
+            } else if name.ends_with('$') {
+                return Err(BBCBasicError::TypeMismatch);
+            } else {
+                Variable::Real(f64::from(value))
+            };
+            self.assign_target(target, value)?;
+        }
+        Ok(())
+    }
+
+    /// Execute ENVELOPE statement - define an envelope
+    fn execute_envelope(&mut self, params: &[Expression]) -> Result<()> {
+        let values = params
+            .iter()
+            .map(|param| self.eval_integer(param))
+            .collect::<Result<Vec<i32>>>()?;
+
+        self.sound.envelope(values[0], &values[1..])
+    }
+
+    /// Get the sound system (for rendering captured audio)
+    pub fn sound(&self) -> &SoundSystem {
+        &self.sound
+    }
+
+    /// Colours and palette set by COLOUR, GCOL and VDU 19
+    pub fn colours(&self) -> &Colours {
+        &self.colours
+    }
+
+    /// Get the sound system mutably (for queueing notes and envelopes
+    /// directly, without BASIC statements)
+    pub fn sound_mut(&mut self) -> &mut SoundSystem {
+        &mut self.sound
+    }
+
+    /// Get the phrases queued by *SAY
+    pub fn speech(&self) -> &Speech {
+        &self.speech
+    }
+
+    /// Get the speech queue mutably (for plugging in a speaker)
+    pub fn speech_mut(&mut self) -> &mut Speech {
+        &mut self.speech
+    }
+
+    /// Get the OS interface (keyboard buffer and OSBYTE calls)
+    pub fn os(&self) -> &OSInterface {
+        &self.os
+    }
+
+    /// Get the OS interface mutably (for inserting keys and *FX calls)
+    pub fn os_mut(&mut self) -> &mut OSInterface {
+        &mut self.os
+    }
+
+    /// Get the emulated memory
+    pub fn memory(&self) -> &MemoryManager {
+        &self.memory
+    }
+
+    /// Get the emulated memory mutably (for *LOAD)
+    pub fn memory_mut(&mut self) -> &mut MemoryManager {
+        &mut self.memory
+    }
+
+    /// Wait for the next key from the keyboard buffer (GET)
+    ///
+    /// When the buffer is empty a line is read from the OS's line input and
+    /// typed into the buffer, followed by RETURN, unless yielding for input.
+    fn read_key(&mut self) -> Result<u8> {
+        self.io += 1;
+        if let Some(key) = self.os.keyboard_mut().read() {
+            return Ok(key);
+        }
+        if self.yield_for_input {
+            return Err(BBCBasicError::WaitingForInput);
+        }
+        if let Some(line) = self.os.read_line() {
+            let keyboard = self.os.keyboard_mut();
+            keyboard.insert_str(&line);
+            keyboard.insert(13);
+            if let Some(key) = keyboard.read() {
+                return Ok(key);
+            }
+        }
+        // Nothing more will ever be typed
+        Err(BBCBasicError::Eof)
+    }
+
+    /// Wait up to `centiseconds` for a key from the keyboard buffer
+    /// (INKEY), returning -1 if none arrives
+    ///
+    /// A negative argument is a BBC key's number instead, giving TRUE if the
+    /// key is held down (see [`crate::keymap`]).
+    fn inkey(&mut self, centiseconds: i32) -> i32 {
+        self.io += 1;
+        if centiseconds < 0 {
+            return if self.os.key_down(centiseconds) {
+                -1
+            } else {
+                0
+            };
+        }
+        if self.os.keyboard().is_empty() && centiseconds > 0 {
+            self.wait(centiseconds as u64);
+        }
+        self.os.keyboard_mut().read().map_or(-1, i32::from)
+    }
+
     /// Get graphics output as string (for display or testing)
     pub fn get_graphics_output(&self) -> String {
         self.graphics.render()
     }
 
+    /// Get the graphics framebuffer (for checksums and image export)
+    pub fn graphics(&self) -> &Graphics {
+        &self.graphics
+    }
+
     /// Evaluate an expression to an integer value
     pub fn eval_integer(&mut self, expr: &Expression) -> Result<i32> {
         match expr {
             Expression::Integer(val) => Ok(*val),
-            Expression::Real(val) => Ok(*val as i32),
+            Expression::Real(val) => real_to_integer(*val),
             Expression::Variable(name) => {
                 // Check for pseudo-variables first
                 if name == "TIME" {
-                    // TIME returns centiseconds since program start or system boot
-                    // For now, use system time
-                    let now = std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap();
-                    // Convert to centiseconds (1/100th of a second)
-                    let centiseconds = (now.as_millis() / 10) as i32;
-                    return Ok(centiseconds);
+                    // TIME returns centiseconds since the executor was started
+                    // (the BBC Micro counts from power-on/reset)
+                    self.io += 1;
+                    return Ok(self.centiseconds() as i32);
                 } else if name == "HIMEM" {
                     // HIMEM returns top of available memory
                     return Ok(self.memory.get_himem() as i32);
                 } else if name == "LOMEM" {
-                    // LOMEM returns bottom of user memory (PAGE)
+                    // LOMEM returns the start of the variable heap, just
+                    // above the program
+                    return Ok(self.memory.get_lomem() as i32);
+                } else if name == "PAGE" {
+                    // PAGE returns the start of the program
                     return Ok(self.memory.get_page() as i32);
+                } else if name == "TOP" {
+                    // TOP returns the end of the program
+                    return Ok(self.memory.get_lomem() as i32);
                 } else if name == "ERR" {
                     // ERR returns the last error number (0 if no error)
                     return Ok(self.last_error.as_ref().map(|e| e.error_number).unwrap_or(0));
                 } else if name == "ERL" {
                     // ERL returns the line number where the last error occurred (0 if no error)
                     return Ok(self.last_error.as_ref().map(|e| e.error_line as i32).unwrap_or(0));
+                } else if name == "GET" {
+                    // GET waits for a key and returns its ASCII code
+                    return Ok(self.read_key()? as i32);
+                } else if name == "TRUE" {
+                    return Ok(-1);
+                } else if name == "FALSE" {
+                    return Ok(0);
                 }
 
-                if name.ends_with('%') {
-                    self.variables
-                        .get_integer_var(name)
-                        .ok_or_else(|| BBCBasicError::NoSuchVariable(name.clone()))
-                } else {
-                    // Try as real variable first, then as integer (for loop vars without % suffix)
-                    if let Some(real_val) = self.variables.get_real_var(name) {
-                        Ok(real_val as i32)
-                    } else if let Some(int_val) = self.variables.get_integer_var(name) {
-                        Ok(int_val)
-                    } else {
-                        Err(BBCBasicError::NoSuchVariable(name.clone()))
-                    }
-                }
+                self.integer_variable(name)
             }
             Expression::ArrayAccess { name, indices } => {
                 use crate::variables::Variable;
@@ -942,106 +2734,85 @@ impl Executor {
                 let element = self.variables.get_array_element(name, &index_values)?;
                 match element {
                     Variable::Integer(val) => Ok(val),
-                    Variable::Real(val) => Ok(val as i32),
+                    Variable::Real(val) => real_to_integer(val),
                     _ => Err(BBCBasicError::TypeMismatch),
                 }
             }
+            Expression::BinaryOp { op, left, right }
+                if is_string_expression(left) || is_string_expression(right) =>
+            {
+                let left = self.eval_string(left)?;
+                let right = self.eval_string(right)?;
+                string_comparison(op, &left, &right).ok_or(BBCBasicError::TypeMismatch)
+            }
             Expression::BinaryOp { op, left, right } => {
-                use crate::parser::BinaryOperator;
                 let left_val = self.eval_integer(left)?;
                 let right_val = self.eval_integer(right)?;
-
-                match op {
-                    BinaryOperator::Add => Ok(left_val + right_val),
-                    BinaryOperator::Subtract => Ok(left_val - right_val),
-                    BinaryOperator::Multiply => Ok(left_val * right_val),
-                    BinaryOperator::Divide => {
-                        if right_val == 0 {
-                            Err(BBCBasicError::DivisionByZero)
-                        } else {
-                            Ok(left_val / right_val)
-                        }
-                    }
-                    BinaryOperator::IntegerDivide => {
-                        if right_val == 0 {
-                            Err(BBCBasicError::DivisionByZero)
-                        } else {
-                            Ok(left_val / right_val)
-                        }
-                    }
-                    BinaryOperator::Modulo => Ok(left_val % right_val),
-                    BinaryOperator::Power => Ok(left_val.pow(right_val as u32)),
-                    // Comparison operators: return -1 for true, 0 for false (BBC BASIC convention)
-                    BinaryOperator::Equal => Ok(if left_val == right_val { -1 } else { 0 }),
-                    BinaryOperator::NotEqual => Ok(if left_val != right_val { -1 } else { 0 }),
-                    BinaryOperator::LessThan => Ok(if left_val < right_val { -1 } else { 0 }),
-                    BinaryOperator::LessThanOrEqual => {
-                        Ok(if left_val <= right_val { -1 } else { 0 })
-                    }
-                    BinaryOperator::GreaterThan => Ok(if left_val > right_val { -1 } else { 0 }),
-                    BinaryOperator::GreaterThanOrEqual => {
-                        Ok(if left_val >= right_val { -1 } else { 0 })
-                    }
-                    // Logical operators
-                    BinaryOperator::And => Ok(left_val & right_val),
-                    BinaryOperator::Or => Ok(left_val | right_val),
-                    BinaryOperator::Eor => Ok(left_val ^ right_val),
-                    // Bitwise shift operators
-                    BinaryOperator::LeftShift => {
-                        if right_val < 0 {
-                            return Err(BBCBasicError::IllegalFunction);
-                        }
-                        Ok(left_val << right_val)
-                    }
-                    BinaryOperator::RightShift => {
-                        if right_val < 0 {
-                            return Err(BBCBasicError::IllegalFunction);
-                        }
-                        Ok(left_val >> right_val)
-                    }
-                    _ => Err(BBCBasicError::IllegalFunction),
-                }
+                integer_binary_op(op, left_val, right_val)
             }
             Expression::UnaryOp { op, operand } => {
-                use crate::parser::UnaryOperator;
                 let val = self.eval_integer(operand)?;
-                match op {
-                    UnaryOperator::Minus => Ok(-val),
-                    UnaryOperator::Plus => Ok(val),
-                    UnaryOperator::Not => Ok(if val == 0 { -1 } else { 0 }),
-                }
+                Ok(integer_unary_op(op, val))
             }
             Expression::FunctionCall { name, args } => self.eval_function_int(name, args),
+            Expression::Indirect { op, address } => match self.read_indirect(*op, address)? {
+                Variable::Integer(value) => Ok(value),
+                _ => Err(BBCBasicError::TypeMismatch),
+            },
             _ => Err(BBCBasicError::TypeMismatch),
         }
     }
 
+    /// Read a variable (not a pseudo-variable) as an integer
+    pub(crate) fn integer_variable(&self, name: &str) -> Result<i32> {
+        if name.ends_with('%') {
+            match self.variables.get_integer_var(name) {
+                Some(int_val) => Ok(int_val),
+                None => self.undefined_variable(name),
+            }
+        } else {
+            // Try as real variable first, then as integer (for loop vars without % suffix)
+            if let Some(real_val) = self.variables.get_real_var(name) {
+                real_to_integer(real_val)
+            } else if let Some(int_val) = self.variables.get_integer_var(name) {
+                Ok(int_val)
+            } else {
+                self.undefined_variable(name)
+            }
+        }
+    }
+
+    /// Read a variable as a real
+    pub(crate) fn real_variable(&self, name: &str) -> Result<f64> {
+        if name.ends_with('%') {
+            match self.variables.get_integer_var(name) {
+                Some(int_val) => Ok(int_val as f64),
+                None => self.undefined_variable(name),
+            }
+        } else if name.ends_with('$') {
+            // String variable can't be converted to real
+            Err(BBCBasicError::TypeMismatch)
+        } else {
+            // Try as real variable first, then as integer
+            if let Some(real_val) = self.variables.get_real_var(name) {
+                Ok(real_val)
+            } else if let Some(int_val) = self.variables.get_integer_var(name) {
+                Ok(int_val as f64)
+            } else {
+                self.undefined_variable(name)
+            }
+        }
+    }
+
     /// Evaluate an expression to a real value
-    fn eval_real(&mut self, expr: &Expression) -> Result<f64> {
-        match expr {
+    pub(crate) fn eval_real(&mut self, expr: &Expression) -> Result<f64> {
+        let value = match expr {
             Expression::Integer(val) => Ok(*val as f64),
             Expression::Real(val) => Ok(*val),
-            Expression::Variable(name) => {
-                if name.ends_with('%') {
-                    let int_val = self
-                        .variables
-                        .get_integer_var(name)
-                        .ok_or_else(|| BBCBasicError::NoSuchVariable(name.clone()))?;
-                    Ok(int_val as f64)
-                } else if name.ends_with('$') {
-                    // String variable can't be converted to real
-                    Err(BBCBasicError::TypeMismatch)
-                } else {
-                    // Try as real variable first, then as integer
-                    if let Some(real_val) = self.variables.get_real_var(name) {
-                        Ok(real_val)
-                    } else if let Some(int_val) = self.variables.get_integer_var(name) {
-                        Ok(int_val as f64)
-                    } else {
-                        Err(BBCBasicError::NoSuchVariable(name.clone()))
-                    }
-                }
+            Expression::Variable(name) if PSEUDO_VARIABLES.contains(&name.as_str()) => {
+                Ok(self.eval_integer(expr)? as f64)
             }
+            Expression::Variable(name) => self.real_variable(name),
             Expression::ArrayAccess { name, indices } => {
                 use crate::variables::Variable;
                 // Evaluate all indices to integers
@@ -1062,48 +2833,33 @@ impl Executor {
                 }
             }
             Expression::BinaryOp { op, left, right } => {
-                use crate::parser::BinaryOperator;
                 let left_val = self.eval_real(left)?;
                 let right_val = self.eval_real(right)?;
-
-                match op {
-                    BinaryOperator::Add => Ok(left_val + right_val),
-                    BinaryOperator::Subtract => Ok(left_val - right_val),
-                    BinaryOperator::Multiply => Ok(left_val * right_val),
-                    BinaryOperator::Divide => {
-                        if right_val == 0.0 {
-                            Err(BBCBasicError::DivisionByZero)
-                        } else {
-                            Ok(left_val / right_val)
-                        }
-                    }
-                    BinaryOperator::Power => Ok(left_val.powf(right_val)),
-                    _ => Err(BBCBasicError::IllegalFunction),
-                }
+                real_binary_op(op, left_val, right_val)
             }
             Expression::UnaryOp { op, operand } => {
-                use crate::parser::UnaryOperator;
                 let val = self.eval_real(operand)?;
-                match op {
-                    UnaryOperator::Minus => Ok(-val),
-                    UnaryOperator::Plus => Ok(val),
-                    UnaryOperator::Not => Ok(if val == 0.0 { -1.0 } else { 0.0 }),
-                }
+                Ok(real_unary_op(op, val))
             }
             Expression::FunctionCall { name, args } => self.eval_function_real(name, args),
+            Expression::Indirect { .. } => Ok(self.eval_integer(expr)? as f64),
             _ => Err(BBCBasicError::TypeMismatch),
-        }
+        }?;
+        self.round_real(value)
     }
 
     /// Evaluate an expression to a string value
-    fn eval_string(&mut self, expr: &Expression) -> Result<String> {
+    pub(crate) fn eval_string(&mut self, expr: &Expression) -> Result<String> {
         match expr {
             Expression::String(val) => Ok(val.clone()),
-            Expression::Variable(name) => self
-                .variables
-                .get_string_var(name)
-                .map(|s| s.to_string())
-                .ok_or_else(|| BBCBasicError::NoSuchVariable(name.clone())),
+            Expression::Variable(name) if name == "GET$" => {
+                Ok(char::from(self.read_key()?).to_string())
+            }
+            Expression::Variable(name) if name == "REPORT$" => Ok(self.report()),
+            Expression::Variable(name) => match self.variables.get_string_var(name) {
+                Some(val) => Ok(val.to_string()),
+                None => self.undefined_variable(name),
+            },
             Expression::ArrayAccess { name, indices } => {
                 use crate::variables::Variable;
                 // Evaluate all indices to integers
@@ -1123,6 +2879,10 @@ impl Executor {
                 }
             }
             Expression::FunctionCall { name, args } => self.eval_function_string(name, args),
+            Expression::Indirect { op, address } => match self.read_indirect(*op, address)? {
+                Variable::String(value) => Ok(value),
+                _ => Err(BBCBasicError::TypeMismatch),
+            },
             _ => Err(BBCBasicError::TypeMismatch),
         }
     }
@@ -1131,7 +2891,7 @@ impl Executor {
     fn eval_function_int(&mut self, name: &str, args: &[Expression]) -> Result<i32> {
         // Check if this is a user-defined function first
         if self.functions.contains_key(name) {
-            return self.call_function_int(name, args);
+            return self.call_function(name, args, Self::eval_integer);
         }
 
         // Otherwise, it's a built-in function
@@ -1196,7 +2956,7 @@ impl Executor {
                     });
                 }
                 let s = self.eval_string(&args[0])?;
-                Ok(s.len() as i32)
+                Ok(s.chars().count() as i32)
             }
             "VAL" => {
                 if args.len() != 1 {
@@ -1206,7 +2966,7 @@ impl Executor {
                     });
                 }
                 let s = self.eval_string(&args[0])?;
-                s.trim().parse::<i32>().or_else(|_| Ok(0)) // BBC BASIC returns 0 for non-numeric strings
+                Ok(s.trim().parse::<i32>().unwrap_or(0)) // BBC BASIC returns 0 for non-numeric strings
             }
             "ERL" => {
                 // Error line number - returns 0 if no error has occurred
@@ -1250,6 +3010,17 @@ impl Executor {
                 let filename = self.eval_string(&args[0])?;
                 self.open_file_for_writing(&filename)
             }
+            "OPENUP" => {
+                // Open an existing file for reading and writing
+                if args.len() != 1 {
+                    return Err(BBCBasicError::SyntaxError {
+                        message: "OPENUP requires 1 argument (filename)".to_string(),
+                        line: None,
+                    });
+                }
+                let filename = self.eval_string(&args[0])?;
+                self.open_file_for_update(&filename)
+            }
             "EOF" => {
                 // Test for end of file, returns -1 (TRUE) if EOF, 0 (FALSE) otherwise
                 if args.len() != 1 {
@@ -1261,6 +3032,20 @@ impl Executor {
                 let handle = self.eval_integer(&args[0])?;
                 self.check_eof(handle)
             }
+            "BGET" | "EXT" | "PTR" => {
+                if args.len() != 1 {
+                    return Err(BBCBasicError::SyntaxError {
+                        message: format!("{}# requires a file handle", name),
+                        line: None,
+                    });
+                }
+                let handle = self.eval_integer(&args[0])?;
+                match name {
+                    "BGET" => self.bget(handle),
+                    "EXT" => self.get_ext(handle),
+                    _ => self.get_ptr(handle),
+                }
+            }
             "TRUE" => {
                 // TRUE constant = -1 (BBC BASIC convention)
                 if !args.is_empty() {
@@ -1305,13 +3090,73 @@ impl Executor {
                     0
                 };
 
-                // Search for needle in haystack starting from start_pos
-                if let Some(pos) = haystack[start_pos.min(haystack.len())..].find(&needle) {
+                // Search for needle in haystack starting from start_pos,
+                // counting in characters (bytes of the BBC character set)
+                let haystack: Vec<char> = haystack.chars().collect();
+                let needle: Vec<char> = needle.chars().collect();
+                let last = haystack.len().saturating_sub(needle.len());
+                Ok((start_pos..=last)
+                    .find(|&pos| haystack[pos..].starts_with(&needle))
                     // Return 1-based position relative to start of string
-                    Ok((start_pos + pos + 1) as i32)
-                } else {
-                    Ok(0)
+                    .map_or(0, |pos| (pos + 1) as i32))
+            }
+            "ADVAL" => {
+                // ADVAL(n) - analogue inputs (0-4) and buffer status (negative)
+                if args.len() != 1 {
+                    return Err(BBCBasicError::SyntaxError {
+                        message: "ADVAL requires 1 argument".to_string(),
+                        line: None,
+                    });
                 }
+                let n = self.eval_integer(&args[0])?;
+                Ok(match n {
+                    // Keys waiting in the keyboard buffer
+                    -1 => self.os.keyboard().len() as i32,
+                    // Free places in sound channel 0-3's queue
+                    -8..=-5 => {
+                        self.update_sound_clock();
+                        self.sound.free_slots((-5 - n) as usize) as i32
+                    }
+                    // No joystick, serial or printer: nothing pressed or waiting
+                    _ => 0,
+                })
+            }
+            "INKEY" => {
+                // INKEY(n) - wait up to n centiseconds for a key, -1 if none,
+                // or INKEY(-key) - TRUE if the key is held down
+                if args.len() != 1 {
+                    return Err(BBCBasicError::SyntaxError {
+                        message: "INKEY requires 1 argument".to_string(),
+                        line: None,
+                    });
+                }
+                let centiseconds = self.eval_integer(&args[0])?;
+                Ok(self.inkey(centiseconds))
+            }
+            "USR" => {
+                if args.len() != 1 {
+                    return Err(BBCBasicError::SyntaxError {
+                        message: "USR requires 1 argument".to_string(),
+                        line: None,
+                    });
+                }
+                // The registers come back as A + 256 * X + 65536 * Y
+                self.check_allowed("USR")?;
+                let address = self.eval_integer(&args[0])?;
+                let (a, x, y) = self.os_call(address)?;
+                Ok(i32::from_le_bytes([a, x, y, 0]))
+            }
+            // FNvdu(n) reads VDU variable n, numbered as OSBYTE &A0 numbers
+            // them
+            "vdu" => {
+                if args.len() != 1 {
+                    return Err(BBCBasicError::SyntaxError {
+                        message: "FNvdu requires 1 argument".to_string(),
+                        line: None,
+                    });
+                }
+                let number = self.eval_integer(&args[0])?;
+                self.vdu_variable(number)
             }
             "POINT" => {
                 // POINT(x, y) - Read pixel state at coordinates
@@ -1334,10 +3179,11 @@ impl Executor {
             // Real-only functions should not be called as integers
             "SIN" | "COS" | "TAN" | "ATN" | "SQR" | "SQRT" | "ACS" | "ASN" | "EXP" | "LN" | "LOG"
             | "DEG" | "RAD" | "PI" | "RND" => Err(BBCBasicError::TypeMismatch),
-            _ => Err(BBCBasicError::SyntaxError {
-                message: format!("Unknown function: {}", name),
-                line: None,
-            }),
+            // Anything else is an FN that was never defined
+            _ => Err(BBCBasicError::NoSuchVariable(format!(
+                "Function {} not defined",
+                name
+            ))),
         }
     }
 
@@ -1345,7 +3191,7 @@ impl Executor {
     fn eval_function_real(&mut self, name: &str, args: &[Expression]) -> Result<f64> {
         // Check if this is a user-defined function first
         if self.functions.contains_key(name) {
-            return self.call_function_real(name, args);
+            return self.call_function(name, args, Self::eval_real);
         }
 
         // Otherwise, it's a built-in function
@@ -1403,7 +3249,7 @@ impl Executor {
                 }
                 let val = self.eval_real(&args[0])?;
                 if val < 0.0 {
-                    return Err(BBCBasicError::IllegalFunction);
+                    return Err(BBCBasicError::NegativeRoot);
                 }
                 Ok(val.sqrt())
             }
@@ -1436,7 +3282,7 @@ impl Executor {
                 }
                 let val = self.eval_real(&args[0])?;
                 if val <= 0.0 {
-                    return Err(BBCBasicError::IllegalFunction);
+                    return Err(BBCBasicError::LogRange);
                 }
                 Ok(val.ln())
             }
@@ -1449,7 +3295,7 @@ impl Executor {
                 }
                 let val = self.eval_real(&args[0])?;
                 if val <= 0.0 {
-                    return Err(BBCBasicError::IllegalFunction);
+                    return Err(BBCBasicError::LogRange);
                 }
                 Ok(val.log10())
             }
@@ -1498,16 +3344,16 @@ impl Executor {
 
                 if (arg_value - 1.0).abs() < 0.0001 {
                     // RND(1) - return random float [0, 1)
-                    Ok(self.rng.borrow_mut().gen::<f64>())
+                    Ok(self.rng.gen::<f64>())
                 } else if arg_value > 1.0 {
                     // RND(n) - return random integer [1, n]
                     let n = arg_value as i32;
-                    let random_int = self.rng.borrow_mut().gen_range(1..=n);
+                    let random_int = self.rng.gen_range(1..=n);
                     Ok(random_int as f64)
                 } else {
                     // For other values, BBC BASIC behavior is undefined
                     // We'll return random [0, 1) as a sensible default
-                    Ok(self.rng.borrow_mut().gen::<f64>())
+                    Ok(self.rng.gen::<f64>())
                 }
             }
             "VAL" => {
@@ -1518,7 +3364,7 @@ impl Executor {
                     });
                 }
                 let s = self.eval_string(&args[0])?;
-                s.trim().parse::<f64>().or_else(|_| Ok(0.0)) // BBC BASIC returns 0 for non-numeric strings
+                Ok(s.trim().parse::<f64>().unwrap_or(0.0)) // BBC BASIC returns 0 for non-numeric strings
             }
             "SQRT" => {
                 // SQRT is an alias for SQR in BBC BASIC
@@ -1530,7 +3376,7 @@ impl Executor {
                 }
                 let val = self.eval_real(&args[0])?;
                 if val < 0.0 {
-                    return Err(BBCBasicError::IllegalFunction);
+                    return Err(BBCBasicError::NegativeRoot);
                 }
                 Ok(val.sqrt())
             }
@@ -1543,11 +3389,14 @@ impl Executor {
                     });
                 }
                 let val = self.eval_real(&args[0])?;
-                if val < -1.0 || val > 1.0 {
-                    return Err(BBCBasicError::IllegalFunction);
+                if !(-1.0..=1.0).contains(&val) {
+                    return Err(BBCBasicError::NegativeRoot);
                 }
                 Ok(val.acos())
             }
+            "INKEY" | "ADVAL" | "BGET" | "EOF" | "EXT" | "PTR" => {
+                Ok(self.eval_function_int(name, args)? as f64)
+            }
             "ASN" => {
                 // ASN(x) = arcsine in radians
                 if args.len() != 1 {
@@ -1557,15 +3406,16 @@ impl Executor {
                     });
                 }
                 let val = self.eval_real(&args[0])?;
-                if val < -1.0 || val > 1.0 {
-                    return Err(BBCBasicError::IllegalFunction);
+                if !(-1.0..=1.0).contains(&val) {
+                    return Err(BBCBasicError::NegativeRoot);
                 }
                 Ok(val.asin())
             }
-            _ => Err(BBCBasicError::SyntaxError {
-                message: format!("Unknown function: {}", name),
-                line: None,
-            }),
+            // Anything else is an FN that was never defined
+            _ => Err(BBCBasicError::NoSuchVariable(format!(
+                "Function {} not defined",
+                name
+            ))),
         }
     }
 
@@ -1573,11 +3423,36 @@ impl Executor {
     fn eval_function_string(&mut self, name: &str, args: &[Expression]) -> Result<String> {
         // Check if this is a user-defined function first
         if self.functions.contains_key(name) {
-            return self.call_function_string(name, args);
+            return self.call_function(name, args, Self::eval_string);
         }
 
         // Otherwise, it's a built-in function
         match name {
+            "GET$" => {
+                // GET$#channel - read a string from a file
+                if args.len() != 1 {
+                    return Err(BBCBasicError::SyntaxError {
+                        message: "GET$# requires a file handle".to_string(),
+                        line: None,
+                    });
+                }
+                let handle = self.eval_integer(&args[0])?;
+                self.get_string(handle)
+            }
+            "INKEY$" => {
+                // INKEY$(n) - wait up to n centiseconds for a key, "" if none
+                if args.len() != 1 {
+                    return Err(BBCBasicError::SyntaxError {
+                        message: "INKEY$ requires 1 argument".to_string(),
+                        line: None,
+                    });
+                }
+                let centiseconds = self.eval_integer(&args[0])?;
+                Ok(match self.inkey(centiseconds.max(0)) {
+                    -1 => String::new(),
+                    key => char::from(key as u8).to_string(),
+                })
+            }
             "CHR$" => {
                 if args.len() != 1 {
                     return Err(BBCBasicError::SyntaxError {
@@ -1586,7 +3461,7 @@ impl Executor {
                     });
                 }
                 let code = self.eval_integer(&args[0])?;
-                if code < 0 || code > 255 {
+                if !(0..=255).contains(&code) {
                     return Err(BBCBasicError::SyntaxError {
                         message: "CHR$ argument must be 0-255".to_string(),
                         line: None,
@@ -1660,16 +3535,16 @@ impl Executor {
                 }
                 // Check if the expression is explicitly a Real or contains decimal point
                 match &args[0] {
-                    Expression::Real(val) => Ok(val.to_string()),
+                    Expression::Real(val) => Ok(self.str_text(self.round_real(*val)?)),
                     Expression::Integer(val) => Ok(val.to_string()),
                     _ => {
                         // Try to evaluate - prefer real if it works
                         if let Ok(real_val) = self.eval_real(&args[0]) {
                             // Check if it's actually an integer value
-                            if real_val.fract() == 0.0 {
+                            if real_val.fract() == 0.0 && real_to_integer(real_val).is_ok() {
                                 Ok((real_val as i32).to_string())
                             } else {
-                                Ok(real_val.to_string())
+                                Ok(self.str_text(real_val))
                             }
                         } else if let Ok(int_val) = self.eval_integer(&args[0]) {
                             Ok(int_val.to_string())
@@ -1703,7 +3578,7 @@ impl Executor {
                     });
                 }
                 let s = self.eval_string(&args[0])?;
-                Ok(s.to_uppercase())
+                Ok(s.to_ascii_uppercase())
             }
             "LOWER$" => {
                 if args.len() != 1 {
@@ -1713,7 +3588,7 @@ impl Executor {
                     });
                 }
                 let s = self.eval_string(&args[0])?;
-                Ok(s.to_lowercase())
+                Ok(s.to_ascii_lowercase())
             }
             "STRING$" => {
                 if args.len() != 2 {
@@ -1741,10 +3616,34 @@ impl Executor {
                 }
                 Ok(error_msg)
             }
-            _ => Err(BBCBasicError::SyntaxError {
-                message: format!("Unknown string function: {}", name),
-                line: None,
-            }),
+            // FNoscli$("command") captures a star command's output;
+            // FNoscli$("command", N) gives line N of it, or the number of
+            // lines for N = 0, for listings too long for one string
+            "oscli$" => {
+                if !matches!(args.len(), 1 | 2) {
+                    return Err(BBCBasicError::SyntaxError {
+                        message: "FNoscli$ requires 1 or 2 arguments (command[, line])".to_string(),
+                        line: None,
+                    });
+                }
+                let command = self.eval_string(&args[0])?;
+                let output = self.oscli(&command)?;
+                let Some(line) = args.get(1) else {
+                    return Ok(output);
+                };
+                let line = self.eval_integer(line)?;
+                let mut lines = output.lines();
+                match line {
+                    0 => Ok(lines.count().to_string()),
+                    1.. => Ok(lines.nth(line as usize - 1).unwrap_or_default().to_string()),
+                    _ => Err(BBCBasicError::IllegalFunction),
+                }
+            }
+            // Anything else is an FN that was never defined
+            _ => Err(BBCBasicError::NoSuchVariable(format!(
+                "Function {} not defined",
+                name
+            ))),
         }
     }
 
@@ -1772,86 +3671,137 @@ impl Executor {
     }
 
     /// Check if the last NEXT caused a loop to continue (not complete)
-    /// Returns Some(line_number) if should loop back, None if loop completed
-    pub fn should_loop_back(&self) -> Option<u16> {
-        // If there are active FOR loops, return the line number of the most recent one
-        // This will be called after execute_next to determine if we should jump back
-        self.for_loops.last().map(|(_, _, _, line)| *line)
+    /// Returns Some(FOR position) if should loop back, None if loop completed
+    pub fn should_loop_back(&self) -> Option<Position> {
+        // Called after execute_next, which knows whether its own loop ended:
+        // an inner loop ending must not send an outer one round again
+        self.next_loop
+    }
+
+    /// Active FOR loops, outermost first
+    pub fn for_loops(&self) -> &[ForLoop] {
+        &self.for_loops
+    }
+
+    /// REPEATs of the active REPEAT loops, outermost first
+    pub fn repeat_loops(&self) -> &[Position] {
+        &self.repeat_stack
+    }
+
+    /// WHILEs of the active WHILE loops, outermost first
+    pub fn while_loops(&self) -> &[Position] {
+        &self.while_stack
+    }
+
+    /// Statements making the active GOSUB and PROC calls, outermost first
+    pub fn return_positions(&self) -> &[Position] {
+        &self.return_stack
+    }
+
+    /// Value of a variable as seen inside the PROC/FN at `depth` in the
+    /// local scope stack (0 = outermost)
+    ///
+    /// Calls further in save the values they hide, so the value is the one
+    /// saved by the first of them to make the name local, or the current
+    /// value if none did. Whole arrays are named with "()", as in LOCAL.
+    pub fn variable_at_depth(&self, depth: usize, name: &str) -> Option<&Variable> {
+        let current = name.strip_suffix("()").unwrap_or(name);
+        self.local_stack
+            .iter()
+            .skip(depth + 1)
+            .find_map(|frame| frame.saved_variables.get(name))
+            .map_or_else(|| self.variables.get_variable(current), Option::as_ref)
     }
 
-    /// Set the line number for a FOR loop (called when FOR is executed)
-    pub fn set_for_loop_line(&mut self, line_number: u16) {
+    /// Set the position of a FOR loop (called when FOR is executed)
+    pub fn set_for_loop_position(&mut self, position: Position) {
         if let Some(loop_state) = self.for_loops.last_mut() {
-            loop_state.3 = line_number;
+            loop_state.position = position;
         }
     }
 
-    /// Push a REPEAT line number onto the repeat stack
-    pub fn push_repeat(&mut self, line_number: u16) {
-        self.repeat_stack.push(line_number);
+    /// Push a REPEAT position onto the repeat stack
+    pub fn push_repeat(&mut self, position: Position) {
+        self.repeat_stack.push(position);
     }
 
-    /// Evaluate UNTIL condition and return the REPEAT line if we should loop back
-    pub fn check_until(&mut self, condition: &Expression) -> Result<Option<u16>> {
+    /// Evaluate UNTIL condition and return the REPEAT if we should loop back
+    pub fn check_until(&mut self, condition: &Expression) -> Result<Option<Position>> {
         // Evaluate the condition
         let result = self.eval_integer(condition)?;
+        Ok(self.check_until_value(result))
+    }
 
+    /// UNTIL with an already evaluated condition
+    pub fn check_until_value(&mut self, result: i32) -> Option<Position> {
         if result == 0 {
             // Condition is false - loop back to REPEAT
-            // Return the REPEAT line number but keep it on stack (don't pop yet)
-            Ok(self.repeat_stack.last().copied())
+            // Return the REPEAT position but keep it on stack (don't pop yet)
+            self.repeat_stack.last().copied()
         } else {
             // Condition is true - exit loop
             self.repeat_stack.pop();
-            Ok(None)
+            None
         }
     }
 
-    /// Push a WHILE line number onto the while stack and check condition
-    /// Returns Some(line_number) if condition is TRUE (continue to loop body)
+    /// Push a WHILE position onto the while stack and check condition
+    /// Returns Some(position) if condition is TRUE (continue to loop body)
     /// Returns None if condition is FALSE (skip loop body)
-    pub fn push_while(&mut self, line_number: u16, condition: &Expression) -> Result<Option<u16>> {
+    pub fn push_while(
+        &mut self,
+        position: Position,
+        condition: &Expression,
+    ) -> Result<Option<Position>> {
         // Evaluate the condition
         let result = self.eval_integer(condition)?;
+        Ok(self.push_while_value(position, result))
+    }
 
+    /// WHILE with an already evaluated condition
+    pub fn push_while_value(&mut self, position: Position, result: i32) -> Option<Position> {
         if result != 0 {
             // Condition is true - enter loop body
-            self.while_stack.push(line_number);
-            Ok(Some(line_number))
+            self.while_stack.push(position);
+            Some(position)
         } else {
             // Condition is false - skip loop body
-            Ok(None)
+            None
         }
     }
 
-    /// Handle ENDWHILE - return the WHILE line if we should loop back
-    pub fn check_endwhile(&mut self, condition: &Expression) -> Result<Option<u16>> {
+    /// Handle ENDWHILE - return the WHILE if we should loop back
+    pub fn check_endwhile(&mut self, condition: &Expression) -> Result<Option<Position>> {
         // Evaluate the condition
         let result = self.eval_integer(condition)?;
+        Ok(self.check_endwhile_value(result))
+    }
 
+    /// ENDWHILE with an already evaluated condition
+    pub fn check_endwhile_value(&mut self, result: i32) -> Option<Position> {
         if result != 0 {
             // Condition is still true - loop back to WHILE
-            // Return the WHILE line number but keep it on stack (don't pop yet)
-            Ok(self.while_stack.last().copied())
+            // Return the WHILE position but keep it on stack (don't pop yet)
+            self.while_stack.last().copied()
         } else {
             // Condition is false - exit loop
             self.while_stack.pop();
-            Ok(None)
+            None
         }
     }
 
-    /// Get the current WHILE line number without popping (for ENDWHILE to retrieve condition)
-    pub fn check_endwhile_get_while_line(&self) -> Option<u16> {
+    /// Get the current WHILE position without popping (for ENDWHILE to retrieve condition)
+    pub fn check_endwhile_get_while_position(&self) -> Option<Position> {
         self.while_stack.last().copied()
     }
 
-    /// Push a return address onto the GOSUB stack
-    pub fn push_gosub_return(&mut self, line_number: u16) {
-        self.return_stack.push(line_number);
+    /// Push a return address (the calling statement) onto the GOSUB stack
+    pub fn push_gosub_return(&mut self, position: Position) {
+        self.return_stack.push(position);
     }
 
     /// Pop a return address from the GOSUB stack
-    pub fn pop_gosub_return(&mut self) -> Result<u16> {
+    pub fn pop_gosub_return(&mut self) -> Result<Position> {
         self.return_stack.pop().ok_or(BBCBasicError::BadCall)
     }
 
@@ -1871,29 +3821,160 @@ impl Executor {
         self.procedures.get(name)
     }
 
+    /// Every procedure defined, by name
+    pub fn procedures(&self) -> impl Iterator<Item = (&String, &ProcedureDefinition)> {
+        self.procedures.iter()
+    }
+
+    /// Define a function with DEF FN on a program line
+    pub fn define_function(
+        &mut self,
+        name: String,
+        line_number: u16,
+        params: Vec<String>,
+        expression: Option<Expression>,
+    ) {
+        self.functions.insert(
+            name,
+            FunctionDefinition {
+                params,
+                expression,
+                line_number: Some(line_number),
+            },
+        );
+    }
+
+    /// Give multi-line functions the compiled program their bodies run in
+    pub fn set_compiled_program(&mut self, compiled: Arc<CompiledProgram>) {
+        self.compiled = Some(compiled);
+    }
+
+    /// The compiled program multi-line functions run in, if there is one
+    pub fn compiled_program(&self) -> Option<Arc<CompiledProgram>> {
+        self.compiled.clone()
+    }
+
+    /// Whether a function has been defined with DEF FN
+    pub fn has_function(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    /// Every function defined with DEF FN, by name
+    pub fn functions(&self) -> impl Iterator<Item = (&String, &FunctionDefinition)> {
+        self.functions.iter()
+    }
+
     /// Enter a new local scope (called on PROC/FN entry)
     pub fn enter_local_scope(&mut self) {
         self.local_stack.push(LocalFrame::new());
     }
 
-    /// Declare a local variable (called on LOCAL statement)
-    pub fn declare_local(&mut self, name: &str) -> Result<()> {
-        let frame = self
-            .local_stack
+    /// Number of PROC and FN calls in progress
+    pub fn call_depth(&self) -> usize {
+        self.local_stack.len()
+    }
+
+    /// Enter a PROC/FN: evaluate its arguments, enter a local scope and bind
+    /// them to the parameters
+    ///
+    /// Numbers and strings are passed by value. A whole array argument such as
+    /// `A%()` is passed by reference, as on BASIC V: the parameter gets the
+    /// array, which goes back to the caller's name when the scope exits.
+    pub fn enter_procedure(&mut self, params: &[String], args: &[Expression]) -> Result<()> {
+        use crate::parser::ExpressionType;
+
+        if params.len() != args.len() {
+            return Err(BBCBasicError::Arguments);
+        }
+        if self.safe.max_depth != 0 && self.local_stack.len() >= self.safe.max_depth {
+            return Err(BBCBasicError::NoRoom);
+        }
+
+        // Evaluate all the arguments before binding any parameter, so that
+        // they see the caller's variables
+        let suffix = |name: &str| name.chars().last().filter(|c| matches!(c, '%' | '$'));
+        let mut values = Vec::with_capacity(args.len());
+        for (param, arg) in params.iter().zip(args) {
+            let whole_array = match arg {
+                Expression::ArrayAccess { name, indices } if indices.is_empty() => Some(name),
+                _ => None,
+            };
+            let arg_type = arg.expression_type();
+            let value = match (param.strip_suffix("()"), whole_array) {
+                (Some(param), Some(name)) => {
+                    if suffix(param) != suffix(name) {
+                        return Err(BBCBasicError::TypeMismatch);
+                    }
+                    match self.variables.get_variable(name) {
+                        Some(array) if array.dimensions().is_some() => {
+                            (array.clone(), Some(name.clone()))
+                        }
+                        _ => return Err(BBCBasicError::ArrayNotDimensioned(name.clone())),
+                    }
+                }
+                (None, None) if param.ends_with('$') => {
+                    if matches!(arg_type, ExpressionType::Integer | ExpressionType::Real) {
+                        return Err(BBCBasicError::TypeMismatch);
+                    }
+                    (Variable::String(self.eval_string(arg)?), None)
+                }
+                (None, None) if arg_type == ExpressionType::String => {
+                    return Err(BBCBasicError::TypeMismatch);
+                }
+                (None, None) if param.ends_with('%') => {
+                    (Variable::Integer(self.eval_integer(arg)?), None)
+                }
+                (None, None) => (Variable::Real(self.eval_real(arg)?), None),
+                // An array where a value is expected, or the other way round
+                _ => return Err(BBCBasicError::TypeMismatch),
+            };
+            values.push(value);
+        }
+
+        self.enter_local_scope();
+        for (param, (value, array)) in params.iter().zip(values) {
+            self.declare_local(param)?;
+            let name = param.strip_suffix("()").unwrap_or(param).to_string();
+            self.variables.insert_variable(name.clone(), value);
+            if let Some(array) = array {
+                self.current_local_frame()?
+                    .array_arguments
+                    .push((name, array));
+            }
+        }
+        Ok(())
+    }
+
+    /// The frame of the innermost PROC/FN, for LOCAL
+    fn current_local_frame(&mut self) -> Result<&mut LocalFrame> {
+        self.local_stack
             .last_mut()
             .ok_or_else(|| BBCBasicError::SyntaxError {
                 message: "LOCAL outside of procedure".to_string(),
                 line: None,
-            })?;
+            })
+    }
+
+    /// Declare a local variable (called on LOCAL statement)
+    ///
+    /// A name ending in "()" makes a whole array local: the array is saved and
+    /// removed, so a DIM inside the procedure creates a fresh one.
+    pub fn declare_local(&mut self, name: &str) -> Result<()> {
+        let array = name.strip_suffix("()");
+        let current_value = self.variables.get_variable(array.unwrap_or(name)).cloned();
 
-        // Save current value (or None if doesn't exist)
-        let current_value = self.variables.get_variable(name).cloned();
-        frame
+        // Only the first LOCAL of a name in a frame holds the caller's value
+        self.current_local_frame()?
             .saved_variables
-            .insert(name.to_string(), current_value);
+            .entry(name.to_string())
+            .or_insert(current_value);
+
+        if let Some(array) = array {
+            self.variables.remove_variable(array);
+            return Ok(());
+        }
 
-        // Remove the variable from the main scope (creating a new local binding)
-        // We'll set it to a default value for its type
+        // Create a new local binding with the default value for its type
         if name.ends_with('%') {
             self.variables.set_integer_var(name.to_string(), 0);
         } else if name.ends_with('$') {
@@ -1916,30 +3997,46 @@ impl Executor {
                 line: None,
             })?;
 
+        // Arrays passed by reference go back to the caller once the
+        // parameters' own saved values are restored
+        let arrays: Vec<_> = frame
+            .array_arguments
+            .iter()
+            .map(|(param, array)| (array.clone(), self.variables.get_variable(param).cloned()))
+            .collect();
+
         // Restore all saved variables
         for (name, saved_value) in frame.saved_variables {
-            match saved_value {
-                Some(var) => {
-                    // Restore previous value
-                    match var {
-                        Variable::Integer(v) => self.variables.set_integer_var(name, v),
-                        Variable::Real(v) => self.variables.set_real_var(name, v),
-                        Variable::String(v) => {
-                            let _ = self.variables.set_string_var(name, v);
-                        }
-                        Variable::IntegerArray { .. }
-                        | Variable::RealArray { .. }
-                        | Variable::StringArray { .. } => {
-                            // For arrays, we need to restore them via dim_array
-                            // This is complex, so for now we'll just leave them
-                            // TODO: Proper array restoration
-                        }
-                    }
-                }
-                None => {
-                    // Variable didn't exist before - ideally we'd remove it
-                    // For now, just leave it (BBC BASIC allows this)
-                }
+            let array = name.strip_suffix("()");
+            match (saved_value, array) {
+                (Some(var), _) => self
+                    .variables
+                    .insert_variable(array.unwrap_or(&name).to_string(), var),
+                // A local array that didn't exist before goes away, so the
+                // caller can DIM it later
+                (None, Some(array)) => self.variables.remove_variable(array),
+                // Other variables that didn't exist before are left in place
+                // (BBC BASIC allows this)
+                (None, None) => {}
+            }
+        }
+
+        for (array, value) in arrays {
+            if let Some(value) = value {
+                self.variables.insert_variable(array, value);
+            }
+        }
+
+        if let Some(data_pointer) = frame.data_pointer {
+            self.data_pointer = data_pointer;
+        }
+        // Error handling saved by LOCAL ERROR comes back, with anything
+        // the procedure saved after it
+        if let Some(depth) = frame.error_depth {
+            let depth = depth.min(self.error_stack.len());
+            let state = self.error_stack.drain(depth..).next();
+            if let Some(state) = state {
+                self.restore_error_state(state);
             }
         }
 
@@ -1949,17 +4046,78 @@ impl Executor {
     /// Execute LOCAL statement
     fn execute_local(&mut self, variables: &[String]) -> Result<()> {
         for var in variables {
+            self.check_constant(var)?;
             self.declare_local(var)?;
         }
         Ok(())
     }
 
-    /// Helper method for tests: set integer variable
-    #[cfg(test)]
+    /// Record the size of the tokenized program, which sets TOP and LOMEM
+    ///
+    /// Fails with "No room" if the program and variables would not fit below
+    /// HIMEM.
+    pub fn set_program_size(&mut self, size: usize) -> Result<()> {
+        self.memory.set_layout(size, self.heap_size())
+    }
+
+    /// Memory map for a program of `program_size` bytes at PAGE, with the
+    /// variables as they are now
+    pub fn memory_status(&self, program_size: usize) -> MemoryStatus {
+        let page = self.memory.get_page();
+        let himem = self.memory.get_himem();
+        let top = page.saturating_add(u16::try_from(program_size).unwrap_or(u16::MAX));
+        let heap = self.heap_size();
+        let mut status = MemoryStatus {
+            page,
+            top,
+            lomem: top,
+            himem,
+            heap,
+            free: (himem.saturating_sub(top) as usize).saturating_sub(heap),
+            integers: 0,
+            reals: 0,
+            strings: 0,
+            arrays: 0,
+        };
+        for (_, variable) in self.variables.iter() {
+            let count = match variable {
+                v if v.is_array() => &mut status.arrays,
+                Variable::Integer(_) => &mut status.integers,
+                Variable::Real(_) => &mut status.reals,
+                _ => &mut status.strings,
+            };
+            *count += 1;
+        }
+        status
+    }
+
+    /// The variable store
+    pub fn variables(&self) -> &VariableStore {
+        &self.variables
+    }
+
+    /// The variable store, for setting variables directly
+    pub fn variables_mut(&mut self) -> &mut VariableStore {
+        &mut self.variables
+    }
+
+    /// Forget the variables, arrays and DIMmed memory, keeping only the
+    /// resident integers (CLEAR, and as NEW, RUN and CHAIN do)
+    pub fn clear_variables(&mut self) {
+        self.variables.clear();
+        self.dim_space = 0;
+    }
+
+    /// Set an integer variable
     pub fn set_variable_int(&mut self, name: &str, value: i32) {
         self.variables.set_integer_var(name.to_string(), value);
     }
 
+    /// Set a real variable
+    pub fn set_variable_real(&mut self, name: &str, value: f64) {
+        self.variables.set_real_var(name.to_string(), value);
+    }
+
     /// Clear all procedure definitions (used when loading new program)
     pub fn clear_procedures(&mut self) {
         self.procedures.clear();
@@ -1980,8 +4138,16 @@ impl Executor {
         self.error_handler
     }
 
+    /// The line ON ERROR sends an error to, if it can be trapped
+    pub fn error_handler_for(&self, error: &BBCBasicError) -> Option<u16> {
+        self.error_handler.filter(|_| error.is_trappable())
+    }
+
     /// Set last error information
     pub fn set_last_error(&mut self, error_number: i32, error_line: u16, message: String) {
+        if self.trace {
+            trace::trapped(error_number, &message, error_line);
+        }
         self.last_error = Some(ErrorInfo {
             error_number,
             error_line,
@@ -2005,6 +4171,14 @@ impl Executor {
             .unwrap_or(0)
     }
 
+    /// Get the last error's message (REPORT and REPORT$)
+    pub fn report(&self) -> String {
+        self.last_error
+            .as_ref()
+            .map(|e| e.message.clone())
+            .unwrap_or_default()
+    }
+
     /// Get last error information (for extension functions)
     #[cfg(test)]
     pub fn get_last_error(&self) -> Option<&ErrorInfo> {
@@ -2016,211 +4190,148 @@ impl Executor {
         &mut self,
         name: &str,
         params: &[String],
-        expression: &Expression,
+        expression: Option<&Expression>,
     ) -> Result<()> {
         self.functions.insert(
             name.to_string(),
             FunctionDefinition {
                 params: params.to_vec(),
-                expression: expression.clone(),
+                expression: expression.cloned(),
+                line_number: self.current_line,
             },
         );
         Ok(())
     }
 
-    /// Call a function and return integer result
-    fn call_function_int(&mut self, name: &str, args: &[Expression]) -> Result<i32> {
-        let func = self
-            .functions
-            .get(name)
-            .ok_or_else(|| BBCBasicError::NoSuchVariable(format!("Function {} not defined", name)))?
-            .clone();
-
-        // Check parameter count
-        if args.len() != func.params.len() {
-            return Err(BBCBasicError::SyntaxError {
-                message: format!(
-                    "Function {} expects {} parameters, got {}",
-                    name,
-                    func.params.len(),
-                    args.len()
-                ),
-                line: None,
-            });
-        }
-
-        // Enter local scope for function
-        self.enter_local_scope();
-
-        // Evaluate arguments and bind to parameters
-        for (param_name, arg_expr) in func.params.iter().zip(args.iter()) {
-            // Declare parameter as local
-            self.declare_local(param_name)?;
-
-            // Evaluate argument and assign to parameter
-            if param_name.ends_with('%') {
-                let value = self.eval_integer(arg_expr)?;
-                self.variables.set_integer_var(param_name.clone(), value);
-            } else if param_name.ends_with('$') {
-                let value = self.eval_string(arg_expr)?;
-                self.variables.set_string_var(param_name.clone(), value)?;
-            } else {
-                let value = self.eval_real(arg_expr)?;
-                self.variables.set_real_var(param_name.clone(), value);
-            }
+    /// The expression giving a function's result, once its arguments are
+    /// bound: a single-line function's own, or for a multi-line function
+    /// the value of the RETURN its body reaches
+    fn function_result(&mut self, name: &str, func: FunctionDefinition) -> Result<Expression> {
+        if let Some(expression) = func.expression {
+            return Ok(expression);
         }
-
-        // Evaluate function expression
-        let result = self.eval_integer(&func.expression)?;
-
-        // Exit local scope (restore variables)
-        self.exit_local_scope()?;
-
-        Ok(result)
+        let (compiled, start) = self
+            .compiled
+            .clone()
+            .and_then(|compiled| {
+                let start = compiled.find(func.line_number?)?;
+                Some((compiled, start))
+            })
+            .ok_or_else(|| {
+                BBCBasicError::NoSuchVariable(format!("Function {} not defined", name))
+            })?;
+        // RETURN may leave loops in the body unfinished: drop them after
+        let line_number = self.current_line;
+        let loops = (
+            self.for_loops.len(),
+            self.repeat_stack.len(),
+            self.while_stack.len(),
+        );
+        let result = compiled.run_function(self, start + 1);
+        self.current_line = line_number;
+        self.for_loops.truncate(loops.0);
+        self.repeat_stack.truncate(loops.1);
+        self.while_stack.truncate(loops.2);
+        result
     }
 
-    /// Call a function and return real result
-    fn call_function_real(&mut self, name: &str, args: &[Expression]) -> Result<f64> {
+    /// Call a function, giving its result as `eval` evaluates it
+    fn call_function<T>(
+        &mut self,
+        name: &str,
+        args: &[Expression],
+        eval: fn(&mut Self, &Expression) -> Result<T>,
+    ) -> Result<T> {
         let func = self
             .functions
             .get(name)
             .ok_or_else(|| BBCBasicError::NoSuchVariable(format!("Function {} not defined", name)))?
             .clone();
 
-        // Check parameter count
-        if args.len() != func.params.len() {
-            return Err(BBCBasicError::SyntaxError {
-                message: format!(
-                    "Function {} expects {} parameters, got {}",
-                    name,
-                    func.params.len(),
-                    args.len()
-                ),
-                line: None,
-            });
-        }
-
-        // Enter local scope for function
-        self.enter_local_scope();
-
-        // Evaluate arguments and bind to parameters
-        for (param_name, arg_expr) in func.params.iter().zip(args.iter()) {
-            // Declare parameter as local
-            self.declare_local(param_name)?;
-
-            // Evaluate argument and assign to parameter
-            if param_name.ends_with('%') {
-                let value = self.eval_integer(arg_expr)?;
-                self.variables.set_integer_var(param_name.clone(), value);
-            } else if param_name.ends_with('$') {
-                let value = self.eval_string(arg_expr)?;
-                self.variables.set_string_var(param_name.clone(), value)?;
-            } else {
-                let value = self.eval_real(arg_expr)?;
-                self.variables.set_real_var(param_name.clone(), value);
-            }
+        // Enter local scope and bind arguments to parameters
+        self.enter_procedure(&func.params, args)?;
+        if self.trace {
+            trace::enter("FN", name, self.call_depth());
         }
 
         // Evaluate function expression
-        let result = self.eval_real(&func.expression)?;
+        let expression = self.function_result(name, func)?;
+        let result = eval(self, &expression)?;
 
         // Exit local scope (restore variables)
         self.exit_local_scope()?;
+        if self.trace {
+            trace::leave("FN", self.call_depth());
+        }
 
         Ok(result)
     }
 
-    /// Call a function and return string result
-    fn call_function_string(&mut self, name: &str, args: &[Expression]) -> Result<String> {
-        let func = self
-            .functions
-            .get(name)
-            .ok_or_else(|| BBCBasicError::NoSuchVariable(format!("Function {} not defined", name)))?
-            .clone();
-
-        // Check parameter count
-        if args.len() != func.params.len() {
-            return Err(BBCBasicError::SyntaxError {
-                message: format!(
-                    "Function {} expects {} parameters, got {}",
-                    name,
-                    func.params.len(),
-                    args.len()
-                ),
-                line: None,
-            });
-        }
-
-        // Enter local scope for function
-        self.enter_local_scope();
-
-        // Evaluate arguments and bind to parameters
-        for (param_name, arg_expr) in func.params.iter().zip(args.iter()) {
-            // Declare parameter as local
-            self.declare_local(param_name)?;
-
-            // Evaluate argument and assign to parameter
-            if param_name.ends_with('%') {
-                let value = self.eval_integer(arg_expr)?;
-                self.variables.set_integer_var(param_name.clone(), value);
-            } else if param_name.ends_with('$') {
-                let value = self.eval_string(arg_expr)?;
-                self.variables.set_string_var(param_name.clone(), value)?;
-            } else {
-                let value = self.eval_real(arg_expr)?;
-                self.variables.set_real_var(param_name.clone(), value);
-            }
-        }
-
-        // Evaluate function expression
-        let result = self.eval_string(&func.expression)?;
-
-        // Exit local scope (restore variables)
-        self.exit_local_scope()?;
-
-        Ok(result)
+    /// Lowest handle not in use, as the filing system allocates them
+    fn free_file_handle(&self) -> Result<i32> {
+        (FIRST_FILE_HANDLE..=LAST_FILE_HANDLE)
+            .find(|handle| !self.open_files.contains_key(handle))
+            .ok_or(BBCBasicError::TooManyOpenFiles)
     }
 
     /// Open a file for reading (OPENIN)
     fn open_file_for_reading(&mut self, filename: &str) -> Result<i32> {
-        // Check if we've hit the maximum number of open files (BBC BASIC limit: ~255)
-        if self.open_files.len() >= 255 {
-            return Err(BBCBasicError::TooManyOpenFiles);
-        }
+        // Find a free handle before touching the file
+        let handle = self.free_file_handle()?;
 
         // Try to open the file
-        let file = File::open(filename)
+        let file = File::open(self.resolve_path(filename)?)
             .map_err(|_| BBCBasicError::FileNotFound(filename.to_string()))?;
         let reader = BufReader::new(file);
 
-        // Allocate a handle
-        let handle = self.next_file_handle;
-        self.next_file_handle += 1;
-
         // Store the file handle
         self.open_files.insert(handle, FileHandle::Input(reader));
+        if self.trace {
+            trace::open("OPENIN", filename, handle);
+        }
 
         Ok(handle)
     }
 
     /// Open a file for writing (OPENOUT)
     fn open_file_for_writing(&mut self, filename: &str) -> Result<i32> {
-        // Check if we've hit the maximum number of open files
-        if self.open_files.len() >= 255 {
-            return Err(BBCBasicError::TooManyOpenFiles);
-        }
+        self.check_allowed("OPENOUT")?;
+        // Find a free handle before touching the file
+        let handle = self.free_file_handle()?;
+
+        // DFS directories exist implicitly, so make the host one on first use
+        let path = self.resolve_path(filename)?;
+        create_parent_directory(&path)?;
 
         // Try to create/truncate the file
-        let file = File::create(filename)
+        let file = File::create(path)
             .map_err(|e| BBCBasicError::DiskError(format!("Cannot create file: {}", e)))?;
         let writer = BufWriter::new(file);
 
-        // Allocate a handle
-        let handle = self.next_file_handle;
-        self.next_file_handle += 1;
-
         // Store the file handle
         self.open_files.insert(handle, FileHandle::Output(writer));
+        if self.trace {
+            trace::open("OPENOUT", filename, handle);
+        }
+
+        Ok(handle)
+    }
+
+    /// Open an existing file for reading and writing (OPENUP)
+    fn open_file_for_update(&mut self, filename: &str) -> Result<i32> {
+        self.check_allowed("OPENUP")?;
+        let handle = self.free_file_handle()?;
+
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(self.resolve_path(filename)?)
+            .map_err(|_| BBCBasicError::FileNotFound(filename.to_string()))?;
+
+        self.open_files.insert(handle, FileHandle::Update(file));
+        if self.trace {
+            trace::open("OPENUP", filename, handle);
+        }
 
         Ok(handle)
     }
@@ -2244,6 +4355,16 @@ impl Executor {
                     0 // FALSE in BBC BASIC
                 })
             }
+            FileHandle::Update(file) => {
+                let pos = file
+                    .stream_position()
+                    .map_err(|e| BBCBasicError::DiskError(e.to_string()))?;
+                let size = file
+                    .metadata()
+                    .map_err(|e| BBCBasicError::DiskError(e.to_string()))?
+                    .len();
+                Ok(if pos >= size { -1 } else { 0 })
+            }
             FileHandle::Output(_) => {
                 // Can't check EOF on output files
                 Err(BBCBasicError::BadCall)
@@ -2252,289 +4373,367 @@ impl Executor {
     }
 
     /// Execute PRINT# statement - write to file
+    ///
+    /// Each value is written as a typed binary record, as BBC BASIC does:
+    /// &40 and a four byte integer (most significant byte first), &FF and a
+    /// five byte real (mantissa least significant byte first, then the
+    /// exponent), or &00, a length byte and the string's characters in
+    /// reverse order.
     fn execute_print_file(&mut self, handle_expr: &Expression, items: &[crate::parser::PrintItem]) -> Result<()> {
         // Evaluate the handle
         let handle = self.eval_integer(handle_expr)?;
 
-        // Process print items and build output string first (to avoid borrow issues)
+        // Encode the values first (to avoid borrow issues); separators and
+        // TAB/SPC have no meaning in a data file
         use crate::parser::PrintItem;
-        let mut output = String::new();
-        
+        let mut output = Vec::new();
         for item in items {
-            match item {
-                PrintItem::Expression(expr) => {
-                    output.push_str(&self.format_expression(expr)?);
-                }
-                PrintItem::Tab(_) | PrintItem::Spc(_) => {
-                    // TAB and SPC not typically used in file I/O, but we can support them
-                    // For simplicity, ignore them in file output
-                }
-                PrintItem::Semicolon => {
-                    // Semicolon suppresses newline - do nothing
-                }
-                PrintItem::Comma => {
-                    // Comma outputs a tab
-                    output.push('\t');
+            if let PrintItem::Expression(expr) = item {
+                if is_string_expression(expr) {
+                    let bytes = crate::charset::to_bytes(&self.eval_string(expr)?);
+                    output.extend([0x00, bytes.len() as u8]);
+                    output.extend(bytes.iter().rev());
+                } else if is_integer_expression(expr) {
+                    output.push(0x40);
+                    output.extend(self.eval_integer(expr)?.to_be_bytes());
+                } else {
+                    output.push(0xFF);
+                    output.extend(encode_real(self.eval_real(expr)?)?);
                 }
             }
         }
 
-        // Add newline unless last item was semicolon
-        if !items.is_empty() {
-            if let Some(last) = items.last() {
-                if !matches!(last, PrintItem::Semicolon) {
-                    output.push('\n');
-                }
-            }
-        } else {
-            output.push('\n');
-        }
+        self.write_file_bytes(handle, &output)
+    }
 
-        // Now get the file handle and write
+    /// Write bytes to an output file
+    fn write_file_bytes(&mut self, handle: i32, bytes: &[u8]) -> Result<()> {
+        self.io += 1;
         let file_handle = self
             .open_files
             .get_mut(&handle)
             .ok_or(BBCBasicError::ChannelNotOpen(handle))?;
 
-        // Only output files can be written to
-        let writer = match file_handle {
+        // Only output and update files can be written to
+        let writer: &mut dyn Write = match file_handle {
             FileHandle::Output(writer) => writer,
+            FileHandle::Update(file) => file,
             FileHandle::Input(_) => return Err(BBCBasicError::BadCall),
         };
 
-        // Write the output
-        write!(writer, "{}", output)
+        writer
+            .write_all(bytes)
             .map_err(|e| BBCBasicError::DiskError(format!("Write error: {}", e)))?;
 
         // Flush to ensure data is written
         writer
             .flush()
-            .map_err(|e| BBCBasicError::DiskError(format!("Flush error: {}", e)))?;
+            .map_err(|e| BBCBasicError::DiskError(format!("Flush error: {}", e)))
+    }
 
-        Ok(())
+    /// Read bytes from an input file, failing with Eof if it ends first
+    fn read_file_bytes<const N: usize>(&mut self, handle: i32) -> Result<[u8; N]> {
+        let mut bytes = [0; N];
+        for byte in &mut bytes {
+            *byte = u8::try_from(self.bget(handle)?).map_err(|_| BBCBasicError::Eof)?;
+        }
+        Ok(bytes)
     }
 
-    /// Execute INPUT# statement - read from file
-    fn execute_input_file(&mut self, handle_expr: &Expression, variables: &[String]) -> Result<()> {
+    /// Execute INPUT# statement - read typed binary records from file
+    /// (see PRINT#); numbers convert between integer and real
+    fn execute_input_file(
+        &mut self,
+        handle_expr: &Expression,
+        variables: &[Expression],
+    ) -> Result<()> {
+        self.io += 1;
         // Evaluate the handle
         let handle = self.eval_integer(handle_expr)?;
 
-        // Get the file handle
-        let file_handle = self
-            .open_files
-            .get_mut(&handle)
-            .ok_or(BBCBasicError::ChannelNotOpen(handle))?;
+        for target in variables {
+            let var_name = target_name(target);
+            self.check_constant(var_name)?;
+            let [record_type] = self.read_file_bytes::<1>(handle)?;
+            let value = match record_type {
+                0x00 => {
+                    let [length] = self.read_file_bytes::<1>(handle)?;
+                    let mut bytes = Vec::with_capacity(length as usize);
+                    for _ in 0..length {
+                        bytes.push(self.read_file_bytes::<1>(handle)?[0]);
+                    }
+                    bytes.reverse();
+                    Variable::String(crate::charset::from_bytes(&bytes))
+                }
+                0x40 => Variable::Integer(i32::from_be_bytes(self.read_file_bytes(handle)?)),
+                0xFF => Variable::Real(decode_real(self.read_file_bytes(handle)?)),
+                _ => return Err(BBCBasicError::TypeMismatch),
+            };
 
-        // Only input files can be read from
-        let reader = match file_handle {
-            FileHandle::Input(reader) => reader,
-            FileHandle::Output(_) => return Err(BBCBasicError::BadCall),
-        };
+            // Assign based on variable type
+            let value = match (var_name.chars().last(), value) {
+                (Some('$'), Variable::String(text)) => Variable::String(text),
+                (Some('$'), _) | (_, Variable::String(_)) => {
+                    return Err(BBCBasicError::TypeMismatch)
+                }
+                (Some('%'), Variable::Real(v)) => Variable::Integer(v as i32),
+                (Some('%'), Variable::Integer(v)) => Variable::Integer(v),
+                (_, Variable::Integer(v)) => Variable::Real(v as f64),
+                (_, Variable::Real(v)) => Variable::Real(v),
+                _ => unreachable!("records are numbers or strings"),
+            };
+            self.assign_target(target, value)?;
+        }
 
-        // Read a line from the file
-        let mut line = String::new();
-        reader
-            .read_line(&mut line)
-            .map_err(|e| BBCBasicError::DiskError(format!("Read error: {}", e)))?;
+        Ok(())
+    }
 
-        // Remove trailing newline
-        if line.ends_with('\n') {
-            line.pop();
-            if line.ends_with('\r') {
-                line.pop();
+    /// Execute BPUT# statement - a number writes one byte; a string writes
+    /// its characters followed by a line feed, unless `newline` is false
+    fn execute_bput_file(
+        &mut self,
+        handle_expr: &Expression,
+        value: &Expression,
+        newline: bool,
+    ) -> Result<()> {
+        let handle = self.eval_integer(handle_expr)?;
+        if is_string_expression(value) {
+            let mut bytes = crate::charset::to_bytes(&self.eval_string(value)?);
+            if newline {
+                bytes.push(10);
             }
+            self.write_file_bytes(handle, &bytes)
+        } else {
+            let byte = self.eval_integer(value)?;
+            self.bput(handle, byte)
         }
+    }
 
-        // Parse the line and assign to variables (simplified - just split by commas)
-        let values: Vec<&str> = line.split(',').map(|s| s.trim()).collect();
-
-        for (i, var_name) in variables.iter().enumerate() {
-            let value_str = values.get(i).unwrap_or(&"");
-
-            // Assign based on variable type
-            if var_name.ends_with('%') {
-                // Integer variable
-                let value = value_str.parse::<i32>().unwrap_or(0);
-                self.variables.set_integer_var(var_name.clone(), value);
-            } else if var_name.ends_with('$') {
-                // String variable
-                self.variables.set_string_var(var_name.clone(), value_str.to_string())?;
-            } else {
-                // Real variable
-                let value = value_str.parse::<f64>().unwrap_or(0.0);
-                self.variables.set_real_var(var_name.clone(), value);
+    /// GET$# function - read a string ended by carriage return, line feed,
+    /// NUL or the end of the file
+    ///
+    /// A line feed straight after a carriage return is part of the same line
+    /// end, so files written with CR LF read a line at a time.
+    pub fn get_string(&mut self, handle: i32) -> Result<String> {
+        let mut bytes = Vec::new();
+        loop {
+            match self.bget(handle)? {
+                -1 | 0 | 10 => break,
+                13 => {
+                    let position = self.get_ptr(handle)?;
+                    if !matches!(self.bget(handle)?, -1 | 10) {
+                        self.set_ptr(handle, position)?;
+                    }
+                    break;
+                }
+                byte => bytes.push(byte as u8),
             }
         }
-
-        Ok(())
+        Ok(crate::charset::from_bytes(&bytes))
     }
 
-    /// Execute CLOSE# statement - close file
+    /// Execute CLOSE# statement - close file (CLOSE#0 closes them all)
     fn execute_close_file(&mut self, handle_expr: &Expression) -> Result<()> {
         // Evaluate the handle
         let handle = self.eval_integer(handle_expr)?;
+        if handle == 0 {
+            return self.close_all_files();
+        }
 
-        // Remove the file handle (this closes the file)
-        self.open_files
+        // Remove the file handle and write out anything still buffered
+        let file_handle = self
+            .open_files
             .remove(&handle)
             .ok_or(BBCBasicError::ChannelNotOpen(handle))?;
+        if self.trace {
+            trace::close(handle);
+        }
+        // PRINT output sent to the file goes back where it went before
+        self.output_targets.retain(
+            |&target| !matches!(target, OutputTarget::File { handle: h, .. } if h == handle),
+        );
+        close_file(file_handle)
+    }
 
-        Ok(())
+    /// Close every open file (CLOSE#0), writing out buffered output
+    ///
+    /// All files are closed even if one fails; the first error is returned.
+    pub fn close_all_files(&mut self) -> Result<()> {
+        let mut handles: Vec<i32> = self.open_files.keys().copied().collect();
+        handles.sort_unstable();
+        self.output_targets
+            .retain(|&target| target == OutputTarget::Screen);
+        let mut result = Ok(());
+        for handle in handles {
+            if let Some(file_handle) = self.open_files.remove(&handle) {
+                if self.trace {
+                    trace::close(handle);
+                }
+                result = result.and(close_file(file_handle));
+            }
+        }
+        result
+    }
+
+    /// Number of files currently open
+    pub fn open_file_count(&self) -> usize {
+        self.open_files.len()
     }
 
     /// BGET# function - Read a single byte from file
     /// Returns the byte value (0-255) or -1 at EOF
     pub fn bget(&mut self, handle: i32) -> Result<i32> {
         use std::io::Read;
+        self.io += 1;
 
         // Get the file handle
         let file_handle = self.open_files
             .get_mut(&handle)
             .ok_or(BBCBasicError::ChannelNotOpen(handle))?;
 
-        // BGET# only works on input files
-        match file_handle {
-            FileHandle::Input(reader) => {
-                // Read a single byte
-                let mut buf = [0u8; 1];
-                match reader.read_exact(&mut buf) {
-                    Ok(_) => Ok(buf[0] as i32),
-                    Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
-                        // EOF reached - return -1
-                        Ok(-1)
-                    }
-                    Err(e) => Err(BBCBasicError::DiskError(e.to_string())),
-                }
-            }
+        // BGET# only works on input and update files
+        let reader: &mut dyn Read = match file_handle {
+            FileHandle::Input(reader) => reader,
+            FileHandle::Update(file) => file,
             FileHandle::Output(_) => {
-                Err(BBCBasicError::TypeMismatch) // Cannot read from output file
+                return Err(BBCBasicError::TypeMismatch); // Cannot read from output file
+            }
+        };
+
+        // Read a single byte
+        let mut buf = [0u8; 1];
+        match reader.read_exact(&mut buf) {
+            Ok(_) => Ok(buf[0] as i32),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                // EOF reached - return -1
+                Ok(-1)
             }
+            Err(e) => Err(BBCBasicError::DiskError(e.to_string())),
         }
     }
 
     /// BPUT# statement - Write a single byte to file
     /// Values > 255 are wrapped using MOD 256
     pub fn bput(&mut self, handle: i32, value: i32) -> Result<()> {
-        use std::io::Write;
-
         // Get the file handle
         let file_handle = self.open_files
             .get_mut(&handle)
             .ok_or(BBCBasicError::ChannelNotOpen(handle))?;
 
-        // BPUT# only works on output files
-        match file_handle {
-            FileHandle::Output(writer) => {
-                // Convert value to byte (MOD 256)
-                let byte = (value % 256) as u8;
+        // BPUT# only works on output and update files
+        let writer: &mut dyn Write = match file_handle {
+            FileHandle::Output(writer) => writer,
+            FileHandle::Update(file) => file,
+            FileHandle::Input(_) => {
+                return Err(BBCBasicError::TypeMismatch); // Cannot write to input file
+            }
+        };
 
-                // Write the byte
-                writer.write_all(&[byte])
-                    .map_err(|e| BBCBasicError::DiskError(e.to_string()))?;
+        // Convert value to byte (MOD 256)
+        let byte = (value % 256) as u8;
 
-                // Flush to ensure byte is written
-                writer.flush()
-                    .map_err(|e| BBCBasicError::DiskError(e.to_string()))?;
+        // Write the byte
+        writer
+            .write_all(&[byte])
+            .map_err(|e| BBCBasicError::DiskError(e.to_string()))?;
 
-                Ok(())
-            }
-            FileHandle::Input(_) => {
-                Err(BBCBasicError::TypeMismatch) // Cannot write to input file
-            }
-        }
+        // Flush to ensure byte is written
+        writer
+            .flush()
+            .map_err(|e| BBCBasicError::DiskError(e.to_string()))
     }
 
     /// PTR# function - Get current file position
     pub fn get_ptr(&mut self, handle: i32) -> Result<i32> {
-        use std::io::Seek;
-
-        // Get the file handle
-        let file_handle = self.open_files
+        let file = self
+            .open_files
             .get_mut(&handle)
-            .ok_or(BBCBasicError::ChannelNotOpen(handle))?;
+            .ok_or(BBCBasicError::ChannelNotOpen(handle))?
+            .seekable();
 
         // Get current position from the underlying file
-        match file_handle {
-            FileHandle::Input(reader) => {
-                let pos = reader.stream_position()
-                    .map_err(|e| BBCBasicError::DiskError(e.to_string()))?;
-                Ok(pos as i32)
-            }
-            FileHandle::Output(writer) => {
-                let pos = writer.stream_position()
-                    .map_err(|e| BBCBasicError::DiskError(e.to_string()))?;
-                Ok(pos as i32)
-            }
-        }
+        let pos = file
+            .stream_position()
+            .map_err(|e| BBCBasicError::DiskError(e.to_string()))?;
+        Ok(pos as i32)
     }
 
     /// PTR# assignment - Set file position
+    ///
+    /// The pointer may be moved past the end of the file; writing there
+    /// extends the file, filling the gap with zeros.
     pub fn set_ptr(&mut self, handle: i32, position: i32) -> Result<()> {
-        use std::io::Seek;
-
-        // Get the file handle
-        let file_handle = self.open_files
+        let file = self
+            .open_files
             .get_mut(&handle)
-            .ok_or(BBCBasicError::ChannelNotOpen(handle))?;
+            .ok_or(BBCBasicError::ChannelNotOpen(handle))?
+            .seekable();
 
         // Seek to the specified position
-        let pos = position as u64;
-        match file_handle {
-            FileHandle::Input(reader) => {
-                reader.seek(std::io::SeekFrom::Start(pos))
-                    .map_err(|e| BBCBasicError::DiskError(e.to_string()))?;
-                Ok(())
-            }
-            FileHandle::Output(writer) => {
-                writer.seek(std::io::SeekFrom::Start(pos))
-                    .map_err(|e| BBCBasicError::DiskError(e.to_string()))?;
-                Ok(())
-            }
-        }
+        file.seek(std::io::SeekFrom::Start(position as u64))
+            .map_err(|e| BBCBasicError::DiskError(e.to_string()))?;
+        Ok(())
     }
 
     /// EXT# function - Get file size
     pub fn get_ext(&mut self, handle: i32) -> Result<i32> {
-        use std::io::Seek;
-
-        // Get the file handle
-        let file_handle = self.open_files
+        let file = self
+            .open_files
             .get_mut(&handle)
-            .ok_or(BBCBasicError::ChannelNotOpen(handle))?;
+            .ok_or(BBCBasicError::ChannelNotOpen(handle))?
+            .seekable();
 
-        // Get file size by seeking to end
-        match file_handle {
-            FileHandle::Input(reader) => {
-                // Save current position
-                let current_pos = reader.stream_position()
-                    .map_err(|e| BBCBasicError::DiskError(e.to_string()))?;
+        // Save current position
+        let current_pos = file
+            .stream_position()
+            .map_err(|e| BBCBasicError::DiskError(e.to_string()))?;
 
-                // Seek to end to get size
-                let size = reader.seek(std::io::SeekFrom::End(0))
-                    .map_err(|e| BBCBasicError::DiskError(e.to_string()))?;
+        // Seek to end to get size
+        let size = file
+            .seek(std::io::SeekFrom::End(0))
+            .map_err(|e| BBCBasicError::DiskError(e.to_string()))?;
 
-                // Restore original position
-                reader.seek(std::io::SeekFrom::Start(current_pos))
-                    .map_err(|e| BBCBasicError::DiskError(e.to_string()))?;
+        // Restore original position
+        file.seek(std::io::SeekFrom::Start(current_pos))
+            .map_err(|e| BBCBasicError::DiskError(e.to_string()))?;
 
-                Ok(size as i32)
-            }
-            FileHandle::Output(writer) => {
-                // Save current position
-                let current_pos = writer.stream_position()
-                    .map_err(|e| BBCBasicError::DiskError(e.to_string()))?;
+        Ok(size as i32)
+    }
 
-                // Seek to end to get size
-                let size = writer.seek(std::io::SeekFrom::End(0))
-                    .map_err(|e| BBCBasicError::DiskError(e.to_string()))?;
+    /// EXT# assignment - Truncate or extend an output or update file
+    ///
+    /// Extending pads the file with zeros. A pointer left beyond the new end
+    /// moves back to it.
+    pub fn set_ext(&mut self, handle: i32, length: i32) -> Result<()> {
+        let length = u64::try_from(length).map_err(|_| BBCBasicError::BadCall)?;
 
-                // Restore original position
-                writer.seek(std::io::SeekFrom::Start(current_pos))
-                    .map_err(|e| BBCBasicError::DiskError(e.to_string()))?;
+        let file_handle = self
+            .open_files
+            .get_mut(&handle)
+            .ok_or(BBCBasicError::ChannelNotOpen(handle))?;
 
-                Ok(size as i32)
+        // Write out anything buffered before changing the length underneath it
+        let file = match file_handle {
+            FileHandle::Output(writer) => {
+                writer
+                    .flush()
+                    .map_err(|e| BBCBasicError::DiskError(e.to_string()))?;
+                writer.get_mut()
             }
+            FileHandle::Update(file) => file,
+            FileHandle::Input(_) => return Err(BBCBasicError::BadCall),
+        };
+
+        file.set_len(length)
+            .map_err(|e| BBCBasicError::DiskError(e.to_string()))?;
+        let pos = file
+            .stream_position()
+            .map_err(|e| BBCBasicError::DiskError(e.to_string()))?;
+        if pos > length {
+            file.seek(std::io::SeekFrom::Start(length))
+                .map_err(|e| BBCBasicError::DiskError(e.to_string()))?;
         }
+        Ok(())
     }
 }
 
@@ -2544,10 +4743,367 @@ impl Default for Executor {
     }
 }
 
+/// Compare two strings (TRUE is -1), character code by character code, or
+/// None if the operator is not a comparison
+fn string_comparison(op: &BinaryOperator, left: &str, right: &str) -> Option<i32> {
+    let result = match op {
+        BinaryOperator::Equal => left == right,
+        BinaryOperator::NotEqual => left != right,
+        BinaryOperator::LessThan => left < right,
+        BinaryOperator::LessThanOrEqual => left <= right,
+        BinaryOperator::GreaterThan => left > right,
+        BinaryOperator::GreaterThanOrEqual => left >= right,
+        _ => return None,
+    };
+    Some(if result { -1 } else { 0 })
+}
+
+/// A real truncated to an integer, or "Too big" outside the 32 bit range
+pub(crate) fn real_to_integer(value: f64) -> Result<i32> {
+    let value = value.trunc();
+    if (-2_147_483_648.0..2_147_483_648.0).contains(&value) {
+        Ok(value as i32)
+    } else {
+        Err(BBCBasicError::TooBig)
+    }
+}
+
+/// Apply a binary operator to integers (BBC BASIC integer arithmetic)
+///
+/// Integers are 32 bit two's complement: addition and subtraction wrap
+/// around as on the 6502, while products and powers too big for 32 bits
+/// are "Too big" (and so are worked out as reals where a real will do).
+pub(crate) fn integer_binary_op(op: &BinaryOperator, left_val: i32, right_val: i32) -> Result<i32> {
+    match op {
+        BinaryOperator::Add => Ok(left_val.wrapping_add(right_val)),
+        BinaryOperator::Subtract => Ok(left_val.wrapping_sub(right_val)),
+        BinaryOperator::Multiply => left_val.checked_mul(right_val).ok_or(BBCBasicError::TooBig),
+        BinaryOperator::Divide => {
+            if right_val == 0 {
+                Err(BBCBasicError::DivisionByZero)
+            } else {
+                Ok(left_val.wrapping_div(right_val))
+            }
+        }
+        BinaryOperator::IntegerDivide => {
+            if right_val == 0 {
+                Err(BBCBasicError::DivisionByZero)
+            } else {
+                Ok(left_val.wrapping_div(right_val))
+            }
+        }
+        BinaryOperator::Modulo => {
+            if right_val == 0 {
+                Err(BBCBasicError::DivisionByZero)
+            } else {
+                Ok(left_val.wrapping_rem(right_val))
+            }
+        }
+        BinaryOperator::Power => u32::try_from(right_val)
+            .ok()
+            .and_then(|power| left_val.checked_pow(power))
+            .ok_or(BBCBasicError::TooBig),
+        // Comparison operators: return -1 for true, 0 for false (BBC BASIC convention)
+        BinaryOperator::Equal => Ok(if left_val == right_val { -1 } else { 0 }),
+        BinaryOperator::NotEqual => Ok(if left_val != right_val { -1 } else { 0 }),
+        BinaryOperator::LessThan => Ok(if left_val < right_val { -1 } else { 0 }),
+        BinaryOperator::LessThanOrEqual => Ok(if left_val <= right_val { -1 } else { 0 }),
+        BinaryOperator::GreaterThan => Ok(if left_val > right_val { -1 } else { 0 }),
+        BinaryOperator::GreaterThanOrEqual => Ok(if left_val >= right_val { -1 } else { 0 }),
+        // Logical operators
+        BinaryOperator::And => Ok(left_val & right_val),
+        BinaryOperator::Or => Ok(left_val | right_val),
+        BinaryOperator::Eor => Ok(left_val ^ right_val),
+        // Bitwise shift operators
+        BinaryOperator::LeftShift => {
+            if right_val < 0 {
+                return Err(BBCBasicError::IllegalFunction);
+            }
+            Ok(left_val.checked_shl(right_val as u32).unwrap_or(0))
+        }
+        BinaryOperator::RightShift => {
+            if right_val < 0 {
+                return Err(BBCBasicError::IllegalFunction);
+            }
+            Ok(left_val >> right_val.min(31))
+        }
+        _ => Err(BBCBasicError::IllegalFunction),
+    }
+}
+
+/// Apply a unary operator to an integer
+pub(crate) fn integer_unary_op(op: &UnaryOperator, val: i32) -> i32 {
+    match op {
+        UnaryOperator::Minus => val.wrapping_neg(),
+        UnaryOperator::Plus => val,
+        // NOT is bitwise, so NOT TRUE is FALSE and NOT 5 is -6
+        UnaryOperator::Not => !val,
+    }
+}
+
+/// Apply a binary operator to reals (only arithmetic is defined)
+pub(crate) fn real_binary_op(op: &BinaryOperator, left_val: f64, right_val: f64) -> Result<f64> {
+    match op {
+        BinaryOperator::Add => Ok(left_val + right_val),
+        BinaryOperator::Subtract => Ok(left_val - right_val),
+        BinaryOperator::Multiply => Ok(left_val * right_val),
+        BinaryOperator::Divide => {
+            if right_val == 0.0 {
+                Err(BBCBasicError::DivisionByZero)
+            } else {
+                Ok(left_val / right_val)
+            }
+        }
+        BinaryOperator::Power => Ok(left_val.powf(right_val)),
+        _ => Err(BBCBasicError::IllegalFunction),
+    }
+}
+
+/// Apply a unary operator to a real
+pub(crate) fn real_unary_op(op: &UnaryOperator, val: f64) -> f64 {
+    match op {
+        UnaryOperator::Minus => -val,
+        UnaryOperator::Plus => val,
+        UnaryOperator::Not => !(val as i32) as f64,
+    }
+}
+
+/// Number of parameter bytes that follow a VDU code
+fn vdu_parameter_count(code: u8) -> usize {
+    match code {
+        1 | 17 | 22 => 1,
+        18 | 31 => 2,
+        19 | 25 => 5,
+        28 | 29 => 4,
+        23 => 9,
+        24 => 8,
+        _ => 0,
+    }
+}
+
+/// The name of a variable or array element set by READ or INPUT
+fn target_name(target: &Expression) -> &str {
+    match target {
+        Expression::Variable(name) | Expression::ArrayAccess { name, .. } => name,
+        _ => "",
+    }
+}
+
+/// The values typed in answer to INPUT, for as many of `variables` as the
+/// lines answer
+///
+/// Each line holds answers separated by commas (see [`split_items`]), taken
+/// in turn; a line with too few leaves the rest to the next line, and any
+/// extra are ignored. An answer for a numeric variable that is not a number
+/// throws away the rest of its line, so the variable is asked for again. An
+/// empty answer is 0.
+fn input_answers(variables: &[Expression], lines: &[String]) -> Vec<Variable> {
+    let mut answers = Vec::new();
+    for line in lines {
+        for item in split_items(line) {
+            let Some(var) = variables.get(answers.len()).map(target_name) else {
+                return answers;
+            };
+            let number = match item.trim() {
+                "" => Some(0.0),
+                item => item.parse::<f64>().ok().filter(|n| n.is_finite()),
+            };
+            let answer = if var.ends_with('$') {
+                Variable::String(item)
+            } else if var.ends_with('%') {
+                match number {
+                    Some(n) if (-2147483648.0..2147483648.0).contains(&n) => {
+                        Variable::Integer(n as i32)
+                    }
+                    _ => break,
+                }
+            } else {
+                match number {
+                    Some(n) => Variable::Real(n),
+                    None => break,
+                }
+            };
+            answers.push(answer);
+        }
+    }
+    answers
+}
+
+/// The number at the start of a DATA item, or 0 if there isn't one
+///
+/// Leading spaces are skipped, and anything after the number is ignored.
+fn number_prefix(text: &str) -> f64 {
+    let text = text.trim_start_matches(' ');
+    let digits = |from: usize| {
+        from + text[from..]
+            .bytes()
+            .take_while(|b| b.is_ascii_digit())
+            .count()
+    };
+    let mut end = digits(usize::from(text.starts_with(['+', '-'])));
+    if text[end..].starts_with('.') {
+        end = digits(end + 1);
+    }
+    if text[end..].starts_with(['E', 'e']) {
+        let sign = usize::from(text[end + 1..].starts_with(['+', '-']));
+        let exponent = digits(end + 1 + sign);
+        if exponent > end + 1 + sign {
+            end = exponent;
+        }
+    }
+    text[..end].parse().unwrap_or(0.0)
+}
+
+/// Whether an expression gives a string
+fn is_string_expression(expr: &Expression) -> bool {
+    use crate::parser::ExpressionType;
+    match expr {
+        Expression::FunctionCall { name, .. } => name.ends_with('$'),
+        _ => expr.expression_type() == ExpressionType::String,
+    }
+}
+
+/// Whether an expression gives an integer, so PRINT# writes an integer
+/// record rather than a real one
+fn is_integer_expression(expr: &Expression) -> bool {
+    use crate::parser::ExpressionType;
+    match expr.expression_type() {
+        ExpressionType::Integer => true,
+        ExpressionType::Numeric => match expr {
+            Expression::BinaryOp {
+                left,
+                op: BinaryOperator::Add | BinaryOperator::Subtract | BinaryOperator::Multiply,
+                right,
+            } => is_integer_expression(left) && is_integer_expression(right),
+            Expression::UnaryOp { operand, .. } => is_integer_expression(operand),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Encode a real in BBC BASIC's five byte format as PRINT# writes it: the
+/// four mantissa bytes least significant first, then the exponent
+///
+/// The exponent is excess-128 and the mantissa is normalised to 0.5-1, so
+/// its top bit holds the sign instead.
+fn encode_real(value: f64) -> Result<[u8; 5]> {
+    let bits = value.to_bits();
+    let exponent = ((bits >> 52) & 0x7FF) as i32;
+    // Zero, and values too small for the format
+    if value == 0.0 || exponent < 1022 - 127 {
+        return Ok([0; 5]);
+    }
+
+    // Round the 53 bit significand to 32 bits
+    let significand = (bits & ((1 << 52) - 1)) | (1 << 52);
+    let mut mantissa = (significand + (1 << 20)) >> 21;
+    let mut exponent = exponent - 1022 + 128;
+    if mantissa >> 32 != 0 {
+        mantissa >>= 1;
+        exponent += 1;
+    }
+    if exponent > 255 {
+        return Err(BBCBasicError::TooBig);
+    }
+
+    let mut mantissa = mantissa as u32 & 0x7FFF_FFFF;
+    if value < 0.0 {
+        mantissa |= 0x8000_0000;
+    }
+    let [m1, m2, m3, m4] = mantissa.to_be_bytes();
+    Ok([m4, m3, m2, m1, exponent as u8])
+}
+
+/// A number as PRINT shows it: right-justified in a field `width` wide if
+/// `justify` is set, or as it is
+fn justified(text: String, width: usize, justify: bool) -> String {
+    if justify {
+        format!("{:>width$}", text, width = width)
+    } else {
+        text
+    }
+}
+
+/// A real formatted as @% says: its second byte chooses general (0),
+/// exponent (1) or fixed (2) format, and its first how many figures, or for
+/// fixed format how many decimal places
+///
+/// The default, general format to nine figures, is how the Model B prints.
+fn formatted_real_text(value: f64, format: i32) -> String {
+    let [_, figures, style, _] = format.to_le_bytes();
+    let figures = usize::from(figures).min(10);
+    match style {
+        1 => {
+            let scientific = format!("{:.*e}", figures.max(1) - 1, value);
+            scientific.replace('e', "E")
+        }
+        2 if value.abs() < 1E10 => format!("{:.*}", figures, value),
+        _ => general_real_text(value, if figures == 0 { 10 } else { figures }),
+    }
+}
+
+/// A real to a number of significant figures, in E notation below 0.01 and
+/// from 10 to the power of the figures
+fn general_real_text(value: f64, figures: usize) -> String {
+    if value == 0.0 {
+        return "0".to_string();
+    }
+    let scientific = format!("{:.*e}", figures - 1, value);
+    let (digits, exponent) = scientific.split_once('e').expect("scientific notation");
+    let exponent: i32 = exponent.parse().expect("exponent");
+    let negative = digits.starts_with('-');
+    let digits: String = digits.chars().filter(char::is_ascii_digit).collect();
+    let digits = digits.trim_end_matches('0');
+    let sign = if negative { "-" } else { "" };
+    if (-2..figures as i32).contains(&exponent) {
+        let text = if exponent < 0 {
+            format!("0.{}{}", "0".repeat((-exponent - 1) as usize), digits)
+        } else if digits.len() > exponent as usize + 1 {
+            let (whole, fraction) = digits.split_at(exponent as usize + 1);
+            format!("{}.{}", whole, fraction)
+        } else {
+            format!("{:0<width$}", digits, width = exponent as usize + 1)
+        };
+        format!("{}{}", sign, text)
+    } else {
+        let (first, rest) = digits.split_at(1);
+        let point = if rest.is_empty() { "" } else { "." };
+        format!("{}{}{}{}E{}", sign, first, point, rest, exponent)
+    }
+}
+
+/// Decode a real written by PRINT# (see `encode_real`)
+fn decode_real(bytes: [u8; 5]) -> f64 {
+    let [m4, m3, m2, m1, exponent] = bytes;
+    if exponent == 0 {
+        return 0.0;
+    }
+    let mantissa = u32::from_be_bytes([m1, m2, m3, m4]);
+    let magnitude = (mantissa | 0x8000_0000) as f64 / 4_294_967_296.0;
+    let value = magnitude * 2f64.powi(exponent as i32 - 128);
+    if mantissa & 0x8000_0000 != 0 {
+        -value
+    } else {
+        value
+    }
+}
+
 #[cfg(test)]
+#[allow(unused_mut, clippy::approx_constant, clippy::manual_range_contains)]
+#[allow(clippy::needless_borrows_for_generic_args)]
 mod tests {
     use super::*;
-    use crate::parser::BinaryOperator;
+    use crate::parser::{BinaryOperator, PrintItem};
+    use std::sync::{Arc, Mutex};
+
+    /// Variables for READ and INPUT to set
+    fn names(names: &[&str]) -> Vec<Expression> {
+        names
+            .iter()
+            .map(|name| Expression::Variable(name.to_string()))
+            .collect()
+    }
 
     #[test]
     fn test_executor_creation() {
@@ -2626,7 +5182,7 @@ mod tests {
         };
 
         executor.execute_statement(&stmt).unwrap();
-        assert_eq!(executor.get_output(), "42\n");
+        assert_eq!(executor.get_output(), "        42\n");
     }
 
     #[test]
@@ -2667,7 +5223,7 @@ mod tests {
         };
         executor.execute_statement(&print).unwrap();
 
-        assert_eq!(executor.get_output(), "100\n");
+        assert_eq!(executor.get_output(), "       100\n");
     }
 
     #[test]
@@ -2772,7 +5328,7 @@ mod tests {
         // RED: Test FOR I% = 1 TO 10
         let mut executor = Executor::new();
         let stmt = Statement::For {
-            variable: "I%".to_string(),
+            variable: Expression::Variable("I%".to_string()),
             start: Expression::Integer(1),
             end: Expression::Integer(10),
             step: None,
@@ -2785,9 +5341,12 @@ mod tests {
 
         // Loop should be on the stack
         assert_eq!(executor.for_loops.len(), 1);
-        assert_eq!(executor.for_loops[0].0, "I%");
-        assert_eq!(executor.for_loops[0].1, 10); // end value
-        assert_eq!(executor.for_loops[0].2, 1); // step value
+        assert_eq!(
+            executor.for_loops[0].variable,
+            Target::Variable("I%".to_string())
+        );
+        assert_eq!(executor.for_loops[0].limit, 10.0);
+        assert_eq!(executor.for_loops[0].step, 1.0);
     }
 
     #[test]
@@ -2795,7 +5354,7 @@ mod tests {
         // RED: Test FOR I% = 10 TO 1 STEP -1
         let mut executor = Executor::new();
         let stmt = Statement::For {
-            variable: "I%".to_string(),
+            variable: Expression::Variable("I%".to_string()),
             start: Expression::Integer(10),
             end: Expression::Integer(1),
             step: Some(Expression::Integer(-1)),
@@ -2808,7 +5367,7 @@ mod tests {
 
         // Loop should be on the stack with correct step
         assert_eq!(executor.for_loops.len(), 1);
-        assert_eq!(executor.for_loops[0].2, -1); // step value
+        assert_eq!(executor.for_loops[0].step, -1.0);
     }
 
     #[test]
@@ -2818,7 +5377,7 @@ mod tests {
 
         // FOR I% = 1 TO 3
         let for_stmt = Statement::For {
-            variable: "I%".to_string(),
+            variable: Expression::Variable("I%".to_string()),
             start: Expression::Integer(1),
             end: Expression::Integer(3),
             step: None,
@@ -2828,7 +5387,7 @@ mod tests {
 
         // NEXT I%
         let next_stmt = Statement::Next {
-            variables: vec!["I%".to_string()],
+            variables: names(&["I%"]),
         };
 
         // First NEXT: I% should become 2
@@ -2852,7 +5411,7 @@ mod tests {
         // RED: Test NEXT without FOR should error
         let mut executor = Executor::new();
         let stmt = Statement::Next {
-            variables: vec!["I%".to_string()],
+            variables: names(&["I%"]),
         };
 
         let result = executor.execute_statement(&stmt);
@@ -2866,7 +5425,7 @@ mod tests {
         let mut executor = Executor::new();
 
         let for_stmt = Statement::For {
-            variable: "I%".to_string(),
+            variable: Expression::Variable("I%".to_string()),
             start: Expression::Integer(5),
             end: Expression::Integer(1),
             step: Some(Expression::Integer(-1)),
@@ -2875,7 +5434,7 @@ mod tests {
         assert_eq!(executor.get_variable_int("I%").unwrap(), 5);
 
         let next_stmt = Statement::Next {
-            variables: vec!["I%".to_string()],
+            variables: names(&["I%"]),
         };
 
         // Countdown: 5, 4, 3, 2, 1
@@ -2902,10 +5461,18 @@ mod tests {
         // RED: Test INPUT A%, B$, C
         let mut executor = Executor::new();
         let stmt = Statement::Input {
-            variables: vec!["A%".to_string(), "B$".to_string(), "C".to_string()],
+            variables: names(&["A%", "B$", "C"]),
         };
 
-        // In test mode, INPUT initializes variables to default values
+        // Nothing more will ever be typed
+        assert!(matches!(
+            executor.execute_statement(&stmt),
+            Err(BBCBasicError::Eof)
+        ));
+
+        // Empty answers
+        executor.os_mut().keyboard_mut().insert_str(",,");
+        executor.os_mut().keyboard_mut().insert(13);
         executor.execute_statement(&stmt).unwrap();
 
         // Variables should be initialized
@@ -2914,6 +5481,50 @@ mod tests {
         assert_eq!(executor.get_variable_real("C").unwrap(), 0.0);
     }
 
+    #[test]
+    fn test_number_prefix() {
+        assert_eq!(number_prefix(" 42"), 42.0);
+        assert_eq!(number_prefix("-1.5E2X"), -150.0);
+        assert_eq!(number_prefix("+.25"), 0.25);
+        assert_eq!(number_prefix("3E"), 3.0);
+        assert_eq!(number_prefix("12 apples"), 12.0);
+        assert_eq!(number_prefix("Fred"), 0.0);
+        assert_eq!(number_prefix(""), 0.0);
+    }
+
+    #[test]
+    fn test_input_answers() {
+        let variables = names(&["A%", "B$", "C"]);
+        let lines =
+            |lines: &[&str]| -> Vec<String> { lines.iter().map(|line| line.to_string()).collect() };
+
+        // Too few answers leave the rest to the next line
+        assert_eq!(
+            input_answers(&variables, &lines(&["3.7, Hello"])),
+            vec![Variable::Integer(3), Variable::String("Hello".to_string())]
+        );
+        assert_eq!(
+            input_answers(&variables, &lines(&["3.7, Hello", "-1E2, 9"])),
+            vec![
+                Variable::Integer(3),
+                Variable::String("Hello".to_string()),
+                Variable::Real(-100.0)
+            ]
+        );
+
+        // A number that is not a number is asked for again
+        assert!(input_answers(&variables, &lines(&["x, Hello"])).is_empty());
+        assert_eq!(
+            input_answers(&variables, &lines(&["x, Hello", "", "", ""])),
+            vec![
+                Variable::Integer(0),
+                Variable::String(String::new()),
+                Variable::Real(0.0)
+            ]
+        );
+        assert!(input_answers(&variables, &lines(&["9999999999"])).is_empty());
+    }
+
     #[test]
     fn test_dim_integer_array() {
         // RED: Test DIM A%(10)
@@ -3000,7 +5611,7 @@ mod tests {
                 right: Box::new(Expression::Integer(5)),
             },
             then_part: vec![Statement::Assignment {
-                target: "Y%".to_string(),
+                target: "YY%".to_string(),
                 expression: Expression::Integer(10),
             }],
             else_part: None,
@@ -3008,8 +5619,8 @@ mod tests {
 
         executor.execute_statement(&stmt).unwrap();
 
-        // Y% should not exist because condition is false
-        assert!(executor.get_variable_int("Y%").is_err());
+        // YY% should not exist because condition is false
+        assert!(executor.get_variable_int("YY%").is_err());
     }
 
     #[test]
@@ -3656,7 +6267,7 @@ mod tests {
 
         // READ A%, B%, C%
         let read_stmt = Statement::Read {
-            variables: vec!["A%".to_string(), "B%".to_string(), "C%".to_string()],
+            variables: names(&["A%", "B%", "C%"]),
         };
         executor.execute_statement(&read_stmt).unwrap();
 
@@ -3682,7 +6293,7 @@ mod tests {
 
         // READ A$, B$, C$
         let read_stmt = Statement::Read {
-            variables: vec!["A$".to_string(), "B$".to_string(), "C$".to_string()],
+            variables: names(&["A$", "B$", "C$"]),
         };
         executor.execute_statement(&read_stmt).unwrap();
 
@@ -3708,7 +6319,7 @@ mod tests {
 
         // READ A%, B, C$
         let read_stmt = Statement::Read {
-            variables: vec!["A%".to_string(), "B".to_string(), "C$".to_string()],
+            variables: names(&["A%", "B", "C$"]),
         };
         executor.execute_statement(&read_stmt).unwrap();
 
@@ -3734,7 +6345,7 @@ mod tests {
 
         // READ A%, B%
         let read_stmt1 = Statement::Read {
-            variables: vec!["A%".to_string(), "B%".to_string()],
+            variables: names(&["A%", "B%"]),
         };
         executor.execute_statement(&read_stmt1).unwrap();
 
@@ -3747,7 +6358,7 @@ mod tests {
 
         // READ C%, D%
         let read_stmt2 = Statement::Read {
-            variables: vec!["C%".to_string(), "D%".to_string()],
+            variables: names(&["C%", "D%"]),
         };
         executor.execute_statement(&read_stmt2).unwrap();
 
@@ -3784,7 +6395,7 @@ mod tests {
 
         // READ A%, B% (should get 100, 200)
         let read_stmt1 = Statement::Read {
-            variables: vec!["A%".to_string(), "B%".to_string()],
+            variables: names(&["A%", "B%"]),
         };
         executor.execute_statement(&read_stmt1).unwrap();
 
@@ -3799,7 +6410,7 @@ mod tests {
 
         // READ C%, D% (should get 300, 400 from line 20)
         let read_stmt2 = Statement::Read {
-            variables: vec!["C%".to_string(), "D%".to_string()],
+            variables: names(&["C%", "D%"]),
         };
         executor.execute_statement(&read_stmt2).unwrap();
 
@@ -3826,12 +6437,7 @@ mod tests {
 
         // READ A%, B%, C%, D%
         let read_stmt = Statement::Read {
-            variables: vec![
-                "A%".to_string(),
-                "B%".to_string(),
-                "C%".to_string(),
-                "D%".to_string(),
-            ],
+            variables: names(&["A%", "B%", "C%", "D%"]),
         };
         executor.execute_statement(&read_stmt).unwrap();
 
@@ -3854,7 +6460,7 @@ mod tests {
 
         // READ A%, B% - should fail on B%
         let read_stmt = Statement::Read {
-            variables: vec!["A%".to_string(), "B%".to_string()],
+            variables: names(&["A%", "B%"]),
         };
         let result = executor.execute_statement(&read_stmt);
 
@@ -3889,11 +6495,11 @@ mod tests {
         // For now, this test will fail because we need a new method to pre-collect DATA
 
         // Simulate what should happen: all DATA is collected first
-        executor.collect_data(&data_stmt).unwrap();
+        executor.collect_data(&data_stmt, 20).unwrap();
 
         // Now READ should work even though we never "executed" line 20
         let read_stmt = Statement::Read {
-            variables: vec!["A%".to_string(), "B%".to_string(), "C%".to_string()],
+            variables: names(&["A%", "B%", "C%"]),
         };
         executor.execute_statement(&read_stmt).unwrap();
 
@@ -4084,7 +6690,7 @@ mod tests {
         executor.execute_statement(&init_stmt).unwrap();
 
         // REPEAT at line 20
-        executor.push_repeat(20);
+        executor.push_repeat(Position::from(20));
 
         // Loop several times
         for expected in 1..=5 {
@@ -4110,7 +6716,11 @@ mod tests {
 
             if expected < 5 {
                 // Should loop back
-                assert_eq!(result, Some(20), "Should loop back to REPEAT at line 20");
+                assert_eq!(
+                    result,
+                    Some(Position::from(20)),
+                    "Should loop back to REPEAT at line 20"
+                );
             } else {
                 // Should exit loop
                 assert_eq!(result, None, "Should exit loop when X% = 5");
@@ -4122,17 +6732,29 @@ mod tests {
 
     #[test]
     fn test_cls() {
-        // RED: Test CLS outputs ANSI clear screen escape sequence
+        // CLS clears the screen and tells the output listeners
         let mut executor = Executor::new();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&events);
+        executor.subscribe(Box::new(move |event: &OutputEvent| {
+            seen.lock().unwrap().push(event.clone())
+        }));
+        let print = Statement::Print {
+            items: vec![PrintItem::Expression(Expression::String("HI".to_string()))],
+        };
+        executor.execute_statement(&print).unwrap();
 
         let cls_stmt = Statement::Cls;
         executor.execute_statement(&cls_stmt).unwrap();
 
-        // CLS should output the ANSI escape sequence: ESC[2J ESC[H
-        // ESC[2J clears screen, ESC[H moves cursor to home
-        assert!(
-            executor.output.contains("\x1b[2J\x1b[H"),
-            "CLS should output ANSI clear screen sequence"
+        assert_eq!(executor.screen().cursor(), (0, 0));
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                OutputEvent::Print("HI".to_string()),
+                OutputEvent::Print("\n".to_string()),
+                OutputEvent::Cls
+            ]
         );
     }
 
@@ -4149,7 +6771,7 @@ mod tests {
         // 110 RETURN      (should return to line AFTER 20, which is 30)
 
         // Push return address for line 20
-        executor.push_gosub_return(20);
+        executor.push_gosub_return(Position::from(20));
 
         // Verify return address was saved
         assert_eq!(executor.return_stack.len(), 1);
@@ -4159,7 +6781,8 @@ mod tests {
 
         // Should return to line 20 (caller will advance to next line)
         assert_eq!(
-            return_line, 20,
+            return_line,
+            Position::from(20),
             "RETURN should pop the line number that called GOSUB"
         );
 
@@ -4179,14 +6802,14 @@ mod tests {
         // 110 RETURN
         // 200 RETURN
 
-        executor.push_gosub_return(10);
-        executor.push_gosub_return(100);
+        executor.push_gosub_return(Position::from(10));
+        executor.push_gosub_return(Position::from(100));
 
         // First RETURN should go back to 100
-        assert_eq!(executor.pop_gosub_return().unwrap(), 100);
+        assert_eq!(executor.pop_gosub_return().unwrap(), Position::from(100));
 
         // Second RETURN should go back to 10
-        assert_eq!(executor.pop_gosub_return().unwrap(), 10);
+        assert_eq!(executor.pop_gosub_return().unwrap(), Position::from(10));
     }
 
     #[test]
@@ -4304,11 +6927,11 @@ mod tests {
         let def_fn_stmt = Statement::DefFn {
             name: "add".to_string(),
             params: vec!["X".to_string(), "Y".to_string()],
-            expression: Expression::BinaryOp {
+            expression: Some(Expression::BinaryOp {
                 left: Box::new(Expression::Variable("X".to_string())),
                 op: BinaryOperator::Add,
                 right: Box::new(Expression::Variable("Y".to_string())),
-            },
+            }),
         };
         executor.execute_statement(&def_fn_stmt).unwrap();
 
@@ -4339,11 +6962,11 @@ mod tests {
         let def_fn_stmt = Statement::DefFn {
             name: "double".to_string(),
             params: vec!["X".to_string()],
-            expression: Expression::BinaryOp {
+            expression: Some(Expression::BinaryOp {
                 left: Box::new(Expression::Variable("X".to_string())),
                 op: BinaryOperator::Multiply,
                 right: Box::new(Expression::Integer(2)),
-            },
+            }),
         };
         executor.execute_statement(&def_fn_stmt).unwrap();
 
@@ -4490,17 +7113,18 @@ mod tests {
 
         // Set error info
         executor.set_last_error(18, 150, "Division by zero".to_string());
-
+        
         // Call ERR function
         let fn_call = Expression::FunctionCall {
             name: "ERR".to_string(),
             args: vec![],
         };
-
+        
         let result = executor.eval_integer(&fn_call).unwrap();
         assert_eq!(result, 18);
     }
-
+        
+    #[cfg(feature = "filesystem-host")]
     #[test]
     fn test_openout_creates_file() {
         // RED: Test OPENOUT function creates file and returns handle
@@ -4511,11 +7135,11 @@ mod tests {
         let _ = fs::remove_file(test_file);
         
         let mut executor = Executor::new();
-        
+
         let result = executor.open_file_for_writing(test_file);
         assert!(result.is_ok());
         let handle = result.unwrap();
-        assert_eq!(handle, 1); // First handle should be 1
+        assert_eq!(handle, 0x11); // First handle should be &11
         
         // File should exist
         assert!(fs::metadata(test_file).is_ok());
@@ -4524,7 +7148,8 @@ mod tests {
         drop(executor);
         let _ = fs::remove_file(test_file);
     }
-
+        
+    #[cfg(feature = "filesystem-host")]
     #[test]
     fn test_openin_opens_existing_file() {
         // RED: Test OPENIN function opens existing file
@@ -4533,19 +7158,20 @@ mod tests {
         
         // Create a test file
         fs::write(test_file, "test content").unwrap();
-        
+
         let mut executor = Executor::new();
         
         let result = executor.open_file_for_reading(test_file);
         assert!(result.is_ok());
         let handle = result.unwrap();
-        assert_eq!(handle, 1);
-        
+        assert_eq!(handle, 0x11);
+
         // Clean up
         drop(executor);
         let _ = fs::remove_file(test_file);
     }
-
+        
+    #[cfg(feature = "filesystem-host")]
     #[test]
     fn test_openin_fails_on_missing_file() {
         // RED: Test OPENIN returns FileNotFound error
@@ -4555,7 +7181,8 @@ mod tests {
         assert!(result.is_err());
         assert!(matches!(result.unwrap_err(), BBCBasicError::FileNotFound(_)));
     }
-
+        
+    #[cfg(feature = "filesystem-host")]
     #[test]
     fn test_close_file() {
         // RED: Test CLOSE# closes a file
@@ -4564,7 +7191,7 @@ mod tests {
         
         // Create a test file
         fs::write(test_file, "test").unwrap();
-        
+
         let mut executor = Executor::new();
         let handle = executor.open_file_for_reading(test_file).unwrap();
         
@@ -4581,7 +7208,8 @@ mod tests {
         // Clean up
         let _ = fs::remove_file(test_file);
     }
-
+        
+    #[cfg(feature = "filesystem-host")]
     #[test]
     fn test_print_file_writes_data() {
         // RED: Test PRINT# writes to file
@@ -4606,52 +7234,73 @@ mod tests {
         
         // Close the file
         executor.execute_close_file(&handle_expr).unwrap();
-        
-        // Read back the content
-        let content = fs::read_to_string(test_file).unwrap();
-        assert_eq!(content, "Hello\tWorld\n");
+
+        // Read back the content: typed string records, reversed
+        let content = fs::read(test_file).unwrap();
+        assert_eq!(content, b"\x00\x05olleH\x00\x05dlroW");
         
         // Clean up
         let _ = fs::remove_file(test_file);
     }
-
+        
+    #[cfg(feature = "filesystem-host")]
     #[test]
     fn test_input_file_reads_data() {
         // RED: Test INPUT# reads from file
         use std::fs;
         let test_file = "test_input.txt";
         
-        // Create test file with data
-        fs::write(test_file, "42,Hello,3.14").unwrap();
+        // Create test file with an integer, a string and a real record
+        let mut data = vec![0x40, 0, 0, 0, 42, 0x00, 5];
+        data.extend(b"olleH");
+        data.extend([0xFF, 0, 0, 0, 0x50, 0x82]);
+        fs::write(test_file, data).unwrap();
         
         let mut executor = Executor::new();
         let handle = executor.open_file_for_reading(test_file).unwrap();
         
         // Read data into variables
         let handle_expr = Expression::Integer(handle);
-        let variables = vec!["A%".to_string(), "B$".to_string(), "C".to_string()];
-        
+        let variables = names(&["A%", "B$", "C"]);
+
         let result = executor.execute_input_file(&handle_expr, &variables);
         assert!(result.is_ok());
-        
+
         // Check the variables were set
         assert_eq!(executor.variables.get_integer_var("A%").unwrap(), 42);
         assert_eq!(executor.variables.get_string_var("B$").unwrap(), "Hello");
-        assert!((executor.variables.get_real_var("C").unwrap() - 3.14).abs() < 0.001);
-        
+        assert!((executor.variables.get_real_var("C").unwrap() - 3.25).abs() < 0.001);
+
         // Clean up
         drop(executor);
         let _ = fs::remove_file(test_file);
     }
 
+    #[test]
+    fn test_real_record_format() {
+        // Five byte reals: mantissa least significant byte first, then the
+        // excess-128 exponent, with the sign in the mantissa's top bit
+        assert_eq!(encode_real(1.0).unwrap(), [0, 0, 0, 0x00, 0x81]);
+        assert_eq!(encode_real(-1.0).unwrap(), [0, 0, 0, 0x80, 0x81]);
+        assert_eq!(encode_real(3.25).unwrap(), [0, 0, 0, 0x50, 0x82]);
+        assert_eq!(encode_real(0.0).unwrap(), [0; 5]);
+        assert!(matches!(encode_real(1e40), Err(BBCBasicError::TooBig)));
+        
+        for value in [1.0, -2.5, 0.1, 123456.789, -1e-20, 1.7e38] {
+            let decoded = decode_real(encode_real(value).unwrap());
+            assert!((decoded - value).abs() <= value.abs() * 1e-9, "{}", value);
+        }
+    }
+        
+    #[cfg(feature = "filesystem-host")]
     #[test]
     fn test_eof_function() {
         // RED: Test EOF# function
         use std::fs;
         let test_file = "test_eof.txt";
         
-        // Create a small test file
-        fs::write(test_file, "one line\n").unwrap();
+        // Create a small test file holding one string record
+        fs::write(test_file, b"\x00\x03ENO").unwrap();
         
         let mut executor = Executor::new();
         let handle = executor.open_file_for_reading(test_file).unwrap();
@@ -4660,9 +7309,9 @@ mod tests {
         let eof = executor.check_eof(handle).unwrap();
         assert_eq!(eof, 0); // FALSE
         
-        // Read the line
+        // Read the record
         let handle_expr = Expression::Integer(handle);
-        let variables = vec!["LINE$".to_string()];
+        let variables = names(&["LINE$"]);
         executor.execute_input_file(&handle_expr, &variables).unwrap();
         
         // Now at EOF
@@ -4673,7 +7322,8 @@ mod tests {
         drop(executor);
         let _ = fs::remove_file(test_file);
     }
-
+        
+    #[cfg(feature = "filesystem-host")]
     #[test]
     fn test_multiple_file_handles() {
         // RED: Test opening multiple files simultaneously
@@ -4689,20 +7339,55 @@ mod tests {
         // Open two files
         let handle1 = executor.open_file_for_reading(file1).unwrap();
         let handle2 = executor.open_file_for_writing(file2).unwrap();
-        
-        assert_eq!(handle1, 1);
-        assert_eq!(handle2, 2);
-        
+
+        assert_eq!(handle1, 0x11);
+        assert_eq!(handle2, 0x12);
+
         // Both should be open
         assert!(executor.open_files.contains_key(&handle1));
         assert!(executor.open_files.contains_key(&handle2));
-        
+
         // Clean up
         drop(executor);
         let _ = fs::remove_file(file1);
         let _ = fs::remove_file(file2);
     }
 
+    #[cfg(feature = "filesystem-host")]
+    #[test]
+    fn test_file_handles_are_reused_and_close_zero_closes_all() {
+        use std::fs;
+        let test_file = "test_handle_table.txt";
+        fs::write(test_file, "data").unwrap();
+
+        let mut executor = Executor::new();
+        let handles: Vec<i32> = (0..5)
+            .map(|_| executor.open_file_for_reading(test_file).unwrap())
+            .collect();
+        assert_eq!(handles, vec![0x11, 0x12, 0x13, 0x14, 0x15]);
+        assert!(matches!(
+            executor.open_file_for_reading(test_file),
+            Err(BBCBasicError::TooManyOpenFiles)
+        ));
+
+        // The lowest free handle is given out next
+        executor
+            .execute_close_file(&Expression::Integer(0x13))
+            .unwrap();
+        assert_eq!(executor.open_file_for_reading(test_file).unwrap(), 0x13);
+
+        executor
+            .execute_close_file(&Expression::Integer(0))
+            .unwrap();
+        assert_eq!(executor.open_file_count(), 0);
+        assert!(matches!(
+            executor.execute_close_file(&Expression::Integer(0x11)),
+            Err(BBCBasicError::ChannelNotOpen(0x11))
+        ));
+
+        let _ = fs::remove_file(test_file);
+    }
+
     #[test]
     fn test_while_loop_helpers() {
         // RED: Test WHILE...ENDWHILE helper methods
@@ -4730,8 +7415,12 @@ mod tests {
         };
 
         // First check - X% = 0, should enter loop
-        let result = executor.push_while(20, &condition).unwrap();
-        assert_eq!(result, Some(20), "Should enter loop when X% = 0");
+        let result = executor.push_while(Position::from(20), &condition).unwrap();
+        assert_eq!(
+            result,
+            Some(Position::from(20)),
+            "Should enter loop when X% = 0"
+        );
 
         // Loop several times
         for expected in 1..=5 {
@@ -4750,7 +7439,12 @@ mod tests {
             if expected < 5 {
                 // Should loop back (X% < 5)
                 let result = executor.check_endwhile(&condition).unwrap();
-                assert_eq!(result, Some(20), "Should loop back when X% = {}", expected);
+                assert_eq!(
+                    result,
+                    Some(Position::from(20)),
+                    "Should loop back when X% = {}",
+                    expected
+                );
             } else {
                 // Should exit loop (X% = 5)
                 let result = executor.check_endwhile(&condition).unwrap();
@@ -4782,7 +7476,7 @@ mod tests {
             right: Box::new(Expression::Integer(5)),
         };
 
-        let result = executor.push_while(20, &condition).unwrap();
+        let result = executor.push_while(Position::from(20), &condition).unwrap();
         assert_eq!(result, None, "Should not enter loop when condition is false");
 
         // while_stack should be empty (loop was never entered)
@@ -4803,7 +7497,9 @@ mod tests {
         };
 
         // Enter outer loop
-        executor.push_while(10, &outer_condition).unwrap();
+        executor
+            .push_while(Position::from(10), &outer_condition)
+            .unwrap();
         assert_eq!(executor.while_stack.len(), 1);
 
         // Inner: WHILE J% < 2
@@ -4815,7 +7511,9 @@ mod tests {
         };
 
         // Enter inner loop
-        executor.push_while(20, &inner_condition).unwrap();
+        executor
+            .push_while(Position::from(20), &inner_condition)
+            .unwrap();
         assert_eq!(executor.while_stack.len(), 2);
 
         // Exit inner loop
@@ -4829,6 +7527,7 @@ mod tests {
         assert_eq!(executor.while_stack.len(), 0, "Outer loop should be popped");
     }
 
+    #[cfg(feature = "filesystem-host")]
     #[test]
     fn test_bget_reads_single_byte() {
         // RED: Test BGET# reads a single byte from file
@@ -4866,6 +7565,7 @@ mod tests {
         let _ = fs::remove_file(test_file);
     }
 
+    #[cfg(feature = "filesystem-host")]
     #[test]
     fn test_bget_at_eof() {
         // RED: Test BGET# at end of file returns -1
@@ -4891,6 +7591,7 @@ mod tests {
         let _ = fs::remove_file(test_file);
     }
 
+    #[cfg(feature = "filesystem-host")]
     #[test]
     fn test_bput_writes_single_byte() {
         // RED: Test BPUT# writes a single byte to file
@@ -4920,6 +7621,7 @@ mod tests {
         let _ = fs::remove_file(test_file);
     }
 
+    #[cfg(feature = "filesystem-host")]
     #[test]
     fn test_bput_with_large_numbers() {
         // RED: Test BPUT# with numbers > 255 (should wrap using MOD 256)
@@ -4947,6 +7649,7 @@ mod tests {
         let _ = fs::remove_file(test_file);
     }
 
+    #[cfg(feature = "filesystem-host")]
     #[test]
     fn test_ptr_get_position() {
         // RED: Test PTR# function returns current file position
@@ -4979,6 +7682,7 @@ mod tests {
         let _ = fs::remove_file(test_file);
     }
 
+    #[cfg(feature = "filesystem-host")]
     #[test]
     fn test_ptr_set_position() {
         // RED: Test PTR# assignment sets file position
@@ -5011,6 +7715,7 @@ mod tests {
         let _ = fs::remove_file(test_file);
     }
 
+    #[cfg(feature = "filesystem-host")]
     #[test]
     fn test_ptr_with_output_file() {
         // RED: Test PTR# works with output files too
@@ -5035,6 +7740,7 @@ mod tests {
         let _ = fs::remove_file(test_file);
     }
 
+    #[cfg(feature = "filesystem-host")]
     #[test]
     fn test_ext_returns_file_size() {
         // RED: Test EXT# function returns file size
@@ -5062,6 +7768,46 @@ mod tests {
         let _ = fs::remove_file(test_file);
     }
 
+    #[cfg(feature = "filesystem-host")]
+    #[test]
+    fn test_ext_assignment_truncates_and_extends() {
+        use std::fs;
+        let test_file = "test_ext_assign.dat";
+        fs::write(test_file, [1, 2, 3, 4, 5, 6, 7, 8, 9, 10]).unwrap();
+
+        let mut executor = Executor::new();
+        let handle = executor.open_file_for_update(test_file).unwrap();
+
+        // Truncating pulls the pointer back to the new end
+        executor.set_ptr(handle, 8).unwrap();
+        executor.set_ext(handle, 4).unwrap();
+        assert_eq!(executor.get_ext(handle).unwrap(), 4);
+        assert_eq!(executor.get_ptr(handle).unwrap(), 4);
+        assert_eq!(executor.check_eof(handle).unwrap(), -1);
+
+        // Extending pads with zeros and leaves the pointer alone
+        executor.set_ext(handle, 6).unwrap();
+        assert_eq!(executor.check_eof(handle).unwrap(), 0);
+        assert_eq!(executor.bget(handle).unwrap(), 0);
+
+        // Writing beyond the end extends the file
+        executor.set_ptr(handle, 9).unwrap();
+        executor.bput(handle, 99).unwrap();
+        assert_eq!(executor.get_ext(handle).unwrap(), 10);
+
+        // Input files cannot change length
+        let input = executor.open_file_for_reading(test_file).unwrap();
+        assert!(executor.set_ext(input, 0).is_err());
+
+        drop(executor);
+        assert_eq!(
+            fs::read(test_file).unwrap(),
+            [1, 2, 3, 4, 0, 0, 0, 0, 0, 99]
+        );
+        let _ = fs::remove_file(test_file);
+    }
+
+    #[cfg(feature = "filesystem-host")]
     #[test]
     fn test_ext_with_empty_file() {
         // RED: Test EXT# with empty file returns 0
@@ -5078,11 +7824,13 @@ mod tests {
         let size = executor.get_ext(handle).unwrap();
         assert_eq!(size, 0);
 
+
         // Clean up
         drop(executor);
         let _ = fs::remove_file(test_file);
     }
 
+    #[cfg(feature = "filesystem-host")]
     #[test]
     fn test_ext_with_output_file() {
         // RED: Test EXT# works with output files
@@ -5108,4 +7856,3 @@ mod tests {
         let _ = fs::remove_file(test_file);
     }
 }
-
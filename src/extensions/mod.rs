@@ -8,7 +8,7 @@
 //! to avoid circular module dependencies. This module serves as documentation
 //! of which functions are extensions vs. standard BBC BASIC functions.
 //!
-//! ### Non-Standard String Functions
+//! ### Non-Standard Functions
 //!
 //! | Function | Description | Standard BBC BASIC? |
 //! |----------|-------------|---------------------|
@@ -16,6 +16,19 @@
 //! | `LOWER$` | Convert string to lowercase | ❌ No |
 //! | `STRING$` | Repeat a character N times | ❌ No |
 //! | `REPORT$` | Get last error message as string | ❌ No |
+//! | `FNvdu` | Read a VDU variable, numbered as OSBYTE &A0 numbers them (`FNvdu(&55)` is the MODE, `FNvdu(&57)` the text colour) | ❌ No |
+//! | `FNoscli$` | Capture a star command's output (`FNoscli$("CAT")`, or line N with `FNoscli$("CAT", N)`) | ❌ No |
+//!
+//! ### Non-Standard Statements
+//!
+//! | Statement | Description | Standard BBC BASIC? |
+//! |-----------|-------------|---------------------|
+//! | `CONST` | Set a variable that cannot be assigned again (error 47, "Constant") | ❌ No |
+//! | `ASSERT` | Stop with error 48 and a message if a condition is FALSE | ❌ No |
+//! | `OUTPUT` | Send PRINT output to an open file (`OUTPUT #ch`), the screen (`OUTPUT #0`), or back where it went before (`OUTPUT`) | ❌ No |
+//! | `FORWARD` | Walk the turtle, drawing while its pen is down (`turtle` feature, see [`crate::turtle`]) | ❌ No |
+//! | `TURN` | Turn the turtle clockwise by degrees (`turtle` feature) | ❌ No |
+//! | `PENUP` / `PENDOWN` | Lift or lower the turtle's pen, `PENDOWN colour` picking its colour (`turtle` feature) | ❌ No |
 //!
 //! ### Standard BBC BASIC String Functions (for reference)
 //!
@@ -1,18 +1,250 @@
 //! File system operations for BBC BASIC
 //!
-//! Handles file I/O operations and star commands.
+//! Handles file I/O operations and star commands, and reads programs from
+//! the forms BBC software is archived in: Acorn tokenized program files, DFS
+//! disc images (.ssd/.dsd) and UEF tape images, which can also be played
+//! block by block through the tape recorder. With the `remote` feature,
+//! programs can also be read from .zip archives and http(s) URLs.
+//!
+//! File names given to programs and star commands go through a
+//! [`FilenameTranslator`], which maps BBC names such as `:0.$.PROG` and
+//! `D.GAME1` to host paths inside the configured root directory.
+//!
+//! Host file access needs the `filesystem-host` feature, and the disc and
+//! tape image readers the `disc-images` feature; without them the same
+//! functions report which feature is missing.
+
+use crate::tokenizer::create_reverse_keyword_maps;
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "disc-images")]
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Longest DFS file name
+pub const DFS_NAME_LENGTH: usize = 7;
+/// Longest ADFS file or directory name
+pub const ADFS_NAME_LENGTH: usize = 10;
 
 /// File system interface
 #[derive(Debug)]
 pub struct FileSystem {
-    // Implementation will be added in later tasks
+    /// Translates file names to host paths
+    names: FilenameTranslator,
+}
+
+/// A file held in a disc or tape image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchivedFile {
+    /// File name, with its DFS directory (e.g. "$.GAME") for disc files
+    pub name: String,
+    /// Load address
+    pub load_address: u32,
+    /// Execution address
+    pub exec_address: u32,
+    /// File contents
+    pub data: Vec<u8>,
 }
 
 impl FileSystem {
     /// Create a new file system interface
     pub fn new() -> Self {
-        Self {}
+        Self::with_translator(FilenameTranslator::default())
+    }
+
+    /// Create a file system interface with file names relative to `root`
+    pub fn with_root(root: Option<PathBuf>) -> Self {
+        Self::with_translator(FilenameTranslator::new(root, false))
+    }
+
+    /// Create a file system interface that maps file names with `names`
+    pub fn with_translator(names: FilenameTranslator) -> Self {
+        Self { names }
+    }
+
+    /// Read a program as source lines
+    ///
+    /// `spec` is a file name or URL, optionally followed by `#NAME` to pick a
+    /// file from a disc image, tape image or archive, e.g.
+    /// `https://example.org/games.zip#ELITE`.
+    pub fn read_program(&self, spec: &str) -> Result<Vec<String>, String> {
+        let (location, wanted) = match spec.split_once('#') {
+            Some((location, name)) => (location, Some(name)),
+            None => (spec, None),
+        };
+
+        let bytes = if is_url(location) {
+            download(location)?
+        } else {
+            let path = self.names.translate(location)?;
+            std::fs::read(&path).map_err(|e| format!("Failed to read file: {}", e))?
+        };
+        program_from(location, &bytes, wanted)
+    }
+
+    /// Read a file with its load and execution addresses, for *LOAD
+    ///
+    /// `spec` is a host file, whose addresses come from a `.inf` file beside
+    /// it (both 0 if it has none, and an error if the contents do not match
+    /// the CRC it records), or a file in a disc or tape image
+    /// (`GAMES.SSD#FONT`), whose addresses come from the catalogue.
+    pub fn read_file(&self, spec: &str) -> Result<ArchivedFile, String> {
+        let (location, wanted) = match spec.split_once('#') {
+            Some((location, name)) => (location, Some(name)),
+            None => (spec, None),
+        };
+        let (bytes, path) = if is_url(location) {
+            (download(location)?, None)
+        } else {
+            let path = self.names.translate(location)?;
+            let bytes = std::fs::read(&path)
+                .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+            (bytes, Some(path))
+        };
+
+        match extension(location).as_str() {
+            "ssd" => pick(location, read_disc_image(&bytes, false)?, wanted),
+            "dsd" => pick(location, read_disc_image(&bytes, true)?, wanted),
+            "uef" => pick(location, read_uef(&bytes)?, wanted),
+            _ if wanted.is_some() => Err(format!("{} is not a disc or tape image", location)),
+            _ => {
+                let inf = path
+                    .and_then(|path| std::fs::read_to_string(inf_path(&path)).ok())
+                    .and_then(|text| parse_inf(&text));
+                if let Some(crc) = inf.as_ref().and_then(|inf| inf.crc) {
+                    if crc != acorn_crc(&bytes) {
+                        return Err(format!(
+                            "Bad CRC: {} does not match its .inf file",
+                            location
+                        ));
+                    }
+                }
+                Ok(ArchivedFile {
+                    name: location.to_string(),
+                    load_address: inf.as_ref().map_or(0, |inf| inf.load_address),
+                    exec_address: inf.as_ref().map_or(0, |inf| inf.exec_address),
+                    data: bytes,
+                })
+            }
+        }
     }
+
+    /// Write a file, with its load and execution addresses in a `.inf` file
+    /// beside it, for *SAVE
+    pub fn write_file(&self, file: &ArchivedFile) -> Result<(), String> {
+        let path = self.names.translate(&file.name)?;
+        std::fs::write(&path, &file.data)
+            .and_then(|()| std::fs::write(inf_path(&path), format_inf(file)))
+            .map_err(|e| format!("Cannot write {}: {}", path.display(), e))
+    }
+}
+
+/// The `.inf` file holding a host file's BBC attributes
+fn inf_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".inf");
+    PathBuf::from(name)
+}
+
+/// The BBC attributes of a host file, as kept in its `.inf` file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inf {
+    /// The file's BBC name (e.g. "$.FONT")
+    pub name: String,
+    /// Load address
+    pub load_address: u32,
+    /// Execution address
+    pub exec_address: u32,
+    /// Length of the file, if recorded
+    pub length: Option<u32>,
+    /// Whether the file is locked against deletion
+    pub locked: bool,
+    /// CRC of the file's contents, if recorded
+    pub crc: Option<u16>,
+}
+
+/// Read a `.inf` file, which holds the BBC name then the addresses and
+/// length in hex, optionally followed by `L` for a locked file and the CRC
+/// of the contents, e.g. `$.FONT FFFF3000 FFFF3000 00000300 L CRC=1D0F`
+///
+/// The exec address defaults to the load address. Six digit addresses
+/// from the I/O processor (`FF3000`) are widened to `FFFF3000`, as archives
+/// made on the real machine often hold them.
+pub fn parse_inf(text: &str) -> Option<Inf> {
+    let address = |field: &str| {
+        let address = u32::from_str_radix(field, 16).ok()?;
+        Some(if field.len() == 6 && address >= 0xFF0000 {
+            address | 0xFF000000
+        } else {
+            address
+        })
+    };
+    let mut fields = text.split_whitespace();
+    let name = fields.next()?.to_string();
+    let load_address = address(fields.next()?)?;
+    let exec_address = match fields.next() {
+        Some(field) => address(field)?,
+        None => load_address,
+    };
+    let mut inf = Inf {
+        name,
+        load_address,
+        exec_address,
+        length: None,
+        locked: false,
+        crc: None,
+    };
+    for field in fields {
+        let upper = field.to_ascii_uppercase();
+        if let Some(crc) = upper.strip_prefix("CRC=") {
+            inf.crc = Some(u16::from_str_radix(crc, 16).ok()?);
+        } else if upper == "L" || upper == "LOCKED" {
+            inf.locked = true;
+        } else if inf.length.is_none() {
+            inf.length = Some(u32::from_str_radix(field, 16).ok()?);
+        }
+    }
+    Some(inf)
+}
+
+/// The `.inf` file contents for a file, naming it as it would be on a DFS
+/// disc (`$.FONT` for `FONT` or `:0.$.FONT`)
+pub fn format_inf(file: &ArchivedFile) -> String {
+    let name = file.name.trim();
+    let name = match name.strip_prefix(':').and_then(|rest| rest.split_once('.')) {
+        Some((_drive, rest)) => rest,
+        None => name,
+    };
+    let name = Path::new(name)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| name.to_string());
+    let name = match name.split_once('.') {
+        Some((directory, _)) if directory.chars().count() == 1 => name,
+        _ => format!("$.{}", name),
+    };
+    format!(
+        "{} {:08X} {:08X} {:08X} CRC={:04X}\n",
+        name,
+        file.load_address,
+        file.exec_address,
+        file.data.len(),
+        acorn_crc(&file.data)
+    )
+}
+
+/// The CRC Acorn's tape filing system records after block headers and
+/// data, and archivers give for whole files in `.inf` files (CRC-16,
+/// polynomial 0x1021, starting from zero)
+pub fn acorn_crc(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ (byte as u16) << 8, |crc, _| {
+            if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
 }
 
 impl Default for FileSystem {
@@ -20,3 +252,1378 @@ impl Default for FileSystem {
         Self::new()
     }
 }
+
+/// Maps file names to host paths inside a sandbox directory
+///
+/// BBC names are a DFS directory letter and a name (`D.GAME1`), an ADFS
+/// path from the root (`$.GAMES.ELITE`) or a bare name (`PROG`), optionally
+/// after a drive number (`:0.$.PROG`). Directories become host directories,
+/// `$` is the root itself and all drives share it. Names containing a `/`,
+/// or whose first part is longer than one character (`game.bas`), are taken
+/// as host paths, and so is a name like `b.bbas` when that host file exists
+/// and the BBC name's file does not.
+///
+/// With a root set, names that would leave it (through `..` or an absolute
+/// path) are rejected. With strict names on, BBC names must fit the DFS
+/// limit of 7 characters, or 10 for each part of an ADFS path.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilenameTranslator {
+    /// Sandbox directory (None = current directory, not sandboxed)
+    root: Option<PathBuf>,
+    /// Enforce the DFS and ADFS name length limits
+    strict_names: bool,
+}
+
+impl FilenameTranslator {
+    /// Create a translator for names under `root`
+    pub fn new(root: Option<PathBuf>, strict_names: bool) -> Self {
+        Self { root, strict_names }
+    }
+
+    /// The sandbox directory, if any
+    pub fn root(&self) -> Option<&Path> {
+        self.root.as_deref()
+    }
+
+    /// Translate a file name to a host path
+    #[cfg(feature = "filesystem-host")]
+    pub fn translate(&self, name: &str) -> Result<PathBuf, String> {
+        use std::path::Component;
+
+        let name = name.trim();
+        let on_host = |path: &Path| match &self.root {
+            Some(root) => root.join(path),
+            None => path.to_path_buf(),
+        };
+        let relative = match bbc_name_parts(name)? {
+            // A host file whose name only looks like a BBC one (b.bbas) is
+            // found as itself, if there is no file with the BBC name
+            Some((parts, _))
+                if !on_host(&parts.iter().collect::<PathBuf>()).exists()
+                    && on_host(Path::new(name)).is_file() =>
+            {
+                PathBuf::from(name)
+            }
+            Some((parts, limit)) => {
+                if self.strict_names {
+                    if let Some(part) = parts.iter().find(|part| part.chars().count() > limit) {
+                        return Err(format!(
+                            "Bad name: {} is longer than {} characters",
+                            part, limit
+                        ));
+                    }
+                }
+                parts.iter().collect()
+            }
+            None => PathBuf::from(name),
+        };
+
+        let Some(root) = &self.root else {
+            return Ok(relative);
+        };
+        if relative
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(format!("Bad name: {} is outside the filing system", name));
+        }
+        Ok(root.join(relative))
+    }
+
+    /// Translate a file name to a host path
+    #[cfg(not(feature = "filesystem-host"))]
+    pub fn translate(&self, _name: &str) -> Result<PathBuf, String> {
+        Err("Files need the 'filesystem-host' feature".to_string())
+    }
+}
+
+/// The *CAT listing of the programs (`.bbas` files) in a directory
+#[cfg(feature = "filesystem-host")]
+pub fn catalogue(directory: &Path) -> Result<String, String> {
+    let paths =
+        std::fs::read_dir(directory).map_err(|e| format!("Failed to read directory: {}", e))?;
+
+    let mut listing = String::from("\nCatalog:\n");
+    listing.push_str(&format!("{:<30} {:>10}  Modified\n", "Filename", "Size"));
+    listing.push_str(&format!("{}\n", "-".repeat(60)));
+
+    let mut count = 0;
+    let mut entries: Vec<_> = paths.collect();
+    entries.sort_by_key(|e| {
+        e.as_ref()
+            .ok()
+            .and_then(|e| e.file_name().to_str().map(|s| s.to_lowercase()))
+    });
+
+    for path in entries {
+        let path = path.map_err(|e| format!("Failed to read entry: {}", e))?;
+        let filename = path.file_name();
+        let filename_str = filename.to_string_lossy();
+
+        if filename_str.ends_with(".bbas") {
+            let metadata = path
+                .metadata()
+                .map_err(|e| format!("Failed to read metadata: {}", e))?;
+
+            let size = metadata.len();
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|m| m.elapsed().ok())
+                .map(|d| {
+                    let secs = d.as_secs();
+                    if secs < 60 {
+                        format!("{}s ago", secs)
+                    } else if secs < 3600 {
+                        format!("{}m ago", secs / 60)
+                    } else if secs < 86400 {
+                        format!("{}h ago", secs / 3600)
+                    } else {
+                        format!("{}d ago", secs / 86400)
+                    }
+                })
+                .unwrap_or_else(|| "unknown".to_string());
+
+            listing.push_str(&format!(
+                "{:<30} {:>10}  {}\n",
+                filename_str, size, modified
+            ));
+            count += 1;
+        }
+    }
+
+    if count == 0 {
+        listing.push_str("(no .bbas files found)\n");
+    } else {
+        listing.push_str(&format!("\n{} file(s)\n", count));
+    }
+
+    Ok(listing)
+}
+
+#[cfg(not(feature = "filesystem-host"))]
+pub fn catalogue(_directory: &Path) -> Result<String, String> {
+    Err("Files need the 'filesystem-host' feature".to_string())
+}
+
+/// Split a BBC file name into host path parts, with the length limit for
+/// its filing system; None if it is a host name
+#[cfg(feature = "filesystem-host")]
+fn bbc_name_parts(name: &str) -> Result<Option<(Vec<&str>, usize)>, String> {
+    let bad_name = || format!("Bad name: {}", name);
+    if name.contains(['/', '\\']) {
+        return Ok(None);
+    }
+
+    // A drive number marks a BBC name even if the rest looks like a host one
+    let (drive, rest) = match name.strip_prefix(':') {
+        Some(rest) => {
+            let (drive, rest) = rest.split_once('.').ok_or_else(bad_name)?;
+            if drive.len() != 1 || !drive.chars().all(|c| c.is_ascii_digit()) {
+                return Err(bad_name());
+            }
+            (true, rest)
+        }
+        None => (false, name),
+    };
+
+    let mut parts: Vec<&str> = rest.split('.').collect();
+    if !drive && parts.len() > 1 && parts[0].chars().count() != 1 {
+        return Ok(None);
+    }
+    if parts.iter().any(|part| part.is_empty()) {
+        return Err(bad_name());
+    }
+
+    // A single-letter directory and a name is DFS; anything deeper is ADFS
+    let limit = if parts.len() <= 2 {
+        DFS_NAME_LENGTH
+    } else {
+        ADFS_NAME_LENGTH
+    };
+    if parts.len() > 1 && parts[0] == "$" {
+        parts.remove(0);
+    }
+    Ok(Some((parts, limit)))
+}
+
+/// Whether LOAD/CHAIN should go through [`FileSystem::read_program`] rather
+/// than reading a plain text program
+pub fn is_archive_spec(spec: &str) -> bool {
+    let location = spec.split('#').next().unwrap_or_default();
+    is_url(location)
+        || spec.contains('#')
+        || matches!(extension(location).as_str(), "ssd" | "dsd" | "uef" | "zip")
+}
+
+/// Whether a file name is an http or https URL, which LOAD and CHAIN
+/// download
+pub fn is_url(location: &str) -> bool {
+    let lower = location.to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+/// Lowercase extension of a file name or URL path
+fn extension(name: &str) -> String {
+    Path::new(name)
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+/// Unwrap the container `name` (disc, tape, archive or plain file) and
+/// decode the program inside
+fn program_from(name: &str, bytes: &[u8], wanted: Option<&str>) -> Result<Vec<String>, String> {
+    match extension(name).as_str() {
+        "zip" => {
+            let entries = read_zip(bytes)?;
+            // An archive entry can be named directly; otherwise use the first
+            // entry we know how to read and look for `wanted` inside it
+            if let Some(entry) = wanted.and_then(|w| {
+                entries.iter().find(|(n, _)| {
+                    let file = n.rsplit('/').next().unwrap_or(n);
+                    file.eq_ignore_ascii_case(w)
+                        || Path::new(file)
+                            .file_stem()
+                            .is_some_and(|s| s.to_string_lossy().eq_ignore_ascii_case(w))
+                })
+            }) {
+                return program_from(&entry.0, &entry.1, None);
+            }
+            let (inner, data) = entries
+                .iter()
+                .find(|(n, _)| {
+                    matches!(
+                        extension(n).as_str(),
+                        "ssd" | "dsd" | "uef" | "bbas" | "bas"
+                    )
+                })
+                .ok_or_else(|| format!("No program, disc or tape image in {}", name))?;
+            program_from(inner, data, wanted)
+        }
+        "ssd" => decode_program(&pick(name, read_disc_image(bytes, false)?, wanted)?.data),
+        "dsd" => decode_program(&pick(name, read_disc_image(bytes, true)?, wanted)?.data),
+        "uef" => decode_program(&pick(name, read_uef(bytes)?, wanted)?.data),
+        _ => decode_program(bytes),
+    }
+}
+
+/// Choose a file from an image by name (ignoring the DFS directory), or the
+/// only file if no name was given
+fn pick(
+    image: &str,
+    files: Vec<ArchivedFile>,
+    wanted: Option<&str>,
+) -> Result<ArchivedFile, String> {
+    match wanted {
+        Some(wanted) => files
+            .into_iter()
+            .find(|f| {
+                f.name.eq_ignore_ascii_case(wanted)
+                    || f.name
+                        .rsplit('.')
+                        .next()
+                        .is_some_and(|n| n.eq_ignore_ascii_case(wanted))
+            })
+            .ok_or_else(|| format!("File not found: {} in {}", wanted, image)),
+        None if files.len() == 1 => Ok(files.into_iter().next().unwrap()),
+        None => {
+            let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+            Err(format!(
+                "{} holds {} files ({}); choose one with #NAME",
+                image,
+                files.len(),
+                names.join(", ")
+            ))
+        }
+    }
+}
+
+/// Decode a program file: Acorn tokenized if it starts with a carriage
+/// return, plain text (Unicode, so `£` is the pound sign) otherwise
+pub fn decode_program(bytes: &[u8]) -> Result<Vec<String>, String> {
+    if bytes.first() == Some(&0x0D) {
+        decode_tokenized_program(bytes)
+    } else {
+        Ok(String::from_utf8_lossy(bytes)
+            .lines()
+            .map(|line| crate::charset::from_unicode(line.trim_end()))
+            .filter(|line| !line.is_empty())
+            .collect())
+    }
+}
+
+/// Convert an Acorn tokenized BASIC program (as saved by a BBC Micro) to
+/// source lines
+///
+/// Fails with "Bad program" if the lines do not follow one another as their
+/// lengths say; [`salvage_tokenized_program`] recovers what it can of such a
+/// program.
+pub fn decode_tokenized_program(bytes: &[u8]) -> Result<Vec<String>, String> {
+    let keywords = create_reverse_keyword_maps();
+    let mut lines = Vec::new();
+    let mut pos = 0;
+
+    loop {
+        if bytes.get(pos) != Some(&0x0D) {
+            return Err("Bad program".to_string());
+        }
+        let hi = *bytes.get(pos + 1).ok_or("Bad program")?;
+        if hi == 0xFF {
+            break; // End of program marker
+        }
+        let lo = *bytes.get(pos + 2).ok_or("Bad program")?;
+        let length = *bytes.get(pos + 3).ok_or("Bad program")? as usize;
+        if length < 4 || pos + length > bytes.len() {
+            return Err("Bad program".to_string());
+        }
+        let number = u16::from(hi) << 8 | u16::from(lo);
+        let body = &bytes[pos + 4..pos + length];
+        pos += length;
+
+        let (text, _) = detokenize_line(body, &keywords, false)
+            .map_err(|byte| format!("Unknown token &{:02X} at line {}", byte, number))?;
+        lines.push(numbered_line(number, &text));
+    }
+
+    Ok(lines)
+}
+
+/// What could be recovered from a damaged tokenized program
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SalvagedProgram {
+    /// The readable lines, as source text
+    pub lines: Vec<String>,
+    /// Lines recovered, but with a bad length or unreadable bytes left out
+    pub repaired: usize,
+    /// Lines given up on, having no usable line number
+    pub lost: usize,
+}
+
+/// Recover what can be read of a damaged or hand-made tokenized program, as
+/// *BADPROG utilities did
+///
+/// Each line starts with a carriage return, so a line whose length is wrong
+/// is taken to run up to the next carriage return instead. Bytes that are
+/// neither printable nor keywords are left out, and lines with impossible
+/// or repeated numbers are dropped.
+pub fn salvage_tokenized_program(bytes: &[u8]) -> SalvagedProgram {
+    let keywords = create_reverse_keyword_maps();
+    let mut salvaged = SalvagedProgram::default();
+    let mut lines = BTreeMap::new();
+    let next_line = |from: usize| {
+        bytes[from.min(bytes.len())..]
+            .iter()
+            .position(|&b| b == 0x0D)
+            .map_or(bytes.len(), |offset| from + offset)
+    };
+    let mut pos = next_line(0);
+
+    while pos + 2 < bytes.len() && bytes[pos + 1] != 0xFF {
+        let number = u16::from(bytes[pos + 1]) << 8 | u16::from(bytes[pos + 2]);
+        let length = bytes.get(pos + 3).map_or(0, |&length| length as usize);
+        let end = pos + length;
+        // A good length ends at the next line or the end of the file
+        let consistent = length >= 4
+            && end <= bytes.len()
+            && (end == bytes.len() || bytes[end] == 0x0D)
+            && !bytes[pos + 4..end].contains(&0x0D);
+        let (body, next) = if consistent {
+            (&bytes[pos + 4..end], end)
+        } else {
+            let next = next_line(pos + 4);
+            (&bytes[(pos + 4).min(next)..next], next)
+        };
+        pos = next;
+
+        if number > 32767 || lines.contains_key(&number) {
+            salvaged.lost += 1;
+            continue;
+        }
+        let (text, skipped) = detokenize_line(body, &keywords, true).unwrap_or_default();
+        if skipped || !consistent {
+            salvaged.repaired += 1;
+        }
+        lines.insert(number, numbered_line(number, &text));
+    }
+
+    // Lines that were out of order go back in order
+    salvaged.lines = lines.into_values().collect();
+    salvaged
+}
+
+/// A tokenized line's text, and whether anything was left out of it
+///
+/// Fails with the first byte that is not a keyword, or when `salvaging`
+/// leaves it and any other unprintable byte out instead.
+fn detokenize_line(
+    body: &[u8],
+    (main_keywords, extended_keywords): &KeywordMaps,
+    salvaging: bool,
+) -> Result<(String, bool), u8> {
+    let mut text = String::new();
+    let mut skipped = false;
+    let mut quoted = false;
+    let mut i = 0;
+    while i < body.len() {
+        let byte = body[i];
+        i += 1;
+        if salvaging && byte < 0x20 {
+            skipped = true;
+            continue;
+        }
+        if quoted || byte < 0x7F {
+            if byte == b'"' {
+                quoted = !quoted;
+            }
+            text.push(byte as char);
+            continue;
+        }
+        if byte == 0x8D && i + 3 <= body.len() {
+            // Line number reference
+            let flags = u32::from(body[i] ^ 0x54);
+            let lo = (u32::from(body[i + 1]) & 0x3F) | ((flags << 2) & 0xC0);
+            let hi = (u32::from(body[i + 2]) & 0x3F) | ((flags << 4) & 0xC0);
+            text.push_str(&(hi << 8 | lo).to_string());
+            i += 3;
+            continue;
+        }
+        let keyword = match body.get(i) {
+            Some(&next) if matches!(byte, 0xC6..=0xC8) => {
+                extended_keywords.get(&(byte, next)).inspect(|_| i += 1)
+            }
+            _ => None,
+        }
+        .or_else(|| main_keywords.get(&byte));
+        let Some(keyword) = keyword else {
+            if !salvaging {
+                return Err(byte);
+            }
+            skipped = true;
+            continue;
+        };
+        text.push_str(keyword);
+        if byte == 0xF4 || byte == 0xDC {
+            // REM and DATA: the rest of the line is literal text
+            for &b in &body[i..] {
+                if salvaging && b < 0x20 {
+                    skipped = true;
+                } else {
+                    text.push(b as char);
+                }
+            }
+            break;
+        }
+    }
+    Ok((text, skipped))
+}
+
+/// The keyword tables, by token (see [`create_reverse_keyword_maps`])
+type KeywordMaps = (HashMap<u8, String>, HashMap<(u8, u8), String>);
+
+/// A source line from its number and detokenized text
+fn numbered_line(number: u16, text: &str) -> String {
+    if text.starts_with(' ') {
+        format!("{}{}", number, text)
+    } else {
+        format!("{} {}", number, text)
+    }
+}
+
+/// Sectors per track in a DFS disc image
+#[cfg(feature = "disc-images")]
+const SECTORS_PER_TRACK: usize = 10;
+/// Bytes per sector in a DFS disc image
+#[cfg(feature = "disc-images")]
+const SECTOR_SIZE: usize = 256;
+
+/// Read the files on an Acorn DFS disc image (.ssd, or .dsd if `double_sided`)
+#[cfg(feature = "disc-images")]
+pub fn read_disc_image(bytes: &[u8], double_sided: bool) -> Result<Vec<ArchivedFile>, String> {
+    if !double_sided {
+        return read_dfs_catalogue(bytes, "");
+    }
+
+    // Double-sided images interleave the two sides track by track
+    let track = SECTORS_PER_TRACK * SECTOR_SIZE;
+    let mut sides = [Vec::new(), Vec::new()];
+    for (i, chunk) in bytes.chunks(track).enumerate() {
+        sides[i % 2].extend_from_slice(chunk);
+    }
+    let mut files = read_dfs_catalogue(&sides[0], "")?;
+    if sides[1].len() >= 2 * SECTOR_SIZE {
+        files.extend(read_dfs_catalogue(&sides[1], ":2.")?);
+    }
+    Ok(files)
+}
+
+/// Read one side's DFS catalogue (sectors 0 and 1)
+#[cfg(feature = "disc-images")]
+fn read_dfs_catalogue(side: &[u8], drive: &str) -> Result<Vec<ArchivedFile>, String> {
+    if side.len() < 2 * SECTOR_SIZE {
+        return Err("Disc image too small".to_string());
+    }
+    let names = &side[..SECTOR_SIZE];
+    let info = &side[SECTOR_SIZE..2 * SECTOR_SIZE];
+    let count = info[5] as usize / 8;
+    if count > 31 {
+        return Err("Bad disc catalogue".to_string());
+    }
+
+    let mut files = Vec::with_capacity(count);
+    for entry in 0..count {
+        let name_bytes = &names[8 + entry * 8..16 + entry * 8];
+        let name: String = name_bytes[..7]
+            .iter()
+            .map(|&b| (b & 0x7F) as char)
+            .collect::<String>()
+            .trim_end()
+            .to_string();
+        let directory = (name_bytes[7] & 0x7F) as char;
+
+        let e = &info[8 + entry * 8..16 + entry * 8];
+        let high = u32::from(e[6]);
+        let load_address = u32::from(e[0]) | u32::from(e[1]) << 8 | (high >> 2 & 3) << 16;
+        let exec_address = u32::from(e[2]) | u32::from(e[3]) << 8 | (high >> 6 & 3) << 16;
+        let length = (u32::from(e[4]) | u32::from(e[5]) << 8 | (high >> 4 & 3) << 16) as usize;
+        let start = ((high as usize & 3) << 8 | e[7] as usize) * SECTOR_SIZE;
+        let data = side
+            .get(start..start + length)
+            .ok_or_else(|| format!("File {} runs past the end of the disc image", name))?;
+
+        files.push(ArchivedFile {
+            name: format!("{}{}.{}", drive, directory, name),
+            load_address,
+            exec_address,
+            data: data.to_vec(),
+        });
+    }
+    Ok(files)
+}
+
+/// A block recorded on tape
+#[cfg(feature = "disc-images")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TapeBlock {
+    /// Name of the file the block belongs to
+    pub name: String,
+    /// Load address of the file
+    pub load_address: u32,
+    /// Execution address of the file
+    pub exec_address: u32,
+    /// Block number within the file
+    pub number: u16,
+    /// Block flag (bit 7 set on the last block of a file)
+    pub flag: u8,
+    /// Block contents
+    pub data: Vec<u8>,
+}
+
+#[cfg(feature = "disc-images")]
+impl TapeBlock {
+    /// Whether this is the last block of its file
+    pub fn is_last(&self) -> bool {
+        self.flag & 0x80 != 0
+    }
+
+    /// Bytes recorded on tape for this block: sync byte, header with CRC,
+    /// and data with CRC
+    pub fn recorded_length(&self) -> usize {
+        1 + self.name.len() + 1 + 19 + self.data.len() + if self.data.is_empty() { 0 } else { 2 }
+    }
+
+    /// The bytes recorded on tape for this block, as counted by
+    /// `recorded_length`, with the header and data CRCs worked out
+    pub fn recorded_bytes(&self) -> Vec<u8> {
+        let mut header = self.name.as_bytes().to_vec();
+        header.push(0);
+        header.extend(self.load_address.to_le_bytes());
+        header.extend(self.exec_address.to_le_bytes());
+        header.extend(self.number.to_le_bytes());
+        header.extend((self.data.len() as u16).to_le_bytes());
+        header.push(self.flag);
+        header.extend([0; 4]); // next file address
+        header.extend(acorn_crc(&header).to_be_bytes());
+
+        let mut bytes = vec![0x2A];
+        bytes.extend(header);
+        if !self.data.is_empty() {
+            bytes.extend(&self.data);
+            bytes.extend(acorn_crc(&self.data).to_be_bytes());
+        }
+        bytes
+    }
+}
+
+/// Read the files on a UEF tape image
+#[cfg(feature = "disc-images")]
+pub fn read_uef(bytes: &[u8]) -> Result<Vec<ArchivedFile>, String> {
+    let mut files: Vec<ArchivedFile> = Vec::new();
+    for block in read_uef_blocks(bytes)? {
+        match files.last_mut() {
+            Some(file) if block.number != 0 && file.name == block.name => {
+                file.data.extend_from_slice(&block.data)
+            }
+            _ => files.push(ArchivedFile {
+                name: block.name,
+                load_address: block.load_address,
+                exec_address: block.exec_address,
+                data: block.data,
+            }),
+        }
+    }
+    Ok(files)
+}
+
+/// Read the blocks recorded on a UEF tape image, in tape order
+#[cfg(feature = "disc-images")]
+pub fn read_uef_blocks(bytes: &[u8]) -> Result<Vec<TapeBlock>, String> {
+    let unpacked;
+    let bytes = if bytes.starts_with(&[0x1F, 0x8B]) {
+        unpacked = gunzip(bytes)?;
+        &unpacked[..]
+    } else {
+        bytes
+    };
+    if !bytes.starts_with(b"UEF File!\0") || bytes.len() < 12 {
+        return Err("Not a UEF tape image".to_string());
+    }
+
+    // Gather the data from every tape data chunk
+    let mut tape = Vec::new();
+    let mut pos = 12;
+    while pos + 6 <= bytes.len() {
+        let id = u16::from_le_bytes([bytes[pos], bytes[pos + 1]]);
+        let length = u32::from_le_bytes([
+            bytes[pos + 2],
+            bytes[pos + 3],
+            bytes[pos + 4],
+            bytes[pos + 5],
+        ]) as usize;
+        let data = bytes
+            .get(pos + 6..pos + 6 + length)
+            .ok_or("Truncated UEF chunk")?;
+        if id == 0x0100 {
+            tape.extend_from_slice(data);
+        }
+        pos += 6 + length;
+    }
+
+    // Split the data into blocks
+    let mut blocks = Vec::new();
+    let mut pos = 0;
+    while let Some(offset) = tape[pos..].iter().position(|&b| b == 0x2A) {
+        pos += offset + 1;
+        let name_end = tape[pos..]
+            .iter()
+            .take(11)
+            .position(|&b| b == 0)
+            .ok_or("Bad tape block header")?;
+        let name = String::from_utf8_lossy(&tape[pos..pos + name_end]).to_string();
+        let header = tape
+            .get(pos + name_end + 1..pos + name_end + 20)
+            .ok_or("Truncated tape block header")?;
+        let block_length = u16::from_le_bytes([header[10], header[11]]) as usize;
+        let data_start = pos + name_end + 1 + 19;
+        let data = tape
+            .get(data_start..data_start + block_length)
+            .ok_or("Truncated tape block")?;
+
+        blocks.push(TapeBlock {
+            name,
+            load_address: u32::from_le_bytes([header[0], header[1], header[2], header[3]]),
+            exec_address: u32::from_le_bytes([header[4], header[5], header[6], header[7]]),
+            number: u16::from_le_bytes([header[8], header[9]]),
+            flag: header[12],
+            data: data.to_vec(),
+        });
+        // Skip the data and its CRC, which a truncated tape may be missing
+        pos = data_start + block_length + if block_length > 0 { 2 } else { 0 };
+        if pos > tape.len() {
+            return Err("Bad tape".to_string());
+        }
+    }
+    Ok(blocks)
+}
+
+/// Tape speed in bytes per second (1200 baud, 10 bits per byte)
+#[cfg(feature = "disc-images")]
+const TAPE_BYTES_PER_SECOND: f64 = 120.0;
+
+/// Seconds of carrier tone before the first block of a file, and between
+/// the blocks after it
+#[cfg(feature = "disc-images")]
+const TAPE_LEADER_SECONDS: (f64, f64) = (5.1, 0.9);
+
+/// Where an authentic load (`Tape::load_authentically`) sends the sound of
+/// the tape, and how it learns that Escape was pressed
+#[cfg(feature = "disc-images")]
+pub trait TapeDeck {
+    /// Start playing `bytes` as recorded on tape, after `leader` seconds of
+    /// carrier tone
+    fn play(&mut self, bytes: &[u8], leader: f64);
+
+    /// Stop the tape sound
+    fn stop(&mut self) {}
+
+    /// Whether Escape is held down, to skip the rest of the wait
+    fn escape(&mut self) -> bool {
+        false
+    }
+}
+
+/// A cassette in the tape recorder (*TAPE), read block by block
+#[cfg(feature = "disc-images")]
+#[derive(Debug, Clone)]
+pub struct Tape {
+    blocks: Vec<TapeBlock>,
+    /// Index of the next block under the read head
+    position: usize,
+    /// Cassette motor relay (*MOTOR)
+    motor: bool,
+    /// Take as long as a real cassette to read
+    realtime: bool,
+}
+
+#[cfg(feature = "disc-images")]
+impl Tape {
+    /// Insert a cassette from a UEF tape image
+    pub fn from_uef(bytes: &[u8], realtime: bool) -> Result<Self, String> {
+        Ok(Self {
+            blocks: read_uef_blocks(bytes)?,
+            position: 0,
+            motor: false,
+            realtime,
+        })
+    }
+
+    /// Switch the cassette motor on or off
+    pub fn set_motor(&mut self, on: bool) {
+        self.motor = on;
+    }
+
+    /// Whether the cassette motor is on
+    pub fn motor(&self) -> bool {
+        self.motor
+    }
+
+    /// Wind the tape back to the start
+    pub fn rewind(&mut self) {
+        self.position = 0;
+    }
+
+    /// Load the next file called `name` (any file if `name` is empty),
+    /// writing the Searching/Loading messages to `messages` as each block
+    /// passes the read head
+    ///
+    /// The motor runs while the tape is read and stops afterwards. Reaching
+    /// the end of the tape without finding the file is an error.
+    pub fn load(&mut self, name: &str, messages: &mut impl Write) -> Result<ArchivedFile, String> {
+        self.motor = true;
+        let result = self.search_and_load(name, messages, None);
+        self.motor = false;
+        let _ = messages.flush();
+        result
+    }
+
+    /// Load as `load` does, but at the speed of a real cassette whether or
+    /// not the tape reads in real time, playing each block's tones through
+    /// `deck` as it passes the read head
+    ///
+    /// Pressing Escape silences the deck and loads the rest at once.
+    pub fn load_authentically(
+        &mut self,
+        name: &str,
+        messages: &mut impl Write,
+        deck: &mut dyn TapeDeck,
+    ) -> Result<ArchivedFile, String> {
+        self.motor = true;
+        let result = self.search_and_load(name, messages, Some(deck));
+        self.motor = false;
+        let _ = messages.flush();
+        result
+    }
+
+    fn search_and_load(
+        &mut self,
+        name: &str,
+        messages: &mut impl Write,
+        mut deck: Option<&mut dyn TapeDeck>,
+    ) -> Result<ArchivedFile, String> {
+        let out = |messages: &mut dyn Write, text: &str| {
+            let _ = write!(messages, "{}", text);
+            let _ = messages.flush();
+        };
+        out(messages, "Searching\n");
+
+        let mut file: Option<ArchivedFile> = None;
+        while let Some(block) = self.blocks.get(self.position).cloned() {
+            self.position += 1;
+            match deck.as_deref_mut() {
+                Some(player) => {
+                    if !self.play(&block, player) {
+                        player.stop();
+                        deck = None;
+                    }
+                }
+                None => self.wait_for(&block),
+            }
+
+            // Only start loading at the first block of a wanted file; files
+            // passed over while searching are shown too
+            let wanted = name.is_empty() || block.name.eq_ignore_ascii_case(name);
+            if file.is_none() && wanted && block.number == 0 {
+                out(messages, "\nLoading\n\n");
+                file = Some(ArchivedFile {
+                    name: block.name.clone(),
+                    load_address: block.load_address,
+                    exec_address: block.exec_address,
+                    data: Vec::new(),
+                });
+            }
+            out(
+                messages,
+                &format!("\r{:<10} {:02X}", block.name, block.number),
+            );
+
+            if let Some(loading) = &mut file {
+                if block.name != loading.name {
+                    return Err("Block?".to_string());
+                }
+                loading.data.extend_from_slice(&block.data);
+                if block.is_last() {
+                    out(messages, &format!(" {:04X}\n", loading.data.len()));
+                    return file.ok_or_else(|| "Data?".to_string());
+                }
+            }
+        }
+
+        out(messages, "\n");
+        match file {
+            Some(_) => Err("Data?".to_string()),
+            None if name.is_empty() => Err("End of tape".to_string()),
+            None => Err(format!("File not found: {}", name)),
+        }
+    }
+
+    /// Pause for as long as a block takes to pass the read head, when
+    /// reading in real time
+    fn wait_for(&self, block: &TapeBlock) {
+        if self.realtime {
+            let seconds = block.recorded_length() as f64 / TAPE_BYTES_PER_SECOND;
+            std::thread::sleep(std::time::Duration::from_secs_f64(seconds));
+        }
+    }
+
+    /// Play a block through `deck` and wait for its leader and data to
+    /// pass the read head, returning false if Escape cut the wait short
+    fn play(&self, block: &TapeBlock, deck: &mut dyn TapeDeck) -> bool {
+        let leader = if block.number == 0 {
+            TAPE_LEADER_SECONDS.0
+        } else {
+            TAPE_LEADER_SECONDS.1
+        };
+        deck.play(&block.recorded_bytes(), leader);
+
+        let seconds = leader + block.recorded_length() as f64 / TAPE_BYTES_PER_SECOND;
+        let end = std::time::Instant::now() + std::time::Duration::from_secs_f64(seconds);
+        let slice = std::time::Duration::from_millis(20);
+        while let Some(left) = end.checked_duration_since(std::time::Instant::now()) {
+            if deck.escape() {
+                return false;
+            }
+            std::thread::sleep(left.min(slice));
+        }
+        true
+    }
+}
+
+#[cfg(not(feature = "disc-images"))]
+pub fn read_disc_image(_bytes: &[u8], _double_sided: bool) -> Result<Vec<ArchivedFile>, String> {
+    Err("Reading disc images needs the 'disc-images' feature".to_string())
+}
+
+#[cfg(not(feature = "disc-images"))]
+pub fn read_uef(_bytes: &[u8]) -> Result<Vec<ArchivedFile>, String> {
+    Err("Reading tape images needs the 'disc-images' feature".to_string())
+}
+
+#[cfg(feature = "remote")]
+fn download(url: &str) -> Result<Vec<u8>, String> {
+    let mut response = ureq::get(url)
+        .call()
+        .map_err(|e| format!("Failed to download {}: {}", url, e))?;
+    response
+        .body_mut()
+        .read_to_vec()
+        .map_err(|e| format!("Failed to download {}: {}", url, e))
+}
+
+#[cfg(not(feature = "remote"))]
+fn download(_url: &str) -> Result<Vec<u8>, String> {
+    Err("Loading from a URL needs the 'remote' feature".to_string())
+}
+
+/// Names and contents of the files in a .zip archive
+#[cfg(feature = "remote")]
+fn read_zip(bytes: &[u8]) -> Result<Vec<(String, Vec<u8>)>, String> {
+    use std::io::Read;
+
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes))
+        .map_err(|e| format!("Bad zip archive: {}", e))?;
+    let mut entries = Vec::new();
+    for i in 0..archive.len() {
+        let mut entry = archive
+            .by_index(i)
+            .map_err(|e| format!("Bad zip archive: {}", e))?;
+        if entry.is_dir() {
+            continue;
+        }
+        let mut data = Vec::new();
+        entry
+            .read_to_end(&mut data)
+            .map_err(|e| format!("Bad zip archive: {}", e))?;
+        entries.push((entry.name().to_string(), data));
+    }
+    Ok(entries)
+}
+
+#[cfg(not(feature = "remote"))]
+fn read_zip(_bytes: &[u8]) -> Result<Vec<(String, Vec<u8>)>, String> {
+    Err("Reading .zip archives needs the 'remote' feature".to_string())
+}
+
+#[cfg(feature = "remote")]
+fn gunzip(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    use std::io::Read;
+
+    let mut data = Vec::new();
+    flate2::read::GzDecoder::new(bytes)
+        .read_to_end(&mut data)
+        .map_err(|e| format!("Bad compressed file: {}", e))?;
+    Ok(data)
+}
+
+#[cfg(all(feature = "disc-images", not(feature = "remote")))]
+fn gunzip(_bytes: &[u8]) -> Result<Vec<u8>, String> {
+    Err("Reading compressed UEF files needs the 'remote' feature".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encode a line number reference the way BASIC does
+    fn line_reference(number: u16) -> [u8; 4] {
+        let lo = (number & 0xFF) as u8;
+        let hi = (number >> 8) as u8;
+        [
+            0x8D,
+            (((lo & 0xC0) >> 2) | ((hi & 0xC0) >> 4)) ^ 0x54,
+            (lo & 0x3F) | 0x40,
+            (hi & 0x3F) | 0x40,
+        ]
+    }
+
+    /// A tokenized program: 10 PRINT "HI" / 20 GOTO 10 / 30 REM ok
+    fn tokenized_program() -> Vec<u8> {
+        let mut program = Vec::new();
+        for (number, body) in [
+            (10u16, [&[0xF1u8][..], b" \"HI\""].concat()),
+            (20, [&[0xE5u8, b' '][..], &line_reference(10)].concat()),
+            (30, [&[0xF4u8][..], b" ok"].concat()),
+        ] {
+            program.extend([
+                0x0D,
+                (number >> 8) as u8,
+                number as u8,
+                body.len() as u8 + 4,
+            ]);
+            program.extend(body);
+        }
+        program.extend([0x0D, 0xFF]);
+        program
+    }
+
+    #[test]
+    fn test_decode_tokenized_program() {
+        assert_eq!(
+            decode_tokenized_program(&tokenized_program()).unwrap(),
+            vec!["10 PRINT \"HI\"", "20 GOTO 10", "30 REM ok"]
+        );
+        let lines = decode_tokenized_program(&{
+            let mut p = vec![0x0D, 0x03, 0xE8, 9, 0xE5];
+            p.extend(line_reference(1000));
+            p.extend([0x0D, 0xFF]);
+            p
+        })
+        .unwrap();
+        assert_eq!(lines, vec!["1000 GOTO1000"]);
+        assert!(decode_tokenized_program(&[0x0D, 0x00, 0x0A, 0x02]).is_err());
+    }
+
+    #[test]
+    fn test_salvage_tokenized_program() {
+        let mut program = vec![0xFF, 0x00];
+        program.extend(tokenized_program());
+        program[2 + 3] = 40; // Line 10 says it runs on past line 20
+        program[2 + 15] = 0x07; // A control code in line 20
+        program.truncate(program.len() - 2); // No end marker
+        program.extend([0x0D, 0x00, 0x0A, 0x05, 0xF1]); // Line 10 again
+        program.extend([0x0D, 0x9C, 0x40, 0x05, 0xF1]); // Line 40000
+        assert_eq!(
+            decode_tokenized_program(&program[2..]),
+            Err("Bad program".to_string())
+        );
+
+        let salvaged = salvage_tokenized_program(&program);
+        assert_eq!(
+            salvaged.lines,
+            vec!["10 PRINT \"HI\"", "20 GOTO10", "30 REM ok"]
+        );
+        assert_eq!((salvaged.repaired, salvaged.lost), (2, 2));
+        // A sound program is recovered whole
+        let salvaged = salvage_tokenized_program(&tokenized_program());
+        assert_eq!(
+            salvaged.lines,
+            decode_tokenized_program(&tokenized_program()).unwrap()
+        );
+        assert_eq!((salvaged.repaired, salvaged.lost), (0, 0));
+    }
+
+    #[cfg(feature = "disc-images")]
+    #[test]
+    fn test_read_disc_image() {
+        let program = tokenized_program();
+        let mut image = vec![0u8; 4 * SECTOR_SIZE];
+        image[8..15].copy_from_slice(b"HELLO  ");
+        image[15] = b'$';
+        image[SECTOR_SIZE + 5] = 8; // one file
+        let entry = &mut image[SECTOR_SIZE + 8..SECTOR_SIZE + 16];
+        entry[0..2].copy_from_slice(&[0x00, 0x19]); // load &1900
+        entry[2..4].copy_from_slice(&[0x23, 0x80]); // exec &8023
+        entry[4..6].copy_from_slice(&(program.len() as u16).to_le_bytes());
+        entry[6] = 0x0C | 0xC0; // high bits of load and exec addresses
+        entry[7] = 2; // start sector
+        image[2 * SECTOR_SIZE..2 * SECTOR_SIZE + program.len()].copy_from_slice(&program);
+
+        let files = read_disc_image(&image, false).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].name, "$.HELLO");
+        assert_eq!(files[0].load_address, 0x31900);
+        assert_eq!(files[0].exec_address, 0x38023);
+        assert_eq!(files[0].data, program);
+
+        let lines = program_from("games.ssd", &image, Some("hello")).unwrap();
+        assert_eq!(lines[0], "10 PRINT \"HI\"");
+        assert!(program_from("games.ssd", &image, Some("OTHER")).is_err());
+    }
+
+    #[cfg(feature = "filesystem-host")]
+    #[test]
+    fn test_inf_files() {
+        let dir = std::env::temp_dir().join("bbc_basic_inf_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let filesystem = FileSystem::with_root(Some(dir.clone()));
+        let file = ArchivedFile {
+            name: "FONT".to_string(),
+            load_address: 0xFFFF3000,
+            exec_address: 0xFFFF3010,
+            data: vec![1, 2, 3],
+        };
+        filesystem.write_file(&file).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join("FONT.inf")).unwrap(),
+            format!(
+                "$.FONT FFFF3000 FFFF3010 00000003 CRC={:04X}\n",
+                acorn_crc(&[1, 2, 3])
+            )
+        );
+        assert_eq!(filesystem.read_file("FONT").unwrap(), file);
+
+        // A file changed since its .inf file was written fails the CRC
+        std::fs::write(dir.join("FONT"), [1, 2, 4]).unwrap();
+        let error = filesystem.read_file("FONT").unwrap_err();
+        assert!(error.starts_with("Bad CRC"), "{}", error);
+
+        // Without a .inf file there are no addresses
+        std::fs::remove_file(dir.join("FONT.inf")).unwrap();
+        let plain = filesystem.read_file("FONT").unwrap();
+        assert_eq!((plain.load_address, plain.exec_address), (0, 0));
+        assert!(filesystem.read_file("FONT#A").is_err());
+
+        let inf = parse_inf("$.CODE 1900").unwrap();
+        assert_eq!((inf.load_address, inf.exec_address), (0x1900, 0x1900));
+        assert_eq!((inf.length, inf.locked, inf.crc), (None, false, None));
+        assert_eq!(parse_inf("$.CODE"), None);
+
+        // The fuller form archive sites use, with short I/O addresses
+        let inf = parse_inf("$.ELITE FF1900 FF8023 002000 L CRC=31C3").unwrap();
+        assert_eq!(inf.name, "$.ELITE");
+        assert_eq!(
+            (inf.load_address, inf.exec_address),
+            (0xFFFF1900, 0xFFFF8023)
+        );
+        assert_eq!(
+            (inf.length, inf.locked, inf.crc),
+            (Some(0x2000), true, Some(0x31C3))
+        );
+        assert_eq!(acorn_crc(b"123456789"), 0x31C3);
+
+        // Names are written as DFS names, whatever the drive or directory
+        let named = |name: &str| {
+            let inf = format_inf(&ArchivedFile {
+                name: name.to_string(),
+                ..file.clone()
+            });
+            parse_inf(&inf).unwrap().name
+        };
+        assert_eq!(named(":0.$.FONT"), "$.FONT");
+        assert_eq!(named("D.GAME1"), "D.GAME1");
+        assert_eq!(named("fonts/FONT"), "$.FONT");
+    }
+
+    /// A UEF image holding the given blocks: (name, block number, data, last)
+    #[cfg(feature = "disc-images")]
+    fn uef_image(blocks: &[(&str, u16, &[u8], bool)]) -> Vec<u8> {
+        let mut uef = b"UEF File!\0\x0A\x00".to_vec();
+        for &(name, number, data, last) in blocks {
+            let mut block = vec![0x2A];
+            block.extend(name.as_bytes());
+            block.push(0);
+            block.extend(0xFFFF1900u32.to_le_bytes());
+            block.extend(0xFFFF8023u32.to_le_bytes());
+            block.extend(number.to_le_bytes());
+            block.extend((data.len() as u16).to_le_bytes());
+            block.push(if last { 0x80 } else { 0 });
+            block.extend([0; 4]); // next file address
+            block.extend([0; 2]); // header CRC
+            block.extend(data);
+            block.extend([0; 2]); // data CRC
+            uef.extend(0x0100u16.to_le_bytes());
+            uef.extend((block.len() as u32).to_le_bytes());
+            uef.extend(block);
+        }
+        uef
+    }
+
+    #[cfg(feature = "disc-images")]
+    #[test]
+    fn test_read_uef() {
+        let program = tokenized_program();
+        // Two blocks, to check they are joined into one file
+        let (first, second) = program.split_at(5);
+        let uef = uef_image(&[("PROG", 0, first, false), ("PROG", 1, second, true)]);
+
+        let files = read_uef(&uef).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].name, "PROG");
+        assert_eq!(files[0].load_address, 0xFFFF1900);
+        assert_eq!(files[0].data, program);
+        assert_eq!(program_from("tape.uef", &uef, None).unwrap().len(), 3);
+    }
+
+    #[cfg(feature = "disc-images")]
+    #[test]
+    fn test_read_truncated_uef() {
+        // The last block's data CRC has been cut off, with its chunk
+        let mut uef = uef_image(&[("PROG", 0, b"one", true)]);
+        uef.truncate(uef.len() - 2);
+        let length = u32::from_le_bytes(uef[14..18].try_into().unwrap()) - 2;
+        uef[14..18].copy_from_slice(&length.to_le_bytes());
+        assert_eq!(read_uef_blocks(&uef).unwrap_err(), "Bad tape");
+        assert!(program_from("tape.uef", &uef, None).is_err());
+    }
+
+    #[cfg(feature = "disc-images")]
+    #[test]
+    fn test_tape_load() {
+        let uef = uef_image(&[
+            ("FIRST", 0, b"one", true),
+            ("SECOND", 0, b"two", false),
+            ("SECOND", 1, b"three", true),
+        ]);
+        let mut tape = Tape::from_uef(&uef, false).unwrap();
+        assert_eq!(tape.blocks.len(), 3);
+
+        // Searching passes over FIRST, then loads both blocks of SECOND
+        let mut messages = Vec::new();
+        let file = tape.load("second", &mut messages).unwrap();
+        assert_eq!(file.name, "SECOND");
+        assert_eq!(file.data, b"twothree");
+        assert!(!tape.motor());
+        assert_eq!(
+            String::from_utf8(messages).unwrap(),
+            "Searching\n\rFIRST      00\nLoading\n\n\rSECOND     00\rSECOND     01 0008\n"
+        );
+
+        // Nothing left on the tape until it is rewound
+        let error = tape.load("FIRST", &mut Vec::new()).unwrap_err();
+        assert_eq!(error, "File not found: FIRST");
+        tape.rewind();
+        assert_eq!(tape.load("", &mut Vec::new()).unwrap().data, b"one");
+    }
+
+    #[cfg(feature = "disc-images")]
+    #[test]
+    fn test_tape_load_authentically() {
+        /// A deck that records what it plays, with Escape held down
+        #[derive(Default)]
+        struct Deck {
+            played: Vec<(Vec<u8>, f64)>,
+            stopped: bool,
+        }
+        impl TapeDeck for Deck {
+            fn play(&mut self, bytes: &[u8], leader: f64) {
+                self.played.push((bytes.to_vec(), leader));
+            }
+            fn stop(&mut self) {
+                self.stopped = true;
+            }
+            fn escape(&mut self) -> bool {
+                true
+            }
+        }
+
+        let uef = uef_image(&[("PROG", 0, b"one", false), ("PROG", 1, b"two", true)]);
+        let mut tape = Tape::from_uef(&uef, false).unwrap();
+
+        // Escape skips the wait after the first block, and the rest loads
+        // without being played
+        let mut deck = Deck::default();
+        let mut messages = Vec::new();
+        let file = tape
+            .load_authentically("PROG", &mut messages, &mut deck)
+            .unwrap();
+        assert_eq!(file.data, b"onetwo");
+        assert!(deck.stopped);
+        assert_eq!(deck.played.len(), 1);
+        assert_eq!(deck.played[0].1, TAPE_LEADER_SECONDS.0);
+
+        // The block is played as recorded, with its CRCs filled in
+        let block = &tape.blocks[0];
+        let recorded = &deck.played[0].0;
+        assert_eq!(recorded.len(), block.recorded_length());
+        assert_eq!(recorded[..2], [0x2A, b'P']);
+        assert_eq!(recorded[recorded.len() - 5..recorded.len() - 2], *b"one");
+        assert_eq!(
+            String::from_utf8(messages).unwrap(),
+            "Searching\n\nLoading\n\n\rPROG       00\rPROG       01 0006\n"
+        );
+    }
+
+    #[cfg(feature = "filesystem-host")]
+    #[test]
+    fn test_filename_translation() {
+        let names = FilenameTranslator::new(Some(PathBuf::from("discs")), true);
+        assert_eq!(
+            names.translate(":0.$.PROG").unwrap(),
+            PathBuf::from("discs/PROG")
+        );
+        assert_eq!(
+            names.translate("D.GAME1").unwrap(),
+            PathBuf::from("discs/D/GAME1")
+        );
+        assert_eq!(
+            names.translate("PROG").unwrap(),
+            PathBuf::from("discs/PROG")
+        );
+        assert_eq!(
+            names.translate("$.GAMES.ELITE").unwrap(),
+            PathBuf::from("discs/GAMES/ELITE")
+        );
+        assert_eq!(
+            names.translate("game.bbas").unwrap(),
+            PathBuf::from("discs/game.bbas")
+        );
+        assert_eq!(
+            names.translate("sub/data.txt").unwrap(),
+            PathBuf::from("discs/sub/data.txt")
+        );
+
+        // Escapes from the root are rejected
+        assert!(names.translate("../secret.txt").is_err());
+        assert!(names.translate("/etc/passwd").is_err());
+
+        // Name length limits and malformed names
+        assert!(names.translate("LONGNAME").is_err());
+        assert!(names.translate("$.DIRECTORY.ELITE").is_ok());
+        assert!(names.translate("$.DIRECTORIES.ELITE").is_err());
+        assert!(names.translate(":A.PROG").is_err());
+        assert!(names.translate("D.").is_err());
+
+        // Without a root or strict names, only the BBC forms are translated
+        let names = FilenameTranslator::default();
+        assert_eq!(
+            names.translate("$.LONGNAME").unwrap(),
+            PathBuf::from("LONGNAME")
+        );
+        assert_eq!(
+            names.translate("../up.txt").unwrap(),
+            PathBuf::from("../up.txt")
+        );
+
+        // Host files with one-letter names are not taken for DFS names
+        let dir = std::env::temp_dir().join("bbc_basic_host_names_test");
+        std::fs::create_dir_all(dir.join("D")).unwrap();
+        std::fs::write(dir.join("b.bbas"), "10 PRINT \"B\"\n").unwrap();
+        std::fs::write(dir.join("D.GAME1"), "").unwrap();
+        std::fs::write(dir.join("D").join("GAME1"), "").unwrap();
+        let names = FilenameTranslator::new(Some(dir.clone()), true);
+        assert_eq!(names.translate("b.bbas").unwrap(), dir.join("b.bbas"));
+        assert_eq!(
+            names.translate("D.GAME1").unwrap(),
+            dir.join("D").join("GAME1")
+        );
+        assert_eq!(names.translate("x.bas").unwrap(), dir.join("x").join("bas"));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_archive_specs() {
+        assert!(is_archive_spec("https://example.org/prog.ssd#PROG"));
+        assert!(is_archive_spec("GAMES.SSD"));
+        assert!(is_archive_spec("tape.uef"));
+        assert!(!is_archive_spec("myprog"));
+        assert!(!is_archive_spec("myprog.bbas"));
+    }
+
+    #[cfg(not(feature = "remote"))]
+    #[test]
+    fn test_remote_needs_feature() {
+        let error = FileSystem::new()
+            .read_program("https://example.org/x.ssd")
+            .unwrap_err();
+        assert!(error.contains("'remote' feature"), "{}", error);
+    }
+
+    #[cfg(not(feature = "disc-images"))]
+    #[test]
+    fn test_disc_images_need_feature() {
+        let error = read_disc_image(&[0; 512], false).unwrap_err();
+        assert!(error.contains("'disc-images' feature"), "{}", error);
+    }
+
+    #[cfg(feature = "remote")]
+    #[test]
+    fn test_read_zip() {
+        use std::io::Write;
+
+        let mut buffer = std::io::Cursor::new(Vec::new());
+        let mut writer = zip::ZipWriter::new(&mut buffer);
+        writer
+            .start_file("readme.txt", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(b"not a program").unwrap();
+        writer
+            .start_file("prog.bbas", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(b"10 PRINT 1\n20 END\n").unwrap();
+        writer.finish().unwrap();
+
+        let lines = program_from("archive.zip", buffer.get_ref(), None).unwrap();
+        assert_eq!(lines, vec!["10 PRINT 1", "20 END"]);
+        let lines = program_from("archive.zip", buffer.get_ref(), Some("prog")).unwrap();
+        assert_eq!(lines.len(), 2);
+    }
+}
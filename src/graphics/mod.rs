@@ -1,6 +1,19 @@
 //! Graphics system for BBC BASIC
 //!
-//! Handles display modes and graphics operations.
+//! Handles display modes and graphics operations. The executor draws
+//! through the [`Canvas`] trait: with the `graphics` feature that is the
+//! [`GraphicsSystem`] pixel canvas, and without it a [`GraphicsCursor`] that
+//! only follows the graphics cursor, for builds that have no screen.
+//!
+//! Like the Master's shadow screen, the canvas can be double buffered
+//! (*SHADOW 1): drawing then goes to a buffer off the screen, and the
+//! picture on show only changes when the program waits for the next frame
+//! (WAIT or *FX 19) and the buffer is flipped onto it. POINT, the graphics
+//! window and saved pictures all read the picture on show.
+//!
+//! Characters drawn at the graphics cursor (VDU 5) are drawn in the Beeb's
+//! own font, and also recorded, so a renderer using the terminal's font
+//! (`Font::System`) can show them as text.
 
 use std::fmt;
 
@@ -16,11 +29,260 @@ struct Point {
     y: i32,
 }
 
+/// The graphics operations the executor performs, in BBC graphics units
+///
+/// Drawing operations leave the graphics cursor where BBC BASIC does: at
+/// the end of a line, the centre of a circle or ellipse, or the second
+/// corner of a rectangle.
+pub trait Canvas: fmt::Debug + Send {
+    /// Clear the graphics canvas to the background colour (CLG)
+    fn clear(&mut self);
+
+    /// Set graphics color mode (GCOL)
+    fn set_color(&mut self, mode: u8, color: u8);
+
+    /// Set graphics origin (VDU 29)
+    fn set_origin(&mut self, x: i32, y: i32);
+
+    /// Get pixel state at given coordinates (None off the canvas, or with
+    /// no canvas at all)
+    fn get_pixel(&self, x: i32, y: i32) -> Option<bool>;
+
+    /// Move graphics cursor without drawing (MOVE or PLOT 4)
+    fn move_to(&mut self, x: i32, y: i32);
+
+    /// Move graphics cursor relative to current position
+    fn move_by(&mut self, dx: i32, dy: i32);
+
+    /// Draw a line from current position to target (DRAW or PLOT 5)
+    fn draw_line_to(&mut self, x: i32, y: i32);
+
+    /// Plot a point with specified plot mode
+    fn plot(&mut self, mode: u8, x: i32, y: i32);
+
+    /// Draw a circle outline (CIRCLE)
+    fn draw_circle(&mut self, center_x: i32, center_y: i32, radius: i32);
+
+    /// Draw an ellipse outline (ELLIPSE)
+    fn draw_ellipse(&mut self, center_x: i32, center_y: i32, rx: i32, ry: i32);
+
+    /// Draw a filled circle (CIRCLE FILL)
+    fn fill_circle(&mut self, center_x: i32, center_y: i32, radius: i32);
+
+    /// Draw a filled ellipse (ELLIPSE FILL)
+    fn fill_ellipse(&mut self, center_x: i32, center_y: i32, rx: i32, ry: i32);
+
+    /// Draw a rectangle between two corners (RECTANGLE)
+    fn draw_rectangle(&mut self, x1: i32, y1: i32, x2: i32, y2: i32, filled: bool);
+
+    /// Copy the block between two corners so that its bottom-left corner is
+    /// at `to` (RECTANGLE ... TO), clearing the source to the background
+    /// first if it is being moved (RECTANGLE FILL ... TO)
+    ///
+    /// Pixels are copied as they are, whatever the GCOL mode.
+    fn copy_rectangle(&mut self, x1: i32, y1: i32, x2: i32, y2: i32, to: (i32, i32), moved: bool);
+
+    /// Draw a character shape with its top-left corner at the graphics
+    /// cursor, in a cell of the given size, and move the cursor on to the
+    /// next cell (VDU 5 text)
+    ///
+    /// Only the shape's set pixels are plotted, so what is behind shows
+    /// through.
+    fn draw_glyph(&mut self, shape: &[u8; 8], width: i32, height: i32);
+
+    /// Draw a character of the system font as [`Canvas::draw_glyph`] does
+    fn draw_char(&mut self, code: u8, width: i32, height: i32) {
+        self.draw_glyph(&crate::font::glyph(code), width, height);
+    }
+
+    /// Flood fill starting from a point (FILL)
+    ///
+    /// As on the BBC, the fill spreads through background-coloured pixels
+    /// and stops at anything else, painting in the graphics foreground
+    /// colour. Nothing happens if the start point is not background, or if
+    /// the foreground is the background colour.
+    fn flood_fill(&mut self, start_x: i32, start_y: i32);
+
+    /// Get canvas width in pixels
+    fn width(&self) -> usize;
+
+    /// Get canvas height in pixels
+    fn height(&self) -> usize;
+
+    /// Get current graphics cursor position
+    fn get_position(&self) -> (i32, i32);
+
+    /// Render the canvas to a string (ASCII art representation)
+    fn render(&self) -> String;
+
+    /// Draw off the screen (true), showing the picture only when it is
+    /// flipped, or straight onto the screen (false)
+    fn set_double_buffered(&mut self, on: bool);
+
+    /// Whether drawing goes off the screen until the next flip
+    fn is_double_buffered(&self) -> bool;
+
+    /// Show what has been drawn off the screen (nothing happens unless
+    /// double buffered)
+    fn flip(&mut self);
+}
+
+/// The canvas the executor draws on
+#[cfg(feature = "graphics")]
+pub type Graphics = GraphicsSystem;
+/// The canvas the executor draws on
+#[cfg(not(feature = "graphics"))]
+pub type Graphics = GraphicsCursor;
+
+/// A canvas with no pixels, which only follows the graphics cursor
+///
+/// Relative plotting and VDU 5 text positions work as usual; POINT finds
+/// nothing, and the canvas renders as an empty string.
+#[derive(Debug, Clone)]
+pub struct GraphicsCursor {
+    width: usize,
+    height: usize,
+    current_pos: Point,
+}
+
+impl GraphicsCursor {
+    /// Create a cursor for a canvas of the default dimensions
+    pub fn new() -> Self {
+        Self {
+            width: DEFAULT_WIDTH,
+            height: DEFAULT_HEIGHT,
+            current_pos: Point { x: 0, y: 0 },
+        }
+    }
+}
+
+impl Default for GraphicsCursor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Canvas for GraphicsCursor {
+    fn clear(&mut self) {}
+
+    fn set_color(&mut self, _mode: u8, _color: u8) {}
+
+    fn set_origin(&mut self, _x: i32, _y: i32) {}
+
+    fn get_pixel(&self, _x: i32, _y: i32) -> Option<bool> {
+        None
+    }
+
+    fn move_to(&mut self, x: i32, y: i32) {
+        self.current_pos = Point { x, y };
+    }
+
+    fn move_by(&mut self, dx: i32, dy: i32) {
+        self.current_pos.x += dx;
+        self.current_pos.y += dy;
+    }
+
+    fn draw_line_to(&mut self, x: i32, y: i32) {
+        self.move_to(x, y);
+    }
+
+    fn plot(&mut self, mode: u8, x: i32, y: i32) {
+        if mode & 0x04 != 0 {
+            self.move_to(x, y);
+        } else {
+            self.move_by(x, y);
+        }
+    }
+
+    fn draw_circle(&mut self, center_x: i32, center_y: i32, _radius: i32) {
+        self.move_to(center_x, center_y);
+    }
+
+    fn draw_ellipse(&mut self, center_x: i32, center_y: i32, _rx: i32, _ry: i32) {
+        self.move_to(center_x, center_y);
+    }
+
+    fn fill_circle(&mut self, center_x: i32, center_y: i32, _radius: i32) {
+        self.move_to(center_x, center_y);
+    }
+
+    fn fill_ellipse(&mut self, center_x: i32, center_y: i32, _rx: i32, _ry: i32) {
+        self.move_to(center_x, center_y);
+    }
+
+    fn draw_rectangle(&mut self, _x1: i32, _y1: i32, x2: i32, y2: i32, _filled: bool) {
+        self.move_to(x2, y2);
+    }
+
+    fn copy_rectangle(
+        &mut self,
+        _x1: i32,
+        _y1: i32,
+        _x2: i32,
+        _y2: i32,
+        _to: (i32, i32),
+        _moved: bool,
+    ) {
+    }
+
+    fn draw_glyph(&mut self, _shape: &[u8; 8], width: i32, _height: i32) {
+        self.move_by(width, 0);
+    }
+
+    fn flood_fill(&mut self, _start_x: i32, _start_y: i32) {}
+
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn get_position(&self) -> (i32, i32) {
+        (self.current_pos.x, self.current_pos.y)
+    }
+
+    fn render(&self) -> String {
+        String::new()
+    }
+
+    fn set_double_buffered(&mut self, _on: bool) {}
+
+    fn is_double_buffered(&self) -> bool {
+        false
+    }
+
+    fn flip(&mut self) {}
+}
+
+/// A character drawn on the canvas (VDU 5), for renderers that show text
+/// in a font of their own
+#[cfg(feature = "graphics")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DrawnChar {
+    /// Canvas column and row of its top-left corner
+    left: usize,
+    top: usize,
+    /// Size of its cell in pixels
+    width: usize,
+    height: usize,
+    code: u8,
+}
+
 /// Graphics canvas for drawing operations
+#[cfg(feature = "graphics")]
 #[derive(Debug, Clone)]
 pub struct GraphicsSystem {
-    /// Canvas buffer (true = pixel set, false = pixel clear)
+    /// Canvas buffer drawn on (true = pixel set, false = pixel clear)
     canvas: Vec<Vec<bool>>,
+    /// The picture on show while double buffered, which drawing leaves
+    /// alone until the next flip (None = the canvas is on show)
+    shown: Option<Vec<Vec<bool>>>,
+    /// Characters drawn on the canvas since it was last cleared
+    chars: Vec<DrawnChar>,
+    /// The characters on show while double buffered
+    shown_chars: Vec<DrawnChar>,
     /// Canvas width in pixels
     width: usize,
     /// Canvas height in pixels
@@ -39,6 +301,7 @@ pub struct GraphicsSystem {
     triangle_corner: Option<Point>,
 }
 
+#[cfg(feature = "graphics")]
 impl GraphicsSystem {
     /// Create a new graphics system with default dimensions
     pub fn new() -> Self {
@@ -49,6 +312,9 @@ impl GraphicsSystem {
     pub fn with_dimensions(width: usize, height: usize) -> Self {
         Self {
             canvas: vec![vec![false; width]; height],
+            shown: None,
+            chars: Vec::new(),
+            shown_chars: Vec::new(),
             width,
             height,
             current_pos: Point { x: 0, y: 0 },
@@ -60,24 +326,6 @@ impl GraphicsSystem {
         }
     }
 
-    /// Clear the graphics canvas
-    pub fn clear(&mut self) {
-        for row in &mut self.canvas {
-            row.fill(false);
-        }
-    }
-
-    /// Set graphics color mode (GCOL)
-    pub fn set_color(&mut self, mode: u8, color: u8) {
-        self.color_mode = mode;
-        self.foreground_color = color;
-    }
-
-    /// Set graphics origin (VDU 29)
-    pub fn set_origin(&mut self, x: i32, y: i32) {
-        self.origin = Point { x, y };
-    }
-
     /// Convert BBC BASIC coordinates to canvas coordinates
     fn to_canvas_coords(&self, x: i32, y: i32) -> Option<(usize, usize)> {
         // BBC BASIC uses bottom-left origin, canvas uses top-left
@@ -96,6 +344,28 @@ impl GraphicsSystem {
         }
     }
 
+    /// The picture on show: the canvas, or while double buffered the
+    /// picture as it was at the last flip
+    fn displayed(&self) -> &Vec<Vec<bool>> {
+        self.shown.as_ref().unwrap_or(&self.canvas)
+    }
+
+    /// The characters on show
+    fn displayed_chars(&self) -> &[DrawnChar] {
+        if self.shown.is_some() {
+            &self.shown_chars
+        } else {
+            &self.chars
+        }
+    }
+
+    /// Get the state of a pixel drawn on the canvas, whether or not it is
+    /// on show yet
+    fn drawn_pixel(&self, x: i32, y: i32) -> Option<bool> {
+        self.to_canvas_coords(x, y)
+            .map(|(cx, cy)| self.canvas[cy][cx])
+    }
+
     /// Set a pixel at the given coordinates
     fn set_pixel(&mut self, x: i32, y: i32) {
         if let Some((cx, cy)) = self.to_canvas_coords(x, y) {
@@ -110,29 +380,6 @@ impl GraphicsSystem {
         }
     }
 
-    /// Get pixel state at given coordinates
-    pub fn get_pixel(&self, x: i32, y: i32) -> Option<bool> {
-        self.to_canvas_coords(x, y)
-            .map(|(cx, cy)| self.canvas[cy][cx])
-    }
-
-    /// Move graphics cursor without drawing (MOVE or PLOT 4)
-    pub fn move_to(&mut self, x: i32, y: i32) {
-        self.current_pos = Point { x, y };
-    }
-
-    /// Move graphics cursor relative to current position
-    pub fn move_by(&mut self, dx: i32, dy: i32) {
-        self.current_pos.x += dx;
-        self.current_pos.y += dy;
-    }
-
-    /// Draw a line from current position to target (DRAW or PLOT 5)
-    pub fn draw_line_to(&mut self, x: i32, y: i32) {
-        self.draw_line(self.current_pos.x, self.current_pos.y, x, y);
-        self.current_pos = Point { x, y };
-    }
-
     /// Draw a line relative to current position
     pub fn draw_line_by(&mut self, dx: i32, dy: i32) {
         let target_x = self.current_pos.x + dx;
@@ -169,50 +416,330 @@ impl GraphicsSystem {
         }
     }
 
-    /// Plot a point with specified plot mode
-    pub fn plot(&mut self, mode: u8, x: i32, y: i32) {
-        // BBC BASIC plot modes:
-        // 0-3: Move cursor (relative to different points)
-        // 4-7: Line drawing
-        // 64-127: Point plotting
-        // 128-191: Triangles
-        // 192-255: Circles/arcs
+    /// Run a drawing operation using the colour selected by PLOT action bits
+    fn with_plot_action(&mut self, action: u8, draw: impl FnOnce(&mut Self)) {
+        let (mode, color) = (self.color_mode, self.foreground_color);
+        match action {
+            0 => return,              // Move only
+            1 => {}                   // Foreground
+            2 => self.color_mode = 4, // Inverse
+            _ => {
+                // Background
+                self.color_mode = 0;
+                self.foreground_color = self.background_color;
+            }
+        }
+        draw(self);
+        self.color_mode = mode;
+        self.foreground_color = color;
+    }
 
-        let relative = (mode & 0x04) != 0; // Bit 2 determines relative mode
-        let (target_x, target_y) = if relative {
-            (self.current_pos.x + x, self.current_pos.y + y)
+    /// Helper to plot 4 quadrants of ellipse
+    fn plot_ellipse_points(&mut self, cx: i32, cy: i32, x: i32, y: i32) {
+        self.set_pixel(cx + x, cy + y);
+        self.set_pixel(cx - x, cy + y);
+        self.set_pixel(cx + x, cy - y);
+        self.set_pixel(cx - x, cy - y);
+    }
+
+    /// Draw a triangle
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_triangle(
+        &mut self,
+        x1: i32,
+        y1: i32,
+        x2: i32,
+        y2: i32,
+        x3: i32,
+        y3: i32,
+        filled: bool,
+    ) {
+        if filled {
+            // Filled triangle using scanline algorithm
+            self.fill_triangle(x1, y1, x2, y2, x3, y3);
         } else {
-            (x, y)
+            // Outline triangle - draw three lines
+            self.draw_line(x1, y1, x2, y2);
+            self.draw_line(x2, y2, x3, y3);
+            self.draw_line(x3, y3, x1, y1);
+        }
+
+        // Update current position to last vertex
+        self.current_pos = Point { x: x3, y: y3 };
+    }
+
+    /// Fill a triangle using scanline algorithm
+    fn fill_triangle(&mut self, x1: i32, y1: i32, x2: i32, y2: i32, x3: i32, y3: i32) {
+        // Sort vertices by y-coordinate
+        let mut verts = [(x1, y1), (x2, y2), (x3, y3)];
+        verts.sort_by_key(|v| v.1);
+        let (x1, y1) = verts[0];
+        let (x2, y2) = verts[1];
+        let (x3, y3) = verts[2];
+
+        // Fill scanlines
+        for y in y1..=y3 {
+            let mut x_left = x1;
+            let mut x_right = x1;
+
+            // Calculate x intersections with triangle edges
+            if y <= y2 {
+                // Upper part of triangle
+                if y2 != y1 {
+                    x_left = x1 + (x2 - x1) * (y - y1) / (y2 - y1);
+                }
+                if y3 != y1 {
+                    x_right = x1 + (x3 - x1) * (y - y1) / (y3 - y1);
+                }
+            } else {
+                // Lower part of triangle
+                if y3 != y2 {
+                    x_left = x2 + (x3 - x2) * (y - y2) / (y3 - y2);
+                }
+                if y3 != y1 {
+                    x_right = x1 + (x3 - x1) * (y - y1) / (y3 - y1);
+                }
+            }
+
+            // Ensure x_left <= x_right
+            if x_left > x_right {
+                std::mem::swap(&mut x_left, &mut x_right);
+            }
+
+            // Draw horizontal line
+            for x in x_left..=x_right {
+                self.set_pixel(x, y);
+            }
+        }
+    }
+
+    /// Compute a checksum of the framebuffer (64-bit FNV-1a)
+    ///
+    /// Stable across runs and platforms, so tests can compare a drawing
+    /// against a known-good value without storing the whole image.
+    pub fn checksum(&self) -> u64 {
+        const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+        const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+        let mut hash = FNV_OFFSET;
+        let mut feed = |byte: u8| {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
         };
 
-        match mode & 0xF8 {
-            // 0-7: Move/line operations
-            0x00 => {
-                // Move operations (modes 0-3)
-                if (mode & 0x04) == 0 {
-                    self.move_to(x, y);
-                } else {
-                    self.move_by(x, y);
+        for byte in (self.width as u32).to_le_bytes() {
+            feed(byte);
+        }
+        for byte in (self.height as u32).to_le_bytes() {
+            feed(byte);
+        }
+        for byte in self.packed_rows() {
+            feed(byte);
+        }
+        hash
+    }
+
+    /// Pack the canvas into bytes, one bit per pixel, MSB first, rows top to bottom
+    fn packed_rows(&self) -> Vec<u8> {
+        let row_bytes = self.width.div_ceil(8);
+        let mut bytes = vec![0u8; row_bytes * self.height];
+        for (y, row) in self.displayed().iter().enumerate() {
+            for (x, &pixel) in row.iter().enumerate() {
+                if pixel {
+                    bytes[y * row_bytes + x / 8] |= 0x80 >> (x % 8);
                 }
             }
-            // 4-7: Draw line
-            _ if mode >= 4 && mode <= 7 => {
-                if relative {
-                    self.draw_line_by(x, y);
-                } else {
-                    self.draw_line_to(target_x, target_y);
+        }
+        bytes
+    }
+
+    /// Encode the canvas as a binary PPM (P6) image
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let header = format!("P6\n{} {}\n255\n", self.width, self.height);
+        let mut data = Vec::with_capacity(header.len() + self.width * self.height * 3);
+        data.extend_from_slice(header.as_bytes());
+        for row in self.displayed() {
+            for &pixel in row {
+                let level = if pixel { 255 } else { 0 };
+                data.extend_from_slice(&[level, level, level]);
+            }
+        }
+        data
+    }
+
+    /// Write the canvas to a PPM file (for inspecting golden images)
+    pub fn save_ppm<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        std::fs::write(path, self.to_ppm())
+    }
+
+    /// Render the canvas with scaling (for terminal display)
+    /// scale_x: how many pixels per character horizontally
+    /// scale_y: how many pixels per character vertically
+    pub fn render_scaled(&self, scale_x: usize, scale_y: usize) -> String {
+        self.render_cells(scale_x, scale_y, false)
+    }
+
+    /// Render the canvas with scaling as [`GraphicsSystem::render_scaled`]
+    /// does, but with each character drawn by VDU 5 shown as itself, in the
+    /// terminal's font, in the cell its top-left corner is in
+    pub fn render_scaled_with_text(&self, scale_x: usize, scale_y: usize) -> String {
+        self.render_cells(scale_x, scale_y, true)
+    }
+
+    /// Render the canvas in characters, each showing how many pixels of its
+    /// block are set, or the characters drawn on it if `text` is set
+    fn render_cells(&self, scale_x: usize, scale_y: usize, text: bool) -> String {
+        let canvas = self.displayed();
+        let chars_wide = self.width / scale_x;
+        let chars_high = self.height / scale_y;
+
+        let mut cells: Vec<Vec<char>> = (0..chars_high)
+            .map(|row_block| {
+                (0..chars_wide)
+                    .map(|col_block| {
+                        // Sample the block and count set pixels
+                        let mut pixel_count = 0usize;
+                        let mut total_pixels = 0;
+
+                        for dy in 0..scale_y {
+                            let y = row_block * scale_y + dy;
+                            if y >= self.height {
+                                break;
+                            }
+                            for dx in 0..scale_x {
+                                let x = col_block * scale_x + dx;
+                                if x >= self.width {
+                                    break;
+                                }
+                                if canvas[y][x] {
+                                    pixel_count += 1;
+                                }
+                                total_pixels += 1;
+                            }
+                        }
+
+                        // Choose character based on pixel density, rounding up
+                        // so that a line one pixel wide still shows
+                        let density = if total_pixels > 0 {
+                            (pixel_count * 4).div_ceil(total_pixels)
+                        } else {
+                            0
+                        };
+
+                        match density {
+                            0 => ' ',
+                            1 => '░',
+                            2 => '▒',
+                            3 => '▓',
+                            _ => '█',
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+
+        if text {
+            for drawn in self.displayed_chars() {
+                // The blocks the character covers are cleared for it
+                let columns = drawn.left / scale_x..(drawn.left + drawn.width).div_ceil(scale_x);
+                let rows = drawn.top / scale_y..(drawn.top + drawn.height).div_ceil(scale_y);
+                for row in cells.iter_mut().take(rows.end).skip(rows.start) {
+                    row.iter_mut()
+                        .take(columns.end)
+                        .skip(columns.start)
+                        .for_each(|cell| *cell = ' ');
+                }
+                if let Some(cell) = cells
+                    .get_mut(rows.start)
+                    .and_then(|row| row.get_mut(columns.start))
+                {
+                    *cell = crate::charset::display_char(drawn.code);
                 }
             }
+        }
+
+        let border = "-".repeat(chars_wide);
+        let mut output = format!("+{}+\n", border);
+        for row in cells {
+            output.push('|');
+            output.extend(row);
+            output.push_str("|\n");
+        }
+        output.push_str(&format!("+{}+", border));
+        output
+    }
+}
+
+#[cfg(feature = "graphics")]
+impl Canvas for GraphicsSystem {
+    fn clear(&mut self) {
+        let background = self.background_color > 0;
+        for row in &mut self.canvas {
+            row.fill(background);
+        }
+        self.chars.clear();
+    }
+
+    fn set_color(&mut self, mode: u8, color: u8) {
+        self.color_mode = mode;
+        self.foreground_color = color;
+    }
+
+    fn set_origin(&mut self, x: i32, y: i32) {
+        self.origin = Point { x, y };
+    }
+
+    fn get_pixel(&self, x: i32, y: i32) -> Option<bool> {
+        self.to_canvas_coords(x, y)
+            .map(|(cx, cy)| self.displayed()[cy][cx])
+    }
+
+    fn move_to(&mut self, x: i32, y: i32) {
+        self.current_pos = Point { x, y };
+    }
+
+    fn move_by(&mut self, dx: i32, dy: i32) {
+        self.current_pos.x += dx;
+        self.current_pos.y += dy;
+    }
+
+    fn draw_line_to(&mut self, x: i32, y: i32) {
+        self.draw_line(self.current_pos.x, self.current_pos.y, x, y);
+        self.current_pos = Point { x, y };
+    }
+
+    fn plot(&mut self, mode: u8, x: i32, y: i32) {
+        // BBC BASIC plot modes:
+        // 0-7: Move/line drawing
+        // 64-71: Point plotting
+        // 128-191: Triangles
+        //
+        // Within each group of eight, bit 2 selects absolute (set) or
+        // relative (clear) coordinates and bits 0-1 select the action:
+        // 0 = move only, 1 = foreground, 2 = inverse, 3 = background
+
+        let absolute = (mode & 0x04) != 0;
+        let (target_x, target_y) = if absolute {
+            (x, y)
+        } else {
+            (self.current_pos.x + x, self.current_pos.y + y)
+        };
+        let action = mode & 0x03;
+
+        match mode {
+            // 0-7: Move or draw line
+            0..=7 => {
+                let (from_x, from_y) = (self.current_pos.x, self.current_pos.y);
+                self.with_plot_action(action, |gfx| {
+                    gfx.draw_line(from_x, from_y, target_x, target_y)
+                });
+            }
             // 64-71: Plot point
-            _ if mode >= 64 && mode <= 71 => {
-                self.set_pixel(target_x, target_y);
-                self.current_pos = Point {
-                    x: target_x,
-                    y: target_y,
-                };
+            64..=71 => {
+                self.with_plot_action(action, |gfx| gfx.set_pixel(target_x, target_y));
             }
             // 128-191: Filled triangle
-            _ if mode >= 128 && mode <= 191 => {
+            128..=191 => {
                 // Triangle modes work in pairs:
                 // - First PLOT stores current position as triangle corner
                 // - Second PLOT draws triangle from corner -> current -> target
@@ -226,24 +753,19 @@ impl GraphicsSystem {
                     // First PLOT: store current position as triangle corner
                     self.triangle_corner = Some(self.current_pos);
                 }
-                // Update current position to target
-                self.current_pos = Point {
-                    x: target_x,
-                    y: target_y,
-                };
             }
             // Default: just move cursor
-            _ => {
-                self.current_pos = Point {
-                    x: target_x,
-                    y: target_y,
-                };
-            }
+            _ => {}
         }
+
+        // Every PLOT leaves the graphics cursor at the target point
+        self.current_pos = Point {
+            x: target_x,
+            y: target_y,
+        };
     }
 
-    /// Draw a circle using midpoint circle algorithm
-    pub fn draw_circle(&mut self, center_x: i32, center_y: i32, radius: i32) {
+    fn draw_circle(&mut self, center_x: i32, center_y: i32, radius: i32) {
         if radius <= 0 {
             return;
         }
@@ -279,8 +801,7 @@ impl GraphicsSystem {
         };
     }
 
-    /// Draw an ellipse using midpoint ellipse algorithm
-    pub fn draw_ellipse(&mut self, center_x: i32, center_y: i32, rx: i32, ry: i32) {
+    fn draw_ellipse(&mut self, center_x: i32, center_y: i32, rx: i32, ry: i32) {
         if rx <= 0 || ry <= 0 {
             return;
         }
@@ -341,16 +862,31 @@ impl GraphicsSystem {
         };
     }
 
-    /// Helper to plot 4 quadrants of ellipse
-    fn plot_ellipse_points(&mut self, cx: i32, cy: i32, x: i32, y: i32) {
-        self.set_pixel(cx + x, cy + y);
-        self.set_pixel(cx - x, cy + y);
-        self.set_pixel(cx + x, cy - y);
-        self.set_pixel(cx - x, cy - y);
+    fn fill_circle(&mut self, center_x: i32, center_y: i32, radius: i32) {
+        self.fill_ellipse(center_x, center_y, radius, radius);
     }
 
-    /// Draw a filled rectangle
-    pub fn draw_rectangle(&mut self, x1: i32, y1: i32, x2: i32, y2: i32, filled: bool) {
+    fn fill_ellipse(&mut self, center_x: i32, center_y: i32, rx: i32, ry: i32) {
+        if rx <= 0 || ry <= 0 {
+            return;
+        }
+
+        for dy in -ry..=ry {
+            let fraction = 1.0 - (dy as f64 / ry as f64).powi(2);
+            let half = (rx as f64 * fraction.sqrt()).round() as i32;
+            for x in center_x - half..=center_x + half {
+                self.set_pixel(x, center_y + dy);
+            }
+        }
+
+        // Update current position to ellipse center
+        self.current_pos = Point {
+            x: center_x,
+            y: center_y,
+        };
+    }
+
+    fn draw_rectangle(&mut self, x1: i32, y1: i32, x2: i32, y2: i32, filled: bool) {
         let min_x = x1.min(x2);
         let max_x = x1.max(x2);
         let min_y = y1.min(y2);
@@ -379,191 +915,179 @@ impl GraphicsSystem {
         self.current_pos = Point { x: x2, y: y2 };
     }
 
-    /// Draw a triangle
-    pub fn draw_triangle(&mut self, x1: i32, y1: i32, x2: i32, y2: i32, x3: i32, y3: i32, filled: bool) {
-        if filled {
-            // Filled triangle using scanline algorithm
-            self.fill_triangle(x1, y1, x2, y2, x3, y3);
-        } else {
-            // Outline triangle - draw three lines
-            self.draw_line(x1, y1, x2, y2);
-            self.draw_line(x2, y2, x3, y3);
-            self.draw_line(x3, y3, x1, y1);
-        }
+    fn copy_rectangle(&mut self, x1: i32, y1: i32, x2: i32, y2: i32, to: (i32, i32), moved: bool) {
+        let (min_x, max_x) = (x1.min(x2), x1.max(x2));
+        let (min_y, max_y) = (y1.min(y2), y1.max(y2));
 
-        // Update current position to last vertex
-        self.current_pos = Point { x: x3, y: y3 };
+        let mut block = Vec::new();
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                block.push((x - min_x, y - min_y, self.drawn_pixel(x, y)));
+            }
+        }
+        let background = self.background_color > 0;
+        if moved {
+            for y in min_y..=max_y {
+                for x in min_x..=max_x {
+                    if let Some((cx, cy)) = self.to_canvas_coords(x, y) {
+                        self.canvas[cy][cx] = background;
+                    }
+                }
+            }
+        }
+        for (dx, dy, pixel) in block {
+            // Pixels from off the screen are left alone
+            let Some(pixel) = pixel else {
+                continue;
+            };
+            if let Some((cx, cy)) = self.to_canvas_coords(to.0 + dx, to.1 + dy) {
+                self.canvas[cy][cx] = pixel;
+            }
+        }
     }
 
-    /// Fill a triangle using scanline algorithm
-    fn fill_triangle(&mut self, x1: i32, y1: i32, x2: i32, y2: i32, x3: i32, y3: i32) {
-        // Sort vertices by y-coordinate
-        let mut verts = [(x1, y1), (x2, y2), (x3, y3)];
-        verts.sort_by_key(|v| v.1);
-        let (x1, y1) = verts[0];
-        let (x2, y2) = verts[1];
-        let (x3, y3) = verts[2];
-
-        // Fill scanlines
-        for y in y1..=y3 {
-            let mut x_left = x1;
-            let mut x_right = x1;
-
-            // Calculate x intersections with triangle edges
-            if y <= y2 {
-                // Upper part of triangle
-                if y2 != y1 {
-                    x_left = x1 + (x2 - x1) * (y - y1) / (y2 - y1);
-                }
-                if y3 != y1 {
-                    x_right = x1 + (x3 - x1) * (y - y1) / (y3 - y1);
-                }
-            } else {
-                // Lower part of triangle
-                if y3 != y2 {
-                    x_left = x2 + (x3 - x2) * (y - y2) / (y3 - y2);
+    fn draw_glyph(&mut self, shape: &[u8; 8], width: i32, height: i32) {
+        let Point { x: left, y: top } = self.current_pos;
+        let (dot_width, dot_height) = ((width / 8).max(1), (height / 8).max(1));
+        for (row, bits) in shape.iter().enumerate() {
+            for column in 0..8 {
+                if bits & (0x80 >> column) == 0 {
+                    continue;
                 }
-                if y3 != y1 {
-                    x_right = x1 + (x3 - x1) * (y - y1) / (y3 - y1);
+                let x = left + column * dot_width;
+                let y = top - row as i32 * dot_height;
+                for dy in 0..dot_height {
+                    for dx in 0..dot_width {
+                        self.set_pixel(x + dx, y - dy);
+                    }
                 }
             }
+        }
+        self.current_pos.x += width;
+    }
 
-            // Ensure x_left <= x_right
-            if x_left > x_right {
-                std::mem::swap(&mut x_left, &mut x_right);
-            }
-
-            // Draw horizontal line
-            for x in x_left..=x_right {
-                self.set_pixel(x, y);
+    fn draw_char(&mut self, code: u8, width: i32, height: i32) {
+        let shape = crate::font::glyph(code);
+        // Blank characters (spaces) leave what is behind them
+        if shape.iter().any(|&bits| bits != 0) {
+            let corner = self.to_canvas_coords(self.current_pos.x, self.current_pos.y);
+            if let Some((left, top)) = corner {
+                // A character drawn over another in the same place replaces it
+                self.chars
+                    .retain(|drawn| (drawn.left, drawn.top) != (left, top));
+                self.chars.push(DrawnChar {
+                    left,
+                    top,
+                    width: width.max(1) as usize,
+                    height: height.max(1) as usize,
+                    code,
+                });
             }
         }
+        self.draw_glyph(&shape, width, height);
     }
 
-    /// Flood fill starting from a point
-    pub fn flood_fill(&mut self, start_x: i32, start_y: i32) {
-        // Get the target color to replace
-        let target_color = match self.get_pixel(start_x, start_y) {
-            Some(color) => color,
-            None => return, // Outside bounds
-        };
-
+    fn flood_fill(&mut self, start_x: i32, start_y: i32) {
+        // Whole horizontal spans are filled at a time, with a stack of spans
+        // still to visit rather than recursion, so any area can be filled
+        let background = self.background_color > 0;
         let fill_color = self.foreground_color > 0;
-
-        // Don't fill if already the target color
-        if target_color == fill_color {
+        if fill_color == background {
             return;
         }
+        let is_background = |gfx: &Self, x: i32, y: i32| gfx.drawn_pixel(x, y) == Some(background);
 
-        // Stack-based flood fill (avoids recursion stack overflow)
-        let mut stack = Vec::new();
-        stack.push((start_x, start_y));
-
+        let mut stack = vec![(start_x, start_y)];
         while let Some((x, y)) = stack.pop() {
-            // Check if pixel is valid and matches target color
-            if let Some(color) = self.get_pixel(x, y) {
-                if color == target_color {
-                    self.set_pixel(x, y);
+            if !is_background(self, x, y) {
+                continue;
+            }
 
-                    // Add adjacent pixels to stack
-                    stack.push((x + 1, y));
-                    stack.push((x - 1, y));
-                    stack.push((x, y + 1));
-                    stack.push((x, y - 1));
+            // Widen to the whole span of background on this row
+            let mut left = x;
+            while is_background(self, left - 1, y) {
+                left -= 1;
+            }
+            let mut right = x;
+            while is_background(self, right + 1, y) {
+                right += 1;
+            }
+            for span_x in left..=right {
+                if let Some((cx, cy)) = self.to_canvas_coords(span_x, y) {
+                    self.canvas[cy][cx] = fill_color;
+                }
+            }
+
+            // Queue the start of each background run above and below
+            for next_y in [y - 1, y + 1] {
+                let mut in_run = false;
+                for span_x in left..=right {
+                    let open = is_background(self, span_x, next_y);
+                    if open && !in_run {
+                        stack.push((span_x, next_y));
+                    }
+                    in_run = open;
                 }
             }
         }
     }
 
-    /// Get current graphics cursor position
-    pub fn get_position(&self) -> (i32, i32) {
-        (self.current_pos.x, self.current_pos.y)
+    fn width(&self) -> usize {
+        self.width
     }
 
-    /// Render the canvas to a string (ASCII art representation)
-    pub fn render(&self) -> String {
-        self.render_scaled(4, 8)
+    fn height(&self) -> usize {
+        self.height
     }
 
-    /// Render the canvas with scaling (for terminal display)
-    /// scale_x: how many pixels per character horizontally
-    /// scale_y: how many pixels per character vertically
-    pub fn render_scaled(&self, scale_x: usize, scale_y: usize) -> String {
-        let mut output = String::new();
-        let chars_wide = self.width / scale_x;
-        let chars_high = self.height / scale_y;
-
-        // Top border
-        output.push('+');
-        output.push_str(&"-".repeat(chars_wide));
-        output.push_str("+\n");
+    fn get_position(&self) -> (i32, i32) {
+        (self.current_pos.x, self.current_pos.y)
+    }
 
-        // Canvas content
-        for row_block in 0..chars_high {
-            output.push('|');
-            for col_block in 0..chars_wide {
-                // Sample the block and count set pixels
-                let mut pixel_count = 0;
-                let mut total_pixels = 0;
-
-                for dy in 0..scale_y {
-                    let y = row_block * scale_y + dy;
-                    if y >= self.height {
-                        break;
-                    }
-                    for dx in 0..scale_x {
-                        let x = col_block * scale_x + dx;
-                        if x >= self.width {
-                            break;
-                        }
-                        if self.canvas[y][x] {
-                            pixel_count += 1;
-                        }
-                        total_pixels += 1;
-                    }
-                }
+    fn render(&self) -> String {
+        self.render_scaled(4, 8)
+    }
 
-                // Choose character based on pixel density
-                let density = if total_pixels > 0 {
-                    pixel_count * 4 / total_pixels
-                } else {
-                    0
-                };
-
-                let ch = match density {
-                    0 => ' ',
-                    1 => '░',
-                    2 => '▒',
-                    3 => '▓',
-                    _ => '█',
-                };
-                output.push(ch);
+    fn set_double_buffered(&mut self, on: bool) {
+        // Turning it on keeps the picture on show; turning it off shows
+        // whatever has been drawn since the last flip
+        match (on, &self.shown) {
+            (true, None) => {
+                self.shown = Some(self.canvas.clone());
+                self.shown_chars.clone_from(&self.chars);
             }
-            output.push_str("|\n");
+            (false, Some(_)) => self.shown = None,
+            _ => {}
         }
+    }
 
-        // Bottom border
-        output.push('+');
-        output.push_str(&"-".repeat(chars_wide));
-        output.push('+');
+    fn is_double_buffered(&self) -> bool {
+        self.shown.is_some()
+    }
 
-        output
+    fn flip(&mut self) {
+        if let Some(shown) = &mut self.shown {
+            shown.clone_from(&self.canvas);
+            self.shown_chars.clone_from(&self.chars);
+        }
     }
 }
 
+#[cfg(feature = "graphics")]
 impl Default for GraphicsSystem {
     fn default() -> Self {
         Self::new()
     }
 }
 
+#[cfg(feature = "graphics")]
 impl fmt::Display for GraphicsSystem {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.render())
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "graphics"))]
 mod tests {
     use super::*;
 
@@ -591,6 +1115,119 @@ mod tests {
         assert!(gfx.get_pixel(20, 20).unwrap());
     }
 
+    #[test]
+    fn test_fill_ellipse_and_copy_rectangle() {
+        let mut gfx = GraphicsSystem::with_dimensions(100, 100);
+        gfx.fill_circle(20, 20, 5);
+        assert!(gfx.get_pixel(20, 20).unwrap());
+        assert!(gfx.get_pixel(24, 21).unwrap());
+        assert!(!gfx.get_pixel(25, 25).unwrap());
+
+        gfx.copy_rectangle(15, 15, 25, 25, (60, 60), false);
+        assert!(gfx.get_pixel(65, 65).unwrap());
+        assert!(gfx.get_pixel(20, 20).unwrap());
+
+        gfx.copy_rectangle(15, 15, 25, 25, (70, 10), true);
+        assert!(gfx.get_pixel(75, 15).unwrap());
+        assert!(!gfx.get_pixel(20, 20).unwrap());
+    }
+
+    #[test]
+    fn test_flood_fill_stays_inside_outline() {
+        let mut gfx = GraphicsSystem::with_dimensions(100, 100);
+        gfx.draw_rectangle(10, 10, 40, 40, false);
+        gfx.flood_fill(20, 20);
+        assert!(gfx.get_pixel(11, 11).unwrap());
+        assert!(gfx.get_pixel(39, 39).unwrap());
+        assert!(!gfx.get_pixel(9, 9).unwrap());
+        assert!(!gfx.get_pixel(60, 60).unwrap());
+
+        // A concave shape: a box split by a wall that stops short of the
+        // bottom, filled from one side, reaches the other round the wall
+        let mut gfx = GraphicsSystem::with_dimensions(100, 100);
+        gfx.draw_rectangle(10, 10, 50, 50, false);
+        gfx.move_to(30, 50);
+        gfx.draw_line_to(30, 20);
+        gfx.flood_fill(20, 40);
+        assert!(gfx.get_pixel(40, 40).unwrap());
+        assert!(gfx.get_pixel(30, 15).unwrap());
+        assert!(!gfx.get_pixel(20, 60).unwrap());
+    }
+
+    #[test]
+    fn test_flood_fill_edge_cases() {
+        // Starting on a non-background pixel fills nothing
+        let mut gfx = GraphicsSystem::with_dimensions(50, 50);
+        gfx.draw_rectangle(10, 10, 20, 20, false);
+        let before = gfx.checksum();
+        gfx.flood_fill(10, 10);
+        assert_eq!(gfx.checksum(), before);
+
+        // Filling in the background colour changes nothing
+        gfx.set_color(0, 0);
+        gfx.flood_fill(40, 40);
+        assert_eq!(gfx.checksum(), before);
+
+        // A whole full-size screen fills without overflowing the stack
+        let mut gfx = GraphicsSystem::new();
+        gfx.flood_fill(640, 512);
+        assert!(gfx.get_pixel(0, 0).unwrap());
+        assert!(gfx.get_pixel(1279, 1023).unwrap());
+    }
+
+    #[test]
+    fn test_double_buffering() {
+        let mut gfx = GraphicsSystem::with_dimensions(100, 100);
+        gfx.set_pixel(10, 10);
+        gfx.set_double_buffered(true);
+        let shown = gfx.checksum();
+
+        // Drawing stays off the screen until the flip, but fills see it
+        gfx.draw_rectangle(20, 20, 40, 40, false);
+        gfx.flood_fill(30, 30);
+        assert_eq!(gfx.get_pixel(30, 30), Some(false));
+        assert_eq!(gfx.checksum(), shown);
+        gfx.flip();
+        assert_eq!(gfx.get_pixel(30, 30), Some(true));
+        assert!(gfx.get_pixel(10, 10).unwrap());
+
+        // Clearing the buffer leaves the picture on show
+        gfx.clear();
+        assert_eq!(gfx.get_pixel(30, 30), Some(true));
+
+        // Turning it off shows what was drawn
+        gfx.set_double_buffered(false);
+        assert!(!gfx.is_double_buffered());
+        assert_eq!(gfx.get_pixel(30, 30), Some(false));
+    }
+
+    #[test]
+    fn test_render_with_text() {
+        let mut gfx = GraphicsSystem::with_dimensions(64, 64);
+        gfx.move_to(0, 63);
+        gfx.draw_char(b'A', 32, 32);
+        gfx.draw_char(b' ', 32, 32);
+        gfx.move_to(0, 31);
+        gfx.draw_line_to(63, 31);
+
+        // The glyph's pixels are drawn either way, for POINT to read
+        assert!(gfx
+            .render_scaled(16, 32)
+            .lines()
+            .nth(1)
+            .unwrap()
+            .contains('▓'));
+        let shown = gfx.render_scaled_with_text(16, 32);
+        assert_eq!(shown.lines().nth(1), Some("|A   |"));
+        assert!(shown.lines().nth(2).unwrap().contains('░'), "{}", shown);
+
+        gfx.clear();
+        assert_eq!(
+            gfx.render_scaled_with_text(16, 32).lines().nth(1),
+            Some("|    |")
+        );
+    }
+
     #[test]
     fn test_clear() {
         let mut gfx = GraphicsSystem::with_dimensions(100, 100);
@@ -600,6 +1237,32 @@ mod tests {
         assert!(!gfx.get_pixel(50, 50).unwrap());
     }
 
+    #[test]
+    fn test_checksum_tracks_pixels() {
+        let mut gfx = GraphicsSystem::with_dimensions(16, 16);
+        let blank = gfx.checksum();
+        assert_eq!(blank, GraphicsSystem::with_dimensions(16, 16).checksum());
+        assert_ne!(blank, GraphicsSystem::with_dimensions(16, 8).checksum());
+
+        gfx.set_pixel(3, 4);
+        assert_ne!(gfx.checksum(), blank);
+        gfx.clear();
+        assert_eq!(gfx.checksum(), blank);
+    }
+
+    #[test]
+    fn test_to_ppm() {
+        let mut gfx = GraphicsSystem::with_dimensions(2, 2);
+        gfx.set_pixel(0, 0); // Bottom-left, i.e. last row of the image
+        let ppm = gfx.to_ppm();
+        let header = b"P6\n2 2\n255\n";
+        assert_eq!(&ppm[..header.len()], header);
+        assert_eq!(
+            &ppm[header.len()..],
+            &[0, 0, 0, 0, 0, 0, 255, 255, 255, 0, 0, 0]
+        );
+    }
+
     #[test]
     fn test_circle() {
         let mut gfx = GraphicsSystem::with_dimensions(200, 200);
@@ -0,0 +1,235 @@
+//! Keyword documentation for HELP
+//!
+//! `HELP PRINT` shows a keyword's syntax and what it does. Every keyword the
+//! tokenizer knows has an entry: those documented here have a syntax line
+//! and description, and the rest are described from the tokenizer's keyword
+//! tables, by what their token says they are. Editors can use [`lookup`] to
+//! show the same help.
+
+use crate::tokenizer::{keyword_tokens, Token};
+use std::fmt;
+
+/// Help for one keyword
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeywordHelp {
+    pub keyword: &'static str,
+    pub token: Token,
+    /// Syntax, if documented
+    pub syntax: Option<&'static str>,
+    /// What the keyword does, if documented
+    pub description: Option<&'static str>,
+}
+
+impl KeywordHelp {
+    /// What the keyword is, from where its token sits in the token tables
+    pub fn kind(&self) -> &'static str {
+        match self.token {
+            Token::Keyword(0x80..=0x8C) => "operator or statement part",
+            Token::Keyword(0x8E..=0xC5) => "function",
+            Token::ExtendedKeyword(0xC6, _) => "BASIC 4 function",
+            Token::ExtendedKeyword(0xC7, _) => "BASIC 4 command",
+            Token::ExtendedKeyword(..) => "BASIC 4 statement",
+            _ => "statement",
+        }
+    }
+}
+
+impl fmt::Display for KeywordHelp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let token = match self.token {
+            Token::Keyword(token) => format!("&{:02X}", token),
+            Token::ExtendedKeyword(prefix, token) => format!("&{:02X} &{:02X}", prefix, token),
+            _ => String::new(),
+        };
+        writeln!(f, "{} ({}, token {})", self.keyword, self.kind(), token)?;
+        if let Some(syntax) = self.syntax {
+            writeln!(f, "  {}", syntax)?;
+        }
+        if let Some(description) = self.description {
+            writeln!(f, "  {}", description)?;
+        }
+        Ok(())
+    }
+}
+
+/// Syntax and description of the documented keywords
+#[rustfmt::skip]
+const DOCUMENTATION: &[(&str, &str, &str)] = &[
+    ("ABS", "ABS(number)", "The value of a number without its sign."),
+    ("AND", "a AND b", "Bitwise AND of two integers; TRUE AND TRUE is TRUE."),
+    ("ASC", "ASC(string)", "The character code of the first character, or -1 if empty."),
+    ("ASSERT", "ASSERT condition [, message]", "Stops with an error (the message, or \"Assertion failed\") if the condition is FALSE. BASIC V only."),
+    ("ATN", "ATN(number)", "Arc tangent, in radians."),
+    ("BPUT", "BPUT#channel, byte / BPUT#channel, string[;]", "Writes a byte, or a string and a line feed (left off after ;), to a file."),
+    ("CALL", "CALL address", "Calls an OS routine (OSWRCH &FFEE, OSASCI &FFE3, OSNEWL &FFE7, OSWORD &FFF1 or OSBYTE &FFF4) with A%, X% and Y% in the registers."),
+    ("CHAIN", "CHAIN \"name\"", "Replaces the program with another, from the program slot of that name or else a file, and runs it."),
+    ("CHR$", "CHR$(code)", "A one-character string with the given character code."),
+    ("CIRCLE", "CIRCLE [FILL] x, y, radius", "Draws a circle, filled with FILL."),
+    ("CLEAR", "CLEAR", "Forgets every variable and array except the resident integers @% and A% to Z%, which keep their values through NEW, RUN and CHAIN too."),
+    ("CLG", "CLG", "Clears the graphics area to the background colour."),
+    ("CLOSE", "CLOSE#channel", "Closes a file opened with OPENIN, OPENOUT or OPENUP."),
+    ("CLS", "CLS", "Clears the text screen and homes the cursor."),
+    ("COLOUR", "COLOUR colour", "Sets the text colour (add 128 for the background)."),
+    ("CONST", "CONST name = value", "Sets a variable that cannot then be changed; assigning it stops with a Constant error. BASIC V only."),
+    ("COS", "COS(radians)", "Cosine of an angle in radians."),
+    ("DATA", "DATA item, item, ...", "Values for READ to take, in program order. Items are kept as typed; quote one to keep commas or leading spaces."),
+    ("DEF", "DEF PROCname(params) / DEF FNname(params) [= expression]", "Defines a procedure or function; a multi-line function ends with RETURN value."),
+    ("DEG", "DEG(radians)", "Converts radians to degrees."),
+    ("DIM", "DIM name(size, ...) / DIM name size", "Creates an array with subscripts from 0 to each size, or reserves size + 1 bytes of memory for ?, ! and $ and sets name to their address."),
+    ("DIV", "a DIV b", "Integer division, rounding towards zero."),
+    ("DRAW", "DRAW [BY] x, y", "Draws a line from the graphics cursor to x, y (BY: relative to it)."),
+    ("ELLIPSE", "ELLIPSE [FILL] x, y, major, minor", "Draws an ellipse, filled with FILL."),
+    ("ELSE", "IF condition THEN ... ELSE ...", "Starts the part of an IF run when the condition is false."),
+    ("END", "END", "Ends the program."),
+    ("ENDIF", "ENDIF", "Ends a multi-line IF ... THEN block."),
+    ("ENDPROC", "ENDPROC", "Returns from a procedure."),
+    ("ENDWHILE", "ENDWHILE", "Ends a WHILE loop."),
+    ("ENVELOPE", "ENVELOPE n, t, pi1, pi2, pi3, pn1, pn2, pn3, aa, ad, as, ar, ala, ald", "Defines pitch and amplitude envelope n (1-16) for SOUND."),
+    ("EOF", "EOF#channel", "TRUE if the end of a file has been reached."),
+    ("EOR", "a EOR b", "Bitwise exclusive OR of two integers."),
+    ("ERL", "ERL", "The line number of the last error."),
+    ("ERR", "ERR", "The number of the last error."),
+    ("EXP", "EXP(number)", "e raised to a power."),
+    ("FALSE", "FALSE", "The value 0."),
+    ("FILL", "FILL x, y", "Flood fills the background-coloured area around x, y."),
+    ("FN", "FNname(arguments)", "Calls a function defined with DEF FN."),
+    ("FOR", "FOR var = start TO end [STEP step]", "Starts a loop that ends at NEXT."),
+    #[cfg(feature = "turtle")]
+    ("FORWARD", "FORWARD distance", "Walks the turtle forward (back if negative), drawing a line while its pen is down."),
+    ("GCOL", "GCOL mode, colour", "Sets the graphics colour and plotting mode."),
+    ("GET", "GET", "Waits for a key and returns its code."),
+    ("GET$", "GET$ / GET$#channel", "Waits for a key and returns it as a string, or reads a line from a file."),
+    ("GOSUB", "GOSUB line", "Calls a subroutine, which returns with RETURN."),
+    ("GOTO", "GOTO line", "Jumps to a line."),
+    ("HELP", "HELP keyword", "Shows the syntax of a keyword and what it does."),
+    ("HIMEM", "HIMEM", "The top of memory for the program and variables."),
+    ("IF", "IF condition THEN statement [ELSE statement]", "Runs a statement if the condition is TRUE (non-zero)."),
+    ("INKEY", "INKEY(centiseconds) / INKEY(-key)", "Waits up to a time for a key, returning its code or -1, or tests whether a key is held down (INKEY(-99) for SPACE)."),
+    ("INPUT", "INPUT var, var, ...", "Reads values typed at the keyboard."),
+    ("INSTALL", "INSTALL \"file\"", "Loads a library of PROCs and FNs for good."),
+    ("INSTR", "INSTR(string, find [, start])", "Position of one string in another, or 0."),
+    ("INT", "INT(number)", "The largest integer not above a number."),
+    ("LEFT$", "LEFT$(string, count)", "The first characters of a string."),
+    ("LEN", "LEN(string)", "The number of characters in a string."),
+    ("LET", "[LET] var = expression", "Assigns a value to a variable."),
+    ("LIBRARY", "LIBRARY \"file\"", "Loads a library of PROCs and FNs until the next RUN."),
+    ("LN", "LN(number)", "Natural logarithm."),
+    ("LOCAL", "LOCAL var, var, ... / LOCAL DATA / LOCAL ERROR", "Makes variables local to a procedure or function, or saves the DATA pointer or the error handling."),
+    ("LOG", "LOG(number)", "Logarithm to base 10."),
+    ("LOMEM", "LOMEM", "Where variables start, just above the program."),
+    ("MID$", "MID$(string, start [, count])", "Part of a string, starting at position 1."),
+    ("MOD", "a MOD b", "The remainder after integer division."),
+    ("MODE", "MODE n", "Changes screen mode (0-7) and clears the screen."),
+    ("MOUSE", "MOUSE x, y, buttons / MOUSE ON / MOUSE OFF", "Reads the pointer's position in graphics units and its buttons (4 left, 2 middle, 1 right), or shows or hides it. BASIC V only."),
+    ("MOVE", "MOVE [BY] x, y", "Moves the graphics cursor without drawing (BY: relative to it)."),
+    ("NEXT", "NEXT [var]", "Ends a FOR loop."),
+    ("NOT", "NOT number", "Bitwise NOT; NOT TRUE is FALSE."),
+    ("ON", "ON expression GOTO|GOSUB line, line, ... / ON ERROR statement", "Jumps to a line chosen by a number, or sets an error handler."),
+    ("OPENIN", "OPENIN(\"file\")", "Opens a file for reading, returning its channel."),
+    ("OPENOUT", "OPENOUT(\"file\")", "Creates a file for writing, returning its channel."),
+    ("OPENUP", "OPENUP(\"file\")", "Opens a file for reading and writing, returning its channel."),
+    ("OR", "a OR b", "Bitwise OR of two integers."),
+    ("ORIGIN", "ORIGIN x, y", "Moves the graphics origin."),
+    ("OUTPUT", "OUTPUT #channel / OUTPUT", "Sends PRINT output to an open file, or to the screen with channel 0, until the next OUTPUT; OUTPUT alone goes back to where it went before. BASIC V only."),
+    ("PAGE", "PAGE", "Where the program starts in memory."),
+    #[cfg(feature = "turtle")]
+    ("PENDOWN", "PENDOWN [colour]", "Lowers the turtle's pen, so FORWARD draws; with a colour, draws in it as GCOL 0 does."),
+    #[cfg(feature = "turtle")]
+    ("PENUP", "PENUP", "Lifts the turtle's pen, so FORWARD moves without drawing."),
+    ("PI", "PI", "3.14159265."),
+    ("PLOT", "PLOT mode, x, y", "Plots points, lines and triangles (MOVE is PLOT 4, DRAW PLOT 5)."),
+    ("POS", "POS", "The column of the text cursor."),
+    ("PRINT", "PRINT [item] [; | , | '] ...", "Prints values; ; joins items, , moves to the next column, ' starts a new line."),
+    ("PROC", "PROCname(arguments)", "Calls a procedure defined with DEF PROC."),
+    ("RAD", "RAD(degrees)", "Converts degrees to radians."),
+    ("READ", "READ var, var, ...", "Reads the next values from DATA statements."),
+    ("RECTANGLE", "RECTANGLE [FILL] x, y, width, height [TO x, y]", "Draws a rectangle, filled with FILL; with TO copies the block (FILL: moves it)."),
+    ("REM", "REM comment", "A comment; the rest of the line is ignored."),
+    ("REPEAT", "REPEAT", "Starts a loop that ends at UNTIL."),
+    ("REPORT", "REPORT or REPORT$", "Prints, or returns, the message of the last error."),
+    ("RESTORE", "RESTORE [line | +n | DATA | ERROR]", "Makes READ start again from the first DATA, a line, the nth DATA line on, or where LOCAL DATA was; RESTORE ERROR puts back the error handling saved by LOCAL ERROR."),
+    ("RETURN", "RETURN [value]", "Returns from a subroutine called by GOSUB, or with a value from a multi-line function."),
+    ("RIGHT$", "RIGHT$(string, count)", "The last characters of a string."),
+    ("RND", "RND(n)", "RND(1) is a random number from 0 to 1; RND(n) a random integer from 1 to n."),
+    ("SGN", "SGN(number)", "-1, 0 or 1 for a negative, zero or positive number."),
+    ("SIN", "SIN(radians)", "Sine of an angle in radians."),
+    ("SOUND", "SOUND channel, amplitude, pitch, duration", "Queues a note; amplitude 0 to -15, or an envelope number."),
+    ("SPC", "PRINT SPC(count)", "Prints a number of spaces."),
+    ("SQR", "SQR(number)", "Square root."),
+    ("STEP", "FOR var = start TO end STEP step", "The amount a FOR loop counts by."),
+    ("STOP", "STOP", "Stops the program; CONT carries on."),
+    ("STR$", "STR$(number)", "A number as a string."),
+    ("STRING$", "STRING$(count, string)", "A string repeated a number of times."),
+    ("TAB", "PRINT TAB(column [, row])", "Moves the text cursor to a column, or to a column and row."),
+    ("TAN", "TAN(radians)", "Tangent of an angle in radians."),
+    ("THEN", "IF condition THEN ...", "Starts the part of an IF run when the condition is true."),
+    ("TIME", "TIME", "Centiseconds since the computer started; can be set."),
+    ("TO", "FOR var = start TO end", "The last value of a FOR loop."),
+    ("TRUE", "TRUE", "The value -1."),
+    #[cfg(feature = "turtle")]
+    ("TURN", "TURN degrees", "Turns the turtle clockwise (anticlockwise if negative)."),
+    ("UNTIL", "UNTIL condition", "Ends a REPEAT loop once the condition is TRUE."),
+    ("USR", "USR(address)", "Calls an OS routine as CALL does, giving A + 256 * X + 65536 * Y as it returns them."),
+    ("VAL", "VAL(string)", "The number at the start of a string."),
+    ("VDU", "VDU code, code; ... [|]", "Sends codes to the screen (; sends a word, | nine zeros; VDU 5 prints at the graphics cursor, VDU 4 at the text cursor)."),
+    ("VPOS", "VPOS", "The row of the text cursor."),
+    ("WAIT", "WAIT [centiseconds]", "Pauses for a time, or alone until the next frame (as *FX 19 does), 50 a second unless refresh_rate is configured."),
+    ("WHILE", "WHILE condition", "Starts a loop run while the condition is TRUE, ending at ENDWHILE."),
+];
+
+/// Help for a keyword, in any case, or None if it is not a keyword
+pub fn lookup(keyword: &str) -> Option<KeywordHelp> {
+    let keyword = keyword.trim().to_ascii_uppercase();
+    let (keyword, token) = keyword_tokens()
+        .into_iter()
+        .find(|(name, _)| *name == keyword)?;
+    let documentation = DOCUMENTATION.iter().find(|(name, ..)| *name == keyword);
+    Some(KeywordHelp {
+        keyword,
+        token,
+        syntax: documentation.map(|&(_, syntax, _)| syntax),
+        description: documentation.map(|&(.., description)| description),
+    })
+}
+
+/// Every keyword, in alphabetical order
+pub fn keywords() -> Vec<&'static str> {
+    let mut keywords: Vec<&str> = keyword_tokens().into_iter().map(|(name, _)| name).collect();
+    keywords.sort_unstable();
+    keywords.dedup();
+    keywords
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_documented_keywords_exist() {
+        for (keyword, ..) in DOCUMENTATION {
+            let help = lookup(keyword).unwrap_or_else(|| panic!("{} is not a keyword", keyword));
+            assert!(help.syntax.is_some());
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        let help = lookup("print").unwrap();
+        assert_eq!(help.token, Token::Keyword(0xF1));
+        assert!(help
+            .to_string()
+            .starts_with("PRINT (statement, token &F1)\n  PRINT"));
+
+        // Keywords without documentation are described from their token
+        let help = lookup("TEMPO").unwrap();
+        assert_eq!(help.description, None);
+        assert_eq!(
+            help.to_string(),
+            "TEMPO (BASIC 4 statement, token &C8 &9F)\n"
+        );
+        assert_eq!(lookup("SIN").unwrap().kind(), "function");
+
+        assert_eq!(lookup("PRINTER"), None);
+        assert!(keywords().contains(&"ENVELOPE"));
+    }
+}
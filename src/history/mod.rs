@@ -0,0 +1,174 @@
+//! Recording of variable changes (*HISTORY)
+//!
+//! With the `history` option set, every change to a variable or array
+//! element is kept with the line that made it and the value before and
+//! after, up to that many changes, the oldest going first. After a program
+//! stops, `*HISTORY A%` shows what happened to A% without running it again
+//! under the debugger. The record starts afresh whenever the variables are
+//! cleared, as RUN, CLEAR and CHAIN do.
+
+use crate::charset;
+use crate::variables::Variable;
+use std::collections::VecDeque;
+use std::fmt;
+
+/// A change to a variable
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    /// Line that made the change (None at the prompt)
+    pub line_number: Option<u16>,
+    /// The variable, or the array element as `A%(1,2)`
+    pub name: String,
+    /// Value before, if it had one
+    pub old: Option<Variable>,
+    /// Value after, or None if the variable was removed
+    pub new: Option<Variable>,
+}
+
+/// A value as it is shown in the history
+fn value(variable: &Variable) -> String {
+    match variable {
+        Variable::Integer(value) => value.to_string(),
+        Variable::Real(value) => value.to_string(),
+        Variable::String(value) => format!("\"{}\"", charset::to_unicode(value)),
+        _ => "(array)".to_string(),
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line_number {
+            Some(line_number) => write!(f, "{:>5} ", line_number)?,
+            None => write!(f, "{:>5} ", ">")?,
+        }
+        match &self.new {
+            Some(new) => write!(f, "{} = {}", self.name, value(new))?,
+            None => write!(f, "{} gone", self.name)?,
+        }
+        if let Some(old) = &self.old {
+            write!(f, " (was {})", value(old))?;
+        }
+        Ok(())
+    }
+}
+
+/// The latest changes to variables, oldest first
+#[derive(Debug, Clone, Default)]
+pub struct ChangeLog {
+    changes: VecDeque<Change>,
+    /// Most changes kept (0 records nothing)
+    capacity: usize,
+    /// Line running now, given to changes as they are recorded
+    line_number: Option<u16>,
+}
+
+impl ChangeLog {
+    /// Keep up to `capacity` changes (0 stops recording), forgetting the
+    /// oldest if there are more
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        let excess = self.changes.len().saturating_sub(capacity);
+        self.changes.drain(..excess);
+    }
+
+    /// Whether changes are being recorded
+    pub fn is_recording(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Note the line now running
+    pub fn set_line(&mut self, line_number: Option<u16>) {
+        self.line_number = line_number;
+    }
+
+    /// Record a change made by the line running
+    pub fn record(&mut self, name: String, old: Option<Variable>, new: Option<Variable>) {
+        if !self.is_recording() {
+            return;
+        }
+        if self.changes.len() == self.capacity {
+            self.changes.pop_front();
+        }
+        self.changes.push_back(Change {
+            line_number: self.line_number,
+            name,
+            old,
+            new,
+        });
+    }
+
+    /// Forget the changes recorded
+    pub fn clear(&mut self) {
+        self.changes.clear();
+    }
+
+    /// All the changes recorded, oldest first
+    pub fn changes(&self) -> impl Iterator<Item = &Change> {
+        self.changes.iter()
+    }
+
+    /// The changes to one variable, oldest first: `A%(` or `A%()` means
+    /// every element of the array A%
+    pub fn changes_to<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Change> {
+        let array = name
+            .strip_suffix("()")
+            .or_else(|| name.strip_suffix('('))
+            .map(|array| format!("{}(", array));
+        self.changes.iter().filter(move |change| match &array {
+            Some(array) => change.name.starts_with(array.as_str()),
+            None => change.name == name,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_change_log() {
+        let mut log = ChangeLog::default();
+        log.record("A%".to_string(), None, Some(Variable::Integer(1)));
+        assert_eq!(log.changes().count(), 0);
+
+        log.set_capacity(3);
+        log.set_line(Some(10));
+        log.record("A%".to_string(), None, Some(Variable::Integer(5)));
+        log.set_line(Some(20));
+        log.record(
+            "N$".to_string(),
+            None,
+            Some(Variable::String("BOB".to_string())),
+        );
+        log.record("B%(1,2)".to_string(), None, Some(Variable::Integer(3)));
+        log.set_line(None);
+        log.record(
+            "A%".to_string(),
+            Some(Variable::Integer(5)),
+            Some(Variable::Integer(0)),
+        );
+
+        // The first change has gone to make room
+        let lines: Vec<String> = log.changes().map(Change::to_string).collect();
+        assert_eq!(
+            lines,
+            [
+                "   20 N$ = \"BOB\"",
+                "   20 B%(1,2) = 3",
+                "    > A% = 0 (was 5)"
+            ]
+        );
+        assert_eq!(log.changes_to("A%").count(), 1);
+        assert_eq!(log.changes_to("B%(").count(), 1);
+        assert_eq!(log.changes_to("B%()").count(), 1);
+        assert_eq!(log.changes_to("B%").count(), 0);
+
+        log.set_capacity(1);
+        assert_eq!(log.changes().count(), 1);
+        log.record("Z%".to_string(), Some(Variable::Integer(1)), None);
+        assert_eq!(
+            log.changes().next().unwrap().to_string(),
+            "    > Z% gone (was 1)"
+        );
+    }
+}
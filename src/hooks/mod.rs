@@ -0,0 +1,174 @@
+//! Hooks called around each program statement
+//!
+//! A [`StatementHook`] sees every statement of a running program, on either
+//! backend, with the number of its line: before it runs, to let it run, skip
+//! it, put another statement in its place or refuse it, and after it has
+//! run, with how it went. Coverage tools, extra security rules and teaching
+//! displays plug in here with [`crate::Interpreter::add_hook`] instead of
+//! running programs themselves.
+
+use crate::error::{BBCBasicError, Result};
+use crate::parser::Statement;
+use std::fmt;
+
+/// What to do with a statement about to run
+#[derive(Debug, Clone, PartialEq)]
+pub enum HookAction {
+    /// Run it
+    Continue,
+    /// Go on to the next statement without running it
+    Skip,
+    /// Run this statement instead (on the bytecode backend a GOTO, PROC or
+    /// other jump put in this way is not followed)
+    Replace(Box<Statement>),
+    /// Stop with "Not allowed" (error 49, which ON ERROR can trap), giving
+    /// what was refused
+    Veto(String),
+}
+
+/// Something called around each statement a program runs
+pub trait StatementHook: Send {
+    /// Decide what to do with a statement about to run (a statement that
+    /// stopped to wait for input is asked about again when it resumes)
+    fn before(&mut self, _line_number: u16, _statement: &Statement) -> HookAction {
+        HookAction::Continue
+    }
+
+    /// Look at a statement that has run (or been skipped), and how it went
+    fn after(&mut self, _line_number: u16, _statement: &Statement, _result: &Result<()>) {}
+}
+
+/// A hook that calls a closure before each statement
+struct Before<F>(F);
+
+impl<F: FnMut(u16, &Statement) -> HookAction + Send> StatementHook for Before<F> {
+    fn before(&mut self, line_number: u16, statement: &Statement) -> HookAction {
+        (self.0)(line_number, statement)
+    }
+}
+
+/// A hook that calls a closure after each statement
+struct After<F>(F);
+
+impl<F: FnMut(u16, &Statement, &Result<()>) + Send> StatementHook for After<F> {
+    fn after(&mut self, line_number: u16, statement: &Statement, result: &Result<()>) {
+        (self.0)(line_number, statement, result)
+    }
+}
+
+/// A hook calling `f` before each statement to decide what to do with it
+pub fn before_each(
+    f: impl FnMut(u16, &Statement) -> HookAction + Send + 'static,
+) -> Box<dyn StatementHook> {
+    Box::new(Before(f))
+}
+
+/// A hook calling `f` after each statement with how it went
+pub fn after_each(
+    f: impl FnMut(u16, &Statement, &Result<()>) + Send + 'static,
+) -> Box<dyn StatementHook> {
+    Box::new(After(f))
+}
+
+/// Identifies an added hook, for removing it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HookId(u32);
+
+/// The hooks added, called in the order they were added
+#[derive(Default)]
+pub struct Hooks {
+    hooks: Vec<(HookId, Box<dyn StatementHook>)>,
+    next_id: u32,
+}
+
+impl Hooks {
+    /// Add a hook, returning the id to remove it by
+    pub fn add(&mut self, hook: Box<dyn StatementHook>) -> HookId {
+        let id = HookId(self.next_id);
+        self.next_id += 1;
+        self.hooks.push((id, hook));
+        id
+    }
+
+    /// Remove a hook, returning whether it was there
+    pub fn remove(&mut self, id: HookId) -> bool {
+        let count = self.hooks.len();
+        self.hooks.retain(|(hook_id, _)| *hook_id != id);
+        self.hooks.len() != count
+    }
+
+    /// Whether there are no hooks
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// Ask each hook in turn about a statement about to run, returning the
+    /// statement to run in its place, if any
+    ///
+    /// A statement put in by one hook is what the later hooks see. Skipping
+    /// runs an empty statement; the first veto refuses the statement.
+    pub fn before(&mut self, line_number: u16, statement: &Statement) -> Result<Option<Statement>> {
+        let mut replacement: Option<Statement> = None;
+        for (_, hook) in &mut self.hooks {
+            let current = replacement.as_ref().unwrap_or(statement);
+            match hook.before(line_number, current) {
+                HookAction::Continue => {}
+                HookAction::Skip => replacement = Some(Statement::Empty),
+                HookAction::Replace(statement) => replacement = Some(*statement),
+                HookAction::Veto(what) => return Err(BBCBasicError::NotAllowed(what)),
+            }
+        }
+        Ok(replacement)
+    }
+
+    /// Tell each hook about a statement that has run
+    pub fn after(&mut self, line_number: u16, statement: &Statement, result: &Result<()>) {
+        for (_, hook) in &mut self.hooks {
+            hook.after(line_number, statement, result);
+        }
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("count", &self.hooks.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Expression;
+
+    #[test]
+    fn test_hooks_in_order() {
+        let mut hooks = Hooks::default();
+        let first = hooks.add(before_each(|_, statement| match statement {
+            Statement::Rem { .. } => HookAction::Replace(Box::new(Statement::Cls)),
+            _ => HookAction::Continue,
+        }));
+        hooks.add(before_each(|line_number, statement| match statement {
+            Statement::Cls if line_number == 20 => HookAction::Veto("CLS".to_string()),
+            Statement::Cls => HookAction::Skip,
+            _ => HookAction::Continue,
+        }));
+        let rem = Statement::Rem {
+            comment: String::new(),
+        };
+        assert_eq!(hooks.before(10, &rem).unwrap(), Some(Statement::Empty));
+        assert_eq!(
+            hooks.before(20, &rem).unwrap_err(),
+            BBCBasicError::NotAllowed("CLS".to_string())
+        );
+        let mode = Statement::Mode {
+            mode: Expression::Integer(1),
+        };
+        assert_eq!(hooks.before(30, &mode).unwrap(), None);
+
+        assert!(hooks.remove(first));
+        assert!(!hooks.remove(first));
+        assert_eq!(hooks.before(20, &rem).unwrap(), None);
+    }
+}
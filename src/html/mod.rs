@@ -0,0 +1,233 @@
+//! HTML listings of BBC BASIC programs (*EXPORT HTML)
+//!
+//! Writes the program as a standalone page, highlighted with the same
+//! token classes as the editor (see [`classify_with_options`]). Every line
+//! has an anchor (`#L100` for line 100), GOTO, GOSUB, RESTORE and THEN/ELSE
+//! line numbers link to the lines they jump to, and PROC and FN calls link
+//! to their DEF lines, which are also listed in an index above the program.
+
+use crate::program::ProgramStore;
+use crate::tokenizer::{classify_with_options, TokenClass, TokenizerOptions};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// Styles for the listing, after GitHub's light theme
+const STYLE: &str = "\
+body { font-family: -apple-system, 'Segoe UI', Helvetica, Arial, sans-serif; color: #24292f; }
+pre.listing { font-family: ui-monospace, SFMono-Regular, Menlo, Consolas, monospace; \
+background: #f6f8fa; border-radius: 6px; padding: 16px; line-height: 1.45; }
+pre.listing a { color: inherit; text-decoration: none; }
+pre.listing a:hover { text-decoration: underline; }
+.line-number { color: #6e7781; }
+.keyword { color: #cf222e; }
+.string { color: #0a3069; }
+.number { color: #0550ae; }
+.name { color: #24292f; }
+.routine { color: #8250df; }
+.comment { color: #6e7781; font-style: italic; }
+.operator { color: #24292f; }
+:target { background: #fff8c5; }";
+
+/// Keywords followed by line numbers
+const JUMP_KEYWORDS: &[&str] = &["GOTO", "GOSUB", "RESTORE", "THEN", "ELSE"];
+
+/// A highlighted HTML page listing the program under `title`
+pub fn html_listing(
+    program: &ProgramStore,
+    title: &str,
+    options: &TokenizerOptions,
+) -> Result<String, String> {
+    let text = program.to_text(options)?;
+    let lines: Vec<(Option<u16>, &str)> = text.iter().map(|line| split_number(line)).collect();
+    let routines = routines(&lines, options);
+
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str(&format!("<title>{}</title>\n", escape(title)));
+    html.push_str(&format!("<style>\n{}\n</style>\n", STYLE));
+    html.push_str("</head>\n<body>\n");
+    html.push_str(&format!("<h1>{}</h1>\n", escape(title)));
+    if !routines.is_empty() {
+        html.push_str("<h2>Procedures and functions</h2>\n<ul class=\"index\">\n");
+        for (name, line_number) in &routines {
+            html.push_str(&format!(
+                "<li><a href=\"#L{0}\">{1}</a> (line {0})</li>\n",
+                line_number,
+                escape(name)
+            ));
+        }
+        html.push_str("</ul>\n");
+    }
+    html.push_str("<pre class=\"listing\">\n");
+    for (line_number, source) in &lines {
+        match line_number {
+            Some(line_number) => {
+                html.push_str(&format!(
+                    "<a id=\"L{0}\" href=\"#L{0}\" class=\"line-number\">{0}</a>",
+                    line_number
+                ));
+                html.push_str(&highlight(source, program, &routines, options));
+            }
+            // Header comments and blank lines
+            None => html.push_str(&span("comment", &escape(source))),
+        }
+        html.push('\n');
+    }
+    html.push_str("</pre>\n</body>\n</html>\n");
+    Ok(html)
+}
+
+/// A listed line's number and the rest of it, with its leading space
+fn split_number(line: &str) -> (Option<u16>, &str) {
+    let digits = line
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(line.len());
+    match line[..digits].parse() {
+        Ok(line_number) => (Some(line_number), &line[digits..]),
+        Err(_) => (None, line),
+    }
+}
+
+/// The PROCs and FNs defined by the program (as `PROCname`), with their lines
+fn routines(lines: &[(Option<u16>, &str)], options: &TokenizerOptions) -> BTreeMap<String, u16> {
+    let mut routines = BTreeMap::new();
+    for (line_number, source) in lines {
+        let Some(line_number) = line_number else {
+            continue;
+        };
+        let spans = classify_with_options(source, options);
+        let words: Vec<&str> = spans
+            .iter()
+            .take(3)
+            .map(|(range, _)| &source[range.clone()])
+            .collect();
+        if let [def, kind, name] = words[..] {
+            let kind = kind.to_ascii_uppercase();
+            if def.eq_ignore_ascii_case("DEF") && (kind == "PROC" || kind == "FN") {
+                routines
+                    .entry(format!("{}{}", kind, name))
+                    .or_insert(*line_number);
+            }
+        }
+    }
+    routines
+}
+
+/// A line's statement as highlighted HTML
+fn highlight(
+    source: &str,
+    program: &ProgramStore,
+    routines: &BTreeMap<String, u16>,
+    options: &TokenizerOptions,
+) -> String {
+    let mut html = String::new();
+    let mut position = 0;
+    // The keyword before the current span, and whether line numbers follow
+    let mut previous: Option<String> = None;
+    let mut jumping = false;
+    for (range, class) in classify_with_options(source, options) {
+        html.push_str(&escape(&source[position..range.start]));
+        let Range { start, end } = range;
+        let token = &source[start..end];
+        let text = escape(token);
+        let keyword = previous.as_deref();
+        html.push_str(&match class {
+            TokenClass::Number if jumping => match token.parse::<u16>() {
+                Ok(line) if program.get_line(line).is_some() => {
+                    format!("<a href=\"#L{}\">{}</a>", line, span("number", &text))
+                }
+                _ => span("number", &text),
+            },
+            TokenClass::Identifier if matches!(keyword, Some("PROC" | "FN")) => {
+                let routine = format!("{}{}", keyword.unwrap_or_default(), token);
+                match routines.get(&routine) {
+                    Some(line) => format!("<a href=\"#L{}\">{}</a>", line, span("routine", &text)),
+                    None => span("routine", &text),
+                }
+            }
+            TokenClass::Keyword => span("keyword", &text),
+            TokenClass::String => span("string", &text),
+            TokenClass::Number => span("number", &text),
+            TokenClass::Identifier => span("name", &text),
+            TokenClass::Comment => span("comment", &text),
+            TokenClass::Operator => span("operator", &text),
+        });
+        // ON x GOTO 10, 20, 30 jumps to any of a list of lines
+        jumping = match class {
+            TokenClass::Keyword => JUMP_KEYWORDS.contains(&token.to_ascii_uppercase().as_str()),
+            TokenClass::Number => jumping,
+            _ => jumping && token == ",",
+        };
+        previous = (class == TokenClass::Keyword).then(|| token.to_ascii_uppercase());
+        position = end;
+    }
+    html.push_str(&escape(&source[position..]));
+    html
+}
+
+/// Text in a span of the given class
+fn span(class: &str, text: &str) -> String {
+    format!("<span class=\"{}\">{}</span>", class, text)
+}
+
+/// Text escaped for HTML
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_html_listing() {
+        let options = TokenizerOptions::default();
+        let mut program = ProgramStore::new();
+        program
+            .load_text(
+                [
+                    "REM Title: <Demo>",
+                    "10 IF X% < 2 THEN 30",
+                    "20 ON X% GOTO 30, 40, 99",
+                    "30 PRINT \"A & B\"",
+                    "40 PROCshow(1)",
+                    "50 END",
+                    "60 DEF PROCshow(N%)",
+                    "70 ENDPROC",
+                    "80 DEF FNtwice(N) = N * 2",
+                ],
+                &options,
+            )
+            .unwrap();
+        let html = html_listing(&program, "Demo", &options).unwrap();
+
+        assert!(html.contains("<title>Demo</title>"));
+        assert!(html.contains("<span class=\"comment\">REM Title: &lt;Demo&gt;</span>"));
+        // Every line has an anchor
+        for line in [10, 20, 30, 40, 50, 60, 70, 80] {
+            assert!(html.contains(&format!("<a id=\"L{0}\" href=\"#L{0}\"", line)));
+        }
+        // Line numbers after THEN and GOTO link to their lines, when they exist
+        assert!(html.contains("<a href=\"#L30\"><span class=\"number\">30</span></a>"));
+        assert!(html.contains("<a href=\"#L40\"><span class=\"number\">40</span></a>"));
+        assert!(html.contains("<span class=\"number\">99</span>"));
+        assert!(!html.contains("#L99"));
+        assert!(!html.contains("<a href=\"#L2\">"));
+        // Strings are escaped
+        assert!(html.contains("<span class=\"string\">&quot;A &amp; B&quot;</span>"));
+        // Calls link to the DEF lines, which are indexed
+        assert!(html.contains("<a href=\"#L60\"><span class=\"routine\">show</span></a>"));
+        assert!(html.contains("<li><a href=\"#L60\">PROCshow</a> (line 60)</li>"));
+        assert!(html.contains("<li><a href=\"#L80\">FNtwice</a> (line 80)</li>"));
+    }
+}
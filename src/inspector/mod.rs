@@ -0,0 +1,377 @@
+//! Variable inspector for running programs (*INSPECT)
+//!
+//! An [`Inspection`] records what a program is doing at one moment: the
+//! line it is on, TIME, the values of the variables being watched and the
+//! PROC calls and GOSUBs in progress. With the `tui` feature, a [`Panel`]
+//! runs the stored program a slice at a time, as front ends do with
+//! [`Interpreter::run_for`], showing its output in the left-hand pane and a
+//! fresh inspection in the right-hand one: a lightweight visual debugger
+//! for terminal users.
+
+use crate::config::Config;
+use crate::executor::Executor;
+use crate::lvar::Listing;
+use crate::postmortem::{calls, show_value, Call};
+use crate::program::ProgramStore;
+use std::fmt;
+
+#[cfg(feature = "tui")]
+use crate::{charset, debugger::Pause, events::OutputEvent, events::OutputListener, Interpreter};
+#[cfg(feature = "tui")]
+use std::sync::{Arc, Mutex};
+
+/// A running program at one moment
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inspection {
+    /// Line being run, if the program has started
+    pub line_number: Option<u16>,
+    /// TIME, in centiseconds
+    pub time: i32,
+    /// Watched variables and their values, shown as they would be typed
+    pub variables: Vec<(String, String)>,
+    /// PROC calls and GOSUBs in progress, outermost first
+    pub calls: Vec<Call>,
+}
+
+impl Inspection {
+    /// Look at the program the executor is running, showing the `watched`
+    /// variables, or every variable that is not an array if none are given
+    pub fn capture(
+        executor: &Executor,
+        program: &ProgramStore,
+        config: &Config,
+        watched: &[String],
+    ) -> Self {
+        let variables = if watched.is_empty() {
+            Listing::capture(executor)
+                .variables
+                .iter()
+                .map(|(name, value)| (name.clone(), show_value(Some(value))))
+                .collect()
+        } else {
+            watched
+                .iter()
+                .map(|name| {
+                    let value = executor.variables().get_variable(name);
+                    (name.clone(), show_value(value))
+                })
+                .collect()
+        };
+        Self {
+            line_number: executor.line_number(),
+            time: executor.time(),
+            variables,
+            calls: calls(executor, program, config),
+        }
+    }
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line_number {
+            Some(line_number) => writeln!(f, "Line {}", line_number)?,
+            None => writeln!(f, "Not started")?,
+        }
+        writeln!(f, "TIME = {}", self.time)?;
+        for (name, value) in &self.variables {
+            writeln!(f, "{} = {}", name, value)?;
+        }
+        if !self.calls.is_empty() {
+            writeln!(f, "Calls, innermost first:")?;
+            for call in self.calls.iter().rev() {
+                writeln!(f, "  {}", call)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Statements run between looks at the keyboard
+#[cfg(feature = "tui")]
+const SLICE: usize = 200;
+
+/// Time between redraws of the panel while the program runs
+#[cfg(feature = "tui")]
+const REDRAW: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// Runs programs beside a live inspection, in the terminal
+#[cfg(feature = "tui")]
+#[derive(Debug, Default)]
+pub struct Panel {
+    /// Variables to show (every one that is not an array if empty)
+    watched: Vec<String>,
+    /// What the program has printed, while the panel is running one
+    output: Arc<Mutex<Option<String>>>,
+}
+
+/// Sends program output to the panel while it is running a program, and
+/// to the usual listener the rest of the time
+#[cfg(feature = "tui")]
+struct Route {
+    output: Arc<Mutex<Option<String>>>,
+    screen: Box<dyn OutputListener>,
+}
+
+#[cfg(feature = "tui")]
+impl OutputListener for Route {
+    fn event(&mut self, event: &OutputEvent) {
+        let mut output = self.output.lock().unwrap();
+        match (output.as_mut(), event) {
+            (Some(text), OutputEvent::Print(printed)) => {
+                text.push_str(&charset::to_unicode(printed).replace('\r', ""))
+            }
+            (Some(text), OutputEvent::Cls) => text.clear(),
+            (Some(_), _) => {}
+            (None, event) => self.screen.event(event),
+        }
+    }
+}
+
+#[cfg(feature = "tui")]
+impl Panel {
+    /// A panel watching the given variables
+    pub fn new(watched: Vec<String>) -> Self {
+        Self {
+            watched,
+            ..Default::default()
+        }
+    }
+
+    /// The variables being watched
+    pub fn watched(&self) -> &[String] {
+        &self.watched
+    }
+
+    /// Watch other variables (every one that is not an array if empty)
+    pub fn set_watched(&mut self, watched: Vec<String>) {
+        self.watched = watched;
+    }
+
+    /// The listener to subscribe to the interpreter in place of `screen`,
+    /// which takes the program's text while the panel is running it and
+    /// passes everything on to `screen` the rest of the time
+    pub fn listener(&self, screen: Box<dyn OutputListener>) -> Box<dyn OutputListener> {
+        Box::new(Route {
+            output: Arc::clone(&self.output),
+            screen,
+        })
+    }
+
+    /// Run the stored program beside the panel until it ends, fails or
+    /// Escape stops it, then print what it printed, so it is still there
+    /// once the terminal is back to normal
+    ///
+    /// Keys typed while it runs go to the keyboard buffer, for INPUT, GET
+    /// and INKEY to read.
+    pub fn run(&mut self, interpreter: &mut Interpreter) -> Result<(), String> {
+        interpreter.start()?;
+        *self.output.lock().unwrap() = Some(String::new());
+        let mut terminal = ratatui::init();
+        let result = self.show(interpreter, &mut terminal);
+        ratatui::restore();
+        let output = self.output.lock().unwrap().take().unwrap_or_default();
+        print!("{}", output);
+        if !output.is_empty() && !output.ends_with('\n') {
+            println!();
+        }
+        result
+    }
+
+    /// Keep the program running and the panel drawn until the program
+    /// stops and a key has been pressed
+    fn show(
+        &self,
+        interpreter: &mut Interpreter,
+        terminal: &mut ratatui::DefaultTerminal,
+    ) -> Result<(), String> {
+        use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+        use std::time::{Duration, Instant};
+
+        let mut drawn = Instant::now();
+        let result = loop {
+            let pause = match interpreter.run_for(SLICE) {
+                Ok(Pause::Ended) => break Ok(()),
+                Ok(pause) => pause,
+                Err(e) => break Err(e),
+            };
+
+            // Wait for keys rather than spin while INPUT or GET wants some
+            let waiting = pause == Pause::WaitingForInput;
+            let timeout = if waiting { REDRAW } else { Duration::ZERO };
+            let mut stop = false;
+            while event::poll(timeout).map_err(|e| e.to_string())? {
+                let Event::Key(key) = event::read().map_err(|e| e.to_string())? else {
+                    continue;
+                };
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                let keys = match key.code {
+                    KeyCode::Esc => {
+                        stop = true;
+                        continue;
+                    }
+                    KeyCode::Enter => "\n".to_string(),
+                    KeyCode::Backspace => "\x7f".to_string(),
+                    KeyCode::Char(c) => c.to_string(),
+                    _ => continue,
+                };
+                interpreter.insert_keys(&keys);
+                if !waiting {
+                    break;
+                }
+            }
+            if stop {
+                break interpreter.stop();
+            }
+
+            if waiting || drawn.elapsed() >= REDRAW {
+                let status = if waiting {
+                    "Waiting for input - Escape stops"
+                } else {
+                    "Running - Escape stops"
+                };
+                self.draw(interpreter, terminal, status)?;
+                drawn = Instant::now();
+            }
+        };
+
+        // Leave the final state up until a key is pressed
+        let status = match &result {
+            Ok(()) => "Program ended - press a key".to_string(),
+            Err(e) => format!("{} - press a key", e),
+        };
+        self.draw(interpreter, terminal, &status)?;
+        loop {
+            match event::read().map_err(|e| e.to_string())? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => break,
+                _ => {}
+            }
+        }
+        result
+    }
+
+    /// Draw the program's output on the left, the inspection on the right
+    /// and `status` along the bottom, in the colours of the configured scheme
+    fn draw(
+        &self,
+        interpreter: &Interpreter,
+        terminal: &mut ratatui::DefaultTerminal,
+        status: &str,
+    ) -> Result<(), String> {
+        use crate::config::ColourScheme;
+        use ratatui::layout::{Constraint, Layout};
+        use ratatui::style::{Color, Style};
+        use ratatui::widgets::{Block, Paragraph};
+
+        let scheme = interpreter.config().colour_scheme;
+        let style = if scheme == ColourScheme::Default {
+            Style::default()
+        } else {
+            let ([fr, fg, fb], [br, bg, bb]) = (scheme.rgb(7), scheme.rgb(0));
+            Style::default()
+                .fg(Color::Rgb(fr, fg, fb))
+                .bg(Color::Rgb(br, bg, bb))
+        };
+        let inspection = interpreter.inspect(&self.watched).to_string();
+        let output = self.output.lock().unwrap().clone().unwrap_or_default();
+        terminal
+            .draw(|frame| {
+                frame.render_widget(Block::new().style(style), frame.area());
+                let [panes, status_line] =
+                    Layout::vertical([Constraint::Min(3), Constraint::Length(1)])
+                        .areas(frame.area());
+                let [left, right] =
+                    Layout::horizontal([Constraint::Percentage(65), Constraint::Percentage(35)])
+                        .areas(panes);
+
+                // Keep the last lines printed in view, as the screen would
+                let rows = left.height.saturating_sub(2) as usize;
+                let lines: Vec<&str> = output.lines().collect();
+                let shown = lines[lines.len().saturating_sub(rows)..].join("\n");
+                frame.render_widget(
+                    Paragraph::new(shown).block(Block::bordered().title(" Program ")),
+                    left,
+                );
+                frame.render_widget(
+                    Paragraph::new(inspection).block(Block::bordered().title(" Inspector ")),
+                    right,
+                );
+                frame.render_widget(Paragraph::new(status), status_line);
+            })
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Backend, Config};
+    use crate::debugger::Pause;
+    use crate::postmortem::Call;
+    use crate::Interpreter;
+
+    #[test]
+    fn test_inspection() {
+        let mut interpreter = Interpreter::with_config(Config {
+            backend: Backend::Tree,
+            deterministic: true,
+            ..Default::default()
+        });
+        for line in [
+            "10 A% = 42",
+            "12 total = 2.5",
+            "14 name$ = \"BOB\"",
+            "20 DIM grid%(3)",
+            "30 PROCshow(7)",
+            "40 END",
+            "100 DEF PROCshow(N%)",
+            "110 PRINT N%",
+            "120 ENDPROC",
+        ] {
+            interpreter.process_line(line).unwrap();
+        }
+        interpreter.debugger_mut().set_breakpoint(110);
+        assert_eq!(interpreter.debug().unwrap(), Pause::Break(110));
+
+        // Every variable but the arrays, when none are watched
+        let inspection = interpreter.inspect(&[]);
+        assert_eq!(inspection.line_number, Some(110));
+        assert!(inspection.time >= 0);
+        let names: Vec<&str> = inspection
+            .variables
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert!(
+            names.contains(&"total") && names.contains(&"name$"),
+            "{:?}",
+            names
+        );
+        assert!(!names.contains(&"grid%"), "{:?}", names);
+        assert!(
+            matches!(&inspection.calls[..], [Call::Proc { name, line_number: 30, .. }] if name == "show")
+        );
+
+        // Just the watched ones, in the order given
+        let watched = ["name$", "A%", "grid%", "missing"].map(String::from);
+        let inspection = interpreter.inspect(&watched);
+        assert_eq!(
+            inspection.variables,
+            [
+                ("name$", "\"BOB\""),
+                ("A%", "42"),
+                ("grid%", "array(3)"),
+                ("missing", "unset"),
+            ]
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+        );
+        let shown = inspection.to_string();
+        assert!(shown.starts_with("Line 110\nTIME = "), "{}", shown);
+        assert!(
+            shown.ends_with("Calls, innermost first:\n  PROCshow(N%=7) called at line 30\n"),
+            "{}",
+            shown
+        );
+    }
+}
@@ -0,0 +1,4553 @@
+//! Interpreter front end for BBC BASIC
+//!
+//! Ties the executor and program store together: stores or executes typed
+//! lines, runs programs (handling control flow across lines) and applies the
+//! interpreter configuration.
+
+use crate::analysis::{check_program, Warning};
+use crate::config::{Backend, Config, StrictFlags};
+use crate::debugger::{Debugger, Pause, Step};
+use crate::error::BBCBasicError;
+use crate::events::{Oswrch, OutputListener};
+use crate::executor::{Executor, Value};
+use crate::filesystem::{
+    decode_program, is_archive_spec, is_url, salvage_tokenized_program, ArchivedFile, FileSystem,
+    SalvagedProgram,
+};
+use crate::hooks::{HookId, StatementHook};
+use crate::html::html_listing;
+use crate::inspector::Inspection;
+use crate::library::bundled;
+use crate::lvar::Listing;
+use crate::memory::{hex_dump, MemoryStatus, DUMP_WIDTH};
+use crate::numbering::number_source;
+use crate::os::LineInput;
+use crate::pack::{pack_program, PackOptions, PackReport};
+use crate::parser::{
+    parse_expression, parse_statement_with_dialect, parse_statements_with_dialect,
+    parse_whole_expression, run_order, Dialect, Expression, PrintItem, Statement,
+};
+use crate::postmortem::PostMortem;
+use crate::program::{LineChange, Position, ProgramStore};
+use crate::speech::Speaker;
+use crate::structure::{structure_program, Rewrite};
+use crate::tokenizer::{
+    classify, detokenize, detokenize_with_options, tokenize_with_options, Token, TokenClass,
+    TokenizedLine,
+};
+use crate::trace;
+use crate::transpiler::{transpile, Transpiled};
+use crate::vm;
+use crate::watchdog::{StuckHandler, Watchdog};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Slot the stored program starts in
+pub const MAIN_SLOT: &str = "MAIN";
+
+/// BBC BASIC interpreter: executor, stored program and configuration
+#[derive(Debug)]
+pub struct Interpreter {
+    executor: Executor,
+    program: ProgramStore,
+    /// Name of the slot the stored program is in
+    slot: String,
+    /// Programs put away in the other slots, by name
+    slots: BTreeMap<String, ProgramStore>,
+    config: Config,
+    /// State of the program when an error last stopped it
+    post_mortem: Option<PostMortem>,
+    /// Breakpoints, and where a debugged program is stopped
+    debugger: Debugger,
+    /// Whether a program started with [`Interpreter::start`] has more to run
+    slicing: bool,
+    /// The error that stopped the running program, if known
+    failure: Option<BBCBasicError>,
+    /// Where the last error was, for the REPL to point at
+    error_source: Option<ErrorSource>,
+    /// Lines the tree backend has parsed into statements
+    parsed: ParsedLines,
+}
+
+/// Program lines parsed into their statements in run order, so that the tree
+/// backend parses each line once rather than at every statement it runs
+#[derive(Debug, Default)]
+struct ParsedLines {
+    /// Revision of the program, and dialect, the lines were parsed for
+    parsed_for: Option<(u64, Dialect)>,
+    lines: HashMap<u16, Vec<Statement>>,
+}
+
+/// The source line an error was reported on, and the part of it at fault
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorSource {
+    /// The line as LIST shows it, or as it was typed
+    pub text: String,
+    /// Byte range of `text` to point at
+    pub span: Range<usize>,
+}
+
+impl ErrorSource {
+    /// Find the part of `text` that `error` is about: the missing variable,
+    /// the division by zero, or else the statement keyword
+    fn new(text: String, numbered: bool, error: Option<&BBCBasicError>) -> Self {
+        let spans = classify(&text);
+        let statement = &spans[usize::from(numbered).min(spans.len())..];
+        let name = match error {
+            Some(BBCBasicError::NoSuchVariable(name)) => Some(
+                name.strip_prefix("Function ")
+                    .and_then(|name| name.strip_suffix(" not defined"))
+                    .unwrap_or(name),
+            ),
+            Some(BBCBasicError::ArrayNotDimensioned(name)) => Some(name.trim_end_matches('(')),
+            _ => None,
+        };
+        let at_fault = statement.iter().find(|(range, class)| {
+            let token = &text[range.clone()];
+            match (error, name) {
+                (_, Some(name)) => *class == TokenClass::Identifier && token == name,
+                (Some(BBCBasicError::DivisionByZero), _) => {
+                    token == "/" || ["DIV", "MOD"].contains(&token.to_ascii_uppercase().as_str())
+                }
+                _ => false,
+            }
+        });
+        let span = at_fault
+            .or(statement.first())
+            .map_or(0..text.len(), |(range, _)| range.clone());
+        Self { text, span }
+    }
+}
+
+impl Interpreter {
+    /// Create a new interpreter with the default configuration
+    pub fn new() -> Self {
+        Self::with_config(Config::default())
+    }
+
+    /// Create a new interpreter with the given configuration
+    pub fn with_config(config: Config) -> Self {
+        let mut interpreter = Self {
+            executor: Executor::new(),
+            program: ProgramStore::new(),
+            slot: MAIN_SLOT.to_string(),
+            slots: BTreeMap::new(),
+            config: Config::default(),
+            post_mortem: None,
+            debugger: Debugger::new(),
+            slicing: false,
+            failure: None,
+            error_source: None,
+            parsed: ParsedLines::default(),
+        };
+        interpreter.apply_config(config);
+        interpreter
+    }
+
+    /// Get the current configuration
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Replace the configuration and apply it
+    pub fn set_config(&mut self, config: Config) {
+        self.apply_config(config);
+    }
+
+    /// Change a single option (*CONFIGURE name value)
+    pub fn configure(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let mut config = self.config.clone();
+        config.set(key, value)?;
+        self.apply_config(config);
+        Ok(())
+    }
+
+    /// Push configuration settings down into the executor
+    fn apply_config(&mut self, config: Config) {
+        // Only switch mode when the configured mode changes, so that
+        // adjusting another option does not undo a MODE statement
+        if config.mode != self.config.mode {
+            // Mode was validated when the configuration was built
+            let _ = self.executor.set_mode(config.mode as i32);
+        }
+        if (config.deterministic, config.seed) != (self.config.deterministic, self.config.seed) {
+            self.executor
+                .set_deterministic(config.deterministic.then_some(config.seed));
+        }
+        // Safe mode holds strings to 255 characters whatever strict says
+        self.executor.set_strict_flags(StrictFlags {
+            string_length: config.strict.string_length || config.safe.enabled,
+            ..config.strict
+        });
+        self.executor.set_safe_mode(config.safe);
+        self.executor.set_float_format(config.floats);
+        self.executor.set_prompts(config.prompts);
+        self.executor.set_filenames(config.filenames());
+        self.executor.set_tracing(config.trace);
+        self.executor
+            .variables_mut()
+            .history_mut()
+            .set_capacity(config.history);
+        self.executor.set_refresh_rate(config.refresh_rate);
+        self.executor
+            .set_watchdog_interval(Duration::from_secs(config.watchdog.into()));
+        // The key mapping was validated with the rest of the configuration
+        if let Ok(keymap) = config.keymap() {
+            self.executor.os_mut().set_keymap(keymap);
+        }
+        self.config = config;
+    }
+
+    /// Get the executor
+    pub fn executor(&self) -> &Executor {
+        &self.executor
+    }
+
+    /// Get the executor mutably
+    pub fn executor_mut(&mut self) -> &mut Executor {
+        &mut self.executor
+    }
+
+    /// Send the program's output events (text, CLS, MODE changes, sound and
+    /// graphics) to `listener` as they happen
+    pub fn subscribe(&mut self, listener: Box<dyn OutputListener>) {
+        self.executor.subscribe(listener);
+    }
+
+    /// Send every byte the program writes to the VDU drivers to `writer`, as
+    /// OSWRCH would see it, including the control codes and their parameters
+    pub fn subscribe_oswrch(&mut self, writer: Box<dyn Oswrch>) {
+        self.executor.subscribe_oswrch(writer);
+    }
+
+    /// Read typing from `line_input` when a program wants keys and none are
+    /// waiting in the keyboard buffer (standard input by default)
+    pub fn set_line_input(&mut self, line_input: Box<dyn LineInput>) {
+        self.executor.os_mut().set_line_input(line_input);
+    }
+
+    /// Type keys into the keyboard buffer, for GET, INKEY and INPUT to read
+    /// (newlines become RETURN), returning how many fitted in the buffer
+    pub fn insert_keys(&mut self, keys: &str) -> usize {
+        self.executor.os_mut().keyboard_mut().insert_str(keys)
+    }
+
+    /// Speak *SAY phrases through `speaker` (silently by default)
+    pub fn set_speaker(&mut self, speaker: Box<dyn Speaker>) {
+        self.executor.speech_mut().set_speaker(speaker);
+    }
+
+    /// Ask `handler` what to do when a program goes round the same few
+    /// lines getting nowhere for the `watchdog` interval (see
+    /// [`crate::watchdog`])
+    pub fn set_stuck_handler(&mut self, handler: Box<dyn StuckHandler>) {
+        let interval = Duration::from_secs(self.config.watchdog.into());
+        self.executor
+            .set_watchdog(Some(Watchdog::new(interval, handler)));
+    }
+
+    /// Call `hook` around each statement programs run, after any hooks
+    /// already added (see [`crate::hooks`]), returning the id to remove it by
+    pub fn add_hook(&mut self, hook: Box<dyn StatementHook>) -> HookId {
+        self.executor.hooks_mut().add(hook)
+    }
+
+    /// Stop calling a hook, returning whether it was there
+    pub fn remove_hook(&mut self, id: HookId) -> bool {
+        self.executor.hooks_mut().remove(id)
+    }
+
+    /// Report a key going down (`down`) or up, by its scancode (`KeyQ`,
+    /// `Space`), for INKEY with a negative number to see
+    pub fn key_event(&mut self, scancode: &str, down: bool) {
+        self.executor.os_mut().key_event(scancode, down);
+    }
+
+    /// Report where the mouse pointer is, as fractions of the way across
+    /// and down the window from its top-left corner, and which buttons are
+    /// held down, for MOUSE to read (see [`crate::os::OSInterface::mouse_event`])
+    pub fn mouse_event(&mut self, across: f64, down: f64, buttons: u8) {
+        self.executor.os_mut().mouse_event(across, down, buttons);
+    }
+
+    /// Make an OSBYTE call (*FX A,X,Y)
+    pub fn fx(&mut self, arguments: &str) -> Result<(), String> {
+        self.executor.fx(arguments).map_err(|e| e.to_string())
+    }
+
+    /// Get the stored program
+    pub fn program(&self) -> &ProgramStore {
+        &self.program
+    }
+
+    /// Get the stored program mutably
+    pub fn program_mut(&mut self) -> &mut ProgramStore {
+        &mut self.program
+    }
+
+    /// Process a typed line: store it if numbered, otherwise execute it
+    pub fn process_line(&mut self, line: &str) -> Result<(), String> {
+        // Typed text is Unicode; programs work in the BBC character set
+        let line = crate::charset::from_unicode(line);
+        self.error_source = None;
+
+        // Tokenize
+        let tokenized = tokenize_with_options(&line, &self.config.tokenizer_options())
+            .map_err(|e| error_message(&e, None))?;
+
+        // Check if this is a numbered line (program mode) or immediate mode
+        if let Some(line_number) = tokenized.line_number {
+            // Program mode: store the line
+            if tokenized.tokens.is_empty() {
+                // Just a line number with no statement = delete that line
+                self.program.delete_line(line_number);
+                println!("Line {} deleted", line_number);
+                self.update_program_size()
+            } else {
+                // The line must fit below HIMEM with the variables
+                let replaced = self
+                    .program
+                    .get_line(line_number)
+                    .map_or(0, TokenizedLine::size);
+                self.executor
+                    .set_program_size(self.program.size() - replaced + tokenized.size())
+                    .map_err(|e| e.to_string())?;
+                self.program.store_line(tokenized);
+                // Silent storage (like real BBC BASIC)
+                Ok(())
+            }
+        } else {
+            // Immediate mode: execute immediately
+            let statements = match parse_statements_with_dialect(&tokenized, self.config.dialect) {
+                Ok(statements) => run_order(statements),
+                // While a program is stopped, a bare expression is printed, to
+                // examine its state like a calculator
+                Err(e) => match parse_expression(&tokenized.tokens) {
+                    Ok(expression) if self.debugger.paused_at().is_some() => {
+                        vec![Statement::Print {
+                            items: vec![PrintItem::Expression(expression)],
+                        }]
+                    }
+                    _ => {
+                        self.error_source = Some(ErrorSource::new(line, false, Some(&e)));
+                        return Err(error_message(&e, None));
+                    }
+                },
+            };
+
+            let mut index = 0;
+            while let Some(statement) = statements.get(index) {
+                // Typed lines have no number, so their loops only go by the
+                // statement they start at
+                let position = Position::new(0, index);
+                index += 1;
+                match statement {
+                    Statement::Library {
+                        filename,
+                        permanent,
+                    } => self.load_library(filename, *permanent),
+                    Statement::Goto { line_number } => return self.goto(*line_number),
+                    Statement::Chain { filename } => match self.chain(filename) {
+                        Ok(()) => return self.run(),
+                        Err(e) => Err(e),
+                    },
+                    Statement::If { condition, .. } => {
+                        self.executor.eval_integer(condition).map(|value| {
+                            if value == 0 {
+                                index = statement
+                                    .else_index(position.statement)
+                                    .unwrap_or(statements.len());
+                            }
+                        })
+                    }
+                    // The THEN part of a one-line IF ran, so skip its ELSE part
+                    Statement::Else if position.statement > 0 => {
+                        index = statements.len();
+                        Ok(())
+                    }
+                    Statement::For { .. } => self
+                        .executor
+                        .execute_statement(statement)
+                        .map(|()| self.executor.set_for_loop_position(position)),
+                    Statement::Next { .. } => {
+                        self.executor.execute_statement(statement).map(|()| {
+                            if let Some(start) = self.executor.should_loop_back() {
+                                index = start.statement + 1;
+                            }
+                        })
+                    }
+                    Statement::Repeat => {
+                        self.executor.push_repeat(position);
+                        Ok(())
+                    }
+                    Statement::Until { condition } => {
+                        self.executor.check_until(condition).map(|repeat| {
+                            if let Some(repeat) = repeat {
+                                index = repeat.statement + 1;
+                            }
+                        })
+                    }
+                    _ => self.executor.execute_statement(statement),
+                }
+                .map_err(|e| {
+                    let message = error_message(&e, None);
+                    self.error_source = Some(ErrorSource::new(line.clone(), false, Some(&e)));
+                    message
+                })?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Undo the last change to the program's lines (UNDO)
+    pub fn undo(&mut self) -> Result<(), String> {
+        if !self.program.undo() {
+            return Err("Nothing to undo".to_string());
+        }
+        self.update_program_size()
+    }
+
+    /// Make the last undone change again (REDO)
+    pub fn redo(&mut self) -> Result<(), String> {
+        if !self.program.redo() {
+            return Err("Nothing to redo".to_string());
+        }
+        self.update_program_size()
+    }
+
+    /// Warnings about the stored program's flow (CHECK): lines that never
+    /// run, empty loops, unopened loop ends and GOTO patterns *STRUCTURE
+    /// would rewrite
+    pub fn check(&self) -> Vec<Warning> {
+        check_program(&self.program, self.config.dialect)
+    }
+
+    /// Rewrite GOTO/GOSUB patterns in the stored program as structured
+    /// statements (*STRUCTURE), returning the rewrites made
+    pub fn structure(&mut self) -> Result<Vec<Rewrite>, String> {
+        let (program, rewrites) = structure_program(&self.program, self.config.dialect)
+            .map_err(|e| format!("Cannot structure program: {}", e))?;
+        self.program = program;
+        Ok(rewrites)
+    }
+
+    /// Pack the stored program (*PACK), reporting its size before and after
+    pub fn pack(&mut self, options: PackOptions) -> Result<PackReport, String> {
+        let (program, report) = pack_program(&self.program, self.config.dialect, options)
+            .map_err(|e| format!("Cannot pack program: {}", e))?;
+        self.program = program;
+        self.update_program_size()?;
+        Ok(report)
+    }
+
+    /// Translate the stored program to standalone Rust source (*COMPILE)
+    pub fn compile(&self) -> Result<Transpiled, String> {
+        transpile(&self.program).map_err(|e| format!("Cannot compile program: {}", e))
+    }
+
+    /// The stored program as a highlighted HTML page (*EXPORT HTML), titled
+    /// with the program's title or else `name`
+    pub fn export_html(&self, name: &str) -> Result<String, String> {
+        let title = self.program.title().unwrap_or_else(|| name.to_string());
+        html_listing(&self.program, &title, &self.config.tokenizer_options())
+    }
+
+    /// List the stored program as source text, one string per line
+    pub fn list(&self) -> Vec<String> {
+        let options = self.config.tokenizer_options();
+        self.program
+            .list()
+            .into_iter()
+            .map(|(_, line)| self.program.line_text(line, &options).unwrap_or_else(|e| e))
+            .collect()
+    }
+
+    /// Read a program or library as source lines: a disc or tape image,
+    /// archive or URL as for LOAD, or a file (with `.bbas` added if the name
+    /// alone is not found)
+    pub fn read_source(&self, filename: &str) -> Result<Vec<String>, String> {
+        if is_archive_spec(filename) {
+            return FileSystem::with_translator(self.config.filenames()).read_program(filename);
+        }
+        decode_program(&self.read_program_file(filename)?)
+    }
+
+    /// Read a program file's bytes, with `.bbas` added to the name if the
+    /// name alone is not found
+    fn read_program_file(&self, filename: &str) -> Result<Vec<u8>, String> {
+        let mut path = self.config.resolve_path(filename)?;
+        if !path.exists() && path.extension().is_none() {
+            path.set_extension("bbas");
+        }
+        std::fs::read(&path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))
+    }
+
+    /// Replace the stored program with what can be recovered of a damaged
+    /// tokenized program (*BADPROG), from a file or image as for LOAD
+    ///
+    /// Lines that cannot be read back as BASIC are counted as lost.
+    pub fn salvage(&mut self, filename: &str) -> Result<SalvagedProgram, String> {
+        let bytes = if is_archive_spec(filename) {
+            FileSystem::with_translator(self.config.filenames())
+                .read_file(filename)?
+                .data
+        } else {
+            self.read_program_file(filename)?
+        };
+        let mut salvaged = salvage_tokenized_program(&bytes);
+        let options = self.config.tokenizer_options();
+        let mut lines = Vec::new();
+        let mut readable = Vec::new();
+        for line in std::mem::take(&mut salvaged.lines) {
+            match tokenize_with_options(&line, &options) {
+                Ok(tokenized) => {
+                    lines.push(tokenized);
+                    readable.push(line);
+                }
+                Err(_) => salvaged.lost += 1,
+            }
+        }
+        salvaged.lines = readable;
+        if lines.is_empty() {
+            return Err("Bad program: nothing could be recovered".to_string());
+        }
+
+        self.new_program();
+        self.program.begin_edit();
+        for line in lines {
+            self.program.store_line(line);
+        }
+        self.program.end_edit();
+        self.update_program_size()?;
+        Ok(salvaged)
+    }
+
+    /// Compare a saved program with the stored one (*DIFF): the changes made
+    /// going from the file to the program in memory
+    pub fn diff(&self, filename: &str) -> Result<Vec<LineChange>, String> {
+        let options = self.config.tokenizer_options();
+        let source = self.read_source(filename)?;
+        let mut saved = ProgramStore::new();
+        saved.load_text(source.iter().map(String::as_str), &options)?;
+        saved.diff(&self.program, &options)
+    }
+
+    /// Merge another program's lines into the stored program (*MERGE), its
+    /// lines replacing any with the same numbers; returns the number merged
+    pub fn merge(&mut self, filename: &str) -> Result<usize, String> {
+        let options = self.config.tokenizer_options();
+        let mut lines = Vec::new();
+        for line in self.read_source(filename)? {
+            let tokenized = tokenize_with_options(line.trim(), &options)
+                .map_err(|e| format!("Tokenization error: {}", e))?;
+            if tokenized.line_number.is_none() {
+                return Err(format!("Line has no line number: {}", line));
+            }
+            lines.push(tokenized);
+        }
+        // Only change the program once the whole file has been read
+        let count = lines.len();
+        self.program.begin_edit();
+        for line in lines {
+            self.program.store_line(line);
+        }
+        self.program.end_edit();
+        Ok(count)
+    }
+
+    /// Load a file into memory (`*LOAD name [address]`), at its own load
+    /// address unless one is given; returns where it went and its length
+    pub fn load_memory(&mut self, arguments: &str) -> Result<(u16, usize), String> {
+        let (name, addresses) = split_star_arguments(arguments)?;
+        let address = match addresses.as_slice() {
+            [] => None,
+            [address] => Some(parse_address(address)?),
+            _ => return Err("Syntax: *LOAD name [address]".to_string()),
+        };
+        let file = FileSystem::with_translator(self.config.filenames()).read_file(&name)?;
+        let address = match address {
+            Some(address) => address,
+            None if file.load_address != 0 => file.load_address,
+            None => return Err(format!("No load address for {}", name)),
+        };
+        let address = io_address(address)?;
+        self.executor
+            .write_memory(address, &file.data)
+            .map_err(|e| e.to_string())?;
+        Ok((address, file.data.len()))
+    }
+
+    /// Save a block of memory to a file
+    /// (`*SAVE name start end|+length [exec [reload]]`), recording its load
+    /// and execution addresses
+    ///
+    /// The block runs up to but not including `end`. The execution and
+    /// reload addresses default to the start of the block.
+    pub fn save_memory(&self, arguments: &str) -> Result<(), String> {
+        let (name, addresses) = split_star_arguments(arguments)?;
+        if !(2..=4).contains(&addresses.len()) {
+            return Err("Syntax: *SAVE name start end|+length [exec [reload]]".to_string());
+        }
+        let start = parse_address(&addresses[0])?;
+        let length = match addresses[1].strip_prefix('+') {
+            Some(length) => parse_address(length)?,
+            None => parse_address(&addresses[1])?
+                .checked_sub(start)
+                .ok_or_else(|| "Bad address: end is before start".to_string())?,
+        };
+        let exec_address = match addresses.get(2) {
+            Some(address) => parse_address(address)?,
+            None => start,
+        };
+        let load_address = match addresses.get(3) {
+            Some(address) => parse_address(address)?,
+            None => start,
+        };
+
+        let data = self
+            .executor
+            .read_memory(io_address(start)?, length as usize)
+            .map_err(|e| e.to_string())?;
+        FileSystem::with_translator(self.config.filenames()).write_file(&ArchivedFile {
+            name,
+            load_address,
+            exec_address,
+            data,
+        })
+    }
+
+    /// Hex and ASCII dump of memory (`*MEMDUMP start [end|+length]`), from
+    /// `start` up to but not including `end`, or 128 bytes if no end is given
+    pub fn memory_dump(&self, arguments: &str) -> Result<Vec<String>, String> {
+        let words: Vec<&str> = arguments.split_whitespace().collect();
+        let (start, length) = match words.as_slice() {
+            [start] => (parse_address(start)?, 16 * DUMP_WIDTH as u32),
+            [start, end] => {
+                let start = parse_address(start)?;
+                let length = match end.strip_prefix('+') {
+                    Some(length) => parse_address(length)?,
+                    None => parse_address(end)?
+                        .checked_sub(start)
+                        .ok_or_else(|| "Bad address: end is before start".to_string())?,
+                };
+                (start, length)
+            }
+            _ => return Err("Syntax: *MEMDUMP start [end|+length]".to_string()),
+        };
+        let start = io_address(start)?;
+        // A dump running past &7FFF stops there
+        let length = (length as usize).min(crate::memory::HIMEM as usize - start as usize);
+        let bytes = self
+            .executor
+            .read_memory(start, length)
+            .map_err(|e| e.to_string())?;
+        Ok(hex_dump(start, &bytes))
+    }
+
+    /// Write bytes into memory (`*MEMSET address byte...`), returning how
+    /// many were written
+    pub fn memory_set(&mut self, arguments: &str) -> Result<usize, String> {
+        let words: Vec<&str> = arguments.split_whitespace().collect();
+        let Some((address, bytes)) = words.split_first().filter(|(_, b)| !b.is_empty()) else {
+            return Err("Syntax: *MEMSET address byte...".to_string());
+        };
+        let address = io_address(parse_address(address)?)?;
+        let bytes = bytes
+            .iter()
+            .map(|byte| {
+                let digits = byte.strip_prefix('&').unwrap_or(byte);
+                u8::from_str_radix(digits, 16).map_err(|_| format!("Bad byte: {}", byte))
+            })
+            .collect::<Result<Vec<u8>, String>>()?;
+        self.executor
+            .write_memory(address, &bytes)
+            .map_err(|e| e.to_string())?;
+        Ok(bytes.len())
+    }
+
+    /// List the program lines containing a string (`*FIND "text"`)
+    ///
+    /// Lines are searched as LIST shows them with keywords in capitals, so
+    /// a keyword is found wherever it is used, as well as text in strings,
+    /// names and REMs. Line numbers themselves are not searched.
+    pub fn find(&self, arguments: &str) -> Result<Vec<String>, String> {
+        let [text] = &string_arguments(arguments)?[..] else {
+            return Err("Syntax: *FIND \"text\"".to_string());
+        };
+        let options = self.config.tokenizer_options();
+        Ok(self
+            .program
+            .list()
+            .into_iter()
+            .filter(|(_, line)| {
+                detokenize(line).is_ok_and(|listed| line_body(&listed).contains(text))
+            })
+            .map(|(line_number, line)| {
+                detokenize_with_options(line, &options)
+                    .unwrap_or_else(|e| format!("Error listing line {}: {}", line_number, e))
+            })
+            .collect())
+    }
+
+    /// Replace a string throughout the program (`*CHANGE "old" "new"`),
+    /// returning the number of lines changed
+    ///
+    /// Lines are changed as LIST shows them and tokenized again, so names
+    /// and strings can be renamed everywhere at once. If any line that
+    /// parsed would no longer parse, nothing is changed. The whole change is
+    /// a single edit for UNDO.
+    pub fn change(&mut self, arguments: &str) -> Result<usize, String> {
+        let [old, new] = &string_arguments(arguments)?[..] else {
+            return Err("Syntax: *CHANGE \"old\" \"new\"".to_string());
+        };
+        let options = self.config.tokenizer_options();
+        let mut changed = Vec::new();
+        for (line_number, line) in self.program.list() {
+            let listed =
+                detokenize(line).map_err(|e| format!("Cannot list line {}: {}", line_number, e))?;
+            let body = line_body(&listed);
+            if !body.contains(old.as_str()) {
+                continue;
+            }
+            let text = format!("{}{}", line_number, body.replace(old.as_str(), new));
+            let broken = || format!("Change would break line {}", line_number);
+            let tokenized = tokenize_with_options(&text, &options).map_err(|_| broken())?;
+            if tokenized.line_number != Some(line_number) || tokenized.tokens.is_empty() {
+                return Err(broken());
+            }
+            let parses = |line: &TokenizedLine| {
+                parse_statement_with_dialect(line, self.config.dialect).is_ok()
+            };
+            if parses(line) && !parses(&tokenized) {
+                return Err(broken());
+            }
+            changed.push(tokenized);
+        }
+
+        let count = changed.len();
+        self.program.begin_edit();
+        for line in changed {
+            self.program.store_line(line);
+        }
+        self.program.end_edit();
+        self.update_program_size()?;
+        Ok(count)
+    }
+
+    /// Load a library of PROC and FN definitions (LIBRARY, or INSTALL when
+    /// `permanent` is set) for the program to call
+    ///
+    /// The library's lines are numbered apart from the program, so any line
+    /// numbers in the file are ignored. A library already loaded is not
+    /// loaded again. Libraries bundled with the interpreter, such as
+    /// `LIB.SORT`, are installed by name without a file.
+    pub fn install_library(&mut self, filename: &str, permanent: bool) -> Result<(), String> {
+        let options = self.config.tokenizer_options();
+        let source = match bundled(filename) {
+            Some(library) => library
+                .source
+                .lines()
+                .map(crate::charset::from_unicode)
+                .collect(),
+            None => self.read_source(filename)?,
+        };
+        let mut lines = Vec::new();
+        for line in source {
+            let tokenized = tokenize_with_options(line.trim(), &options)
+                .map_err(|e| format!("Tokenization error in {}: {:?}", filename, e))?;
+            if !tokenized.tokens.is_empty() {
+                lines.push(tokenized);
+            }
+        }
+        if self.program.install_library(filename, lines, permanent)? {
+            define_library_routines(
+                &mut self.executor,
+                &self.program.library_lines(),
+                self.config.dialect,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Run a LIBRARY or INSTALL statement
+    fn load_library(&mut self, filename: &Expression, permanent: bool) -> crate::error::Result<()> {
+        let filename = self.executor.eval_string(filename)?;
+        self.check_location(&filename)?;
+        self.install_library(&filename, permanent)
+            .and_then(|()| self.compile_program())
+            .map_err(BBCBasicError::DiskError)
+    }
+
+    /// Name of the slot the stored program is in
+    pub fn slot(&self) -> &str {
+        &self.slot
+    }
+
+    /// Every program slot in name order, with how many lines it holds
+    pub fn slots(&self) -> Vec<(String, usize)> {
+        let mut slots: Vec<(String, usize)> = self
+            .slots
+            .iter()
+            .map(|(name, program)| (name.clone(), program.len()))
+            .chain([(self.slot.clone(), self.program.len())])
+            .collect();
+        slots.sort();
+        slots
+    }
+
+    /// Whether there is a program slot called `name`, ignoring case
+    pub fn has_slot(&self, name: &str) -> bool {
+        let name = name.trim().to_ascii_uppercase();
+        name == self.slot || self.slots.contains_key(&name)
+    }
+
+    /// Put the stored program away in its slot and take out the program in
+    /// the slot called `name`, starting the slot empty if it is new (*SLOT)
+    ///
+    /// Slot names ignore case. Each slot keeps its own lines and libraries;
+    /// variables are shared, as they are by CHAIN.
+    pub fn switch_slot(&mut self, name: &str) -> Result<(), String> {
+        let name = name.trim().to_ascii_uppercase();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(format!("Bad slot name: {}", name));
+        }
+        if name == self.slot {
+            return Ok(());
+        }
+        let program = self.slots.remove(&name).unwrap_or_default();
+        let previous = std::mem::replace(&mut self.program, program);
+        self.slots
+            .insert(std::mem::replace(&mut self.slot, name), previous);
+        self.debugger.set_paused_at(None);
+        self.slicing = false;
+        self.update_program_size()
+    }
+
+    /// Run a CHAIN statement: replace the stored program with the program
+    /// in the slot of that name, or else the program file
+    ///
+    /// The file is read as for LOAD, numbering its lines if it has none.
+    /// The old program's variables are forgotten, except the resident
+    /// integers that pass values between programs, and so is its ON ERROR
+    /// handler.
+    fn chain(&mut self, filename: &Expression) -> crate::error::Result<()> {
+        let name = self.executor.eval_string(filename)?;
+        self.check_location(&name)?;
+        self.load_chained(&name).map_err(BBCBasicError::DiskError)?;
+        self.executor.clear_error_handler();
+        self.executor.clear_variables();
+        Ok(())
+    }
+
+    /// Refuse, in safe mode, a program or library from a URL
+    fn check_location(&self, name: &str) -> crate::error::Result<()> {
+        let location = name.split('#').next().unwrap_or_default();
+        if self.config.safe.enabled && is_url(location) {
+            return Err(BBCBasicError::NotAllowed(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Replace the stored program with the program to CHAIN to
+    fn load_chained(&mut self, name: &str) -> Result<(), String> {
+        if self.has_slot(name) {
+            return self.switch_slot(name);
+        }
+        let source = self.read_source(name)?;
+        let lines = number_source(source.iter().map(String::as_str), self.config.numbering())?;
+        let lines = || lines.iter().map(String::as_str);
+        let options = self.config.tokenizer_options();
+        // Check it loads before the program calling it is lost
+        ProgramStore::new().load_text(lines(), &options)?;
+        self.program.load_text(lines(), &options)?;
+        self.update_program_size()
+    }
+
+    /// List one program or library line as source text
+    pub fn list_line(&self, line_number: u16) -> Option<String> {
+        let line = self.program.get_line(line_number)?;
+        detokenize_with_options(line, &self.config.tokenizer_options()).ok()
+    }
+
+    /// Clear the stored program (NEW)
+    ///
+    /// Libraries loaded with INSTALL stay loaded, and so do the values of
+    /// the resident integers @% and A% to Z%.
+    pub fn new_program(&mut self) {
+        self.program.clear();
+        self.debugger.set_paused_at(None);
+        self.slicing = false;
+        self.program.discard_temporary_libraries();
+        self.executor.clear_variables();
+        // Only the resident integers are left, and they take no room
+        let _ = self.update_program_size();
+        if self.config.close_files {
+            // Nothing is left running that could report a failed flush
+            let _ = self.executor.close_all_files();
+        }
+    }
+
+    /// Run the stored program from the first line
+    ///
+    /// Variables left from before are forgotten, except the resident
+    /// integers @% and A% to Z%. Unless configured otherwise, files the program left open are closed
+    /// when it ends, whether normally or with an error. If an error stops
+    /// it, the state it stopped in is kept for [`Interpreter::post_mortem`].
+    pub fn run(&mut self) -> Result<(), String> {
+        self.slicing = false;
+        self.executor.set_trace_lines(false);
+        self.debugger.set_paused_at(None);
+        self.executor.set_line_number(None);
+        self.executor.clear_variables();
+        self.executor.start_limits();
+        let result = self.run_program();
+        self.finish_run(result)
+    }
+
+    /// Carry on running the program from a line, keeping its variables
+    /// (GOTO typed as a command)
+    fn goto(&mut self, line_number: u16) -> Result<(), String> {
+        if self.program.get_line(line_number).is_none() {
+            return Err("No such line".to_string());
+        }
+        self.slicing = false;
+        self.debugger.set_paused_at(None);
+        self.executor.set_line_number(None);
+        self.executor.start_limits();
+        let result = self.prepare_program().and_then(|()| {
+            self.program.goto_line(line_number);
+            self.execute_lines(None, false, None).map(|_| ())
+        });
+        self.finish_run(result)
+    }
+
+    /// Jumps to lines the program does not have, as (line, target) pairs
+    ///
+    /// Every GOTO, GOSUB, RESTORE and ON target is checked, whether or not
+    /// the jump would be made. With `strict.line_numbers` set, RUN refuses a
+    /// program that has any.
+    pub fn missing_lines(&self) -> Vec<(u16, u16)> {
+        self.program
+            .list()
+            .into_iter()
+            .filter_map(|(line_number, line)| {
+                let statements = parse_statements_with_dialect(line, self.config.dialect).ok()?;
+                let targets: Vec<u16> = statements
+                    .iter()
+                    .flat_map(Statement::jump_targets)
+                    .collect();
+                Some((line_number, targets))
+            })
+            .flat_map(|(line_number, targets)| {
+                targets
+                    .into_iter()
+                    .filter(|&target| self.program.get_line(target).is_none())
+                    .map(move |target| (line_number, target))
+            })
+            .collect()
+    }
+
+    /// Get the debugger, for its breakpoints
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    /// Get the debugger mutably, to set or clear breakpoints
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    /// Run the stored program under the debugger, stopping at breakpoints
+    ///
+    /// Debugged programs always run on the tree backend, which goes line by
+    /// line. When the program stops, immediate commands can inspect or change
+    /// its variables before it is resumed with [`Interpreter::step`].
+    pub fn debug(&mut self) -> Result<Pause, String> {
+        self.debugger.set_paused_at(None);
+        self.executor.set_line_number(None);
+        self.executor.clear_variables();
+        self.executor.start_limits();
+        let result = self.prepare_program().and_then(|()| {
+            self.program.start_execution();
+            self.execute_lines(Some(Step::Continue), false, None)
+        });
+        self.after_debug(result)
+    }
+
+    /// Resume a program stopped in the debugger or by an error (CONT),
+    /// running until the step is done or a breakpoint is reached
+    pub fn step(&mut self, step: Step) -> Result<Pause, String> {
+        if self.debugger.paused_at().is_none() {
+            return Err("Not stopped in the debugger".to_string());
+        }
+        let result = self.execute_lines(Some(step), true, None);
+        self.after_debug(result)
+    }
+
+    /// Note where a debugged program stopped, or finish the run if it ended
+    fn after_debug(&mut self, result: Result<Pause, String>) -> Result<Pause, String> {
+        match result {
+            Ok(Pause::Break(line_number)) => {
+                self.debugger.set_paused_at(Some(line_number));
+                Ok(Pause::Break(line_number))
+            }
+            result => {
+                self.debugger.set_paused_at(None);
+                self.finish_run(result.map(|_| ())).map(|()| Pause::Ended)
+            }
+        }
+    }
+
+    /// Start running the stored program a slice at a time, for front ends
+    /// that have their own event loop to keep turning
+    ///
+    /// Nothing runs until [`Interpreter::run_for`] is called. Like debugged
+    /// programs, sliced programs always run on the tree backend.
+    pub fn start(&mut self) -> Result<(), String> {
+        self.slicing = false;
+        self.debugger.set_paused_at(None);
+        self.executor.set_line_number(None);
+        self.executor.clear_variables();
+        self.executor.start_limits();
+        match self.prepare_program() {
+            Ok(()) => {
+                self.program.start_execution();
+                self.slicing = true;
+                Ok(())
+            }
+            Err(e) => self.finish_run(Err(e)),
+        }
+    }
+
+    /// Run up to `statements` more statements of a program started with
+    /// [`Interpreter::start`]
+    ///
+    /// Returns [`Pause::Yielded`] while the program has more to run. INPUT
+    /// and GET never block: when they need keys that have not been typed,
+    /// this returns [`Pause::WaitingForInput`], and once the keys have been
+    /// given to [`Interpreter::insert_keys`] the next call carries on with
+    /// the same statement. WAIT and INKEY still pause for their time, except
+    /// in deterministic mode.
+    pub fn run_for(&mut self, statements: usize) -> Result<Pause, String> {
+        if !self.slicing {
+            return Err("No program running".to_string());
+        }
+        self.executor.set_yield_for_input(true);
+        let result = self.execute_lines(None, false, Some(statements));
+        self.executor.set_yield_for_input(false);
+        match result {
+            Ok(Pause::Yielded | Pause::WaitingForInput) => result,
+            result => {
+                self.slicing = false;
+                self.finish_run(result.map(|_| ())).map(|()| Pause::Ended)
+            }
+        }
+    }
+
+    /// Stop a program started with [`Interpreter::start`] with an Escape
+    /// error, which ON ERROR is not given the chance to trap
+    pub fn stop(&mut self) -> Result<(), String> {
+        if !self.slicing {
+            return Err("No program running".to_string());
+        }
+        self.slicing = false;
+        let line_number = self.executor.line_number();
+        self.finish_run(Err(error_message(&BBCBasicError::Escape, line_number)))
+    }
+
+    /// Tidy up after a program ends, keeping a post-mortem if it failed
+    ///
+    /// A program stopped by an error is left stopped at the failing line, so
+    /// that immediate commands can examine and fix its variables before
+    /// [`Interpreter::step`] runs the line again.
+    fn finish_run(&mut self, result: Result<(), String>) -> Result<(), String> {
+        self.post_mortem = result
+            .as_ref()
+            .err()
+            .map(|error| PostMortem::capture(&self.executor, &self.program, &self.config, error));
+        let failed_at = self.post_mortem.as_ref().and_then(|dump| dump.line_number);
+        if let (true, Err(message)) = (self.config.trace, &result) {
+            trace::failed(message, failed_at);
+        }
+        let failure = self.failure.take();
+        self.executor.reset_output();
+        self.error_source = failed_at
+            .and_then(|line_number| self.list_line(line_number))
+            .map(|text| ErrorSource::new(text, true, failure.as_ref()));
+        if let Some(line_number) = failed_at.filter(|&n| self.program.goto_line(n)) {
+            self.debugger.set_paused_at(Some(line_number));
+        }
+        if self.config.close_files {
+            let closed = self.executor.close_all_files();
+            if result.is_ok() {
+                closed.map_err(|e| error_message(&e, None))?;
+            }
+        }
+        result
+    }
+
+    /// Where the last error stopped the program or typed command, with the
+    /// part of the line to point at
+    pub fn error_source(&self) -> Option<&ErrorSource> {
+        self.error_source.as_ref()
+    }
+
+    /// Note the error stopping the program at a line, returning its message
+    fn fail(&mut self, error: BBCBasicError, line_number: u16) -> String {
+        let message = error_message(&error, Some(line_number));
+        self.failure = Some(error);
+        message
+    }
+
+    /// State of the program when an unhandled error stopped the last RUN:
+    /// the offending line, PROC calls, GOSUBs and loops in progress
+    pub fn post_mortem(&self) -> Option<&PostMortem> {
+        self.post_mortem.as_ref()
+    }
+
+    /// The variables, arrays, PROCs and FNs defined so far (LVAR)
+    pub fn lvar(&self) -> Listing {
+        Listing::capture(&self.executor)
+    }
+
+    /// What the program is doing now, with the values of the `watched`
+    /// variables (all of them if none are given), for the *INSPECT panel
+    pub fn inspect(&self, watched: &[String]) -> Inspection {
+        let mut inspection =
+            Inspection::capture(&self.executor, &self.program, &self.config, watched);
+        // Stopped in the debugger, it is about to run the line it stopped at
+        if let Some(line_number) = self.debugger.paused_at() {
+            inspection.line_number = Some(line_number);
+        }
+        inspection
+    }
+
+    /// Evaluate an expression against the variables, PROCs and FNs as they
+    /// are now, without running a statement: for debugger front ends,
+    /// editors and hosts using BASIC as a formula language
+    ///
+    /// Functions the expression calls still run, and may change variables.
+    pub fn eval(&mut self, expression: &str) -> Result<Value, String> {
+        let text = crate::charset::from_unicode(expression);
+        let tokenized = tokenize_with_options(&text, &self.config.tokenizer_options())
+            .map_err(|e| error_message(&e, None))?;
+        // A number followed by a name reads as a line number
+        let mut tokens: Vec<Token> = tokenized
+            .line_number
+            .map(|n| Token::Integer(n.into()))
+            .into_iter()
+            .collect();
+        tokens.extend(tokenized.tokens);
+        let value = parse_whole_expression(&tokens)
+            .and_then(|expression| self.executor.evaluate(&expression))
+            .map_err(|e| error_message(&e, None))?;
+        Ok(match value {
+            Value::String(text) => Value::String(crate::charset::to_unicode(&text)),
+            value => value,
+        })
+    }
+
+    /// The recorded changes to a variable, or to every variable if `name`
+    /// is empty, oldest first (*HISTORY; `A%(` gives the elements of A%)
+    pub fn history(&self, name: &str) -> Result<Vec<String>, String> {
+        let history = self.executor.variables().history();
+        if !history.is_recording() {
+            return Err("No history kept: *CONFIGURE HISTORY 1000 keeps some".to_string());
+        }
+        let changes: Vec<String> = if name.is_empty() {
+            history.changes().map(ToString::to_string).collect()
+        } else {
+            history.changes_to(name).map(ToString::to_string).collect()
+        };
+        Ok(changes)
+    }
+
+    fn run_program(&mut self) -> Result<(), String> {
+        self.prepare_program()?;
+        if self.config.backend == Backend::Bytecode {
+            return self.run_bytecode();
+        }
+
+        // Start execution from first line
+        self.program.start_execution();
+        self.execute_lines(None, false, None).map(|_| ())
+    }
+
+    /// Memory map of the program and variables
+    pub fn memory_status(&self) -> MemoryStatus {
+        self.executor.memory_status(self.program.size())
+    }
+
+    /// Tell the executor how big the program is, which sets TOP and LOMEM
+    fn update_program_size(&mut self) -> Result<(), String> {
+        self.executor
+            .set_program_size(self.program.size())
+            .map_err(|e| e.to_string())
+    }
+
+    /// Get ready to run the program: collect its DATA and procedures
+    fn prepare_program(&mut self) -> Result<(), String> {
+        if self.program.is_empty() {
+            return Err("No program to run".to_string());
+        }
+        if self.config.strict.line_numbers {
+            let missing = self.missing_lines();
+            if !missing.is_empty() {
+                let jumps: Vec<String> = missing
+                    .into_iter()
+                    .map(|(line_number, target)| no_such_line(target, line_number))
+                    .collect();
+                return Err(jumps.join(", "));
+            }
+        }
+        // The program may have been loaded or edited since it was last typed in
+        self.update_program_size()?;
+        // Each deterministic run starts from the same seed and time
+        if self.config.deterministic {
+            self.executor.set_deterministic(Some(self.config.seed));
+        }
+        // A sliced run may have stopped part way through answering INPUT
+        self.executor.cancel_input();
+
+        // CRITICAL: Reset and collect all DATA statements BEFORE execution begins
+        // This ensures READ can access DATA regardless of program flow (GOTO, etc.)
+        self.executor.reset_data();
+        self.executor.clear_constants();
+        self.executor.clear_saved_errors();
+
+        // Libraries loaded with LIBRARY last only until the next RUN
+        self.program.discard_temporary_libraries();
+
+        // First pass: collect all DATA statements and procedure definitions,
+        // keeping each line's statements for the run
+        self.executor.clear_procedures();
+        self.parsed = ParsedLines {
+            parsed_for: Some((self.program.revision(), self.config.dialect)),
+            lines: HashMap::new(),
+        };
+        for (line_number, line) in self.program.list() {
+            let statements = parse_statements_with_dialect(line, self.config.dialect)
+                .map_err(|e| error_message(&e, Some(line_number)))?;
+
+            // Collect DATA statements, wherever they are in the line
+            for statement in &statements {
+                if matches!(statement, Statement::Data { .. }) {
+                    self.executor
+                        .collect_data(statement, line_number)
+                        .map_err(|e| error_message(&e, Some(line_number)))?;
+                }
+            }
+
+            // Collect procedure and function definitions, which start their lines
+            let statement = statements.first().cloned().unwrap_or(Statement::Empty);
+            self.parsed.lines.insert(line_number, run_order(statements));
+            match statement {
+                Statement::DefProc { name, params } => {
+                    self.executor.define_procedure(name, line_number, params)
+                }
+                Statement::DefFn {
+                    name,
+                    params,
+                    expression,
+                } => self
+                    .executor
+                    .define_function(name, line_number, params, expression),
+                _ => {}
+            }
+        }
+
+        // Then the library routines the program does not define itself
+        define_library_routines(
+            &mut self.executor,
+            &self.program.library_lines(),
+            self.config.dialect,
+        )?;
+        self.compile_program()
+    }
+
+    /// Compile the program for the VM, which runs it on the bytecode
+    /// backend and runs multi-line functions' bodies on either
+    fn compile_program(&mut self) -> Result<(), String> {
+        let compiled = vm::compile(&self.program, self.config.dialect)?;
+        self.executor.set_compiled_program(Arc::new(compiled));
+        Ok(())
+    }
+
+    /// Run program statements from the current one on the tree backend
+    ///
+    /// Under the debugger (`debug` set), stops before a line at a breakpoint
+    /// or where the step ends and returns that line; `resuming` runs the
+    /// current statement first whatever. With a
+    /// `budget`, yields after running that many statements, and stops at a
+    /// statement waiting for input so that it runs again next time.
+    fn execute_lines(
+        &mut self,
+        debug: Option<Step>,
+        mut resuming: bool,
+        budget: Option<usize>,
+    ) -> Result<Pause, String> {
+        let _run = self.config.trace.then(|| trace::run("tree"));
+        let mut throttle = Throttle::new(self.config.pacing());
+        let start_depth = self.executor.return_positions().len();
+        let mut executed = 0;
+
+        while let Some(position) = self.program.current_position() {
+            let line_number = position.line_number;
+            if budget == Some(executed) {
+                return Ok(Pause::Yielded);
+            }
+            executed += 1;
+            if let Some(step) = debug {
+                // The debugger goes line by line, stopping at their starts
+                let depth = self.executor.return_positions().len();
+                if position.statement == 0
+                    && self
+                        .debugger
+                        .should_stop(line_number, depth, step, start_depth, resuming)
+                {
+                    return Ok(Pause::Break(line_number));
+                }
+                resuming = false;
+            }
+
+            if self.program.get_line(line_number).is_none() {
+                return Err(format!("Line {} not found", line_number));
+            }
+
+            // Take the statement to run from the line's statements
+            let (statement, count) = match self.parsed_line(line_number) {
+                Ok(statements) => (
+                    statements.get(position.statement).cloned(),
+                    statements.len(),
+                ),
+                Err(e) => return Err(self.fail(e, line_number)),
+            };
+            let Some(statement) = statement else {
+                // The line has been edited since: carry on with the next one
+                self.program.next_line();
+                continue;
+            };
+            let is_if = matches!(statement, Statement::If { .. });
+
+            // The hooks may skip it, put another statement in its place or
+            // refuse it
+            let (statement, hooked) = match self.executor.hook_before(line_number, &statement) {
+                Ok(replacement) => (replacement.unwrap_or(statement), Ok(())),
+                Err(e) => (statement, Err(e)),
+            };
+
+            // ON GOTO, ON GOSUB and ON PROC go on as the GOTO, GOSUB or PROC
+            // call they pick, or as nothing
+            let picked = match (&statement, &hooked) {
+                (
+                    Statement::OnGoto { expression, .. }
+                    | Statement::OnGosub { expression, .. }
+                    | Statement::OnProc { expression, .. },
+                    Ok(()),
+                ) => Some(
+                    self.executor
+                        .eval_integer(expression)
+                        .map(|index| statement.on_choice(index)),
+                ),
+                _ => None,
+            };
+            let (statement, hooked) = match picked {
+                Some(Ok(call)) => (call, Ok(())),
+                Some(Err(e)) => (statement, Err(e)),
+                None => (statement, hooked),
+            };
+
+            // Check statement type before executing
+            let is_goto = matches!(statement, Statement::Goto { .. });
+            let is_gosub = matches!(statement, Statement::Gosub { .. });
+            let is_return = matches!(statement, Statement::Return { value: None });
+            // RETURN with a value only ends a multi-line function's body,
+            // which the VM runs
+            let is_result = matches!(statement, Statement::Return { value: Some(_) });
+            let is_end = matches!(statement, Statement::End | Statement::Stop);
+            let is_for = matches!(statement, Statement::For { .. });
+            let is_next = matches!(statement, Statement::Next { .. });
+            let is_repeat = matches!(statement, Statement::Repeat);
+            let is_until = matches!(statement, Statement::Until { .. });
+            let is_while = matches!(statement, Statement::While { .. });
+            let is_endwhile = matches!(statement, Statement::EndWhile);
+            let is_if_block = matches!(statement, Statement::IfBlock { .. });
+            let is_else = matches!(statement, Statement::Else);
+            let is_proc_call = matches!(statement, Statement::ProcCall { .. });
+            let is_endproc = matches!(statement, Statement::EndProc);
+            let is_chain = matches!(statement, Statement::Chain { .. });
+            // A PROC or FN is only run by calling it: the program's flow
+            // reaching its DEF passes over the rest of the line
+            let is_def = position.statement == 0
+                && matches!(
+                    statement,
+                    Statement::DefProc { .. } | Statement::DefFn { .. }
+                );
+            // A statement put in place of a one-line IF takes its THEN and
+            // ELSE parts with it
+            let replaced_if = is_if && !matches!(statement, Statement::If { .. });
+            let is_if = is_if && !replaced_if;
+            let mut taken = false;
+
+            // Execute the statement (pausing first if a speed limit is set)
+            self.executor.set_line_number(Some(line_number));
+            throttle.tick();
+            let execution_result =
+                hooked
+                    .and_then(|()| self.executor.watch())
+                    .and_then(|()| match &statement {
+                        Statement::Library {
+                            filename,
+                            permanent,
+                        } => self.load_library(filename, *permanent),
+                        Statement::Chain { filename } => self.chain(filename),
+                        // DATA, PROCs and FNs were collected before the run
+                        Statement::Data { .. } => Ok(()),
+                        _ if is_def => Ok(()),
+                        // A one-line IF's THEN and ELSE parts follow it
+                        Statement::If { condition, .. } => self
+                            .executor
+                            .eval_integer(condition)
+                            .map(|value| taken = value != 0),
+                        _ => self.executor.execute_statement(&statement),
+                    });
+
+            // Handle errors with ON ERROR handler if set
+            if let Err(BBCBasicError::WaitingForInput) = execution_result {
+                return Ok(Pause::WaitingForInput);
+            }
+            self.executor
+                .hook_after(line_number, &statement, &execution_result);
+            if let Err(e) = execution_result {
+                if let Some(handler_line) = self.executor.error_handler_for(&e) {
+                    // Set error information (ERL and ERR)
+                    self.executor
+                        .set_last_error(e.number(), line_number, e.report());
+
+                    // Jump to error handler
+                    if !self.program.goto_line(handler_line) {
+                        return Err(format!(
+                            "Error handler line {} not found (from error at line {})",
+                            handler_line, line_number
+                        ));
+                    }
+                    // Continue execution from error handler
+                    continue;
+                } else {
+                    // No error handler - propagate error as before
+                    return Err(self.fail(e, line_number));
+                }
+            }
+
+            // Handle control flow
+            if is_end {
+                break;
+            } else if is_goto {
+                // GOTO: extract target and jump
+                if let Statement::Goto {
+                    line_number: target,
+                } = statement
+                {
+                    if !self.program.goto_line(target) {
+                        return Err(no_such_line(target, line_number));
+                    }
+                }
+            } else if is_gosub {
+                // GOSUB: save return address (this line) and jump to target
+                if let Statement::Gosub {
+                    line_number: target,
+                } = statement
+                {
+                    // Push this statement so RETURN can come back after it
+                    self.executor.push_gosub_return(position);
+
+                    // Jump to the target subroutine
+                    if !self.program.goto_line(target) {
+                        return Err(no_such_line(target, line_number));
+                    }
+                }
+            } else if is_result {
+                return Err(no_fn(line_number));
+            } else if is_return {
+                // RETURN: pop return address and jump back
+                match self.executor.pop_gosub_return() {
+                    Ok(gosub) => {
+                        // Carry on after the GOSUB, which may be part way
+                        // along its line
+                        if !self.resume_after(gosub) {
+                            return Err(format!("Return line {} not found", gosub.line_number));
+                        }
+                    }
+                    Err(_) => {
+                        return Err("RETURN without GOSUB".to_string());
+                    }
+                }
+            } else if is_proc_call {
+                // PROC call: get procedure definition, bind parameters, push return address, jump
+                if let Statement::ProcCall { name, args } = statement {
+                    // Get procedure definition
+                    let proc = self
+                        .executor
+                        .get_procedure(&name)
+                        .ok_or_else(|| format!("Procedure {} not defined", name))?;
+
+                    let proc_line = proc.line_number;
+                    let params = proc.params.clone();
+
+                    // Enter local scope and bind arguments to parameters
+                    self.executor
+                        .enter_procedure(&params, &args)
+                        .map_err(|e| self.fail(e, line_number))?;
+                    if self.config.trace {
+                        trace::enter("PROC", &name, self.executor.call_depth());
+                    }
+
+                    // Push return address (this statement)
+                    self.executor.push_gosub_return(position);
+
+                    // Carry on after the DEF PROC
+                    if !self.resume_after(Position::from(proc_line)) {
+                        return Err(format!("Procedure {} line {} not found", name, proc_line));
+                    }
+                }
+            } else if is_endproc {
+                // ENDPROC: exit local scope and pop return address
+                self.executor
+                    .exit_local_scope()
+                    .map_err(|e| self.fail(e, line_number))?;
+                if self.config.trace {
+                    trace::leave("PROC", self.executor.call_depth());
+                }
+
+                match self.executor.pop_gosub_return() {
+                    Ok(call) => {
+                        // Carry on after the PROC call
+                        if !self.resume_after(call) {
+                            return Err(format!("Return line {} not found", call.line_number));
+                        }
+                    }
+                    Err(_) => {
+                        return Err("ENDPROC without PROC call".to_string());
+                    }
+                }
+            } else if is_for {
+                // FOR: record this statement for NEXT to loop back to
+                self.executor.set_for_loop_position(position);
+                self.program.next_statement(count);
+            } else if is_next {
+                // NEXT: check if we should loop back
+                if let Some(for_position) = self.executor.should_loop_back() {
+                    // Loop continues - go back to the statement after the FOR
+                    if !self.resume_after(for_position) {
+                        return Err(format!(
+                            "FOR loop line {} not found",
+                            for_position.line_number
+                        ));
+                    }
+                } else {
+                    // Loop completed - continue to the next statement
+                    self.program.next_statement(count);
+                }
+            } else if is_repeat {
+                // REPEAT: push this statement for UNTIL to loop back to
+                self.executor.push_repeat(position);
+                self.program.next_statement(count);
+            } else if is_until {
+                // UNTIL: check condition and loop back if false
+                if let Statement::Until { condition } = statement {
+                    match self.executor.check_until(&condition) {
+                        Ok(Some(repeat)) => {
+                            // Condition false - loop back to the statement after REPEAT
+                            if !self.resume_after(repeat) {
+                                return Err(format!(
+                                    "REPEAT line {} not found",
+                                    repeat.line_number
+                                ));
+                            }
+                        }
+                        Ok(None) => {
+                            // Condition true - exit loop, continue to the next statement
+                            self.program.next_statement(count);
+                        }
+                        Err(BBCBasicError::WaitingForInput) => return Ok(Pause::WaitingForInput),
+                        Err(e) => {
+                            return Err(self.fail(e, line_number));
+                        }
+                    }
+                }
+            } else if is_while {
+                // WHILE: check condition and enter loop if true, skip to ENDWHILE if false
+                if let Statement::While { condition } = statement {
+                    match self.executor.push_while(position, &condition) {
+                        Ok(Some(_)) => {
+                            // Condition true - enter loop body
+                            self.program.next_statement(count);
+                        }
+                        Ok(None) => {
+                            // Condition false - skip to the statement after ENDWHILE
+                            // Find the matching ENDWHILE by scanning forward
+                            let mut depth = 1;
+                            while depth > 0 {
+                                match self.step_forward() {
+                                    None => {
+                                        return Err("WHILE without matching ENDWHILE".to_string())
+                                    }
+                                    Some(Statement::While { .. }) => depth += 1,
+                                    Some(Statement::EndWhile) => depth -= 1,
+                                    Some(_) => {}
+                                }
+                            }
+                            self.step_forward(); // Move past ENDWHILE
+                        }
+                        Err(BBCBasicError::WaitingForInput) => return Ok(Pause::WaitingForInput),
+                        Err(e) => {
+                            return Err(self.fail(e, line_number));
+                        }
+                    }
+                }
+            } else if is_endwhile {
+                // ENDWHILE: check condition and loop back if true
+                // Need to retrieve the WHILE condition from the original WHILE statement
+                // Find the matching WHILE by using the while_stack
+                if let Some(while_position) = self.executor.check_endwhile_get_while_position() {
+                    let while_line = while_position.line_number;
+                    if self.program.get_line(while_line).is_some() {
+                        if let Some(Statement::While { condition }) = self
+                            .statements_of(while_line)
+                            .get(while_position.statement)
+                            .cloned()
+                        {
+                            match self.executor.check_endwhile(&condition) {
+                                Ok(Some(while_position)) => {
+                                    // Condition still true - loop back to the statement after WHILE
+                                    if !self.resume_after(while_position) {
+                                        return Err(format!("WHILE line {} not found", while_line));
+                                    }
+                                }
+                                Ok(None) => {
+                                    // Condition false - exit loop, continue to the next statement
+                                    self.program.next_statement(count);
+                                }
+                                Err(e) => {
+                                    return Err(self.fail(e, while_line));
+                                }
+                            }
+                        } else {
+                            return Err(format!(
+                                "Could not parse WHILE statement at line {}",
+                                while_line
+                            ));
+                        }
+                    } else {
+                        return Err(format!("WHILE line {} not found", while_line));
+                    }
+                } else {
+                    return Err("ENDWHILE without matching WHILE".to_string());
+                }
+            } else if is_if_block {
+                // Block IF: run the THEN branch, or skip to ELSE or ENDIF
+                if let Statement::IfBlock { condition } = statement {
+                    match self.executor.eval_integer(&condition) {
+                        Ok(0) => self.skip_if_branch(true)?,
+                        Ok(_) => {
+                            self.program.next_statement(count);
+                        }
+                        Err(BBCBasicError::WaitingForInput) => return Ok(Pause::WaitingForInput),
+                        Err(e) => {
+                            return Err(self.fail(e, line_number));
+                        }
+                    }
+                }
+            } else if is_if {
+                // One-line IF: its THEN part follows it, and its ELSE part
+                // (if it has one) follows that
+                if taken {
+                    self.program.next_statement(count);
+                } else if let Some(index) = statement.else_index(position.statement) {
+                    self.program
+                        .goto_position(Position::new(line_number, index));
+                } else {
+                    self.program.next_line();
+                }
+            } else if is_else && position.statement > 0 {
+                // Reaching a one-line IF's ELSE means the THEN part ran, so
+                // skip the rest of the line
+                self.program.next_line();
+            } else if is_else {
+                // Reaching ELSE means the THEN branch ran, so skip the ELSE branch
+                self.skip_if_branch(false)?;
+            } else if is_chain {
+                // CHAIN loaded another program: run it from the start
+                self.prepare_program()?;
+                self.program.start_execution();
+            } else if replaced_if || is_def {
+                // Skip the THEN and ELSE parts, or the body of the PROC or FN,
+                // which run to the end of the line
+                if self.program.next_line().is_none() {
+                    break;
+                }
+            } else {
+                // Normal: advance to the next statement
+                if self.program.next_statement(count).is_none() {
+                    break;
+                }
+            }
+        }
+
+        self.program.stop_execution();
+        Ok(Pause::Ended)
+    }
+
+    /// Run the program on the bytecode VM, loading libraries when it asks
+    fn run_bytecode(&mut self) -> Result<(), String> {
+        let _run = self.config.trace.then(|| trace::run("bytecode"));
+        let mut pc = 0;
+        loop {
+            // Compiled again after each library is loaded, to take in its lines
+            let compiled = self
+                .executor
+                .compiled_program()
+                .expect("the program is compiled before it runs");
+            let Some(request) = compiled.run(&mut self.executor, self.config.pacing(), pc)? else {
+                return Ok(());
+            };
+            let (line_number, result) = match request {
+                vm::Request::Library(request) => (
+                    request.line_number,
+                    self.load_library(&request.filename, request.permanent)
+                        .map(|()| Some(request.resume)),
+                ),
+                vm::Request::Chain {
+                    line_number,
+                    filename,
+                } => (line_number, self.chain(&filename).map(|()| None)),
+                vm::Request::Result { line_number, .. } => return Err(no_fn(line_number)),
+            };
+            pc = match result {
+                Ok(Some(resume)) => resume,
+                Ok(None) => {
+                    // CHAIN loaded another program: run it from the start
+                    self.prepare_program()?;
+                    0
+                }
+                Err(e) => {
+                    let Some(handler_line) = self.executor.error_handler_for(&e) else {
+                        return Err(self.fail(e, line_number));
+                    };
+                    self.executor
+                        .set_last_error(e.number(), line_number, e.report());
+                    compiled.find(handler_line).ok_or_else(|| {
+                        format!(
+                            "Error handler line {} not found (from error at line {})",
+                            handler_line, line_number
+                        )
+                    })?
+                }
+            };
+        }
+    }
+
+    /// Skip forward over a block IF branch, stopping after the matching
+    /// ENDIF (or ELSE, when `stop_at_else` is set); nested blocks are skipped
+    fn skip_if_branch(&mut self, stop_at_else: bool) -> Result<(), String> {
+        let mut depth = 0;
+        loop {
+            let Some(statement) = self.step_forward() else {
+                return Err("Missing ENDIF".to_string());
+            };
+            // A block IF's ELSE starts its line; any other ends a one-line IF
+            let starts_line = self
+                .program
+                .current_position()
+                .is_some_and(|position| position.statement == 0);
+            match statement {
+                Statement::IfBlock { .. } => depth += 1,
+                Statement::Else if depth == 0 && stop_at_else && starts_line => break,
+                Statement::EndIf if depth == 0 => break,
+                Statement::EndIf => depth -= 1,
+                _ => {}
+            }
+        }
+        self.step_forward(); // Move past ELSE or ENDIF
+        Ok(())
+    }
+
+    /// A line's statements in the order they run, or none if it is missing
+    /// or cannot be parsed
+    fn statements_of(&mut self, line_number: u16) -> &[Statement] {
+        self.parsed_line(line_number).unwrap_or_default()
+    }
+
+    /// The statements of a line in run order (none if there is no such
+    /// line), parsed when first needed and kept until the program changes
+    fn parsed_line(&mut self, line_number: u16) -> crate::error::Result<&[Statement]> {
+        let parsed_for = (self.program.revision(), self.config.dialect);
+        if self.parsed.parsed_for != Some(parsed_for) {
+            self.parsed = ParsedLines {
+                parsed_for: Some(parsed_for),
+                lines: HashMap::new(),
+            };
+        }
+        let statements = match self.parsed.lines.entry(line_number) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => match self.program.get_line(line_number) {
+                Some(line) => entry.insert(run_order(parse_statements_with_dialect(
+                    line,
+                    self.config.dialect,
+                )?)),
+                None => return Ok(&[]),
+            },
+        };
+        Ok(statements)
+    }
+
+    /// Move on to the next statement of the program and return it, or None
+    /// at the end of the program
+    fn step_forward(&mut self) -> Option<Statement> {
+        let position = self.program.current_position()?;
+        let count = self.statements_of(position.line_number).len();
+        let next = self.program.next_statement(count)?;
+        self.statements_of(next.line_number)
+            .get(next.statement)
+            .cloned()
+    }
+
+    /// Carry on from the statement after `position`, as RETURN, ENDPROC and
+    /// the ends of loops do; false if its line has gone
+    fn resume_after(&mut self, position: Position) -> bool {
+        if !self.program.goto_position(position) {
+            return false;
+        }
+        let count = self.statements_of(position.line_number).len();
+        self.program.next_statement(count);
+        true
+    }
+}
+
+/// Split star command arguments into a file name, which may be quoted, and
+/// the words after it
+fn split_star_arguments(arguments: &str) -> Result<(String, Vec<String>), String> {
+    let arguments = arguments.trim();
+    let (name, rest) = match arguments.strip_prefix('"') {
+        Some(quoted) => quoted
+            .split_once('"')
+            .ok_or_else(|| "Missing closing quote".to_string())?,
+        None => arguments.split_once(' ').unwrap_or((arguments, "")),
+    };
+    if name.is_empty() {
+        return Err("Expected filename".to_string());
+    }
+    let words = rest.split_whitespace().map(str::to_string).collect();
+    Ok((name.to_string(), words))
+}
+
+/// Parse an address given to a star command, in hex with an optional &
+fn parse_address(text: &str) -> Result<u32, String> {
+    let digits = text.strip_prefix('&').unwrap_or(text);
+    u32::from_str_radix(digits, 16).map_err(|_| format!("Bad address: {}", text))
+}
+
+/// Split star command arguments into strings: quoted ones, which may hold
+/// spaces (and `""` for a quote), or single words
+///
+/// Typed text is Unicode, so the strings are converted to the BBC character
+/// set that programs are stored in.
+fn string_arguments(arguments: &str) -> Result<Vec<String>, String> {
+    let mut strings = Vec::new();
+    let mut chars = arguments.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        let mut string = String::new();
+        if c == '"' {
+            loop {
+                match chars.next() {
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        string.push('"');
+                    }
+                    Some('"') => break,
+                    Some(c) => string.push(c),
+                    None => return Err("Missing closing quote".to_string()),
+                }
+            }
+        } else {
+            string.push(c);
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                string.push(c);
+            }
+        }
+        strings.push(crate::charset::from_unicode(&string));
+    }
+    Ok(strings)
+}
+
+/// A listed line without its line number
+fn line_body(listed: &str) -> &str {
+    listed.trim_start_matches(|c: char| c.is_ascii_digit())
+}
+
+/// Where an address falls in the I/O processor's memory
+///
+/// The top 16 bits only say which processor an address is in (&FFFF for the
+/// I/O processor), so they are ignored; user memory ends at &7FFF.
+fn io_address(address: u32) -> Result<u16, String> {
+    let address = (address & 0xFFFF) as u16;
+    if address >= crate::memory::HIMEM {
+        return Err(format!("Bad address: &{:04X}", address));
+    }
+    Ok(address)
+}
+
+/// Define the PROCs and FNs in library lines, except those already defined,
+/// so that the program's own definitions come first
+fn define_library_routines(
+    executor: &mut Executor,
+    lines: &[(u16, &TokenizedLine)],
+    dialect: Dialect,
+) -> Result<(), String> {
+    for (line_number, line) in lines {
+        let statement = parse_statement_with_dialect(line, dialect)
+            .map_err(|e| format!("Library line {}: {}", line_number, e))?;
+        match statement {
+            Statement::DefProc { name, params } if executor.get_procedure(&name).is_none() => {
+                executor.define_procedure(name, *line_number, params);
+            }
+            Statement::DefFn {
+                name,
+                params,
+                expression,
+            } if !executor.has_function(&name) => {
+                executor.define_function(name, *line_number, params, expression);
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// An error as the REPL reports it: BBC BASIC's message and where it was
+pub(crate) fn error_message(error: &BBCBasicError, line_number: Option<u16>) -> String {
+    let at_line = line_number
+        .map(|line_number| format!(" at line {}", line_number))
+        .unwrap_or_default();
+    match error {
+        // What the parser expected says more than BBC BASIC's "Syntax error"
+        BBCBasicError::SyntaxError { message, .. } => {
+            format!("Syntax error{}: {}", at_line, message)
+        }
+        // Reported with the line in the function's body it stopped at
+        BBCBasicError::InFunction(message) => message.clone(),
+        _ => format!("{}{}", error.report(), at_line),
+    }
+}
+
+/// The error for a jump from a line to a line the program does not have
+pub(crate) fn no_such_line(target: u16, line_number: u16) -> String {
+    format!("No such line {} at line {}", target, line_number)
+}
+
+/// The error for RETURN with a value outside a multi-line function
+pub(crate) fn no_fn(line_number: u16) -> String {
+    format!("No FN at line {}", line_number)
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Paces statement execution to a configured number of statements per second
+pub(crate) struct Throttle {
+    speed: u32,
+    started: Instant,
+    statements: u64,
+}
+
+impl Throttle {
+    pub(crate) fn new(speed: u32) -> Self {
+        Self {
+            speed,
+            started: Instant::now(),
+            statements: 0,
+        }
+    }
+
+    /// Account for one statement, sleeping if we are ahead of schedule
+    pub(crate) fn tick(&mut self) {
+        if self.speed == 0 {
+            return;
+        }
+        self.statements += 1;
+        let due = Duration::from_secs_f64(self.statements as f64 / self.speed as f64);
+        let elapsed = self.started.elapsed();
+        if due > elapsed {
+            std::thread::sleep(due - elapsed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Store each line of a program and run it
+    /// One interpreter per execution backend, so tests cover both
+    fn interpreters() -> Vec<Interpreter> {
+        [Backend::Tree, Backend::Bytecode]
+            .into_iter()
+            .map(|backend| {
+                Interpreter::with_config(Config {
+                    backend,
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    fn run_program(interpreter: &mut Interpreter, lines: &[&str]) -> Result<(), String> {
+        for line in lines {
+            interpreter.process_line(line)?;
+        }
+        interpreter.run()
+    }
+
+    #[test]
+    fn test_run_program_with_control_flow() {
+        for mut interpreter in interpreters() {
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 T% = 0",
+                    "20 FOR I% = 1 TO 4",
+                    "30 GOSUB 100",
+                    "40 NEXT I%",
+                    "50 END",
+                    "100 T% = T% + I%",
+                    "110 RETURN",
+                ],
+            )
+            .unwrap();
+            assert_eq!(interpreter.executor().get_variable_int("T%").unwrap(), 10);
+        }
+    }
+
+    #[test]
+    fn test_statements_along_a_line() {
+        for mut interpreter in interpreters() {
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 A% = 0: GOSUB 100: PRINT \"back \";A%",
+                    "20 FOR I% = 1 TO 3: PRINT I%;: NEXT: PRINT",
+                    "30 IF A% = 1 THEN PRINT \"one\": GOSUB 200: PRINT \"then\" ELSE PRINT \"else\"",
+                    "40 IF A% = 2 THEN PRINT \"two\" ELSE PRINT \"not two\": PRINT \"also\"",
+                    "50 REPEAT: A% = A% + 1: UNTIL A% > 4: PRINT A%",
+                    "60 WHILE 0: PRINT \"never\": ENDWHILE: PROCshow: PRINT \"end\"",
+                    "70 END",
+                    "100 A% = 1: IF A% = 1 THEN RETURN",
+                    "110 PRINT \"missed\": RETURN",
+                    "200 RETURN",
+                    "300 DEF PROCshow: PRINT \"proc\";: ENDPROC",
+                ],
+            )
+            .unwrap();
+            assert_eq!(
+                interpreter.executor().get_output(),
+                "back 1\n         1         2         3\none\nthen\nnot two\nalso\n         5\nprocend\n"
+            );
+        }
+
+        // Typed lines run along the line too
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .process_line("T% = 0: FOR I% = 1 TO 4: T% = T% + I%: NEXT: IF T% = 10 THEN A% = 42")
+            .unwrap();
+        assert_eq!(interpreter.executor().get_variable_int("A%").unwrap(), 42);
+
+        // A sliced run picks up part way along a line
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .process_line("10 A% = 1: A% = A% * 2: A% = A% + 3")
+            .unwrap();
+        interpreter.start().unwrap();
+        assert_eq!(interpreter.run_for(2).unwrap(), Pause::Yielded);
+        assert_eq!(interpreter.executor().get_variable_int("A%").unwrap(), 2);
+        assert_eq!(interpreter.run_for(2).unwrap(), Pause::Ended);
+        assert_eq!(interpreter.executor().get_variable_int("A%").unwrap(), 5);
+    }
+
+    #[test]
+    fn test_multi_line_function() {
+        for mut interpreter in interpreters() {
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 I% = 7: C% = 0",
+                    "20 PRINT ;FNfact(5);\" \";FNfirst(3);\" \";FNname$(\"BBC\");\" \";I%",
+                    "30 END",
+                    "40 DEF FNfact(N%)",
+                    "50 IF N% < 2 THEN RETURN 1",
+                    "60 RETURN N% * FNfact(N% - 1)",
+                    "70 DEF FNfirst(N%)",
+                    "80 LOCAL I%",
+                    "90 FOR I% = 1 TO 10",
+                    "100 IF I% * I% > N% THEN RETURN I%",
+                    "110 NEXT",
+                    "120 RETURN 0",
+                    "130 DEF FNname$(A$)",
+                    "140 C% = C% + 1",
+                    "150 RETURN LEFT$(A$, 2)",
+                ],
+            )
+            .unwrap();
+            // LOCAL I% is restored, and the FOR loop left by RETURN is dropped
+            assert_eq!(interpreter.executor().get_output(), "120 2 BB 7\n");
+            // PRINT runs a function's body once, whatever its result is
+            assert_eq!(interpreter.executor().get_variable_int("C%").unwrap(), 1);
+
+            // RETURN with a value outside a function is an error
+            interpreter.new_program();
+            let error = run_program(&mut interpreter, &["10 RETURN 1"]).unwrap_err();
+            assert!(error.contains("No FN at line 10"), "{}", error);
+
+            // So is a function body that runs off the end without one
+            interpreter.new_program();
+            let error = run_program(
+                &mut interpreter,
+                &["10 PRINT FNx", "20 END", "30 DEF FNx", "40 A% = 1"],
+            )
+            .unwrap_err();
+            assert!(error.contains("without RETURN"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_def_line_reached_in_flow() {
+        for mut interpreter in interpreters() {
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 PROCa: PRINT \"after\"",
+                    "20 DEF PROCa: PRINT \"in a\": ENDPROC",
+                    "30 DEF FNtwice(N%) = N% * 2: PRINT \"never\"",
+                    "40 PRINT FNtwice(4)",
+                ],
+            )
+            .unwrap();
+            // Falling onto the DEF lines passes over the rest of them
+            assert_eq!(
+                interpreter.executor().get_output(),
+                "in a\nafter\n         8\n"
+            );
+
+            // A line edited between runs is parsed again
+            interpreter.executor_mut().clear_output();
+            interpreter
+                .process_line("20 DEF PROCa: PRINT \"in b\": ENDPROC")
+                .unwrap();
+            interpreter.run().unwrap();
+            assert_eq!(
+                interpreter.executor().get_output(),
+                "in b\nafter\n         8\n"
+            );
+        }
+    }
+
+    #[test]
+    fn test_next_with_several_variables() {
+        for mut interpreter in interpreters() {
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 FOR I% = 1 TO 3",
+                    "20 FOR J% = 1 TO 2",
+                    "30 PRINT ;I%;J%;\" \";",
+                    "40 NEXT J%, I%",
+                    "50 PRINT",
+                    // NEXT I% leaves the unfinished J% loop inside it
+                    "60 FOR I% = 1 TO 3",
+                    "70 FOR J% = 1 TO 5",
+                    "80 NEXT I%",
+                    "90 PRINT ;I%;\" \";J%",
+                ],
+            )
+            .unwrap();
+            assert_eq!(
+                interpreter.executor().get_output(),
+                "11 12 21 22 31 32 \n4 1\n"
+            );
+            assert_eq!(interpreter.executor().for_loops().len(), 0);
+        }
+    }
+
+    #[test]
+    fn test_for_real_and_array_element() {
+        for mut interpreter in interpreters() {
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 DIM C%(2), A(2)",
+                    "15 T = 0",
+                    "20 FOR X = 0.5 TO 2 STEP 0.5",
+                    "30 T = T + X",
+                    "40 NEXT X",
+                    "50 FOR C%(1) = 1 TO 4",
+                    "60 FOR A(1) = 1 TO 0 STEP -0.25",
+                    "70 N% = N% + 1",
+                    "80 NEXT A(1)",
+                    "90 NEXT C%(1)",
+                    "100 E% = C%(1)",
+                    "110 F = A(1)",
+                ],
+            )
+            .unwrap();
+            let executor = interpreter.executor();
+            assert_eq!(executor.get_variable_real("T").unwrap(), 5.0);
+            assert_eq!(executor.get_variable_real("X").unwrap(), 2.5);
+            assert_eq!(executor.get_variable_int("N%").unwrap(), 20);
+            assert_eq!(executor.get_variable_int("E%").unwrap(), 5);
+            assert_eq!(executor.get_variable_real("F").unwrap(), -0.25);
+        }
+    }
+
+    #[test]
+    fn test_nested_for_loops() {
+        for mut interpreter in interpreters() {
+            run_program(
+                &mut interpreter,
+                &[
+                    "5 DIM A(2)",
+                    "10 T% = 0",
+                    "20 FOR I% = 1 TO 3",
+                    "30 FOR J% = I% TO 3",
+                    "40 T% = T% + 1",
+                    "50 NEXT J%",
+                    "60 NEXT I%",
+                    "70 FOR X = 0.5 TO 1.5 STEP 0.5",
+                    "80 FOR A(1) = X TO 1.5 STEP 0.5",
+                    "90 U% = U% + 1",
+                    "100 NEXT A(1)",
+                    "110 NEXT X",
+                ],
+            )
+            .unwrap();
+            // The inner loop ending does not send the outer one round again,
+            // whatever the loops count with
+            let executor = interpreter.executor();
+            assert_eq!(executor.get_variable_int("T%").unwrap(), 6);
+            assert_eq!(executor.get_variable_int("I%").unwrap(), 4);
+            assert_eq!(executor.get_variable_int("U%").unwrap(), 6);
+            assert_eq!(executor.get_variable_real("X").unwrap(), 2.0);
+        }
+    }
+
+    #[test]
+    fn test_indirection() {
+        for mut interpreter in interpreters() {
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 DIM list% 100",
+                    "20 P% = list%",
+                    "30 FOR I% = 1 TO 3",
+                    "40 !P% = I% * 10",
+                    "50 P%!4 = P% + 8",
+                    "60 P% = P% + 8",
+                    "70 NEXT I%",
+                    "80 Q% = P% - 8",
+                    "90 Q%!4 = 0",
+                    "100 T% = 0",
+                    "110 P% = list%",
+                    "120 REPEAT",
+                    "130 T% = T% + !P%",
+                    "140 P% = P%!4",
+                    "150 UNTIL P% = 0",
+                    "160 $(list% + 50) = \"HELLO\"",
+                    "170 list%?52 = ASC(\"X\")",
+                    "180 S$ = $(list% + 50)",
+                    "190 L% = LEN($(list% + 50))",
+                    "200 B% = list%?50 + 1",
+                    "210 W% = !list% * 2 + list%!4 - list%",
+                    "220 ?(list% + 99) = 300",
+                    "230 C% = list%?99",
+                    "240 PRINT $(list% + 50)",
+                ],
+            )
+            .unwrap();
+            let executor = interpreter.executor();
+            // The list's nodes were linked through memory
+            assert_eq!(executor.get_variable_int("T%").unwrap(), 60);
+            assert_eq!(executor.get_variable_string("S$").unwrap(), "HEXLO");
+            assert_eq!(executor.get_variable_int("L%").unwrap(), 5);
+            assert_eq!(executor.get_variable_int("B%").unwrap(), 73);
+            assert_eq!(executor.get_variable_int("W%").unwrap(), 28);
+            assert_eq!(executor.get_variable_int("C%").unwrap(), 44);
+            assert!(executor.get_output().ends_with("HEXLO\n"));
+        }
+
+        let mut interpreter = Interpreter::new();
+        assert!(interpreter.process_line("?40000 = 1").is_err());
+        assert!(interpreter.process_line("DIM A$ 10").is_err());
+    }
+
+    #[test]
+    fn test_backends_agree() {
+        let program = [
+            "10 ON ERROR GOTO 200",
+            "20 N% = 0",
+            "30 X = 1",
+            "40 WHILE N% < 5",
+            "50 N% = N% + 1",
+            "60 REPEAT",
+            "70 X = X * 1.5",
+            "80 UNTIL X > N% * 2",
+            "90 ON N% MOD 2 + 1 GOSUB 150, 170",
+            "100 ENDWHILE",
+            "110 PROCscale(N%, 3)",
+            "120 Z% = 1 DIV 0",
+            "130 END",
+            "150 E% = E% + 1",
+            "160 RETURN",
+            "170 O% = O% + 1",
+            "180 RETURN",
+            "200 R% = ERR",
+            "210 END",
+            "300 DEF PROCscale(A%, B%)",
+            "310 S% = A% * B%",
+            "320 ENDPROC",
+        ];
+        let mut results = Vec::new();
+        for mut interpreter in interpreters() {
+            for name in ["E%", "O%", "S%", "R%"] {
+                interpreter.process_line(&format!("{} = 0", name)).unwrap();
+            }
+            run_program(&mut interpreter, &program).unwrap();
+            let executor = interpreter.executor();
+            results.push((
+                ["N%", "E%", "O%", "S%", "R%"].map(|name| executor.get_variable_int(name).unwrap()),
+                executor.get_variable_real("X").unwrap(),
+            ));
+        }
+        assert_eq!(results[0], ([5, 2, 3, 15, 18], 17.0859375));
+        assert_eq!(results[0], results[1]);
+    }
+
+    #[cfg(all(feature = "turtle", feature = "graphics"))]
+    #[test]
+    fn test_turtle_square() {
+        use crate::graphics::Canvas;
+        for mut interpreter in interpreters() {
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 MOVE 100, 100",
+                    "20 FOR I% = 1 TO 4",
+                    "30 FORWARD 200",
+                    "40 TURN 90",
+                    "50 NEXT I%",
+                    "60 PENUP",
+                    "70 TURN 45",
+                    "80 FORWARD 100",
+                ],
+            )
+            .unwrap();
+            let graphics = interpreter.executor().graphics();
+            assert_eq!(graphics.get_position(), (171, 171));
+            for (x, y) in [(100, 200), (200, 300), (300, 200), (200, 100)] {
+                assert_eq!(graphics.get_pixel(x, y), Some(true), "{}, {}", x, y);
+            }
+            // The pen was up for the diagonal
+            assert_eq!(graphics.get_pixel(150, 150), Some(false));
+        }
+    }
+
+    #[test]
+    fn test_on_proc() {
+        for mut interpreter in interpreters() {
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 T% = 0",
+                    "20 S% = 0",
+                    "30 FOR I% = 0 TO 4",
+                    "40 ON I% PROCadd(1), PROCadd(10), PROCshow",
+                    "50 NEXT I%",
+                    "60 END",
+                    "100 DEF PROCadd(N%)",
+                    "110 T% = T% + N%",
+                    "120 ENDPROC",
+                    "200 DEF PROCshow",
+                    "210 S% = T%",
+                    "220 ENDPROC",
+                ],
+            )
+            .unwrap();
+            let executor = interpreter.executor();
+            assert_eq!(executor.get_variable_int("T%").unwrap(), 11);
+            assert_eq!(executor.get_variable_int("S%").unwrap(), 11);
+        }
+    }
+
+    #[test]
+    fn test_local_arrays_data_and_error() {
+        for mut interpreter in interpreters() {
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 DIM A%(2)",
+                    "20 A%(0) = 1",
+                    "25 T% = 0",
+                    "30 READ F%",
+                    "40 PROCsum(3)",
+                    "50 READ S%",
+                    "60 H% = 1 DIV 0",
+                    "70 END",
+                    "100 DEF PROCsum(N%)",
+                    "110 LOCAL A%()",
+                    "112 LOCAL DATA",
+                    "114 LOCAL ERROR",
+                    "120 ON ERROR GOTO 70",
+                    "130 DIM A%(N%)",
+                    "140 A%(0) = N%",
+                    "150 IF N% > 1 THEN",
+                    "160 PROCsum(N% - 1)",
+                    "170 ENDIF",
+                    "180 T% = T% + A%(0)",
+                    "190 RESTORE",
+                    "200 ENDPROC",
+                    "300 DATA 10, 20",
+                ],
+            )
+            // The ON ERROR handler set inside the procedure has gone
+            .unwrap_err();
+            interpreter.process_line("B% = A%(0)").unwrap();
+            let executor = interpreter.executor();
+            // Each call saw its own array, and the caller's array came back
+            assert_eq!(executor.get_variable_int("T%").unwrap(), 6);
+            assert_eq!(executor.get_variable_int("B%").unwrap(), 1);
+            assert_eq!(executor.get_variable_int("S%").unwrap(), 20);
+        }
+    }
+
+    #[test]
+    fn test_restore_error() {
+        for mut interpreter in interpreters() {
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 ON ERROR GOTO 200",
+                    "20 PROCtry",
+                    "30 LOCAL ERROR",
+                    "40 ON ERROR GOTO 300",
+                    "50 RESTORE ERROR",
+                    "60 X = 1 / 0",
+                    "70 END",
+                    "100 DEF PROCtry",
+                    "110 LOCAL ERROR",
+                    "120 ON ERROR GOTO 150",
+                    "130 X = LN(0)",
+                    "140 ENDPROC",
+                    "150 PRINT \"In PROCtry: \"; REPORT$",
+                    "160 RESTORE ERROR",
+                    "170 ENDPROC",
+                    "200 PRINT \"Outer: \"; REPORT$",
+                    "210 END",
+                    "300 PRINT \"Wrong handler\"",
+                ],
+            )
+            .unwrap();
+            assert_eq!(
+                interpreter.executor().get_output(),
+                "In PROCtry: Log range\nOuter: Division by zero\n"
+            );
+        }
+
+        for mut interpreter in interpreters() {
+            let error = run_program(&mut interpreter, &["10 RESTORE ERROR"]).unwrap_err();
+            assert!(
+                error.contains("RESTORE ERROR without LOCAL ERROR"),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn test_restore_forms() {
+        for mut interpreter in interpreters() {
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 RESTORE 200",
+                    "20 READ A%",
+                    "30 PROCsum",
+                    "40 READ B%",
+                    "50 L% = 200",
+                    "60 RESTORE L% + 10",
+                    "70 READ C%",
+                    "80 END",
+                    "100 DEF PROCsum",
+                    "110 LOCAL DATA",
+                    "120 RESTORE +1",
+                    "130 READ X%, Y%",
+                    "140 PRINT X% + Y%",
+                    "150 RESTORE DATA",
+                    "160 READ Z%",
+                    "170 ENDPROC",
+                    "180 DATA 5, 6",
+                    "200 DATA 1, 2",
+                    "210 DATA 7",
+                ],
+            )
+            .unwrap();
+            let executor = interpreter.executor();
+            assert_eq!(executor.get_output(), "        11\n");
+            // RESTORE DATA went back to the caller's DATA, and so did ENDPROC
+            assert_eq!(executor.get_variable_int("Z%").unwrap(), 2);
+            assert_eq!(
+                ["A%", "B%", "C%"].map(|name| executor.get_variable_int(name).unwrap()),
+                [1, 2, 7]
+            );
+
+            let error = run_program(&mut interpreter, &["10 RESTORE DATA"]).unwrap_err();
+            assert!(
+                error.contains("RESTORE DATA without LOCAL DATA"),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn test_pack_keeps_the_program_running() {
+        for mut interpreter in interpreters() {
+            let program = [
+                "10 REM count down",
+                "20 N% = 3",
+                "30 REM loop",
+                "40 PRINT N%;",
+                "50 N% = N% - 1 : REM next",
+                "60 ON N% + 1 GOTO 70, 30, 30, 30",
+                "70 PRINT \" done\"",
+            ];
+            for line in program {
+                interpreter.process_line(line).unwrap();
+            }
+            let report = interpreter.pack(PackOptions::default()).unwrap();
+            assert_eq!((report.before.lines, report.after.lines), (7, 6));
+            assert_eq!(interpreter.list()[1], "30 REM");
+            assert_eq!(interpreter.list()[3], "50 N%=N%- 1");
+            interpreter.run().unwrap();
+            assert_eq!(
+                interpreter.executor().get_output(),
+                "         3         2         1 done\n"
+            );
+
+            assert!(interpreter.program.undo());
+            assert_eq!(interpreter.list().len(), 7);
+        }
+    }
+
+    #[test]
+    fn test_read_data_as_typed() {
+        for mut interpreter in interpreters() {
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 READ A$, B$, C$, D$",
+                    "20 RESTORE",
+                    "30 READ A, B%, C, D$",
+                    "40 READ E$, F$",
+                    "50 PRINT \"[\";A$;\"][\";B$;\"][\";C$;\"][\";D$;\"]\"",
+                    "60 DATA 1.50,  Hello, World ,\"x, \"\"y\"\"\"",
+                    "70 DATA ,  :PRINT",
+                ],
+            )
+            .unwrap();
+            let executor = interpreter.executor();
+            assert_eq!(executor.get_output(), "[1.50][Hello][World ][x, \"y\"]\n");
+            assert_eq!(executor.get_variable_real("A").unwrap(), 1.5);
+            assert_eq!(executor.get_variable_int("B%").unwrap(), 0);
+            assert_eq!(executor.get_variable_string("E$").unwrap(), "");
+            assert_eq!(executor.get_variable_string("F$").unwrap(), ":PRINT");
+        }
+    }
+
+    #[test]
+    fn test_read_and_input_array_elements() {
+        for mut interpreter in interpreters() {
+            // Subscripts are worked out as each value arrives, so they can
+            // use a variable read earlier in the same statement
+            interpreter.insert_keys("BOB, 2.5\n7\n");
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 DIM A%(4), N$(4), B(3, 3)",
+                    "20 FOR I% = 0 TO 3",
+                    "24 READ A%(I%)",
+                    "26 NEXT",
+                    "30 READ J%, B(J%, 1), N$(J%)",
+                    "40 INPUT N$(3), B(0, 0), A%(A%(0) - 10)",
+                    "50 PRINT A%(0); A%(1); A%(2); A%(3); N$(2); N$(3)",
+                    "55 X = B(2, 1) + B(0, 0)",
+                    "60 DATA 10, 20, 30, 40, 2, 1.5, \"X\"",
+                ],
+            )
+            .unwrap();
+            let executor = interpreter.executor();
+            assert_eq!(executor.get_output(), "         7203040XBOB\n");
+            assert_eq!(executor.get_variable_real("X").unwrap(), 4.0);
+        }
+
+        let mut interpreter = Interpreter::new();
+        let error = run_program(
+            &mut interpreter,
+            &["10 DIM A%(3)", "20 READ A%(4)", "30 DATA 1"],
+        )
+        .unwrap_err();
+        assert!(error.starts_with("Subscript"), "{}", error);
+    }
+
+    #[test]
+    fn test_array_and_string_parameters() {
+        let program = [
+            "5 DEF FNsum(B%()) = B%(0) + B%(1)",
+            "10 DIM V%(3), V(3)",
+            "20 S$ = \"KEEP\"",
+            "30 N% = 7",
+            "40 PROCfill(V%(), 5, S$)",
+            "50 T% = FNsum(V%())",
+            "60 END",
+            "100 DEF PROCfill(A%(), N%, S$)",
+            "110 A%(0) = N%",
+            "120 A%(1) = LEN(S$)",
+            "130 S$ = \"CHANGED\"",
+            "140 N% = 0",
+            "150 ENDPROC",
+        ];
+        for mut interpreter in interpreters() {
+            run_program(&mut interpreter, &program).unwrap();
+            interpreter.process_line("A% = V%(0)").unwrap();
+            interpreter.process_line("B% = V%(1)").unwrap();
+            let executor = interpreter.executor();
+            // The array was passed by reference, the string and number by value
+            assert_eq!(executor.get_variable_int("A%").unwrap(), 5);
+            assert_eq!(executor.get_variable_int("B%").unwrap(), 4);
+            assert_eq!(executor.get_variable_int("T%").unwrap(), 9);
+            assert_eq!(executor.get_variable_int("N%").unwrap(), 7);
+            assert_eq!(executor.get_variable_string("S$").unwrap(), "KEEP");
+
+            for (call, error) in [
+                ("40 PROCfill(V%(), 5)", "Arguments"),
+                ("40 PROCfill(V%(), \"5\", S$)", "Type mismatch"),
+                ("40 PROCfill(V%(), 5, N%)", "Type mismatch"),
+                ("40 PROCfill(V(), 5, S$)", "Type mismatch"),
+                ("40 PROCfill(N%, 5, S$)", "Type mismatch"),
+            ] {
+                interpreter.process_line(call).unwrap();
+                let message = interpreter.run().unwrap_err();
+                assert!(message.contains(error), "{}: {}", call, message);
+            }
+        }
+    }
+
+    #[test]
+    fn test_strings_use_bbc_character_set() {
+        for mut interpreter in interpreters() {
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 A$ = \"£5\"",
+                    "20 L% = LEN(A$)",
+                    "30 C% = ASC(A$)",
+                    "40 B$ = CHR$(255)",
+                    "50 P% = INSTR(\"£X£Y\", \"Y\")",
+                    "60 N% = ASC(MID$(\"££Z\", 3))",
+                    "70 Q% = LEN(B$)",
+                ],
+            )
+            .unwrap();
+            let executor = interpreter.executor();
+            // The pound sign is the single byte 96, and high codes are one
+            // byte each
+            assert_eq!(executor.get_variable_int("L%").unwrap(), 2);
+            assert_eq!(executor.get_variable_int("C%").unwrap(), 96);
+            assert_eq!(executor.get_variable_int("P%").unwrap(), 4);
+            assert_eq!(executor.get_variable_int("N%").unwrap(), 90);
+            assert_eq!(executor.get_variable_int("Q%").unwrap(), 1);
+        }
+    }
+
+    #[cfg(feature = "filesystem-host")]
+    #[test]
+    fn test_data_file_records() {
+        for (i, mut interpreter) in interpreters().into_iter().enumerate() {
+            let path = std::env::temp_dir().join(format!("bbc_basic_records_{}.dat", i));
+            let path = path.to_str().unwrap();
+            run_program(
+                &mut interpreter,
+                &[
+                    &format!("10 F% = OPENOUT(\"{}\")", path),
+                    "20 PRINT#F%, 42, 2.5, \"ABC\"",
+                    "30 BPUT#F%, 65",
+                    "40 BPUT#F%, \"LINE\"",
+                    "50 CLOSE#F%",
+                    &format!("60 F% = OPENIN(\"{}\")", path),
+                    "70 INPUT#F%, A, B%, C$",
+                    "80 K% = BGET#F%",
+                    "90 L$ = GET$#F%",
+                    "100 E% = EOF#F%",
+                    "110 CLOSE#F%",
+                ],
+            )
+            .unwrap();
+            let executor = interpreter.executor();
+            assert_eq!(executor.get_variable_real("A").unwrap(), 42.0);
+            assert_eq!(executor.get_variable_int("B%").unwrap(), 2);
+            assert_eq!(executor.get_variable_string("C$").unwrap(), "ABC");
+            assert_eq!(executor.get_variable_int("K%").unwrap(), 65);
+            assert_eq!(executor.get_variable_string("L$").unwrap(), "LINE");
+            assert_eq!(executor.get_variable_int("E%").unwrap(), -1);
+
+            let data = std::fs::read(path).unwrap();
+            std::fs::remove_file(path).ok();
+            assert_eq!(&data[..5], &[0x40, 0, 0, 0, 42]);
+            assert_eq!(&data[11..16], b"\x00\x03CBA");
+        }
+    }
+
+    #[cfg(feature = "filesystem-host")]
+    #[test]
+    fn test_diff_against_saved_program() {
+        let path = std::env::temp_dir().join("bbc_basic_diff.bbas");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "10 PRINT \"HELLO\"\n20 GOTO 10\n30 END\n").unwrap();
+        let mut interpreter = Interpreter::new();
+        for line in ["10 PRINT \"HELLO\"", "20 GOTO 10", "30 END"] {
+            interpreter.process_line(line).unwrap();
+        }
+        assert!(interpreter.diff(path).unwrap().is_empty());
+
+        interpreter.process_line("20 GOTO 30").unwrap();
+        interpreter.process_line("25 CLS").unwrap();
+        let changes = interpreter.diff(path).unwrap();
+        std::fs::remove_file(path).ok();
+        assert_eq!(
+            changes,
+            vec![
+                LineChange::Changed {
+                    line_number: 20,
+                    old: "20 GOTO 10".to_string(),
+                    new: "20 GOTO 30".to_string(),
+                },
+                LineChange::Added {
+                    line_number: 25,
+                    text: "25 CLS".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[cfg(feature = "filesystem-host")]
+    #[test]
+    fn test_pound_sign_in_program_file() {
+        let path = std::env::temp_dir().join("bbc_basic_pound.bbas");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "10 PRINT ASC(\"£\");\" \";LEN(\"£5\")\n").unwrap();
+        for mut interpreter in interpreters() {
+            run_program(&mut interpreter, &[&format!("10 CHAIN \"{}\"", path)]).unwrap();
+            assert_eq!(interpreter.executor().get_output(), "        96 2\n");
+        }
+        std::fs::remove_file(path).ok();
+    }
+
+    #[cfg(feature = "filesystem-host")]
+    #[test]
+    fn test_output_redirection() {
+        for (i, mut interpreter) in interpreters().into_iter().enumerate() {
+            let path = std::env::temp_dir().join(format!("bbc_basic_output_{}.txt", i));
+            let path = path.to_str().unwrap();
+            run_program(
+                &mut interpreter,
+                &[
+                    &format!("10 F% = OPENOUT(\"{}\")", path),
+                    "20 PRINT \"SCREEN\"",
+                    "30 OUTPUT #F%",
+                    "40 PRINT \"A\", \"B\";",
+                    "50 PRINT TAB(12);\"C\"",
+                    "60 OUTPUT #0",
+                    "70 PRINT \"BACK\"",
+                    "80 OUTPUT",
+                    "90 PRINT ~255",
+                    "100 CLOSE#F%",
+                    "110 PRINT \"DONE\"",
+                ],
+            )
+            .unwrap();
+            let file = std::fs::read_to_string(path).unwrap();
+            std::fs::remove_file(path).ok();
+            assert_eq!(file, "A         B C\n        FF\n");
+            assert_eq!(interpreter.executor().get_output(), "SCREEN\nBACK\nDONE\n");
+
+            let error = run_program(&mut interpreter, &["10 OUTPUT #9"]).unwrap_err();
+            assert!(error.contains("Channel"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_resident_integers() {
+        for mut interpreter in interpreters() {
+            interpreter.process_line("A% = 42").unwrap();
+            run_program(&mut interpreter, &["10 PRINT A%", "20 B% = 7", "30 Q = 2"]).unwrap();
+            interpreter.new_program();
+            run_program(&mut interpreter, &["10 PRINT B%", "20 PRINT Z%"]).unwrap();
+            assert_eq!(
+                interpreter.executor().get_output(),
+                "        42\n         7\n         0\n"
+            );
+            interpreter.new_program();
+            let error = run_program(&mut interpreter, &["10 PRINT Q"]).unwrap_err();
+            assert!(error.contains("No such variable"), "{}", error);
+            interpreter.new_program();
+            let error =
+                run_program(&mut interpreter, &["10 Q = 1", "20 CLEAR", "30 PRINT Q"]).unwrap_err();
+            assert!(error.contains("No such variable"), "{}", error);
+
+            // They live at &400 on, four bytes each from @%
+            interpreter.new_program();
+            interpreter.executor_mut().clear_output();
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 !&404 = 99",
+                    "20 PRINT A%",
+                    "30 C% = &01020304",
+                    "40 PRINT ?&40C",
+                    "50 ?&40F = 5",
+                    "60 PRINT ~C%",
+                ],
+            )
+            .unwrap();
+            assert_eq!(
+                interpreter.executor().get_output(),
+                "        99\n         4\n   5020304\n"
+            );
+            let dump = interpreter.memory_dump("404 +4").unwrap();
+            assert!(dump[0].contains("63 00 00 00"), "{:?}", dump);
+
+            // @% sets how PRINT, and STR$ if its top byte is set, show reals
+            interpreter.new_program();
+            interpreter.executor_mut().clear_output();
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 @% = &20208",
+                    "20 PRINT 3.14159, 2",
+                    "30 PRINT STR$(2.5)",
+                    "40 @% = &1020203",
+                    "50 PRINT STR$(2.5)",
+                    "60 @% = &1030A",
+                    "70 PRINT 1234.5",
+                    "80 @% = &90A",
+                    "90 X = 1 / 3",
+                    "100 PRINT X",
+                    "110 PRINT 1",
+                    "120 @% = &20205",
+                    "130 PRINT 3.14159",
+                    "140 PRINT 1;-2;~&FF,3;",
+                ],
+            )
+            .unwrap();
+            // Numbers are right-justified in the field, after the first
+            // semicolon only as far as the next comma
+            assert_eq!(
+                interpreter.executor().get_output(),
+                "    3.14       2\n2.5\n2.50\n    1.23E3\n0.333333333\n         1\n \
+                 3.14\n    1-2FF     3"
+            );
+        }
+    }
+
+    #[test]
+    fn test_safe_mode() {
+        for mut interpreter in interpreters() {
+            interpreter.configure("safe", "on").unwrap();
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 ON ERROR GOTO 100",
+                    "20 F% = OPENOUT(\"SAFE\")",
+                    "30 CALL &FFEE",
+                    "40 A$ = FNoscli$(\"CAT\")",
+                    "50 CHAIN \"http://example.com/GAME\"",
+                    "60 ON ERROR OFF",
+                    "70 END",
+                    "100 E% = ERR",
+                    "110 PRINT E%;\" \";REPORT$",
+                    "120 N% = N% + 1",
+                    "130 ON N% GOTO 30, 40, 50, 60",
+                ],
+            )
+            .unwrap();
+            assert_eq!(
+                interpreter.executor().get_output(),
+                "        49 Not allowed\n".repeat(4)
+            );
+            assert!(!std::path::Path::new("SAFE").exists());
+
+            interpreter
+                .configure("strict.string_length", "off")
+                .unwrap();
+            interpreter.new_program();
+            let error =
+                run_program(&mut interpreter, &["10 A$ = STRING$(300, \"X\")"]).unwrap_err();
+            assert!(error.contains("String too long"), "{}", error);
+
+            interpreter.configure("safe.max_depth", "10").unwrap();
+            interpreter.new_program();
+            let error = run_program(
+                &mut interpreter,
+                &["10 X% = FNr(0)", "20 END", "30 DEF FNr(N%) = FNr(N% + 1)"],
+            )
+            .unwrap_err();
+            assert!(error.contains("No room"), "{}", error);
+
+            // Limits stop a program even with an error handler
+            interpreter.configure("safe.max_statements", "100").unwrap();
+            interpreter.new_program();
+            let error = run_program(
+                &mut interpreter,
+                &["10 ON ERROR GOTO 30", "20 GOTO 20", "30 GOTO 20"],
+            )
+            .unwrap_err();
+            assert!(error.contains("Statement limit reached"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_vdu_state() {
+        for mut interpreter in interpreters() {
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 MODE 1",
+                    "20 COLOUR 2",
+                    "30 COLOUR 129",
+                    "40 GCOL 3, 1",
+                    "50 VDU 19, 1, 4, 0, 0, 0",
+                    "60 ORIGIN 100, -50",
+                    "70 PRINT FNvdu(&55);FNvdu(&57);FNvdu(&58);FNvdu(&59);FNvdu(&5B);FNvdu(&60)",
+                    "80 PRINT FNvdu(&0C);\" \";FNvdu(&0E);\" \";FNvdu(&0A)",
+                    "90 DIM B% 4",
+                    "100 X% = B% MOD 256",
+                    "110 Y% = B% DIV 256",
+                    "120 ?B% = 1",
+                    "130 A% = &0B",
+                    "140 CALL &FFF1",
+                    "150 PRINT B%?1",
+                    "160 B%?1 = 5",
+                    "170 A% = &0C",
+                    "180 CALL &FFF1",
+                    "190 A% = &87",
+                    "200 PRINT ~USR(&FFF4)",
+                    "210 A% = 65",
+                    "220 CALL &FFEE",
+                    "230 CALL &FFE7",
+                ],
+            )
+            .unwrap();
+            assert_eq!(
+                interpreter.executor().get_output(),
+                "         121133\n       100 -50 39\n         4\n     12087\nA\n\r"
+            );
+            let colours = interpreter.executor().colours();
+            assert_eq!((colours.physical(1), colours.text_foreground), (5, 2));
+
+            let error = run_program(&mut interpreter, &["10 CALL &3000"]).unwrap_err();
+            assert!(error.contains("Bad call"), "{}", error);
+            let error = run_program(&mut interpreter, &["10 X% = FNvdu(200)"]).unwrap_err();
+            assert!(error.contains("Bad call"), "{}", error);
+        }
+    }
+
+    #[cfg(feature = "filesystem-host")]
+    #[test]
+    fn test_program_slots() {
+        for mut interpreter in interpreters() {
+            interpreter.process_line("10 PRINT \"MAIN\"").unwrap();
+            interpreter.switch_slot("sut").unwrap();
+            assert!(interpreter.program().is_empty());
+            interpreter.process_line("10 PRINT \"SUT\"").unwrap();
+            interpreter.process_line("20 END").unwrap();
+            assert_eq!(interpreter.slot(), "SUT");
+            assert_eq!(
+                interpreter.slots(),
+                [("MAIN".to_string(), 1), ("SUT".to_string(), 2)]
+            );
+            interpreter.switch_slot("Main").unwrap();
+            assert_eq!(interpreter.list_line(10).unwrap(), "10 PRINT \"MAIN\"");
+            assert!(interpreter.switch_slot("TWO WORDS").is_err());
+
+            // A test harness in MAIN drives the program under test in SUT
+            interpreter.new_program();
+            for line in [
+                "10 PRINT \"HARNESS \"; T%",
+                "20 IF T% < 2 THEN",
+                "30 CHAIN \"sut\"",
+                "40 ENDIF",
+                "50 PRINT \"PASSED\"",
+            ] {
+                interpreter.process_line(line).unwrap();
+            }
+            interpreter.switch_slot("SUT").unwrap();
+            interpreter.new_program();
+            for line in [
+                "10 T% = T% + 1",
+                "20 PRINT \"TEST \"; T%",
+                "30 CHAIN \"MAIN\"",
+            ] {
+                interpreter.process_line(line).unwrap();
+            }
+            interpreter.switch_slot("MAIN").unwrap();
+            interpreter.process_line("T% = 0").unwrap();
+            interpreter.run().unwrap();
+            assert_eq!(
+                interpreter.executor().get_output(),
+                "HARNESS 0\nTEST 1\nHARNESS 1\nTEST 2\nHARNESS 2\nPASSED\n"
+            );
+            assert_eq!(interpreter.slot(), "MAIN");
+
+            let error = run_program(&mut interpreter, &["10 CHAIN \"NOPROG\""]).unwrap_err();
+            assert!(error.starts_with("Disk error: Cannot read"), "{}", error);
+        }
+    }
+
+    #[cfg(feature = "filesystem-host")]
+    #[test]
+    fn test_text_file_lines() {
+        for (i, mut interpreter) in interpreters().into_iter().enumerate() {
+            let path = std::env::temp_dir().join(format!("bbc_basic_lines_{}.txt", i));
+            let path = path.to_str().unwrap();
+            run_program(
+                &mut interpreter,
+                &[
+                    &format!("10 F% = OPENOUT(\"{}\")", path),
+                    "20 BPUT#F%, \"ONE, \";",
+                    "30 BPUT#F%, \"TWO\"",
+                    "40 BPUT#F%, \"THREE\";",
+                    "50 BPUT#F%, 13",
+                    "60 BPUT#F%, 10",
+                    "70 BPUT#F%, \"FOUR\";",
+                    "72 BPUT#F%, 13",
+                    "74 BPUT#F%, \"FIVE\";",
+                    "80 CLOSE#F%",
+                    &format!("90 F% = OPENIN(\"{}\")", path),
+                    "100 REPEAT",
+                    "110 PRINT GET$#F%",
+                    "120 UNTIL EOF#F%",
+                    "130 CLOSE#F%",
+                ],
+            )
+            .unwrap();
+            std::fs::remove_file(path).ok();
+            assert_eq!(
+                interpreter.executor().get_output(),
+                "ONE, TWO\nTHREE\nFOUR\nFIVE\n"
+            );
+        }
+    }
+
+    #[cfg(feature = "filesystem-host")]
+    #[test]
+    fn test_update_file_length() {
+        for (i, mut interpreter) in interpreters().into_iter().enumerate() {
+            let path = std::env::temp_dir().join(format!("bbc_basic_update_{}.dat", i));
+            let path = path.to_str().unwrap();
+            std::fs::write(path, b"ABCDEFGH").unwrap();
+            run_program(
+                &mut interpreter,
+                &[
+                    &format!("10 F% = OPENUP(\"{}\")", path),
+                    "20 EXT#F% = 3",
+                    "30 PTR#F% = EXT#F% + 2",
+                    "40 BPUT#F%, 90",
+                    "50 L% = EXT#F%",
+                    "60 PTR#F% = 0",
+                    "70 K% = BGET#F%",
+                    "80 CLOSE#F%",
+                ],
+            )
+            .unwrap();
+            let executor = interpreter.executor();
+            assert_eq!(executor.get_variable_int("L%").unwrap(), 6);
+            assert_eq!(executor.get_variable_int("K%").unwrap(), 65);
+            assert_eq!(std::fs::read(path).unwrap(), b"ABC\0\0Z");
+            std::fs::remove_file(path).ok();
+        }
+    }
+
+    #[cfg(feature = "filesystem-host")]
+    #[test]
+    fn test_files_closed_when_program_stops() {
+        for (i, mut interpreter) in interpreters().into_iter().enumerate() {
+            let path = std::env::temp_dir().join(format!("bbc_basic_unclosed_{}.dat", i));
+            let path = path.to_str().unwrap();
+            run_program(
+                &mut interpreter,
+                &[
+                    &format!("10 F% = OPENOUT(\"{}\")", path),
+                    "20 BPUT#F%, 65",
+                    "30 G% = OPENIN(\"no such file\")",
+                ],
+            )
+            .unwrap_err();
+            assert_eq!(interpreter.executor().open_file_count(), 0);
+            assert_eq!(std::fs::read(path).unwrap(), b"A");
+
+            // With closing turned off the file stays open until NEW or CLOSE#0
+            interpreter.configure("close_files", "off").unwrap();
+            interpreter.process_line("30 END").unwrap();
+            interpreter.run().unwrap();
+            assert_eq!(interpreter.executor().open_file_count(), 1);
+            interpreter.process_line("CLOSE#0").unwrap();
+            assert_eq!(interpreter.executor().open_file_count(), 0);
+            std::fs::remove_file(path).ok();
+        }
+    }
+
+    #[cfg(feature = "filesystem-host")]
+    #[test]
+    fn test_bbc_filenames_in_sandbox() {
+        for (i, mut interpreter) in interpreters().into_iter().enumerate() {
+            let root = std::env::temp_dir().join(format!("bbc_basic_sandbox_{}", i));
+            interpreter
+                .configure("root", root.to_str().unwrap())
+                .unwrap();
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 F% = OPENOUT(\":0.D.SCORES\")",
+                    "20 BPUT#F%, 66",
+                    "30 CLOSE#F%",
+                    "40 F% = OPENIN(\"D.SCORES\")",
+                    "50 K% = BGET#F%",
+                    "60 CLOSE#F%",
+                ],
+            )
+            .unwrap();
+            assert_eq!(interpreter.executor().get_variable_int("K%").unwrap(), 66);
+            assert_eq!(std::fs::read(root.join("D").join("SCORES")).unwrap(), b"B");
+
+            // Escaping the root or overlong names fail with Bad name
+            for name in ["../outside", "D.TOOLONGNAME"] {
+                interpreter
+                    .process_line(&format!("10 F% = OPENIN(\"{}\")", name))
+                    .unwrap();
+                let error = interpreter.run().unwrap_err();
+                assert!(error.contains("Bad name"), "{}", error);
+            }
+            std::fs::remove_dir_all(&root).ok();
+        }
+    }
+
+    #[cfg(feature = "filesystem-host")]
+    #[test]
+    fn test_oscli_captures_star_commands() {
+        for (i, mut interpreter) in interpreters().into_iter().enumerate() {
+            let root = std::env::temp_dir().join(format!("bbc_basic_oscli_{}", i));
+            std::fs::create_dir_all(&root).unwrap();
+            std::fs::write(root.join("GAME.bbas"), "10 END\n").unwrap();
+            interpreter
+                .configure("root", root.to_str().unwrap())
+                .unwrap();
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 N% = VAL(FNoscli$(\"CAT\", 0))",
+                    "20 L$ = FNoscli$(\"*CAT\", 5)",
+                    "30 R$ = FNoscli$(\"RENAME GAME.bbas OLD.bbas\")",
+                    "40 D$ = FNoscli$(\"*DELETE OLD.bbas\")",
+                    "50 C$ = FNoscli$(\"*.\")",
+                ],
+            )
+            .unwrap();
+            let executor = interpreter.executor();
+            assert_eq!(executor.get_variable_int("N%").unwrap(), 7);
+            assert!(executor
+                .get_variable_string("L$")
+                .unwrap()
+                .starts_with("GAME.bbas "));
+            assert_eq!(executor.get_variable_string("R$").unwrap(), "");
+            assert!(executor
+                .get_variable_string("C$")
+                .unwrap()
+                .contains("(no .bbas files found)"));
+            assert!(std::fs::read_dir(&root).unwrap().next().is_none());
+
+            interpreter
+                .process_line("10 X$ = FNoscli$(\"NOSUCH\")")
+                .unwrap();
+            let error = interpreter.run().unwrap_err();
+            assert!(error.contains("Bad command"), "{}", error);
+            std::fs::remove_dir_all(&root).ok();
+        }
+    }
+
+    #[cfg(feature = "speech")]
+    #[test]
+    fn test_say_queues_phrases() {
+        let mut interpreter = Interpreter::new();
+        run_program(
+            &mut interpreter,
+            &[
+                "10 A$ = FNoscli$(\"SAY HELLO\")",
+                "20 A$ = FNoscli$(\"*SAY GOODBYE\")",
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            interpreter.executor().speech().transcript(),
+            ["HELLO", "GOODBYE"]
+        );
+    }
+
+    #[cfg(feature = "filesystem-host")]
+    #[test]
+    fn test_libraries_and_merge() {
+        let dir = std::env::temp_dir().join("bbc_basic_libraries");
+        std::fs::create_dir_all(&dir).unwrap();
+        let library = dir.join("shapes.bbas");
+        std::fs::write(
+            &library,
+            "DEF PROCarea(W%, H%)\nA% = W% * H%\nENDPROC\nDEF FNdouble(X%) = X% * 2\n",
+        )
+        .unwrap();
+        let extra = dir.join("extra.bbas");
+        std::fs::write(&extra, "20 B% = 2\n30 C% = 3\n").unwrap();
+
+        for mut interpreter in interpreters() {
+            // Library line numbers do not clash with the program's
+            run_program(
+                &mut interpreter,
+                &[
+                    &format!("10 LIBRARY \"{}\"", library.display()),
+                    "20 PROCarea(3, 4)",
+                    "30 D% = FNdouble(A%)",
+                    "40 E% = 1",
+                ],
+            )
+            .unwrap();
+            let executor = interpreter.executor();
+            assert_eq!(executor.get_variable_int("A%").unwrap(), 12);
+            assert_eq!(executor.get_variable_int("D%").unwrap(), 24);
+            assert_eq!(executor.get_variable_int("E%").unwrap(), 1);
+            assert_eq!(interpreter.list().len(), 4);
+
+            // A LIBRARY lasts until the next RUN; INSTALL lasts
+            interpreter.process_line("10 REM").unwrap();
+            let error = interpreter.run().unwrap_err();
+            assert!(error.contains("Procedure area not defined"), "{}", error);
+            assert!(interpreter.program().library_names().is_empty());
+            interpreter
+                .process_line(&format!("INSTALL \"{}\"", library.display()))
+                .unwrap();
+            interpreter.run().unwrap();
+            interpreter.run().unwrap();
+            assert_eq!(interpreter.program().library_names().len(), 1);
+
+            // *MERGE replaces lines with the same number and keeps the rest
+            interpreter.merge(extra.to_str().unwrap()).unwrap();
+            interpreter.run().unwrap();
+            let executor = interpreter.executor();
+            assert_eq!(executor.get_variable_int("B%").unwrap(), 2);
+            assert_eq!(executor.get_variable_int("C%").unwrap(), 3);
+            assert_eq!(interpreter.list().len(), 4);
+
+            // A missing library can be trapped with ON ERROR
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 ON ERROR GOTO 40",
+                    "20 LIBRARY \"no_such_library\"",
+                    "30 END",
+                    "40 F% = 1",
+                ],
+            )
+            .unwrap();
+            assert_eq!(interpreter.executor().get_variable_int("F%").unwrap(), 1);
+        }
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_debugger_steps() {
+        let program = [
+            "10 A% = 1",
+            "20 PROCset",
+            "30 GOSUB 100",
+            "40 A% = 4",
+            "50 END",
+            "60 DEF PROCset",
+            "70 A% = 2",
+            "80 ENDPROC",
+            "100 A% = 3",
+            "110 RETURN",
+        ];
+        // Debugged programs run on the tree backend whatever is configured
+        for mut interpreter in interpreters() {
+            for line in program {
+                interpreter.process_line(line).unwrap();
+            }
+            assert!(interpreter.step(Step::Into).is_err());
+            interpreter.debugger_mut().set_breakpoint(20);
+
+            // Step over the PROC call and into the GOSUB, then out of it
+            assert_eq!(interpreter.debug(), Ok(Pause::Break(20)));
+            assert_eq!(interpreter.step(Step::Over), Ok(Pause::Break(30)));
+            assert_eq!(interpreter.executor().get_variable_int("A%").unwrap(), 2);
+            assert_eq!(interpreter.step(Step::Into), Ok(Pause::Break(100)));
+            assert_eq!(interpreter.step(Step::Out), Ok(Pause::Break(40)));
+            assert_eq!(interpreter.executor().get_variable_int("A%").unwrap(), 3);
+            assert_eq!(interpreter.step(Step::Continue), Ok(Pause::Ended));
+            assert_eq!(interpreter.debugger().paused_at(), None);
+
+            // A breakpoint inside a PROC stops a step over its call
+            interpreter.debugger_mut().set_breakpoint(70);
+            assert_eq!(interpreter.debug(), Ok(Pause::Break(20)));
+            assert_eq!(interpreter.step(Step::Over), Ok(Pause::Break(70)));
+            assert_eq!(interpreter.step(Step::Out), Ok(Pause::Break(30)));
+            assert_eq!(interpreter.list_line(30).as_deref(), Some("30 GOSUB 100"));
+        }
+    }
+
+    #[test]
+    fn test_calculator_mode_after_error() {
+        for mut interpreter in interpreters() {
+            let error = run_program(
+                &mut interpreter,
+                &[
+                    "10 X% = 0",
+                    "20 Y% = 10 DIV X%",
+                    "30 Z% = Y% + FNscore(1)",
+                    "40 END",
+                    "50 DEF FNscore(N%) = N% * 100",
+                ],
+            )
+            .unwrap_err();
+            assert!(error.contains("line 20"), "{}", error);
+            assert_eq!(interpreter.debugger().paused_at(), Some(20));
+
+            // Expressions and assignments work on the stopped program's
+            // variables, then CONT runs the failing line again
+            interpreter.executor_mut().clear_output();
+            interpreter.process_line("FNscore(3) + X%").unwrap();
+            assert_eq!(interpreter.executor().get_output().trim(), "300");
+            interpreter.process_line("X% = 2").unwrap();
+            assert_eq!(interpreter.step(Step::Continue), Ok(Pause::Ended));
+            assert_eq!(interpreter.executor().get_variable_int("Z%").unwrap(), 105);
+
+            // Bare expressions are only accepted while a program is stopped
+            assert!(interpreter.process_line("X% + 1").is_err());
+            assert!(interpreter.step(Step::Continue).is_err());
+        }
+    }
+
+    #[test]
+    fn test_no_room_below_himem() {
+        for mut interpreter in interpreters() {
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 DIM A%(9)",
+                    "20 T% = TOP",
+                    "30 L% = LOMEM",
+                    "40 H% = HIMEM",
+                ],
+            )
+            .unwrap();
+            let status = interpreter.memory_status();
+            assert_eq!(status.page, 0x1900);
+            assert_eq!(status.himem, 0x7C00);
+            let executor = interpreter.executor();
+            assert_eq!(executor.get_variable_int("T%").unwrap(), status.top as i32);
+            assert_eq!(
+                executor.get_variable_int("L%").unwrap(),
+                status.lomem as i32
+            );
+            assert_eq!(executor.get_variable_int("H%").unwrap(), 0x7C00);
+            assert_eq!((status.integers, status.arrays), (3, 1));
+
+            // The memory map prints too
+            interpreter
+                .process_line("PRINT PAGE, ~HIMEM, TOP, LOMEM")
+                .unwrap();
+            assert_eq!(
+                interpreter.executor().get_output(),
+                format!(
+                    "{:>10}{:>10X}{:>10}{:>10}\n",
+                    status.page, status.himem, status.top, status.lomem
+                )
+            );
+
+            // Keep typing lines until MODE 7's 25K is used up
+            let text = "X".repeat(200);
+            let mut line_number = 100;
+            let error = loop {
+                match interpreter.process_line(&format!("{} REM {}", line_number, text)) {
+                    Ok(()) => line_number += 10,
+                    Err(e) => break e,
+                }
+            };
+            assert_eq!(error, "No room");
+            assert!(interpreter.program().get_line(line_number).is_none());
+            assert!(interpreter.memory_status().free < 210);
+
+            // Screen memory for MODE 0 would overwrite the program, and a
+            // new array will not fit either
+            assert!(interpreter.process_line("MODE 0").is_err());
+            assert!(interpreter.process_line("DIM B(100)").is_err());
+            interpreter.new_program();
+            interpreter.process_line("MODE 0").unwrap();
+            assert_eq!(interpreter.memory_status().himem, 0x3000);
+        }
+    }
+
+    #[cfg(feature = "filesystem-host")]
+    #[test]
+    fn test_save_and_load_memory() {
+        let path = std::env::temp_dir().join("bbc_basic_memory_block.bin");
+        let mut interpreter = Interpreter::new();
+        let memory = interpreter.executor_mut().memory_mut();
+        memory.load_block(0x3000, b"HELLO").unwrap();
+
+        let name = path.display();
+        interpreter
+            .save_memory(&format!("\"{}\" 3000 +5 3002", name))
+            .unwrap();
+        let inf = std::fs::read_to_string(format!("{}.inf", name)).unwrap();
+        assert!(inf.contains(" 00003000 00003002 00000005 CRC="), "{}", inf);
+        interpreter
+            .save_memory(&format!("\"{}\" 3000 &3005", name))
+            .unwrap();
+
+        // The file goes back to its load address, or to the one given
+        let memory = interpreter.executor_mut().memory_mut();
+        memory.load_block(0x3000, &[0; 5]).unwrap();
+        assert_eq!(
+            interpreter.load_memory(&format!("\"{}\"", name)),
+            Ok((0x3000, 5))
+        );
+        assert_eq!(
+            interpreter.load_memory(&format!("\"{}\" 7FF0", name)),
+            Ok((0x7FF0, 5))
+        );
+        let memory = interpreter.executor().memory();
+        assert_eq!(memory.block(0x3000, 5).unwrap(), b"HELLO");
+        assert_eq!(memory.block(0x7FF0, 5).unwrap(), b"HELLO");
+
+        // Nothing goes past &7FFF
+        assert!(interpreter
+            .load_memory(&format!("\"{}\" 7FFE", name))
+            .is_err());
+        assert!(interpreter
+            .save_memory(&format!("\"{}\" 7FF0 8010", name))
+            .is_err());
+        assert!(interpreter
+            .save_memory(&format!("\"{}\" 3005 3000", name))
+            .is_err());
+        assert!(interpreter
+            .save_memory(&format!("\"{}\" 3000", name))
+            .is_err());
+    }
+
+    #[test]
+    fn test_memory_dump_and_set() {
+        let mut interpreter = Interpreter::new();
+        assert_eq!(interpreter.memory_set("3000 48 49 &0D"), Ok(3));
+        assert_eq!(
+            interpreter.memory_dump("3000 +4").unwrap(),
+            vec!["3000 48 49 0D 00             HI.."]
+        );
+        assert_eq!(interpreter.memory_dump("&3000").unwrap().len(), 16);
+        assert_eq!(interpreter.memory_dump("2FF8 3003").unwrap().len(), 2);
+
+        // Dumps stop at &7FFF, but writes must fit below it
+        assert_eq!(interpreter.memory_dump("7FFC").unwrap().len(), 1);
+        assert!(interpreter.memory_set("7FFF 1 2").is_err());
+        assert!(interpreter.memory_set("3000 100").is_err());
+        assert!(interpreter.memory_set("3000").is_err());
+        assert!(interpreter.memory_dump("3000 2000").is_err());
+    }
+
+    #[test]
+    fn test_mouse() {
+        use crate::os::{MOUSE_LEFT, MOUSE_MIDDLE};
+        use crate::variables::Variable;
+        for mut interpreter in interpreters() {
+            interpreter.mouse_event(0.25, 0.5, MOUSE_LEFT | MOUSE_MIDDLE);
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 DIM P(2)",
+                    "20 MOUSE ON",
+                    "30 MOUSE X%, Y%, B%",
+                    "40 ORIGIN 100, 12",
+                    "50 MOUSE P(0), P(1), C",
+                ],
+            )
+            .unwrap();
+            let executor = interpreter.executor();
+            let int = |name| executor.get_variable_int(name).unwrap();
+            assert_eq!([int("X%"), int("Y%"), int("B%")], [320, 512, 6]);
+            assert_eq!(executor.get_variable_real("C").unwrap(), 6.0);
+            let element = |i| executor.variables().get_array_element("P", &[i]).unwrap();
+            assert_eq!(
+                [element(0), element(1)],
+                [Variable::Real(220.0), Variable::Real(500.0)]
+            );
+            assert!(executor.os().pointer().visible);
+
+            run_program(&mut interpreter, &["10 MOUSE OFF", "20 MOUSE A$, B$, C$"]).unwrap_err();
+            assert!(!interpreter.executor().os().pointer().visible);
+        }
+    }
+
+    #[test]
+    fn test_frame_sync() {
+        use crate::events::OutputEvent;
+        use std::sync::{Arc, Mutex};
+        for backend in [Backend::Tree, Backend::Bytecode] {
+            let mut interpreter = Interpreter::with_config(Config {
+                backend,
+                deterministic: true,
+                refresh_rate: 25,
+                ..Default::default()
+            });
+            let frames = Arc::new(Mutex::new(0));
+            let seen = Arc::clone(&frames);
+            interpreter.subscribe(Box::new(move |event: &OutputEvent| {
+                if *event == OutputEvent::Frame {
+                    *seen.lock().unwrap() += 1;
+                }
+            }));
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 WAIT 1",
+                    "20 WAIT",
+                    "30 T1% = TIME",
+                    "40 D$ = FNoscli$(\"*FX 19\")",
+                    "50 A% = 19",
+                    "60 CALL &FFF4",
+                    "70 *FX 19",
+                    "80 T2% = TIME",
+                ],
+            )
+            .unwrap();
+            // Frames start every four centiseconds at 25 Hz
+            let executor = interpreter.executor();
+            assert_eq!(executor.get_variable_int("T1%").unwrap(), 4);
+            assert_eq!(executor.get_variable_int("T2%").unwrap(), 16);
+            assert_eq!(*frames.lock().unwrap(), 4);
+        }
+    }
+
+    #[cfg(feature = "graphics")]
+    #[test]
+    fn test_shadow_screen() {
+        use crate::graphics::Canvas;
+        for backend in [Backend::Tree, Backend::Bytecode] {
+            let mut interpreter = Interpreter::with_config(Config {
+                backend,
+                deterministic: true,
+                ..Default::default()
+            });
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 *SHADOW 1",
+                    "20 MOVE 0, 0",
+                    "30 DRAW 100, 0",
+                    "35 P% = POINT(50, 0)",
+                    "40 WAIT",
+                    "50 B% = POINT(50, 0)",
+                    "60 CLG",
+                    "70 C% = POINT(50, 0)",
+                    "80 A% = 114",
+                    "85 X% = 1",
+                    "88 CALL &FFF4",
+                    "90 D% = POINT(50, 0)",
+                ],
+            )
+            .unwrap();
+            // POINT sees the picture on show, not the one being drawn
+            let executor = interpreter.executor();
+            let point = |name| executor.get_variable_int(name).unwrap();
+            assert_eq!(
+                [point("P%"), point("B%"), point("C%"), point("D%")],
+                [0, -1, -1, 0]
+            );
+            assert!(!executor.graphics().is_double_buffered());
+            assert!(interpreter.executor_mut().oscli("SHADOW 2").is_err());
+        }
+    }
+
+    #[test]
+    fn test_deterministic_runs() {
+        let program = [
+            "10 A = RND(1000)",
+            "20 B = RND(1)",
+            "30 T1% = TIME",
+            "40 WAIT 150",
+            "50 K% = INKEY(25)",
+            "60 WAIT",
+            "70 T2% = TIME",
+        ];
+        let results = |interpreter: &Interpreter| {
+            let executor = interpreter.executor();
+            (
+                executor.get_variable_real("A").unwrap(),
+                executor.get_variable_real("B").unwrap(),
+                executor.get_variable_int("T1%").unwrap(),
+                executor.get_variable_int("K%").unwrap(),
+                executor.get_variable_int("T2%").unwrap(),
+            )
+        };
+
+        let started = Instant::now();
+        let mut seen = Vec::new();
+        for backend in [Backend::Tree, Backend::Bytecode] {
+            for seed in [1, 1, 2] {
+                let mut interpreter = Interpreter::with_config(Config {
+                    backend,
+                    deterministic: true,
+                    seed,
+                    ..Default::default()
+                });
+                run_program(&mut interpreter, &program).unwrap();
+                let first = results(&interpreter);
+                // TIME only moves on by the time waited, WAIT alone to the
+                // next frame
+                assert_eq!((first.2, first.3, first.4), (0, -1, 176));
+
+                // Running again starts from the same seed and time
+                interpreter.run().unwrap();
+                assert_eq!(results(&interpreter), first);
+                seen.push((first.0, first.1));
+            }
+        }
+        assert_eq!(seen[0], seen[1]);
+        assert_ne!(seen[0], seen[2]);
+        assert_eq!(seen[..3], seen[3..]);
+        // Nothing really waited
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        // TIME reads into a real variable and prints as well
+        for backend in [Backend::Tree, Backend::Bytecode] {
+            let mut interpreter = Interpreter::with_config(Config {
+                backend,
+                deterministic: true,
+                ..Default::default()
+            });
+            run_program(
+                &mut interpreter,
+                &["10 WAIT 25", "20 T = TIME", "30 PRINT TIME;\" \";T / 5"],
+            )
+            .unwrap();
+            let executor = interpreter.executor();
+            assert_eq!(executor.get_variable_real("T").unwrap(), 25.0);
+            assert_eq!(executor.get_output(), "        25 5\n");
+        }
+    }
+
+    #[test]
+    fn test_output_events() {
+        use crate::events::{GraphicsOp, OutputEvent, QueuedSound};
+        use std::sync::{Arc, Mutex};
+
+        for mut interpreter in interpreters() {
+            let events = Arc::new(Mutex::new(Vec::new()));
+            let seen = Arc::clone(&events);
+            interpreter.subscribe(Box::new(move |event: &OutputEvent| {
+                seen.lock().unwrap().push(event.clone())
+            }));
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 MODE 1",
+                    "20 PRINT \"HI\";",
+                    "30 SOUND 1, -15, 53, 20",
+                    "40 MOVE 0, 0",
+                    "50 DRAW 100, 200",
+                ],
+            )
+            .unwrap();
+            assert_eq!(
+                *events.lock().unwrap(),
+                vec![
+                    OutputEvent::Cls,
+                    OutputEvent::ModeChange(1),
+                    OutputEvent::Print("HI".to_string()),
+                    OutputEvent::SoundQueued(QueuedSound {
+                        channel: 1,
+                        amplitude: -15,
+                        pitch: 53,
+                        duration: 20,
+                    }),
+                    OutputEvent::GraphicsOp(GraphicsOp::Move { x: 0, y: 0 }),
+                    OutputEvent::GraphicsOp(GraphicsOp::Draw { x: 100, y: 200 }),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_oswrch_sees_the_vdu_byte_stream() {
+        use std::sync::{Arc, Mutex};
+
+        for mut interpreter in interpreters() {
+            let bytes = Arc::new(Mutex::new(Vec::new()));
+            let seen = Arc::clone(&bytes);
+            interpreter.subscribe_oswrch(Box::new(move |byte: u8| seen.lock().unwrap().push(byte)));
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 MODE 1",
+                    "20 PRINT \"HI\"",
+                    "30 VDU 7, 31, 2, 3",
+                    "40 GCOL 0, 2",
+                    "50 MOVE 100, 300",
+                    "60 DRAW BY -200, 0",
+                    "70 CLS",
+                ],
+            )
+            .unwrap();
+            assert_eq!(
+                *bytes.lock().unwrap(),
+                vec![
+                    22, 1, b'H', b'I', 10, 13, 7, 31, 2, 3, 18, 0, 2, 25, 4, 100, 0, 44, 1, 25, 1,
+                    56, 255, 0, 0, 12,
+                ]
+            );
+        }
+    }
+
+    #[cfg(feature = "graphics")]
+    #[test]
+    fn test_vdu5_prints_at_the_graphics_cursor() {
+        use crate::events::{GraphicsOp, OutputEvent};
+        use crate::graphics::Canvas;
+        use std::sync::{Arc, Mutex};
+
+        for mut interpreter in interpreters() {
+            let events = Arc::new(Mutex::new(Vec::new()));
+            let seen = Arc::clone(&events);
+            interpreter.subscribe(Box::new(move |event: &OutputEvent| {
+                if let OutputEvent::GraphicsOp(GraphicsOp::Char { x, y, code }) = event {
+                    seen.lock().unwrap().push((*x, *y, *code));
+                }
+            }));
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 MODE 1",
+                    "20 VDU 5",
+                    "30 MOVE 100, 500",
+                    "40 PRINT \"HI\"",
+                    "50 VDU 4",
+                    "60 PRINT \"TEXT\"",
+                ],
+            )
+            .unwrap();
+            // Mode 1 cells are 32 graphics units square
+            assert_eq!(
+                *events.lock().unwrap(),
+                vec![(100, 500, b'H'), (132, 500, b'I')]
+            );
+            let executor = interpreter.executor();
+            let graphics = executor.graphics();
+            // The left upright of the H, and the gap in its middle
+            assert_eq!(graphics.get_pixel(100, 500), Some(true));
+            assert_eq!(graphics.get_pixel(110, 500), Some(false));
+            // The newline went to the start of the next character row
+            assert_eq!(graphics.get_position(), (0, 468));
+            // Only the text printed after VDU 4 reached the text screen
+            assert_eq!(executor.get_output(), "TEXT\n");
+        }
+    }
+
+    #[test]
+    fn test_inserted_keys() {
+        for mut interpreter in interpreters() {
+            assert_eq!(interpreter.insert_keys("YxQUIT\n42\n"), 10);
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 K% = GET",
+                    "20 K$ = GET$",
+                    "30 INPUT C$, N%",
+                    "40 I% = INKEY(0)",
+                ],
+            )
+            .unwrap();
+            let executor = interpreter.executor();
+            assert_eq!(executor.get_variable_int("K%").unwrap(), 89);
+            assert_eq!(executor.get_variable_string("K$").unwrap(), "x");
+            assert_eq!(executor.get_variable_string("C$").unwrap(), "QUIT");
+            assert_eq!(executor.get_variable_int("N%").unwrap(), 42);
+            assert_eq!(executor.get_variable_int("I%").unwrap(), -1);
+
+            // *FX 15 discards keys typed ahead
+            interpreter.insert_keys("ABC");
+            interpreter.fx("15,0").unwrap();
+            assert!(interpreter.executor().os().keyboard().is_empty());
+            interpreter.fx("138,0,65").unwrap();
+            interpreter.process_line("A% = INKEY(10)").unwrap();
+            assert_eq!(interpreter.executor().get_variable_int("A%").unwrap(), 65);
+        }
+    }
+
+    #[test]
+    fn test_negative_inkey() {
+        let mut interpreter = Interpreter::new();
+        interpreter.process_line("A% = INKEY(-99)").unwrap();
+        assert_eq!(interpreter.executor().get_variable_int("A%").unwrap(), 0);
+
+        interpreter.key_event("Space", true);
+        interpreter.process_line("A% = INKEY(-99)").unwrap();
+        interpreter.process_line("B% = INKEY(-17)").unwrap();
+        assert_eq!(interpreter.executor().get_variable_int("A%").unwrap(), -1);
+        assert_eq!(interpreter.executor().get_variable_int("B%").unwrap(), 0);
+        interpreter.key_event("Space", false);
+
+        // A game's Z and X keys on the cursor keys
+        interpreter.configure("key.ArrowLeft", "Z").unwrap();
+        interpreter.key_event("ArrowLeft", true);
+        interpreter.process_line("A% = INKEY(-98)").unwrap();
+        assert_eq!(interpreter.executor().get_variable_int("A%").unwrap(), -1);
+    }
+
+    #[test]
+    fn test_jumps_to_missing_lines() {
+        let program = [
+            "10 A% = 0",
+            "20 IF A% THEN GOTO 500",
+            "30 ON A% GOSUB 10, 600",
+            "40 IF A% THEN RESTORE 700",
+            "50 GOTO 800",
+        ];
+        for mut interpreter in interpreters() {
+            // Only the jump that is made fails
+            let error = run_program(&mut interpreter, &program).unwrap_err();
+            assert_eq!(error, "No such line 800 at line 50");
+            assert_eq!(
+                interpreter.missing_lines(),
+                vec![(20, 500), (30, 600), (40, 700), (50, 800)]
+            );
+
+            // A strict RUN lists them all before starting
+            interpreter.configure("strict.line_numbers", "on").unwrap();
+            interpreter.process_line("A% = 1").unwrap();
+            let error = interpreter.run().unwrap_err();
+            assert_eq!(
+                error,
+                "No such line 500 at line 20, No such line 600 at line 30, \
+                 No such line 700 at line 40, No such line 800 at line 50"
+            );
+            assert_eq!(interpreter.executor().get_variable_int("A%").unwrap(), 1);
+        }
+    }
+
+    #[test]
+    fn test_immediate_goto() {
+        let mut interpreter = Interpreter::new();
+        assert_eq!(
+            interpreter.process_line("GOTO 10"),
+            Err("No such line".to_string())
+        );
+
+        // The program carries on from the line with its variables kept
+        for line in ["10 PRINT \"TEN\"", "20 PRINT A%", "A% = 42"] {
+            interpreter.process_line(line).unwrap();
+        }
+        interpreter.process_line("GOTO 20").unwrap();
+        assert_eq!(interpreter.executor().get_output(), "        42\n");
+    }
+
+    #[test]
+    fn test_input_answers() {
+        for mut interpreter in interpreters() {
+            // Quotes keep commas, too few answers ask again with "?", and a
+            // word given for a number is asked for again
+            interpreter.insert_keys("  \"SMITH, J\"\nten\n10, 20\n");
+            run_program(&mut interpreter, &["10 INPUT N$, A%, B"]).unwrap();
+            let executor = interpreter.executor();
+            assert_eq!(executor.get_variable_string("N$").unwrap(), "SMITH, J");
+            assert_eq!(executor.get_variable_int("A%").unwrap(), 10);
+            assert_eq!(executor.get_variable_real("B").unwrap(), 20.0);
+        }
+    }
+
+    #[test]
+    fn test_sliced_run_waits_for_input() {
+        let mut interpreter = Interpreter::new();
+        for line in [
+            "10 PRINT \"NAME\";",
+            "20 INPUT N$, A%",
+            "30 REPEAT",
+            "40 UNTIL GET = 32",
+            "50 K% = GET",
+        ] {
+            interpreter.process_line(line).unwrap();
+        }
+        assert_eq!(
+            interpreter.run_for(10),
+            Err("No program running".to_string())
+        );
+        interpreter.start().unwrap();
+        assert_eq!(interpreter.run_for(1), Ok(Pause::Yielded));
+        assert_eq!(interpreter.run_for(10), Ok(Pause::WaitingForInput));
+        assert_eq!(interpreter.executor().get_output(), "NAME? ");
+
+        // Answers typed a bit at a time carry on the same INPUT
+        interpreter.insert_keys("BO");
+        assert_eq!(interpreter.run_for(10), Ok(Pause::WaitingForInput));
+        interpreter.insert_keys("B\n4");
+        assert_eq!(interpreter.run_for(10), Ok(Pause::WaitingForInput));
+        assert_eq!(interpreter.executor().get_output(), "NAME? ? ");
+        interpreter.insert_keys("2\nxy");
+        assert_eq!(interpreter.run_for(10), Ok(Pause::WaitingForInput));
+        assert_eq!(
+            interpreter.executor().get_variable_string("N$").unwrap(),
+            "BOB"
+        );
+        assert_eq!(interpreter.executor().get_variable_int("A%").unwrap(), 42);
+
+        interpreter.insert_keys(" ");
+        assert_eq!(interpreter.run_for(10), Ok(Pause::WaitingForInput));
+        interpreter.insert_keys("Z");
+        assert_eq!(interpreter.run_for(10), Ok(Pause::Ended));
+        assert_eq!(interpreter.executor().get_variable_int("K%").unwrap(), 90);
+        assert_eq!(
+            interpreter.run_for(10),
+            Err("No program running".to_string())
+        );
+    }
+
+    #[test]
+    fn test_piped_input() {
+        #[derive(Debug)]
+        struct Piped(Vec<&'static str>);
+
+        impl LineInput for Piped {
+            fn read_line(&mut self) -> Option<String> {
+                self.0.pop().map(str::to_string)
+            }
+
+            fn is_interactive(&self) -> bool {
+                false
+            }
+        }
+
+        let program = [
+            "10 ON ERROR GOTO 50",
+            "20 INPUT L$",
+            "30 PRINT \">\";L$",
+            "40 GOTO 20",
+            "50 E% = ERR",
+            "60 PRINT E%",
+        ];
+        for mut interpreter in interpreters() {
+            interpreter.set_line_input(Box::new(Piped(vec!["TWO", "ONE"])));
+            run_program(&mut interpreter, &program).unwrap();
+            assert_eq!(
+                interpreter.executor().get_output(),
+                ">ONE\n>TWO\n       223\n"
+            );
+        }
+
+        let mut interpreter = Interpreter::new();
+        interpreter.configure("prompts", "on").unwrap();
+        interpreter.set_line_input(Box::new(Piped(vec!["ONE"])));
+        let error = run_program(&mut interpreter, &program[1..4]).unwrap_err();
+        assert!(error.contains("Eof"), "{}", error);
+        assert_eq!(interpreter.executor().get_output(), "? >ONE\n? ");
+    }
+
+    #[test]
+    fn test_runs_on_another_thread() {
+        #[derive(Debug)]
+        struct Typed(Vec<&'static str>);
+
+        impl LineInput for Typed {
+            fn read_line(&mut self) -> Option<String> {
+                self.0.pop().map(str::to_string)
+            }
+        }
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_line_input(Box::new(Typed(vec!["7", "ADA"])));
+        let mut interpreter = std::thread::spawn(move || {
+            run_program(&mut interpreter, &["10 INPUT N$, A%", "20 R = RND(1)"]).unwrap();
+            interpreter
+        })
+        .join()
+        .unwrap();
+        let executor = interpreter.executor();
+        assert_eq!(executor.get_variable_string("N$").unwrap(), "ADA");
+        assert_eq!(executor.get_variable_int("A%").unwrap(), 7);
+        assert_eq!(executor.get_output(), "? ? ");
+
+        // Once the line input has run out, GET gives up with Eof
+        let error = run_program(&mut interpreter, &["10 K% = GET"]).unwrap_err();
+        assert!(error.contains("Eof"), "{}", error);
+    }
+
+    #[test]
+    fn test_trapped_error_numbers_and_reports() {
+        for (statement, number, report) in [
+            ("X = 1 / 0", 18, "Division by zero"),
+            ("X% = 7 MOD 0", 18, "Division by zero"),
+            ("X = Q", 26, "No such variable"),
+            ("X = FNmissing", 29, "No such FN/PROC"),
+            ("X = D(4)", 15, "Subscript"),
+            ("X$ = 1", 6, "Type mismatch"),
+            ("X = SQR(-1)", 21, "-ve root"),
+            ("X = ASN(2)", 21, "-ve root"),
+            ("X = LN(0)", 22, "Log range"),
+            ("X$ = STRING$(300, \"A\")", 19, "String too long"),
+            ("X = BGET#99", 222, "Channel"),
+        ] {
+            for mut interpreter in interpreters() {
+                let program = [
+                    "10 ON ERROR GOTO 100",
+                    "20 DIM D(3)",
+                    &format!("30 {}", statement),
+                    "40 END",
+                    "100 E% = ERR",
+                    "110 R$ = REPORT$",
+                    "120 END",
+                ];
+                run_program(&mut interpreter, &program).unwrap();
+                let executor = interpreter.executor();
+                assert_eq!(
+                    (
+                        executor.get_variable_int("E%").unwrap(),
+                        executor.get_variable_string("R$").unwrap(),
+                    ),
+                    (number, report.to_string()),
+                    "{}",
+                    statement
+                );
+            }
+        }
+
+        // REPORT prints the message too
+        let mut interpreter = Interpreter::new();
+        run_program(
+            &mut interpreter,
+            &["10 ON ERROR GOTO 30", "20 X = LN(-1)", "30 REPORT"],
+        )
+        .unwrap();
+        assert_eq!(interpreter.executor().get_output(), "Log range");
+
+        // ERR and ERL print, as PRINT and in expressions, inside a handler
+        for mut interpreter in interpreters() {
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 ON ERROR GOTO 100",
+                    "20 X = 1 / 0",
+                    "30 END",
+                    "100 PRINT ERR;\" \";ERL;\" \";(ERR);\" \";ERL / 10",
+                ],
+            )
+            .unwrap();
+            interpreter.process_line("PRINT ERR, ERL").unwrap();
+            assert_eq!(
+                interpreter.executor().get_output(),
+                "        18 20 18 2\n        18        20\n"
+            );
+        }
+    }
+
+    #[test]
+    fn test_constants_and_assertions() {
+        for mut interpreter in interpreters() {
+            let program = [
+                "10 ON ERROR GOTO 100",
+                "20 CONST LIMIT% = 3",
+                "30 CONST HALF = 1 / 2",
+                "40 ASSERT LIMIT% = 3",
+                "50 LIMIT% = 4",
+                "60 END",
+                "100 E% = ERR",
+                "110 R$ = REPORT$",
+                "120 END",
+            ];
+            run_program(&mut interpreter, &program).unwrap();
+            let executor = interpreter.executor();
+            assert_eq!(executor.get_variable_int("LIMIT%").unwrap(), 3);
+            assert_eq!(executor.get_variable_int("E%").unwrap(), 47);
+            assert_eq!(executor.get_variable_string("R$").unwrap(), "Constant");
+        }
+
+        // A constant cannot be a loop variable either
+        for mut interpreter in interpreters() {
+            let program = ["10 CONST N% = 1", "20 FOR N% = 1 TO 2", "30 NEXT"];
+            let error = run_program(&mut interpreter, &program).unwrap_err();
+            assert_eq!(error, "Constant at line 20");
+        }
+
+        // A failed assertion reports its message, and can be trapped
+        for mut interpreter in interpreters() {
+            let program = [
+                "10 ON ERROR GOTO 100",
+                "20 X% = 2",
+                "30 ASSERT X% < 2, \"X% out of range\"",
+                "40 END",
+                "100 E% = ERR",
+                "110 R$ = REPORT$",
+            ];
+            run_program(&mut interpreter, &program).unwrap();
+            let executor = interpreter.executor();
+            assert_eq!(executor.get_variable_int("E%").unwrap(), 48);
+            assert_eq!(
+                executor.get_variable_string("R$").unwrap(),
+                "X% out of range"
+            );
+        }
+        for mut interpreter in interpreters() {
+            let error = run_program(&mut interpreter, &["10 ASSERT 1 = 2"]).unwrap_err();
+            assert_eq!(error, "Assertion failed at line 10");
+        }
+    }
+
+    #[test]
+    fn test_error_source() {
+        let mut interpreter = Interpreter::new();
+        let error = run_program(&mut interpreter, &["10 A% = 1", "20 PRINT A%; B"]).unwrap_err();
+        assert_eq!(error, "No such variable at line 20");
+        let source = interpreter.error_source().unwrap();
+        assert_eq!(source.text, "20 PRINT A%;B");
+        assert_eq!(&source.text[source.span.clone()], "B");
+
+        let error = interpreter.process_line("X = 1/0").unwrap_err();
+        assert_eq!(error, "Division by zero");
+        let source = interpreter.error_source().unwrap();
+        assert_eq!(&source.text[source.span.clone()], "/");
+
+        // Otherwise the statement is pointed at
+        let error = interpreter.process_line("PRINT LEN(1)").unwrap_err();
+        assert_eq!(error, "Type mismatch");
+        let source = interpreter.error_source().unwrap();
+        assert_eq!(&source.text[source.span.clone()], "PRINT");
+
+        interpreter.process_line("PRINT").unwrap();
+        assert!(interpreter.error_source().is_none());
+    }
+
+    #[test]
+    fn test_find_and_change() {
+        let mut interpreter = Interpreter::new();
+        for line in [
+            "10 score% = 0",
+            "20 PRINT \"score\"; score%",
+            "30 REPEAT score% = score% + 1",
+            "40 UNTIL score% > 3",
+        ] {
+            interpreter.process_line(line).unwrap();
+        }
+        assert_eq!(
+            interpreter.find("PRINT"),
+            Ok(vec!["20 PRINT \"score\";score%".to_string()])
+        );
+        assert_eq!(interpreter.find("\"score%\"").unwrap().len(), 4);
+        assert_eq!(interpreter.find("\"0\"").unwrap().len(), 1);
+        assert!(interpreter.find("").is_err());
+
+        assert_eq!(interpreter.change("score% points%"), Ok(4));
+        assert_eq!(interpreter.find("score").unwrap().len(), 1);
+        assert_eq!(interpreter.find("points%").unwrap().len(), 4);
+
+        // A change that would break a line changes nothing
+        assert_eq!(
+            interpreter.change("UNTIL \"UNTIL (\""),
+            Err("Change would break line 40".to_string())
+        );
+        assert_eq!(interpreter.find("\"UNTIL points%\"").unwrap().len(), 1);
+
+        // The whole change is undone at once
+        interpreter.undo().unwrap();
+        assert_eq!(interpreter.find("score%").unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_copy_key_input() {
+        let mut interpreter = Interpreter::new();
+        // Cursor up to the printed line, COPY it and add to the end
+        let keys = format!("\u{8B}{}!\r", "\u{87}".repeat(6));
+        interpreter.insert_keys(&keys);
+        run_program(&mut interpreter, &["10 PRINT \"ANSWER\"", "20 INPUT A$"]).unwrap();
+        assert_eq!(
+            interpreter.executor().get_variable_string("A$").unwrap(),
+            "ANSWER!"
+        );
+        assert_eq!(interpreter.executor().screen().row(1), "ANSWER!");
+    }
+
+    #[test]
+    fn test_immediate_mode() {
+        let mut interpreter = Interpreter::new();
+        interpreter.process_line("A% = 6 * 7").unwrap();
+        assert_eq!(interpreter.executor().get_variable_int("A%").unwrap(), 42);
+        assert!(interpreter.program().is_empty());
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut interpreter = Interpreter::new();
+        let program = [
+            "10 A% = 6",
+            "20 total = 2.5",
+            "30 price$ = \"\u{a3}5\"",
+            "40 END",
+            "50 DEF FNdouble(X) = X * 2",
+        ];
+        run_program(&mut interpreter, &program).unwrap();
+
+        assert_eq!(interpreter.eval("A% * 7"), Ok(Value::Integer(42)));
+        assert_eq!(interpreter.eval("total"), Ok(Value::Real(2.5)));
+        assert_eq!(interpreter.eval("FNdouble(A%) + 1"), Ok(Value::Integer(13)));
+        assert_eq!(interpreter.eval("2 AND A%"), Ok(Value::Integer(2)));
+        assert_eq!(
+            interpreter.eval("price$"),
+            Ok(Value::String("\u{a3}5".to_string()))
+        );
+        assert_eq!(
+            interpreter.eval("SQR(2)").unwrap().to_string(),
+            "1.41421356"
+        );
+        assert!(interpreter.eval("A% 7").is_err());
+        assert!(interpreter.eval("").is_err());
+
+        // Nothing is run or printed
+        assert_eq!(interpreter.executor().get_variable_int("A%").unwrap(), 6);
+        assert!(interpreter.executor().get_output().is_empty());
+    }
+
+    #[test]
+    fn test_config_applied() {
+        let mut config = Config {
+            mode: 1,
+            ..Default::default()
+        };
+        config.strict.undefined_variables = false;
+        let mut interpreter = Interpreter::with_config(config);
+        assert_eq!(interpreter.executor().screen_mode(), 1);
+
+        // Unset variables read as zero when the strict flag is off
+        interpreter.process_line("A% = BB% + 1").unwrap();
+        assert_eq!(interpreter.executor().get_variable_int("A%").unwrap(), 1);
+
+        interpreter
+            .configure("strict.undefined_variables", "on")
+            .unwrap();
+        assert!(interpreter.process_line("A% = CC% + 1").is_err());
+        // The resident integers are never unset
+        interpreter.process_line("A% = C% + 1").unwrap();
+
+        interpreter.configure("mode", "4").unwrap();
+        assert_eq!(interpreter.executor().screen_mode(), 4);
+        assert!(interpreter.configure("mode", "99").is_err());
+        assert_eq!(interpreter.config().mode, 4);
+    }
+
+    #[test]
+    fn test_bbc_floats() {
+        let program = [
+            "10 X = 2 / 3",
+            "15 PRINT X",
+            "20 X = 1 + 0.0000000001 - 1",
+            "25 PRINT X",
+            "30 PRINT 1000000000.0",
+            "40 PRINT 0.001",
+            "50 PRINT 123456789.5",
+            "60 A$ = STR$(1 / 3)",
+            "70 PRINT A$",
+        ];
+        let mut interpreter = Interpreter::new();
+        run_program(&mut interpreter, &program).unwrap();
+        assert_eq!(
+            interpreter.executor().get_output(),
+            "0.666666667\n1.00000008E-10\n       1E9\n      1E-3\n 123456790\n0.333333333\n"
+        );
+
+        let mut interpreter = Interpreter::new();
+        interpreter.configure("floats", "bbc").unwrap();
+        run_program(&mut interpreter, &program).unwrap();
+        assert_eq!(
+            interpreter.executor().get_output(),
+            "0.666666667\n         0\n       1E9\n      1E-3\n 123456790\n0.333333333\n"
+        );
+        assert!(run_program(&mut interpreter, &["10 X = 2.0 ^ 126 * 4"]).is_err());
+    }
+
+    #[test]
+    fn test_watchdog() {
+        use crate::watchdog::{Stuck, WatchdogAction};
+        use std::sync::{Arc, Mutex};
+        for mut interpreter in interpreters() {
+            interpreter.configure("watchdog", "1").unwrap();
+            let reports = Arc::new(Mutex::new(Vec::new()));
+            let seen = Arc::clone(&reports);
+            interpreter.set_stuck_handler(Box::new(move |stuck: &Stuck| {
+                let mut seen = seen.lock().unwrap();
+                seen.push(stuck.lines.clone());
+                if seen.len() == 1 {
+                    WatchdogAction::Trace
+                } else {
+                    WatchdogAction::Break
+                }
+            }));
+            let error = run_program(
+                &mut interpreter,
+                &["10 PRINT \"GO\"", "20 A% = 1", "30 A% = 1", "40 GOTO 20"],
+            )
+            .unwrap_err();
+            assert!(error.contains("Escape"), "{}", error);
+            assert_eq!(*reports.lock().unwrap(), [[20, 30, 40], [20, 30, 40]]);
+            let output = interpreter.executor().get_output();
+            assert!(output.starts_with("GO\n"), "{}", output);
+            assert!(output.contains("[20] [30] [40] [20] "), "{}", output);
+        }
+    }
+
+    #[test]
+    fn test_statement_hooks() {
+        use crate::hooks::{after_each, before_each, HookAction};
+        use std::sync::{Arc, Mutex};
+        for mut interpreter in interpreters() {
+            let covered = Arc::new(Mutex::new(Vec::new()));
+            let seen = Arc::clone(&covered);
+            interpreter.add_hook(after_each(move |line_number, _, result| {
+                seen.lock().unwrap().push((line_number, result.is_ok()));
+            }));
+            let policy = interpreter.add_hook(before_each(|_, statement| match statement {
+                Statement::Sound { .. } => HookAction::Veto("SOUND".to_string()),
+                Statement::Assignment { target, .. } if target == "B%" => {
+                    HookAction::Replace(Box::new(Statement::Assignment {
+                        target: "B%".to_string(),
+                        expression: Expression::Integer(3),
+                    }))
+                }
+                Statement::Assignment { target, .. } if target == "C%" => HookAction::Skip,
+                _ => HookAction::Continue,
+            }));
+            let program = [
+                "10 A% = 1",
+                "20 B% = 2",
+                "30 C% = 7",
+                "40 GOSUB 70",
+                "50 SOUND 1, -15, 53, 10",
+                "60 END",
+                "70 RETURN",
+            ];
+            let error = run_program(&mut interpreter, &program).unwrap_err();
+            assert_eq!(error, "Not allowed at line 50");
+            let executor = interpreter.executor();
+            let values: Vec<i32> = ["A%", "B%", "C%"]
+                .iter()
+                .map(|name| executor.get_variable_int(name).unwrap())
+                .collect();
+            assert_eq!(values, [1, 3, 0]);
+            assert_eq!(
+                *covered.lock().unwrap(),
+                [
+                    (10, true),
+                    (20, true),
+                    (30, true),
+                    (40, true),
+                    (70, true),
+                    (50, false)
+                ]
+            );
+
+            assert!(interpreter.remove_hook(policy));
+            covered.lock().unwrap().clear();
+            interpreter.run().unwrap();
+            assert_eq!(interpreter.executor().get_variable_int("B%").unwrap(), 2);
+            assert_eq!(covered.lock().unwrap().len(), 7);
+        }
+    }
+
+    #[test]
+    fn test_variable_history() {
+        for mut interpreter in interpreters() {
+            assert!(interpreter.history("A%").is_err());
+            interpreter.configure("history", "100").unwrap();
+            let error = run_program(
+                &mut interpreter,
+                &[
+                    "10 A% = 5",
+                    "20 DIM B%(3)",
+                    "30 B%(2) = 7",
+                    "40 PROCp(1)",
+                    "50 A% = A% - 5",
+                    "60 X = 1 / A%",
+                    "100 DEF PROCp(N%)",
+                    "110 LOCAL S$",
+                    "120 S$ = \"HI\"",
+                    "130 ENDPROC",
+                ],
+            )
+            .unwrap_err();
+            assert!(error.contains("Division by zero"), "{}", error);
+            assert_eq!(
+                interpreter.history("A%").unwrap(),
+                ["   10 A% = 5", "   50 A% = 0 (was 5)"]
+            );
+            assert_eq!(
+                interpreter.history("B%(").unwrap(),
+                ["   30 B%(2) = 7 (was 0)"]
+            );
+            assert_eq!(
+                interpreter.history("S$").unwrap(),
+                ["  110 S$ = \"\"", "  120 S$ = \"HI\" (was \"\")"]
+            );
+            assert_eq!(interpreter.history("").unwrap().len(), 7);
+
+            // RUN starts the record afresh
+            interpreter.new_program();
+            run_program(&mut interpreter, &["10 A% = 1"]).unwrap();
+            assert_eq!(interpreter.history("").unwrap(), ["   10 A% = 1 (was 0)"]);
+        }
+    }
+
+    #[test]
+    fn test_speed_throttle() {
+        let config = Config {
+            speed: 200,
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::with_config(config);
+
+        let started = Instant::now();
+        run_program(&mut interpreter, &["10 FOR I% = 1 TO 9", "20 NEXT I%"]).unwrap();
+        // FOR plus nine NEXTs at 200 statements per second take at least 0.05s
+        assert!(started.elapsed() >= Duration::from_millis(45));
+    }
+
+    #[test]
+    fn test_keyword_case_options() {
+        let mut interpreter = Interpreter::new();
+        interpreter.process_line("10 print \"hi\"").unwrap();
+        interpreter.configure("list_case", "lower").unwrap();
+        assert_eq!(interpreter.list(), vec!["10 print \"hi\"".to_string()]);
+
+        // With strict keyword case, `print` is just a name
+        interpreter.configure("case", "off").unwrap();
+        assert!(interpreter.process_line("print \"hi\"").is_err());
+    }
+
+    #[test]
+    fn test_crunched_program() {
+        for mut interpreter in interpreters() {
+            run_program(
+                &mut interpreter,
+                &[
+                    "10 total=0",
+                    "15 Total=100",
+                    "20 FORI=1TO4",
+                    "30 PROCadd",
+                    "40 NEXTI",
+                    "50 END",
+                    "60 DEFPROCadd",
+                    "70 total=total+I",
+                    "80 ENDPROC",
+                ],
+            )
+            .unwrap();
+            // Names are case-sensitive, so Total is a separate variable
+            assert_eq!(
+                interpreter.executor().get_variable_real("total").unwrap(),
+                10.0
+            );
+            assert_eq!(
+                interpreter.executor().get_variable_real("Total").unwrap(),
+                100.0
+            );
+        }
+    }
+
+    #[test]
+    fn test_basic2_dialect() {
+        for mut interpreter in interpreters() {
+            let program = [
+                "10 X% = 0",
+                "20 WHILE X% < 3",
+                "30 X% = X% + 1",
+                "40 ENDWHILE",
+            ];
+            run_program(&mut interpreter, &program).unwrap();
+            assert_eq!(interpreter.executor().get_variable_int("X%").unwrap(), 3);
+
+            // The same program will not run on a Model B
+            interpreter.configure("dialect", "basic2").unwrap();
+            let error = interpreter.run().unwrap_err();
+            assert!(
+                error.contains("line 20") && error.contains("Mistake"),
+                "{}",
+                error
+            );
+            assert!(interpreter.process_line("ENDWHILE").is_err());
+        }
+    }
+
+    #[test]
+    fn test_block_if() {
+        for mut interpreter in interpreters() {
+            let program = [
+                "10 FOR I% = 1 TO 4",
+                "20 IF I% MOD 2 = 0 THEN",
+                "30 E% = E% + I%",
+                "40 IF I% = 4 THEN",
+                "50 F% = 1",
+                "60 ENDIF",
+                "70 ELSE",
+                "80 O% = O% + I%",
+                "90 IF I% = 4 THEN",
+                "100 F% = 2",
+                "110 ELSE",
+                "120 G% = G% + 1",
+                "130 ENDIF",
+                "140 ENDIF",
+                "150 NEXT I%",
+            ];
+            for name in ["E%", "O%", "F%", "G%"] {
+                interpreter.process_line(&format!("{} = 0", name)).unwrap();
+            }
+            run_program(&mut interpreter, &program).unwrap();
+
+            let executor = interpreter.executor();
+            assert_eq!(executor.get_variable_int("E%").unwrap(), 6);
+            assert_eq!(executor.get_variable_int("O%").unwrap(), 4);
+            assert_eq!(executor.get_variable_int("F%").unwrap(), 1);
+            assert_eq!(executor.get_variable_int("G%").unwrap(), 2);
+        }
+    }
+
+    #[test]
+    fn test_block_if_missing_endif() {
+        for mut interpreter in interpreters() {
+            let error = run_program(&mut interpreter, &["10 IF 0 THEN", "20 PRINT"]).unwrap_err();
+            assert!(error.contains("Missing ENDIF"), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,255 @@
+//! The BBC keyboard as seen by INKEY with a negative number
+//!
+//! `INKEY(-n)` asks whether a particular key is held down, by the key's
+//! number on the BBC keyboard (-99 for SPACE, -17 for Q), as does OSBYTE
+//! &81. Front ends report keys going down and up by their modern scancodes,
+//! named as in a browser's `KeyboardEvent.code` (`KeyQ`, `Space`,
+//! `ArrowLeft`), and a layout maps each scancode to the BBC key it stands
+//! for. The standard layout puts every BBC key on the PC key with the same
+//! legend, or failing that in the same place, and a configuration can remap
+//! any scancode (to play with the cursor keys a game that expects Z and X).
+
+use std::collections::{BTreeMap, HashMap};
+
+/// The keys of the BBC keyboard and their negative INKEY numbers
+pub const BBC_KEYS: &[(&str, i32)] = &[
+    ("SHIFT", -1),
+    ("CTRL", -2),
+    ("Q", -17),
+    ("3", -18),
+    ("4", -19),
+    ("5", -20),
+    ("f4", -21),
+    ("8", -22),
+    ("f7", -23),
+    ("-", -24),
+    ("^", -25),
+    ("LEFT", -26),
+    ("f0", -33),
+    ("W", -34),
+    ("E", -35),
+    ("T", -36),
+    ("7", -37),
+    ("I", -38),
+    ("9", -39),
+    ("0", -40),
+    ("_", -41),
+    ("DOWN", -42),
+    ("1", -49),
+    ("2", -50),
+    ("D", -51),
+    ("R", -52),
+    ("6", -53),
+    ("U", -54),
+    ("O", -55),
+    ("P", -56),
+    ("[", -57),
+    ("UP", -58),
+    ("CAPS LOCK", -65),
+    ("A", -66),
+    ("X", -67),
+    ("F", -68),
+    ("Y", -69),
+    ("J", -70),
+    ("K", -71),
+    ("@", -72),
+    (":", -73),
+    ("RETURN", -74),
+    ("SHIFT LOCK", -81),
+    ("S", -82),
+    ("C", -83),
+    ("G", -84),
+    ("H", -85),
+    ("N", -86),
+    ("L", -87),
+    (";", -88),
+    ("]", -89),
+    ("DELETE", -90),
+    ("TAB", -97),
+    ("Z", -98),
+    ("SPACE", -99),
+    ("V", -100),
+    ("B", -101),
+    ("M", -102),
+    (",", -103),
+    (".", -104),
+    ("/", -105),
+    ("COPY", -106),
+    ("ESCAPE", -113),
+    ("f1", -114),
+    ("f2", -115),
+    ("f3", -116),
+    ("f5", -117),
+    ("f6", -118),
+    ("f8", -119),
+    ("f9", -120),
+    ("\\", -121),
+    ("RIGHT", -122),
+];
+
+/// The standard layout: scancodes and the BBC keys they stand for (letters
+/// and digits are added by [`KeyMap::new`])
+const STANDARD_LAYOUT: &[(&str, &str)] = &[
+    ("ShiftLeft", "SHIFT"),
+    ("ShiftRight", "SHIFT"),
+    ("ControlLeft", "CTRL"),
+    ("ControlRight", "CTRL"),
+    ("CapsLock", "CAPS LOCK"),
+    ("Tab", "TAB"),
+    ("Escape", "ESCAPE"),
+    ("Enter", "RETURN"),
+    ("NumpadEnter", "RETURN"),
+    ("Backspace", "DELETE"),
+    ("Delete", "DELETE"),
+    ("End", "COPY"),
+    ("Space", "SPACE"),
+    ("ArrowLeft", "LEFT"),
+    ("ArrowRight", "RIGHT"),
+    ("ArrowUp", "UP"),
+    ("ArrowDown", "DOWN"),
+    ("F10", "f0"),
+    ("F1", "f1"),
+    ("F2", "f2"),
+    ("F3", "f3"),
+    ("F4", "f4"),
+    ("F5", "f5"),
+    ("F6", "f6"),
+    ("F7", "f7"),
+    ("F8", "f8"),
+    ("F9", "f9"),
+    ("Minus", "-"),
+    ("Equal", "^"),
+    ("Backslash", "\\"),
+    ("Backquote", "_"),
+    ("BracketLeft", "["),
+    ("BracketRight", "]"),
+    ("Semicolon", ";"),
+    ("Quote", ":"),
+    ("Comma", ","),
+    ("Period", "."),
+    ("Slash", "/"),
+];
+
+/// The negative INKEY number of a BBC key, by name (`SPACE`, `Q`, `f1`),
+/// ignoring case
+pub fn inkey_number(name: &str) -> Option<i32> {
+    BBC_KEYS
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|&(_, number)| number)
+}
+
+/// The name of the BBC key with a negative INKEY number
+pub fn key_name(number: i32) -> Option<&'static str> {
+    BBC_KEYS
+        .iter()
+        .find(|&&(_, key)| key == number)
+        .map(|&(name, _)| name)
+}
+
+/// Which BBC key each scancode stands for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyMap {
+    keys: HashMap<String, i32>,
+}
+
+impl KeyMap {
+    /// The standard layout
+    pub fn new() -> Self {
+        let mut keys = HashMap::new();
+        for (scancode, key) in STANDARD_LAYOUT {
+            keys.insert(scancode.to_string(), Self::number(key));
+        }
+        for c in ('A'..='Z').chain('0'..='9') {
+            let scancode = if c.is_ascii_digit() { "Digit" } else { "Key" };
+            keys.insert(format!("{}{}", scancode, c), Self::number(&c.to_string()));
+        }
+        Self { keys }
+    }
+
+    /// The standard layout with some scancodes remapped, as `scancode = key`
+    /// (see [`Config::keys`](crate::config::Config::keys))
+    pub fn with_overrides(overrides: &BTreeMap<String, String>) -> Result<Self, String> {
+        let mut keymap = Self::new();
+        for (scancode, key) in overrides {
+            keymap.map(scancode, key)?;
+        }
+        Ok(keymap)
+    }
+
+    /// Make a scancode stand for the named BBC key
+    pub fn map(&mut self, scancode: &str, key: &str) -> Result<(), String> {
+        let number = inkey_number(key).ok_or_else(|| format!("No BBC key called {}", key))?;
+        self.keys.insert(scancode.to_string(), number);
+        Ok(())
+    }
+
+    /// The negative INKEY number of the BBC key a scancode stands for
+    pub fn inkey(&self, scancode: &str) -> Option<i32> {
+        self.keys.get(scancode).copied()
+    }
+
+    fn number(key: &str) -> i32 {
+        inkey_number(key).expect("standard layout names a BBC key")
+    }
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_names() {
+        assert_eq!(inkey_number("SPACE"), Some(-99));
+        assert_eq!(inkey_number("space"), Some(-99));
+        assert_eq!(inkey_number("q"), Some(-17));
+        assert_eq!(inkey_number("F"), Some(-68));
+        assert_eq!(inkey_number("f1"), Some(-114));
+        assert_eq!(inkey_number("Shift Lock"), Some(-81));
+        assert_eq!(inkey_number("HOME"), None);
+        assert_eq!(key_name(-106), Some("COPY"));
+        assert_eq!(key_name(-3), None);
+    }
+
+    #[test]
+    fn test_layout() {
+        let keymap = KeyMap::new();
+        assert_eq!(keymap.inkey("Space"), Some(-99));
+        assert_eq!(keymap.inkey("KeyQ"), Some(-17));
+        assert_eq!(keymap.inkey("Digit3"), Some(-18));
+        assert_eq!(keymap.inkey("ShiftRight"), Some(-1));
+        assert_eq!(keymap.inkey("F10"), Some(-33));
+        assert_eq!(keymap.inkey("MetaLeft"), None);
+        // Every BBC key has a scancode, but SHIFT LOCK and @, which PC
+        // keyboards do not have
+        for &(name, number) in BBC_KEYS {
+            assert!(
+                ["SHIFT LOCK", "@"].contains(&name)
+                    || keymap.keys.values().any(|&key| key == number),
+                "{}",
+                name
+            );
+        }
+
+        let overrides = BTreeMap::from([
+            ("ArrowLeft".to_string(), "Z".to_string()),
+            ("ArrowRight".to_string(), "x".to_string()),
+        ]);
+        let keymap = KeyMap::with_overrides(&overrides).unwrap();
+        assert_eq!(keymap.inkey("ArrowLeft"), Some(-98));
+        assert_eq!(keymap.inkey("ArrowRight"), Some(-67));
+        assert_eq!(keymap.inkey("KeyZ"), Some(-98));
+
+        let overrides = BTreeMap::from([("KeyA".to_string(), "HOME".to_string())]);
+        assert_eq!(
+            KeyMap::with_overrides(&overrides),
+            Err("No BBC key called HOME".to_string())
+        );
+    }
+}
@@ -4,20 +4,53 @@
 //! This interpreter emulates the original 6502-based system with 32K RAM and full
 //! compatibility with BBC BASIC programs.
 
+pub mod analysis;
+pub mod autosave;
+pub mod charset;
+pub mod config;
+pub mod debugger;
+pub mod events;
 pub mod executor;
 pub mod extensions;
 pub mod filesystem;
+pub mod font;
 pub mod graphics;
+pub mod help;
+pub mod history;
+pub mod hooks;
+pub mod html;
+pub mod inspector;
+pub mod interpreter;
+pub mod keymap;
+pub mod library;
+pub mod lsp;
+pub mod lvar;
 pub mod memory;
+pub mod numbering;
 pub mod os;
+pub mod pack;
+pub mod palette;
 pub mod parser;
+pub mod postmortem;
 pub mod program;
+pub mod screen;
 pub mod sound;
+pub mod speech;
+pub mod structure;
 pub mod tokenizer;
+pub mod trace;
+pub mod transpiler;
+pub mod turtle;
 pub mod variables;
+pub mod vm;
+pub mod watchdog;
+#[cfg(feature = "graphics")]
+pub mod window;
 
 // Re-export core types for convenience
 pub use crate::error::{BBCBasicError, Result};
+pub use executor::Value;
+pub use interpreter::Interpreter;
 pub use memory::MemoryManager;
 pub use parser::{BinaryOperator, Expression, Statement, UnaryOperator};
 pub use program::ProgramStore;
@@ -37,12 +70,15 @@ pub mod error {
         // Syntax errors
         SyntaxError { message: String, line: Option<u16> },
         BadProgram,
+        Mistake,
 
         // Runtime errors
         TypeMismatch,
         NoRoom,
         SubscriptOutOfRange,
         DivisionByZero,
+        NegativeRoot,
+        LogRange,
         StringTooLong,
 
         // Variable and array errors
@@ -58,13 +94,37 @@ pub mod error {
         DiskError(String),
         ChannelNotOpen(i32),
         TooManyOpenFiles,
+        BadName(String),
 
         // System errors
         IllegalFunction,
         BadCall,
+        BadCommand,
+        Escape,
+        Arguments,
+        Eof,
+        TooBig,
+
+        // Extension errors: assigning to a CONST, and a failed ASSERT
+        // with its message
+        Constant(String),
+        AssertionFailed(String),
+
+        // Safe mode: something a program may not do, and a limit it has
+        // reached
+        NotAllowed(String),
+        LimitReached(String),
+
+        // An error that stopped a multi-line function's body, as it was
+        // reported there
+        InFunction(String),
 
         // Custom error for ON ERROR handling
         UserError(u8),
+
+        // Not an error: INPUT or GET needs keys that have not been typed
+        // yet, and the statement should be run again once they have
+        WaitingForInput,
     }
 
     impl fmt::Display for BBCBasicError {
@@ -78,10 +138,13 @@ pub mod error {
                     }
                 }
                 BBCBasicError::BadProgram => write!(f, "Bad program"),
+                BBCBasicError::Mistake => write!(f, "Mistake"),
                 BBCBasicError::TypeMismatch => write!(f, "Type mismatch"),
                 BBCBasicError::NoRoom => write!(f, "No room"),
                 BBCBasicError::SubscriptOutOfRange => write!(f, "Subscript out of range"),
                 BBCBasicError::DivisionByZero => write!(f, "Division by zero"),
+                BBCBasicError::NegativeRoot => write!(f, "-ve root"),
+                BBCBasicError::LogRange => write!(f, "Log range"),
                 BBCBasicError::StringTooLong => write!(f, "String too long"),
                 BBCBasicError::NoSuchVariable(name) => write!(f, "No such variable: {}", name),
                 BBCBasicError::ArrayNotDimensioned(name) => {
@@ -93,9 +156,99 @@ pub mod error {
                 BBCBasicError::DiskError(msg) => write!(f, "Disk error: {}", msg),
                 BBCBasicError::ChannelNotOpen(handle) => write!(f, "Channel {} not open", handle),
                 BBCBasicError::TooManyOpenFiles => write!(f, "Too many open files"),
+                BBCBasicError::BadName(message) => write!(f, "{}", message),
                 BBCBasicError::IllegalFunction => write!(f, "Illegal function"),
                 BBCBasicError::BadCall => write!(f, "Bad call"),
+                BBCBasicError::BadCommand => write!(f, "Bad command"),
+                BBCBasicError::Escape => write!(f, "Escape"),
+                BBCBasicError::Arguments => write!(f, "Arguments"),
+                BBCBasicError::Eof => write!(f, "Eof"),
+                BBCBasicError::TooBig => write!(f, "Too big"),
+                BBCBasicError::Constant(name) => write!(f, "Constant: {}", name),
+                BBCBasicError::AssertionFailed(message) => write!(f, "{}", message),
+                BBCBasicError::NotAllowed(what) => write!(f, "Not allowed: {}", what),
+                BBCBasicError::LimitReached(limit) => write!(f, "{} limit reached", limit),
+                BBCBasicError::InFunction(message) => write!(f, "{}", message),
                 BBCBasicError::UserError(code) => write!(f, "Error {}", code),
+                BBCBasicError::WaitingForInput => write!(f, "Waiting for input"),
+            }
+        }
+    }
+
+    impl BBCBasicError {
+        /// The error's number, as ERR returns it
+        pub fn number(&self) -> i32 {
+            self.classic().0
+        }
+
+        /// Whether ON ERROR can trap the error
+        ///
+        /// Reaching a safe mode limit stops the program whatever ON ERROR
+        /// says, or a handler could keep it going for ever. An error that
+        /// stopped a function's body has already been offered to ON ERROR.
+        pub fn is_trappable(&self) -> bool {
+            !matches!(
+                self,
+                BBCBasicError::LimitReached(_) | BBCBasicError::InFunction(_)
+            )
+        }
+
+        /// The message BBC BASIC gives for the error, as REPORT prints it
+        ///
+        /// These are the Model B's short messages ("No such variable",
+        /// "Subscript"), without the details [`fmt::Display`] adds.
+        pub fn report(&self) -> String {
+            match self.classic().1 {
+                Some(text) => text.to_string(),
+                None => self.to_string(),
+            }
+        }
+
+        /// The Model B's error number and report text for the error
+        ///
+        /// Errors the Model B has no text for (None) are reported with their
+        /// details. Those it has no number for are 255, except the fatal
+        /// "No room" and "Bad program", which are 0.
+        fn classic(&self) -> (i32, Option<&'static str>) {
+            match self {
+                BBCBasicError::SyntaxError { .. } => (16, Some("Syntax error")),
+                BBCBasicError::BadProgram => (0, Some("Bad program")),
+                BBCBasicError::Mistake => (4, Some("Mistake")),
+                BBCBasicError::TypeMismatch => (6, Some("Type mismatch")),
+                BBCBasicError::NoRoom | BBCBasicError::MemoryExhausted => (0, Some("No room")),
+                BBCBasicError::SubscriptOutOfRange => (15, Some("Subscript")),
+                BBCBasicError::DivisionByZero => (18, Some("Division by zero")),
+                BBCBasicError::NegativeRoot => (21, Some("-ve root")),
+                BBCBasicError::LogRange => (22, Some("Log range")),
+                BBCBasicError::StringTooLong => (19, Some("String too long")),
+                // Undefined functions are reported as missing variables too
+                BBCBasicError::NoSuchVariable(name) if name.starts_with("Function ") => {
+                    (29, Some("No such FN/PROC"))
+                }
+                BBCBasicError::NoSuchVariable(_) => (26, Some("No such variable")),
+                BBCBasicError::ArrayNotDimensioned(_) => (14, Some("Array")),
+                BBCBasicError::InvalidAddress(_) => (255, None),
+                BBCBasicError::FileNotFound(_) => (214, Some("File not found")),
+                BBCBasicError::DiskError(_) => (199, None),
+                BBCBasicError::ChannelNotOpen(_) => (222, Some("Channel")),
+                BBCBasicError::TooManyOpenFiles => (192, Some("Too many open files")),
+                BBCBasicError::BadName(_) => (204, None),
+                BBCBasicError::IllegalFunction | BBCBasicError::Arguments => {
+                    (31, Some("Arguments"))
+                }
+                BBCBasicError::BadCall => (30, Some("Bad call")),
+                BBCBasicError::BadCommand => (254, Some("Bad command")),
+                BBCBasicError::Escape => (17, Some("Escape")),
+                BBCBasicError::Eof => (223, Some("Eof")),
+                BBCBasicError::TooBig => (20, Some("Too big")),
+                // The extensions' errors have numbers the Model B does not use
+                BBCBasicError::Constant(_) => (47, Some("Constant")),
+                BBCBasicError::AssertionFailed(_) => (48, None),
+                BBCBasicError::NotAllowed(_) => (49, Some("Not allowed")),
+                BBCBasicError::LimitReached(_) => (50, None),
+                BBCBasicError::InFunction(_) => (255, None),
+                BBCBasicError::UserError(code) => (i32::from(*code), None),
+                BBCBasicError::WaitingForInput => (255, None),
             }
         }
     }
@@ -0,0 +1,95 @@
+//! Libraries of PROCs and FNs bundled with the interpreter
+//!
+//! A bundled library is installed by name like a library file, with
+//! `INSTALL "LIB.SORT"` in a program or `*INSTALL LIB.SORT` at the prompt,
+//! but needs no file: its source is built into the interpreter. Bundled
+//! names all start `LIB.` and come before any file of the same name.
+
+/// A library built into the interpreter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BundledLibrary {
+    /// Name it is installed by
+    pub name: &'static str,
+    /// What it holds
+    pub description: &'static str,
+    /// BASIC source, without line numbers
+    pub source: &'static str,
+}
+
+/// The bundled libraries
+pub const LIBRARIES: &[BundledLibrary] = &[BundledLibrary {
+    name: "LIB.SORT",
+    description: "Sorting and binary search of arrays, and string tests",
+    source: include_str!("sort.bbas"),
+}];
+
+/// The bundled library with a name, ignoring case
+pub fn bundled(name: &str) -> Option<&'static BundledLibrary> {
+    LIBRARIES
+        .iter()
+        .find(|library| library.name.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Interpreter;
+
+    #[test]
+    fn test_sort_library() {
+        let mut interpreter = Interpreter::new();
+        for line in [
+            "10 INSTALL \"lib.sort\"",
+            "20 DIM A%(5), R(3), S$(4)",
+            "30 A%(0) = 5",
+            "40 A%(1) = -2",
+            "50 A%(2) = 9",
+            "60 A%(3) = 0",
+            "70 A%(4) = 5",
+            "80 PROCsort_int(A%(), 5)",
+            "90 F% = FNsearch_int(A%(), 5, 9)",
+            "100 G% = FNsearch_int(A%(), 5, 3)",
+            "130 R(0) = 2.5",
+            "140 R(1) = 0.5",
+            "150 R(2) = 1.5",
+            "160 PROCsort_real(R(), 3)",
+            "165 H% = FNsearch_real(R(), 3, 1.5)",
+            "170 S$(0) = \"PEAR\"",
+            "180 S$(1) = \"APPLE\"",
+            "190 S$(2) = \"FIG\"",
+            "200 S$(3) = \"KIWI\"",
+            "210 PROCsort_str(S$(), 4)",
+            "220 K% = FNsearch_str(S$(), 4, \"KIWI\")",
+            "240 T% = FNstarts(\"BASIC\", \"BAS\")",
+            "250 U% = FNends(\"BASIC\", \"BAS\")",
+            "260 V% = FNcontains(\"BASIC\", \"SI\")",
+        ] {
+            interpreter.process_line(line).unwrap();
+        }
+        interpreter.run().unwrap();
+
+        let variables = interpreter.executor().variables();
+        let ints: Vec<i32> = (0..5)
+            .map(|i| match variables.get_array_element("A%", &[i]).unwrap() {
+                crate::variables::Variable::Integer(value) => value,
+                other => panic!("{:?}", other),
+            })
+            .collect();
+        assert_eq!(ints, [-2, 0, 5, 5, 9]);
+        let executor = interpreter.executor();
+        assert_eq!(executor.get_variable_int("F%").unwrap(), 4);
+        assert_eq!(executor.get_variable_int("G%").unwrap(), -1);
+        assert_eq!(executor.get_variable_int("H%").unwrap(), 1);
+        assert_eq!(executor.get_variable_int("K%").unwrap(), 2);
+        assert_eq!(
+            variables.get_array_element("R", &[0]).unwrap(),
+            crate::variables::Variable::Real(0.5)
+        );
+        assert_eq!(
+            variables.get_array_element("S$", &[3]).unwrap(),
+            crate::variables::Variable::String("PEAR".to_string())
+        );
+        assert_eq!(executor.get_variable_int("T%").unwrap(), -1);
+        assert_eq!(executor.get_variable_int("U%").unwrap(), 0);
+        assert_eq!(executor.get_variable_int("V%").unwrap(), -1);
+    }
+}
@@ -0,0 +1,407 @@
+//! Source analysis for the bbc-basic-lsp language server
+//!
+//! A [`Document`] is the text of a program as an editor holds it: numbered
+//! lines, optionally preceded by header comments. It is checked with the
+//! interpreter's own tokenizer and parser, so an editor sees the errors the
+//! interpreter would report, and it knows where each PROC and FN is defined.
+//! A document without errors is also checked as CHECK does, and its
+//! warnings are shown too.
+//! The JSON-RPC side lives in the `bbc-basic-lsp` binary (the `lsp` feature);
+//! this module has no protocol dependencies.
+//!
+//! Lines and columns count from zero, as in the Language Server Protocol.
+//! Columns count characters.
+
+use crate::analysis::check_program;
+use crate::help;
+use crate::parser::{parse_statement_with_dialect, Dialect};
+use crate::program::{comment_text, ProgramStore};
+use crate::tokenizer::{tokenize, Token};
+use std::collections::{BTreeMap, BTreeSet};
+
+const DEF: u8 = 0xDD;
+const FN: u8 = 0xA4;
+const PROC: u8 = 0xF2;
+
+/// A place in a document
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub line: u32,
+    pub character: u32,
+}
+
+/// A span of a document, ending just before `end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+/// How serious a problem is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The interpreter would refuse the line
+    Error,
+    /// The program runs, but probably not as meant
+    Warning,
+}
+
+/// A problem found in a document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub range: Range,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// What a completion inserts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    Keyword,
+    Variable,
+    Procedure,
+    Function,
+}
+
+/// A word an editor can offer while typing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+}
+
+/// A program's source text, analysed
+#[derive(Debug, Clone)]
+pub struct Document {
+    lines: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+    /// Document line of each BASIC line number
+    line_numbers: BTreeMap<u16, usize>,
+    /// Document line of each DEF PROC and DEF FN, keyed by "PROCname" or
+    /// "FNname"
+    definitions: BTreeMap<String, usize>,
+    variables: BTreeSet<String>,
+}
+
+impl Document {
+    /// Analyse source text in the given dialect
+    pub fn new(text: &str, dialect: Dialect) -> Self {
+        let mut document = Self {
+            lines: text.lines().map(str::to_string).collect(),
+            diagnostics: Vec::new(),
+            line_numbers: BTreeMap::new(),
+            definitions: BTreeMap::new(),
+            variables: BTreeSet::new(),
+        };
+        document.analyse(dialect);
+        document
+    }
+
+    /// Syntax errors, misnumbered lines and jumps to missing lines
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Where the PROC or FN name, or GOTO-style line number, at `position`
+    /// is defined
+    pub fn definition(&self, position: Position) -> Option<Range> {
+        let (word, start) = self.word_at(position)?;
+        let line = if word.starts_with(|c: char| c.is_ascii_digit()) {
+            // The line's own number is not a reference to itself
+            if start == self.leading_space(position.line as usize) {
+                return None;
+            }
+            *self.line_numbers.get(&word.parse().ok()?)?
+        } else {
+            *self.definitions.get(&word)?
+        };
+        Some(self.line_range(line))
+    }
+
+    /// Keywords, the program's variables and its procedures and functions
+    pub fn completions(&self) -> Vec<Completion> {
+        let keywords = help::keywords().into_iter().map(|keyword| Completion {
+            label: keyword.to_string(),
+            kind: CompletionKind::Keyword,
+        });
+        let variables = self.variables.iter().map(|name| Completion {
+            label: name.clone(),
+            kind: CompletionKind::Variable,
+        });
+        let routines = self.definitions.keys().map(|name| Completion {
+            label: name.clone(),
+            kind: if name.starts_with("PROC") {
+                CompletionKind::Procedure
+            } else {
+                CompletionKind::Function
+            },
+        });
+        keywords.chain(variables).chain(routines).collect()
+    }
+
+    fn analyse(&mut self, dialect: Dialect) {
+        let mut jumps = Vec::new();
+        let mut program = ProgramStore::new();
+        for (index, source) in self.lines.iter().enumerate() {
+            let text = source.trim();
+            if text.is_empty() {
+                continue;
+            }
+            // Header comments are allowed before the first numbered line
+            if self.line_numbers.is_empty() && comment_text(text).is_some() {
+                continue;
+            }
+            let range = self.line_range(index);
+            let tokenized = match tokenize(text) {
+                Ok(tokenized) => tokenized,
+                Err(e) => {
+                    self.diagnostics.push(Diagnostic {
+                        range,
+                        severity: Severity::Error,
+                        message: e.to_string(),
+                    });
+                    continue;
+                }
+            };
+            let Some(line_number) = tokenized.line_number else {
+                self.diagnostics.push(Diagnostic {
+                    range,
+                    severity: Severity::Error,
+                    message: "Line has no line number".to_string(),
+                });
+                continue;
+            };
+            if let Some(&first) = self.line_numbers.get(&line_number) {
+                self.diagnostics.push(Diagnostic {
+                    range,
+                    severity: Severity::Error,
+                    message: format!(
+                        "Line {} is already defined on line {}",
+                        line_number,
+                        first + 1
+                    ),
+                });
+                continue;
+            }
+            self.line_numbers.insert(line_number, index);
+            program.store_line(tokenized.clone());
+
+            let tokens = &tokenized.tokens;
+            for (position, token) in tokens.iter().enumerate() {
+                let Token::Identifier(name) = token else {
+                    continue;
+                };
+                match position.checked_sub(1).map(|before| &tokens[before]) {
+                    Some(Token::Keyword(PROC)) | Some(Token::Keyword(FN)) => {
+                        let defined = position >= 2 && tokens[position - 2] == Token::Keyword(DEF);
+                        if defined {
+                            let prefix = if tokens[position - 1] == Token::Keyword(PROC) {
+                                "PROC"
+                            } else {
+                                "FN"
+                            };
+                            self.definitions
+                                .entry(format!("{}{}", prefix, name))
+                                .or_insert(index);
+                        }
+                    }
+                    _ => {
+                        self.variables.insert(name.clone());
+                    }
+                }
+            }
+
+            match parse_statement_with_dialect(&tokenized, dialect) {
+                Ok(statement) => jumps.extend(
+                    statement
+                        .jump_targets()
+                        .into_iter()
+                        .map(|target| (index, target)),
+                ),
+                Err(e) => self.diagnostics.push(Diagnostic {
+                    range,
+                    severity: Severity::Error,
+                    message: e.to_string(),
+                }),
+            }
+        }
+
+        for (index, target) in jumps {
+            if !self.line_numbers.contains_key(&target) {
+                self.diagnostics.push(Diagnostic {
+                    range: self.number_range(index, target),
+                    severity: Severity::Error,
+                    message: format!("No such line {}", target),
+                });
+            }
+        }
+        // Errors can hide jumps, so warnings wait until there are none
+        if self.diagnostics.is_empty() {
+            for warning in check_program(&program, dialect) {
+                let (first, last) = warning.lines();
+                let (Some(&first), Some(&last)) =
+                    (self.line_numbers.get(&first), self.line_numbers.get(&last))
+                else {
+                    continue;
+                };
+                self.diagnostics.push(Diagnostic {
+                    range: Range {
+                        start: self.line_range(first).start,
+                        end: self.line_range(last).end,
+                    },
+                    severity: Severity::Warning,
+                    message: warning.to_string(),
+                });
+            }
+        }
+        self.diagnostics
+            .sort_by_key(|diagnostic| diagnostic.range.start);
+    }
+
+    /// The whole of a line, without its surrounding spaces
+    fn line_range(&self, line: usize) -> Range {
+        let text = &self.lines[line];
+        let start = self.leading_space(line);
+        let end = text.trim_end().chars().count();
+        Range {
+            start: Position {
+                line: line as u32,
+                character: start as u32,
+            },
+            end: Position {
+                line: line as u32,
+                character: end.max(start) as u32,
+            },
+        }
+    }
+
+    fn leading_space(&self, line: usize) -> usize {
+        self.lines[line]
+            .chars()
+            .take_while(|c| c.is_whitespace())
+            .count()
+    }
+
+    /// Where a jump's target line number is written, or the whole line if
+    /// it cannot be found
+    fn number_range(&self, line: usize, target: u16) -> Range {
+        let chars: Vec<char> = self.lines[line].chars().collect();
+        let number: Vec<char> = target.to_string().chars().collect();
+        // Skip the line's own number
+        let body = chars
+            .iter()
+            .position(|c| !c.is_whitespace() && !c.is_ascii_digit())
+            .unwrap_or(chars.len());
+        let found = (body..chars.len()).find(|&start| {
+            chars[start..].starts_with(&number)
+                && (start == 0 || !chars[start - 1].is_ascii_digit())
+                && chars
+                    .get(start + number.len())
+                    .is_none_or(|c| !c.is_ascii_digit())
+        });
+        match found {
+            Some(start) => Range {
+                start: Position {
+                    line: line as u32,
+                    character: start as u32,
+                },
+                end: Position {
+                    line: line as u32,
+                    character: (start + number.len()) as u32,
+                },
+            },
+            None => self.line_range(line),
+        }
+    }
+
+    /// The name or number at a position, and the column it starts at
+    fn word_at(&self, position: Position) -> Option<(String, usize)> {
+        let chars: Vec<char> = self.lines.get(position.line as usize)?.chars().collect();
+        let in_word = |c: &char| c.is_ascii_alphanumeric() || matches!(c, '_' | '$' | '%');
+        let cursor = (position.character as usize).min(chars.len());
+        let start = chars[..cursor]
+            .iter()
+            .rposition(|c| !in_word(c))
+            .map_or(0, |before| before + 1);
+        let end = chars[cursor..]
+            .iter()
+            .position(|c| !in_word(c))
+            .map_or(chars.len(), |after| cursor + after);
+        (start < end).then(|| (chars[start..end].iter().collect(), start))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(text: &str) -> Document {
+        Document::new(text, Dialect::default())
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let doc = document("REM header\n\n10 PRINT \"HI\"\n20 GOTO 500\nPRINT 1\n10 END\n30 FOR\n");
+        let messages: Vec<(u32, &str)> = doc
+            .diagnostics()
+            .iter()
+            .map(|d| (d.range.start.line, d.message.as_str()))
+            .collect();
+        assert_eq!(messages[0], (3, "No such line 500"));
+        assert_eq!(messages[1], (4, "Line has no line number"));
+        assert_eq!(messages[2], (5, "Line 10 is already defined on line 3"));
+        assert_eq!(messages[3].0, 6);
+        assert_eq!(messages.len(), 4);
+
+        let target = doc.diagnostics()[0].range;
+        assert_eq!((target.start.character, target.end.character), (8, 11));
+
+        assert!(document("10 PRINT 1\n20 GOTO 10\n")
+            .diagnostics()
+            .is_empty());
+        assert!(doc
+            .diagnostics()
+            .iter()
+            .all(|d| d.severity == Severity::Error));
+    }
+
+    #[test]
+    fn test_warnings() {
+        let doc = document("10 PRINT 1\n20 END\n30 PRINT 2\n  40 PRINT 3\n50 DATA 1\n");
+        let warnings = doc.diagnostics();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].severity, Severity::Warning);
+        assert_eq!(warnings[0].message, "Lines 30-40: never run");
+        let range = warnings[0].range;
+        assert_eq!((range.start.line, range.start.character), (2, 0));
+        assert_eq!((range.end.line, range.end.character), (3, 12));
+    }
+
+    #[test]
+    fn test_definition() {
+        let doc = document("10 PROCgreet\n20 GOTO 10\n30 END\n40 DEF PROCgreet\n50 ENDPROC\n");
+        let at = |line, character| doc.definition(Position { line, character });
+        assert_eq!(at(0, 6).map(|r| r.start.line), Some(3));
+        assert_eq!(at(1, 9).map(|r| r.start.line), Some(0));
+        // A line's own number, and keywords, go nowhere
+        assert_eq!(at(2, 1), None);
+        assert_eq!(at(2, 4), None);
+    }
+
+    #[test]
+    fn test_completions() {
+        let doc = document("10 count% = 1\n20 X = FNtwice(count%)\n30 DEF FNtwice(N) = N * 2\n");
+        let completions = doc.completions();
+        let kind_of = |label: &str| {
+            completions
+                .iter()
+                .find(|c| c.label == label)
+                .map(|c| c.kind)
+        };
+        assert_eq!(kind_of("PRINT"), Some(CompletionKind::Keyword));
+        assert_eq!(kind_of("count%"), Some(CompletionKind::Variable));
+        assert_eq!(kind_of("N"), Some(CompletionKind::Variable));
+        assert_eq!(kind_of("FNtwice"), Some(CompletionKind::Function));
+        assert_eq!(kind_of("twice"), None);
+    }
+}
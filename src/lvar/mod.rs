@@ -0,0 +1,140 @@
+//! Variable listing for BBC BASIC (LVAR)
+//!
+//! Lists what a program has defined, as BASIC V's LVAR does: every variable
+//! with its value, every array with its dimensions, and every PROC and FN
+//! with its parameters. The same names complete a partly typed name, for
+//! front ends that offer completion.
+
+use crate::charset;
+use crate::executor::Executor;
+use crate::variables::Variable;
+use serde::Serialize;
+use std::fmt;
+
+/// The variables, arrays and routines a program has defined, each in name
+/// order
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Listing {
+    /// Variables and their values
+    pub variables: Vec<(String, Variable)>,
+    /// Arrays and their dimensions, as given to DIM
+    pub arrays: Vec<(String, Vec<usize>)>,
+    /// PROCs and FNs (as `PROCname` or `FNname`) and their parameters
+    pub routines: Vec<(String, Vec<String>)>,
+}
+
+impl Listing {
+    /// List what the executor holds now
+    pub fn capture(executor: &Executor) -> Self {
+        let mut variables = Vec::new();
+        let mut arrays = Vec::new();
+        for (name, variable) in executor.variables().iter() {
+            match variable.dimensions() {
+                Some(dimensions) => arrays.push((name.clone(), dimensions.to_vec())),
+                None => variables.push((name.clone(), variable.clone())),
+            }
+        }
+        let mut routines: Vec<(String, Vec<String>)> = executor
+            .procedures()
+            .map(|(name, proc)| (format!("PROC{}", name), proc.params.clone()))
+            .chain(
+                executor
+                    .functions()
+                    .map(|(name, function)| (format!("FN{}", name), function.params.clone())),
+            )
+            .collect();
+        variables.sort_by(|a, b| a.0.cmp(&b.0));
+        arrays.sort();
+        routines.sort();
+        Self {
+            variables,
+            arrays,
+            routines,
+        }
+    }
+
+    /// The names starting with `prefix`, as they would be typed: arrays with
+    /// an opening bracket, and routines with `PROC` or `FN` in front
+    pub fn complete(&self, prefix: &str) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .variables
+            .iter()
+            .map(|(name, _)| name.clone())
+            .chain(self.arrays.iter().map(|(name, _)| format!("{}(", name)))
+            .chain(self.routines.iter().map(|(name, _)| name.clone()))
+            .filter(|name| name.starts_with(prefix))
+            .collect();
+        names.sort();
+        names
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in &self.variables {
+            match value {
+                Variable::Integer(value) => writeln!(f, "{} = {}", name, value)?,
+                Variable::Real(value) => writeln!(f, "{} = {}", name, value)?,
+                Variable::String(value) => {
+                    writeln!(f, "{} = \"{}\"", name, charset::to_unicode(value))?
+                }
+                _ => {}
+            }
+        }
+        for (name, dimensions) in &self.arrays {
+            let dimensions: Vec<String> = dimensions.iter().map(usize::to_string).collect();
+            writeln!(f, "{}({})", name, dimensions.join(","))?;
+        }
+        for (name, params) in &self.routines {
+            if params.is_empty() {
+                writeln!(f, "{}", name)?;
+            } else {
+                writeln!(f, "{}({})", name, params.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Interpreter;
+
+    #[test]
+    fn test_lvar() {
+        let mut interpreter = Interpreter::new();
+        for line in [
+            "10 A% = 42",
+            "20 total = 2.5",
+            "30 N$ = \"BOB\"",
+            "40 DIM grid%(3, 4), names$(9)",
+            "60 END",
+            "70 DEF PROCshow(N%, S$)",
+            "80 ENDPROC",
+            "90 DEF PROCtidy",
+            "100 ENDPROC",
+            "110 DEF FNtwice(N) = N * 2",
+        ] {
+            interpreter.process_line(line).unwrap();
+        }
+        interpreter.run().unwrap();
+
+        let listing = Listing::capture(interpreter.executor());
+        assert_eq!(
+            listing.to_string(),
+            "A% = 42\n\
+             N$ = \"BOB\"\n\
+             total = 2.5\n\
+             grid%(3,4)\n\
+             names$(9)\n\
+             FNtwice(N)\n\
+             PROCshow(N%, S$)\n\
+             PROCtidy\n"
+        );
+        assert_eq!(listing.complete("PROC"), ["PROCshow", "PROCtidy"]);
+        assert_eq!(listing.complete("n"), ["names$("]);
+        assert_eq!(listing.complete("FNt"), ["FNtwice"]);
+        assert!(listing.complete("Q").is_empty());
+    }
+}
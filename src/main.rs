@@ -1,36 +1,104 @@
 use bbc_basic_interpreter::{
-    executor::Executor,
-    parser::parse_statement,
-    program::ProgramStore,
-    tokenizer::{detokenize, tokenize},
+    autosave::Autosave,
+    charset,
+    config::{BootOption, Config, CONFIG_FILE_NAME},
+    debugger::{Pause, Step},
+    events::{OutputListener, TerminalRenderer},
+    filesystem::{self, decode_program, is_archive_spec, FileSystem, Tape, TapeDeck},
+    help,
+    interpreter::Interpreter,
+    numbering::{number_source, Numbering},
+    pack::PackOptions,
+    program::{self, ProgramStore},
+    sound,
+    tokenizer::TokenizerOptions,
+    watchdog::{Stuck, WatchdogAction},
+    window::{self, GraphicsWindow},
 };
-use std::io::{self, Write};
+use std::collections::VecDeque;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::Path;
 
 fn main() {
-    println!("BBC BASIC Interpreter v0.1.0");
-    println!("Type 'EXIT' to quit, 'HELP' for help\n");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let [command, filename] = args.as_slice() {
+        if command.eq_ignore_ascii_case("run") {
+            std::process::exit(run_file(filename));
+        }
+    }
 
-    let mut executor = Executor::new();
-    let mut program = ProgramStore::new();
+    let config = load_config();
+    for message in [&config.banner, &config.motd] {
+        if !message.is_empty() {
+            println!("{}", message);
+        }
+    }
+    println!("Type 'EXIT' to quit, 'HELP' for help\n");
+    print!("{}", config.colour_scheme.ansi_prefix());
+    // The inspector takes the program's output while RUN shows it (*INSPECT)
+    #[cfg(feature = "tui")]
+    let mut panel = bbc_basic_interpreter::inspector::Panel::default();
+    #[cfg(feature = "tui")]
+    let mut inspecting = false;
+    #[cfg(feature = "tui")]
+    let screen = panel.listener(Box::new(TerminalRenderer));
+    #[cfg(not(feature = "tui"))]
+    let screen: Box<dyn OutputListener> = Box::new(TerminalRenderer);
+    let mut interpreter = new_interpreter(config, screen);
     let stdin = io::stdin();
     let mut line_buffer = String::new();
+    // Cassette in the tape recorder, and whether LOAD/CHAIN read from it
+    let mut tape: Option<Tape> = None;
+    let mut tape_selected = false;
+    let mut autosave = Autosave::from_config(interpreter.config());
+    if let Some(autosave) = &autosave {
+        offer_recovery(&mut interpreter, autosave);
+    }
+    // Lines to be typed for *EXEC, before any more are read
+    let mut exec_lines = boot(&mut interpreter);
+    let mut window = GraphicsWindow::default();
 
     loop {
+        if let Some(autosave) = autosave.as_mut() {
+            if let Err(e) = autosave.tick(&interpreter) {
+                println!("Warning: autosave failed: {}", e);
+            }
+        }
+
+        // Draw the graphics window again if the picture has changed
+        let font = interpreter.config().font;
+        if let Some(picture) = window.update(interpreter.executor().graphics(), font) {
+            println!("{}", picture);
+        }
+
         // Prompt
         print!("> ");
         io::stdout().flush().unwrap();
 
-        // Read line
+        // Read line (stopping at the end of input)
         line_buffer.clear();
-        if stdin.read_line(&mut line_buffer).is_err() {
+        if let Some(line) = exec_lines.pop_front() {
+            println!("{}", line);
+            line_buffer = line;
+        } else if !matches!(stdin.read_line(&mut line_buffer), Ok(n) if n > 0) {
             break;
         }
 
-        let input = line_buffer.trim();
+        // Cursor keys and COPY (Tab) in the typed line copy text from the
+        // screen; show the resulting line when editing changed it
+        let typed = line_buffer.trim_end_matches(['\r', '\n']);
+        let executor = interpreter.executor_mut();
+        executor.screen_mut().write_str("> ");
+        let edited = executor.edit_line(typed);
+        if edited != charset::from_unicode(typed) {
+            println!("{}", charset::to_unicode(&edited));
+        }
+        let input = edited.trim();
 
         // Check for commands
         if input.eq_ignore_ascii_case("exit") || input.eq_ignore_ascii_case("quit") {
             println!("Goodbye!");
+            print!("{}", interpreter.config().colour_scheme.ansi_reset());
             break;
         }
 
@@ -39,36 +107,94 @@ fn main() {
             continue;
         }
 
+        // HELP keyword (show a keyword's syntax and description)
+        if let Some(keyword) = input
+            .split_once(' ')
+            .filter(|(command, _)| command.eq_ignore_ascii_case("help"))
+            .map(|(_, keyword)| keyword.trim())
+        {
+            if keyword.eq_ignore_ascii_case("keywords") {
+                println!("{}", help::keywords().join(" "));
+            } else {
+                match help::lookup(keyword) {
+                    Some(help) => print!("{}", help),
+                    None => println!("No help for {}: try HELP KEYWORDS", keyword),
+                }
+            }
+            continue;
+        }
+
         if input.is_empty() {
             continue;
         }
 
         // Handle special commands
         if input.eq_ignore_ascii_case("run") {
-            match run_program(&mut executor, &mut program) {
-                Ok(()) => {}
-                Err(e) => println!("Error: {}", e),
+            #[cfg(feature = "tui")]
+            if inspecting {
+                if let Err(e) = panel.run(&mut interpreter) {
+                    report_run_error(&interpreter, &e);
+                }
+                continue;
             }
+            run_program(&mut interpreter);
             continue;
         }
 
         if input.eq_ignore_ascii_case("list") {
-            list_program(&program);
+            list_program(&interpreter);
+            continue;
+        }
+
+        // CHECK command (warn of lines that never run and suspicious loops)
+        if input.eq_ignore_ascii_case("check") {
+            let warnings = interpreter.check();
+            if warnings.is_empty() {
+                println!("No problems found");
+            }
+            for warning in warnings {
+                println!("{}", warning);
+            }
+            continue;
+        }
+
+        // LVAR command (list the variables, arrays, PROCs and FNs)
+        if input.eq_ignore_ascii_case("lvar") {
+            print!("{}", interpreter.lvar());
             continue;
         }
 
         if input.eq_ignore_ascii_case("new") {
-            program.clear();
+            interpreter.new_program();
             println!("Program cleared");
             continue;
         }
 
+        // UNDO and REDO commands (step back and forth through program edits)
+        if input.eq_ignore_ascii_case("undo") || input.eq_ignore_ascii_case("redo") {
+            let result = if input.eq_ignore_ascii_case("undo") {
+                interpreter.undo()
+            } else {
+                interpreter.redo()
+            };
+            if let Err(e) = result {
+                println!("Error: {}", e);
+            }
+            continue;
+        }
+
         // SAVE command
         let input_upper = input.to_uppercase();
         if input_upper.starts_with("SAVE ") {
             match extract_filename(input) {
                 Ok(filename) => {
-                    if let Err(e) = save_program(&program, &filename) {
+                    if let Err(e) = interpreter
+                        .config()
+                        .resolve_path(&filename)
+                        .and_then(|path| {
+                            save_program(interpreter.program(), &path.to_string_lossy())
+                        })
+                    {
                         println!("Error: {}", e);
                     }
                 }
@@ -79,475 +205,740 @@ fn main() {
 
         // LOAD command
         if input_upper.starts_with("LOAD ") {
-            match extract_filename(input) {
-                Ok(filename) => {
-                    if let Err(e) = load_program(&mut program, &filename) {
-                        println!("Error: {}", e);
-                    }
+            let result = if tape_selected {
+                // LOAD "" loads the next file on the tape
+                let name = extract_filename(input).unwrap_or_default();
+                load_from_tape(&mut interpreter, tape.as_mut(), &name)
+            } else {
+                extract_filename(input)
+                    .and_then(|filename| load_any_program(&mut interpreter, &filename))
+            };
+            if let Err(e) = result {
+                println!("Error: {}", e);
+                if e == "Bad program" {
+                    println!("*BADPROG recovers what can still be read of it");
                 }
-                Err(e) => println!("Error: {}", e),
             }
             continue;
         }
 
         // CHAIN command (LOAD and RUN)
         if input_upper.starts_with("CHAIN ") {
-            match extract_filename(input) {
-                Ok(filename) => match load_program(&mut program, &filename) {
-                    Ok(_) => {
-                        if let Err(e) = run_program(&mut executor, &mut program) {
-                            println!("Error: {}", e);
-                        }
+            let result = if tape_selected {
+                let name = extract_filename(input).unwrap_or_default();
+                load_from_tape(&mut interpreter, tape.as_mut(), &name)
+            } else {
+                extract_filename(input).and_then(|filename| {
+                    if interpreter.has_slot(&filename) {
+                        interpreter.switch_slot(&filename)
+                    } else {
+                        load_any_program(&mut interpreter, &filename)
                     }
-                    Err(e) => println!("Error: {}", e),
-                },
+                })
+            };
+            match result {
+                Ok(_) => run_program(&mut interpreter),
                 Err(e) => println!("Error: {}", e),
             }
             continue;
         }
 
-        // *CAT command (catalog files)
-        if input.trim() == "*CAT" || input.trim().eq_ignore_ascii_case("*cat") {
-            if let Err(e) = catalog_files() {
-                println!("Error: {}", e);
+        // *EXEC command (type a file's lines as if at the keyboard)
+        if input_upper.starts_with("*EXEC ") {
+            let filename = input["*EXEC".len()..].trim().trim_matches('"');
+            match read_exec_file(&interpreter, filename) {
+                Ok(lines) => exec_lines.extend(lines),
+                Err(e) => println!("Error: {}", e),
             }
             continue;
         }
 
-        // Process the line (either store or execute)
-        match process_line(&mut executor, &mut program, input) {
-            Ok(()) => {}
-            Err(e) => println!("Error: {}", e),
+        // *OPT 4,n command (what SHIFT-BREAK, here starting up, does with
+        // the boot file)
+        if let Some(args) = input_upper.strip_prefix("*OPT") {
+            match args.split_once(',') {
+                Some((option, action)) if option.trim() == "4" => {
+                    configure(&mut interpreter, &format!("boot {}", action.trim()));
+                }
+                _ => println!("Error: Bad command (only *OPT 4,n is understood)"),
+            }
+            continue;
         }
-    }
-}
 
-fn process_line(
-    executor: &mut Executor,
-    program: &mut ProgramStore,
-    line: &str,
-) -> Result<(), String> {
-    // Tokenize
-    let tokenized = tokenize(line).map_err(|e| format!("Tokenization error: {:?}", e))?;
-
-    // Check if this is a numbered line (program mode) or immediate mode
-    if let Some(line_number) = tokenized.line_number {
-        // Program mode: store the line
-        if tokenized.tokens.is_empty() {
-            // Just a line number with no statement = delete that line
-            program.delete_line(line_number);
-            println!("Line {} deleted", line_number);
-        } else {
-            program.store_line(tokenized);
-            // Silent storage (like real BBC BASIC)
+        // *TAPE command (select the cassette filing system, optionally
+        // inserting a UEF tape image)
+        if input_upper == "*TAPE" || input_upper.starts_with("*TAPE ") {
+            let filename = input["*TAPE".len()..].trim().trim_matches('"');
+            if !filename.is_empty() {
+                let config = interpreter.config();
+                let realtime = config.tape_realtime && !config.deterministic;
+                match interpreter
+                    .config()
+                    .resolve_path(filename)
+                    .and_then(|path| {
+                        std::fs::read(path).map_err(|e| format!("Failed to read file: {}", e))
+                    })
+                    .and_then(|bytes| Tape::from_uef(&bytes, realtime))
+                {
+                    Ok(cassette) => tape = Some(cassette),
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        continue;
+                    }
+                }
+            }
+            tape_selected = true;
+            continue;
         }
-        Ok(())
-    } else {
-        // Immediate mode: execute immediately
-        let statement = parse_statement(&tokenized).map_err(|e| format!("Parse error: {:?}", e))?;
-
-        executor
-            .execute_statement(&statement)
-            .map_err(|e| format!("Runtime error: {:?}", e))?;
-
-        Ok(())
-    }
-}
-
-fn run_program(executor: &mut Executor, program: &mut ProgramStore) -> Result<(), String> {
-    if program.is_empty() {
-        return Err("No program to run".to_string());
-    }
 
-    // CRITICAL: Reset and collect all DATA statements BEFORE execution begins
-    // This ensures READ can access DATA regardless of program flow (GOTO, etc.)
-    executor.reset_data();
-
-    // First pass: collect all DATA statements and procedure definitions
-    executor.clear_procedures();
-    for (line_number, line) in program.list() {
-        let statement = parse_statement(line)
-            .map_err(|e| format!("Parse error at line {}: {:?}", line_number, e))?;
-
-        // Collect DATA statements
-        if matches!(statement, bbc_basic_interpreter::Statement::Data { .. }) {
-            executor
-                .collect_data(&statement)
-                .map_err(|e| format!("Error collecting DATA at line {}: {:?}", line_number, e))?;
+        // *DISC command (return to the disc filing system)
+        if input_upper == "*DISC" || input_upper == "*DISK" {
+            tape_selected = false;
+            continue;
         }
 
-        // Collect procedure definitions
-        if let bbc_basic_interpreter::Statement::DefProc { name, params } = statement {
-            executor.define_procedure(name, line_number, params);
+        // *MOTOR command (switch the cassette motor relay)
+        if input_upper.starts_with("*MOTOR") {
+            match (input["*MOTOR".len()..].trim(), tape.as_mut()) {
+                ("0", Some(cassette)) => cassette.set_motor(false),
+                ("1", Some(cassette)) => cassette.set_motor(true),
+                (_, None) => println!("Error: No tape inserted"),
+                _ => println!("Error: Syntax: *MOTOR 0|1"),
+            }
+            continue;
         }
-    }
 
-    // Start execution from first line
-    program.start_execution();
+        // *INSTALL command (install a library for good; *INSTALL alone lists
+        // the libraries bundled with the interpreter)
+        if input_upper == "*INSTALL" {
+            for library in bbc_basic_interpreter::library::LIBRARIES {
+                println!("{:<10} {}", library.name, library.description);
+            }
+            continue;
+        }
+        if input_upper.starts_with("*INSTALL ") {
+            let name = input["*INSTALL".len()..].trim().trim_matches('"');
+            match interpreter.install_library(name, true) {
+                Ok(()) => println!("Installed {}", name),
+                Err(e) => println!("Error: {}", e),
+            }
+            continue;
+        }
 
-    while let Some(line_number) = program.get_current_line() {
-        // Get the line
-        let line = program
-            .get_line(line_number)
-            .ok_or_else(|| format!("Line {} not found", line_number))?;
+        // *SLOT command (switch to another program held in memory; *SLOT
+        // alone lists them)
+        if input_upper == "*SLOT" {
+            for (name, lines) in interpreter.slots() {
+                let current = if name == interpreter.slot() { "*" } else { " " };
+                let plural = if lines == 1 { "" } else { "s" };
+                println!("{}{:<10} {} line{}", current, name, lines, plural);
+            }
+            continue;
+        }
+        if input_upper.starts_with("*SLOT ") {
+            let name = input["*SLOT".len()..].trim().trim_matches('"');
+            if let Err(e) = interpreter.switch_slot(name) {
+                println!("Error: {}", e);
+            }
+            continue;
+        }
 
-        // Parse the statement
-        let statement = parse_statement(line)
-            .map_err(|e| format!("Parse error at line {}: {:?}", line_number, e))?;
+        // *MERGE command (merge another program's lines into this one)
+        if input_upper.starts_with("*MERGE ") {
+            match extract_filename(input).and_then(|filename| interpreter.merge(&filename)) {
+                Ok(count) => println!("Merged {} lines", count),
+                Err(e) => println!("Error: {}", e),
+            }
+            continue;
+        }
 
-        // Check statement type before executing
-        let is_goto = matches!(statement, bbc_basic_interpreter::Statement::Goto { .. });
-        let is_gosub = matches!(statement, bbc_basic_interpreter::Statement::Gosub { .. });
-        let is_on_goto = matches!(statement, bbc_basic_interpreter::Statement::OnGoto { .. });
-        let is_on_gosub = matches!(statement, bbc_basic_interpreter::Statement::OnGosub { .. });
-        let is_return = matches!(statement, bbc_basic_interpreter::Statement::Return { .. });
-        let is_end = matches!(
-            statement,
-            bbc_basic_interpreter::Statement::End | bbc_basic_interpreter::Statement::Stop
-        );
-        let is_for = matches!(statement, bbc_basic_interpreter::Statement::For { .. });
-        let is_next = matches!(statement, bbc_basic_interpreter::Statement::Next { .. });
-        let is_repeat = matches!(statement, bbc_basic_interpreter::Statement::Repeat);
-        let is_until = matches!(statement, bbc_basic_interpreter::Statement::Until { .. });
-        let is_while = matches!(statement, bbc_basic_interpreter::Statement::While { .. });
-        let is_endwhile = matches!(statement, bbc_basic_interpreter::Statement::EndWhile);
-        let is_proc_call = matches!(statement, bbc_basic_interpreter::Statement::ProcCall { .. });
-        let is_endproc = matches!(statement, bbc_basic_interpreter::Statement::EndProc);
-
-        // Execute the statement
-        let execution_result = executor.execute_statement(&statement);
-
-        // Handle errors with ON ERROR handler if set
-        if let Err(e) = execution_result {
-            if let Some(handler_line) = executor.get_error_handler() {
-                // Convert BBCBasicError to error number
-                let error_number = match &e {
-                    bbc_basic_interpreter::BBCBasicError::DivisionByZero => 18,
-                    bbc_basic_interpreter::BBCBasicError::TypeMismatch => 6,
-                    bbc_basic_interpreter::BBCBasicError::SubscriptOutOfRange => 15,
-                    bbc_basic_interpreter::BBCBasicError::NoRoom => 11,
-                    bbc_basic_interpreter::BBCBasicError::StringTooLong => 19,
-                    bbc_basic_interpreter::BBCBasicError::NoSuchVariable(_) => 26,
-                    bbc_basic_interpreter::BBCBasicError::ArrayNotDimensioned(_) => 14,
-                    bbc_basic_interpreter::BBCBasicError::SyntaxError { .. } => 220,
-                    bbc_basic_interpreter::BBCBasicError::BadProgram => 254,
-                    bbc_basic_interpreter::BBCBasicError::IllegalFunction => 31,
-                    _ => 255, // Unknown error
-                };
-
-                // Set error information (ERL and ERR)
-                executor.set_last_error(error_number, line_number, format!("{:?}", e));
-
-                // Jump to error handler
-                if !program.goto_line(handler_line) {
-                    return Err(format!(
-                        "Error handler line {} not found (from error at line {})",
-                        handler_line, line_number
-                    ));
-                }
-                // Continue execution from error handler
-                continue;
-            } else {
-                // No error handler - propagate error as before
-                return Err(format!("Runtime error at line {}: {:?}", line_number, e));
+        // *BADPROG command (recover what can be read of a damaged program)
+        if input_upper.starts_with("*BADPROG ") {
+            match extract_filename(input).and_then(|filename| interpreter.salvage(&filename)) {
+                Ok(salvaged) => println!(
+                    "Recovered {} lines ({} repaired, {} lost)",
+                    salvaged.lines.len(),
+                    salvaged.repaired,
+                    salvaged.lost
+                ),
+                Err(e) => println!("Error: {}", e),
             }
+            continue;
         }
 
-        // Handle control flow
-        if is_end {
-            break;
-        } else if is_goto {
-            // GOTO: extract target and jump
-            if let bbc_basic_interpreter::Statement::Goto {
-                line_number: target,
-            } = statement
+        // *DIFF command (compare a saved program with the one in memory)
+        if input_upper.starts_with("*DIFF ") {
+            match extract_filename(input)
+                .and_then(|filename| Ok((interpreter.diff(&filename)?, filename)))
             {
-                if !program.goto_line(target) {
-                    return Err(format!("Line {} not found (GOTO)", target));
+                Ok((changes, _)) if changes.is_empty() => println!("No differences"),
+                Ok((changes, filename)) => {
+                    for line in program::unified_diff(&filename, "(memory)", &changes) {
+                        println!("{}", charset::to_unicode(&line));
+                    }
                 }
+                Err(e) => println!("Error: {}", e),
             }
-        } else if is_gosub {
-            // GOSUB: save return address (this line) and jump to target
-            if let bbc_basic_interpreter::Statement::Gosub {
-                line_number: target,
-            } = statement
-            {
-                // Push the current line number so RETURN can come back here
-                executor.push_gosub_return(line_number);
+            continue;
+        }
 
-                // Jump to the target subroutine
-                if !program.goto_line(target) {
-                    return Err(format!("Line {} not found (GOSUB)", target));
-                }
-            }
-        } else if is_on_goto {
-            // ON GOTO: evaluate expression and jump to computed target
-            if let bbc_basic_interpreter::Statement::OnGoto {
-                expression,
-                targets,
-            } = &statement
-            {
-                // Evaluate expression - BBC BASIC uses 1-based indexing
-                let index = executor
-                    .eval_integer(expression)
-                    .map_err(|e| format!("Error evaluating ON GOTO expression: {:?}", e))?;
-
-                // Check if index is valid (1-based, so 1 = first target, 2 = second, etc.)
-                if index >= 1 && (index as usize) <= targets.len() {
-                    let target = targets[(index - 1) as usize];
-                    if !program.goto_line(target) {
-                        return Err(format!("Line {} not found (ON GOTO)", target));
+        // *FIND and *CHANGE commands (search and replace across the program)
+        if input_upper.starts_with("*FIND") {
+            match interpreter.find(&input["*FIND".len()..]) {
+                Ok(lines) => {
+                    for line in lines {
+                        println!("{}", charset::to_unicode(&line));
                     }
                 }
-                // If index is out of range, just continue to next line (fall through)
-            }
-        } else if is_on_gosub {
-            // ON GOSUB: evaluate expression and gosub to computed target
-            if let bbc_basic_interpreter::Statement::OnGosub {
-                expression,
-                targets,
-            } = &statement
-            {
-                // Evaluate expression - BBC BASIC uses 1-based indexing
-                let index = executor
-                    .eval_integer(expression)
-                    .map_err(|e| format!("Error evaluating ON GOSUB expression: {:?}", e))?;
-
-                // Check if index is valid (1-based)
-                if index >= 1 && (index as usize) <= targets.len() {
-                    let target = targets[(index - 1) as usize];
+                Err(e) => println!("Error: {}", e),
+            }
+            continue;
+        }
+        if input_upper.starts_with("*CHANGE") {
+            match interpreter.change(&input["*CHANGE".len()..]) {
+                Ok(count) => println!("Changed {} lines", count),
+                Err(e) => println!("Error: {}", e),
+            }
+            continue;
+        }
 
-                    // Push return address
-                    executor.push_gosub_return(line_number);
+        // *LOAD and *SAVE commands (move raw memory to and from files)
+        if input_upper.starts_with("*LOAD ") {
+            if let Err(e) = interpreter.load_memory(&input["*LOAD".len()..]) {
+                println!("Error: {}", e);
+            }
+            continue;
+        }
+        if input_upper.starts_with("*SAVE ") {
+            if let Err(e) = interpreter.save_memory(&input["*SAVE".len()..]) {
+                println!("Error: {}", e);
+            }
+            continue;
+        }
 
-                    // Jump to target
-                    if !program.goto_line(target) {
-                        return Err(format!("Line {} not found (ON GOSUB)", target));
-                    }
-                }
-                // If index is out of range, just continue to next line (fall through)
-            }
-        } else if is_return {
-            // RETURN: pop return address and jump back
-            match executor.pop_gosub_return() {
-                Ok(return_line) => {
-                    // Jump back to the line that called GOSUB
-                    if program.goto_line(return_line) {
-                        // Move to the line AFTER the GOSUB
-                        program.next_line();
-                    } else {
-                        return Err(format!("Return line {} not found", return_line));
+        // *MEMDUMP and *MEMSET commands (show and change memory)
+        if input_upper.starts_with("*MEMDUMP") {
+            match interpreter.memory_dump(&input["*MEMDUMP".len()..]) {
+                Ok(lines) => {
+                    for line in lines {
+                        println!("{}", line);
                     }
                 }
-                Err(_) => {
-                    return Err("RETURN without GOSUB".to_string());
-                }
+                Err(e) => println!("Error: {}", e),
             }
-        } else if is_proc_call {
-            // PROC call: get procedure definition, bind parameters, push return address, jump
-            if let bbc_basic_interpreter::Statement::ProcCall { name, args } = statement {
-                // Get procedure definition
-                let proc = executor
-                    .get_procedure(&name)
-                    .ok_or_else(|| format!("Procedure {} not defined", name))?;
-
-                // Check parameter count
-                if args.len() != proc.params.len() {
-                    return Err(format!(
-                        "Procedure {} expects {} parameters, got {}",
-                        name,
-                        proc.params.len(),
-                        args.len()
-                    ));
-                }
-
-                // Clone procedure data before entering local scope
-                let proc_line = proc.line_number;
-                let params_and_args: Vec<_> = proc
-                    .params
-                    .iter()
-                    .zip(args.iter())
-                    .map(|(p, a)| (p.clone(), a.clone()))
-                    .collect();
-
-                // Enter local scope for procedure
-                executor.enter_local_scope();
+            continue;
+        }
+        if input_upper.starts_with("*MEMSET") {
+            if let Err(e) = interpreter.memory_set(&input["*MEMSET".len()..]) {
+                println!("Error: {}", e);
+            }
+            continue;
+        }
 
-                // Bind arguments to parameters (as global variables)
-                for (param_name, arg_expr) in params_and_args {
-                    executor
-                        .execute_statement(&bbc_basic_interpreter::Statement::Assignment {
-                            target: param_name,
-                            expression: arg_expr,
-                        })
-                        .map_err(|e| format!("Error binding parameter: {:?}", e))?;
+        // *HISTORY command (show the recorded changes to a variable)
+        if input_upper.starts_with("*HISTORY") {
+            match interpreter.history(input["*HISTORY".len()..].trim()) {
+                Ok(lines) => {
+                    for line in lines {
+                        println!("{}", line);
+                    }
                 }
+                Err(e) => println!("Error: {}", e),
+            }
+            continue;
+        }
 
-                // Push return address (current line number)
-                executor.push_gosub_return(line_number);
-
-                // Jump to procedure line
-                if !program.goto_line(proc_line) {
-                    return Err(format!("Procedure {} line {} not found", name, proc_line));
-                }
+        // *CAT command (catalog files)
+        if input.trim() == "*CAT" || input.trim().eq_ignore_ascii_case("*cat") {
+            let names = interpreter.config().filenames();
+            match filesystem::catalogue(names.root().unwrap_or(Path::new("."))) {
+                Ok(listing) => print!("{}", listing),
+                Err(e) => println!("Error: {}", e),
+            }
+            continue;
+        }
 
-                // Move to line AFTER DEF PROC (skip the definition line)
-                program.next_line();
-            }
-        } else if is_endproc {
-            // ENDPROC: exit local scope and pop return address
-            executor
-                .exit_local_scope()
-                .map_err(|e| format!("Error exiting local scope: {:?}", e))?;
-
-            match executor.pop_gosub_return() {
-                Ok(return_line) => {
-                    // Jump back to the line that called PROC
-                    if program.goto_line(return_line) {
-                        // Move to the line AFTER the PROC call
-                        program.next_line();
+        // *WAV command (save captured SOUND output)
+        if input_upper.starts_with("*WAV ") {
+            match extract_filename(input) {
+                Ok(filename) => {
+                    let filename = if filename.contains('.') {
+                        filename
                     } else {
-                        return Err(format!("Return line {} not found", return_line));
+                        format!("{}.wav", filename)
+                    };
+                    match interpreter
+                        .config()
+                        .resolve_path(&filename)
+                        .and_then(|path| {
+                            interpreter
+                                .executor()
+                                .sound()
+                                .save_wav(&path)
+                                .map_err(|e| e.to_string())
+                        }) {
+                        Ok(()) => println!("Sound saved to {}", filename),
+                        Err(e) => println!("Error: {}", e),
                     }
                 }
-                Err(_) => {
-                    return Err("ENDPROC without PROC call".to_string());
-                }
+                Err(e) => println!("Error: {}", e),
             }
-        } else if is_for {
-            // FOR: record this line number for NEXT to loop back to
-            executor.set_for_loop_line(line_number);
-            program.next_line();
-        } else if is_next {
-            // NEXT: check if we should loop back
-            if let Some(for_line) = executor.should_loop_back() {
-                // Loop continues - go back to the line AFTER the FOR statement
-                if program.goto_line(for_line) {
-                    program.next_line(); // Move to line after FOR
-                } else {
-                    return Err(format!("FOR loop line {} not found", for_line));
-                }
-            } else {
-                // Loop completed - continue to next line
-                program.next_line();
-            }
-        } else if is_repeat {
-            // REPEAT: push this line number for UNTIL to loop back to
-            executor.push_repeat(line_number);
-            program.next_line();
-        } else if is_until {
-            // UNTIL: check condition and loop back if false
-            if let bbc_basic_interpreter::Statement::Until { condition } = statement {
-                match executor.check_until(&condition) {
-                    Ok(Some(repeat_line)) => {
-                        // Condition false - loop back to line AFTER REPEAT
-                        if program.goto_line(repeat_line) {
-                            program.next_line();
-                        } else {
-                            return Err(format!("REPEAT line {} not found", repeat_line));
-                        }
-                    }
-                    Ok(None) => {
-                        // Condition true - exit loop, continue to next line
-                        program.next_line();
-                    }
-                    Err(e) => {
-                        return Err(format!("Error evaluating UNTIL condition: {:?}", e));
+            continue;
+        }
+
+        // *STRUCTURE command (rewrite GOTO/GOSUB as structured statements)
+        if input_upper == "*STRUCTURE" {
+            match interpreter.structure() {
+                Ok(rewrites) if rewrites.is_empty() => println!("No changes"),
+                Ok(rewrites) => {
+                    for rewrite in rewrites {
+                        println!("{}", rewrite);
                     }
                 }
+                Err(e) => println!("Error: {}", e),
             }
-        } else if is_while {
-            // WHILE: check condition and enter loop if true, skip to ENDWHILE if false
-            if let bbc_basic_interpreter::Statement::While { condition } = statement {
-                match executor.push_while(line_number, &condition) {
-                    Ok(Some(_)) => {
-                        // Condition true - enter loop body
-                        program.next_line();
-                    }
-                    Ok(None) => {
-                        // Condition false - skip to line after ENDWHILE
-                        // Find the matching ENDWHILE by scanning forward
-                        let mut depth = 1;
-                        while depth > 0 {
-                            if program.next_line().is_none() {
-                                return Err("WHILE without matching ENDWHILE".to_string());
+            continue;
+        }
+
+        // *PACK command (take out REMs and spaces; *PACK KEEP keeps the REMs)
+        if input_upper == "*PACK" || input_upper == "*PACK KEEP" {
+            let options = PackOptions {
+                strip_comments: input_upper == "*PACK",
+            };
+            match interpreter.pack(options) {
+                Ok(report) => println!("{}", report),
+                Err(e) => println!("Error: {}", e),
+            }
+            continue;
+        }
+
+        // *COMPILE command (translate the program to Rust source)
+        if input_upper.starts_with("*COMPILE ") {
+            match extract_filename(input) {
+                Ok(filename) => {
+                    let filename = if filename.contains('.') {
+                        filename
+                    } else {
+                        format!("{}.rs", filename)
+                    };
+                    match interpreter
+                        .config()
+                        .resolve_path(&filename)
+                        .and_then(|path| interpreter.compile().map(|compiled| (path, compiled)))
+                    {
+                        Ok((path, compiled)) => {
+                            for diagnostic in &compiled.diagnostics {
+                                println!("Warning: {}", diagnostic);
                             }
-                            
-                            let current_line = program.get_current_line().unwrap();
-                            if let Some(line) = program.get_line(current_line) {
-                                if let Ok(stmt) = parse_statement(&line) {
-                                    if matches!(stmt, bbc_basic_interpreter::Statement::While { .. }) {
-                                        depth += 1;
-                                    } else if matches!(stmt, bbc_basic_interpreter::Statement::EndWhile) {
-                                        depth -= 1;
-                                    }
-                                }
+                            match std::fs::write(&path, compiled.source) {
+                                Ok(()) => println!("Rust source written to {}", filename),
+                                Err(e) => println!("Error: {}", e),
                             }
                         }
-                        program.next_line(); // Move past ENDWHILE
-                    }
-                    Err(e) => {
-                        return Err(format!("Error evaluating WHILE condition: {:?}", e));
+                        Err(e) => println!("Error: {}", e),
                     }
                 }
+                Err(e) => println!("Error: {}", e),
             }
-        } else if is_endwhile {
-            // ENDWHILE: check condition and loop back if true
-            // Need to retrieve the WHILE condition from the original WHILE statement
-            // Find the matching WHILE by using the while_stack
-            if let Some(while_line) = executor.check_endwhile_get_while_line() {
-                if let Some(line) = program.get_line(while_line) {
-                    if let Ok(bbc_basic_interpreter::Statement::While { condition }) = 
-                        parse_statement(&line) {
-                        match executor.check_endwhile(&condition) {
-                            Ok(Some(while_line_num)) => {
-                                // Condition still true - loop back to line AFTER WHILE
-                                if program.goto_line(while_line_num) {
-                                    program.next_line();
-                                } else {
-                                    return Err(format!("WHILE line {} not found", while_line_num));
-                                }
-                            }
-                            Ok(None) => {
-                                // Condition false - exit loop, continue to next line
-                                program.next_line();
-                            }
-                            Err(e) => {
-                                return Err(format!("Error evaluating WHILE condition at ENDWHILE: {:?}", e));
-                            }
-                        }
+            continue;
+        }
+
+        // *EXPORT HTML command (write a highlighted listing of the program)
+        if input_upper.starts_with("*EXPORT ") {
+            match input["*EXPORT".len()..].trim().split_once(' ') {
+                Some((format, filename)) if format.eq_ignore_ascii_case("HTML") => {
+                    let filename = filename.trim().trim_matches('"');
+                    let filename = if filename.contains('.') {
+                        filename.to_string()
                     } else {
-                        return Err(format!("Could not parse WHILE statement at line {}", while_line));
+                        format!("{}.html", filename)
+                    };
+                    match interpreter
+                        .config()
+                        .resolve_path(&filename)
+                        .and_then(|path| {
+                            interpreter.export_html(&filename).map(|html| (path, html))
+                        }) {
+                        Ok((path, html)) => match std::fs::write(&path, html) {
+                            Ok(()) => println!("Listing written to {}", filename),
+                            Err(e) => println!("Error: {}", e),
+                        },
+                        Err(e) => println!("Error: {}", e),
                     }
-                } else {
-                    return Err(format!("WHILE line {} not found", while_line));
                 }
+                _ => println!("Error: Expected *EXPORT HTML filename"),
+            }
+            continue;
+        }
+
+        // *BREAK and *NOBREAK commands (set, list or clear breakpoints)
+        if input_upper.starts_with("*BREAK") || input_upper.starts_with("*NOBREAK") {
+            breakpoints(&mut interpreter, &input_upper);
+            continue;
+        }
+
+        // *DEBUG, *STEP and CONT commands (run under the debugger, step, and
+        // resume after a breakpoint or error)
+        if matches!(input_upper.as_str(), "*DEBUG" | "CONT" | "*CONT")
+            || input_upper.starts_with("*STEP")
+        {
+            let words: Vec<&str> = input_upper.split_whitespace().collect();
+            let result = match words[..] {
+                ["*DEBUG"] => interpreter.debug(),
+                ["CONT"] | ["*CONT"] => interpreter.step(Step::Continue),
+                ["*STEP"] | ["*STEP", "INTO"] => interpreter.step(Step::Into),
+                ["*STEP", "OVER"] => interpreter.step(Step::Over),
+                ["*STEP", "OUT"] => interpreter.step(Step::Out),
+                _ => Err("Syntax: *STEP [INTO|OVER|OUT]".to_string()),
+            };
+            match result {
+                Ok(Pause::Break(line_number)) => println!(
+                    "Break at {}",
+                    interpreter
+                        .list_line(line_number)
+                        .unwrap_or_else(|| format!("line {}", line_number))
+                ),
+                Ok(_) => {}
+                Err(e) => report_run_error(&interpreter, &e),
+            }
+            continue;
+        }
+
+        // *STATUS or INFO command (show the memory map and free space)
+        if input_upper == "*STATUS" || input_upper == "INFO" {
+            println!("{}", interpreter.memory_status());
+            continue;
+        }
+
+        // *TITLE command (show the program's title and author, or set its title)
+        if input_upper == "*TITLE" || input_upper.starts_with("*TITLE ") {
+            let title = input["*TITLE".len()..].trim();
+            if title.is_empty() {
+                let program = interpreter.program();
+                println!("Title:  {}", program.title().unwrap_or_default());
+                println!("Author: {}", program.author().unwrap_or_default());
+            } else {
+                interpreter.program_mut().set_title(title.trim_matches('"'));
+            }
+            continue;
+        }
+
+        // *FX command (OSBYTE call, e.g. *FX 15 to flush the keyboard buffer)
+        if input_upper.starts_with("*FX") {
+            if let Err(e) = interpreter.fx(&input["*FX".len()..]) {
+                println!("Error: {}", e);
+            }
+            continue;
+        }
+
+        // *CLOSEWIN command (stop drawing the graphics window)
+        if input_upper == "*CLOSEWIN" {
+            window.close();
+            continue;
+        }
+
+        // *THEME command (show or change the colour scheme)
+        if input_upper == "*THEME" || input_upper.starts_with("*THEME ") {
+            let theme = input["*THEME".len()..].trim();
+            if theme.is_empty() {
+                println!("Theme: {}", interpreter.config().colour_scheme);
+                println!("Themes: default, classic, contrast, green, amber");
             } else {
-                return Err("ENDWHILE without matching WHILE".to_string());
+                configure(&mut interpreter, &format!("colour_scheme {}", theme));
+            }
+            continue;
+        }
+
+        // *FONT command (show or change the font VDU 5 text is shown in)
+        if input_upper == "*FONT" || input_upper.starts_with("*FONT ") {
+            let font = input["*FONT".len()..].trim();
+            if font.is_empty() {
+                println!("Font: {}", interpreter.config().font);
+                println!("Fonts: beeb, system");
+            } else {
+                configure(&mut interpreter, &format!("font {}", font));
+            }
+            continue;
+        }
+
+        // *SHADOW command (draw off the screen until the next frame)
+        if input_upper.starts_with("*SHADOW") {
+            if let Err(e) = interpreter.executor_mut().oscli(input) {
+                println!("Error: {}", e);
+            }
+            continue;
+        }
+
+        // *INSPECT command (RUN programs beside a panel of variables, PROC
+        // calls and TIME, watching the variables named, or all of them)
+        #[cfg(feature = "tui")]
+        if input_upper.starts_with("*INSPECT") {
+            let arguments = input["*INSPECT".len()..].trim();
+            inspecting = !arguments.eq_ignore_ascii_case("OFF");
+            if inspecting {
+                let watched = arguments
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(String::from)
+                    .collect();
+                panel.set_watched(watched);
+                println!("RUN shows the inspector until *INSPECT OFF");
+            }
+            continue;
+        }
+
+        // *SAY command (speak a phrase, queued behind any still being spoken)
+        #[cfg(feature = "speech")]
+        if input_upper.starts_with("*SAY") {
+            if let Err(e) = interpreter.executor_mut().oscli(input) {
+                println!("Error: {}", e);
             }
-        } else {
-            // Normal: advance to next line
-            if program.next_line().is_none() {
-                break;
+            continue;
+        }
+
+        // *CONFIGURE command (show or change interpreter options)
+        if input_upper.starts_with("*CONFIGURE") {
+            let settings = |config: &Config| (config.autosave, config.autosave_variables);
+            let before = settings(interpreter.config());
+            configure(&mut interpreter, input["*CONFIGURE".len()..].trim());
+            // Pick up changes to the autosave settings
+            if settings(interpreter.config()) != before {
+                if let Some(old) = autosave.take() {
+                    let _ = old.finish();
+                }
+                autosave = Autosave::from_config(interpreter.config());
             }
+            continue;
+        }
+
+        // Drawing at the prompt opens the graphics window
+        if window::draws(input) {
+            window.open();
+        }
+
+        // Process the line (either store or execute)
+        if let Err(e) = interpreter.process_line(input) {
+            report_error(&interpreter, &e);
         }
     }
 
-    program.stop_execution();
-    Ok(())
+    // A clean exit leaves nothing to recover
+    if let Some(autosave) = autosave {
+        if let Err(e) = autosave.finish() {
+            println!("Warning: {}", e);
+        }
+    }
 }
 
-fn list_program(program: &ProgramStore) {
-    if program.is_empty() {
-        println!("No program");
+/// Offer to restore the program saved by a session that did not exit cleanly
+fn offer_recovery(interpreter: &mut Interpreter, autosave: &Autosave) {
+    let snapshot = match autosave.recover() {
+        Ok(Some(snapshot)) => snapshot,
+        Ok(None) => return,
+        Err(e) => {
+            println!("Warning: {}", e);
+            return;
+        }
+    };
+
+    print!(
+        "The last session did not exit cleanly. Restore its program ({} lines)? (Y/N) ",
+        snapshot.program.len()
+    );
+    io::stdout().flush().unwrap();
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_ok() && answer.trim().eq_ignore_ascii_case("y") {
+        match snapshot.restore(interpreter) {
+            Ok(()) => println!("Program restored"),
+            Err(e) => println!("Error: {}", e),
+        }
+    }
+}
+
+/// An interpreter writing to the terminal through `screen`
+fn new_interpreter(config: Config, screen: Box<dyn OutputListener>) -> Interpreter {
+    let mut interpreter = Interpreter::with_config(config);
+    interpreter.subscribe(screen);
+    // *SAY speaks through the host's speech program, if it has one
+    #[cfg(feature = "speech")]
+    if let Some(speaker) = bbc_basic_interpreter::speech::HostSpeaker::detect() {
+        interpreter.set_speaker(Box::new(speaker));
+    }
+    // Someone at the keyboard is asked what to do about a stuck program
+    if io::stdin().is_terminal() {
+        interpreter.set_stuck_handler(Box::new(ask_when_stuck));
+    }
+    interpreter
+}
+
+/// Run a program file without the prompt (`run prog.bbas`), returning the
+/// exit status
+///
+/// Only the program's own output goes to stdout, and errors go to stderr,
+/// so with its input piped in (`run prog.bbas < data.txt`) a program works
+/// as a filter: INPUT and GET read the piped lines without prompting, and
+/// the end of them raises Eof, which ON ERROR can trap.
+fn run_file(filename: &str) -> i32 {
+    let mut interpreter = new_interpreter(load_config(), Box::new(TerminalRenderer));
+    let options = interpreter.config().tokenizer_options();
+    let numbering = interpreter.config().numbering();
+    let path = interpreter.config().resolve_path(filename);
+    let loaded = path.and_then(|path| {
+        load_program(
+            interpreter.program_mut(),
+            &path.to_string_lossy(),
+            &options,
+            numbering,
+        )
+    });
+    let result = loaded.and_then(|_| interpreter.run());
+    io::stdout().flush().ok();
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            1
+        }
+    }
+}
+
+/// Act on the boot file at start-up as *OPT 4 says, like SHIFT-BREAK with
+/// a disc in the drive: LOAD it, CHAIN it, or *EXEC it, returning the
+/// lines to be typed
+fn boot(interpreter: &mut Interpreter) -> VecDeque<String> {
+    let file = interpreter.config().boot_file.clone();
+    let result = match interpreter.config().boot {
+        BootOption::Off => return VecDeque::new(),
+        BootOption::Load => load_any_program(interpreter, &file).map(|()| VecDeque::new()),
+        BootOption::Run => load_any_program(interpreter, &file).map(|()| {
+            run_program(interpreter);
+            VecDeque::new()
+        }),
+        BootOption::Exec => read_exec_file(interpreter, &file),
+    };
+    result.unwrap_or_else(|e| {
+        println!("Error: cannot boot {}: {}", file, e);
+        VecDeque::new()
+    })
+}
+
+/// The lines of a text file for *EXEC, which may be in a disc image
+/// (`GAMES.SSD#!BOOT`); BBC files end lines with CR, host files with LF
+fn read_exec_file(interpreter: &Interpreter, spec: &str) -> Result<VecDeque<String>, String> {
+    let filesystem = FileSystem::with_translator(interpreter.config().filenames());
+    let file = filesystem.read_file(spec)?;
+    Ok(charset::to_unicode(&charset::from_bytes(&file.data))
+        .split(['\r', '\n'])
+        .filter(|line| !line.trim().is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Ask what to do about a program that has got nowhere for a while
+fn ask_when_stuck(stuck: &Stuck) -> WatchdogAction {
+    loop {
+        print!("\n{}. (B)reak, (T)race or (C)ontinue? ", stuck);
+        io::stdout().flush().unwrap();
+        let mut answer = String::new();
+        if !matches!(io::stdin().read_line(&mut answer), Ok(n) if n > 0) {
+            return WatchdogAction::Break;
+        }
+        match answer.trim().to_ascii_uppercase().as_str() {
+            "B" => return WatchdogAction::Break,
+            "T" => return WatchdogAction::Trace,
+            "C" => return WatchdogAction::Continue,
+            _ => {}
+        }
+    }
+}
+
+/// Load the configuration file, falling back to defaults if there is none
+fn load_config() -> Config {
+    match Config::default_path() {
+        Some(path) => Config::load(&path).unwrap_or_else(|e| {
+            eprintln!("Warning: {} (using default configuration)", e);
+            Config::default()
+        }),
+        None => Config::default(),
+    }
+}
+
+/// *BREAK line sets a breakpoint and *BREAK lists them; *NOBREAK line
+/// clears one and *NOBREAK clears them all
+fn breakpoints(interpreter: &mut Interpreter, command: &str) {
+    let (name, args) = command
+        .split_once(char::is_whitespace)
+        .unwrap_or((command, ""));
+    let line_number = match args.trim() {
+        "" => None,
+        args => match args.parse::<u16>() {
+            Ok(line_number) => Some(line_number),
+            Err(_) => {
+                println!("Error: Syntax: {} [line]", name);
+                return;
+            }
+        },
+    };
+
+    let debugger = interpreter.debugger_mut();
+    match (name, line_number) {
+        ("*BREAK", Some(line_number)) => debugger.set_breakpoint(line_number),
+        ("*BREAK", None) => {
+            let lines: Vec<String> = debugger.breakpoints().map(|n| n.to_string()).collect();
+            if lines.is_empty() {
+                println!("No breakpoints");
+            } else {
+                println!("Breakpoints: {}", lines.join(", "));
+            }
+        }
+        ("*NOBREAK", Some(line_number)) => {
+            if !debugger.clear_breakpoint(line_number) {
+                println!("No breakpoint at line {}", line_number);
+            }
+        }
+        ("*NOBREAK", None) => debugger.clear_breakpoints(),
+        _ => println!("Error: Unknown command {}", name),
+    }
+}
+
+/// Handle *CONFIGURE: list options, set one, or SAVE them to the config file
+fn configure(interpreter: &mut Interpreter, args: &str) {
+    if args.is_empty() {
+        println!("{}", interpreter.config().describe());
         return;
     }
 
-    for (line_number, line) in program.list() {
-        match detokenize(line) {
-            Ok(text) => println!("{}", text),
-            Err(e) => println!("Error listing line {}: {:?}", line_number, e),
+    if args.eq_ignore_ascii_case("save") {
+        let path = Config::default_path().unwrap_or_else(|| CONFIG_FILE_NAME.into());
+        match interpreter.config().save(&path) {
+            Ok(()) => println!("Configuration saved to {}", path.display()),
+            Err(e) => println!("Error: {}", e),
+        }
+        return;
+    }
+
+    let (key, value) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let old_scheme = interpreter.config().colour_scheme;
+    match interpreter.configure(key, value.trim()) {
+        Ok(()) => {
+            let scheme = interpreter.config().colour_scheme;
+            if scheme != old_scheme {
+                print!("{}{}", old_scheme.ansi_reset(), scheme.ansi_prefix());
+            }
         }
+        Err(e) => println!("Error: {}", e),
+    }
+}
+
+fn list_program(interpreter: &Interpreter) {
+    if interpreter.program().is_empty() {
+        println!("No program");
+        return;
+    }
+
+    for text in interpreter.list() {
+        println!("{}", charset::to_unicode(&text));
     }
 }
 
@@ -592,119 +983,216 @@ fn save_program(program: &ProgramStore, filename: &str) -> Result<(), String> {
     let mut file =
         std::fs::File::create(&path).map_err(|e| format!("Failed to create file: {}", e))?;
 
-    // Write each line (detokenized)
+    // Write each line (detokenized), with the comments and blank lines it
+    // was loaded with
     use std::io::Write;
-    for (line_number, line) in program.list() {
-        let text = detokenize(line)
-            .map_err(|e| format!("Failed to detokenize line {}: {:?}", line_number, e))?;
-        writeln!(file, "{}", text)
-            .map_err(|e| format!("Failed to write line {}: {}", line_number, e))?;
+    for text in program.to_text(&TokenizerOptions::default())? {
+        writeln!(file, "{}", text).map_err(|e| format!("Failed to write {}: {}", path, e))?;
     }
 
     println!("Saved to {}", path);
     Ok(())
 }
 
-/// Load program from a .bbas file
-fn load_program(program: &mut ProgramStore, filename: &str) -> Result<(), String> {
-    // Add .bbas extension if not present
-    let path = if filename.ends_with(".bbas") {
-        filename.to_string()
+/// Report an error in red, showing the line it was on with a caret under
+/// the part at fault
+fn report_error(interpreter: &Interpreter, error: &str) {
+    // Piped output is left as plain text
+    let (red, restore) = if io::stdout().is_terminal() {
+        let scheme = interpreter.config().colour_scheme;
+        ("\x1b[91m", format!("\x1b[0m{}", scheme.ansi_prefix()))
     } else {
-        format!("{}.bbas", filename)
+        ("", String::new())
     };
+    println!("{}Error: {}{}", red, error, restore);
+    if let Some(source) = interpreter.error_source() {
+        let indent = source.text[..source.span.start].chars().count();
+        let width = source.text[source.span.clone()].chars().count().max(1);
+        println!("  {}", charset::to_unicode(&source.text));
+        println!(
+            "  {}{}{}{}",
+            " ".repeat(indent),
+            red,
+            "^".repeat(width),
+            restore
+        );
+    }
+}
 
-    // Read file
-    let content =
-        std::fs::read_to_string(&path).map_err(|e| format!("Failed to read file: {}", e))?;
-
-    // Clear current program (like NEW command)
-    program.clear();
-    // Note: We don't reset executor state - variables persist across LOAD
-    // This matches BBC BASIC behavior where LOAD doesn't clear variables
-
-    // Parse and add each line
-    for (line_num, line) in content.lines().enumerate() {
-        let line = line.trim();
-        if line.is_empty() {
-            continue; // Skip empty lines
+/// RUN the program, first warning of any jumps to lines it does not have
+/// (unless `strict.line_numbers` refuses to run it at all)
+fn run_program(interpreter: &mut Interpreter) {
+    if !interpreter.config().strict.line_numbers {
+        for (line_number, target) in interpreter.missing_lines() {
+            println!("Warning: no such line {} at line {}", target, line_number);
         }
+    }
+    if let Err(e) = interpreter.run() {
+        report_run_error(interpreter, &e);
+    }
+}
 
-        // Tokenize and store
-        let tokenized =
-            tokenize(line).map_err(|e| format!("Parse error at line {}: {:?}", line_num + 1, e))?;
-
-        if tokenized.line_number.is_some() {
-            program.store_line(tokenized);
-        } else {
-            return Err(format!(
-                "Line {} has no line number: {}",
-                line_num + 1,
-                line
-            ));
+/// Report an error that stopped a program, with the post-mortem dump if
+/// configured
+fn report_run_error(interpreter: &Interpreter, error: &str) {
+    report_error(interpreter, error);
+    if interpreter.config().post_mortem {
+        if let Some(dump) = interpreter.post_mortem() {
+            print!("{}", dump);
         }
     }
+}
 
-    println!("Loaded from {}", path);
-    Ok(())
+/// LOAD or CHAIN a program from a .bbas file, or from a URL, disc or tape
+/// image or archive
+fn load_any_program(interpreter: &mut Interpreter, filename: &str) -> Result<(), String> {
+    let options = interpreter.config().tokenizer_options();
+    if is_archive_spec(filename) {
+        let filesystem = FileSystem::with_translator(interpreter.config().filenames());
+        load_archived_program(interpreter.program_mut(), &filesystem, filename, &options)
+    } else {
+        let path = interpreter.config().resolve_path(filename)?;
+        let numbering = interpreter.config().numbering();
+        let path = load_program(
+            interpreter.program_mut(),
+            &path.to_string_lossy(),
+            &options,
+            numbering,
+        )?;
+        println!("Loaded from {}", path);
+        Ok(())
+    }
 }
 
-/// Catalog all .bbas files in current directory
-fn catalog_files() -> Result<(), String> {
-    let paths = std::fs::read_dir(".").map_err(|e| format!("Failed to read directory: {}", e))?;
+/// LOAD or CHAIN the next program called `name` (or the next program if
+/// `name` is empty) from the cassette in the tape recorder
+fn load_from_tape(
+    interpreter: &mut Interpreter,
+    tape: Option<&mut Tape>,
+    name: &str,
+) -> Result<(), String> {
+    let tape = tape.ok_or("No tape inserted")?;
+    let config = interpreter.config();
+    let file = if config.tape_authentic && !config.deterministic {
+        tape.load_authentically(name, &mut io::stdout(), &mut TerminalDeck::new())?
+    } else {
+        tape.load(name, &mut io::stdout())?
+    };
+    let lines = decode_program(&file.data)?;
+    let options = interpreter.config().tokenizer_options();
+    interpreter
+        .program_mut()
+        .load_text(lines.iter().map(String::as_str), &options)
+}
 
-    println!("\nCatalog:");
-    println!("{:<30} {:>10}  {}", "Filename", "Size", "Modified");
-    println!("{}", "-".repeat(60));
+/// The tape deck of an authentic load: the tones go to the host's audio
+/// player, if there is one, and Escape is read straight from the terminal
+struct TerminalDeck {
+    player: Option<sound::HostPlayer>,
+    /// Terminal settings to put back, once keys are read as they are typed
+    saved_terminal: Option<String>,
+}
 
-    let mut count = 0;
-    let mut entries: Vec<_> = paths.collect();
-    entries.sort_by_key(|e| {
-        e.as_ref()
-            .ok()
-            .and_then(|e| e.file_name().to_str().map(|s| s.to_lowercase()))
-    });
+impl TerminalDeck {
+    fn new() -> Self {
+        Self {
+            player: sound::HostPlayer::detect(),
+            saved_terminal: unbuffer_terminal(),
+        }
+    }
+}
 
-    for path in entries {
-        let path = path.map_err(|e| format!("Failed to read entry: {}", e))?;
-        let filename = path.file_name();
-        let filename_str = filename.to_string_lossy();
-
-        if filename_str.ends_with(".bbas") {
-            let metadata = path
-                .metadata()
-                .map_err(|e| format!("Failed to read metadata: {}", e))?;
-
-            let size = metadata.len();
-            let modified = metadata
-                .modified()
-                .ok()
-                .and_then(|m| m.elapsed().ok())
-                .map(|d| {
-                    let secs = d.as_secs();
-                    if secs < 60 {
-                        format!("{}s ago", secs)
-                    } else if secs < 3600 {
-                        format!("{}m ago", secs / 60)
-                    } else if secs < 86400 {
-                        format!("{}h ago", secs / 3600)
-                    } else {
-                        format!("{}d ago", secs / 86400)
-                    }
-                })
-                .unwrap_or_else(|| "unknown".to_string());
+impl TapeDeck for TerminalDeck {
+    fn play(&mut self, bytes: &[u8], leader: f64) {
+        if let Some(player) = self.player.as_mut() {
+            if let Err(e) = player.play(&sound::tape_tones(bytes, leader)) {
+                println!("Warning: {}", e);
+                self.player = None;
+            }
+        }
+    }
 
-            println!("{:<30} {:>10}  {}", filename_str, size, modified);
-            count += 1;
+    fn stop(&mut self) {
+        if let Some(player) = self.player.as_mut() {
+            player.stop();
         }
     }
 
-    if count == 0 {
-        println!("(no .bbas files found)");
-    } else {
-        println!("\n{} file(s)", count);
+    fn escape(&mut self) -> bool {
+        if self.saved_terminal.is_none() {
+            return false;
+        }
+        // Keys typed so far, without waiting for any more
+        let mut key = [0u8];
+        while let Ok(1) = io::stdin().read(&mut key) {
+            if key[0] == 0x1B {
+                return true;
+            }
+        }
+        false
     }
+}
 
+impl Drop for TerminalDeck {
+    fn drop(&mut self) {
+        if let Some(saved) = self.saved_terminal.take() {
+            let _ = std::process::Command::new("stty").arg(saved).status();
+        }
+    }
+}
+
+/// Have the terminal hand over keys as they are typed, unechoed, with reads
+/// not waiting for any, returning the settings to restore afterwards (None
+/// if input is not a terminal or stty is not available)
+fn unbuffer_terminal() -> Option<String> {
+    if !io::stdin().is_terminal() {
+        return None;
+    }
+    let saved = std::process::Command::new("stty").arg("-g").output().ok()?;
+    let saved = String::from_utf8(saved.stdout).ok()?.trim().to_string();
+    let unbuffered = std::process::Command::new("stty")
+        .args(["-icanon", "-echo", "min", "0", "time", "0"])
+        .status()
+        .ok()?;
+    unbuffered.success().then_some(saved)
+}
+
+/// Load program from a .bbas file, numbering its lines if it has none, and
+/// return the file's path
+fn load_program(
+    program: &mut ProgramStore,
+    filename: &str,
+    options: &TokenizerOptions,
+    numbering: Numbering,
+) -> Result<String, String> {
+    // Add .bbas extension if not present
+    let path = if filename.ends_with(".bbas") {
+        filename.to_string()
+    } else {
+        format!("{}.bbas", filename)
+    };
+
+    // Read file
+    let content =
+        std::fs::read_to_string(&path).map_err(|e| format!("Failed to read file: {}", e))?;
+    let content = charset::from_unicode(&content);
+
+    let lines = number_source(content.lines(), numbering)?;
+    program.load_text(lines.iter().map(String::as_str), options)?;
+    Ok(path)
+}
+
+/// Load a program from a URL, disc or tape image or archive (e.g.
+/// `GAMES.SSD#ELITE`) through the file system
+fn load_archived_program(
+    program: &mut ProgramStore,
+    filesystem: &FileSystem,
+    spec: &str,
+    options: &TokenizerOptions,
+) -> Result<(), String> {
+    let lines = filesystem.read_program(spec)?;
+    program.load_text(lines.iter().map(String::as_str), options)?;
+    println!("Loaded from {}", spec);
     Ok(())
 }
 
@@ -717,17 +1205,73 @@ fn print_help() {
     println!("  10                       - Delete line 10");
     println!();
     println!("Immediate Commands:");
+    println!(
+        "  HELP keyword             - Show the syntax of a keyword (HELP KEYWORDS lists them)"
+    );
     println!("  LIST                     - List the program");
+    println!("  LVAR                     - List the variables, arrays, PROCs and FNs");
+    println!("  CHECK                    - Warn of lines that never run and suspicious loops");
     println!("  RUN                      - Run the stored program");
     println!("  NEW                      - Clear the program");
+    println!("  UNDO / REDO              - Take back or redo the last program edit");
     println!("  SAVE \"filename\"          - Save program to filename.bbas");
     println!("  LOAD \"filename\"          - Load program from filename.bbas");
+    println!("  LOAD \"GAMES.SSD#NAME\"    - Load a program from a disc or tape image");
     println!("  CHAIN \"filename\"         - Load and run program");
+    println!("  *SLOT [name]             - Switch to another program held in memory, or list them");
+    println!("  *MERGE \"filename\"        - Merge a program's lines into this one");
+    println!("  *DIFF \"filename\"         - Show how this program differs from a saved one");
+    println!("  *BADPROG \"filename\"      - Recover what can be read of a damaged program");
+    println!("  INSTALL \"filename\"       - Load a library of PROCs and FNs for good");
+    println!("  *INSTALL [LIB.SORT]      - Install a bundled library, or list them");
+    println!("  *FIND \"text\"             - List the lines containing some text");
+    println!("  *CHANGE \"old\" \"new\"      - Replace text throughout the program");
+    println!("  *PACK [KEEP]             - Take out REMs (unless KEEP) and spaces");
+    println!("  *SAVE name start end     - Save memory up to end (or +length) to a file");
+    println!("  *LOAD name [address]     - Load a file into memory at its load address");
+    println!("  *MEMDUMP start [end]     - Show memory in hex and ASCII");
+    println!("  *MEMSET addr byte...     - Write bytes (in hex) into memory");
+    println!("  *HISTORY [name]          - Show the recorded changes to a variable");
     println!("  *CAT                     - List all .bbas files");
+    println!("  *TAPE \"file.uef\"         - Insert a cassette and load from tape");
+    println!("  *EXEC \"file\"             - Type a file's lines as if at the keyboard");
+    println!("  *OPT 4,n                 - At start-up, 0 ignore, 1 LOAD, 2 CHAIN, 3 *EXEC !BOOT");
+    println!("  *DISC                    - Load from files again instead of tape");
+    println!("  *MOTOR 0|1               - Switch the cassette motor off or on");
+    println!(
+        "  *CONFIGURE AUTHENTIC ON  - Load from tape at real speed with its tones (Escape skips)"
+    );
+    println!("  Cursor keys, then Tab    - Copy text from the screen into the line");
+    println!("  *FX 138,0,65             - OSBYTE call (138 types a key, 15 flushes)");
+    println!("  *CLOSEWIN                - Close the window MOVE, DRAW or PLOT opened");
+    println!("  *SHADOW [0|1]            - Draw off the screen, shown at each WAIT");
+    println!(
+        "  *THEME [name]            - Show or change the colours (classic, contrast, amber...)"
+    );
+    println!("  *FONT [BEEB|SYSTEM]      - Show graphics text in the BBC font or the terminal's");
+    #[cfg(feature = "speech")]
+    println!("  *SAY \"text\"              - Speak a phrase");
+    #[cfg(feature = "tui")]
+    println!("  *INSPECT [vars] | OFF    - RUN beside a panel of variables, PROC calls and TIME");
+    println!("  *STATUS or INFO          - Show PAGE, TOP, LOMEM, HIMEM and free memory");
+    println!("  *TITLE [title]           - Show the title and author, or set the title");
+    println!("  *WAV \"filename\"          - Save SOUND output to filename.wav");
+    println!("  *CONFIGURE               - Show interpreter options");
+    println!("  *CONFIGURE option value  - Change an option (e.g. *CONFIGURE SPEED 100)");
+    println!("  *CONFIGURE SAVE          - Save options to bbcbasic.toml");
+    println!("  *STRUCTURE               - Rewrite GOTO/GOSUB as REPEAT/WHILE/PROC");
+    println!("  *COMPILE file.rs         - Translate the program to Rust source");
+    println!("  *EXPORT HTML file        - Write a highlighted listing to file.html");
+    println!("  *BREAK [line]            - Set a breakpoint, or list them");
+    println!("  *NOBREAK [line]          - Clear a breakpoint, or all of them");
+    println!("  *DEBUG                   - Run the program, stopping at breakpoints");
+    println!("  *STEP [OVER|OUT]         - Step into, over or out of a PROC or GOSUB");
+    println!("  CONT                     - Continue after a breakpoint or error");
     println!();
     println!("Immediate Mode (no line numbers):");
     println!("  A% = 42                  - Execute immediately");
     println!("  PRINT \"text\"             - Execute immediately");
+    println!("  X% * 2                   - Show a value while a program is stopped");
     println!();
     println!("Statements:");
     println!("  LET A% = 42              - Assign integer variable");
@@ -744,6 +1288,8 @@ fn print_help() {
     println!("  DEF PROC name(params)    - Define procedure");
     println!("  PROC name(args)          - Call procedure");
     println!("  ENDPROC                  - End procedure");
+    println!("  LIBRARY \"filename\"       - Load PROCs and FNs until the next RUN");
+    println!("  C$ = FNoscli$(\"CAT\", 5)  - Capture a star command's output (line 5 of it)");
     println!("  REM comment              - Comment");
     println!("  END                      - End program");
     println!();
@@ -2,8 +2,20 @@
 //!
 //! Emulates the exact memory layout of the BBC Model B with 32K RAM,
 //! including proper memory mapping and allocation.
+//!
+//! User memory runs from PAGE to HIMEM. The tokenized program sits at PAGE
+//! and ends at TOP, the variable heap grows up from LOMEM (which starts at
+//! TOP), and HIMEM is the bottom of screen memory, so it moves with MODE and
+//! is never above &8000. Anything that would push the program or heap past
+//! HIMEM fails with "No room", as on a Model B.
+//!
+//! Below PAGE, the resident integers @% and A% to Z% have four bytes each
+//! from &400, so `!&404` is A%. They are kept with the other variables,
+//! and the executor lays them over this workspace when memory is read or
+//! written through it.
 
 use crate::error::{BBCBasicError, Result};
+use std::fmt;
 
 /// BBC Model B memory constants
 pub const MEMORY_SIZE: usize = 32768; // 32K RAM
@@ -12,6 +24,7 @@ pub const HIMEM: u16 = 0x8000; // End of user memory
 pub const ZERO_PAGE_SIZE: usize = 0x100;
 pub const STACK_START: u16 = 0x0100;
 pub const STACK_SIZE: usize = 0x100;
+pub const RESIDENT_START: u16 = 0x0400; // @% to Z%, four bytes each
 
 /// Memory manager for the BBC BASIC interpreter
 #[derive(Debug, Clone)]
@@ -20,6 +33,8 @@ pub struct MemoryManager {
     ram: [u8; MEMORY_SIZE],
     /// Current top of used memory
     top: u16,
+    /// End of user memory (the start of screen memory)
+    himem: u16,
     /// Allocation tracking
     allocations: Vec<MemoryAllocation>,
 }
@@ -34,19 +49,64 @@ struct MemoryAllocation {
 
 /// Types of memory allocations
 #[derive(Debug, Clone, PartialEq)]
-enum AllocationType {
+pub enum AllocationType {
     Program,
     Variables,
     Stack,
     System,
 }
 
+/// Where the program and variables sit in memory, as shown by *STATUS
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryStatus {
+    pub page: u16,
+    /// End of the program
+    pub top: u16,
+    /// Start of the variable heap
+    pub lomem: u16,
+    /// Start of screen memory
+    pub himem: u16,
+    /// Bytes the variables take on the heap
+    pub heap: usize,
+    /// Bytes left between the heap and HIMEM
+    pub free: usize,
+    pub integers: usize,
+    pub reals: usize,
+    pub strings: usize,
+    pub arrays: usize,
+}
+
+impl fmt::Display for MemoryStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "PAGE  = &{:04X}", self.page)?;
+        writeln!(f, "TOP   = &{:04X}", self.top)?;
+        writeln!(f, "LOMEM = &{:04X}", self.lomem)?;
+        writeln!(f, "HIMEM = &{:04X}", self.himem)?;
+        writeln!(
+            f,
+            "Variables: {} bytes ({} integer, {} real, {} string, {} array)",
+            self.heap, self.integers, self.reals, self.strings, self.arrays
+        )?;
+        write!(f, "Free: {} bytes", self.free)
+    }
+}
+
 impl MemoryManager {
+    /// Memory for a machine in `mode`, with HIMEM at the start of its screen
+    /// memory
+    pub fn for_mode(mode: u8) -> Self {
+        Self {
+            himem: screen_start(mode),
+            ..Self::new()
+        }
+    }
+
     /// Create a new memory manager with initialized memory
     pub fn new() -> Self {
         let mut manager = Self {
             ram: [0; MEMORY_SIZE],
             top: PAGE,
+            himem: HIMEM,
             allocations: Vec::new(),
         };
 
@@ -88,6 +148,25 @@ impl MemoryManager {
         Ok(())
     }
 
+    /// Copy `data` into memory from `address` (*LOAD)
+    pub fn load_block(&mut self, address: u16, data: &[u8]) -> Result<()> {
+        let start = address as usize;
+        let end = start + data.len();
+        if end > MEMORY_SIZE {
+            return Err(BBCBasicError::InvalidAddress(address));
+        }
+        self.ram[start..end].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// The `length` bytes from `address` (*SAVE)
+    pub fn block(&self, address: u16, length: usize) -> Result<&[u8]> {
+        let start = address as usize;
+        self.ram
+            .get(start..start + length)
+            .ok_or(BBCBasicError::InvalidAddress(address))
+    }
+
     /// Check if a system memory write is safe
     fn is_safe_system_write(&self, address: u16) -> bool {
         // For now, allow most system writes
@@ -102,7 +181,19 @@ impl MemoryManager {
 
     /// Get the HIMEM value (end of user memory)
     pub fn get_himem(&self) -> u16 {
-        HIMEM
+        self.himem
+    }
+
+    /// Move HIMEM, e.g. to the start of screen memory for a new MODE
+    ///
+    /// Fails with "No room" if the program and variables would no longer fit.
+    pub fn set_himem(&mut self, himem: u16) -> Result<()> {
+        let himem = himem.min(HIMEM);
+        if himem < self.top {
+            return Err(BBCBasicError::NoRoom);
+        }
+        self.himem = himem;
+        Ok(())
     }
 
     /// Get the current TOP value (top of used memory)
@@ -110,6 +201,36 @@ impl MemoryManager {
         self.top
     }
 
+    /// End of the program and start of the variable heap (LOMEM)
+    pub fn get_lomem(&self) -> u16 {
+        PAGE + self.allocated(AllocationType::Program) as u16
+    }
+
+    /// Bytes allocated for a type of data
+    pub fn allocated(&self, allocation_type: AllocationType) -> usize {
+        self.allocations
+            .iter()
+            .filter(|alloc| alloc.allocation_type == allocation_type)
+            .map(|alloc| alloc.size)
+            .sum()
+    }
+
+    /// Lay out user memory for a program of `program` bytes and a variable
+    /// heap of `heap` bytes above it
+    ///
+    /// Fails with "No room", leaving the layout unchanged, if they do not fit
+    /// below HIMEM.
+    pub fn set_layout(&mut self, program: usize, heap: usize) -> Result<()> {
+        if program + heap > (self.himem - PAGE) as usize {
+            return Err(BBCBasicError::NoRoom);
+        }
+        self.allocations.clear();
+        self.top = PAGE;
+        self.allocate_program_space(program)?;
+        self.allocate_variable_space(heap)?;
+        Ok(())
+    }
+
     /// Allocate memory for program storage
     pub fn allocate_program_space(&mut self, size: usize) -> Result<u16> {
         self.allocate_memory(size, AllocationType::Program)
@@ -122,7 +243,7 @@ impl MemoryManager {
 
     /// Generic memory allocation
     fn allocate_memory(&mut self, size: usize, allocation_type: AllocationType) -> Result<u16> {
-        let available_space = (HIMEM - self.top) as usize;
+        let available_space = (self.himem - self.top) as usize;
         if size > available_space {
             return Err(BBCBasicError::NoRoom);
         }
@@ -162,13 +283,13 @@ impl MemoryManager {
 
     /// Get available memory
     pub fn get_available_memory(&self) -> usize {
-        (HIMEM - self.top) as usize
+        (self.himem - self.top) as usize
     }
 
     /// Clear all user memory
     pub fn clear_user_memory(&mut self) {
         // Clear user memory area
-        for addr in PAGE as usize..HIMEM as usize {
+        for addr in PAGE as usize..self.himem as usize {
             self.ram[addr] = 0;
         }
 
@@ -192,6 +313,51 @@ impl MemoryManager {
     }
 }
 
+/// Bytes shown on each line of a memory dump, which fits a MODE 7 screen
+pub const DUMP_WIDTH: usize = 8;
+
+/// Format `bytes`, which start at `address`, as a hex and ASCII dump (*MEMDUMP)
+///
+/// Each line is the address, then up to [`DUMP_WIDTH`] bytes in hex and the
+/// same bytes as characters, with `.` for anything unprintable.
+pub fn hex_dump(address: u16, bytes: &[u8]) -> Vec<String> {
+    bytes
+        .chunks(DUMP_WIDTH)
+        .enumerate()
+        .map(|(row, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{:02X}", b)).collect();
+            let text: String = chunk
+                .iter()
+                .map(|&b| {
+                    if (0x20..0x7F).contains(&b) {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            format!(
+                "{:04X} {:<width$} {}",
+                address as usize + row * DUMP_WIDTH,
+                hex.join(" "),
+                text,
+                width = DUMP_WIDTH * 3 - 1
+            )
+        })
+        .collect()
+}
+
+/// Start of screen memory in a MODE, which is where HIMEM goes
+pub fn screen_start(mode: u8) -> u16 {
+    match mode {
+        0..=2 => 0x3000,
+        3 => 0x4000,
+        4 | 5 => 0x5800,
+        6 => 0x6000,
+        _ => 0x7C00,
+    }
+}
+
 impl Default for MemoryManager {
     fn default() -> Self {
         Self::new()
@@ -263,4 +429,58 @@ mod tests {
         let result = mem.allocate_program_space(available + 1);
         assert!(matches!(result, Err(BBCBasicError::NoRoom)));
     }
+
+    #[test]
+    fn test_blocks() {
+        let mut mem = MemoryManager::new();
+        mem.load_block(0x3000, &[1, 2, 3]).unwrap();
+        assert_eq!(mem.peek(0x3001).unwrap(), 2);
+        assert_eq!(mem.block(0x3000, 3).unwrap(), &[1, 2, 3]);
+
+        // Blocks must end by &7FFF
+        mem.load_block(0x7FFE, &[4, 5]).unwrap();
+        assert_eq!(mem.block(0x7FFE, 2).unwrap(), &[4, 5]);
+        assert!(mem.load_block(0x7FFE, &[4, 5, 6]).is_err());
+        assert!(mem.block(0x7FFF, 2).is_err());
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let lines = hex_dump(0x3000, b"HELLO\0\x7F WORLD");
+        assert_eq!(
+            lines,
+            vec![
+                "3000 48 45 4C 4C 4F 00 7F 20 HELLO.. ",
+                "3008 57 4F 52 4C 44          WORLD",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_layout_below_himem() {
+        let mut mem = MemoryManager::new();
+        mem.set_himem(screen_start(7)).unwrap();
+        assert_eq!(mem.get_himem(), 0x7C00);
+
+        mem.set_layout(0x100, 0x40).unwrap();
+        assert_eq!(mem.get_lomem(), PAGE + 0x100);
+        assert_eq!(mem.get_top(), PAGE + 0x140);
+        assert_eq!(mem.get_available_memory(), 0x7C00 - 0x1A40);
+
+        // MODE 0 would put screen memory over the program
+        mem.set_layout(0x1000, 0x800).unwrap();
+        assert!(matches!(
+            mem.set_himem(screen_start(0)),
+            Err(BBCBasicError::NoRoom)
+        ));
+        assert_eq!(mem.get_himem(), 0x7C00);
+
+        let full = (0x7C00 - PAGE) as usize;
+        assert!(matches!(
+            mem.set_layout(full, 1),
+            Err(BBCBasicError::NoRoom)
+        ));
+        assert_eq!(mem.get_lomem(), PAGE + 0x1000);
+        mem.set_layout(full, 0).unwrap();
+    }
 }
@@ -0,0 +1,252 @@
+//! Line numbering for source text written without line numbers
+//!
+//! Programs written in an ordinary editor can leave the line numbers out.
+//! When such a file is loaded, each statement line is numbered in turn from
+//! a configurable start and step, and labels are resolved to the numbers of
+//! their lines:
+//!
+//! ```text
+//! X% = 3
+//! @loop: PRINT X%
+//! X% = X% - 1
+//! IF X% > 0 THEN GOTO @loop
+//! ```
+//!
+//! A label is written `@name:` at the start of a line, on its own (naming the
+//! next statement line) or before a statement, and `@name` anywhere else in
+//! a statement stands for its line number. Header comments and blank lines
+//! are left as they are, so they are kept as the program's metadata. `@%`,
+//! the PRINT format variable, is not a label.
+
+use crate::program::{comment_text, join_continued_lines};
+use std::collections::HashMap;
+
+/// Highest line number a program can use
+const MAX_LINE_NUMBER: u32 = 32767;
+
+/// How unnumbered source lines are numbered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Numbering {
+    /// Number of the first line
+    pub start: u16,
+    /// Gap between line numbers
+    pub step: u16,
+}
+
+impl Default for Numbering {
+    fn default() -> Self {
+        Self {
+            start: 10,
+            step: 10,
+        }
+    }
+}
+
+/// Number the lines of source text that has no line numbers
+///
+/// Source whose first statement line starts with a line number is returned
+/// as it is. Fails on a label that is defined twice, used but not defined,
+/// or defined at the end of the file, or if the numbers would run past 32767.
+pub fn number_source<'a>(
+    lines: impl IntoIterator<Item = &'a str>,
+    numbering: Numbering,
+) -> Result<Vec<String>, String> {
+    let lines: Vec<&str> = lines.into_iter().collect();
+    let joined = join_continued_lines(lines.iter().copied());
+    let is_statement = |line: &str| {
+        let line = line.trim();
+        !line.is_empty() && comment_text(line).is_none()
+    };
+    let Some(first) = joined.iter().position(|(_, line)| is_statement(line)) else {
+        return Ok(lines.into_iter().map(str::to_string).collect());
+    };
+    if joined[first]
+        .1
+        .trim_start()
+        .starts_with(|c: char| c.is_ascii_digit())
+    {
+        return Ok(lines.into_iter().map(str::to_string).collect());
+    }
+
+    // First pass: number the statement lines and find the labels
+    let mut labels = HashMap::new();
+    let mut pending = Vec::new();
+    let mut statements = Vec::new();
+    let mut next = u32::from(numbering.start);
+    for (position, (index, line)) in joined.iter().enumerate() {
+        let line = line.trim();
+        if position < first || line.is_empty() {
+            statements.push((*index, None, line));
+            continue;
+        }
+        let (label, statement) = split_label(line);
+        if let Some(label) = label {
+            if labels.contains_key(label) || pending.contains(&label) {
+                return Err(format!(
+                    "Label @{} defined twice at line {}",
+                    label,
+                    index + 1
+                ));
+            }
+            pending.push(label);
+        }
+        if statement.is_empty() {
+            continue;
+        }
+        if next > MAX_LINE_NUMBER {
+            return Err(format!("Too many lines to number at line {}", index + 1));
+        }
+        for label in pending.drain(..) {
+            labels.insert(label, next);
+        }
+        statements.push((*index, Some(next), statement));
+        next += u32::from(numbering.step.max(1));
+    }
+    if let Some(label) = pending.first() {
+        return Err(format!("Label @{} has no line after it", label));
+    }
+
+    // Second pass: put the line numbers in place of the labels
+    let mut numbered = Vec::with_capacity(statements.len());
+    for (index, number, line) in statements {
+        match number {
+            Some(number) => {
+                let statement = resolve_labels(line, &labels)
+                    .map_err(|label| format!("No such label @{} at line {}", label, index + 1))?;
+                numbered.push(format!("{} {}", number, statement));
+            }
+            None => numbered.push(line.to_string()),
+        }
+    }
+    Ok(numbered)
+}
+
+/// The label a line starts with, if any, and the statement after it
+fn split_label(line: &str) -> (Option<&str>, &str) {
+    let Some(rest) = line.strip_prefix('@') else {
+        return (None, line);
+    };
+    let length = label_length(rest);
+    match rest[length..].strip_prefix(':') {
+        Some(statement) if length > 0 => (Some(&rest[..length]), statement.trim()),
+        _ => (None, line),
+    }
+}
+
+/// Length of the label name at the start of `text` (0 if there is none)
+fn label_length(text: &str) -> usize {
+    if !text.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return 0;
+    }
+    text.find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .unwrap_or(text.len())
+}
+
+/// A statement with each `@label` outside strings and comments replaced by
+/// its line number, or the first label that is not defined
+fn resolve_labels(statement: &str, labels: &HashMap<&str, u32>) -> Result<String, String> {
+    let mut resolved = String::with_capacity(statement.len());
+    let mut in_string = false;
+    let mut rest = statement;
+    while let Some(c) = rest.chars().next() {
+        if !in_string && starts_comment(&resolved, rest) {
+            resolved.push_str(rest);
+            break;
+        }
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '"' => in_string = !in_string,
+            '@' if !in_string && label_length(rest) > 0 => {
+                let label = &rest[..label_length(rest)];
+                let number = labels.get(label).ok_or_else(|| label.to_string())?;
+                resolved.push_str(&number.to_string());
+                rest = &rest[label.len()..];
+                continue;
+            }
+            _ => {}
+        }
+        resolved.push(c);
+    }
+    Ok(resolved)
+}
+
+/// Whether a REM starts at `rest`, with `before` the text before it
+fn starts_comment(before: &str, rest: &str) -> bool {
+    let is_word = before.ends_with(|c: char| c.is_ascii_alphanumeric());
+    !is_word
+        && rest
+            .get(..3)
+            .is_some_and(|rem| rem.eq_ignore_ascii_case("REM"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_number_source() {
+        let source = [
+            "REM Countdown",
+            "",
+            "X% = 3",
+            "@loop:",
+            "PRINT X%; \" @loop\"",
+            "X% = X% - 1",
+            "IF X% > 0 THEN GOTO @loop",
+            "",
+            "GOSUB @done : REM see @nowhere",
+            "END",
+            "@done: PRINT @%",
+            "RETURN",
+        ];
+        let numbering = Numbering {
+            start: 100,
+            step: 5,
+        };
+        assert_eq!(
+            number_source(source, numbering).unwrap(),
+            [
+                "REM Countdown",
+                "",
+                "100 X% = 3",
+                "105 PRINT X%; \" @loop\"",
+                "110 X% = X% - 1",
+                "115 IF X% > 0 THEN GOTO 105",
+                "",
+                "120 GOSUB 130 : REM see @nowhere",
+                "125 END",
+                "130 PRINT @%",
+                "135 RETURN",
+            ]
+        );
+
+        // Numbered source is left alone
+        let source = ["REM Title", "10 PRINT @x"];
+        assert_eq!(number_source(source, Numbering::default()).unwrap(), source);
+    }
+
+    #[test]
+    fn test_label_errors() {
+        let numbering = Numbering::default();
+        assert_eq!(
+            number_source(["PRINT 1", "GOTO @missing"], numbering),
+            Err("No such label @missing at line 2".to_string())
+        );
+        assert_eq!(
+            number_source(["@a: PRINT 1", "@a: PRINT 2"], numbering),
+            Err("Label @a defined twice at line 2".to_string())
+        );
+        assert_eq!(
+            number_source(["PRINT 1", "@end:"], numbering),
+            Err("Label @end has no line after it".to_string())
+        );
+        let numbering = Numbering {
+            start: 32767,
+            step: 1,
+        };
+        assert_eq!(
+            number_source(["PRINT 1", "PRINT 2"], numbering),
+            Err("Too many lines to number at line 2".to_string())
+        );
+    }
+}
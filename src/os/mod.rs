@@ -1,17 +1,428 @@
 //! Operating system interface for BBC BASIC
 //!
-//! Handles OS calls and ROM functionality.
+//! Handles OS calls and ROM functionality: OSBYTE calls (*FX), the
+//! keyboard buffer that GET, INKEY and INPUT read from, the keys held down
+//! that a negative INKEY scans for, the mouse pointer that MOUSE reads, and
+//! the line editor with its COPY key screen editing.
+
+use crate::charset;
+use crate::error::{BBCBasicError, Result};
+use crate::keymap::KeyMap;
+use crate::screen::TextScreen;
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+
+/// Size of the MOS keyboard buffer
+pub const KEYBOARD_BUFFER_SIZE: usize = 31;
+
+/// Key codes of the editing keys
+pub const KEY_COPY: u8 = 135;
+pub const KEY_LEFT: u8 = 136;
+pub const KEY_RIGHT: u8 = 137;
+pub const KEY_DOWN: u8 = 138;
+pub const KEY_UP: u8 = 139;
+
+/// The MOS keyboard buffer
+///
+/// Keys typed at the keyboard, or inserted with OSBYTE 138, wait here until
+/// a program reads them. When the buffer is full, further keys are lost.
+#[derive(Debug, Clone, Default)]
+pub struct KeyboardBuffer {
+    keys: VecDeque<u8>,
+}
+
+impl KeyboardBuffer {
+    /// Create an empty keyboard buffer
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert a key, returning false if the buffer is full
+    pub fn insert(&mut self, key: u8) -> bool {
+        if self.keys.len() >= KEYBOARD_BUFFER_SIZE {
+            return false;
+        }
+        self.keys.push_back(key);
+        true
+    }
+
+    /// Insert the characters of a string (newlines become RETURN), returning
+    /// how many fitted in the buffer
+    pub fn insert_str(&mut self, text: &str) -> usize {
+        text.chars()
+            .map(|c| if c == '\n' { 13 } else { charset::byte(c) })
+            .take_while(|&key| self.insert(key))
+            .count()
+    }
+
+    /// Remove and return the next key
+    pub fn read(&mut self) -> Option<u8> {
+        self.keys.pop_front()
+    }
+
+    /// Discard everything in the buffer (*FX 15)
+    pub fn flush(&mut self) {
+        self.keys.clear();
+    }
+
+    /// Number of keys waiting
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Whether no keys are waiting
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+/// Translate a line typed at a terminal into BBC key codes: the cursor keys
+/// become the editing keys, and Tab or End acts as COPY
+pub fn keys_from_terminal(line: &str) -> Vec<u8> {
+    let mut keys = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' if matches!(chars.peek(), Some('[') | Some('O')) => {
+                chars.next();
+                match chars.next() {
+                    Some('A') => keys.push(KEY_UP),
+                    Some('B') => keys.push(KEY_DOWN),
+                    Some('C') => keys.push(KEY_RIGHT),
+                    Some('D') => keys.push(KEY_LEFT),
+                    Some('F') => keys.push(KEY_COPY),
+                    _ => {}
+                }
+            }
+            '\t' => keys.push(KEY_COPY),
+            '\x08' => keys.push(127),
+            '\r' | '\n' => {}
+            _ => keys.push(charset::byte(c)),
+        }
+    }
+    keys
+}
+
+/// The MOS line editor used by INPUT and the command line
+///
+/// Typed characters are echoed to the screen. The cursor keys detach a copy
+/// cursor from the write cursor and move it around the screen; COPY then
+/// copies the character under the copy cursor into the line and moves the
+/// copy cursor on, so earlier output can be re-entered.
+#[derive(Debug, Clone, Default)]
+pub struct LineEditor {
+    line: String,
+    /// Copy cursor (column, row) and the screen's scroll count when it was
+    /// last placed
+    copy_cursor: Option<(usize, usize, usize)>,
+}
+
+impl LineEditor {
+    /// Start editing an empty line
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The line typed so far
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    /// Copy cursor position (column, row), if the cursor keys have been used
+    pub fn copy_cursor(&self, screen: &TextScreen) -> Option<(usize, usize)> {
+        let (x, y, scrolled) = self.copy_cursor?;
+        // The copy cursor moves up with the text as the screen scrolls
+        Some((x, y.saturating_sub(screen.scrolled() - scrolled)))
+    }
+
+    /// Handle a key, returning the finished line when RETURN is pressed
+    pub fn key(&mut self, key: u8, screen: &mut TextScreen) -> Option<String> {
+        match key {
+            13 => {
+                screen.write_str("\n");
+                self.copy_cursor = None;
+                return Some(std::mem::take(&mut self.line));
+            }
+            127 => {
+                if self.line.pop().is_some() {
+                    screen.write_char(127);
+                }
+            }
+            KEY_COPY => {
+                if let Some((x, y)) = self.copy_cursor(screen) {
+                    let c = screen.char_at(x, y);
+                    let (x, y) = if x + 1 < screen.width() {
+                        (x + 1, y)
+                    } else {
+                        (0, (y + 1).min(screen.height() - 1))
+                    };
+                    self.type_char(c, screen);
+                    self.copy_cursor = Some((x, y, screen.scrolled()));
+                }
+            }
+            KEY_LEFT..=KEY_UP => {
+                let (x, y) = self.copy_cursor(screen).unwrap_or_else(|| screen.cursor());
+                let (x, y) = match key {
+                    KEY_LEFT => (x.saturating_sub(1), y),
+                    KEY_RIGHT => ((x + 1).min(screen.width() - 1), y),
+                    KEY_DOWN => (x, (y + 1).min(screen.height() - 1)),
+                    _ => (x, y.saturating_sub(1)),
+                };
+                self.copy_cursor = Some((x, y, screen.scrolled()));
+            }
+            0..=31 => {}
+            _ => self.type_char(key, screen),
+        }
+        None
+    }
+
+    fn type_char(&mut self, c: u8, screen: &mut TextScreen) {
+        self.line.push(char::from(c));
+        screen.write_char(c);
+    }
+}
+
+/// Where typing comes from once the keyboard buffer is empty
+///
+/// Front ends that run the interpreter on another thread, or have no
+/// terminal, supply their own.
+pub trait LineInput: Send + fmt::Debug {
+    /// Wait for a line to be typed, returning it without its line ending,
+    /// or None if nothing more will ever be typed
+    fn read_line(&mut self) -> Option<String>;
+
+    /// Whether someone is typing the lines, and so wants prompting for
+    /// them (piped input and other machine-made lines are not)
+    fn is_interactive(&self) -> bool {
+        true
+    }
+}
+
+/// Lines typed at the terminal, on standard input
+#[derive(Debug, Default)]
+pub struct StdinInput;
+
+impl LineInput for StdinInput {
+    fn read_line(&mut self) -> Option<String> {
+        use std::io::{self, Write};
+        io::stdout().flush().ok();
+        let mut line = String::new();
+        match io::stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim_end_matches(['\r', '\n']).to_string()),
+        }
+    }
+
+    fn is_interactive(&self) -> bool {
+        use std::io::IsTerminal;
+        std::io::stdin().is_terminal()
+    }
+}
+
+/// No keyboard: nothing is ever typed
+#[derive(Debug, Default)]
+pub struct NoInput;
+
+impl LineInput for NoInput {
+    fn read_line(&mut self) -> Option<String> {
+        None
+    }
+
+    fn is_interactive(&self) -> bool {
+        false
+    }
+}
+
+/// Mouse buttons as MOUSE gives them, added together
+pub const MOUSE_LEFT: u8 = 4;
+pub const MOUSE_MIDDLE: u8 = 2;
+pub const MOUSE_RIGHT: u8 = 1;
+
+/// Width and height of the screen in graphics units, which the pointer's
+/// position is mapped into
+const POINTER_AREA: (f64, f64) = (1280.0, 1024.0);
+
+/// The mouse pointer, as a front end last reported it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Pointer {
+    /// Position in graphics units from the bottom-left corner of the screen,
+    /// before the graphics origin is taken into account
+    pub x: i32,
+    pub y: i32,
+    /// Buttons held down: [`MOUSE_LEFT`], [`MOUSE_MIDDLE`] and
+    /// [`MOUSE_RIGHT`] added together
+    pub buttons: u8,
+    /// Whether the pointer is shown (MOUSE ON) or hidden (MOUSE OFF)
+    pub visible: bool,
+}
 
 /// Operating system interface
 #[derive(Debug)]
 pub struct OSInterface {
-    // Implementation will be added in later tasks
+    keyboard: KeyboardBuffer,
+    /// Read when a program wants keys and the buffer is empty
+    line_input: Box<dyn LineInput>,
+    /// Which BBC key each scancode stands for
+    keymap: KeyMap,
+    /// Scancodes of the keys held down
+    held: HashSet<String>,
+    pointer: Pointer,
 }
 
 impl OSInterface {
-    /// Create a new OS interface
+    /// Create a new OS interface, reading standard input once the keyboard
+    /// buffer is empty (in test builds, nothing more is typed)
     pub fn new() -> Self {
-        Self {}
+        #[cfg(not(test))]
+        let line_input = Box::new(StdinInput);
+        #[cfg(test)]
+        let line_input = Box::new(NoInput);
+        Self {
+            keyboard: KeyboardBuffer::new(),
+            line_input,
+            keymap: KeyMap::new(),
+            held: HashSet::new(),
+            pointer: Pointer::default(),
+        }
+    }
+
+    /// Read typing from `line_input` once the keyboard buffer is empty
+    pub fn set_line_input(&mut self, line_input: Box<dyn LineInput>) {
+        self.line_input = line_input;
+    }
+
+    /// Wait for a line to be typed, for when the keyboard buffer is empty
+    pub fn read_line(&mut self) -> Option<String> {
+        self.line_input.read_line()
+    }
+
+    /// Whether lines read for the program are typed by someone
+    pub fn is_interactive(&self) -> bool {
+        self.line_input.is_interactive()
+    }
+
+    /// The keyboard buffer
+    pub fn keyboard(&self) -> &KeyboardBuffer {
+        &self.keyboard
+    }
+
+    /// The keyboard buffer, for inserting or reading keys
+    pub fn keyboard_mut(&mut self) -> &mut KeyboardBuffer {
+        &mut self.keyboard
+    }
+
+    /// Map scancodes to BBC keys with `keymap`
+    pub fn set_keymap(&mut self, keymap: KeyMap) {
+        self.keymap = keymap;
+    }
+
+    /// Report a key going down (`down`) or up, by its scancode
+    pub fn key_event(&mut self, scancode: &str, down: bool) {
+        if down {
+            self.held.insert(scancode.to_string());
+        } else {
+            self.held.remove(scancode);
+        }
+    }
+
+    /// Whether the BBC key with a negative INKEY number is held down, by any
+    /// of the scancodes that stand for it
+    pub fn key_down(&self, number: i32) -> bool {
+        self.held
+            .iter()
+            .any(|scancode| self.keymap.inkey(scancode) == Some(number))
+    }
+
+    /// Report where the mouse pointer is, as fractions of the way across
+    /// and down the window from its top-left corner, and which buttons are
+    /// held down ([`MOUSE_LEFT`] and the rest added together)
+    ///
+    /// The position is mapped into the 1280 by 1024 graphics units of the
+    /// screen, with y counting up from the bottom as BBC graphics do.
+    pub fn mouse_event(&mut self, across: f64, down: f64, buttons: u8) {
+        let (width, height) = POINTER_AREA;
+        let across = across.clamp(0.0, 1.0);
+        let up = 1.0 - down.clamp(0.0, 1.0);
+        self.pointer.x = ((across * width) as i32).min(width as i32 - 1);
+        self.pointer.y = ((up * height) as i32).min(height as i32 - 1);
+        self.pointer.buttons = buttons & (MOUSE_LEFT | MOUSE_MIDDLE | MOUSE_RIGHT);
+    }
+
+    /// The mouse pointer: where it is, its buttons and whether it is shown
+    pub fn pointer(&self) -> Pointer {
+        self.pointer
+    }
+
+    /// Show (MOUSE ON) or hide (MOUSE OFF) the mouse pointer
+    pub fn set_pointer_visible(&mut self, visible: bool) {
+        self.pointer.visible = visible;
+    }
+
+    /// Make an OSBYTE call with A, X and Y, returning the new X and Y
+    ///
+    /// Supported calls:
+    /// - 15: flush all buffers (X=0) or just the input buffer (X=1)
+    /// - 21: flush buffer X (0 is the keyboard buffer)
+    /// - 128: with X=255, the number of keys in the keyboard buffer (in X)
+    /// - 129: with Y=255, whether the key with negative INKEY number X (as a
+    ///   byte) is held down: 255 in X and Y if it is, else 0
+    /// - 138: insert character Y into buffer X (0 is the keyboard buffer)
+    pub fn osbyte(&mut self, a: u8, x: u8, y: u8) -> Result<(u8, u8)> {
+        match a {
+            15 => {
+                self.keyboard.flush();
+                Ok((x, y))
+            }
+            21 => {
+                if x == 0 {
+                    self.keyboard.flush();
+                }
+                Ok((x, y))
+            }
+            128 if x == 255 => Ok((self.keyboard.len() as u8, 0)),
+            129 if y == 255 => {
+                let held = if self.key_down(i32::from(x as i8)) {
+                    255
+                } else {
+                    0
+                };
+                Ok((held, held))
+            }
+            138 => {
+                // The real MOS signals a full buffer with the carry flag;
+                // the key is simply lost here
+                if x == 0 {
+                    self.keyboard.insert(y);
+                }
+                Ok((x, y))
+            }
+            _ => Err(BBCBasicError::BadCall),
+        }
+    }
+
+    /// Run a *FX command: the arguments after *FX, as `A[,X[,Y]]`
+    pub fn fx(&mut self, arguments: &str) -> Result<()> {
+        let (a, x, y) = Self::parse_fx(arguments)?;
+        self.osbyte(a, x, y).map(|_| ())
+    }
+
+    /// The A, X and Y of a *FX command's arguments (X and Y are 0 if left
+    /// out)
+    pub fn parse_fx(arguments: &str) -> Result<(u8, u8, u8)> {
+        let values = arguments
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|part| !part.is_empty())
+            .map(|part| part.parse::<u8>().map_err(|_| BBCBasicError::BadCall))
+            .collect::<Result<Vec<u8>>>()?;
+        match values[..] {
+            [a] => Ok((a, 0, 0)),
+            [a, x] => Ok((a, x, 0)),
+            [a, x, y] => Ok((a, x, y)),
+            _ => Err(BBCBasicError::SyntaxError {
+                message: "Syntax: *FX A[,X[,Y]]".to_string(),
+                line: None,
+            }),
+        }
     }
 }
 
@@ -20,3 +431,115 @@ impl Default for OSInterface {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keyboard_buffer() {
+        let mut keyboard = KeyboardBuffer::new();
+        assert_eq!(keyboard.insert_str("YES\n"), 4);
+        assert_eq!(keyboard.read(), Some(b'Y'));
+        assert_eq!(keyboard.len(), 3);
+        keyboard.flush();
+        assert!(keyboard.is_empty());
+
+        // Keys beyond the buffer size are lost
+        assert_eq!(keyboard.insert_str(&"A".repeat(40)), KEYBOARD_BUFFER_SIZE);
+        assert!(!keyboard.insert(b'B'));
+    }
+
+    #[test]
+    fn test_copy_key_editing() {
+        let mut screen = TextScreen::new(20, 5);
+        screen.write_str("10 PRINT \"HELLO\"\n");
+        let mut editor = LineEditor::new();
+
+        // Up to the previous line, then copy "10 PRINT" and type a new ending
+        let mut keys = vec![KEY_UP];
+        keys.extend([KEY_COPY; 9]);
+        keys.extend(b"\"BYE\"");
+        let mut line = None;
+        for key in keys.into_iter().chain([13]) {
+            line = editor.key(key, &mut screen);
+        }
+        assert_eq!(line.as_deref(), Some("10 PRINT \"BYE\""));
+        assert_eq!(screen.row(1), "10 PRINT \"BYE\"");
+        assert_eq!(editor.copy_cursor(&screen), None);
+
+        // DELETE rubs out typed characters; COPY does nothing without a copy cursor
+        for key in [b'A', b'B', 127, KEY_COPY] {
+            editor.key(key, &mut screen);
+        }
+        assert_eq!(editor.line(), "A");
+    }
+
+    #[test]
+    fn test_keys_from_terminal() {
+        assert_eq!(
+            keys_from_terminal("\x1b[AX\t\x1bOF\x1b[D\x08\n"),
+            vec![KEY_UP, b'X', KEY_COPY, KEY_COPY, KEY_LEFT, 127]
+        );
+    }
+
+    #[test]
+    fn test_key_scanning() {
+        let mut os = OSInterface::new();
+        assert!(!os.key_down(-99));
+        os.key_event("Space", true);
+        assert!(os.key_down(-99));
+        assert_eq!(os.osbyte(129, (-99i8) as u8, 255).unwrap(), (255, 255));
+        assert_eq!(os.osbyte(129, (-17i8) as u8, 255).unwrap(), (0, 0));
+
+        // SHIFT is down while either shift key is
+        os.key_event("ShiftLeft", true);
+        os.key_event("ShiftRight", true);
+        os.key_event("ShiftLeft", false);
+        assert!(os.key_down(-1));
+        os.key_event("ShiftRight", false);
+        assert!(!os.key_down(-1));
+
+        let mut keymap = KeyMap::new();
+        keymap.map("ArrowLeft", "Z").unwrap();
+        os.set_keymap(keymap);
+        os.key_event("ArrowLeft", true);
+        assert!(os.key_down(-98));
+        assert!(!os.key_down(-26));
+    }
+
+    #[test]
+    fn test_mouse_pointer() {
+        let mut os = OSInterface::new();
+        os.mouse_event(0.5, 0.25, MOUSE_LEFT | MOUSE_RIGHT);
+        let pointer = os.pointer();
+        assert_eq!((pointer.x, pointer.y, pointer.buttons), (640, 768, 5));
+
+        // The corners stay on the screen, and unknown buttons are ignored
+        os.mouse_event(1.0, 1.0, 0xF8);
+        let pointer = os.pointer();
+        assert_eq!((pointer.x, pointer.y, pointer.buttons), (1279, 0, 0));
+        os.mouse_event(-1.0, 0.0, 0);
+        assert_eq!((os.pointer().x, os.pointer().y), (0, 1023));
+
+        assert!(!os.pointer().visible);
+        os.set_pointer_visible(true);
+        assert!(os.pointer().visible);
+    }
+
+    #[test]
+    fn test_fx_calls() {
+        let mut os = OSInterface::new();
+        os.fx("138,0,65").unwrap();
+        os.fx("138 0 66").unwrap();
+        assert_eq!(os.osbyte(128, 255, 0).unwrap(), (2, 0));
+        assert_eq!(os.keyboard_mut().read(), Some(b'A'));
+
+        os.fx("15,1").unwrap();
+        assert!(os.keyboard().is_empty());
+
+        assert!(os.fx("").is_err());
+        assert!(os.fx("300").is_err());
+        assert!(matches!(os.osbyte(200, 0, 0), Err(BBCBasicError::BadCall)));
+    }
+}
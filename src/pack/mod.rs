@@ -0,0 +1,218 @@
+//! Program packing for BBC BASIC (*PACK)
+//!
+//! Shrinks a program the classic way: REM statements and the comments on
+//! the end of lines are taken out, along with header comments and blank
+//! lines, and the program is listed and saved without the spaces BBC BASIC
+//! can do without. A REM line that something jumps to (GOTO, GOSUB, ON or
+//! ON ERROR) is kept as a bare REM, so every jump still lands.
+//!
+//! Lines are not joined into multi-statement lines, so ERL, the debugger and
+//! error reports still point at the lines as they were written.
+
+use crate::error::{BBCBasicError, Result};
+use crate::parser::{parse_statement_with_dialect, Dialect};
+use crate::program::ProgramStore;
+use crate::tokenizer::{Token, TokenizedLine};
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// REM keyword token
+const REM: Token = Token::Keyword(0xF4);
+
+/// What *PACK does besides taking out spaces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PackOptions {
+    /// Take out REMs and header comments
+    pub strip_comments: bool,
+}
+
+impl Default for PackOptions {
+    fn default() -> Self {
+        Self {
+            strip_comments: true,
+        }
+    }
+}
+
+/// Size of a program's lines
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Statistics {
+    /// Number of lines
+    pub lines: usize,
+    /// Number of tokens, not counting line numbers
+    pub tokens: usize,
+    /// Bytes the program takes in memory (see [`ProgramStore::size`])
+    pub bytes: usize,
+    /// Number of REM statements, including comments on the end of lines
+    pub comments: usize,
+}
+
+impl Statistics {
+    /// Count the lines, tokens and comments of a program
+    pub fn of(program: &ProgramStore) -> Self {
+        let lines = program.list();
+        Self {
+            lines: lines.len(),
+            tokens: lines.iter().map(|(_, line)| line.tokens.len()).sum(),
+            bytes: program.size(),
+            comments: lines
+                .iter()
+                .filter(|(_, line)| line.tokens.contains(&REM))
+                .count(),
+        }
+    }
+}
+
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} lines, {} tokens, {} bytes, {} comments",
+            self.lines, self.tokens, self.bytes, self.comments
+        )
+    }
+}
+
+/// The sizes of a program before and after packing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PackReport {
+    pub before: Statistics,
+    pub after: Statistics,
+}
+
+impl fmt::Display for PackReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Before: {}", self.before)?;
+        write!(f, "After:  {}", self.after)
+    }
+}
+
+/// Pack a program, returning the packed program and how much it shrank
+///
+/// Fails if any line does not parse, since its jumps could not be found.
+pub fn pack_program(
+    program: &ProgramStore,
+    dialect: Dialect,
+    options: PackOptions,
+) -> Result<(ProgramStore, PackReport)> {
+    let before = Statistics::of(program);
+    let mut targets = BTreeSet::new();
+    for (number, line) in program.list() {
+        let statement = parse_statement_with_dialect(line, dialect).map_err(|e| match e {
+            BBCBasicError::SyntaxError { message, .. } => BBCBasicError::SyntaxError {
+                message,
+                line: Some(number),
+            },
+            e => e,
+        })?;
+        targets.extend(statement.jump_targets());
+    }
+
+    let mut packed = program.clone();
+    // UNDO takes back the whole pack
+    packed.begin_edit();
+    if options.strip_comments {
+        for (number, line) in program.list() {
+            match strip_comment(line) {
+                Some(tokens) if tokens.is_empty() && !targets.contains(&number) => {
+                    packed.delete_line(number)
+                }
+                // A jump target keeps its REM, without the text
+                Some(tokens) if tokens.is_empty() => {
+                    packed.store_line(TokenizedLine::new(Some(number), vec![REM]))
+                }
+                Some(tokens) => packed.store_line(TokenizedLine::new(Some(number), tokens)),
+                None => {}
+            }
+        }
+    }
+    packed.set_packed(!options.strip_comments);
+    packed.end_edit();
+
+    let after = Statistics::of(&packed);
+    Ok((packed, PackReport { before, after }))
+}
+
+/// A line's tokens with its REM and the rest of the line taken out, or None
+/// if it has no REM
+fn strip_comment(line: &TokenizedLine) -> Option<Vec<Token>> {
+    let rem = line.tokens.iter().position(|token| *token == REM)?;
+    let mut tokens = line.tokens[..rem].to_vec();
+    // A colon before the REM goes with it
+    if tokens.last() == Some(&Token::Separator(':')) {
+        tokens.pop();
+    }
+    Some(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::TokenizerOptions;
+
+    fn program(source: &[&str]) -> ProgramStore {
+        let mut program = ProgramStore::new();
+        program
+            .load_text(source.iter().copied(), &TokenizerOptions::default())
+            .unwrap();
+        program
+    }
+
+    #[test]
+    fn test_pack_strips_comments_and_spaces() {
+        let source = [
+            "REM Title: Counter",
+            "10 REM set up",
+            "20 X% = 1 : REM start",
+            "",
+            "30 REM loop",
+            "40 PRINT X% AND 7, \"A B\"",
+            "50 X% = X% - 1",
+            "60 IF X% > 0 THEN GOTO 30",
+            "70 GOSUB 90 ' done",
+            "80 END",
+            "90 RETURN",
+        ];
+        let options = TokenizerOptions::default();
+        let original = program(&source);
+        let (packed, report) =
+            pack_program(&original, Dialect::default(), PackOptions::default()).unwrap();
+        assert_eq!(
+            packed.to_text(&options).unwrap(),
+            [
+                "20 X%=1",
+                "30 REM",
+                "40 PRINTX%AND7,\"A B\"",
+                "50 X%=X%- 1",
+                "60 IFX%>0 THENGOTO30",
+                "70 GOSUB90",
+                "80 END",
+                "90 RETURN",
+            ]
+        );
+        assert_eq!(report.before, Statistics::of(&original));
+        assert_eq!(report.after.lines, 8);
+        assert_eq!(report.after.comments, 1);
+        assert!(report.after.bytes < report.before.bytes);
+
+        // Every packed line tokenizes back as it was
+        for text in packed.to_text(&options).unwrap() {
+            let line = crate::tokenizer::tokenize(&text).unwrap();
+            assert_eq!(packed.get_line(line.line_number.unwrap()), Some(&line));
+        }
+    }
+
+    #[test]
+    fn test_pack_keeping_comments() {
+        let original = program(&["REM Title: Demo", "10 REM hello", "20 PRINT 1"]);
+        let options = PackOptions {
+            strip_comments: false,
+        };
+        let (packed, report) = pack_program(&original, Dialect::default(), options).unwrap();
+        assert_eq!(
+            packed.to_text(&TokenizerOptions::default()).unwrap(),
+            ["REM Title: Demo", "10 REM hello", "20 PRINT1"]
+        );
+        assert_eq!(report.before, report.after);
+    }
+}
@@ -0,0 +1,149 @@
+//! Colours and the palette for BBC BASIC
+//!
+//! Keeps what COLOUR, GCOL and VDU 19 set: the text and graphics colours,
+//! the GCOL actions, and the physical colour each logical colour shows. The
+//! executor updates them as the VDU codes go by, and programs read them
+//! back with OSWORD &0B and the VDU variables (OSBYTE &A0, or `FNvdu`).
+
+/// Colours a MODE has: two, four or sixteen (MODE 7, which has no
+/// palette, counts as two)
+pub fn colours_in_mode(mode: u8) -> u8 {
+    match mode {
+        1 | 5 => 4,
+        2 => 16,
+        _ => 2,
+    }
+}
+
+/// The physical colour each logical colour shows when a MODE is chosen
+fn default_palette(colours: u8) -> [u8; 16] {
+    let mut palette: [u8; 16] = std::array::from_fn(|i| i as u8);
+    match colours {
+        2 => palette[1] = 7,
+        4 => palette[..4].copy_from_slice(&[0, 1, 3, 7]),
+        _ => {}
+    }
+    palette
+}
+
+/// The colours in use and the palette
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Colours {
+    mode: u8,
+    /// Text foreground colour (COLOUR n)
+    pub text_foreground: u8,
+    /// Text background colour (COLOUR 128+n)
+    pub text_background: u8,
+    /// Graphics foreground colour (GCOL a, n)
+    pub graphics_foreground: u8,
+    /// Graphics background colour (GCOL a, 128+n)
+    pub graphics_background: u8,
+    /// GCOL action for the graphics foreground
+    pub foreground_action: u8,
+    /// GCOL action for the graphics background
+    pub background_action: u8,
+    palette: [u8; 16],
+}
+
+impl Colours {
+    /// The colours a MODE starts with: white on black, and the default
+    /// palette
+    pub fn for_mode(mode: u8) -> Self {
+        let colours = colours_in_mode(mode);
+        let white = (colours - 1).min(7);
+        Self {
+            mode,
+            text_foreground: white,
+            text_background: 0,
+            graphics_foreground: white,
+            graphics_background: 0,
+            foreground_action: 0,
+            background_action: 0,
+            palette: default_palette(colours),
+        }
+    }
+
+    /// Number of logical colours
+    pub fn count(&self) -> u8 {
+        colours_in_mode(self.mode)
+    }
+
+    /// A colour number as a logical colour, wrapped as the OS does
+    fn logical(&self, colour: u8) -> u8 {
+        colour & (self.count() - 1)
+    }
+
+    /// Set a text colour (VDU 17): the foreground below 128, else the
+    /// background
+    pub fn set_text(&mut self, colour: u8) {
+        if colour < 128 {
+            self.text_foreground = self.logical(colour);
+        } else {
+            self.text_background = self.logical(colour - 128);
+        }
+    }
+
+    /// Set a graphics colour and its action (VDU 18): the foreground below
+    /// 128, else the background
+    pub fn set_graphics(&mut self, action: u8, colour: u8) {
+        if colour < 128 {
+            self.graphics_foreground = self.logical(colour);
+            self.foreground_action = action;
+        } else {
+            self.graphics_background = self.logical(colour - 128);
+            self.background_action = action;
+        }
+    }
+
+    /// Show a logical colour as a physical one (VDU 19)
+    pub fn set_palette(&mut self, logical: u8, physical: u8) {
+        let logical = self.logical(logical);
+        self.palette[usize::from(logical)] = physical & 15;
+    }
+
+    /// The physical colour a logical colour shows (OSWORD &0B)
+    pub fn physical(&self, logical: u8) -> u8 {
+        self.palette[usize::from(self.logical(logical))]
+    }
+
+    /// Go back to the MODE's colours and palette (VDU 20)
+    pub fn reset(&mut self) {
+        *self = Self::for_mode(self.mode);
+    }
+}
+
+impl Default for Colours {
+    fn default() -> Self {
+        Self::for_mode(7)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colours_and_palette() {
+        let mut colours = Colours::for_mode(1);
+        assert_eq!(colours.count(), 4);
+        assert_eq!(colours.text_foreground, 3);
+        assert_eq!(colours.physical(2), 3);
+
+        colours.set_text(2);
+        colours.set_text(129);
+        colours.set_graphics(3, 6);
+        colours.set_palette(1, 4);
+        assert_eq!((colours.text_foreground, colours.text_background), (2, 1));
+        assert_eq!(
+            (colours.graphics_foreground, colours.foreground_action),
+            (2, 3)
+        );
+        assert_eq!(colours.physical(1), 4);
+        assert_eq!(colours.physical(5), 4);
+
+        colours.reset();
+        assert_eq!(colours, Colours::for_mode(1));
+        assert_eq!(Colours::for_mode(2).text_foreground, 7);
+        assert_eq!(Colours::for_mode(0).physical(1), 7);
+    }
+}
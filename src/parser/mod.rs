@@ -1,11 +1,37 @@
 //! Parser for BBC BASIC statements and expressions
 //!
 //! Analyzes tokenized BBC BASIC statements and creates abstract syntax trees
-//! for execution.
+//! for execution, and [`unparse`] turns a statement back into source.
 
 use crate::error::BBCBasicError;
 use crate::error::Result;
-use crate::tokenizer::{create_reverse_keyword_maps, Token, TokenizedLine};
+use crate::tokenizer::{
+    create_keyword_maps, create_reverse_keyword_maps, detokenize, Token, TokenizedLine,
+};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+
+/// BBC BASIC dialect accepted by the parser
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Dialect {
+    /// BBC BASIC II, as in the Model B ROM: no WHILE, CASE, block IF or
+    /// other extended keywords
+    #[serde(rename = "basic2")]
+    BasicII,
+    /// BBC BASIC V, with the structured extensions
+    #[default]
+    #[serde(rename = "basic5")]
+    BasicV,
+}
+
+impl std::fmt::Display for Dialect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Dialect::BasicII => write!(f, "BASIC II"),
+            Dialect::BasicV => write!(f, "BASIC V"),
+        }
+    }
+}
 
 /// Binary operators in BBC BASIC
 #[derive(Debug, Clone, PartialEq)]
@@ -48,6 +74,34 @@ pub enum UnaryOperator {
     Not,
 }
 
+/// Memory indirection operators, which read and write memory at an address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indirection {
+    Byte,   // ?
+    Word,   // ! (four bytes, least significant first)
+    String, // $ (ended by a carriage return)
+}
+
+impl Indirection {
+    /// The operator's character
+    pub fn symbol(self) -> char {
+        match self {
+            Indirection::Byte => '?',
+            Indirection::Word => '!',
+            Indirection::String => '$',
+        }
+    }
+
+    fn from_token(token: Option<&Token>) -> Option<Self> {
+        match token {
+            Some(Token::Operator('?')) => Some(Indirection::Byte),
+            Some(Token::Operator('!')) => Some(Indirection::Word),
+            Some(Token::Operator('$')) => Some(Indirection::String),
+            _ => None,
+        }
+    }
+}
+
 /// BBC BASIC expressions
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expression {
@@ -59,7 +113,8 @@ pub enum Expression {
     String(String),
     /// Variable reference
     Variable(String),
-    /// Array access with indices
+    /// Array access with indices (none for a whole array, as passed to a
+    /// PROC or FN)
     ArrayAccess {
         name: String,
         indices: Vec<Expression>,
@@ -77,16 +132,31 @@ pub enum Expression {
         op: UnaryOperator,
         operand: Box<Expression>,
     },
+    /// Memory at an address (`?A%`, `!A%`, `$A%`); `A%!4` and `A%?I%`
+    /// are the same as `!(A% + 4)` and `?(A% + I%)`
+    Indirect {
+        op: Indirection,
+        address: Box<Expression>,
+    },
 }
 
 /// Print item types for PRINT statements
 #[derive(Debug, Clone, PartialEq)]
 pub enum PrintItem {
     Expression(Expression),
+    Hex(Expression), // ~expr
     Tab(Expression), // TAB(n)
     Spc(Expression), // SPC(n)
     Semicolon,       // ;
     Comma,           // ,
+    Apostrophe,      // ' (new line)
+}
+
+/// Values sent by a VDU statement
+#[derive(Debug, Clone, PartialEq)]
+pub enum VduItem {
+    Byte(Expression), // followed by , or nothing
+    Word(Expression), // followed by ; (low byte, then high byte)
 }
 
 /// BBC BASIC statements
@@ -97,6 +167,12 @@ pub enum Statement {
         target: String,
         expression: Expression,
     },
+    /// Assignment to memory (?A% = 5, A%!4 = X%, $buffer% = "TEXT")
+    IndirectAssignment {
+        op: Indirection,
+        address: Expression,
+        expression: Expression,
+    },
     /// Array element assignment
     ArrayAssignment {
         name: String,
@@ -105,23 +181,30 @@ pub enum Statement {
     },
     /// PRINT statement
     Print { items: Vec<PrintItem> },
-    /// INPUT statement
-    Input { variables: Vec<String> },
+    /// INPUT statement (each a variable or an array element)
+    Input { variables: Vec<Expression> },
     /// FOR loop
     For {
-        variable: String,
+        /// A variable or array element
+        variable: Expression,
         start: Expression,
         end: Expression,
         step: Option<Expression>,
     },
     /// NEXT statement
-    Next { variables: Vec<String> },
+    Next { variables: Vec<Expression> },
     /// IF statement
     If {
         condition: Expression,
         then_part: Vec<Statement>,
         else_part: Option<Vec<Statement>>,
     },
+    /// Block IF (THEN ends the line) - the branches follow on later lines
+    IfBlock { condition: Expression },
+    /// ELSE on its own line, separating the branches of a block IF
+    Else,
+    /// ENDIF statement - ends a block IF
+    EndIf,
     /// GOTO statement
     Goto { line_number: u16 },
     /// GOSUB statement
@@ -132,8 +215,13 @@ pub enum Statement {
     Dim {
         arrays: Vec<(String, Vec<Expression>)>,
     },
+    /// DIM name size - reserve size + 1 bytes of memory and set the
+    /// variable to their address
+    DimSpace { name: String, size: Expression },
     /// REM statement (comment)
     Rem { comment: String },
+    /// Star command (*FX 19), the rest of its line passed to the OS
+    StarCommand { command: String },
     /// END statement
     End,
     /// STOP statement
@@ -144,22 +232,37 @@ pub enum Statement {
     ProcCall { name: String, args: Vec<Expression> },
     /// DEF PROC - define a procedure
     DefProc { name: String, params: Vec<String> },
-    /// DEF FN - define a function (single-line with return expression)
+    /// DEF FN - define a function: single-line with its result expression,
+    /// or multi-line (no expression) with a body that ends in RETURN value
     DefFn {
         name: String,
         params: Vec<String>,
-        expression: Expression,
+        expression: Option<Expression>,
     },
     /// ENDPROC - end procedure definition
     EndProc,
-    /// LOCAL statement - declares local variables in a procedure
+    /// LOCAL statement - declares local variables in a procedure (arrays
+    /// are named with a trailing "()")
     Local { variables: Vec<String> },
+    /// LOCAL DATA - restores the DATA pointer when the procedure returns
+    LocalData,
+    /// LOCAL ERROR - saves the ON ERROR handler and last error, to be put
+    /// back by RESTORE ERROR or when the procedure returns
+    LocalError,
     /// DATA statement - stores data values
     Data { values: Vec<DataValue> },
-    /// READ statement - reads data into variables
-    Read { variables: Vec<String> },
+    /// READ statement - reads data into variables or array elements
+    Read { variables: Vec<Expression> },
     /// RESTORE statement - resets data pointer (optionally to specific line)
     Restore { line_number: Option<u16> },
+    /// RESTORE to a line given by an expression
+    RestoreLine { line: Expression },
+    /// RESTORE +n - to the nth DATA line after this one
+    RestoreRelative { offset: Expression },
+    /// RESTORE DATA - back to the DATA pointer saved by LOCAL DATA
+    RestoreData,
+    /// RESTORE ERROR - back to the error handling saved by LOCAL ERROR
+    RestoreError,
     /// REPEAT statement - starts a REPEAT...UNTIL loop
     Repeat,
     /// UNTIL statement - ends a REPEAT...UNTIL loop
@@ -170,6 +273,12 @@ pub enum Statement {
     EndWhile,
     /// CLS statement - clear screen
     Cls,
+    /// CLEAR statement - forget all variables but the resident integers
+    Clear,
+    /// REPORT statement - print the last error's message
+    Report,
+    /// VDU statement - send bytes to the screen driver
+    Vdu { items: Vec<VduItem> },
     /// ON GOTO statement - computed GOTO based on expression value
     OnGoto {
         expression: Expression,
@@ -180,6 +289,11 @@ pub enum Statement {
         expression: Expression,
         targets: Vec<u16>,
     },
+    /// ON PROC statement - computed PROC call based on expression value
+    OnProc {
+        expression: Expression,
+        calls: Vec<(String, Vec<Expression>)>,
+    },
     /// ON ERROR GOTO statement - set error handler
     OnError { line_number: u16 },
     /// ON ERROR OFF statement - clear error handler
@@ -192,54 +306,143 @@ pub enum Statement {
     /// INPUT# statement - read from file
     InputFile {
         handle: Expression,
-        variables: Vec<String>,
+        variables: Vec<Expression>,
     },
     /// CLOSE# statement - close file
     CloseFile { handle: Expression },
+    /// BPUT# statement - write a byte, or a string and a line feed, to a file
+    /// (a trailing ; leaves off the line feed)
+    BputFile {
+        handle: Expression,
+        value: Expression,
+        newline: bool,
+    },
+    /// LIBRARY or INSTALL statement - load a file of PROC/FN definitions
+    /// (INSTALLed libraries stay loaded when the program is run again)
+    Library {
+        filename: Expression,
+        permanent: bool,
+    },
+    /// CHAIN statement - replace the program with another, from a program
+    /// slot or a file, and run it
+    Chain { filename: Expression },
+    /// CALL statement - call an OS routine with A%, X% and Y% in the registers
+    Call { address: Expression },
+    /// PTR#handle = position - move a file's pointer
+    PtrFile {
+        handle: Expression,
+        value: Expression,
+    },
+    /// EXT#handle = length - truncate or extend a file
+    ExtFile {
+        handle: Expression,
+        value: Expression,
+    },
     /// PLOT statement - general plotting with mode code
     Plot {
         mode: Expression,
         x: Expression,
         y: Expression,
     },
-    /// MOVE statement - move graphics cursor
-    Move { x: Expression, y: Expression },
-    /// DRAW statement - draw line to coordinates
-    Draw { x: Expression, y: Expression },
-    /// CIRCLE statement - draw a circle
+    /// MOVE statement - move graphics cursor (MOVE BY moves relative to it)
+    Move {
+        x: Expression,
+        y: Expression,
+        relative: bool,
+    },
+    /// DRAW statement - draw line to coordinates (DRAW BY is relative)
+    Draw {
+        x: Expression,
+        y: Expression,
+        relative: bool,
+    },
+    /// CIRCLE statement - draw a circle (CIRCLE FILL fills it)
     Circle {
         x: Expression,
         y: Expression,
         radius: Expression,
+        filled: bool,
     },
     /// GCOL statement - set graphics color
     Gcol { mode: Expression, color: Expression },
+    /// COLOUR statement - set the text foreground, or the background from 128
+    Colour { colour: Expression },
     /// CLG statement - clear graphics screen
     Clg,
-    /// ELLIPSE statement - draw an ellipse
+    /// MODE statement - change screen mode
+    Mode { mode: Expression },
+    /// ELLIPSE statement - draw an ellipse (ELLIPSE FILL fills it)
     Ellipse {
         x: Expression,
         y: Expression,
         major: Expression,
         minor: Expression,
+        filled: bool,
     },
-    /// RECTANGLE statement - draw a rectangle
+    /// RECTANGLE statement - draw a rectangle, or with TO copy the block to
+    /// another place (RECTANGLE FILL ... TO moves it, clearing the source)
     Rectangle {
         x1: Expression,
         y1: Expression,
         width: Expression,
         height: Expression,
         filled: bool,
+        target: Option<(Expression, Expression)>,
     },
     /// FILL statement - flood fill from coordinates
     Fill { x: Expression, y: Expression },
     /// ORIGIN statement - set graphics origin
     Origin { x: Expression, y: Expression },
+    /// SOUND statement - queue a note on a sound channel
+    Sound {
+        channel: Expression,
+        amplitude: Expression,
+        pitch: Expression,
+        duration: Expression,
+    },
+    /// ENVELOPE statement - define a pitch/amplitude envelope
+    Envelope { params: Vec<Expression> },
+    /// WAIT statement - pause for a number of centiseconds, or one frame
+    Wait { centiseconds: Option<Expression> },
+    /// MOUSE statement - read the pointer's position and buttons into
+    /// three variables or array elements
+    Mouse {
+        x: Expression,
+        y: Expression,
+        buttons: Expression,
+    },
+    /// MOUSE ON / MOUSE OFF statement - show or hide the pointer
+    MousePointer { visible: bool },
+    /// CONST statement - give a variable a value that cannot be changed
+    Const {
+        name: String,
+        expression: Expression,
+    },
+    /// ASSERT statement - stop with an error, or the message, if the
+    /// condition is FALSE
+    Assert {
+        condition: Expression,
+        message: Option<Expression>,
+    },
+    /// OUTPUT statement - send PRINT output to a file (`OUTPUT #ch`), back
+    /// to the screen (`OUTPUT #0`), or back where it went before (`OUTPUT`)
+    Output { handle: Option<Expression> },
+    /// FORWARD statement - walk the turtle, drawing while its pen is down
+    Forward { distance: Expression },
+    /// TURN statement - turn the turtle clockwise by a number of degrees
+    Turn { degrees: Expression },
+    /// PENUP statement - lift the turtle's pen
+    PenUp,
+    /// PENDOWN statement - lower the turtle's pen, in a colour if given
+    PenDown { colour: Option<Expression> },
     /// Empty statement
     Empty,
 }
 
 /// Data value types for DATA statement
+///
+/// The parser keeps every item as it was typed, in [`DataValue::String`], so
+/// READ can take it as a string or a number.
 #[derive(Debug, Clone, PartialEq)]
 pub enum DataValue {
     Integer(i32),
@@ -255,6 +458,9 @@ impl Statement {
             Statement::For { .. }
                 | Statement::Next { .. }
                 | Statement::If { .. }
+                | Statement::IfBlock { .. }
+                | Statement::Else
+                | Statement::EndIf
                 | Statement::Goto { .. }
                 | Statement::Gosub { .. }
                 | Statement::Return { .. }
@@ -265,6 +471,77 @@ impl Statement {
     pub fn is_terminating(&self) -> bool {
         matches!(self, Statement::End | Statement::Stop | Statement::Quit)
     }
+
+    /// Where running goes from a one-line IF at `index` in its line's
+    /// [`run_order`] when its condition is false: the statement after its
+    /// ELSE, or None for the next line
+    pub fn else_index(&self, index: usize) -> Option<usize> {
+        match self {
+            Statement::If {
+                then_part,
+                else_part: Some(_),
+                ..
+            } => Some(index + run_length(then_part) + 2),
+            _ => None,
+        }
+    }
+
+    /// The GOTO, GOSUB or PROC call an ON GOTO, ON GOSUB or ON PROC picks
+    /// when its expression comes to `index`, counting from 1
+    ///
+    /// Both backends follow ON this way. An index out of range, or a
+    /// statement that is not an ON, gives [`Statement::Empty`], so the next
+    /// statement runs.
+    pub fn on_choice(&self, index: i32) -> Statement {
+        let Some(index) = usize::try_from(index)
+            .ok()
+            .and_then(|index| index.checked_sub(1))
+        else {
+            return Statement::Empty;
+        };
+        let choice = match self {
+            Statement::OnGoto { targets, .. } => targets
+                .get(index)
+                .map(|&line_number| Statement::Goto { line_number }),
+            Statement::OnGosub { targets, .. } => targets
+                .get(index)
+                .map(|&line_number| Statement::Gosub { line_number }),
+            Statement::OnProc { calls, .. } => {
+                calls.get(index).map(|(name, args)| Statement::ProcCall {
+                    name: name.clone(),
+                    args: args.clone(),
+                })
+            }
+            _ => None,
+        };
+        choice.unwrap_or(Statement::Empty)
+    }
+
+    /// The lines this statement can jump to: GOTO, GOSUB, RESTORE, ON and
+    /// ON ERROR targets, including those in a one-line IF
+    pub fn jump_targets(&self) -> Vec<u16> {
+        match self {
+            Statement::Goto { line_number }
+            | Statement::Gosub { line_number }
+            | Statement::OnError { line_number } => vec![*line_number],
+            Statement::Restore {
+                line_number: Some(line_number),
+            } => vec![*line_number],
+            Statement::OnGoto { targets, .. } | Statement::OnGosub { targets, .. } => {
+                targets.clone()
+            }
+            Statement::If {
+                then_part,
+                else_part,
+                ..
+            } => then_part
+                .iter()
+                .chain(else_part.iter().flatten())
+                .flat_map(Statement::jump_targets)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
 }
 
 impl Expression {
@@ -317,6 +594,10 @@ impl Expression {
                 UnaryOperator::Plus | UnaryOperator::Minus => ExpressionType::Numeric,
                 UnaryOperator::Not => ExpressionType::Integer,
             },
+            Expression::Indirect { op, .. } => match op {
+                Indirection::Byte | Indirection::Word => ExpressionType::Integer,
+                Indirection::String => ExpressionType::String,
+            },
         }
     }
 }
@@ -331,13 +612,218 @@ pub enum ExpressionType {
     Unknown,
 }
 
-/// Parse a tokenized line into a statement
+/// Deepest a statement may nest: brackets, function arguments, unary
+/// operators, IF ... THEN, the statements of a THEN or ELSE part and
+/// operators in a row all count. More than a 255
+/// character line can hold, and little enough that neither parsing nor
+/// running what was parsed can run out of stack.
+pub const MAX_NESTING: usize = 128;
+
+thread_local! {
+    /// How deeply nested the parse under way is
+    static NESTING: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Levels of nesting taken by a parse, given back when it is dropped
+struct Nesting(usize);
+
+impl Nesting {
+    /// Go a level deeper
+    fn enter() -> Result<Self> {
+        let mut nesting = Nesting(0);
+        nesting.deeper()?;
+        Ok(nesting)
+    }
+
+    /// Go another level deeper, failing with "Too complex" past
+    /// [`MAX_NESTING`]
+    fn deeper(&mut self) -> Result<()> {
+        let depth = NESTING.get() + 1;
+        if depth > MAX_NESTING {
+            return Err(BBCBasicError::SyntaxError {
+                message: "Too complex".to_string(),
+                line: None,
+            });
+        }
+        NESTING.set(depth);
+        self.0 += 1;
+        Ok(())
+    }
+}
+
+impl Drop for Nesting {
+    fn drop(&mut self) {
+        NESTING.set(NESTING.get() - self.0);
+    }
+}
+
+/// Parse the first statement of a tokenized line, rejecting features the
+/// dialect lacks (see [`parse_statements_with_dialect`])
+pub fn parse_statement_with_dialect(line: &TokenizedLine, dialect: Dialect) -> Result<Statement> {
+    parse_statements_with_dialect(line, dialect).map(first_statement)
+}
+
+/// Parse a tokenized line into its statements, rejecting features the
+/// dialect lacks
+///
+/// BASIC II has none of the extended keywords, so it would have read WHILE or
+/// CASE as a variable name: at the start of a statement that is a "Mistake",
+/// and elsewhere the name is not a known variable.
+pub fn parse_statements_with_dialect(
+    line: &TokenizedLine,
+    dialect: Dialect,
+) -> Result<Vec<Statement>> {
+    if dialect == Dialect::BasicII {
+        let (_, extended_reverse) = create_reverse_keyword_maps();
+        for (index, token) in line.tokens.iter().enumerate() {
+            if let Token::ExtendedKeyword(prefix, byte) = token {
+                if index == 0 {
+                    return Err(BBCBasicError::Mistake);
+                }
+                let keyword = extended_reverse
+                    .get(&(*prefix, *byte))
+                    .cloned()
+                    .unwrap_or_default();
+                return Err(BBCBasicError::NoSuchVariable(keyword));
+            }
+        }
+
+        // Without block IF, THEN at the end of a line does nothing, and ELSE
+        // skips the rest of its line
+        let mut statements = parse_statements(line)?;
+        if let Some(index) = statements.iter().position(|s| *s == Statement::Else) {
+            statements.truncate(index);
+        }
+        if statements.is_empty() {
+            statements.push(Statement::Empty);
+        }
+        return Ok(statements
+            .into_iter()
+            .map(|statement| match statement {
+                Statement::IfBlock { condition } => Statement::If {
+                    condition,
+                    then_part: Vec::new(),
+                    else_part: None,
+                },
+                statement => statement,
+            })
+            .collect());
+    }
+    parse_statements(line)
+}
+
+/// Parse the first statement of a tokenized line
 pub fn parse_statement(line: &TokenizedLine) -> Result<Statement> {
+    parse_statements(line).map(first_statement)
+}
+
+/// Parse a tokenized line into its statements, which colons separate
+///
+/// A line with nothing on it has one statement, [`Statement::Empty`]. REM
+/// takes the rest of its line, and so does an IF with statements after its
+/// THEN: the colons that follow separate the statements of its THEN and
+/// ELSE parts.
+pub fn parse_statements(line: &TokenizedLine) -> Result<Vec<Statement>> {
+    let _nesting = Nesting::enter()?;
+    let mut statements = Vec::new();
+    let mut rest = &line.tokens[..];
+    while !rest.is_empty() {
+        let end = match rest[0] {
+            Token::Keyword(0xF4) => rest.len(),
+            Token::Keyword(0xE7) if is_one_line_if(rest) => rest.len(),
+            // The ' that stands for REM ends a statement as a colon does
+            _ => rest
+                .iter()
+                .position(|token| matches!(token, Token::Separator(':') | Token::Keyword(0xF4)))
+                .unwrap_or(rest.len()),
+        };
+        if end > 0 {
+            let statement = TokenizedLine::new(line.line_number, rest[..end].to_vec());
+            statements.push(parse_single_statement(&statement)?);
+        }
+        let colon = usize::from(rest.get(end) == Some(&Token::Separator(':')));
+        rest = &rest[end + colon..];
+    }
+    if statements.is_empty() {
+        statements.push(Statement::Empty);
+    }
+    Ok(statements)
+}
+
+/// Whether the IF at the start of `tokens` has statements after its THEN,
+/// rather than starting a block IF
+fn is_one_line_if(tokens: &[Token]) -> bool {
+    let Some(then) = tokens
+        .iter()
+        .position(|token| *token == Token::Keyword(0x8C))
+    else {
+        return false;
+    };
+    // A colon before the THEN ends the IF first
+    !tokens[..then].contains(&Token::Separator(':'))
+        && tokens
+            .get(then + 1)
+            .is_some_and(|token| *token != Token::Separator(':'))
+}
+
+fn first_statement(statements: Vec<Statement>) -> Statement {
+    statements.into_iter().next().unwrap_or(Statement::Empty)
+}
+
+/// A line's statements in the order they run
+///
+/// Each one-line IF is followed by the statements of its THEN part and, if
+/// it has one, by [`Statement::Else`] and the statements of its ELSE part.
+/// Running them in turn, an IF whose condition is false goes on from its
+/// [`Statement::else_index`], and meeting an ELSE ends the line, as it does
+/// in BBC BASIC at the end of a THEN part. (A block IF's ELSE is always the
+/// first statement of its line.)
+pub fn run_order(statements: Vec<Statement>) -> Vec<Statement> {
+    let mut order = Vec::with_capacity(statements.len());
+    for statement in statements {
+        let branches = match &statement {
+            Statement::If {
+                then_part,
+                else_part,
+                ..
+            } => Some((then_part.clone(), else_part.clone())),
+            _ => None,
+        };
+        order.push(statement);
+        if let Some((then_part, else_part)) = branches {
+            order.extend(run_order(then_part));
+            if let Some(else_part) = else_part {
+                order.push(Statement::Else);
+                order.extend(run_order(else_part));
+            }
+        }
+    }
+    order
+}
+
+/// Number of statements in the run order of `statements`
+fn run_length(statements: &[Statement]) -> usize {
+    statements
+        .iter()
+        .map(|statement| match statement {
+            Statement::If {
+                then_part,
+                else_part,
+                ..
+            } => 1 + run_length(then_part) + else_part.as_deref().map_or(0, |e| 1 + run_length(e)),
+            _ => 1,
+        })
+        .sum()
+}
+
+/// Parse the tokens of one statement
+fn parse_single_statement(line: &TokenizedLine) -> Result<Statement> {
     let tokens = &line.tokens;
 
     if tokens.is_empty() {
         return Ok(Statement::Empty);
     }
+    let _nesting = Nesting::enter()?;
 
     // Check first token to determine statement type
     match &tokens[0] {
@@ -366,6 +852,9 @@ pub fn parse_statement(line: &TokenizedLine) -> Result<Statement> {
         // Variable assignment (without LET keyword)
         Token::Identifier(_) => parse_assignment(tokens, line.line_number),
 
+        // Assignment to memory (?A% = 5, !A% = 0, $A% = "TEXT")
+        Token::Operator('?' | '!' | '$') => parse_assignment(tokens, line.line_number),
+
         // FOR loop
         Token::Keyword(0xE3) => parse_for_statement(&tokens[1..], line.line_number),
 
@@ -409,26 +898,39 @@ pub fn parse_statement(line: &TokenizedLine) -> Result<Statement> {
         // IF statement
         Token::Keyword(0xE7) => parse_if_statement(&tokens[1..], line.line_number),
 
+        // ELSE of a block IF (must be on a line of its own)
+        Token::Keyword(0x8B) if tokens.len() == 1 => Ok(Statement::Else),
+
         // END statement
-        Token::Keyword(0xE0) => Ok(Statement::End),
+        Token::Keyword(0xE0) => parse_bare_statement(tokens, Statement::End, line.line_number),
 
         // STOP statement
-        Token::Keyword(0xFA) => Ok(Statement::Stop),
+        Token::Keyword(0xFA) => parse_bare_statement(tokens, Statement::Stop, line.line_number),
 
         // QUIT statement
-        Token::Keyword(0x98) => Ok(Statement::Quit),
+        Token::Keyword(0x98) => parse_bare_statement(tokens, Statement::Quit, line.line_number),
 
         // REM statement (comment)
         Token::Keyword(0xF4) => {
-            // Everything after REM is a comment
-            let comment = tokens[1..]
-                .iter()
-                .map(|t| format!("{:?}", t))
-                .collect::<Vec<_>>()
-                .join(" ");
+            // Everything after REM is a comment, kept as typed
+            let comment = match &tokens[1..] {
+                [Token::Text(text)] => text.trim_start().to_string(),
+                rest => detokenize(&TokenizedLine::new(None, rest.to_vec()))?,
+            };
             Ok(Statement::Rem { comment })
         }
 
+        // Star command
+        Token::Operator('*') => match &tokens[1..] {
+            [Token::Text(command)] => Ok(Statement::StarCommand {
+                command: command.clone(),
+            }),
+            _ => Err(BBCBasicError::SyntaxError {
+                message: "* must start a statement".to_string(),
+                line: line.line_number,
+            }),
+        },
+
         // DATA statement
         Token::Keyword(0xDC) => parse_data_statement(&tokens[1..], line.line_number),
 
@@ -439,19 +941,31 @@ pub fn parse_statement(line: &TokenizedLine) -> Result<Statement> {
         Token::Keyword(0xF7) => parse_restore_statement(&tokens[1..], line.line_number),
 
         // REPEAT statement
-        Token::Keyword(0xF5) => Ok(Statement::Repeat),
+        Token::Keyword(0xF5) => parse_bare_statement(tokens, Statement::Repeat, line.line_number),
 
         // UNTIL statement
         Token::Keyword(0xFD) => parse_until_statement(&tokens[1..], line.line_number),
 
         // CLS statement
-        Token::Keyword(0xDB) => Ok(Statement::Cls),
+        Token::Keyword(0xDB) => parse_bare_statement(tokens, Statement::Cls, line.line_number),
+
+        // CLEAR statement
+        Token::Keyword(0xD8) => parse_bare_statement(tokens, Statement::Clear, line.line_number),
+
+        // REPORT statement
+        Token::Keyword(0xF6) => parse_bare_statement(tokens, Statement::Report, line.line_number),
 
         // DEF statement (DEF PROC or DEF FN)
         Token::Keyword(0xDD) => parse_def_statement(&tokens[1..], line.line_number),
 
         // ENDPROC statement
-        Token::Keyword(0xE1) => Ok(Statement::EndProc),
+        Token::Keyword(0xE1) => parse_bare_statement(tokens, Statement::EndProc, line.line_number),
+
+        // CHAIN statement
+        Token::Keyword(0xD7) => parse_chain_statement(&tokens[1..], line.line_number),
+
+        // CALL statement
+        Token::Keyword(0xD6) => parse_call_statement(&tokens[1..], line.line_number),
 
         // LOCAL statement
         Token::Keyword(0xEA) => parse_local_statement(&tokens[1..], line.line_number),
@@ -472,6 +986,28 @@ pub fn parse_statement(line: &TokenizedLine) -> Result<Statement> {
             }
         }
 
+        // BPUT# statement (file I/O)
+        Token::Keyword(0xD5) => {
+            if tokens.len() > 1 && matches!(tokens[1], Token::Operator('#')) {
+                parse_bput_file_statement(&tokens[2..], line.line_number)
+            } else {
+                Err(BBCBasicError::SyntaxError {
+                    message: "BPUT requires # (use BPUT#)".to_string(),
+                    line: line.line_number,
+                })
+            }
+        }
+
+        // PTR# and EXT# assignments (file I/O)
+        Token::Keyword(0xCF) | Token::Keyword(0xA2)
+            if tokens.len() > 1 && matches!(tokens[1], Token::Operator('#')) =>
+        {
+            parse_file_attribute_statement(&tokens[0], &tokens[2..], line.line_number)
+        }
+
+        // VDU statement
+        Token::Keyword(0xEF) => parse_vdu_statement(&tokens[1..], line.line_number),
+
         // Graphics statements
         // PLOT statement
         Token::Keyword(0xF0) => parse_plot_statement(&tokens[1..], line.line_number),
@@ -485,15 +1021,40 @@ pub fn parse_statement(line: &TokenizedLine) -> Result<Statement> {
         // GCOL statement
         Token::Keyword(0xE6) => parse_gcol_statement(&tokens[1..], line.line_number),
 
+        // COLOUR statement
+        Token::Keyword(0xFB) => parse_colour_statement(&tokens[1..], line.line_number),
+
         // CLG statement
-        Token::Keyword(0xDA) => Ok(Statement::Clg),
+        Token::Keyword(0xDA) => parse_bare_statement(tokens, Statement::Clg, line.line_number),
+
+        // MODE statement
+        Token::Keyword(0xEB) => {
+            if tokens.len() < 2 {
+                return Err(BBCBasicError::SyntaxError {
+                    message: "MODE requires a mode number".to_string(),
+                    line: line.line_number,
+                });
+            }
+            Ok(Statement::Mode {
+                mode: parse_expression(&tokens[1..])?,
+            })
+        }
+
+        // Sound statements
+        // SOUND statement
+        Token::Keyword(0xD4) => parse_sound_statement(&tokens[1..], line.line_number),
+
+        // ENVELOPE statement
+        Token::Keyword(0xE2) => parse_envelope_statement(&tokens[1..], line.line_number),
 
         // Extended statements (0xC8 prefix)
         Token::ExtendedKeyword(0xC8, extended_token) => match extended_token {
             // WHILE statement
             0x95 => parse_while_statement(&tokens[1..], line.line_number),
             // ENDWHILE statement
-            0xA4 => Ok(Statement::EndWhile),
+            0xA4 => parse_bare_statement(tokens, Statement::EndWhile, line.line_number),
+            // ENDIF statement
+            0xA5 => parse_bare_statement(tokens, Statement::EndIf, line.line_number),
             // CIRCLE statement
             0x8F => parse_circle_statement(&tokens[1..], line.line_number),
             // FILL statement
@@ -504,12 +1065,44 @@ pub fn parse_statement(line: &TokenizedLine) -> Result<Statement> {
             0x93 => parse_rectangle_statement(&tokens[1..], line.line_number),
             // ELLIPSE statement
             0x9D => parse_ellipse_statement(&tokens[1..], line.line_number),
-            _ => Err(BBCBasicError::SyntaxError {
-                message: format!("Unknown extended statement: {:?}", tokens[0]),
-                line: line.line_number,
+            // WAIT statement
+            0x96 => Ok(Statement::Wait {
+                centiseconds: (tokens.len() > 1)
+                    .then(|| parse_expression(&tokens[1..]))
+                    .transpose()?,
+            }),
+            // MOUSE statement
+            0x97 => parse_mouse_statement(&tokens[1..], line.line_number),
+            // INSTALL and LIBRARY statements
+            0x9A | 0x9B => {
+                parse_library_statement(&tokens[1..], *extended_token == 0x9A, line.line_number)
+            }
+            // CONST statement
+            0xA6 => parse_const_statement(&tokens[1..], line.line_number),
+            // ASSERT statement
+            0xA7 => parse_assert_statement(&tokens[1..], line.line_number),
+            // OUTPUT statement
+            0xA8 => parse_output_statement(&tokens[1..], line.line_number),
+            // Turtle statements
+            0xA9 => Ok(Statement::Forward {
+                distance: parse_expression(&tokens[1..])?,
+            }),
+            0xAA => Ok(Statement::Turn {
+                degrees: parse_expression(&tokens[1..])?,
+            }),
+            0xAB if tokens.len() == 1 => Ok(Statement::PenUp),
+            0xAC => Ok(Statement::PenDown {
+                colour: (tokens.len() > 1)
+                    .then(|| parse_expression(&tokens[1..]))
+                    .transpose()?,
             }),
+            // A keyword that cannot start a statement, such as the TO that
+            // TOTAL=6 tokenizes to, is a "Mistake" as on the BBC Micro
+            _ => Err(BBCBasicError::Mistake),
         },
 
+        Token::Keyword(_) => Err(BBCBasicError::Mistake),
+
         _ => Err(BBCBasicError::SyntaxError {
             message: format!("Unknown statement: {:?}", tokens[0]),
             line: line.line_number,
@@ -521,9 +1114,15 @@ pub fn parse_statement(line: &TokenizedLine) -> Result<Statement> {
 fn parse_print_statement(tokens: &[Token]) -> Result<Statement> {
     let mut items = Vec::new();
     let mut pos = 0;
+    // Set by ~, which prints the next expression in hex
+    let mut hex = false;
 
     while pos < tokens.len() {
         match &tokens[pos] {
+            Token::Operator('~') => {
+                hex = true;
+                pos += 1;
+            }
             Token::Separator(';') => {
                 items.push(PrintItem::Semicolon);
                 pos += 1;
@@ -532,6 +1131,10 @@ fn parse_print_statement(tokens: &[Token]) -> Result<Statement> {
                 items.push(PrintItem::Comma);
                 pos += 1;
             }
+            Token::Separator('\'') => {
+                items.push(PrintItem::Apostrophe);
+                pos += 1;
+            }
             // Handle TAB(expr)
             Token::Keyword(0x8A) => {
                 pos += 1; // skip TAB keyword
@@ -615,7 +1218,7 @@ fn parse_print_statement(tokens: &[Token]) -> Result<Statement> {
                             paren_depth -= 1;
                             end_pos += 1;
                         }
-                        Token::Separator(';') | Token::Separator(',') if paren_depth == 0 => {
+                        Token::Separator(';' | ',' | '\'') if paren_depth == 0 => {
                             break;
                         }
                         _ => {
@@ -626,7 +1229,11 @@ fn parse_print_statement(tokens: &[Token]) -> Result<Statement> {
 
                 if end_pos > start_pos {
                     let expr = parse_expression(&tokens[start_pos..end_pos])?;
-                    items.push(PrintItem::Expression(expr));
+                    if std::mem::take(&mut hex) {
+                        items.push(PrintItem::Hex(expr));
+                    } else {
+                        items.push(PrintItem::Expression(expr));
+                    }
                     pos = end_pos;
                 } else {
                     break;
@@ -640,6 +1247,11 @@ fn parse_print_statement(tokens: &[Token]) -> Result<Statement> {
 
 /// Parse assignment statement (A% = 42 or LET A% = 42, or array assignment like arr(i) = 5)
 fn parse_assignment(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
+    if Indirection::from_token(tokens.first()).is_some()
+        || matches!(tokens.get(1), Some(Token::Operator('?' | '!')))
+    {
+        return parse_indirect_assignment(tokens, line_number);
+    }
     if tokens.len() < 3 {
         return Err(BBCBasicError::SyntaxError {
             message: "Invalid assignment".to_string(),
@@ -718,6 +1330,34 @@ fn parse_assignment(tokens: &[Token], line_number: Option<u16>) -> Result<Statem
     Ok(Statement::Assignment { target, expression })
 }
 
+/// Parse an assignment to memory (?A% = 5, A%!4 = X%, $(buffer% + 8) = "TEXT")
+fn parse_indirect_assignment(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
+    let syntax_error = |message: &str| BBCBasicError::SyntaxError {
+        message: message.to_string(),
+        line: line_number,
+    };
+    let mut depth = 0;
+    let equals = tokens
+        .iter()
+        .position(|token| {
+            match token {
+                Token::Separator('(') => depth += 1,
+                Token::Separator(')') => depth -= 1,
+                _ => {}
+            }
+            depth == 0 && matches!(token, Token::Operator('='))
+        })
+        .ok_or_else(|| syntax_error("Expected '='"))?;
+    match parse_expression(&tokens[..equals])? {
+        Expression::Indirect { op, address } => Ok(Statement::IndirectAssignment {
+            op,
+            address: *address,
+            expression: parse_expression(&tokens[equals + 1..])?,
+        }),
+        _ => Err(syntax_error("Invalid assignment")),
+    }
+}
+
 /// Parse FOR statement
 fn parse_for_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
     // FOR variable = start TO end [STEP step]
@@ -728,17 +1368,15 @@ fn parse_for_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Sta
         });
     }
 
-    let variable = match &tokens[0] {
-        Token::Identifier(name) => name.clone(),
-        _ => {
-            return Err(BBCBasicError::SyntaxError {
-                message: "Expected variable name after FOR".to_string(),
-                line: line_number,
-            })
-        }
+    // The variable may be an array element: FOR A(2) = 1 TO 10
+    let Some((variable, equals)) = parse_target(tokens, 0, line_number)? else {
+        return Err(BBCBasicError::SyntaxError {
+            message: "Expected variable name after FOR".to_string(),
+            line: line_number,
+        });
     };
 
-    if !matches!(tokens[1], Token::Operator('=')) {
+    if !matches!(tokens.get(equals), Some(Token::Operator('='))) {
         return Err(BBCBasicError::SyntaxError {
             message: "Expected '=' in FOR statement".to_string(),
             line: line_number,
@@ -748,18 +1386,22 @@ fn parse_for_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Sta
     // Find TO keyword
     let to_pos = tokens
         .iter()
+        .skip(equals)
         .position(|t| matches!(t, Token::Keyword(0xB8)))
+        .map(|pos| pos + equals)
         .ok_or(BBCBasicError::SyntaxError {
             message: "Expected TO in FOR statement".to_string(),
             line: line_number,
         })?;
 
-    let start = parse_expression(&tokens[2..to_pos])?;
+    let start = parse_expression(&tokens[equals + 1..to_pos])?;
 
     // Check for STEP keyword
     let step_pos = tokens
         .iter()
-        .position(|t| matches!(t, Token::Keyword(0x88)));
+        .skip(to_pos)
+        .position(|t| matches!(t, Token::Keyword(0x88)))
+        .map(|pos| pos + to_pos);
 
     let (end, step) = if let Some(step_pos) = step_pos {
         let end = parse_expression(&tokens[to_pos + 1..step_pos])?;
@@ -789,19 +1431,17 @@ fn parse_next_statement(tokens: &[Token]) -> Result<Statement> {
 
     // Parse variable list (comma-separated)
     let mut pos = 0;
-    while pos < tokens.len() {
-        match &tokens[pos] {
-            Token::Identifier(name) => {
-                variables.push(name.clone());
-                pos += 1;
+    while let Some((target, next)) = parse_target(tokens, pos, None)? {
+        variables.push(target);
+        pos = next;
 
-                if pos < tokens.len() && matches!(tokens[pos], Token::Separator(',')) {
-                    pos += 1; // skip comma
-                }
-            }
-            _ => break,
+        if pos < tokens.len() && matches!(tokens[pos], Token::Separator(',')) {
+            pos += 1; // skip comma
+        } else {
+            break;
         }
     }
+    expect_end(tokens, pos, None)?;
 
     Ok(Statement::Next { variables })
 }
@@ -824,6 +1464,7 @@ fn parse_goto_statement(tokens: &[Token], line_number: Option<u16>) -> Result<St
             })
         }
     };
+    expect_end(tokens, 1, line_number)?;
 
     Ok(Statement::Goto {
         line_number: line_num,
@@ -848,15 +1489,17 @@ fn parse_gosub_statement(tokens: &[Token], line_number: Option<u16>) -> Result<S
             })
         }
     };
+    expect_end(tokens, 1, line_number)?;
 
     Ok(Statement::Gosub {
         line_number: line_num,
     })
 }
 
-/// Parse ON statement (ON GOTO or ON GOSUB)
+/// Parse ON statement (ON GOTO, ON GOSUB or ON PROC)
 fn parse_on_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
     // Syntax: ON <expression> GOTO|GOSUB <line1>, <line2>, ...
+    // or: ON <expression> PROC<name1>, PROC<name2>(<args>), ...
     // or: ON ERROR GOTO <line>
     // or: ON ERROR OFF
 
@@ -880,6 +1523,7 @@ fn parse_on_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Stat
         match tokens[1] {
             Token::Keyword(0x87) => {
                 // OFF keyword (0x87)
+                expect_end(tokens, 2, line_number)?;
                 return Ok(Statement::OnErrorOff);
             }
             Token::Keyword(0xE5) => {
@@ -892,6 +1536,7 @@ fn parse_on_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Stat
                 }
                 match tokens[2] {
                     Token::Integer(n) => {
+                        expect_end(tokens, 3, line_number)?;
                         return Ok(Statement::OnError {
                             line_number: n as u16,
                         });
@@ -913,9 +1558,10 @@ fn parse_on_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Stat
         }
     }
 
-    // Find GOTO or GOSUB keyword
+    // Find GOTO, GOSUB or PROC keyword
     let mut goto_pos = None;
     let mut gosub_pos = None;
+    let mut proc_pos = None;
 
     for (i, token) in tokens.iter().enumerate() {
         match token {
@@ -929,10 +1575,20 @@ fn parse_on_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Stat
                 gosub_pos = Some(i);
                 break;
             }
+            Token::Keyword(0xF2) => {
+                // PROC
+                proc_pos = Some(i);
+                break;
+            }
             _ => {}
         }
     }
 
+    if let Some(pos) = proc_pos {
+        let expression = parse_expression(&tokens[..pos])?;
+        return parse_on_proc(expression, &tokens[pos..], line_number);
+    }
+
     let (keyword_pos, is_goto) = if let Some(pos) = goto_pos {
         (pos, true)
     } else if let Some(pos) = gosub_pos {
@@ -965,9 +1621,11 @@ fn parse_on_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Stat
                 targets.push(*n as u16);
                 pos += 1;
 
-                // Skip comma if present
+                // Line numbers are separated by commas
                 if pos < line_tokens.len() && matches!(line_tokens[pos], Token::Separator(',')) {
                     pos += 1;
+                } else {
+                    break;
                 }
             }
             _ => {
@@ -979,6 +1637,8 @@ fn parse_on_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Stat
         }
     }
 
+    expect_end(line_tokens, pos, line_number)?;
+
     if targets.is_empty() {
         return Err(BBCBasicError::SyntaxError {
             message: "Expected at least one line number in ON GOTO/GOSUB".to_string(),
@@ -999,41 +1659,140 @@ fn parse_on_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Stat
     }
 }
 
-/// Parse INPUT statement
-fn parse_input_statement(tokens: &[Token]) -> Result<Statement> {
-    let mut variables = Vec::new();
-    let mut pos = 0;
+/// Parse the calls of ON ... PROC, from the first PROC keyword on
+fn parse_on_proc(
+    expression: Expression,
+    tokens: &[Token],
+    line_number: Option<u16>,
+) -> Result<Statement> {
+    let starts: Vec<usize> = tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| matches!(token, Token::Keyword(0xF2)))
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut calls = Vec::new();
+    for (n, &start) in starts.iter().enumerate() {
+        let mut call = &tokens[start + 1..starts.get(n + 1).copied().unwrap_or(tokens.len())];
+        // Each call but the last is followed by a comma
+        if n + 1 < starts.len() {
+            call = match call.split_last() {
+                Some((Token::Separator(','), call)) => call,
+                _ => {
+                    return Err(BBCBasicError::SyntaxError {
+                        message: "Expected , between PROCs in ON PROC".to_string(),
+                        line: line_number,
+                    })
+                }
+            };
+        }
+        let Statement::ProcCall { name, args } = parse_proc_call(call, line_number)? else {
+            unreachable!("PROC parsed as another statement");
+        };
+        calls.push((name, args));
+    }
 
-    while pos < tokens.len() {
-        match &tokens[pos] {
-            Token::Identifier(name) => {
-                variables.push(name.clone());
-                pos += 1;
+    Ok(Statement::OnProc { expression, calls })
+}
+
+/// Parse a variable or array element for READ or INPUT to set, returning
+/// it and the position after it, or None if there is no name at `pos`
+///
+/// The subscripts of an element are kept as expressions, to be worked out
+/// when the value arrives: `READ I%, A%(I%)` uses the I% just read.
+fn parse_target(
+    tokens: &[Token],
+    pos: usize,
+    line_number: Option<u16>,
+) -> Result<Option<(Expression, usize)>> {
+    let Some(Token::Identifier(name)) = tokens.get(pos) else {
+        return Ok(None);
+    };
+    if !matches!(tokens.get(pos + 1), Some(Token::Separator('('))) {
+        return Ok(Some((Expression::Variable(name.clone()), pos + 1)));
+    }
 
-                if pos < tokens.len() && matches!(tokens[pos], Token::Separator(',')) {
-                    pos += 1; // skip comma
+    let mut paren_depth = 0;
+    for (i, token) in tokens.iter().enumerate().skip(pos + 1) {
+        match token {
+            Token::Separator('(') => paren_depth += 1,
+            Token::Separator(')') => {
+                paren_depth -= 1;
+                if paren_depth == 0 {
+                    let indices =
+                        parse_comma_separated_expressions(&tokens[pos + 2..i], line_number)?;
+                    let element = Expression::ArrayAccess {
+                        name: name.clone(),
+                        indices,
+                    };
+                    return Ok(Some((element, i + 1)));
                 }
             }
-            _ => break,
+            _ => {}
         }
     }
-
-    Ok(Statement::Input { variables })
+    Err(BBCBasicError::SyntaxError {
+        message: "Missing )".to_string(),
+        line: line_number,
+    })
 }
 
-/// Parse PRINT# statement (file I/O)
-fn parse_print_file_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
-    // First token should be the file handle expression
-    // Format: PRINT# handle, items...
-    
-    // Find the comma that separates handle from print items
-    let comma_pos = tokens.iter().position(|t| matches!(t, Token::Separator(',')));
-    
-    if comma_pos.is_none() {
-        return Err(BBCBasicError::SyntaxError {
-            message: "Expected comma after file handle in PRINT#".to_string(),
+/// Fail if any tokens are left from `pos` on, rather than ignore the rest
+/// of a statement
+fn expect_end(tokens: &[Token], pos: usize, line_number: Option<u16>) -> Result<()> {
+    match tokens.get(pos) {
+        Some(token) => Err(BBCBasicError::SyntaxError {
+            message: format!("Unexpected {:?} at end of statement", token),
             line: line_number,
-        });
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Parse a statement that is its keyword alone, such as END or CLS
+fn parse_bare_statement(
+    tokens: &[Token],
+    statement: Statement,
+    line_number: Option<u16>,
+) -> Result<Statement> {
+    expect_end(tokens, 1, line_number)?;
+    Ok(statement)
+}
+
+/// Parse INPUT statement
+fn parse_input_statement(tokens: &[Token]) -> Result<Statement> {
+    let mut variables = Vec::new();
+    let mut pos = 0;
+
+    while let Some((target, next)) = parse_target(tokens, pos, None)? {
+        variables.push(target);
+        pos = next;
+
+        if pos < tokens.len() && matches!(tokens[pos], Token::Separator(',')) {
+            pos += 1; // skip comma
+        } else {
+            break;
+        }
+    }
+    expect_end(tokens, pos, None)?;
+
+    Ok(Statement::Input { variables })
+}
+    
+/// Parse PRINT# statement (file I/O)
+fn parse_print_file_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
+    // First token should be the file handle expression
+    // Format: PRINT# handle, items...
+
+    // Find the comma that separates handle from print items
+    let comma_pos = tokens.iter().position(|t| matches!(t, Token::Separator(',')));
+    
+    if comma_pos.is_none() {
+        return Err(BBCBasicError::SyntaxError {
+            message: "Expected comma after file handle in PRINT#".to_string(),
+            line: line_number,
+        });
     }
     
     let comma_pos = comma_pos.unwrap();
@@ -1050,11 +1809,11 @@ fn parse_print_file_statement(tokens: &[Token], line_number: Option<u16>) -> Res
     
     Ok(Statement::PrintFile { handle, items })
 }
-
+    
 /// Parse INPUT# statement (file I/O)
 fn parse_input_file_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
     // Format: INPUT# handle, var1, var2, ...
-    
+
     // Find the comma that separates handle from variables
     let comma_pos = tokens.iter().position(|t| matches!(t, Token::Separator(',')));
     
@@ -1074,20 +1833,18 @@ fn parse_input_file_statement(tokens: &[Token], line_number: Option<u16>) -> Res
     let mut variables = Vec::new();
     let mut pos = comma_pos + 1;
     
-    while pos < tokens.len() {
-        match &tokens[pos] {
-            Token::Identifier(name) => {
-                variables.push(name.clone());
-                pos += 1;
-                
-                if pos < tokens.len() && matches!(tokens[pos], Token::Separator(',')) {
-                    pos += 1; // skip comma
-                }
-            }
-            _ => break,
+    while let Some((target, next)) = parse_target(tokens, pos, line_number)? {
+        variables.push(target);
+        pos = next;
+    
+        if pos < tokens.len() && matches!(tokens[pos], Token::Separator(',')) {
+            pos += 1; // skip comma
+        } else {
+            break;
         }
     }
-    
+    expect_end(tokens, pos, line_number)?;
+
     if variables.is_empty() {
         return Err(BBCBasicError::SyntaxError {
             message: "Expected at least one variable in INPUT#".to_string(),
@@ -1115,6 +1872,48 @@ fn parse_close_file_statement(tokens: &[Token], line_number: Option<u16>) -> Res
     Ok(Statement::CloseFile { handle })
 }
 
+/// Parse BPUT# statement (file I/O)
+fn parse_bput_file_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
+    // Format: BPUT# handle, value [;]
+    let (tokens, newline) = match tokens {
+        [rest @ .., Token::Separator(';')] => (rest, false),
+        _ => (tokens, true),
+    };
+    let args = parse_comma_separated_expressions(tokens, line_number)?;
+    match <[Expression; 2]>::try_from(args) {
+        Ok([handle, value]) => Ok(Statement::BputFile {
+            handle,
+            value,
+            newline,
+        }),
+        Err(_) => Err(BBCBasicError::SyntaxError {
+            message: "BPUT# requires a file handle and a value".to_string(),
+            line: line_number,
+        }),
+    }
+}
+
+/// Parse PTR#handle = value or EXT#handle = value (file I/O)
+fn parse_file_attribute_statement(
+    keyword: &Token,
+    tokens: &[Token],
+    line_number: Option<u16>,
+) -> Result<Statement> {
+    let mut pos = 0;
+    let handle = parse_primary(tokens, &mut pos)?;
+    if !matches!(tokens.get(pos), Some(Token::Operator('='))) {
+        return Err(BBCBasicError::SyntaxError {
+            message: "Expected = after file handle".to_string(),
+            line: line_number,
+        });
+    }
+    let value = parse_expression(&tokens[pos + 1..])?;
+    Ok(match keyword {
+        Token::Keyword(0xA2) => Statement::ExtFile { handle, value },
+        _ => Statement::PtrFile { handle, value },
+    })
+}
+
 /// Parse PLOT statement: PLOT mode, x, y
 fn parse_plot_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
     if tokens.is_empty() {
@@ -1141,8 +1940,9 @@ fn parse_plot_statement(tokens: &[Token], line_number: Option<u16>) -> Result<St
     })
 }
 
-/// Parse MOVE statement: MOVE x, y
+/// Parse MOVE statement: MOVE [BY] x, y
 fn parse_move_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
+    let (relative, tokens) = strip_by(tokens);
     if tokens.is_empty() {
         return Err(BBCBasicError::SyntaxError {
             message: "MOVE requires x, y parameters".to_string(),
@@ -1162,11 +1962,13 @@ fn parse_move_statement(tokens: &[Token], line_number: Option<u16>) -> Result<St
     Ok(Statement::Move {
         x: args[0].clone(),
         y: args[1].clone(),
+        relative,
     })
 }
 
-/// Parse DRAW statement: DRAW x, y
+/// Parse DRAW statement: DRAW [BY] x, y
 fn parse_draw_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
+    let (relative, tokens) = strip_by(tokens);
     if tokens.is_empty() {
         return Err(BBCBasicError::SyntaxError {
             message: "DRAW requires x, y parameters".to_string(),
@@ -1186,11 +1988,13 @@ fn parse_draw_statement(tokens: &[Token], line_number: Option<u16>) -> Result<St
     Ok(Statement::Draw {
         x: args[0].clone(),
         y: args[1].clone(),
+        relative,
     })
 }
 
-/// Parse CIRCLE statement: CIRCLE x, y, radius
+/// Parse CIRCLE statement: CIRCLE [FILL] x, y, radius
 fn parse_circle_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
+    let (filled, tokens) = strip_fill(tokens);
     if tokens.is_empty() {
         return Err(BBCBasicError::SyntaxError {
             message: "CIRCLE requires x, y, radius parameters".to_string(),
@@ -1211,6 +2015,7 @@ fn parse_circle_statement(tokens: &[Token], line_number: Option<u16>) -> Result<
         x: args[0].clone(),
         y: args[1].clone(),
         radius: args[2].clone(),
+        filled,
     })
 }
 
@@ -1238,8 +2043,22 @@ fn parse_gcol_statement(tokens: &[Token], line_number: Option<u16>) -> Result<St
     })
 }
 
-/// Parse ELLIPSE statement: ELLIPSE x, y, major, minor
+/// Parse COLOUR statement: COLOUR colour
+fn parse_colour_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
+    if tokens.is_empty() {
+        return Err(BBCBasicError::SyntaxError {
+            message: "COLOUR requires a colour".to_string(),
+            line: line_number,
+        });
+    }
+    Ok(Statement::Colour {
+        colour: parse_expression(tokens)?,
+    })
+}
+
+/// Parse ELLIPSE statement: ELLIPSE [FILL] x, y, major, minor
 fn parse_ellipse_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
+    let (filled, tokens) = strip_fill(tokens);
     if tokens.is_empty() {
         return Err(BBCBasicError::SyntaxError {
             message: "ELLIPSE requires x, y, major, minor parameters".to_string(),
@@ -1264,11 +2083,13 @@ fn parse_ellipse_statement(tokens: &[Token], line_number: Option<u16>) -> Result
         y: args[1].clone(),
         major: args[2].clone(),
         minor: args[3].clone(),
+        filled,
     })
 }
 
-/// Parse RECTANGLE statement: RECTANGLE x1, y1, width, height
+/// Parse RECTANGLE statement: RECTANGLE [FILL] x1, y1, width, height [TO x, y]
 fn parse_rectangle_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
+    let (filled, tokens) = strip_fill(tokens);
     if tokens.is_empty() {
         return Err(BBCBasicError::SyntaxError {
             message: "RECTANGLE requires x1, y1, width, height parameters".to_string(),
@@ -1276,6 +2097,22 @@ fn parse_rectangle_statement(tokens: &[Token], line_number: Option<u16>) -> Resu
         });
     }
 
+    let (tokens, target) = match tokens.iter().position(|t| *t == Token::Keyword(0xB8)) {
+        Some(to) => {
+            let target = parse_comma_separated_expressions(&tokens[to + 1..], line_number)?;
+            let [x, y] = <[Expression; 2]>::try_from(target).map_err(|target| {
+                BBCBasicError::SyntaxError {
+                    message: format!(
+                        "RECTANGLE ... TO requires 2 parameters (x, y), got {}",
+                        target.len()
+                    ),
+                    line: line_number,
+                }
+            })?;
+            (&tokens[..to], Some((x, y)))
+        }
+        None => (tokens, None),
+    };
     let args = parse_comma_separated_expressions(tokens, line_number)?;
 
     if args.len() != 4 {
@@ -1293,10 +2130,33 @@ fn parse_rectangle_statement(tokens: &[Token], line_number: Option<u16>) -> Resu
         y1: args[1].clone(),
         width: args[2].clone(),
         height: args[3].clone(),
-        filled: true, // BBC BASIC RECTANGLE draws filled rectangles
+        filled,
+        target,
     })
 }
 
+/// Split a leading FILL (CIRCLE FILL, RECTANGLE FILL) from a statement's
+/// arguments
+fn strip_fill(tokens: &[Token]) -> (bool, &[Token]) {
+    match tokens.split_first() {
+        Some((Token::ExtendedKeyword(0xC8, 0x90), rest)) => (true, rest),
+        _ => (false, tokens),
+    }
+}
+
+/// Split a leading BY (MOVE BY, DRAW BY) from a statement's arguments
+///
+/// BY is not a keyword, so it is only taken as one when it cannot be a
+/// variable: when something other than a comma follows it.
+fn strip_by(tokens: &[Token]) -> (bool, &[Token]) {
+    match tokens {
+        [Token::Identifier(by), next, ..] if by == "BY" && *next != Token::Separator(',') => {
+            (true, &tokens[1..])
+        }
+        _ => (false, tokens),
+    }
+}
+
 /// Parse FILL statement: FILL x, y
 fn parse_fill_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
     if tokens.is_empty() {
@@ -1346,6 +2206,100 @@ fn parse_origin_statement(tokens: &[Token], line_number: Option<u16>) -> Result<
     })
 }
 
+/// Parse VDU statement: VDU a, b; c | (; sends a 16-bit word, | nine zeros)
+fn parse_vdu_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
+    let mut items = Vec::new();
+    let mut start = 0;
+    let mut depth = 0;
+    for (pos, token) in tokens.iter().enumerate() {
+        let separator = match token {
+            Token::Separator('(') => {
+                depth += 1;
+                continue;
+            }
+            Token::Separator(')') => {
+                depth -= 1;
+                continue;
+            }
+            Token::Separator(c @ (',' | ';' | '|')) if depth == 0 => *c,
+            _ => continue,
+        };
+        if start < pos {
+            let expression = parse_expression(&tokens[start..pos])?;
+            items.push(if separator == ';' {
+                VduItem::Word(expression)
+            } else {
+                VduItem::Byte(expression)
+            });
+        }
+        if separator == '|' {
+            items.extend(std::iter::repeat_n(
+                VduItem::Byte(Expression::Integer(0)),
+                9,
+            ));
+        }
+        start = pos + 1;
+    }
+    if start < tokens.len() {
+        items.push(VduItem::Byte(parse_expression(&tokens[start..])?));
+    }
+
+    if items.is_empty() {
+        return Err(BBCBasicError::SyntaxError {
+            message: "VDU requires at least one value".to_string(),
+            line: line_number,
+        });
+    }
+    Ok(Statement::Vdu { items })
+}
+
+/// Parse SOUND statement: SOUND channel, amplitude, pitch, duration
+fn parse_sound_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
+    if tokens.is_empty() {
+        return Err(BBCBasicError::SyntaxError {
+            message: "SOUND requires channel, amplitude, pitch, duration parameters".to_string(),
+            line: line_number,
+        });
+    }
+
+    let args = parse_comma_separated_expressions(tokens, line_number)?;
+
+    if args.len() != 4 {
+        return Err(BBCBasicError::SyntaxError {
+            message: format!(
+                "SOUND requires 4 parameters (channel, amplitude, pitch, duration), got {}",
+                args.len()
+            ),
+            line: line_number,
+        });
+    }
+
+    Ok(Statement::Sound {
+        channel: args[0].clone(),
+        amplitude: args[1].clone(),
+        pitch: args[2].clone(),
+        duration: args[3].clone(),
+    })
+}
+
+/// Parse ENVELOPE statement: ENVELOPE n, t, pi1, pi2, pi3, pn1, pn2, pn3, aa, ad, as, ar, ala, ald
+fn parse_envelope_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
+    let params = if tokens.is_empty() {
+        Vec::new()
+    } else {
+        parse_comma_separated_expressions(tokens, line_number)?
+    };
+
+    if params.len() != 14 {
+        return Err(BBCBasicError::SyntaxError {
+            message: format!("ENVELOPE requires 14 parameters, got {}", params.len()),
+            line: line_number,
+        });
+    }
+
+    Ok(Statement::Envelope { params })
+}
+
 /// Helper function to parse comma-separated expressions
 fn parse_comma_separated_expressions(
     tokens: &[Token],
@@ -1512,6 +2466,15 @@ fn parse_dim_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Sta
         };
         pos += 1;
 
+        // DIM name size reserves a block of memory instead
+        if arrays.is_empty() && pos < tokens.len() && !matches!(tokens[pos], Token::Separator('('))
+        {
+            return Ok(Statement::DimSpace {
+                name,
+                size: parse_expression(&tokens[pos..])?,
+            });
+        }
+
         // Expect opening paren
         if pos >= tokens.len() || !matches!(tokens[pos], Token::Separator('(')) {
             return Err(BBCBasicError::SyntaxError {
@@ -1566,89 +2529,91 @@ fn parse_dim_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Sta
             break;
         }
     }
+    expect_end(tokens, pos, line_number)?;
 
     Ok(Statement::Dim { arrays })
 }
 
 /// Parse DATA statement
-/// Supports: DATA value1, value2, value3, ...
+///
+/// Items are kept as typed, split at commas outside quotes (see
+/// [`split_items`]). An unquoted item keeps its trailing spaces.
 fn parse_data_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
-    let mut values = Vec::new();
-    let mut pos = 0;
-
-    while pos < tokens.len() {
-        // Skip commas
-        if matches!(tokens[pos], Token::Separator(',')) {
-            pos += 1;
-            continue;
+    let text = match tokens {
+        [] => "",
+        [Token::Text(text)] => text,
+        _ => {
+            return Err(BBCBasicError::SyntaxError {
+                message: "DATA must start a statement".to_string(),
+                line: line_number,
+            })
         }
+    };
+    let values = split_items(text)
+        .into_iter()
+        .map(DataValue::String)
+        .collect();
+    Ok(Statement::Data { values })
+}
 
-        // Parse value
-        match &tokens[pos] {
-            Token::Integer(val) => {
-                values.push(DataValue::Integer(*val));
-                pos += 1;
-            }
-            Token::Real(val) => {
-                values.push(DataValue::Real(*val));
-                pos += 1;
-            }
-            Token::String(val) => {
-                values.push(DataValue::String(val.clone()));
-                pos += 1;
-            }
-            Token::Operator('-') if pos + 1 < tokens.len() => {
-                // Handle negative numbers
-                pos += 1;
-                match &tokens[pos] {
-                    Token::Integer(val) => {
-                        values.push(DataValue::Integer(-val));
-                        pos += 1;
-                    }
-                    Token::Real(val) => {
-                        values.push(DataValue::Real(-val));
-                        pos += 1;
-                    }
-                    _ => {
-                        return Err(BBCBasicError::SyntaxError {
-                            message: "Expected number after minus in DATA".to_string(),
-                            line: line_number,
-                        });
-                    }
+/// Split DATA text, or a line typed in answer to INPUT, at its commas
+///
+/// Leading spaces are skipped. An item in quotes may hold commas, with ""
+/// standing for a quote, and anything after the closing quote is ignored.
+pub(crate) fn split_items(line: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if_eq(&' ').is_some() {}
+        let mut item = String::new();
+        let more = if chars.next_if_eq(&'"').is_some() {
+            while let Some(c) = chars.next() {
+                if c != '"' {
+                    item.push(c);
+                } else if chars.next_if_eq(&'"').is_some() {
+                    item.push('"');
+                } else {
+                    break;
                 }
             }
-            _ => {
-                return Err(BBCBasicError::SyntaxError {
-                    message: format!("Invalid DATA value: {:?}", tokens[pos]),
-                    line: line_number,
-                });
+            chars.any(|c| c == ',')
+        } else {
+            loop {
+                match chars.next() {
+                    Some(',') => break true,
+                    Some(c) => item.push(c),
+                    None => break false,
+                }
             }
+        };
+        items.push(item);
+        if !more {
+            return items;
         }
     }
-
-    Ok(Statement::Data { values })
 }
 
 /// Parse READ statement
-/// Supports: READ var1, var2, var3, ...
+/// Supports: READ var1, var2, array(index), ...
 fn parse_read_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
     let mut variables = Vec::new();
     let mut pos = 0;
 
     while pos < tokens.len() {
-        // Skip commas
-        if matches!(tokens[pos], Token::Separator(',')) {
+        // Expect variable name or array element, after a comma if it is
+        // not the first
+        if !variables.is_empty() {
+            if !matches!(tokens[pos], Token::Separator(',')) {
+                break;
+            }
             pos += 1;
-            continue;
         }
-
-        // Expect variable name
-        match &tokens[pos] {
-            Token::Identifier(name) => {
-                variables.push(name.clone());
-                pos += 1;
+        match parse_target(tokens, pos, line_number)? {
+            Some((target, next)) => {
+                variables.push(target);
+                pos = next;
             }
-            _ => {
+            None => {
                 return Err(BBCBasicError::SyntaxError {
                     message: "Expected variable name in READ".to_string(),
                     line: line_number,
@@ -1657,6 +2622,8 @@ fn parse_read_statement(tokens: &[Token], line_number: Option<u16>) -> Result<St
         }
     }
 
+    expect_end(tokens, pos, line_number)?;
+
     if variables.is_empty() {
         return Err(BBCBasicError::SyntaxError {
             message: "READ requires at least one variable".to_string(),
@@ -1668,30 +2635,26 @@ fn parse_read_statement(tokens: &[Token], line_number: Option<u16>) -> Result<St
 }
 
 /// Parse RESTORE statement
-/// Supports: RESTORE [line_number]
+/// Supports: RESTORE [line_number], RESTORE expression, RESTORE +offset,
+/// RESTORE DATA and RESTORE ERROR
 fn parse_restore_statement(tokens: &[Token], _line_number: Option<u16>) -> Result<Statement> {
-    if tokens.is_empty() {
+    match tokens {
         // RESTORE with no line number - reset to beginning
-        Ok(Statement::Restore { line_number: None })
-    } else if tokens.len() == 1 {
-        // RESTORE with line number
-        match &tokens[0] {
-            Token::Integer(num) => Ok(Statement::Restore {
-                line_number: Some(*num as u16),
-            }),
-            Token::LineNumber(num) => Ok(Statement::Restore {
-                line_number: Some(*num),
-            }),
-            _ => Err(BBCBasicError::SyntaxError {
-                message: "RESTORE expects line number".to_string(),
-                line: None,
-            }),
-        }
-    } else {
-        Err(BBCBasicError::SyntaxError {
-            message: "RESTORE expects at most one line number".to_string(),
-            line: None,
-        })
+        [] => Ok(Statement::Restore { line_number: None }),
+        [Token::Integer(num)] => Ok(Statement::Restore {
+            line_number: Some(*num as u16),
+        }),
+        [Token::LineNumber(num)] => Ok(Statement::Restore {
+            line_number: Some(*num),
+        }),
+        [Token::Keyword(0xDC)] => Ok(Statement::RestoreData),
+        [Token::Keyword(0x85)] => Ok(Statement::RestoreError),
+        [Token::Operator('+'), offset @ ..] => Ok(Statement::RestoreRelative {
+            offset: parse_expression(offset)?,
+        }),
+        line => Ok(Statement::RestoreLine {
+            line: parse_expression(line)?,
+        }),
     }
 }
 
@@ -1710,61 +2673,179 @@ fn parse_until_statement(tokens: &[Token], line_number: Option<u16>) -> Result<S
     Ok(Statement::Until { condition })
 }
 
-/// Parse WHILE statement
-/// WHILE condition
-fn parse_while_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
+/// Parse LIBRARY or INSTALL statement: LIBRARY filename
+fn parse_library_statement(
+    tokens: &[Token],
+    permanent: bool,
+    line_number: Option<u16>,
+) -> Result<Statement> {
     if tokens.is_empty() {
         return Err(BBCBasicError::SyntaxError {
-            message: "WHILE requires a condition".to_string(),
+            message: "Expected file name".to_string(),
             line: line_number,
         });
     }
-
-    // Parse the condition expression
-    let condition = parse_expression(tokens)?;
-    Ok(Statement::While { condition })
+    Ok(Statement::Library {
+        filename: parse_expression(tokens)?,
+        permanent,
+    })
 }
 
-/// Parse DEF statement (DEF PROC or DEF FN)
-/// Supports: DEF PROCname(param1, param2, ...)
-/// Supports: DEF FNname(param1, param2, ...)
-fn parse_def_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
+/// Parse CHAIN statement: CHAIN filename
+fn parse_chain_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
     if tokens.is_empty() {
         return Err(BBCBasicError::SyntaxError {
-            message: "DEF requires PROC or FN".to_string(),
+            message: "Expected program name".to_string(),
             line: line_number,
         });
     }
+    Ok(Statement::Chain {
+        filename: parse_expression(tokens)?,
+    })
+}
 
-    // Check if it's DEF PROC or DEF FN
-    match &tokens[0] {
-        Token::Keyword(0xF2) => {
-            // DEF PROC
-            parse_def_proc(&tokens[1..], line_number)
-        }
-        Token::Keyword(0xA4) => {
-            // DEF FN
-            parse_def_fn(&tokens[1..], line_number)
-        }
-        Token::Keyword(_) => {
-            // Unknown keyword after DEF
-            Err(BBCBasicError::SyntaxError {
-                message: "Expected PROC or FN after DEF".to_string(),
-                line: line_number,
-            })
-        }
-        _ => Err(BBCBasicError::SyntaxError {
-            message: "Expected PROC or FN after DEF".to_string(),
+/// Parse CALL statement: CALL address
+fn parse_call_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
+    if tokens.is_empty() {
+        return Err(BBCBasicError::SyntaxError {
+            message: "CALL requires an address".to_string(),
             line: line_number,
-        }),
+        });
     }
+    Ok(Statement::Call {
+        address: parse_expression(tokens)?,
+    })
 }
 
-/// Parse DEF PROCname(params)
-fn parse_def_proc(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
+/// Parse WHILE statement
+/// WHILE condition
+fn parse_while_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
     if tokens.is_empty() {
         return Err(BBCBasicError::SyntaxError {
-            message: "Expected procedure name after DEF PROC".to_string(),
+            message: "WHILE requires a condition".to_string(),
+            line: line_number,
+        });
+    }
+
+    // Parse the condition expression
+    let condition = parse_expression(tokens)?;
+    Ok(Statement::While { condition })
+}
+
+/// Parse CONST statement
+/// Supports: CONST name = expression
+fn parse_const_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
+    match tokens {
+        [Token::Identifier(name), Token::Operator('='), expression @ ..]
+            if !expression.is_empty() =>
+        {
+            Ok(Statement::Const {
+                name: name.clone(),
+                expression: parse_expression(expression)?,
+            })
+        }
+        _ => Err(BBCBasicError::SyntaxError {
+            message: "CONST requires a name and a value (CONST name = value)".to_string(),
+            line: line_number,
+        }),
+    }
+}
+
+/// Parse OUTPUT statement
+/// Supports: OUTPUT #handle, OUTPUT
+fn parse_output_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
+    match tokens {
+        [] => Ok(Statement::Output { handle: None }),
+        [Token::Operator('#'), handle @ ..] if !handle.is_empty() => Ok(Statement::Output {
+            handle: Some(parse_expression(handle)?),
+        }),
+        _ => Err(BBCBasicError::SyntaxError {
+            message: "OUTPUT requires a channel (OUTPUT #handle) or nothing".to_string(),
+            line: line_number,
+        }),
+    }
+}
+
+/// Parse MOUSE statement
+/// Supports: MOUSE x, y, buttons / MOUSE ON / MOUSE OFF
+fn parse_mouse_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
+    match tokens {
+        [Token::Keyword(0xEE)] => return Ok(Statement::MousePointer { visible: true }),
+        [Token::Keyword(0x87)] => return Ok(Statement::MousePointer { visible: false }),
+        _ => {}
+    }
+    let mut targets = Vec::new();
+    let mut pos = 0;
+    while let Some((target, next)) = parse_target(tokens, pos, line_number)? {
+        targets.push(target);
+        pos = next;
+        if targets.len() == 3 || !matches!(tokens.get(pos), Some(Token::Separator(','))) {
+            break;
+        }
+        pos += 1;
+    }
+    match <[Expression; 3]>::try_from(targets) {
+        Ok([x, y, buttons]) if pos == tokens.len() => Ok(Statement::Mouse { x, y, buttons }),
+        _ => Err(BBCBasicError::SyntaxError {
+            message: "MOUSE requires three variables (x, y, buttons), ON or OFF".to_string(),
+            line: line_number,
+        }),
+    }
+}
+
+/// Parse ASSERT statement
+/// Supports: ASSERT condition [, message]
+fn parse_assert_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
+    let mut args = parse_comma_separated_expressions(tokens, line_number)?.into_iter();
+    match (args.next(), args.next(), args.next()) {
+        (Some(condition), message, None) => Ok(Statement::Assert { condition, message }),
+        _ => Err(BBCBasicError::SyntaxError {
+            message: "ASSERT requires a condition and an optional message".to_string(),
+            line: line_number,
+        }),
+    }
+}
+
+/// Parse DEF statement (DEF PROC or DEF FN)
+/// Supports: DEF PROCname(param1, param2, ...)
+/// Supports: DEF FNname(param1, param2, ...)
+fn parse_def_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
+    if tokens.is_empty() {
+        return Err(BBCBasicError::SyntaxError {
+            message: "DEF requires PROC or FN".to_string(),
+            line: line_number,
+        });
+    }
+
+    // Check if it's DEF PROC or DEF FN
+    match &tokens[0] {
+        Token::Keyword(0xF2) => {
+            // DEF PROC
+            parse_def_proc(&tokens[1..], line_number)
+        }
+        Token::Keyword(0xA4) => {
+            // DEF FN
+            parse_def_fn(&tokens[1..], line_number)
+        }
+        Token::Keyword(_) => {
+            // Unknown keyword after DEF
+            Err(BBCBasicError::SyntaxError {
+                message: "Expected PROC or FN after DEF".to_string(),
+                line: line_number,
+            })
+        }
+        _ => Err(BBCBasicError::SyntaxError {
+            message: "Expected PROC or FN after DEF".to_string(),
+            line: line_number,
+        }),
+    }
+}
+
+/// Parse DEF PROCname(params)
+fn parse_def_proc(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
+    if tokens.is_empty() {
+        return Err(BBCBasicError::SyntaxError {
+            message: "Expected procedure name after DEF PROC".to_string(),
             line: line_number,
         });
     }
@@ -1783,7 +2864,9 @@ fn parse_def_proc(tokens: &[Token], line_number: Option<u16>) -> Result<Statemen
     // Parse parameters if present
     let params = if tokens.len() > 1 {
         // Should be ( param1, param2, ... )
-        parse_parameter_list(&tokens[1..], line_number)?
+        let (params, used) = parse_parameter_list(&tokens[1..], line_number)?;
+        expect_end(tokens, 1 + used, line_number)?;
+        params
     } else {
         Vec::new()
     };
@@ -1812,50 +2895,25 @@ fn parse_def_fn(tokens: &[Token], line_number: Option<u16>) -> Result<Statement>
     };
 
     // Parse parameters if present
-    let (params, rest_start) = if tokens.len() > 1 && matches!(tokens[1], Token::Operator('(')) {
-        // Find closing parenthesis
-        let close_pos = tokens
-            .iter()
-            .skip(1)
-            .position(|t| matches!(t, Token::Operator(')')))
-            .ok_or(BBCBasicError::SyntaxError {
-                message: "Expected ) after parameter list".to_string(),
-                line: line_number,
-            })?
-            + 1;
-
-        // Parse parameters (comma-separated identifiers between parentheses)
-        let mut params = Vec::new();
-        let mut pos = 2; // Skip name and (
-        while pos < close_pos {
-            // Skip commas
-            if matches!(tokens[pos], Token::Separator(',')) {
-                pos += 1;
-                continue;
-            }
-
-            // Expect identifier
-            match &tokens[pos] {
-                Token::Identifier(param) => {
-                    params.push(param.clone());
-                    pos += 1;
-                }
-                _ => {
-                    return Err(BBCBasicError::SyntaxError {
-                        message: "Expected parameter name".to_string(),
-                        line: line_number,
-                    });
-                }
-            }
-        }
-
-        (params, close_pos + 1) // Skip past the )
+    let (params, used) = if tokens.len() > 1 && matches!(tokens[1], Token::Separator('(')) {
+        parse_parameter_list(&tokens[1..], line_number)?
     } else {
-        (Vec::new(), 1)
+        (Vec::new(), 0)
     };
+    let rest_start = 1 + used;
+
+    // Nothing after the parameters starts a multi-line function, whose
+    // body follows on the next lines
+    if rest_start >= tokens.len() {
+        return Ok(Statement::DefFn {
+            name,
+            params,
+            expression: None,
+        });
+    }
 
     // Expect = after parameters
-    if rest_start >= tokens.len() || !matches!(tokens[rest_start], Token::Operator('=')) {
+    if !matches!(tokens[rest_start], Token::Operator('=')) {
         return Err(BBCBasicError::SyntaxError {
             message: "Expected = after function parameters".to_string(),
             line: line_number,
@@ -1863,7 +2921,7 @@ fn parse_def_fn(tokens: &[Token], line_number: Option<u16>) -> Result<Statement>
     }
 
     // Parse the expression after =
-    let expression = parse_expression(&tokens[rest_start + 1..])?;
+    let expression = Some(parse_expression(&tokens[rest_start + 1..])?);
 
     Ok(Statement::DefFn {
         name,
@@ -1902,23 +2960,38 @@ fn parse_proc_call(tokens: &[Token], line_number: Option<u16>) -> Result<Stateme
     Ok(Statement::ProcCall { name, args })
 }
 
-/// Parse LOCAL statement: LOCAL var1, var2, var3
+/// Parse LOCAL statement: LOCAL var1, array(), ... or LOCAL DATA or LOCAL ERROR
 fn parse_local_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Statement> {
+    match tokens {
+        [Token::Keyword(0xDC)] => return Ok(Statement::LocalData),
+        [Token::Keyword(0x85)] => return Ok(Statement::LocalError),
+        _ => {}
+    }
+
     let mut variables = Vec::new();
     let mut pos = 0;
 
     while pos < tokens.len() {
-        // Skip commas
-        if matches!(tokens[pos], Token::Separator(',')) {
+        // Expect variable name, after a comma if it is not the first
+        if !variables.is_empty() {
+            if !matches!(tokens[pos], Token::Separator(',')) {
+                break;
+            }
             pos += 1;
-            continue;
         }
-
-        // Expect variable name
-        match &tokens[pos] {
-            Token::Identifier(name) => {
-                variables.push(name.clone());
-                pos += 1;
+        match tokens.get(pos) {
+            Some(Token::Identifier(name)) => {
+                // A whole array: name()
+                if matches!(
+                    tokens.get(pos + 1..pos + 3),
+                    Some([Token::Separator('('), Token::Separator(')')])
+                ) {
+                    variables.push(format!("{}()", name));
+                    pos += 3;
+                } else {
+                    variables.push(name.clone());
+                    pos += 1;
+                }
             }
             _ => {
                 return Err(BBCBasicError::SyntaxError {
@@ -1929,6 +3002,8 @@ fn parse_local_statement(tokens: &[Token], line_number: Option<u16>) -> Result<S
         }
     }
 
+    expect_end(tokens, pos, line_number)?;
+
     if variables.is_empty() {
         return Err(BBCBasicError::SyntaxError {
             message: "LOCAL requires at least one variable".to_string(),
@@ -1946,22 +3021,32 @@ fn parse_argument_list(tokens: &[Token], line_number: Option<u16>) -> Result<Vec
     }
 
     // Expect opening parenthesis
-    if !matches!(tokens[0], Token::Operator('(')) {
+    if !matches!(tokens[0], Token::Separator('(')) {
         return Err(BBCBasicError::SyntaxError {
             message: "Expected ( after procedure name".to_string(),
             line: line_number,
         });
     }
 
-    // Find closing parenthesis
+    // Find the matching closing parenthesis
+    let mut depth = 0;
     let close_pos = tokens
         .iter()
-        .position(|t| matches!(t, Token::Operator(')')))
+        .position(|t| {
+            match t {
+                Token::Separator('(') => depth += 1,
+                Token::Separator(')') => depth -= 1,
+                _ => {}
+            }
+            depth == 0
+        })
         .ok_or(BBCBasicError::SyntaxError {
             message: "Expected ) after argument list".to_string(),
             line: line_number,
         })?;
 
+    expect_end(tokens, close_pos + 1, line_number)?;
+
     if close_pos == 1 {
         // Empty argument list: ()
         return Ok(Vec::new());
@@ -1974,8 +3059,8 @@ fn parse_argument_list(tokens: &[Token], line_number: Option<u16>) -> Result<Vec
 
     for i in 1..close_pos {
         match &tokens[i] {
-            Token::Operator('(') => depth += 1,
-            Token::Operator(')') => depth -= 1,
+            Token::Separator('(') => depth += 1,
+            Token::Separator(')') => depth -= 1,
             Token::Separator(',') if depth == 0 => {
                 // Parse expression from start to i
                 let expr = parse_expression(&tokens[start..i])?;
@@ -1995,60 +3080,75 @@ fn parse_argument_list(tokens: &[Token], line_number: Option<u16>) -> Result<Vec
     Ok(args)
 }
 
-/// Parse parameter list: (param1, param2, ...)
-fn parse_parameter_list(tokens: &[Token], line_number: Option<u16>) -> Result<Vec<String>> {
+/// Parse a DEF parameter list: (param1, array(), ...)
+///
+/// Whole array parameters are named with a trailing "()". Returns the
+/// parameters and the number of tokens used, including the parentheses.
+fn parse_parameter_list(
+    tokens: &[Token],
+    line_number: Option<u16>,
+) -> Result<(Vec<String>, usize)> {
     if tokens.is_empty() {
-        return Ok(Vec::new());
+        return Ok((Vec::new(), 0));
     }
 
     // Expect opening parenthesis
-    if !matches!(tokens[0], Token::Operator('(')) {
+    if !matches!(tokens[0], Token::Separator('(')) {
         return Err(BBCBasicError::SyntaxError {
             message: "Expected ( after procedure name".to_string(),
             line: line_number,
         });
     }
 
-    // Find closing parenthesis
-    let close_pos = tokens
-        .iter()
-        .position(|t| matches!(t, Token::Operator(')')))
-        .ok_or(BBCBasicError::SyntaxError {
-            message: "Expected ) after parameter list".to_string(),
-            line: line_number,
-        })?;
-
-    // Extract parameter names between parentheses
     let mut params = Vec::new();
     let mut i = 1;
-    while i < close_pos {
-        match &tokens[i] {
-            Token::Identifier(name) => {
-                params.push(name.clone());
-                i += 1;
+    loop {
+        match tokens.get(i) {
+            Some(Token::Identifier(name)) => {
+                if matches!(
+                    tokens.get(i + 1..i + 3),
+                    Some([Token::Separator('('), Token::Separator(')')])
+                ) {
+                    params.push(format!("{}()", name));
+                    i += 3;
+                } else {
+                    params.push(name.clone());
+                    i += 1;
+                }
 
                 // Check for comma or end
-                if i < close_pos {
-                    if matches!(tokens[i], Token::Separator(',')) {
-                        i += 1; // Skip comma
-                    } else {
+                match tokens.get(i) {
+                    Some(Token::Separator(',')) => i += 1,
+                    Some(Token::Separator(')')) => {}
+                    Some(_) => {
                         return Err(BBCBasicError::SyntaxError {
                             message: "Expected , between parameters".to_string(),
                             line: line_number,
-                        });
+                        })
+                    }
+                    None => {
+                        return Err(BBCBasicError::SyntaxError {
+                            message: "Expected ) after parameter list".to_string(),
+                            line: line_number,
+                        })
                     }
                 }
             }
-            _ => {
+            Some(Token::Separator(')')) => return Ok((params, i + 1)),
+            Some(_) => {
                 return Err(BBCBasicError::SyntaxError {
                     message: "Expected identifier in parameter list".to_string(),
                     line: line_number,
                 })
             }
+            None => {
+                return Err(BBCBasicError::SyntaxError {
+                    message: "Expected ) after parameter list".to_string(),
+                    line: line_number,
+                })
+            }
         }
     }
-
-    Ok(params)
 }
 
 /// Parse IF statement
@@ -2067,6 +3167,11 @@ fn parse_if_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Stat
     let condition_tokens = &tokens[..then_pos];
     let condition = parse_expression(condition_tokens)?;
 
+    // THEN at the end of the line starts a block IF
+    if then_pos + 1 == tokens.len() {
+        return Ok(Statement::IfBlock { condition });
+    }
+
     // Find ELSE keyword (if present)
     let else_pos = tokens[then_pos + 1..]
         .iter()
@@ -2084,16 +3189,15 @@ fn parse_if_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Stat
         (&tokens[then_pos + 1..], None)
     };
 
-    // Parse THEN part (single statement for now)
+    // Parse THEN part
     let then_part = if then_tokens.is_empty() {
         return Err(BBCBasicError::SyntaxError {
             message: "Expected statement after THEN".to_string(),
             line: line_number,
         });
     } else {
-        // Create a temporary TokenizedLine for parsing
         let then_line = TokenizedLine::new(line_number, then_tokens.to_vec());
-        vec![parse_statement(&then_line)?]
+        parse_statements(&then_line)?
     };
 
     // Parse ELSE part if present
@@ -2105,7 +3209,7 @@ fn parse_if_statement(tokens: &[Token], line_number: Option<u16>) -> Result<Stat
             });
         }
         let else_line = TokenizedLine::new(line_number, else_toks.to_vec());
-        Some(vec![parse_statement(&else_line)?])
+        Some(parse_statements(&else_line)?)
     } else {
         None
     };
@@ -2127,7 +3231,30 @@ pub fn parse_expression(tokens: &[Token]) -> Result<Expression> {
     }
 
     let mut pos = 0;
-    parse_expr_precedence(tokens, &mut pos, 0)
+    let expression = parse_expr_precedence(tokens, &mut pos, 0)?;
+    if pos < tokens.len() {
+        return Err(BBCBasicError::SyntaxError {
+            message: "Unexpected text after expression".to_string(),
+            line: None,
+        });
+    }
+    Ok(expression)
+}
+
+/// Parse tokens that hold one expression and nothing after it
+pub fn parse_whole_expression(tokens: &[Token]) -> Result<Expression> {
+    if tokens.is_empty() {
+        return parse_expression(tokens);
+    }
+    let mut pos = 0;
+    let expression = parse_expr_precedence(tokens, &mut pos, 0)?;
+    if pos < tokens.len() {
+        return Err(BBCBasicError::SyntaxError {
+            message: "Unexpected text after expression".to_string(),
+            line: None,
+        });
+    }
+    Ok(expression)
 }
 
 /// Get operator precedence (higher number = higher precedence)
@@ -2144,10 +3271,10 @@ fn get_precedence(op: char) -> Option<u8> {
 /// Get keyword operator precedence
 fn get_keyword_precedence(keyword_code: u8) -> Option<u8> {
     match keyword_code {
-        0x81 => Some(50), // DIV - same as / (integer division)
-        0x83 => Some(50), // MOD - same as / (modulo)
-        0x80 => Some(20), // AND - lower than comparison
-        0x82 => Some(15), // OR - lower than AND
+        0x81 => Some(50),        // DIV - same as / (integer division)
+        0x83 => Some(50),        // MOD - same as / (modulo)
+        0x80 => Some(20),        // AND - lower than comparison
+        0x82 | 0x84 => Some(15), // EOR and OR - lower than AND
         _ => None,
     }
 }
@@ -2173,7 +3300,8 @@ fn keyword_to_binary_op(keyword_code: u8) -> Option<BinaryOperator> {
         0x81 => Some(BinaryOperator::IntegerDivide), // DIV
         0x83 => Some(BinaryOperator::Modulo),        // MOD
         0x80 => Some(BinaryOperator::And),           // AND
-        0x82 => Some(BinaryOperator::Or),            // OR
+        0x82 => Some(BinaryOperator::Eor),           // EOR
+        0x84 => Some(BinaryOperator::Or),            // OR
         _ => None,
     }
 }
@@ -2182,21 +3310,25 @@ fn keyword_to_binary_op(keyword_code: u8) -> Option<BinaryOperator> {
 fn parse_expr_precedence(tokens: &[Token], pos: &mut usize, min_prec: u8) -> Result<Expression> {
     // Parse the left-hand side (primary expression)
     let mut left = parse_primary(tokens, pos)?;
+    // Each operator puts the expression so far a level further down
+    let mut nesting = Nesting(0);
 
     // Parse binary operators with precedence
     while *pos < tokens.len() {
         // Check if current token is a binary operator (either operator or keyword)
         let (prec, op, consumed) = match &tokens[*pos] {
             Token::Operator(ch) => {
-                // Check for >= and <= (two-character operators)
+                // Check for >=, <=, <>, << and >> (two-character operators)
                 if (*ch == '>' || *ch == '<') && *pos + 1 < tokens.len() {
-                    if let Token::Operator('=') = tokens[*pos + 1] {
-                        // This is >= or <=
-                        let op = if *ch == '>' {
-                            BinaryOperator::GreaterThanOrEqual
-                        } else {
-                            BinaryOperator::LessThanOrEqual
-                        };
+                    let pair = match (*ch, &tokens[*pos + 1]) {
+                        ('>', Token::Operator('=')) => Some(BinaryOperator::GreaterThanOrEqual),
+                        ('<', Token::Operator('=')) => Some(BinaryOperator::LessThanOrEqual),
+                        ('<', Token::Operator('>')) => Some(BinaryOperator::NotEqual),
+                        ('<', Token::Operator('<')) => Some(BinaryOperator::LeftShift),
+                        ('>', Token::Operator('>')) => Some(BinaryOperator::RightShift),
+                        _ => None,
+                    };
+                    if let Some(op) = pair {
                         (30, op, 2) // Consume 2 tokens
                     } else if let Some(p) = get_precedence(*ch) {
                         if let Some(binary_op) = char_to_binary_op(*ch) {
@@ -2237,6 +3369,7 @@ fn parse_expr_precedence(tokens: &[Token], pos: &mut usize, min_prec: u8) -> Res
         }
 
         *pos += consumed; // consume operator(s)
+        nesting.deeper()?;
 
         // Parse right-hand side with higher precedence
         let right = parse_expr_precedence(tokens, pos, prec + 1)?;
@@ -2254,6 +3387,33 @@ fn parse_expr_precedence(tokens: &[Token], pos: &mut usize, min_prec: u8) -> Res
 
 /// Parse a primary expression (literal, variable, function call, or parenthesized expression)
 fn parse_primary(tokens: &[Token], pos: &mut usize) -> Result<Expression> {
+    let _nesting = Nesting::enter()?;
+    let primary = parse_operand(tokens, pos)?;
+    // A variable followed by ? or ! is an address plus an offset (A%!4)
+    if matches!(
+        primary,
+        Expression::Variable(_) | Expression::ArrayAccess { .. }
+    ) {
+        if let Some(op @ (Indirection::Byte | Indirection::Word)) =
+            Indirection::from_token(tokens.get(*pos))
+        {
+            *pos += 1;
+            let offset = parse_operand(tokens, pos)?;
+            return Ok(Expression::Indirect {
+                op,
+                address: Box::new(Expression::BinaryOp {
+                    left: Box::new(primary),
+                    op: BinaryOperator::Add,
+                    right: Box::new(offset),
+                }),
+            });
+        }
+    }
+    Ok(primary)
+}
+
+/// Parse a primary expression without a following ? or ! offset
+fn parse_operand(tokens: &[Token], pos: &mut usize) -> Result<Expression> {
     if *pos >= tokens.len() {
         return Err(BBCBasicError::SyntaxError {
             message: "Unexpected end of expression".to_string(),
@@ -2343,6 +3503,23 @@ fn parse_primary(tokens: &[Token], pos: &mut usize) -> Result<Expression> {
                 operand: Box::new(operand),
             })
         }
+        Token::Keyword(0xAC) => {
+            *pos += 1;
+            let operand = parse_primary(tokens, pos)?;
+            Ok(Expression::UnaryOp {
+                op: UnaryOperator::Not,
+                operand: Box::new(operand),
+            })
+        }
+        Token::Operator('?' | '!' | '$') => {
+            let op = Indirection::from_token(tokens.get(*pos)).expect("indirection operator");
+            *pos += 1;
+            let address = parse_primary(tokens, pos)?;
+            Ok(Expression::Indirect {
+                op,
+                address: Box::new(address),
+            })
+        }
 
         // Parenthesized expressions
         Token::Separator('(') => {
@@ -2360,6 +3537,59 @@ fn parse_primary(tokens: &[Token], pos: &mut usize) -> Result<Expression> {
             Ok(expr)
         }
 
+        // FNname(args) - call a user-defined function
+        Token::Keyword(0xA4) => {
+            *pos += 1;
+            let name = match tokens.get(*pos) {
+                Some(Token::Identifier(name)) => name.clone(),
+                _ => {
+                    return Err(BBCBasicError::SyntaxError {
+                        message: "Expected function name after FN".to_string(),
+                        line: None,
+                    })
+                }
+            };
+            *pos += 1;
+
+            let mut args = Vec::new();
+            if matches!(tokens.get(*pos), Some(Token::Separator('('))) {
+                *pos += 1;
+                loop {
+                    args.push(parse_expr_precedence(tokens, pos, 0)?);
+                    match tokens.get(*pos) {
+                        Some(Token::Separator(',')) => *pos += 1,
+                        Some(Token::Separator(')')) => {
+                            *pos += 1;
+                            break;
+                        }
+                        _ => {
+                            return Err(BBCBasicError::SyntaxError {
+                                message: "Expected ')'".to_string(),
+                                line: None,
+                            })
+                        }
+                    }
+                }
+            }
+            Ok(Expression::FunctionCall { name, args })
+        }
+
+        // TOP is tokenized as TO followed by P, as on the BBC Micro
+        Token::Keyword(0xB8) if matches!(tokens.get(*pos + 1), Some(Token::Identifier(p)) if p == "P") =>
+        {
+            *pos += 2;
+            Ok(Expression::Variable("TOP".to_string()))
+        }
+
+        // REPORT$ is REPORT followed by $, which is not kept as a token
+        Token::Keyword(0xF6) => {
+            *pos += 1;
+            if matches!(tokens.get(*pos), Some(Token::Operator('$'))) {
+                *pos += 1;
+            }
+            Ok(Expression::Variable("REPORT$".to_string()))
+        }
+
         // Keywords (functions and constants)
         Token::Keyword(byte) => {
             let (main_reverse, _) = create_reverse_keyword_maps();
@@ -2370,6 +3600,19 @@ fn parse_primary(tokens: &[Token], pos: &mut usize) -> Result<Expression> {
 
             *pos += 1;
 
+            // File functions take a channel after #: BGET#, EOF#, EXT#,
+            // GET$# and PTR#
+            if matches!(tokens.get(*pos), Some(Token::Operator('#')))
+                && matches!(keyword.as_str(), "BGET" | "EOF" | "EXT" | "GET$" | "PTR")
+            {
+                *pos += 1;
+                let handle = parse_primary(tokens, pos)?;
+                return Ok(Expression::FunctionCall {
+                    name: keyword,
+                    args: vec![handle],
+                });
+            }
+
             // Check if this is a function call (followed by opening paren)
             if *pos < tokens.len() && matches!(tokens[*pos], Token::Separator('(')) {
                 *pos += 1; // consume '('
@@ -2423,49 +3666,620 @@ fn parse_primary(tokens: &[Token], pos: &mut usize) -> Result<Expression> {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_statement_types() {
-        let assignment = Statement::Assignment {
-            target: "A".to_string(),
-            expression: Expression::Integer(42),
-        };
-        assert!(!assignment.is_control_flow());
-        assert!(!assignment.is_terminating());
-
-        let end_stmt = Statement::End;
-        assert!(end_stmt.is_terminating());
-
-        let for_stmt = Statement::For {
-            variable: "I".to_string(),
-            start: Expression::Integer(1),
-            end: Expression::Integer(10),
-            step: None,
-        };
-        assert!(for_stmt.is_control_flow());
-    }
-
-    #[test]
-    fn test_expression_types() {
-        let int_expr = Expression::Integer(42);
-        assert_eq!(int_expr.expression_type(), ExpressionType::Integer);
-
-        let real_expr = Expression::Real(3.14);
-        assert_eq!(real_expr.expression_type(), ExpressionType::Real);
-
-        let string_expr = Expression::String("hello".to_string());
-        assert_eq!(string_expr.expression_type(), ExpressionType::String);
-
-        let int_var = Expression::Variable("A%".to_string());
-        assert_eq!(int_var.expression_type(), ExpressionType::Integer);
-
-        let string_var = Expression::Variable("B$".to_string());
-        assert_eq!(string_var.expression_type(), ExpressionType::String);
+/// Regenerate BBC BASIC source for a statement, the inverse of
+/// [`parse_statement`]
+///
+/// The source is in a canonical style, spaced like a typed listing
+/// (`PRINT "A"; B%, C`, `FOR I% = 1 TO 10 STEP 2`, `DEF FNtwice(N) = N * 2`),
+/// with only the parentheses the expressions need. Tokenizing and parsing it
+/// gives back the same statement.
+pub fn unparse(statement: &Statement) -> String {
+    let list = |expressions: &[Expression]| {
+        expressions
+            .iter()
+            .map(unparse_expression)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let with_args = |name: &str, args: &[Expression]| {
+        if args.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}({})", name, list(args))
+        }
+    };
+    let with_params = |name: &str, params: &[String]| {
+        if params.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}({})", name, params.join(", "))
+        }
+    };
+    let with_optional = |keyword: &str, expression: &Option<Expression>| match expression {
+        Some(expression) => format!("{} {}", keyword, unparse_expression(expression)),
+        None => keyword.to_string(),
+    };
+    let lines = |targets: &[u16]| {
+        targets
+            .iter()
+            .map(u16::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let by = |relative: bool| if relative { "BY " } else { "" };
+    let fill = |filled: bool| if filled { "FILL " } else { "" };
 
-        let real_var = Expression::Variable("C".to_string());
+    match statement {
+        Statement::Assignment { target, expression } => {
+            format!("{} = {}", target, unparse_expression(expression))
+        }
+        Statement::IndirectAssignment {
+            op,
+            address,
+            expression,
+        } => format!(
+            "{} = {}",
+            unparse_indirect(*op, address),
+            unparse_expression(expression)
+        ),
+        Statement::ArrayAssignment {
+            name,
+            indices,
+            expression,
+        } => format!(
+            "{}({}) = {}",
+            name,
+            list(indices),
+            unparse_expression(expression)
+        ),
+        Statement::Print { items } => format!("PRINT{}", unparse_print_items(items)),
+        Statement::Input { variables } => format!("INPUT {}", list(variables)),
+        Statement::For {
+            variable,
+            start,
+            end,
+            step,
+        } => {
+            let mut source = format!(
+                "FOR {} = {} TO {}",
+                unparse_expression(variable),
+                unparse_expression(start),
+                unparse_expression(end)
+            );
+            if let Some(step) = step {
+                source.push_str(&format!(" STEP {}", unparse_expression(step)));
+            }
+            source
+        }
+        Statement::Next { variables } if variables.is_empty() => "NEXT".to_string(),
+        Statement::Next { variables } => format!("NEXT {}", list(variables)),
+        Statement::If {
+            condition,
+            then_part,
+            else_part,
+        } => {
+            let branch = |statements: &[Statement]| {
+                statements
+                    .iter()
+                    .map(unparse)
+                    .collect::<Vec<_>>()
+                    .join(" : ")
+            };
+            let mut source = format!(
+                "IF {} THEN {}",
+                unparse_expression(condition),
+                branch(then_part)
+            );
+            if let Some(else_part) = else_part {
+                source.push_str(&format!(" ELSE {}", branch(else_part)));
+            }
+            source
+        }
+        Statement::IfBlock { condition } => format!("IF {} THEN", unparse_expression(condition)),
+        Statement::Else => "ELSE".to_string(),
+        Statement::EndIf => "ENDIF".to_string(),
+        Statement::Goto { line_number } => format!("GOTO {}", line_number),
+        Statement::Gosub { line_number } => format!("GOSUB {}", line_number),
+        Statement::Return { value } => with_optional("RETURN", value),
+        Statement::Dim { arrays } => format!(
+            "DIM {}",
+            arrays
+                .iter()
+                .map(|(name, dimensions)| format!("{}({})", name, list(dimensions)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Statement::DimSpace { name, size } => {
+            format!("DIM {} {}", name, unparse_expression(size))
+        }
+        Statement::Rem { comment } if comment.is_empty() => "REM".to_string(),
+        Statement::Rem { comment } => format!("REM {}", comment),
+        Statement::StarCommand { command } => format!("*{}", command),
+        Statement::End => "END".to_string(),
+        Statement::Stop => "STOP".to_string(),
+        Statement::Quit => "QUIT".to_string(),
+        Statement::ProcCall { name, args } => with_args(&format!("PROC{}", name), args),
+        Statement::DefProc { name, params } => {
+            format!("DEF {}", with_params(&format!("PROC{}", name), params))
+        }
+        Statement::DefFn {
+            name,
+            params,
+            expression: Some(expression),
+        } => format!(
+            "DEF {} = {}",
+            with_params(&format!("FN{}", name), params),
+            unparse_expression(expression)
+        ),
+        Statement::DefFn {
+            name,
+            params,
+            expression: None,
+        } => format!("DEF {}", with_params(&format!("FN{}", name), params)),
+        Statement::EndProc => "ENDPROC".to_string(),
+        Statement::Local { variables } => format!("LOCAL {}", variables.join(", ")),
+        Statement::LocalData => "LOCAL DATA".to_string(),
+        Statement::LocalError => "LOCAL ERROR".to_string(),
+        Statement::Data { values } if values.is_empty() => "DATA".to_string(),
+        Statement::Data { values } => format!(
+            "DATA {}",
+            values
+                .iter()
+                .map(unparse_data_value)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Statement::Read { variables } => format!("READ {}", list(variables)),
+        Statement::Restore { line_number: None } => "RESTORE".to_string(),
+        Statement::Restore {
+            line_number: Some(line_number),
+        } => format!("RESTORE {}", line_number),
+        Statement::RestoreLine { line } => format!("RESTORE {}", unparse_expression(line)),
+        Statement::RestoreRelative { offset } => {
+            format!("RESTORE +{}", unparse_operand(offset))
+        }
+        Statement::RestoreData => "RESTORE DATA".to_string(),
+        Statement::RestoreError => "RESTORE ERROR".to_string(),
+        Statement::Repeat => "REPEAT".to_string(),
+        Statement::Until { condition } => format!("UNTIL {}", unparse_expression(condition)),
+        Statement::While { condition } => format!("WHILE {}", unparse_expression(condition)),
+        Statement::EndWhile => "ENDWHILE".to_string(),
+        Statement::Cls => "CLS".to_string(),
+        Statement::Clear => "CLEAR".to_string(),
+        Statement::Report => "REPORT".to_string(),
+        Statement::Vdu { items } => {
+            let items: Vec<String> = items
+                .iter()
+                .enumerate()
+                .map(|(index, item)| match item {
+                    VduItem::Word(value) => format!("{};", unparse_expression(value)),
+                    VduItem::Byte(value) if index + 1 < items.len() => {
+                        format!("{},", unparse_expression(value))
+                    }
+                    VduItem::Byte(value) => unparse_expression(value),
+                })
+                .collect();
+            format!("VDU {}", items.join(" "))
+        }
+        Statement::OnGoto {
+            expression,
+            targets,
+        } => format!(
+            "ON {} GOTO {}",
+            unparse_expression(expression),
+            lines(targets)
+        ),
+        Statement::OnGosub {
+            expression,
+            targets,
+        } => format!(
+            "ON {} GOSUB {}",
+            unparse_expression(expression),
+            lines(targets)
+        ),
+        Statement::OnProc { expression, calls } => {
+            let calls: Vec<String> = calls
+                .iter()
+                .map(|(name, args)| with_args(&format!("PROC{}", name), args))
+                .collect();
+            format!("ON {} {}", unparse_expression(expression), calls.join(", "))
+        }
+        Statement::OnError { line_number } => format!("ON ERROR GOTO {}", line_number),
+        Statement::OnErrorOff => "ON ERROR OFF".to_string(),
+        Statement::PrintFile { handle, items } => format!(
+            "PRINT#{},{}",
+            unparse_expression(handle),
+            unparse_print_items(items)
+        ),
+        Statement::InputFile { handle, variables } => {
+            format!("INPUT#{}, {}", unparse_expression(handle), list(variables))
+        }
+        Statement::CloseFile { handle } => format!("CLOSE#{}", unparse_expression(handle)),
+        Statement::Output { handle: None } => "OUTPUT".to_string(),
+        Statement::Output {
+            handle: Some(handle),
+        } => format!("OUTPUT #{}", unparse_expression(handle)),
+        Statement::BputFile {
+            handle,
+            value,
+            newline,
+        } => format!(
+            "BPUT#{}, {}{}",
+            unparse_expression(handle),
+            unparse_expression(value),
+            if *newline { "" } else { ";" }
+        ),
+        Statement::Library {
+            filename,
+            permanent,
+        } => format!(
+            "{} {}",
+            if *permanent { "INSTALL" } else { "LIBRARY" },
+            unparse_expression(filename)
+        ),
+        Statement::Chain { filename } => format!("CHAIN {}", unparse_expression(filename)),
+        Statement::Call { address } => format!("CALL {}", unparse_expression(address)),
+        Statement::PtrFile { handle, value } => format!(
+            "PTR#{} = {}",
+            unparse_operand(handle),
+            unparse_expression(value)
+        ),
+        Statement::ExtFile { handle, value } => format!(
+            "EXT#{} = {}",
+            unparse_operand(handle),
+            unparse_expression(value)
+        ),
+        Statement::Plot { mode, x, y } => {
+            format!("PLOT {}", list(&[mode.clone(), x.clone(), y.clone()]))
+        }
+        Statement::Move { x, y, relative } => {
+            format!("MOVE {}{}", by(*relative), list(&[x.clone(), y.clone()]))
+        }
+        Statement::Draw { x, y, relative } => {
+            format!("DRAW {}{}", by(*relative), list(&[x.clone(), y.clone()]))
+        }
+        Statement::Circle {
+            x,
+            y,
+            radius,
+            filled,
+        } => format!(
+            "CIRCLE {}{}",
+            fill(*filled),
+            list(&[x.clone(), y.clone(), radius.clone()])
+        ),
+        Statement::Gcol { mode, color } => {
+            format!("GCOL {}", list(&[mode.clone(), color.clone()]))
+        }
+        Statement::Colour { colour } => format!("COLOUR {}", unparse_expression(colour)),
+        Statement::Clg => "CLG".to_string(),
+        Statement::Mode { mode } => format!("MODE {}", unparse_expression(mode)),
+        Statement::Ellipse {
+            x,
+            y,
+            major,
+            minor,
+            filled,
+        } => format!(
+            "ELLIPSE {}{}",
+            fill(*filled),
+            list(&[x.clone(), y.clone(), major.clone(), minor.clone()])
+        ),
+        Statement::Rectangle {
+            x1,
+            y1,
+            width,
+            height,
+            filled,
+            target,
+        } => {
+            let mut source = format!(
+                "RECTANGLE {}{}",
+                fill(*filled),
+                list(&[x1.clone(), y1.clone(), width.clone(), height.clone()])
+            );
+            if let Some((x, y)) = target {
+                source.push_str(&format!(" TO {}", list(&[x.clone(), y.clone()])));
+            }
+            source
+        }
+        Statement::Fill { x, y } => format!("FILL {}", list(&[x.clone(), y.clone()])),
+        Statement::Origin { x, y } => format!("ORIGIN {}", list(&[x.clone(), y.clone()])),
+        Statement::Sound {
+            channel,
+            amplitude,
+            pitch,
+            duration,
+        } => format!(
+            "SOUND {}",
+            list(&[
+                channel.clone(),
+                amplitude.clone(),
+                pitch.clone(),
+                duration.clone()
+            ])
+        ),
+        Statement::Envelope { params } => format!("ENVELOPE {}", list(params)),
+        Statement::Wait { centiseconds } => with_optional("WAIT", centiseconds),
+        Statement::Mouse { x, y, buttons } => format!(
+            "MOUSE {}, {}, {}",
+            unparse_expression(x),
+            unparse_expression(y),
+            unparse_expression(buttons)
+        ),
+        Statement::MousePointer { visible } => {
+            format!("MOUSE {}", if *visible { "ON" } else { "OFF" })
+        }
+        Statement::Const { name, expression } => {
+            format!("CONST {} = {}", name, unparse_expression(expression))
+        }
+        Statement::Assert { condition, message } => {
+            let mut source = format!("ASSERT {}", unparse_expression(condition));
+            if let Some(message) = message {
+                source.push_str(&format!(", {}", unparse_expression(message)));
+            }
+            source
+        }
+        Statement::Forward { distance } => format!("FORWARD {}", unparse_expression(distance)),
+        Statement::Turn { degrees } => format!("TURN {}", unparse_expression(degrees)),
+        Statement::PenUp => "PENUP".to_string(),
+        Statement::PenDown { colour: None } => "PENDOWN".to_string(),
+        Statement::PenDown {
+            colour: Some(colour),
+        } => format!("PENDOWN {}", unparse_expression(colour)),
+        Statement::Empty => String::new(),
+    }
+}
+
+/// Regenerate BBC BASIC source for an expression, with only the parentheses
+/// its operators' precedence needs
+pub fn unparse_expression(expression: &Expression) -> String {
+    match expression {
+        Expression::Integer(value) => value.to_string(),
+        // A whole number keeps its point, so it reads back as a real
+        Expression::Real(value) if value.fract() == 0.0 && value.is_finite() => {
+            format!("{:.1}", value)
+        }
+        Expression::Real(value) => value.to_string(),
+        Expression::String(text) => format!("\"{}\"", text.replace('"', "\"\"")),
+        Expression::Variable(name) => name.clone(),
+        Expression::ArrayAccess { name, indices } => format!(
+            "{}({})",
+            name,
+            indices
+                .iter()
+                .map(unparse_expression)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Expression::FunctionCall { name, args } => {
+            let args_text = args
+                .iter()
+                .map(unparse_expression)
+                .collect::<Vec<_>>()
+                .join(", ");
+            let (keywords, _) = create_keyword_maps();
+            if !keywords.contains_key(name) {
+                // A user-defined function
+                if args.is_empty() {
+                    format!("FN{}", name)
+                } else {
+                    format!("FN{}({})", name, args_text)
+                }
+            } else if let [handle] = &args[..] {
+                match name.as_str() {
+                    "BGET" | "EOF" | "EXT" | "GET$" | "PTR" => {
+                        format!("{}#{}", name, unparse_operand(handle))
+                    }
+                    _ => format!("{}({})", name, args_text),
+                }
+            } else {
+                format!("{}({})", name, args_text)
+            }
+        }
+        Expression::BinaryOp { left, op, right } => {
+            let precedence = binary_precedence(op);
+            let side = |operand: &Expression, parenthesize: bool| {
+                if parenthesize {
+                    format!("({})", unparse_expression(operand))
+                } else {
+                    unparse_expression(operand)
+                }
+            };
+            // Operators group to the left, so a right operand of the same
+            // precedence needs parentheses and a left one does not
+            format!(
+                "{} {} {}",
+                side(left, expression_precedence(left) < precedence),
+                binary_operator_text(op),
+                side(right, expression_precedence(right) <= precedence)
+            )
+        }
+        Expression::UnaryOp { op, operand } => {
+            let op = match op {
+                UnaryOperator::Plus => "+",
+                UnaryOperator::Minus => "-",
+                UnaryOperator::Not => "NOT ",
+            };
+            format!("{}{}", op, unparse_operand(operand))
+        }
+        Expression::Indirect { op, address } => unparse_indirect(*op, address),
+    }
+}
+
+/// Source for indirection, as `A%!4` when the address is a variable plus an
+/// offset
+fn unparse_indirect(op: Indirection, address: &Expression) -> String {
+    match address {
+        Expression::BinaryOp {
+            left,
+            op: BinaryOperator::Add,
+            right,
+        } if op != Indirection::String
+            && matches!(
+                **left,
+                Expression::Variable(_) | Expression::ArrayAccess { .. }
+            ) =>
+        {
+            format!(
+                "{}{}{}",
+                unparse_expression(left),
+                op.symbol(),
+                unparse_operand(right)
+            )
+        }
+        _ => format!("{}{}", op.symbol(), unparse_operand(address)),
+    }
+}
+
+/// Source for an expression where only a primary is parsed (after a unary
+/// operator or #), in parentheses if it is a binary operation
+fn unparse_operand(expression: &Expression) -> String {
+    match expression {
+        Expression::BinaryOp { .. } => format!("({})", unparse_expression(expression)),
+        _ => unparse_expression(expression),
+    }
+}
+
+/// PRINT items as source, each expression after a space and each separator
+/// against what it follows
+fn unparse_print_items(items: &[PrintItem]) -> String {
+    let mut source = String::new();
+    for item in items {
+        match item {
+            PrintItem::Expression(expression) => {
+                source.push(' ');
+                source.push_str(&unparse_expression(expression));
+            }
+            PrintItem::Hex(expression) => {
+                source.push_str(" ~");
+                source.push_str(&unparse_expression(expression));
+            }
+            PrintItem::Tab(expression) => {
+                source.push_str(&format!(" TAB({})", unparse_expression(expression)))
+            }
+            PrintItem::Spc(expression) => {
+                source.push_str(&format!(" SPC({})", unparse_expression(expression)))
+            }
+            PrintItem::Semicolon => source.push(';'),
+            PrintItem::Comma => source.push(','),
+            PrintItem::Apostrophe => source.push('\''),
+        }
+    }
+    source
+}
+
+/// A DATA item as source, quoted if it would not read back as it is
+fn unparse_data_value(value: &DataValue) -> String {
+    match value {
+        DataValue::Integer(value) => value.to_string(),
+        DataValue::Real(value) => value.to_string(),
+        DataValue::String(text) if text.contains([',', '"']) || text.starts_with(' ') => {
+            format!("\"{}\"", text.replace('"', "\"\""))
+        }
+        DataValue::String(text) => text.clone(),
+    }
+}
+
+/// Precedence of a binary operator, as the expression parser uses it
+fn binary_precedence(op: &BinaryOperator) -> u8 {
+    match op {
+        BinaryOperator::Power => 60,
+        BinaryOperator::Multiply
+        | BinaryOperator::Divide
+        | BinaryOperator::IntegerDivide
+        | BinaryOperator::Modulo => 50,
+        BinaryOperator::Add | BinaryOperator::Subtract | BinaryOperator::StringConcat => 40,
+        BinaryOperator::Equal
+        | BinaryOperator::NotEqual
+        | BinaryOperator::LessThan
+        | BinaryOperator::LessThanOrEqual
+        | BinaryOperator::GreaterThan
+        | BinaryOperator::GreaterThanOrEqual
+        | BinaryOperator::LeftShift
+        | BinaryOperator::RightShift => 30,
+        BinaryOperator::And => 20,
+        BinaryOperator::Or | BinaryOperator::Eor => 15,
+    }
+}
+
+/// Precedence of an expression as an operand: anything but a binary
+/// operation binds tighter than every operator
+fn expression_precedence(expression: &Expression) -> u8 {
+    match expression {
+        Expression::BinaryOp { op, .. } => binary_precedence(op),
+        _ => u8::MAX,
+    }
+}
+
+/// Source text of a binary operator
+fn binary_operator_text(op: &BinaryOperator) -> &'static str {
+    match op {
+        BinaryOperator::Add | BinaryOperator::StringConcat => "+",
+        BinaryOperator::Subtract => "-",
+        BinaryOperator::Multiply => "*",
+        BinaryOperator::Divide => "/",
+        BinaryOperator::IntegerDivide => "DIV",
+        BinaryOperator::Modulo => "MOD",
+        BinaryOperator::Power => "^",
+        BinaryOperator::Equal => "=",
+        BinaryOperator::NotEqual => "<>",
+        BinaryOperator::LessThan => "<",
+        BinaryOperator::LessThanOrEqual => "<=",
+        BinaryOperator::GreaterThan => ">",
+        BinaryOperator::GreaterThanOrEqual => ">=",
+        BinaryOperator::And => "AND",
+        BinaryOperator::Or => "OR",
+        BinaryOperator::Eor => "EOR",
+        BinaryOperator::LeftShift => "<<",
+        BinaryOperator::RightShift => ">>",
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::approx_constant)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_statement_types() {
+        let assignment = Statement::Assignment {
+            target: "A".to_string(),
+            expression: Expression::Integer(42),
+        };
+        assert!(!assignment.is_control_flow());
+        assert!(!assignment.is_terminating());
+
+        let end_stmt = Statement::End;
+        assert!(end_stmt.is_terminating());
+
+        let for_stmt = Statement::For {
+            variable: Expression::Variable("I".to_string()),
+            start: Expression::Integer(1),
+            end: Expression::Integer(10),
+            step: None,
+        };
+        assert!(for_stmt.is_control_flow());
+    }
+
+    #[test]
+    fn test_expression_types() {
+        let int_expr = Expression::Integer(42);
+        assert_eq!(int_expr.expression_type(), ExpressionType::Integer);
+
+        let real_expr = Expression::Real(3.14);
+        assert_eq!(real_expr.expression_type(), ExpressionType::Real);
+
+        let string_expr = Expression::String("hello".to_string());
+        assert_eq!(string_expr.expression_type(), ExpressionType::String);
+
+        let int_var = Expression::Variable("A%".to_string());
+        assert_eq!(int_var.expression_type(), ExpressionType::Integer);
+
+        let string_var = Expression::Variable("B$".to_string());
+        assert_eq!(string_var.expression_type(), ExpressionType::String);
+
+        let real_var = Expression::Variable("C".to_string());
         assert_eq!(real_var.expression_type(), ExpressionType::Real);
     }
 
@@ -2728,7 +4542,7 @@ mod tests {
         assert_eq!(
             stmt,
             Statement::For {
-                variable: "I%".to_string(),
+                variable: Expression::Variable("I%".to_string()),
                 start: Expression::Integer(1),
                 end: Expression::Integer(10),
                 step: None,
@@ -2746,7 +4560,7 @@ mod tests {
         assert_eq!(
             stmt,
             Statement::For {
-                variable: "I%".to_string(),
+                variable: Expression::Variable("I%".to_string()),
                 start: Expression::Integer(10),
                 end: Expression::Integer(1),
                 step: Some(Expression::Integer(-1)),
@@ -2764,7 +4578,33 @@ mod tests {
         assert_eq!(
             stmt,
             Statement::Next {
-                variables: vec!["I%".to_string()],
+                variables: vec![Expression::Variable("I%".to_string())],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_for_array_element() {
+        use crate::tokenizer::tokenize;
+        let element = Expression::ArrayAccess {
+            name: "A".to_string(),
+            indices: vec![Expression::Integer(2)],
+        };
+        let stmt = parse_statement(&tokenize("FOR A(2) = 0.5 TO 10 STEP 0.5").unwrap()).unwrap();
+        assert_eq!(
+            stmt,
+            Statement::For {
+                variable: element.clone(),
+                start: Expression::Real(0.5),
+                end: Expression::Integer(10),
+                step: Some(Expression::Real(0.5)),
+            }
+        );
+        let stmt = parse_statement(&tokenize("NEXT A(2)").unwrap()).unwrap();
+        assert_eq!(
+            stmt,
+            Statement::Next {
+                variables: vec![element],
             }
         );
     }
@@ -2858,6 +4698,50 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_on_choice() {
+        use crate::tokenizer::tokenize;
+        let parse = |text| parse_statement(&tokenize(text).unwrap()).unwrap();
+        let on_gosub = parse("ON Y% GOSUB 1000, 2000");
+        assert_eq!(
+            on_gosub.on_choice(2),
+            Statement::Gosub { line_number: 2000 }
+        );
+        assert_eq!(on_gosub.on_choice(0), Statement::Empty);
+        assert_eq!(on_gosub.on_choice(3), Statement::Empty);
+        assert_eq!(
+            parse("ON X GOTO 100").on_choice(1),
+            Statement::Goto { line_number: 100 }
+        );
+        assert_eq!(
+            parse("ON X PROCa, PROCb(1)").on_choice(2),
+            parse("PROCb(1)")
+        );
+    }
+
+    #[test]
+    fn test_parse_on_proc() {
+        use crate::tokenizer::tokenize;
+        let line = tokenize("ON N% PROCa, PROCb(1, (2)), PROCc").unwrap();
+        match parse_statement(&line).unwrap() {
+            Statement::OnProc { expression, calls } => {
+                assert_eq!(expression, Expression::Variable("N%".to_string()));
+                let names: Vec<&str> = calls.iter().map(|(name, _)| name.as_str()).collect();
+                assert_eq!(names, ["a", "b", "c"]);
+                assert_eq!(calls[1].1.len(), 2);
+            }
+            other => panic!("Expected OnProc statement, got {:?}", other),
+        }
+
+        for source in ["ON N% PROCa PROCb", "ON N% PROCa, GOTO 10", "ON N% PROC"] {
+            assert!(
+                parse_statement(&tokenize(source).unwrap()).is_err(),
+                "{}",
+                source
+            );
+        }
+    }
+
     #[test]
     fn test_parse_input() {
         // RED: Parse "INPUT A%, B$"
@@ -2868,9 +4752,53 @@ mod tests {
         assert_eq!(
             stmt,
             Statement::Input {
-                variables: vec!["A%".to_string(), "B$".to_string()],
+                variables: vec![
+                    Expression::Variable("A%".to_string()),
+                    Expression::Variable("B$".to_string())
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_read_and_input_array_elements() {
+        use crate::tokenizer::tokenize;
+        let element = |name: &str, indices: Vec<Expression>| Expression::ArrayAccess {
+            name: name.to_string(),
+            indices,
+        };
+
+        let stmt = parse_statement(&tokenize("READ I%, A%(I%), B(I%, 2), C$").unwrap()).unwrap();
+        assert_eq!(
+            stmt,
+            Statement::Read {
+                variables: vec![
+                    Expression::Variable("I%".to_string()),
+                    element("A%", vec![Expression::Variable("I%".to_string())]),
+                    element(
+                        "B",
+                        vec![
+                            Expression::Variable("I%".to_string()),
+                            Expression::Integer(2)
+                        ]
+                    ),
+                    Expression::Variable("C$".to_string()),
+                ],
+            }
+        );
+
+        let stmt = parse_statement(&tokenize("INPUT N$(3), X").unwrap()).unwrap();
+        assert_eq!(
+            stmt,
+            Statement::Input {
+                variables: vec![
+                    element("N$", vec![Expression::Integer(3)]),
+                    Expression::Variable("X".to_string()),
+                ],
             }
         );
+
+        assert!(parse_statement(&tokenize("READ A%(1").unwrap()).is_err());
     }
 
     #[test]
@@ -2894,6 +4822,110 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_local() {
+        use crate::tokenizer::tokenize;
+        let line = tokenize("LOCAL X, A%(), N$").unwrap();
+        assert_eq!(
+            parse_statement(&line).unwrap(),
+            Statement::Local {
+                variables: vec!["X".to_string(), "A%()".to_string(), "N$".to_string()],
+            }
+        );
+
+        let line = tokenize("RESTORE DATA").unwrap();
+        assert_eq!(parse_statement(&line).unwrap(), Statement::RestoreData);
+        let line = tokenize("RESTORE +2").unwrap();
+        assert_eq!(
+            parse_statement(&line).unwrap(),
+            Statement::RestoreRelative {
+                offset: Expression::Integer(2),
+            }
+        );
+        let line = tokenize("RESTORE L%").unwrap();
+        assert_eq!(
+            parse_statement(&line).unwrap(),
+            Statement::RestoreLine {
+                line: Expression::Variable("L%".to_string()),
+            }
+        );
+
+        let line = tokenize("BPUT#F%, A$;").unwrap();
+        assert_eq!(
+            parse_statement(&line).unwrap(),
+            Statement::BputFile {
+                handle: Expression::Variable("F%".to_string()),
+                value: Expression::Variable("A$".to_string()),
+                newline: false,
+            }
+        );
+
+        let line = tokenize("LOCAL DATA").unwrap();
+        assert_eq!(parse_statement(&line).unwrap(), Statement::LocalData);
+        let line = tokenize("LOCAL ERROR").unwrap();
+        assert_eq!(parse_statement(&line).unwrap(), Statement::LocalError);
+        let line = tokenize("RESTORE ERROR").unwrap();
+        assert_eq!(parse_statement(&line).unwrap(), Statement::RestoreError);
+    }
+
+    #[test]
+    fn test_parse_data() {
+        use crate::tokenizer::tokenize;
+        let strings = |items: &[&str]| Statement::Data {
+            values: items
+                .iter()
+                .map(|item| DataValue::String(item.to_string()))
+                .collect(),
+        };
+        let line = tokenize("DATA 12, -3.5E2,Fred Bloggs , \"a, \"\"b\"\"\" ,,").unwrap();
+        assert_eq!(
+            parse_statement(&line).unwrap(),
+            strings(&["12", "-3.5E2", "Fred Bloggs ", "a, \"b\"", "", ""])
+        );
+        let line = tokenize("DATA").unwrap();
+        assert_eq!(parse_statement(&line).unwrap(), strings(&[""]));
+    }
+
+    #[test]
+    fn test_split_items() {
+        assert_eq!(split_items("  12, ab c ,"), vec!["12", "ab c ", ""]);
+        assert_eq!(
+            split_items(r#" "SMITH, J" x,"say ""hi""""#),
+            vec!["SMITH, J", r#"say "hi""#]
+        );
+        assert_eq!(split_items(""), vec![""]);
+    }
+
+    #[test]
+    fn test_parse_array_parameters() {
+        use crate::tokenizer::tokenize;
+        let line = tokenize("DEF PROCsort(A%(), N%)").unwrap();
+        assert_eq!(
+            parse_statement(&line).unwrap(),
+            Statement::DefProc {
+                name: "sort".to_string(),
+                params: vec!["A%()".to_string(), "N%".to_string()],
+            }
+        );
+
+        let line = tokenize("DEF FNfirst(N$()) = N$(0)").unwrap();
+        match parse_statement(&line).unwrap() {
+            Statement::DefFn { params, .. } => assert_eq!(params, vec!["N$()".to_string()]),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // With nothing after the parameters, the body follows
+        let line = tokenize("DEF FNsum(A%(), N%)").unwrap();
+        assert_eq!(
+            parse_statement(&line).unwrap(),
+            Statement::DefFn {
+                name: "sum".to_string(),
+                params: vec!["A%()".to_string(), "N%".to_string()],
+                expression: None,
+            }
+        );
+    }
+
     #[test]
     fn test_parse_end() {
         // RED: Parse "END"
@@ -3066,8 +5098,8 @@ mod tests {
             Statement::InputFile { handle, variables } => {
                 assert!(matches!(handle, Expression::Variable(_)));
                 assert_eq!(variables.len(), 2);
-                assert_eq!(variables[0], "A%");
-                assert_eq!(variables[1], "B$");
+                assert_eq!(variables[0], Expression::Variable("A%".to_string()));
+                assert_eq!(variables[1], Expression::Variable("B$".to_string()));
             }
             _ => panic!("Expected InputFile statement, got {:?}", stmt),
         }
@@ -3095,6 +5127,29 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_ptr_and_ext_assignment() {
+        use crate::tokenizer::tokenize;
+        let stmt = parse_statement(&tokenize("PTR#F% = EXT#F% + 10").unwrap()).unwrap();
+        match stmt {
+            Statement::PtrFile { handle, value } => {
+                assert!(matches!(handle, Expression::Variable(_)));
+                assert!(matches!(value, Expression::BinaryOp { .. }));
+            }
+            _ => panic!("Expected PtrFile statement, got {:?}", stmt),
+        }
+
+        let stmt = parse_statement(&tokenize("EXT#F%=0").unwrap()).unwrap();
+        assert!(matches!(
+            stmt,
+            Statement::ExtFile {
+                value: Expression::Integer(0),
+                ..
+            }
+        ));
+        assert!(parse_statement(&tokenize("EXT#F%").unwrap()).is_err());
+    }
+        
     #[test]
     fn test_parse_openin_function() {
         // Test: F% = OPENIN("test.txt")
@@ -3102,7 +5157,7 @@ mod tests {
         let line = tokenize("F% = OPENIN(\"test.txt\")").unwrap();
         
         let stmt = parse_statement(&line).unwrap();
-        
+
         match stmt {
             Statement::Assignment { target, expression } => {
                 assert_eq!(target, "F%");
@@ -3118,7 +5173,7 @@ mod tests {
             _ => panic!("Expected Assignment statement, got {:?}", stmt),
         }
     }
-
+        
     #[test]
     fn test_parse_openout_function() {
         // Test: F% = OPENOUT("output.txt")
@@ -3126,7 +5181,7 @@ mod tests {
         let line = tokenize("F% = OPENOUT(\"output.txt\")").unwrap();
         
         let stmt = parse_statement(&line).unwrap();
-        
+
         match stmt {
             Statement::Assignment { target, expression } => {
                 assert_eq!(target, "F%");
@@ -3150,4 +5205,474 @@ mod tests {
         let stmt = parse_statement(&line).unwrap();
         assert_eq!(stmt, Statement::Quit);
     }
+
+    #[test]
+    fn test_parse_sound_and_envelope() {
+        use crate::tokenizer::tokenize;
+        let line = tokenize("SOUND 1, -15, 53, 20").unwrap();
+        match parse_statement(&line).unwrap() {
+            Statement::Sound { channel, pitch, .. } => {
+                assert_eq!(channel, Expression::Integer(1));
+                assert_eq!(pitch, Expression::Integer(53));
+            }
+            stmt => panic!("Expected Sound statement, got {:?}", stmt),
+        }
+
+        let line = tokenize("ENVELOPE 1,1,0,0,0,0,0,0,126,-4,0,-10,126,100").unwrap();
+        match parse_statement(&line).unwrap() {
+            Statement::Envelope { params } => assert_eq!(params.len(), 14),
+            stmt => panic!("Expected Envelope statement, got {:?}", stmt),
+        }
+
+        let line = tokenize("SOUND 1, -15, 53").unwrap();
+        assert!(parse_statement(&line).is_err());
+    }
+
+    #[test]
+    fn test_parse_vdu() {
+        use crate::tokenizer::tokenize;
+        let line = tokenize("VDU 23, 1, 0; 0; CHR$(65) |").unwrap();
+        match parse_statement(&line).unwrap() {
+            Statement::Vdu { items } => {
+                assert_eq!(items.len(), 14);
+                assert_eq!(items[0], VduItem::Byte(Expression::Integer(23)));
+                assert_eq!(items[2], VduItem::Word(Expression::Integer(0)));
+                assert!(matches!(
+                    items[4],
+                    VduItem::Byte(Expression::FunctionCall { .. })
+                ));
+                assert_eq!(items[13], VduItem::Byte(Expression::Integer(0)));
+            }
+            stmt => panic!("Expected Vdu statement, got {:?}", stmt),
+        }
+
+        let line = tokenize("VDU").unwrap();
+        assert!(parse_statement(&line).is_err());
+    }
+
+    #[test]
+    fn test_keyword_that_cannot_start_a_statement() {
+        use crate::tokenizer::tokenize;
+        // TOTAL tokenizes to TO and TAL, as on the Model B
+        let line = tokenize("TOTAL=6").unwrap();
+        assert_eq!(parse_statement(&line), Err(BBCBasicError::Mistake));
+        assert_eq!(BBCBasicError::Mistake.to_string(), "Mistake");
+    }
+
+    #[test]
+    fn test_dialect_gating() {
+        use crate::tokenizer::tokenize;
+        let line = tokenize("WHILE X% < 10").unwrap();
+        assert!(matches!(
+            parse_statement_with_dialect(&line, Dialect::BasicV).unwrap(),
+            Statement::While { .. }
+        ));
+        assert_eq!(
+            parse_statement_with_dialect(&line, Dialect::BasicII),
+            Err(BBCBasicError::Mistake)
+        );
+
+        let line = tokenize("ENDWHILE").unwrap();
+        assert_eq!(
+            parse_statement_with_dialect(&line, Dialect::BasicII),
+            Err(BBCBasicError::Mistake)
+        );
+
+        // Extended keywords inside a statement are unknown names in BASIC II
+        let line = tokenize("X = SUM").unwrap();
+        assert_eq!(
+            parse_statement_with_dialect(&line, Dialect::BasicII),
+            Err(BBCBasicError::NoSuchVariable("SUM".to_string()))
+        );
+
+        // BASIC II statements parse the same in both dialects
+        let line = tokenize("PRINT 42").unwrap();
+        assert_eq!(
+            parse_statement_with_dialect(&line, Dialect::BasicII),
+            parse_statement(&line)
+        );
+    }
+
+    #[test]
+    fn test_parse_block_if() {
+        use crate::tokenizer::tokenize;
+        let line = tokenize("IF X% > 1 THEN").unwrap();
+        assert!(matches!(
+            parse_statement(&line).unwrap(),
+            Statement::IfBlock { .. }
+        ));
+        assert_eq!(
+            parse_statement(&tokenize("ELSE").unwrap()).unwrap(),
+            Statement::Else
+        );
+        assert_eq!(
+            parse_statement(&tokenize("ENDIF").unwrap()).unwrap(),
+            Statement::EndIf
+        );
+
+        // BASIC II ignores an empty THEN, and has no ENDIF
+        assert!(matches!(
+            parse_statement_with_dialect(&line, Dialect::BasicII).unwrap(),
+            Statement::If { ref then_part, .. } if then_part.is_empty()
+        ));
+        assert_eq!(
+            parse_statement_with_dialect(&tokenize("ENDIF").unwrap(), Dialect::BasicII),
+            Err(BBCBasicError::Mistake)
+        );
+    }
+
+    #[test]
+    fn test_parse_statements() {
+        use crate::tokenizer::tokenize;
+        let kinds = |text: &str| -> Vec<String> {
+            run_order(parse_statements(&tokenize(text).unwrap()).unwrap())
+                .iter()
+                .map(|statement| format!("{:?}", statement))
+                .map(|shown| shown.split([' ', '{']).next().unwrap().to_string())
+                .collect()
+        };
+
+        // Colons separate statements, except in REM and DATA
+        assert_eq!(
+            kinds("A% = 1: PRINT A%:: REM a: b"),
+            ["Assignment", "Print", "Rem"]
+        );
+        assert_eq!(kinds("READ A$: DATA x:y"), ["Read", "Data"]);
+        assert_eq!(kinds(""), ["Empty"]);
+
+        // A one-line IF takes the rest of the line, its parts following it
+        let line = tokenize("IF A% THEN B% = 1: GOSUB 10 ELSE B% = 2: C% = 3").unwrap();
+        let statements = parse_statements(&line).unwrap();
+        assert!(matches!(
+            &statements[..],
+            [Statement::If { then_part, else_part: Some(else_part), .. }]
+                if then_part.len() == 2 && else_part.len() == 2
+        ));
+        assert_eq!(statements[0].else_index(0), Some(4));
+        assert_eq!(
+            kinds("IF A% THEN B% = 1: GOSUB 10 ELSE B% = 2: C% = 3"),
+            [
+                "If",
+                "Assignment",
+                "Gosub",
+                "Else",
+                "Assignment",
+                "Assignment"
+            ]
+        );
+        assert_eq!(
+            kinds("PRINT: IF A% THEN IF B% THEN PRINT ELSE END"),
+            ["Print", "If", "If", "Print", "Else", "End"]
+        );
+
+        // THEN at the end of a statement still starts a block IF
+        assert_eq!(kinds("A% = 1: IF A% THEN"), ["Assignment", "IfBlock"]);
+
+        // BASIC II skips the rest of the line at ELSE
+        let line = tokenize("PRINT: ELSE: PRINT").unwrap();
+        assert_eq!(
+            parse_statements_with_dialect(&line, Dialect::BasicII)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[cfg(feature = "turtle")]
+    #[test]
+    fn test_parse_turtle() {
+        use crate::tokenizer::tokenize;
+        let parse = |source: &str| parse_statement(&tokenize(source).unwrap());
+        assert_eq!(
+            parse("FORWARD 100").unwrap(),
+            Statement::Forward {
+                distance: Expression::Integer(100)
+            }
+        );
+        assert_eq!(
+            parse("PENDOWN").unwrap(),
+            Statement::PenDown { colour: None }
+        );
+        assert!(parse("FORWARD").is_err());
+        assert!(parse("PENUP 1").is_err());
+        for source in ["FORWARD L% * 2", "TURN -90", "PENUP", "PENDOWN 2"] {
+            assert_eq!(unparse(&parse(source).unwrap()), source);
+        }
+    }
+
+    #[test]
+    fn test_parse_mouse() {
+        use crate::tokenizer::tokenize;
+        let parse = |source: &str| parse_statement(&tokenize(source).unwrap());
+        assert_eq!(
+            parse("MOUSE X%, Y%, B%").unwrap(),
+            Statement::Mouse {
+                x: Expression::Variable("X%".to_string()),
+                y: Expression::Variable("Y%".to_string()),
+                buttons: Expression::Variable("B%".to_string()),
+            }
+        );
+        assert_eq!(
+            parse("MOUSE OFF").unwrap(),
+            Statement::MousePointer { visible: false }
+        );
+        assert!(parse("MOUSE X%, Y%").is_err());
+        assert!(parse("MOUSE X%, Y%, B%, T%").is_err());
+        assert!(parse("MOUSE 1, 2, 3").is_err());
+        for source in ["MOUSE P(0), P(1), B%", "MOUSE ON"] {
+            assert_eq!(unparse(&parse(source).unwrap()), source);
+        }
+    }
+
+    #[test]
+    fn test_parse_const_and_assert() {
+        use crate::tokenizer::tokenize;
+        assert_eq!(
+            parse_statement(&tokenize("CONST MAX% = 10").unwrap()).unwrap(),
+            Statement::Const {
+                name: "MAX%".to_string(),
+                expression: Expression::Integer(10),
+            }
+        );
+        assert!(parse_statement(&tokenize("CONST MAX%").unwrap()).is_err());
+
+        assert!(matches!(
+            parse_statement(&tokenize("ASSERT X% > 0").unwrap()).unwrap(),
+            Statement::Assert { message: None, .. }
+        ));
+        assert!(matches!(
+            parse_statement(&tokenize("ASSERT X% > 0, \"X% too small\"").unwrap()).unwrap(),
+            Statement::Assert {
+                message: Some(Expression::String(_)),
+                ..
+            }
+        ));
+        assert!(parse_statement(&tokenize("ASSERT").unwrap()).is_err());
+        assert!(parse_statement(&tokenize("ASSERT 1, 2, 3").unwrap()).is_err());
+
+        // Both are extensions, so BASIC II does not have them
+        assert_eq!(
+            parse_statement_with_dialect(&tokenize("CONST A = 1").unwrap(), Dialect::BasicII),
+            Err(BBCBasicError::Mistake)
+        );
+    }
+
+    #[test]
+    fn test_unparse_round_trip() {
+        use crate::tokenizer::tokenize;
+        // Each line is in the canonical style, so it unparses to itself
+        for source in [
+            "X% = 3",
+            "A$ = \"HELLO\"",
+            "A(1, J% + 1) = SIN(1.5) * 2.0",
+            "X = (A + B) * C - D / E",
+            "X = A - (B - C)",
+            "X = 2 ^ 3 ^ 2",
+            "X = -(A + B) + -C",
+            "X% = A% AND 3 OR B% EOR 8",
+            "X% = NOT A% = 0",
+            "X% = A% DIV 2 + A% MOD 3",
+            "F% = FNtwice(N% + 1) + FNrnd",
+            "PRINT \"A\"; B%, C;",
+            "PRINT TAB(3); X, SPC(2) \"Y\"",
+            "PRINT",
+            "PRINT#F%, A, B$",
+            "INPUT A, B$",
+            "INPUT N$(3), A%(I% + 1, 2)",
+            "INPUT#F%, A$",
+            "INPUT#F%, A$(I%), B",
+            "READ I%, A%(I%)",
+            "FOR I% = 1 TO 10 STEP 2",
+            "FOR A(I% + 1) = X TO Y",
+            "NEXT A(I% + 1)",
+            "NEXT I%, J%",
+            "NEXT",
+            "IF X% < 2 THEN GOTO 30 ELSE PRINT \"NO\"",
+            "IF A% >= B% AND C% <= D% THEN",
+            "ON X% GOTO 10, 20, 30",
+            "ON X% + 1 GOSUB 100, 200",
+            "ON X% PROCone, PROCtwo(A%, \"B\"), PROCthree",
+            "ON ERROR GOTO 1000",
+            "ON ERROR OFF",
+            "GOSUB 500",
+            "RETURN",
+            "DIM A%(10), B(2, 3)",
+            "DIM B% 100",
+            "X% = A%!4 + B%?I% * 2",
+            "X% = -!A% + ?(A% * 2)",
+            "?A% = 5",
+            "A%!4 = !B%",
+            "$(A% + 8) = \"TEXT\"",
+            "PRINT ~A%; $B%",
+            "X% = A% <> B%",
+            "X% = 1 << 4",
+            "REM hello world",
+            "REM",
+            "PROCdraw(X%, Y% * 2)",
+            "PROCinit",
+            "DEF PROCdraw(X%, A())",
+            "DEF FNtwice(N) = N * 2",
+            "LOCAL A%, B$, C()",
+            "LOCAL DATA",
+            "LOCAL ERROR",
+            "DATA 1, two, \"three, four\", \"say \"\"hi\"\"\"",
+            "READ A%, B$",
+            "RESTORE",
+            "RESTORE 100",
+            "RESTORE +2",
+            "RESTORE DATA",
+            "RESTORE ERROR",
+            "REPEAT",
+            "UNTIL X% > 10",
+            "WHILE X% < 10",
+            "VDU 22, 7",
+            "VDU 29, 640; 512;",
+            "CLOSE#F%",
+            "BPUT#F%, 10",
+            "BPUT#F%, \"TEXT\";",
+            "B% = BGET#F% + EOF#F%",
+            "PTR#F% = EXT#F% - 1",
+            "INSTALL \"lib\"",
+            "CHAIN \"TEST\" + N$",
+            "CLEAR",
+            "CALL A%",
+            "COLOUR 128 + C%",
+            "MOVE BY 10, 20",
+            "DRAW 100, 200",
+            "PLOT 85, X%, Y%",
+            "CIRCLE FILL 640, 512, 100",
+            "ELLIPSE 640, 512, 200, 100",
+            "RECTANGLE FILL 0, 0, 100, 50 TO 200, 200",
+            "GCOL 0, 3",
+            "MODE 7",
+            "SOUND 1, -15, 53, 20",
+            "WAIT",
+            "WAIT 50",
+            "CONST MAX% = 10",
+            "ASSERT X% > 0, \"X% too small\"",
+            "OUTPUT #F%",
+            "OUTPUT",
+        ] {
+            let statement = parse_statement(&tokenize(source).unwrap()).unwrap();
+            let text = unparse(&statement);
+            assert_eq!(text, source);
+            assert_eq!(
+                parse_statement(&tokenize(&text).unwrap()).unwrap(),
+                statement
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_indirection() {
+        use crate::tokenizer::tokenize;
+        let expression = |source: &str| {
+            let tokens = tokenize(source).unwrap().tokens;
+            parse_expression(&tokens).unwrap()
+        };
+        let variable = |name: &str| Box::new(Expression::Variable(name.to_string()));
+        let offset = |base: &str, offset: Expression| {
+            Box::new(Expression::BinaryOp {
+                left: variable(base),
+                op: BinaryOperator::Add,
+                right: Box::new(offset),
+            })
+        };
+
+        // An offset binds tighter than any binary operator
+        assert_eq!(
+            expression("A%!4 + 1"),
+            Expression::BinaryOp {
+                left: Box::new(Expression::Indirect {
+                    op: Indirection::Word,
+                    address: offset("A%", Expression::Integer(4)),
+                }),
+                op: BinaryOperator::Add,
+                right: Box::new(Expression::Integer(1)),
+            }
+        );
+        assert_eq!(
+            expression("buffer%?I% * 2"),
+            Expression::BinaryOp {
+                left: Box::new(Expression::Indirect {
+                    op: Indirection::Byte,
+                    address: offset("buffer%", Expression::Variable("I%".to_string())),
+                }),
+                op: BinaryOperator::Multiply,
+                right: Box::new(Expression::Integer(2)),
+            }
+        );
+        // So does a unary operator's address
+        assert_eq!(
+            expression("?A% = 0"),
+            Expression::BinaryOp {
+                left: Box::new(Expression::Indirect {
+                    op: Indirection::Byte,
+                    address: variable("A%"),
+                }),
+                op: BinaryOperator::Equal,
+                right: Box::new(Expression::Integer(0)),
+            }
+        );
+        assert_eq!(
+            expression("$(buf% + 8)"),
+            Expression::Indirect {
+                op: Indirection::String,
+                address: offset("buf%", Expression::Integer(8)),
+            }
+        );
+        assert_eq!(
+            expression("$(buf% + 8)").expression_type(),
+            ExpressionType::String
+        );
+        assert_eq!(
+            expression("REPORT$"),
+            Expression::Variable("REPORT$".to_string())
+        );
+
+        let statement = parse_statement(&tokenize("10 list%!4 = 0").unwrap()).unwrap();
+        assert_eq!(
+            statement,
+            Statement::IndirectAssignment {
+                op: Indirection::Word,
+                address: *offset("list%", Expression::Integer(4)),
+                expression: Expression::Integer(0),
+            }
+        );
+        assert!(parse_statement(&tokenize("A% + 1 = 0").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_unparse_built_statements() {
+        // Statements made without the parser get the parentheses they need
+        let variable = |name: &str| Box::new(Expression::Variable(name.to_string()));
+        let sum = Expression::BinaryOp {
+            left: variable("A"),
+            op: BinaryOperator::Add,
+            right: variable("B"),
+        };
+        let statement = Statement::Assignment {
+            target: "X".to_string(),
+            expression: Expression::BinaryOp {
+                left: Box::new(sum.clone()),
+                op: BinaryOperator::Multiply,
+                right: Box::new(Expression::BinaryOp {
+                    left: variable("C"),
+                    op: BinaryOperator::Divide,
+                    right: Box::new(sum),
+                }),
+            },
+        };
+        assert_eq!(unparse(&statement), "X = (A + B) * (C / (A + B))");
+
+        let statement = Statement::Print {
+            items: vec![
+                PrintItem::Expression(Expression::Real(2.0)),
+                PrintItem::Semicolon,
+                PrintItem::Expression(Expression::String("say \"hi\"".to_string())),
+            ],
+        };
+        assert_eq!(unparse(&statement), "PRINT 2.0; \"say \"\"hi\"\"\"");
+        assert_eq!(unparse(&Statement::Empty), "");
+    }
 }
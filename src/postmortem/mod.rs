@@ -0,0 +1,367 @@
+//! Post-mortem dump of a program stopped by an unhandled error
+//!
+//! Records where the program was when it stopped: the offending line, the
+//! PROC calls and GOSUBs it was inside (with the values of each PROC's
+//! parameters) and the FOR, REPEAT and WHILE loops in progress. The dump can
+//! be printed for the user or inspected by tools.
+
+use crate::charset;
+use crate::config::Config;
+use crate::executor::Executor;
+use crate::parser::{parse_statements_with_dialect, run_order, Expression, Statement};
+use crate::program::ProgramStore;
+use crate::tokenizer::detokenize_with_options;
+use crate::variables::Variable;
+use serde::Serialize;
+use std::fmt;
+
+/// State of a program when an unhandled error stopped it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PostMortem {
+    /// The error
+    pub error: String,
+    /// Line the error happened at, if it happened while running a line
+    pub line_number: Option<u16>,
+    /// Listing of that line
+    pub statement: Option<String>,
+    /// PROC calls and GOSUBs in progress, outermost first
+    pub calls: Vec<Call>,
+    /// FOR, REPEAT and WHILE loops in progress, outermost first
+    pub loops: Vec<Loop>,
+}
+
+/// A PROC call or GOSUB in progress
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum Call {
+    /// PROC call, with the values its parameters had when it stopped
+    Proc {
+        name: String,
+        /// Line of the call
+        line_number: u16,
+        /// Parameters in order, with their values (None if unset)
+        params: Vec<(String, Option<Variable>)>,
+    },
+    /// GOSUB (or ON GOSUB)
+    Gosub {
+        /// Line of the GOSUB
+        line_number: u16,
+    },
+}
+
+/// A loop in progress
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum Loop {
+    For {
+        variable: String,
+        /// Value of the control variable
+        value: Option<f64>,
+        limit: f64,
+        step: f64,
+        /// Line of the FOR
+        line_number: u16,
+    },
+    Repeat {
+        /// Line of the REPEAT
+        line_number: u16,
+    },
+    While {
+        /// Line of the WHILE
+        line_number: u16,
+    },
+}
+
+impl PostMortem {
+    /// Record the state of a program that has just stopped with `error`
+    pub fn capture(
+        executor: &Executor,
+        program: &ProgramStore,
+        config: &Config,
+        error: &str,
+    ) -> Self {
+        let line_number = executor.line_number();
+        let statement = line_number.and_then(|n| program.get_line(n)).map(|line| {
+            detokenize_with_options(line, &config.tokenizer_options())
+                .unwrap_or_else(|e| format!("{:?}", e))
+        });
+
+        let calls = calls(executor, program, config);
+        let mut loops: Vec<Loop> = executor
+            .for_loops()
+            .iter()
+            .map(|active| Loop::For {
+                variable: active.variable.to_string(),
+                value: match executor.variables().get_target(&active.variable) {
+                    Ok(Variable::Integer(value)) => Some(f64::from(value)),
+                    Ok(Variable::Real(value)) => Some(value),
+                    _ => None,
+                },
+                limit: active.limit,
+                step: active.step,
+                line_number: active.position.line_number,
+            })
+            .collect();
+        loops.extend(executor.repeat_loops().iter().map(|repeat| Loop::Repeat {
+            line_number: repeat.line_number,
+        }));
+        loops.extend(executor.while_loops().iter().map(|start| Loop::While {
+            line_number: start.line_number,
+        }));
+        // The loop stacks are kept by kind, so take loops to nest in line order
+        loops.sort_by_key(Loop::line_number);
+
+        Self {
+            error: error.to_string(),
+            line_number,
+            statement,
+            calls,
+            loops,
+        }
+    }
+}
+
+impl Loop {
+    /// Line the loop starts at
+    pub fn line_number(&self) -> u16 {
+        match self {
+            Loop::For { line_number, .. }
+            | Loop::Repeat { line_number }
+            | Loop::While { line_number } => *line_number,
+        }
+    }
+}
+
+/// The PROC calls and GOSUBs a program is inside, outermost first
+pub fn calls(executor: &Executor, program: &ProgramStore, config: &Config) -> Vec<Call> {
+    // The return stack holds the calling statement of each GOSUB and PROC;
+    // PROC calls also have a local scope each, in the same order
+    let mut calls = Vec::new();
+    let mut depth = 0;
+    let returns = executor.return_positions();
+    for (i, position) in returns.iter().enumerate() {
+        let line_number = position.line_number;
+        let call = program
+            .get_line(line_number)
+            .and_then(|line| parse_statements_with_dialect(line, config.dialect).ok())
+            .and_then(|statements| run_order(statements).into_iter().nth(position.statement));
+        let name = match call {
+            Some(Statement::ProcCall { name, .. }) => Some(name),
+            Some(Statement::OnProc { calls, .. }) => {
+                let reached = returns
+                    .get(i + 1)
+                    .map(|call| call.line_number)
+                    .or(executor.line_number());
+                Some(called(executor, &calls, reached))
+            }
+            _ => None,
+        };
+        match name {
+            Some(name) => {
+                let params = executor
+                    .get_procedure(&name)
+                    .map(|proc| proc.params.clone())
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|param| {
+                        let value = executor.variable_at_depth(depth, &param).cloned();
+                        (param, value)
+                    })
+                    .collect();
+                calls.push(Call::Proc {
+                    name,
+                    line_number,
+                    params,
+                });
+                depth += 1;
+            }
+            None => calls.push(Call::Gosub { line_number }),
+        }
+    }
+    calls
+}
+
+/// Which PROC an ON ... PROC call went to, going by `reached`, the line
+/// the call went on to: the one whose DEF PROC comes last before it
+fn called(
+    executor: &Executor,
+    calls: &[(String, Vec<Expression>)],
+    reached: Option<u16>,
+) -> String {
+    calls
+        .iter()
+        .filter_map(|(name, _)| Some((name, executor.get_procedure(name)?.line_number)))
+        .filter(|(_, def)| reached.is_some_and(|reached| *def <= reached))
+        .max_by_key(|(_, def)| *def)
+        .map_or_else(|| "?".to_string(), |(name, _)| name.clone())
+}
+
+/// Show a value as it would be typed
+pub(crate) fn show_value(value: Option<&Variable>) -> String {
+    match value {
+        None => "unset".to_string(),
+        Some(Variable::Integer(value)) => value.to_string(),
+        Some(Variable::Real(value)) => value.to_string(),
+        Some(Variable::String(value)) => format!("\"{}\"", charset::to_unicode(value)),
+        Some(array) => {
+            let dimensions = array.dimensions().unwrap_or_default();
+            let sizes: Vec<String> = dimensions.iter().map(|d| d.to_string()).collect();
+            format!("array({})", sizes.join(","))
+        }
+    }
+}
+
+impl fmt::Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Call::Proc {
+                name,
+                line_number,
+                params,
+            } => {
+                let params: Vec<String> = params
+                    .iter()
+                    .map(|(param, value)| format!("{}={}", param, show_value(value.as_ref())))
+                    .collect();
+                write!(
+                    f,
+                    "PROC{}({}) called at line {}",
+                    name,
+                    params.join(", "),
+                    line_number
+                )
+            }
+            Call::Gosub { line_number } => write!(f, "GOSUB at line {}", line_number),
+        }
+    }
+}
+
+impl fmt::Display for PostMortem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.line_number, &self.statement) {
+            (Some(_), Some(statement)) => {
+                writeln!(f, "Stopped at: {}", charset::to_unicode(statement))?
+            }
+            (Some(line_number), None) => writeln!(f, "Stopped at line {}", line_number)?,
+            _ => writeln!(f, "Stopped before the program started")?,
+        }
+
+        if !self.calls.is_empty() {
+            writeln!(f, "Calls, innermost first:")?;
+            for call in self.calls.iter().rev() {
+                writeln!(f, "  {}", call)?;
+            }
+        }
+
+        if !self.loops.is_empty() {
+            writeln!(f, "Loops, innermost first:")?;
+            for active in self.loops.iter().rev() {
+                match active {
+                    Loop::For {
+                        variable,
+                        value,
+                        limit,
+                        step,
+                        line_number,
+                    } => {
+                        let value = value.map_or_else(|| "unset".to_string(), |v| v.to_string());
+                        writeln!(
+                            f,
+                            "  FOR {} at line {}: {}={} TO {} STEP {}",
+                            variable, line_number, variable, value, limit, step
+                        )?
+                    }
+                    Loop::Repeat { line_number } => {
+                        writeln!(f, "  REPEAT at line {}", line_number)?
+                    }
+                    Loop::While { line_number } => writeln!(f, "  WHILE at line {}", line_number)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Backend;
+    use crate::Interpreter;
+
+    #[test]
+    fn test_post_mortem_dump() {
+        for backend in [Backend::Tree, Backend::Bytecode] {
+            let mut interpreter = Interpreter::with_config(Config {
+                backend,
+                ..Default::default()
+            });
+            for line in [
+                "10 GOSUB 100",
+                "20 END",
+                "100 FOR I% = 1 TO 5",
+                "110 PROCouter(7, \"A\")",
+                "120 NEXT I%",
+                "130 RETURN",
+                "200 DEF PROCouter(N%, S$)",
+                "210 REPEAT",
+                "220 ON 2 PROCouter(0, \"B\"), PROCinner(N% * 2)",
+                "230 UNTIL N% > 0",
+                "240 ENDPROC",
+                "300 DEF PROCinner(N%)",
+                "310 X% = N% DIV 0",
+                "320 ENDPROC",
+            ] {
+                interpreter.process_line(line).unwrap();
+            }
+            let error = interpreter.run().unwrap_err();
+
+            let dump = interpreter.post_mortem().unwrap();
+            assert_eq!(dump.error, error);
+            assert_eq!(dump.line_number, Some(310));
+            assert_eq!(dump.statement.as_deref(), Some("310 X% = N% DIV 0"));
+            assert_eq!(
+                dump.calls,
+                vec![
+                    Call::Gosub { line_number: 10 },
+                    Call::Proc {
+                        name: "outer".to_string(),
+                        line_number: 110,
+                        params: vec![
+                            ("N%".to_string(), Some(Variable::Integer(7))),
+                            ("S$".to_string(), Some(Variable::String("A".to_string()))),
+                        ],
+                    },
+                    Call::Proc {
+                        name: "inner".to_string(),
+                        line_number: 220,
+                        params: vec![("N%".to_string(), Some(Variable::Integer(14)))],
+                    },
+                ]
+            );
+            assert_eq!(
+                dump.loops,
+                vec![
+                    Loop::For {
+                        variable: "I%".to_string(),
+                        value: Some(1.0),
+                        limit: 5.0,
+                        step: 1.0,
+                        line_number: 100,
+                    },
+                    Loop::Repeat { line_number: 210 },
+                ]
+            );
+
+            let text = dump.to_string();
+            assert!(
+                text.contains("PROCinner(N%=14) called at line 220"),
+                "{}",
+                text
+            );
+            assert!(text.contains("PROCouter(N%=7, S$=\"A\")"), "{}", text);
+
+            // A clean run leaves no dump
+            interpreter.process_line("310 X% = N%").unwrap();
+            interpreter.run().unwrap();
+            assert_eq!(interpreter.post_mortem(), None);
+        }
+    }
+}
@@ -1,17 +1,170 @@
 //! Program storage and management for BBC BASIC
 //!
 //! Manages BBC BASIC program lines in tokenized format with automatic sorting.
+//!
+//! Libraries of PROC/FN definitions loaded with LIBRARY or INSTALL are kept
+//! apart from the program, renumbered into a line range of their own, so they
+//! can be called from the program without clashing with its line numbers.
+//! LIST, SAVE and NEW only see the program itself.
+//!
+//! Programs loaded from text keep the comments at the top of their file and
+//! their blank lines as [`ProgramMetadata`], so that SAVE writes them back,
+//! and a title and author can be found in those comments or the program's
+//! opening REMs for catalogue tools.
+//!
+//! Every change to the program's lines is recorded, so that UNDO and REDO
+//! can step back and forth through the last [`UNDO_LIMIT`] edits. NEW, LOAD
+//! and other commands that change many lines at once are a single edit.
+//!
+//! Two programs can be compared line by line with [`ProgramStore::diff`],
+//! and the changes shown as a unified diff with [`unified_diff`] (*DIFF).
+//!
+//! Tools and tests can build programs without going through the REPL, from
+//! source text with [`ProgramStore::from_source`] or line by line with
+//! [`ProgramBuilder`], and read them back as parsed statements with
+//! [`ProgramStore::statements`].
+
+use crate::error::BBCBasicError;
+use crate::parser::{parse_statement_with_dialect, Dialect, Statement};
+use crate::tokenizer::{
+    detokenize_compact, detokenize_with_options, tokenize_with_options, TokenizedLine,
+    TokenizerOptions,
+};
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::RangeBounds;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// First line number given to library lines (programs use lines 0-32767, as
+/// on the BBC Micro)
+pub const LIBRARY_BASE: u16 = 32768;
 
-use crate::tokenizer::TokenizedLine;
-use std::collections::BTreeMap;
+/// Number of edits UNDO can go back through
+pub const UNDO_LIMIT: usize = 100;
+
+/// The last revision given to a program (see [`ProgramStore::revision`])
+static REVISIONS: AtomicU64 = AtomicU64::new(0);
+
+/// A statement of a program: its line, and where it comes in the line
+///
+/// Statements are counted from 0 in the order they run (see
+/// [`crate::parser::run_order`]). GOSUB, PROC calls and loops remember
+/// positions rather than lines, so that they can carry on part way along a
+/// line of colon-separated statements.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Position {
+    /// Line number
+    pub line_number: u16,
+    /// Index of the statement in the line
+    pub statement: usize,
+}
+
+impl Position {
+    /// A statement of a line
+    pub fn new(line_number: u16, statement: usize) -> Self {
+        Self {
+            line_number,
+            statement,
+        }
+    }
+}
+
+impl From<u16> for Position {
+    /// The first statement of a line
+    fn from(line_number: u16) -> Self {
+        Self::new(line_number, 0)
+    }
+}
 
 /// Program line storage with execution support
 #[derive(Debug, Clone)]
 pub struct ProgramStore {
     /// Stored program lines (line_number -> TokenizedLine)
     lines: BTreeMap<u16, TokenizedLine>,
+    /// Loaded libraries, in the order they were loaded
+    libraries: Vec<Library>,
     /// Current execution line (for RUN, GOTO, etc.)
     current_line: Option<u16>,
+    /// Index of the statement being run in the current line
+    current_statement: usize,
+    /// Comments and blank lines from the file the program was loaded from
+    metadata: ProgramMetadata,
+    /// Edits that UNDO and REDO step through
+    history: EditHistory,
+    /// Changes whenever the program or library lines do
+    revision: u64,
+}
+
+/// The parts of a program's source file that are not program lines
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProgramMetadata {
+    /// Comment lines before the first numbered line, as written
+    pub header: Vec<String>,
+    /// Number of blank lines before each numbered line
+    pub blank_lines: BTreeMap<u16, usize>,
+    /// Listed without the spaces BBC BASIC can do without (after *PACK)
+    pub packed: bool,
+}
+
+/// A change made by an edit, with what it replaced
+#[derive(Debug, Clone)]
+enum Change {
+    /// A line stored, replaced or deleted
+    Line {
+        line_number: u16,
+        before: Option<TokenizedLine>,
+        after: Option<TokenizedLine>,
+    },
+    /// The comments and blank lines replaced (NEW and LOAD)
+    Metadata {
+        before: ProgramMetadata,
+        after: ProgramMetadata,
+    },
+}
+
+/// Edits to the program, for UNDO and REDO
+#[derive(Debug, Clone, Default)]
+struct EditHistory {
+    /// Edits made, oldest first
+    undo: VecDeque<Vec<Change>>,
+    /// Edits undone, most recently undone last
+    redo: Vec<Vec<Change>>,
+    /// Changes since the outermost open `begin_edit`
+    group: Vec<Change>,
+    /// Number of `begin_edit` calls not yet ended
+    depth: usize,
+}
+
+impl EditHistory {
+    /// Note a change, as an edit of its own unless a group is open
+    fn record(&mut self, change: Change) {
+        self.group.push(change);
+        if self.depth == 0 {
+            self.finish_group();
+        }
+    }
+
+    /// Make the changes grouped so far one edit, forgetting what was undone
+    fn finish_group(&mut self) {
+        if self.group.is_empty() {
+            return;
+        }
+        self.undo.push_back(std::mem::take(&mut self.group));
+        if self.undo.len() > UNDO_LIMIT {
+            self.undo.pop_front();
+        }
+        self.redo.clear();
+    }
+}
+
+/// A library of PROC/FN definitions
+#[derive(Debug, Clone)]
+struct Library {
+    /// File name it was loaded from
+    name: String,
+    /// INSTALLed libraries stay loaded; LIBRARY ones go at the next RUN
+    permanent: bool,
+    /// Lines, renumbered into the library area
+    lines: BTreeMap<u16, TokenizedLine>,
 }
 
 impl ProgramStore {
@@ -19,25 +172,171 @@ impl ProgramStore {
     pub fn new() -> Self {
         Self {
             lines: BTreeMap::new(),
+            libraries: Vec::new(),
             current_line: None,
+            current_statement: 0,
+            metadata: ProgramMetadata::default(),
+            history: EditHistory::default(),
+            revision: 0,
         }
     }
 
+    /// A number that changes whenever the program's lines or its libraries
+    /// do, for callers that keep something worked out from them
+    ///
+    /// Revisions are never reused, so no other program has the same one
+    /// unless it is a clone of this one with the same lines.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Give the program a new revision, as its lines have changed
+    fn touch(&mut self) {
+        self.revision = REVISIONS.fetch_add(1, Ordering::Relaxed) + 1;
+    }
+
+    /// A program from numbered source text, as LOAD reads it
+    pub fn from_source(source: &str) -> Result<Self, String> {
+        let mut program = Self::new();
+        program.load_text(source.lines(), &TokenizerOptions::default())?;
+        Ok(program)
+    }
+
     /// Store a program line
     pub fn store_line(&mut self, line: TokenizedLine) {
         if let Some(line_number) = line.line_number {
-            self.lines.insert(line_number, line);
+            self.set_line(line_number, Some(line));
         }
     }
 
     /// Delete a program line (entering just a line number deletes it)
     pub fn delete_line(&mut self, line_number: u16) {
-        self.lines.remove(&line_number);
+        self.set_line(line_number, None);
+    }
+
+    /// Tokenize `text` and store it as line `line_number`, replacing any
+    /// line with that number
+    pub fn set_line_text(
+        &mut self,
+        line_number: u16,
+        text: &str,
+        options: &TokenizerOptions,
+    ) -> Result<(), String> {
+        if line_number >= LIBRARY_BASE {
+            return Err(format!("Line number too big: {}", line_number));
+        }
+        let line = tokenize_with_options(&format!("{} {}", line_number, text), options)
+            .map_err(|e| format!("Parse error at line {}: {:?}", line_number, e))?;
+        self.store_line(line);
+        Ok(())
+    }
+
+    /// Delete the lines with numbers in `range` as a single edit, returning
+    /// how many there were
+    pub fn delete_lines(&mut self, range: impl RangeBounds<u16>) -> usize {
+        let doomed: Vec<u16> = self.lines.range(range).map(|(k, _)| *k).collect();
+        self.begin_edit();
+        for line_number in &doomed {
+            self.delete_line(*line_number);
+        }
+        self.end_edit();
+        doomed.len()
+    }
+
+    /// Store or delete a line, recording the change for UNDO
+    fn set_line(&mut self, line_number: u16, line: Option<TokenizedLine>) {
+        let before = match &line {
+            Some(line) => self.lines.insert(line_number, line.clone()),
+            None => self.lines.remove(&line_number),
+        };
+        if before != line {
+            self.touch();
+            self.history.record(Change::Line {
+                line_number,
+                before,
+                after: line,
+            });
+        }
+    }
+
+    /// Replace the comments and blank lines, recording the change for UNDO
+    fn set_metadata(&mut self, metadata: ProgramMetadata) {
+        if metadata != self.metadata {
+            let before = std::mem::replace(&mut self.metadata, metadata.clone());
+            self.history.record(Change::Metadata {
+                before,
+                after: metadata,
+            });
+        }
+    }
+
+    /// Make the changes from here to [`ProgramStore::end_edit`] a single
+    /// edit for UNDO (calls may be nested)
+    pub fn begin_edit(&mut self) {
+        self.history.depth += 1;
+    }
+
+    /// End an edit started with [`ProgramStore::begin_edit`]
+    pub fn end_edit(&mut self) {
+        self.history.depth = self.history.depth.saturating_sub(1);
+        if self.history.depth == 0 {
+            self.history.finish_group();
+        }
+    }
+
+    /// Undo the last edit, returning false if there is none
+    pub fn undo(&mut self) -> bool {
+        let Some(edit) = self.history.undo.pop_back() else {
+            return false;
+        };
+        for change in edit.iter().rev() {
+            self.restore(change, true);
+        }
+        self.history.redo.push(edit);
+        true
     }
 
-    /// Get a program line
+    /// Make an undone edit again, returning false if there is none
+    pub fn redo(&mut self) -> bool {
+        let Some(edit) = self.history.redo.pop() else {
+            return false;
+        };
+        for change in &edit {
+            self.restore(change, false);
+        }
+        self.history.undo.push_back(edit);
+        true
+    }
+
+    /// Put back the state before (`undo`) or after a change, unrecorded
+    fn restore(&mut self, change: &Change, undo: bool) {
+        match change {
+            Change::Line {
+                line_number,
+                before,
+                after,
+            } => {
+                let line = if undo { before } else { after };
+                match line {
+                    Some(line) => self.lines.insert(*line_number, line.clone()),
+                    None => self.lines.remove(line_number),
+                };
+                self.touch();
+            }
+            Change::Metadata { before, after } => {
+                self.metadata = if undo { before } else { after }.clone();
+            }
+        }
+        self.current_line = None;
+    }
+
+    /// Get a program or library line
     pub fn get_line(&self, line_number: u16) -> Option<&TokenizedLine> {
-        self.lines.get(&line_number)
+        self.lines.get(&line_number).or_else(|| {
+            self.libraries
+                .iter()
+                .find_map(|library| library.lines.get(&line_number))
+        })
     }
 
     /// Get all line numbers in order
@@ -45,12 +344,188 @@ impl ProgramStore {
         self.lines.keys().copied().collect()
     }
 
-    /// Clear all program lines (NEW command)
+    /// Clear all program lines and their metadata (NEW command)
     pub fn clear(&mut self) {
-        self.lines.clear();
+        self.begin_edit();
+        for line_number in self.get_line_numbers() {
+            self.set_line(line_number, None);
+        }
+        self.set_metadata(ProgramMetadata::default());
+        self.end_edit();
         self.current_line = None;
     }
 
+    /// Replace the program with numbered source lines (LOAD of a text file)
+    ///
+    /// Comment lines (REM, `'`, `*|` or `#`) before the first numbered line
+    /// and blank lines anywhere are kept as metadata; any other line without
+    /// a number is an error.
+    ///
+    /// A line ending in `\` continues on the next, which is joined on
+    /// without the backslash or its indentation, so a long statement can be
+    /// split across lines of the file.
+    pub fn load_text<'a>(
+        &mut self,
+        lines: impl IntoIterator<Item = &'a str>,
+        options: &TokenizerOptions,
+    ) -> Result<(), String> {
+        self.begin_edit();
+        self.clear();
+        let mut metadata = ProgramMetadata::default();
+        let result = self.store_text(lines, options, &mut metadata);
+        self.set_metadata(metadata);
+        self.end_edit();
+        result
+    }
+
+    /// Store the numbered lines of source text, collecting its header
+    /// comments and blank lines in `metadata`
+    fn store_text<'a>(
+        &mut self,
+        lines: impl IntoIterator<Item = &'a str>,
+        options: &TokenizerOptions,
+        metadata: &mut ProgramMetadata,
+    ) -> Result<(), String> {
+        let mut blank_lines = 0;
+        for (index, line) in join_continued_lines(lines) {
+            let line = line.trim();
+            if line.is_empty() {
+                blank_lines += 1;
+                continue;
+            }
+            if self.lines.is_empty() && comment_text(line).is_some() {
+                // Blank lines among the header comments are kept with them
+                let blanks = std::iter::repeat_n(String::new(), blank_lines);
+                metadata.header.extend(blanks);
+                metadata.header.push(line.to_string());
+                blank_lines = 0;
+                continue;
+            }
+            let tokenized = tokenize_with_options(line, options)
+                .map_err(|e| format!("Parse error at line {}: {:?}", index + 1, e))?;
+            let Some(line_number) = tokenized.line_number else {
+                return Err(format!("Line {} has no line number: {}", index + 1, line));
+            };
+            if blank_lines > 0 {
+                metadata.blank_lines.insert(line_number, blank_lines);
+            }
+            self.store_line(tokenized);
+            blank_lines = 0;
+        }
+        Ok(())
+    }
+
+    /// The program as source lines for SAVE, with its header comments and
+    /// blank lines
+    pub fn to_text(&self, options: &TokenizerOptions) -> Result<Vec<String>, String> {
+        let mut text = self.metadata.header.clone();
+        for (line_number, line) in &self.lines {
+            let blanks = self
+                .metadata
+                .blank_lines
+                .get(line_number)
+                .copied()
+                .unwrap_or(0);
+            text.extend(std::iter::repeat_n(String::new(), blanks));
+            text.push(self.line_text(line, options)?);
+        }
+        Ok(text)
+    }
+
+    /// A line as it is listed: spaced as usual, or packed after *PACK
+    pub fn line_text(
+        &self,
+        line: &TokenizedLine,
+        options: &TokenizerOptions,
+    ) -> Result<String, String> {
+        let text = if self.metadata.packed {
+            detokenize_compact(line, options)
+        } else {
+            detokenize_with_options(line, options)
+        };
+        text.map_err(|e| {
+            let line_number = line.line_number.unwrap_or(0);
+            format!("Failed to detokenize line {}: {:?}", line_number, e)
+        })
+    }
+
+    /// Drop the header comments and blank lines and list the program packed
+    /// from now on (*PACK); header comments stay if `keep_comments` is set
+    pub fn set_packed(&mut self, keep_comments: bool) {
+        let header = if keep_comments {
+            self.metadata.header.clone()
+        } else {
+            Vec::new()
+        };
+        self.set_metadata(ProgramMetadata {
+            header,
+            blank_lines: BTreeMap::new(),
+            packed: true,
+        });
+    }
+
+    /// Comments and blank lines kept from the program's source file
+    pub fn metadata(&self) -> &ProgramMetadata {
+        &self.metadata
+    }
+
+    /// The program's title: a `Title:` comment in the header or the
+    /// opening REMs, or else the first of those comments
+    pub fn title(&self) -> Option<String> {
+        let comments = self.comments();
+        tagged(&comments, "TITLE").or_else(|| {
+            comments
+                .into_iter()
+                .find(|comment| !comment.is_empty() && !comment.contains(':'))
+        })
+    }
+
+    /// The program's author, from an `Author:` comment in the header or the
+    /// opening REMs
+    pub fn author(&self) -> Option<String> {
+        tagged(&self.comments(), "AUTHOR")
+    }
+
+    /// Give the program a title (*TITLE), changing the header's `Title:`
+    /// comment or adding a REM one at the top
+    pub fn set_title(&mut self, title: &str) {
+        let mut metadata = self.metadata.clone();
+        let header = &mut metadata.header;
+        let existing = header.iter_mut().find(|line| {
+            comment_text(line).is_some_and(|text| tag_value(&text, "TITLE").is_some())
+        });
+        match existing {
+            // Keep the comment's own marker and spelling of the tag
+            Some(line) => {
+                let tag = line.split_once(':').map_or("", |(tag, _)| tag);
+                *line = format!("{}: {}", tag, title);
+            }
+            None => header.insert(0, format!("REM Title: {}", title)),
+        }
+        self.set_metadata(metadata);
+    }
+
+    /// Text of the header comments followed by the REMs that open the program
+    fn comments(&self) -> Vec<String> {
+        let options = TokenizerOptions::default();
+        let header = self
+            .metadata
+            .header
+            .iter()
+            .filter_map(|line| comment_text(line));
+        let rems = self
+            .lines
+            .values()
+            .map_while(|line| detokenize_with_options(line, &options).ok())
+            .map_while(|text| {
+                let text = text
+                    .trim_start_matches(|c: char| c.is_ascii_digit())
+                    .trim_start();
+                comment_text(text)
+            });
+        header.chain(rems).collect()
+    }
+
     /// Check if program is empty
     pub fn is_empty(&self) -> bool {
         self.lines.is_empty()
@@ -61,22 +536,101 @@ impl ProgramStore {
         self.lines.len()
     }
 
+    /// Bytes the tokenized program takes from PAGE to TOP, including the
+    /// two byte end marker
+    pub fn size(&self) -> usize {
+        2 + self.lines.values().map(TokenizedLine::size).sum::<usize>()
+    }
+
     /// List the program (returns lines in order)
     pub fn list(&self) -> Vec<(u16, &TokenizedLine)> {
-        self.lines.iter().map(|(k, v)| (*k, v)).collect()
+        self.iter().collect()
+    }
+
+    /// The program's lines in order
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &TokenizedLine)> + '_ {
+        self.lines.iter().map(|(k, v)| (*k, v))
+    }
+
+    /// The program's lines in order, each parsed as a statement
+    pub fn statements(
+        &self,
+        dialect: Dialect,
+    ) -> impl Iterator<Item = (u16, Result<Statement, BBCBasicError>)> + '_ {
+        self.iter()
+            .map(move |(k, v)| (k, parse_statement_with_dialect(v, dialect)))
+    }
+
+    /// The lines added, removed and changed going from this program to
+    /// `newer`, in line number order
+    ///
+    /// Lines are matched by number and compared by their tokens, so spacing
+    /// that tokenizes the same is not a change.
+    pub fn diff(
+        &self,
+        newer: &ProgramStore,
+        options: &TokenizerOptions,
+    ) -> Result<Vec<LineChange>, String> {
+        let text = |line_number: u16, line: &TokenizedLine| {
+            detokenize_with_options(line, options)
+                .map_err(|e| format!("Failed to detokenize line {}: {:?}", line_number, e))
+        };
+        let mut line_numbers: Vec<u16> = self
+            .lines
+            .keys()
+            .chain(newer.lines.keys())
+            .copied()
+            .collect();
+        line_numbers.sort_unstable();
+        line_numbers.dedup();
+        let mut changes = Vec::new();
+        for line_number in line_numbers {
+            let change = match (self.lines.get(&line_number), newer.lines.get(&line_number)) {
+                (Some(old), Some(new)) if old == new => continue,
+                (Some(old), Some(new)) => LineChange::Changed {
+                    line_number,
+                    old: text(line_number, old)?,
+                    new: text(line_number, new)?,
+                },
+                (Some(old), None) => LineChange::Removed {
+                    line_number,
+                    text: text(line_number, old)?,
+                },
+                (None, Some(new)) => LineChange::Added {
+                    line_number,
+                    text: text(line_number, new)?,
+                },
+                (None, None) => continue,
+            };
+            changes.push(change);
+        }
+        Ok(changes)
     }
 
     /// Start program execution from the first line
     pub fn start_execution(&mut self) -> Option<u16> {
         self.current_line = self.lines.keys().next().copied();
+        self.current_statement = 0;
         self.current_line
     }
 
     /// Get the next line to execute
+    ///
+    /// Execution runs off the end of the program or of a library rather than
+    /// into the next one.
     pub fn next_line(&mut self) -> Option<u16> {
         if let Some(current) = self.current_line {
-            // Find the next line after current
-            self.current_line = self.lines.range((current + 1)..).next().map(|(k, _)| *k);
+            // Find the next line after current, in the same program or library
+            let lines = if self.lines.contains_key(&current) {
+                &self.lines
+            } else {
+                self.libraries
+                    .iter()
+                    .map(|library| &library.lines)
+                    .find(|lines| lines.contains_key(&current))?
+            };
+            self.current_line = lines.range((current + 1)..).next().map(|(k, _)| *k);
+            self.current_statement = 0;
             self.current_line
         } else {
             None
@@ -85,23 +639,206 @@ impl ProgramStore {
 
     /// Jump to a specific line (for GOTO, GOSUB)
     pub fn goto_line(&mut self, line_number: u16) -> bool {
-        if self.lines.contains_key(&line_number) {
-            self.current_line = Some(line_number);
+        self.goto_position(Position::from(line_number))
+    }
+
+    /// Jump to a statement of a line (for RETURN, NEXT and the like)
+    pub fn goto_position(&mut self, position: Position) -> bool {
+        if self.get_line(position.line_number).is_some() {
+            self.current_line = Some(position.line_number);
+            self.current_statement = position.statement;
             true
         } else {
             false
         }
     }
 
+    /// Go on to the statement after the current one, in a line of
+    /// `statements` statements, or to the next line after its last
+    pub fn next_statement(&mut self, statements: usize) -> Option<Position> {
+        if self.current_statement + 1 < statements {
+            self.current_statement += 1;
+            self.current_position()
+        } else {
+            self.next_line()?;
+            self.current_position()
+        }
+    }
+
     /// Get the current execution line
     pub fn get_current_line(&self) -> Option<u16> {
         self.current_line
     }
 
+    /// Get the statement being run
+    pub fn current_position(&self) -> Option<Position> {
+        self.current_line
+            .map(|line_number| Position::new(line_number, self.current_statement))
+    }
+
     /// Stop execution
     pub fn stop_execution(&mut self) {
         self.current_line = None;
     }
+
+    /// Load a library, numbering its lines after those of the libraries
+    /// already loaded; returns false if a library of that name is loaded
+    /// already (an INSTALL then keeps it loaded for good)
+    pub fn install_library(
+        &mut self,
+        name: &str,
+        lines: Vec<TokenizedLine>,
+        permanent: bool,
+    ) -> Result<bool, String> {
+        if let Some(library) = self
+            .libraries
+            .iter_mut()
+            .find(|library| library.name == name)
+        {
+            library.permanent |= permanent;
+            return Ok(false);
+        }
+
+        // Number the lines on from the last library line
+        let first = self
+            .libraries
+            .iter()
+            .filter_map(|library| library.lines.keys().next_back())
+            .max()
+            .map_or(u32::from(LIBRARY_BASE), |&last| u32::from(last) + 1);
+        if first as usize + lines.len() > usize::from(u16::MAX) + 1 {
+            return Err("No room for library".to_string());
+        }
+        let lines = (first..)
+            .zip(lines)
+            .map(|(number, mut line)| {
+                let number = number as u16;
+                line.line_number = Some(number);
+                (number, line)
+            })
+            .collect();
+        self.libraries.push(Library {
+            name: name.to_string(),
+            permanent,
+            lines,
+        });
+        self.touch();
+        Ok(true)
+    }
+
+    /// Names of the loaded libraries, in the order they were loaded
+    pub fn library_names(&self) -> Vec<&str> {
+        self.libraries
+            .iter()
+            .map(|library| library.name.as_str())
+            .collect()
+    }
+
+    /// Lines of all loaded libraries, in the order they were loaded
+    pub fn library_lines(&self) -> Vec<(u16, &TokenizedLine)> {
+        self.libraries
+            .iter()
+            .flat_map(|library| library.lines.iter().map(|(k, v)| (*k, v)))
+            .collect()
+    }
+
+    /// Discard libraries loaded with LIBRARY, keeping INSTALLed ones (RUN)
+    pub fn discard_temporary_libraries(&mut self) {
+        let count = self.libraries.len();
+        self.libraries.retain(|library| library.permanent);
+        if self.libraries.len() != count {
+            self.touch();
+        }
+    }
+}
+
+/// A difference between two versions of a program (see [`ProgramStore::diff`])
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineChange {
+    /// A line only in the newer program
+    Added { line_number: u16, text: String },
+    /// A line only in the older program
+    Removed { line_number: u16, text: String },
+    /// A line in both whose text differs
+    Changed {
+        line_number: u16,
+        old: String,
+        new: String,
+    },
+}
+
+/// Show changes as a unified diff: `---` and `+++` headers naming the two
+/// programs, then `-` for each old line and `+` for each new one
+pub fn unified_diff(old_name: &str, new_name: &str, changes: &[LineChange]) -> Vec<String> {
+    let mut diff = vec![format!("--- {}", old_name), format!("+++ {}", new_name)];
+    for change in changes {
+        match change {
+            LineChange::Added { text, .. } => diff.push(format!("+{}", text)),
+            LineChange::Removed { text, .. } => diff.push(format!("-{}", text)),
+            LineChange::Changed { old, new, .. } => {
+                diff.push(format!("-{}", old));
+                diff.push(format!("+{}", new));
+            }
+        }
+    }
+    diff
+}
+
+/// Source lines with each line ending in `\` joined to the next, paired
+/// with the index of their first line in the file
+///
+/// A header comment (one without a line number) never continues.
+pub(crate) fn join_continued_lines<'a>(
+    lines: impl IntoIterator<Item = &'a str>,
+) -> Vec<(usize, String)> {
+    let mut joined: Vec<(usize, String)> = Vec::new();
+    let mut continuing = false;
+    for (index, line) in lines.into_iter().enumerate() {
+        let line = line.trim();
+        let continues = line.ends_with('\\') && (continuing || comment_text(line).is_none());
+        let text = if continues {
+            &line[..line.len() - 1]
+        } else {
+            line
+        };
+        match joined.last_mut() {
+            Some((_, logical)) if continuing => logical.push_str(text),
+            _ => joined.push((index, text.to_string())),
+        }
+        continuing = continues;
+    }
+    joined
+}
+
+/// The text of a comment line, without its REM, `'`, `*|` or `#` marker
+/// (None if it is not a comment; a `#!` interpreter line has no text)
+pub(crate) fn comment_text(line: &str) -> Option<String> {
+    let text = if line
+        .get(..3)
+        .is_some_and(|rem| rem.eq_ignore_ascii_case("REM"))
+    {
+        &line[3..]
+    } else if line.starts_with("#!") {
+        ""
+    } else {
+        line.strip_prefix('\'')
+            .or_else(|| line.strip_prefix("*|"))
+            .or_else(|| line.strip_prefix('#'))?
+    };
+    Some(text.trim().to_string())
+}
+
+/// The value of a `tag: value` comment, matching the tag in any case
+fn tag_value(comment: &str, tag: &str) -> Option<String> {
+    let (name, value) = comment.split_once(':')?;
+    name.trim()
+        .eq_ignore_ascii_case(tag)
+        .then(|| value.trim().to_string())
+}
+
+/// The value of the first `tag: value` comment
+fn tagged(comments: &[String], tag: &str) -> Option<String> {
+    comments.iter().find_map(|comment| tag_value(comment, tag))
 }
 
 impl Default for ProgramStore {
@@ -110,7 +847,54 @@ impl Default for ProgramStore {
     }
 }
 
+/// Builds a program line by line, for tools and tests
+///
+/// `ProgramBuilder::new().line(10, "PRINT \"HI\"").line(20, "END").build()`
+/// gives a two line program. The first line that does not tokenize is
+/// reported by [`ProgramBuilder::build`].
+#[derive(Debug, Default)]
+pub struct ProgramBuilder {
+    program: ProgramStore,
+    options: TokenizerOptions,
+    error: Option<String>,
+}
+
+impl ProgramBuilder {
+    /// Start an empty program
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Tokenize the lines that follow with `options`
+    pub fn options(mut self, options: TokenizerOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Add line `line_number`, replacing any line with that number
+    pub fn line(mut self, line_number: u16, text: &str) -> Self {
+        if self.error.is_none() {
+            let result = self.program.set_line_text(line_number, text, &self.options);
+            self.error = result.err();
+        }
+        self
+    }
+
+    /// The finished program, or the first error
+    pub fn build(mut self) -> Result<ProgramStore, String> {
+        match self.error {
+            Some(error) => Err(error),
+            None => {
+                // The program starts with nothing to UNDO
+                self.program.history = EditHistory::default();
+                Ok(self.program)
+            }
+        }
+    }
+}
+
 #[cfg(test)]
+#[allow(unused_imports)]
 mod tests {
     use super::*;
     use crate::tokenizer::{tokenize, Token};
@@ -208,6 +992,277 @@ mod tests {
         assert_eq!(next, None);
     }
 
+    #[test]
+    fn test_text_keeps_comments_and_blank_lines() {
+        let source = [
+            "#!/usr/bin/env bbcbasic",
+            "REM Title: Space Invaders",
+            "",
+            "' by the arcade club",
+            "10 PRINT \"A\"",
+            "",
+            "",
+            "20 END",
+        ];
+        let options = TokenizerOptions::default();
+        let mut store = ProgramStore::new();
+        store.load_text(source, &options).unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.metadata().header.len(), 4);
+        assert_eq!(store.to_text(&options).unwrap(), source);
+
+        let error = store.load_text(["10 PRINT", "PRINT 2"], &options);
+        assert_eq!(error, Err("Line 2 has no line number: PRINT 2".to_string()));
+
+        store.clear();
+        assert_eq!(store.metadata(), &ProgramMetadata::default());
+    }
+
+    #[test]
+    fn test_text_line_continuation() {
+        let options = TokenizerOptions::default();
+        let mut store = ProgramStore::new();
+        let source = [
+            "REM C:\\",
+            "10 PRINT \"A\", \\",
+            "      \"B\", \\",
+            "      \"C\"",
+            "20 X% = 1 + \\",
+            "  2",
+            "30 PRINT",
+            "PRINT 2",
+        ];
+        let error = store.load_text(source, &options);
+        assert_eq!(error, Err("Line 8 has no line number: PRINT 2".to_string()));
+
+        store
+            .load_text(source[..7].iter().copied(), &options)
+            .unwrap();
+        assert_eq!(store.metadata().header, ["REM C:\\"]);
+        assert_eq!(
+            store.to_text(&options).unwrap()[1..],
+            ["10 PRINT \"A\",\"B\",\"C\"", "20 X% = 1 + 2", "30 PRINT"]
+        );
+    }
+
+    #[test]
+    fn test_building_programs() {
+        let program = ProgramBuilder::new()
+            .line(20, "END")
+            .line(10, "PRINT \"HI\"")
+            .line(15, "X% = 1")
+            .build()
+            .unwrap();
+        assert_eq!(program.get_line_numbers(), [10, 15, 20]);
+        assert_eq!(
+            program.statements(Dialect::default()).nth(1),
+            Some((
+                15,
+                Ok(Statement::Assignment {
+                    target: "X%".to_string(),
+                    expression: crate::parser::Expression::Integer(1),
+                })
+            ))
+        );
+        let mut built = program.clone();
+        assert!(!built.undo());
+        assert_eq!(
+            ProgramBuilder::new()
+                .line(10, "PRINT")
+                .line(40000, "END")
+                .build()
+                .unwrap_err(),
+            "Line number too big: 40000"
+        );
+
+        let mut program =
+            ProgramStore::from_source("10 PRINT \"HI\"\n15 X% = 1\n20 END\n").unwrap();
+        assert_eq!(
+            program.iter().map(|(_, line)| line).collect::<Vec<_>>(),
+            built.iter().map(|(_, line)| line).collect::<Vec<_>>()
+        );
+        program
+            .set_line_text(15, "X% = 2", &TokenizerOptions::default())
+            .unwrap();
+        assert_eq!(program.get_line(15), Some(&tokenize("15 X% = 2").unwrap()));
+        assert_eq!(program.delete_lines(11..), 2);
+        assert_eq!(program.get_line_numbers(), [10]);
+        assert!(program.undo());
+        assert_eq!(program.len(), 3);
+    }
+
+    #[test]
+    fn test_diff() {
+        let options = TokenizerOptions::default();
+        let mut old = ProgramStore::new();
+        old.load_text(["10 PRINT \"A\"", "20 X = 1", "30 END"], &options)
+            .unwrap();
+        let mut new = ProgramStore::new();
+        new.load_text(
+            ["5 CLS", "10 PRINT  \"A\"", "20 X = 2", "40 STOP"],
+            &options,
+        )
+        .unwrap();
+
+        let changes = old.diff(&new, &options).unwrap();
+        assert_eq!(
+            changes,
+            vec![
+                LineChange::Added {
+                    line_number: 5,
+                    text: "5 CLS".to_string(),
+                },
+                LineChange::Changed {
+                    line_number: 20,
+                    old: "20 X = 1".to_string(),
+                    new: "20 X = 2".to_string(),
+                },
+                LineChange::Removed {
+                    line_number: 30,
+                    text: "30 END".to_string(),
+                },
+                LineChange::Added {
+                    line_number: 40,
+                    text: "40 STOP".to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            unified_diff("OLD", "NEW", &changes),
+            [
+                "--- OLD",
+                "+++ NEW",
+                "+5 CLS",
+                "-20 X = 1",
+                "+20 X = 2",
+                "-30 END",
+                "+40 STOP"
+            ]
+        );
+        assert!(old.diff(&old, &options).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_title_and_author() {
+        let options = TokenizerOptions::default();
+        let mut store = ProgramStore::new();
+        let source = ["10 REM Author: Ann", "20 REM Title: Maze", "30 PRINT"];
+        store.load_text(source, &options).unwrap();
+        assert_eq!(store.title().as_deref(), Some("Maze"));
+        assert_eq!(store.author().as_deref(), Some("Ann"));
+
+        // Without a tag, the first plain comment is the title
+        let source = ["*| Lunar Lander", "10 REM Version 2"];
+        store.load_text(source, &options).unwrap();
+        assert_eq!(store.title().as_deref(), Some("Lunar Lander"));
+        assert_eq!(store.author(), None);
+
+        // *TITLE sets the header's title comment
+        store.set_title("Lander");
+        store.set_title("Moon Lander");
+        assert_eq!(store.title().as_deref(), Some("Moon Lander"));
+        assert_eq!(
+            store.metadata().header,
+            ["REM Title: Moon Lander", "*| Lunar Lander"]
+        );
+    }
+
+    #[test]
+    fn test_undo_and_redo() {
+        let mut store = ProgramStore::new();
+        assert!(!store.undo());
+        store.store_line(tokenize("10 PRINT \"A\"").unwrap());
+        store.store_line(tokenize("20 PRINT \"B\"").unwrap());
+        store.store_line(tokenize("20 PRINT \"C\"").unwrap());
+        let text = |store: &ProgramStore| store.to_text(&TokenizerOptions::default()).unwrap();
+
+        // NEW is one edit
+        store.clear();
+        assert!(store.is_empty());
+        assert!(store.undo());
+        assert_eq!(text(&store), ["10 PRINT \"A\"", "20 PRINT \"C\""]);
+        assert!(store.undo());
+        assert_eq!(text(&store), ["10 PRINT \"A\"", "20 PRINT \"B\""]);
+        assert!(store.redo());
+        assert!(store.redo());
+        assert!(store.is_empty());
+        assert!(!store.redo());
+
+        // A new edit forgets what was undone
+        store.undo();
+        store.delete_line(10);
+        assert!(!store.redo());
+        assert!(store.undo());
+        assert_eq!(store.len(), 2);
+
+        // Deleting a line that is not there is no edit
+        store.delete_line(99);
+        assert!(store.undo());
+        assert_eq!(text(&store), ["10 PRINT \"A\"", "20 PRINT \"B\""]);
+
+        // Only the last UNDO_LIMIT edits are kept
+        for line_number in 1..=UNDO_LIMIT as u16 + 10 {
+            store.store_line(tokenize(&format!("{} PRINT", line_number)).unwrap());
+        }
+        let mut undone = 0;
+        while store.undo() {
+            undone += 1;
+        }
+        assert_eq!(undone, UNDO_LIMIT);
+        assert_eq!(store.get_line_numbers()[..3], [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_install_library() {
+        let mut store = ProgramStore::new();
+        store.store_line(tokenize("10 PROCA").unwrap());
+        let library = |lines: &[&str]| lines.iter().map(|l| tokenize(l).unwrap()).collect();
+
+        let lines = library(&["DEF PROCA", "ENDPROC"]);
+        assert!(store.install_library("ONE", lines, false).unwrap());
+        let lines = library(&["10 DEF PROCB", "20 ENDPROC"]);
+        assert!(store.install_library("TWO", lines, true).unwrap());
+        assert!(!store.install_library("ONE", Vec::new(), false).unwrap());
+
+        // Library lines are numbered on from the library base, apart from
+        // the program, and execution stays within each library
+        let numbers: Vec<u16> = store.library_lines().iter().map(|(n, _)| *n).collect();
+        assert_eq!(
+            numbers,
+            (LIBRARY_BASE..LIBRARY_BASE + 4).collect::<Vec<_>>()
+        );
+        assert_eq!(store.len(), 1);
+        assert!(store.goto_line(LIBRARY_BASE + 1));
+        assert_eq!(store.next_line(), None);
+
+        store.discard_temporary_libraries();
+        assert_eq!(store.library_names(), vec!["TWO"]);
+    }
+
+    #[test]
+    fn test_revision() {
+        let mut store = ProgramStore::new();
+        store.store_line(tokenize("10 PRINT").unwrap());
+        let first = store.revision();
+        let copy = store.clone();
+        assert_eq!(copy.revision(), first);
+
+        // Only changes to the lines give a new revision, never used before
+        store.start_execution();
+        store.store_line(tokenize("10 PRINT").unwrap());
+        assert_eq!(store.revision(), first);
+        store.store_line(tokenize("20 END").unwrap());
+        let second = store.revision();
+        assert_ne!(second, first);
+        assert!(store.undo());
+        assert_ne!(store.revision(), first);
+        assert_ne!(store.revision(), second);
+        let lines = vec![tokenize("DEF PROCA").unwrap()];
+        let before = store.revision();
+        store.install_library("ONE", lines, false).unwrap();
+        assert_ne!(store.revision(), before);
+    }
+
     #[test]
     fn test_goto_line() {
         let mut store = ProgramStore::new();
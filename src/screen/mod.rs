@@ -0,0 +1,191 @@
+//! Text screen for BBC BASIC
+//!
+//! Keeps a character cell buffer of everything printed, the size of the
+//! current MODE's text screen, so that screen editing (the COPY key) can read
+//! back characters that are already on the screen.
+
+/// Text screen size (columns, rows) of each screen MODE
+const MODE_SIZES: [(usize, usize); 8] = [
+    (80, 32),
+    (40, 32),
+    (20, 32),
+    (80, 25),
+    (40, 32),
+    (20, 32),
+    (40, 25),
+    (40, 25),
+];
+
+/// A character cell text screen with a write cursor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextScreen {
+    width: usize,
+    height: usize,
+    cells: Vec<u8>,
+    /// Write cursor column and row
+    cursor: (usize, usize),
+    /// Lines scrolled off the top since the screen was created
+    scrolled: usize,
+}
+
+impl TextScreen {
+    /// Create a blank screen of the given size
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![b' '; width * height],
+            cursor: (0, 0),
+            scrolled: 0,
+        }
+    }
+
+    /// Create a blank screen the size of a MODE's text screen
+    pub fn for_mode(mode: u8) -> Self {
+        let (width, height) = MODE_SIZES[mode as usize % MODE_SIZES.len()];
+        Self::new(width, height)
+    }
+
+    /// Number of columns
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Number of rows
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Write cursor position (column, row)
+    pub fn cursor(&self) -> (usize, usize) {
+        self.cursor
+    }
+
+    /// Number of lines scrolled off the top so far
+    pub fn scrolled(&self) -> usize {
+        self.scrolled
+    }
+
+    /// Character at a column and row (a space outside the screen)
+    pub fn char_at(&self, x: usize, y: usize) -> u8 {
+        if x < self.width && y < self.height {
+            self.cells[y * self.width + x]
+        } else {
+            b' '
+        }
+    }
+
+    /// Text of a row, without trailing spaces
+    pub fn row(&self, y: usize) -> String {
+        let start = y.min(self.height) * self.width;
+        let end = (start + self.width).min(self.cells.len());
+        self.cells[start..end]
+            .iter()
+            .map(|&c| char::from(c))
+            .collect::<String>()
+            .trim_end()
+            .to_string()
+    }
+
+    /// Clear the screen and home the cursor (CLS)
+    pub fn clear(&mut self) {
+        self.cells.fill(b' ');
+        self.cursor = (0, 0);
+    }
+
+    /// Write text at the cursor; a newline starts the next line
+    pub fn write_str(&mut self, text: &str) {
+        for c in text.chars() {
+            match c {
+                '\n' => {
+                    self.write_char(13);
+                    self.write_char(10);
+                }
+                _ => self.write_char(crate::charset::code(c)),
+            }
+        }
+    }
+
+    /// Write a character code at the cursor, acting on control codes for
+    /// cursor movement
+    pub fn write_char(&mut self, code: u8) {
+        match code {
+            // Backspace
+            8 => self.cursor.0 = self.cursor.0.saturating_sub(1),
+            // Line feed
+            10 => self.line_feed(),
+            // Carriage return
+            13 => self.cursor.0 = 0,
+            // Delete: back up and rub out the character
+            127 => {
+                self.write_char(8);
+                let (x, y) = self.cursor;
+                self.cells[y * self.width + x] = b' ';
+            }
+            // Other control codes don't appear on the screen
+            0..=31 => {}
+            _ => {
+                let (x, y) = self.cursor;
+                self.cells[y * self.width + x] = code;
+                self.cursor.0 += 1;
+                if self.cursor.0 == self.width {
+                    self.cursor.0 = 0;
+                    self.line_feed();
+                }
+            }
+        }
+    }
+
+    /// Move the cursor down a line, scrolling at the bottom of the screen
+    fn line_feed(&mut self) {
+        if self.cursor.1 + 1 < self.height {
+            self.cursor.1 += 1;
+        } else {
+            self.cells.drain(..self.width);
+            self.cells.extend(std::iter::repeat_n(b' ', self.width));
+            self.scrolled += 1;
+        }
+    }
+}
+
+impl Default for TextScreen {
+    fn default() -> Self {
+        Self::for_mode(7)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_and_scroll() {
+        let mut screen = TextScreen::new(10, 3);
+        screen.write_str("HELLO\nWORLD");
+        assert_eq!(screen.row(0), "HELLO");
+        assert_eq!(screen.row(1), "WORLD");
+        assert_eq!(screen.cursor(), (5, 1));
+        assert_eq!(screen.char_at(1, 1), b'O');
+
+        // Long lines wrap, and the screen scrolls at the bottom
+        screen.write_str("\n0123456789AB");
+        assert_eq!(screen.scrolled(), 1);
+        assert_eq!(screen.row(0), "WORLD");
+        assert_eq!(screen.row(1), "0123456789");
+        assert_eq!(screen.row(2), "AB");
+
+        screen.write_char(127);
+        assert_eq!(screen.row(2), "A");
+        screen.clear();
+        assert_eq!(screen.cursor(), (0, 0));
+        assert_eq!(screen.row(1), "");
+    }
+
+    #[test]
+    fn test_mode_sizes() {
+        let screen = TextScreen::for_mode(0);
+        assert_eq!((screen.width(), screen.height()), (80, 32));
+        let screen = TextScreen::default();
+        assert_eq!((screen.width(), screen.height()), (40, 25));
+    }
+}
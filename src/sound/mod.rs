@@ -1,17 +1,641 @@
 //! Sound system for BBC BASIC
 //!
-//! Handles sound generation and music.
+//! Handles sound generation and music. SOUND and ENVELOPE commands are queued
+//! per channel and rendered offline to 16-bit PCM samples, so music programs
+//! can be tested deterministically and exported as WAV files.
+//!
+//! Notes are scheduled against a sound clock, as on the real machine: a note
+//! starts when its channel is free (and, for synchronised notes, when the
+//! other channels in its group are ready), and each channel queues at most
+//! four notes waiting to start.
+//!
+//! Front-ends can drive the sound system directly with typed requests
+//! ([`SoundCommand`], [`Envelope`]) or raw OSWORD 7 and 8 parameter blocks.
+//!
+//! Rendering to samples needs the `sound` feature; without it notes are
+//! still queued and scheduled, so ADVAL and the sound clock behave the same.
+//! The same feature renders the screech of bytes being read from tape
+//! ([`tape_tones`]) and adds [`HostPlayer`], which plays samples through
+//! the host's own audio player.
+
+use crate::error::{BBCBasicError, Result};
+
+/// Sample rate of rendered audio in Hz
+pub const SAMPLE_RATE: u32 = 44100;
+/// Number of sound channels (0 = noise, 1-3 = tone)
+pub const CHANNELS: usize = 4;
+/// Number of definable envelopes
+pub const ENVELOPES: usize = 16;
+/// Notes that can wait in each channel's queue behind the playing note
+pub const QUEUE_SIZE: usize = 4;
+
+/// Samples per SOUND duration unit (twentieths of a second)
+#[cfg(feature = "sound")]
+const SAMPLES_PER_DURATION: usize = SAMPLE_RATE as usize / 20;
+/// Samples per envelope step unit (centiseconds)
+#[cfg(feature = "sound")]
+const SAMPLES_PER_CENTISECOND: usize = SAMPLE_RATE as usize / 100;
+/// Duration units rendered for a note that would otherwise play forever
+#[cfg(feature = "sound")]
+const INFINITE_DURATION: usize = 100;
+/// Maximum envelope amplitude level
+const MAX_LEVEL: i32 = 126;
+/// Output level of one channel at full volume (leaves headroom for mixing)
+#[cfg(feature = "sound")]
+const CHANNEL_PEAK: f64 = 8000.0;
+/// Tape tones: a 0 bit is one cycle of the low tone and a 1 bit two cycles
+/// of the high tone, at 1200 bits a second; the leader is high tone
+#[cfg(feature = "sound")]
+const TAPE_LOW: f64 = 1200.0;
+#[cfg(feature = "sound")]
+const TAPE_HIGH: f64 = 2400.0;
+
+/// A queued SOUND command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Note {
+    /// Channel number (0-3)
+    pub channel: u8,
+    /// Volume (-15 loudest to 0 silent) or envelope number (1-16)
+    pub amplitude: i32,
+    /// Pitch in quarter semitones (53 = middle C)
+    pub pitch: u8,
+    /// Duration in twentieths of a second (None = until replaced)
+    pub duration: Option<u8>,
+    /// Hold: let the previous note's release continue instead of sounding
+    /// (the H in &HSFC)
+    pub hold: bool,
+    /// Number of other channels this note waits for (the S in &HSFC)
+    pub sync: u8,
+    /// Sound clock time the note was queued, in twentieths of a second
+    pub queued_at: u32,
+}
+
+/// Loudness of a note: a fixed volume or an envelope
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Amplitude {
+    /// Fixed volume, 0 (silent) to 15 (loudest)
+    Volume(u8),
+    /// Envelope number, 1-16
+    Envelope(u8),
+}
+
+impl Amplitude {
+    /// The SOUND amplitude parameter (-15 to 0 for volumes, 1-16 for envelopes)
+    pub fn to_param(self) -> i32 {
+        match self {
+            Amplitude::Volume(volume) => -(volume.min(15) as i32),
+            Amplitude::Envelope(number) => number as i32,
+        }
+    }
+
+    /// Decode a SOUND amplitude parameter
+    pub fn from_param(amplitude: i32) -> Result<Self> {
+        match amplitude {
+            -15..=0 => Ok(Amplitude::Volume((-amplitude) as u8)),
+            1..=16 => Ok(Amplitude::Envelope(amplitude as u8)),
+            _ => Err(BBCBasicError::IllegalFunction),
+        }
+    }
+}
+
+/// A sound request: the typed form of SOUND's parameters, or of an OSWORD 7
+/// parameter block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SoundCommand {
+    /// Channel number (0-3)
+    pub channel: u8,
+    /// Cut off the playing note and discard queued ones first (F)
+    pub flush: bool,
+    /// Number of other channels to start together with (S, 0-3)
+    pub sync: u8,
+    /// Let the previous note's release continue instead of sounding (H)
+    pub hold: bool,
+    /// Volume or envelope
+    pub amplitude: Amplitude,
+    /// Pitch in quarter semitones (53 = middle C)
+    pub pitch: u8,
+    /// Duration in twentieths of a second (None = until flushed)
+    pub duration: Option<u8>,
+}
+
+impl SoundCommand {
+    /// A plain note on a channel
+    pub fn note(channel: u8, amplitude: Amplitude, pitch: u8, duration: Option<u8>) -> Self {
+        Self {
+            channel: channel & 0x03,
+            flush: false,
+            sync: 0,
+            hold: false,
+            amplitude,
+            pitch,
+            duration,
+        }
+    }
+
+    /// Start together with `sync` other channels' notes (S)
+    pub fn with_sync(mut self, sync: u8) -> Self {
+        self.sync = sync & 0x03;
+        self
+    }
+
+    /// Flush the channel before queueing (F)
+    pub fn with_flush(mut self) -> Self {
+        self.flush = true;
+        self
+    }
+
+    /// Continue the previous note's release instead of sounding (H)
+    pub fn with_hold(mut self) -> Self {
+        self.hold = true;
+        self
+    }
+
+    /// Decode SOUND's four parameters (&HSFC, amplitude, pitch, duration)
+    pub fn from_params(channel: i32, amplitude: i32, pitch: i32, duration: i32) -> Result<Self> {
+        Ok(Self {
+            channel: (channel & 0x03) as u8,
+            flush: channel & 0x10 != 0,
+            sync: ((channel >> 8) & 0x03) as u8,
+            hold: channel & 0x1000 != 0,
+            amplitude: Amplitude::from_param(amplitude)?,
+            pitch: (pitch & 0xFF) as u8,
+            // Durations are a byte; 255 (i.e. -1) means play until replaced
+            duration: match duration & 0xFF {
+                255 => None,
+                d => Some(d as u8),
+            },
+        })
+    }
+
+    /// The &HSFC channel word
+    pub fn channel_word(&self) -> u16 {
+        (self.hold as u16) << 12
+            | (self.sync as u16 & 0x03) << 8
+            | (self.flush as u16) << 4
+            | self.channel as u16 & 0x03
+    }
+
+    /// Encode as an OSWORD 7 parameter block: channel, amplitude, pitch and
+    /// duration as little-endian 16-bit words
+    pub fn to_osword(&self) -> [u8; 8] {
+        let words = [
+            self.channel_word(),
+            self.amplitude.to_param() as i16 as u16,
+            self.pitch as u16,
+            self.duration.map_or(0xFFFF, u16::from),
+        ];
+        let mut block = [0; 8];
+        for (bytes, word) in block.chunks_mut(2).zip(words) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        block
+    }
+
+    /// Decode an OSWORD 7 parameter block
+    pub fn from_osword(block: &[u8; 8]) -> Result<Self> {
+        let word = |i: usize| i16::from_le_bytes([block[i], block[i + 1]]) as i32;
+        Self::from_params(word(0), word(2), word(4), word(6))
+    }
+}
+
+/// When a queued note plays, in twentieths of a second on the sound clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slot {
+    /// Time the note starts
+    pub start: u32,
+    /// Time the note ends (None = plays until flushed)
+    pub end: Option<u32>,
+}
+
+/// An ENVELOPE definition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Envelope {
+    /// Length of each step in centiseconds
+    pub step_length: u8,
+    /// Whether the pitch envelope repeats (bit 7 of T clear)
+    pub auto_repeat: bool,
+    /// Pitch change per step in each of the three sections
+    pub pitch_changes: [i32; 3],
+    /// Number of steps in each of the three sections
+    pub pitch_steps: [i32; 3],
+    /// Amplitude change per step during attack, decay, sustain and release
+    pub rates: [i32; 4],
+    /// Target level at the end of the attack phase
+    pub attack_target: i32,
+    /// Target level at the end of the decay phase
+    pub decay_target: i32,
+}
+
+impl Envelope {
+    /// Build an envelope from the 13 ENVELOPE parameters after the number
+    pub fn from_params(params: &[i32]) -> Result<Self> {
+        if params.len() != 13 {
+            return Err(BBCBasicError::IllegalFunction);
+        }
+
+        Ok(Self {
+            step_length: ((params[0] & 0x7F) as u8).max(1),
+            auto_repeat: params[0] & 0x80 == 0,
+            pitch_changes: [params[1], params[2], params[3]],
+            pitch_steps: [params[4], params[5], params[6]],
+            rates: [params[7], params[8], params[9], params[10]],
+            attack_target: params[11].clamp(0, MAX_LEVEL),
+            decay_target: params[12].clamp(0, MAX_LEVEL),
+        })
+    }
+
+    /// Decode an OSWORD 8 parameter block: the envelope number followed by
+    /// the 13 ENVELOPE parameters as bytes (pitch changes and rates signed)
+    pub fn from_osword(block: &[u8; 14]) -> Result<(u8, Self)> {
+        let params: Vec<i32> = block[1..]
+            .iter()
+            .enumerate()
+            .map(|(i, &byte)| match i {
+                // T, the pitch step counts and the target levels are unsigned
+                0 | 4..=6 | 11 | 12 => byte as i32,
+                _ => byte as i8 as i32,
+            })
+            .collect();
+        Ok((block[0], Self::from_params(&params)?))
+    }
+}
 
 /// Sound system
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SoundSystem {
-    // Implementation will be added in later tasks
+    /// Notes queued on each channel, in the order they will play
+    queues: [Vec<Note>; CHANNELS],
+    /// Envelope definitions (index 0 = envelope 1)
+    envelopes: [Option<Envelope>; ENVELOPES],
+    /// Current sound clock time in twentieths of a second
+    clock: u32,
 }
 
 impl SoundSystem {
     /// Create a new sound system
     pub fn new() -> Self {
-        Self {}
+        Self {
+            queues: Default::default(),
+            envelopes: [None; ENVELOPES],
+            clock: 0,
+        }
+    }
+
+    /// Queue a note (SOUND &HSFC, amplitude, pitch, duration)
+    ///
+    /// The channel number's hex digits are flags: H=1 holds the previous
+    /// note's release, S=1-3 starts the note together with that many other
+    /// channels' notes that have the same S, and F=1 flushes the channel,
+    /// cutting off the playing note and discarding queued ones.
+    pub fn sound(&mut self, channel: i32, amplitude: i32, pitch: i32, duration: i32) -> Result<()> {
+        self.enqueue(SoundCommand::from_params(
+            channel, amplitude, pitch, duration,
+        )?)
+    }
+
+    /// Queue a note from a typed sound request (OSWORD 7)
+    pub fn enqueue(&mut self, command: SoundCommand) -> Result<()> {
+        if let Amplitude::Envelope(number) = command.amplitude {
+            if !(1..=ENVELOPES as u8).contains(&number) {
+                return Err(BBCBasicError::IllegalFunction);
+            }
+        }
+
+        let channel = command.channel & 0x03;
+        if command.flush {
+            self.flush(channel as usize);
+        }
+        self.queues[channel as usize].push(Note {
+            channel,
+            amplitude: command.amplitude.to_param(),
+            pitch: command.pitch,
+            duration: command.duration,
+            hold: command.hold,
+            sync: command.sync & 0x03,
+            queued_at: self.clock,
+        });
+        Ok(())
+    }
+
+    /// Make an OSWORD call on the sound system: 7 queues a note from an
+    /// 8-byte block, 8 defines an envelope from a 14-byte block
+    pub fn osword(&mut self, call: u8, block: &[u8]) -> Result<()> {
+        match call {
+            7 => {
+                let block = block.try_into().map_err(|_| BBCBasicError::BadCall)?;
+                self.enqueue(SoundCommand::from_osword(block)?)
+            }
+            8 => {
+                let block = block.try_into().map_err(|_| BBCBasicError::BadCall)?;
+                let (number, envelope) = Envelope::from_osword(block)?;
+                self.define_envelope(number, envelope)
+            }
+            _ => Err(BBCBasicError::BadCall),
+        }
+    }
+
+    /// Set the sound clock, in twentieths of a second
+    pub fn set_clock(&mut self, time: u32) {
+        self.clock = self.clock.max(time);
+    }
+
+    /// Current sound clock time, in twentieths of a second
+    pub fn clock(&self) -> u32 {
+        self.clock
+    }
+
+    /// Cut off the note playing on a channel and discard the notes waiting
+    /// behind it
+    pub fn flush(&mut self, channel: usize) {
+        let clock = self.clock;
+        let slots = self.schedule();
+        let queue = &mut self.queues[channel];
+        let mut kept = 0;
+        for (index, slot) in slots[channel].iter().enumerate() {
+            match slot {
+                Some(slot) if slot.start < clock => {
+                    // Finished notes stay for rendering; the playing one stops now
+                    if slot.end.is_none_or(|end| end > clock) {
+                        queue[index].duration = Some((clock - slot.start).min(254) as u8);
+                    }
+                    kept = index + 1;
+                }
+                _ => break,
+            }
+        }
+        queue.truncate(kept);
+    }
+
+    /// Number of free places in a channel's queue (ADVAL(-5 - channel))
+    pub fn free_slots(&self, channel: usize) -> usize {
+        let slots = self.schedule();
+        let waiting = slots.get(channel).map_or(0, |slots| {
+            slots
+                .iter()
+                .filter(|slot| slot.is_none_or(|slot| slot.start > self.clock))
+                .count()
+        });
+        QUEUE_SIZE.saturating_sub(waiting)
+    }
+
+    /// Time until a full queue has room for another note on a channel, in
+    /// twentieths of a second (None if it never will, without a flush)
+    pub fn wait_for_space(&self, channel: usize) -> Option<u32> {
+        if self.free_slots(channel) > 0 {
+            return Some(0);
+        }
+        // Space appears when the first waiting note starts
+        let slots = self.schedule();
+        let start = slots[channel]
+            .iter()
+            .flatten()
+            .map(|slot| slot.start)
+            .find(|&start| start > self.clock)?;
+        Some(start - self.clock)
+    }
+
+    /// Work out when every queued note plays
+    ///
+    /// Each channel plays its notes in turn. A note with a sync count S waits
+    /// until S other channels have a note with the same count at the head of
+    /// their queues, then they all start together. Notes that can never
+    /// start (behind a note that plays until flushed, or waiting for a sync
+    /// that never comes) have no slot.
+    pub fn schedule(&self) -> [Vec<Option<Slot>>; CHANNELS] {
+        let mut slots: [Vec<Option<Slot>>; CHANNELS] = Default::default();
+        // Index of the next unscheduled note and the time each channel is free
+        let mut heads = [0usize; CHANNELS];
+        let mut free_at = [Some(0u32); CHANNELS];
+
+        let place = |slots: &mut [Vec<Option<Slot>>; CHANNELS],
+                     heads: &mut [usize; CHANNELS],
+                     free_at: &mut [Option<u32>; CHANNELS],
+                     channel: usize,
+                     start: u32| {
+            let note = &self.queues[channel][heads[channel]];
+            let end = note.duration.map(|d| start + d as u32);
+            slots[channel].push(Some(Slot { start, end }));
+            heads[channel] += 1;
+            free_at[channel] = end;
+        };
+
+        loop {
+            let mut progress = false;
+            for channel in 0..CHANNELS {
+                // Next note on this channel, and when it could start
+                let ready =
+                    |heads: &[usize; CHANNELS], free_at: &[Option<u32>; CHANNELS], c: usize| {
+                        let note = self.queues[c].get(heads[c])?;
+                        Some((note, free_at[c]?.max(note.queued_at)))
+                    };
+                let Some((note, start)) = ready(&heads, &free_at, channel) else {
+                    continue;
+                };
+                if note.sync == 0 {
+                    place(&mut slots, &mut heads, &mut free_at, channel, start);
+                    progress = true;
+                    continue;
+                }
+
+                let group: Vec<(usize, u32)> = (0..CHANNELS)
+                    .filter_map(|c| {
+                        let (other, start) = ready(&heads, &free_at, c)?;
+                        (other.sync == note.sync).then_some((c, start))
+                    })
+                    .collect();
+                if group.len() > note.sync as usize {
+                    let start = group.iter().map(|&(_, start)| start).max().unwrap_or(start);
+                    for &(c, _) in group.iter().take(note.sync as usize + 1) {
+                        place(&mut slots, &mut heads, &mut free_at, c, start);
+                    }
+                    progress = true;
+                }
+            }
+            if !progress {
+                break;
+            }
+        }
+
+        for (channel, channel_slots) in slots.iter_mut().enumerate() {
+            channel_slots.resize(self.queues[channel].len(), None);
+        }
+        slots
+    }
+
+    /// Define an envelope (ENVELOPE n, t, pi1, pi2, pi3, pn1, pn2, pn3, aa, ad, as, ar, ala, ald)
+    pub fn envelope(&mut self, number: i32, params: &[i32]) -> Result<()> {
+        let number = u8::try_from(number).map_err(|_| BBCBasicError::IllegalFunction)?;
+        self.define_envelope(number, Envelope::from_params(params)?)
+    }
+
+    /// Define an envelope (1-16) from a typed definition (OSWORD 8)
+    pub fn define_envelope(&mut self, number: u8, envelope: Envelope) -> Result<()> {
+        if !(1..=ENVELOPES as u8).contains(&number) {
+            return Err(BBCBasicError::IllegalFunction);
+        }
+        self.envelopes[(number - 1) as usize] = Some(envelope);
+        Ok(())
+    }
+
+    /// Get an envelope definition (1-16)
+    pub fn get_envelope(&self, number: usize) -> Option<&Envelope> {
+        number
+            .checked_sub(1)
+            .and_then(|index| self.envelopes.get(index))
+            .and_then(|envelope| envelope.as_ref())
+    }
+
+    /// Get the notes queued on a channel
+    pub fn notes(&self, channel: usize) -> &[Note] {
+        self.queues
+            .get(channel)
+            .map_or(&[], |queue| queue.as_slice())
+    }
+
+    /// Discard all queued notes (envelopes are kept)
+    pub fn clear(&mut self) {
+        for queue in &mut self.queues {
+            queue.clear();
+        }
+    }
+}
+
+#[cfg(feature = "sound")]
+impl SoundSystem {
+    /// Render all queued notes to mono 16-bit samples at SAMPLE_RATE
+    pub fn render(&self) -> Vec<i16> {
+        let slots = self.schedule();
+        let channels: Vec<Vec<f64>> = self
+            .queues
+            .iter()
+            .zip(&slots)
+            .map(|(queue, slots)| self.render_channel(queue, slots))
+            .collect();
+
+        let length = channels.iter().map(Vec::len).max().unwrap_or(0);
+        (0..length)
+            .map(|i| {
+                let mixed: f64 = channels.iter().filter_map(|c| c.get(i)).sum();
+                mixed.round().clamp(i16::MIN as f64, i16::MAX as f64) as i16
+            })
+            .collect()
+    }
+
+    /// Render the queued notes into a buffer, starting `offset` samples in,
+    /// returning how many samples were written (fewer than the buffer holds
+    /// once the audio ends)
+    pub fn render_into(&self, offset: usize, buffer: &mut [i16]) -> usize {
+        let samples = self.render();
+        let available = samples.get(offset..).unwrap_or(&[]);
+        let count = available.len().min(buffer.len());
+        buffer[..count].copy_from_slice(&available[..count]);
+        count
+    }
+
+    /// Encode the rendered audio as a WAV file (PCM, mono, 16-bit)
+    pub fn to_wav(&self) -> Vec<u8> {
+        encode_wav(&self.render())
+    }
+
+    /// Write the rendered audio to a WAV file
+    pub fn save_wav<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        std::fs::write(path, self.to_wav())
+    }
+
+    /// Render one channel's queue to unmixed samples
+    fn render_channel(&self, queue: &[Note], slots: &[Option<Slot>]) -> Vec<f64> {
+        let mut output = Vec::new();
+        let mut oscillator = Oscillator::new();
+        let to_samples = |time: u32| time as usize * SAMPLES_PER_DURATION;
+
+        for (index, (note, slot)) in queue.iter().zip(slots).enumerate() {
+            let Some(slot) = slot else { break };
+            if note.hold {
+                // The previous note's release has already sounded through this
+                let end = slot.end.unwrap_or(slot.start + INFINITE_DURATION as u32);
+                if output.len() < to_samples(end) {
+                    output.resize(to_samples(end), 0.0);
+                }
+                continue;
+            }
+            // Silence until the note starts; a release tail stops here
+            output.resize(to_samples(slot.start), 0.0);
+
+            let units = note.duration.map_or(INFINITE_DURATION, |d| d as usize);
+            let length = units * SAMPLES_PER_DURATION;
+            // An envelope's release sounds until the next note that isn't held
+            let next_start = queue[index + 1..]
+                .iter()
+                .zip(&slots[index + 1..])
+                .find(|(next, _)| !next.hold)
+                .and_then(|(_, slot)| slot.as_ref())
+                .map(|slot| to_samples(slot.start));
+            let release = match next_start {
+                Some(next_start) => next_start.saturating_sub(to_samples(slot.start) + length),
+                None => INFINITE_DURATION * SAMPLES_PER_DURATION,
+            };
+
+            if note.amplitude <= 0 {
+                // Fixed volume: -15 is loudest, 0 is silent
+                let level = (-note.amplitude) as f64 / 15.0;
+                for _ in 0..length {
+                    output.push(oscillator.next(note.channel, note.pitch) * level * CHANNEL_PEAK);
+                }
+            } else if let Some(envelope) = self.get_envelope(note.amplitude as usize) {
+                self.render_enveloped(
+                    &mut output,
+                    &mut oscillator,
+                    note,
+                    envelope,
+                    length,
+                    release,
+                );
+            } else {
+                // An undefined envelope has all rates zero, so stays silent
+                output.resize(output.len() + length, 0.0);
+            }
+        }
+        output
+    }
+
+    /// Render a note shaped by an envelope
+    ///
+    /// The release phase sounds for at most `release` samples after the
+    /// note, until the next note on the channel cuts it off, as on the real
+    /// machine.
+    fn render_enveloped(
+        &self,
+        output: &mut Vec<f64>,
+        oscillator: &mut Oscillator,
+        note: &Note,
+        envelope: &Envelope,
+        length: usize,
+        release: usize,
+    ) {
+        let step_samples = envelope.step_length as usize * SAMPLES_PER_CENTISECOND;
+        let mut state = EnvelopeState::default();
+        let mut produced = 0;
+
+        loop {
+            let releasing = produced >= length;
+            if releasing && (produced >= length + release || state.level == 0) {
+                break;
+            }
+
+            let pitch = (note.pitch as i32 + state.pitch_offset).rem_euclid(256) as u8;
+            let level = state.level as f64 / MAX_LEVEL as f64;
+            let step_end = if releasing {
+                (produced + step_samples).min(length + release)
+            } else {
+                (produced + step_samples).min(length)
+            };
+            while produced < step_end {
+                output.push(oscillator.next(note.channel, pitch) * level * CHANNEL_PEAK);
+                produced += 1;
+            }
+
+            state.advance(envelope, releasing);
+        }
     }
 }
 
@@ -20,3 +644,521 @@ impl Default for SoundSystem {
         Self::new()
     }
 }
+
+/// Encode mono 16-bit samples at SAMPLE_RATE as a WAV file
+#[cfg(feature = "sound")]
+pub fn encode_wav(samples: &[i16]) -> Vec<u8> {
+    let data_len = (samples.len() * 2) as u32;
+
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes()); // fmt chunk size
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // Mono
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes()); // Byte rate
+    wav.extend_from_slice(&2u16.to_le_bytes()); // Block align
+    wav.extend_from_slice(&16u16.to_le_bytes()); // Bits per sample
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
+
+/// Render the tones of `bytes` being recorded on tape, after `leader`
+/// seconds of carrier tone
+///
+/// Each byte is sent as on the real machine: a 0 start bit, the eight data
+/// bits lowest first, and a 1 stop bit.
+#[cfg(feature = "sound")]
+pub fn tape_tones(bytes: &[u8], leader: f64) -> Vec<i16> {
+    let bits = bytes.iter().flat_map(|&byte| {
+        std::iter::once(false)
+            .chain((0..8).map(move |bit| byte >> bit & 1 != 0))
+            .chain(std::iter::once(true))
+    });
+    // The leader is a whole number of 1 bits, keeping the bits in step
+    let leader_bits = (leader * TAPE_LOW) as usize;
+    let mut oscillator = Oscillator::new();
+    let mut samples = Vec::new();
+    let bit_samples = SAMPLE_RATE as f64 / TAPE_LOW;
+    for (index, one) in std::iter::repeat_n(true, leader_bits)
+        .chain(bits)
+        .enumerate()
+    {
+        let end = ((index + 1) as f64 * bit_samples).round() as usize;
+        let frequency = if one { TAPE_HIGH } else { TAPE_LOW };
+        while samples.len() < end {
+            oscillator.advance(frequency);
+            let level = if oscillator.phase < 0.5 { 1.0 } else { -1.0 };
+            samples.push((level * CHANNEL_PEAK) as i16);
+        }
+    }
+    samples
+}
+
+#[cfg(feature = "sound")]
+pub use host::HostPlayer;
+
+#[cfg(feature = "sound")]
+mod host {
+    use super::encode_wav;
+    use std::path::PathBuf;
+    use std::process::{Child, Command, Stdio};
+
+    /// Host audio players, tried in turn, each taking a WAV file as its
+    /// last argument
+    const PLAYERS: &[(&str, &[&str])] = &[("afplay", &[]), ("paplay", &[]), ("aplay", &["-q"])];
+
+    /// Plays samples through a host audio player, one sound at a time
+    #[derive(Debug)]
+    pub struct HostPlayer {
+        program: &'static str,
+        arguments: &'static [&'static str],
+        /// The WAV file handed to the player
+        file: PathBuf,
+        /// The player playing now, so a new sound or a stop can cut it off
+        playing: Option<Child>,
+    }
+
+    impl HostPlayer {
+        /// Play through the first of the known audio players found on the
+        /// PATH, or None if there is none
+        pub fn detect() -> Option<Self> {
+            let path = std::env::var_os("PATH")?;
+            let found = |(program, _): &&(&str, &[&str])| {
+                std::env::split_paths(&path).any(|dir| dir.join(program).is_file())
+            };
+            let &(program, arguments) = PLAYERS.iter().find(found)?;
+            let file =
+                std::env::temp_dir().join(format!("bbc-basic-sound-{}.wav", std::process::id()));
+            Some(Self {
+                program,
+                arguments,
+                file,
+                playing: None,
+            })
+        }
+
+        /// Start playing mono 16-bit samples at SAMPLE_RATE, cutting off
+        /// whatever is still playing, without waiting for them to finish
+        pub fn play(&mut self, samples: &[i16]) -> Result<(), String> {
+            self.stop();
+            std::fs::write(&self.file, encode_wav(samples)).map_err(|e| e.to_string())?;
+            let child = Command::new(self.program)
+                .args(self.arguments)
+                .arg(&self.file)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(|e| format!("Cannot run {}: {}", self.program, e))?;
+            self.playing = Some(child);
+            Ok(())
+        }
+
+        /// Stop playing
+        pub fn stop(&mut self) {
+            if let Some(mut child) = self.playing.take() {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+        }
+    }
+
+    impl Drop for HostPlayer {
+        fn drop(&mut self) {
+            self.stop();
+            let _ = std::fs::remove_file(&self.file);
+        }
+    }
+}
+
+/// Convert a BBC pitch value (quarter semitones, 89 = A at 440Hz) to Hz
+pub fn pitch_to_frequency(pitch: u8) -> f64 {
+    440.0 * 2f64.powf((pitch as f64 - 89.0) / 48.0)
+}
+
+/// Amplitude and pitch envelope progress for a single note
+#[cfg(feature = "sound")]
+#[derive(Debug, Default)]
+struct EnvelopeState {
+    /// Current amplitude level (0-126)
+    level: i32,
+    /// Amplitude phase (0 = attack, 1 = decay, 2 = sustain)
+    phase: usize,
+    /// Current pitch envelope section (0-2, 3 = finished)
+    section: usize,
+    /// Steps taken in the current section
+    section_step: i32,
+    /// Accumulated pitch change
+    pitch_offset: i32,
+}
+
+#[cfg(feature = "sound")]
+impl EnvelopeState {
+    /// Advance the envelope by one step
+    fn advance(&mut self, envelope: &Envelope, releasing: bool) {
+        // Amplitude envelope
+        if releasing {
+            self.level = (self.level + envelope.rates[3]).clamp(0, MAX_LEVEL);
+        } else {
+            match self.phase {
+                0 => {
+                    self.level = (self.level + envelope.rates[0]).clamp(0, MAX_LEVEL);
+                    if envelope.rates[0] <= 0 || self.level >= envelope.attack_target {
+                        self.level = self.level.min(envelope.attack_target);
+                        self.phase = 1;
+                    }
+                }
+                1 => {
+                    self.level = (self.level + envelope.rates[1]).clamp(0, MAX_LEVEL);
+                    let reached = if envelope.rates[1] < 0 {
+                        self.level <= envelope.decay_target
+                    } else {
+                        self.level >= envelope.decay_target
+                    };
+                    if reached {
+                        self.level = envelope.decay_target;
+                        self.phase = 2;
+                    }
+                }
+                _ => {
+                    self.level = (self.level + envelope.rates[2]).clamp(0, MAX_LEVEL);
+                }
+            }
+        }
+
+        // Pitch envelope: three sections, optionally repeating
+        while self.section < 3 && self.section_step >= envelope.pitch_steps[self.section] {
+            self.section += 1;
+            self.section_step = 0;
+            if self.section == 3
+                && envelope.auto_repeat
+                && envelope.pitch_steps.iter().any(|&n| n > 0)
+            {
+                self.section = 0;
+                self.pitch_offset = 0;
+            }
+        }
+        if self.section < 3 {
+            self.pitch_offset += envelope.pitch_changes[self.section];
+            self.section_step += 1;
+        }
+    }
+}
+
+/// Waveform generator for a channel
+#[cfg(feature = "sound")]
+#[derive(Debug)]
+struct Oscillator {
+    /// Phase through the current cycle (0.0-1.0)
+    phase: f64,
+    /// Linear feedback shift register for the noise channel
+    lfsr: u16,
+    /// Current noise output bit
+    noise_bit: bool,
+}
+
+#[cfg(feature = "sound")]
+impl Oscillator {
+    fn new() -> Self {
+        Self {
+            phase: 0.0,
+            lfsr: 0x4000,
+            noise_bit: false,
+        }
+    }
+
+    /// Produce the next sample in the range -1.0 to 1.0
+    fn next(&mut self, channel: u8, pitch: u8) -> f64 {
+        if channel == 0 {
+            self.next_noise(pitch)
+        } else {
+            self.advance(pitch_to_frequency(pitch));
+            if self.phase < 0.5 {
+                1.0
+            } else {
+                -1.0
+            }
+        }
+    }
+
+    /// Noise channel: pitch 0-2 periodic, 4-6 white (high/medium/low rate)
+    fn next_noise(&mut self, pitch: u8) -> f64 {
+        let frequency = match pitch & 0x03 {
+            0 => 7812.5,
+            1 => 3906.25,
+            2 => 1953.125,
+            // 3 follows channel 1 on the real chip; use middle C here
+            _ => pitch_to_frequency(53),
+        };
+        let white = pitch & 0x04 != 0;
+
+        if self.advance(frequency) {
+            if white {
+                let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+                self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+                self.noise_bit = self.lfsr & 1 != 0;
+            } else {
+                // Periodic noise: one pulse every 15 clocks
+                self.lfsr = (self.lfsr + 1) % 15;
+                self.noise_bit = self.lfsr == 0;
+            }
+        }
+        if self.noise_bit {
+            1.0
+        } else {
+            -1.0
+        }
+    }
+
+    /// Advance the phase, returning true when a cycle wraps
+    fn advance(&mut self, frequency: f64) -> bool {
+        self.phase += frequency / SAMPLE_RATE as f64;
+        if self.phase >= 1.0 {
+            self.phase = self.phase.fract();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pitch_to_frequency() {
+        assert!((pitch_to_frequency(89) - 440.0).abs() < 0.001);
+        assert!((pitch_to_frequency(53) - 261.63).abs() < 0.01); // Middle C
+    }
+
+    #[test]
+    fn test_sound_queues_notes() {
+        let mut sound = SoundSystem::new();
+        sound.sound(1, -15, 53, 20).unwrap();
+        sound.sound(1, -10, 89, -1).unwrap();
+        assert_eq!(sound.notes(1).len(), 2);
+        assert_eq!(sound.notes(1)[0].duration, Some(20));
+        assert_eq!(sound.notes(1)[1].duration, None);
+        assert!(sound.sound(1, -16, 53, 20).is_err());
+    }
+
+    #[cfg(feature = "sound")]
+    #[test]
+    fn test_render_length_and_silence() {
+        let mut sound = SoundSystem::new();
+        sound.sound(1, 0, 53, 10).unwrap(); // Half a second at volume 0
+        let samples = sound.render();
+        assert_eq!(samples.len(), SAMPLE_RATE as usize / 2);
+        assert!(samples.iter().all(|&s| s == 0));
+    }
+
+    #[cfg(feature = "sound")]
+    #[test]
+    fn test_render_is_deterministic() {
+        let mut sound = SoundSystem::new();
+        sound.sound(0, -15, 4, 5).unwrap();
+        sound.sound(2, -8, 101, 5).unwrap();
+        assert_eq!(sound.render(), sound.render());
+        assert!(sound.render().iter().any(|&s| s != 0));
+    }
+
+    #[cfg(feature = "sound")]
+    #[test]
+    fn test_envelope_attack_and_release() {
+        let mut sound = SoundSystem::new();
+        // Fast attack to 126, no decay or sustain change, slow release
+        sound
+            .envelope(1, &[1, 0, 0, 0, 0, 0, 0, 126, 0, 0, -10, 126, 126])
+            .unwrap();
+        sound.sound(1, 1, 53, 2).unwrap();
+        let samples = sound.render();
+        // Full level during the note, then a fading tail after the 0.1s note
+        let note_len = SAMPLES_PER_DURATION * 2;
+        assert!(samples.len() > note_len);
+        let peak = |range: &[i16]| range.iter().map(|s| s.unsigned_abs()).max().unwrap_or(0);
+        assert_eq!(peak(&samples[..note_len]), CHANNEL_PEAK as u16);
+        assert!(
+            peak(&samples[samples.len() - SAMPLES_PER_CENTISECOND..]) < CHANNEL_PEAK as u16 / 4
+        );
+    }
+
+    #[cfg(feature = "sound")]
+    #[test]
+    fn test_synchronised_notes() {
+        let mut sound = SoundSystem::new();
+        sound.sound(1, -15, 53, 10).unwrap();
+        // &101 and &102 wait for one other channel with S=1
+        sound.sound(0x101, -15, 69, 4).unwrap();
+        sound.sound(0x102, -15, 89, 4).unwrap();
+        // A lone S=1 note never finds a partner
+        sound.sound(0x103, -15, 89, 4).unwrap();
+
+        let slots = sound.schedule();
+        let slot = |channel: usize, index: usize| slots[channel][index];
+        assert_eq!(
+            slot(1, 1),
+            Some(Slot {
+                start: 10,
+                end: Some(14)
+            })
+        );
+        assert_eq!(
+            slot(2, 0),
+            Some(Slot {
+                start: 10,
+                end: Some(14)
+            })
+        );
+        assert_eq!(slot(3, 0), None);
+
+        // Channel 2 is silent until channel 1 is ready
+        let samples = sound.render();
+        assert_eq!(samples.len(), 14 * SAMPLES_PER_DURATION);
+    }
+
+    #[test]
+    fn test_flush_and_queue_space() {
+        let mut sound = SoundSystem::new();
+        for _ in 0..5 {
+            sound.sound(1, -15, 53, 10).unwrap();
+        }
+        // One note playing and four waiting
+        assert_eq!(sound.free_slots(1), 0);
+        assert_eq!(sound.wait_for_space(1), Some(10));
+        sound.set_clock(15);
+        assert_eq!(sound.free_slots(1), 1);
+        assert_eq!(sound.free_slots(2), QUEUE_SIZE);
+
+        // &11 cuts the second note short and discards the rest
+        sound.sound(0x11, -15, 101, 2).unwrap();
+        assert_eq!(sound.notes(1).len(), 3);
+        assert_eq!(sound.notes(1)[1].duration, Some(5));
+        assert_eq!(
+            sound.schedule()[1][2],
+            Some(Slot {
+                start: 15,
+                end: Some(17)
+            })
+        );
+    }
+
+    #[cfg(feature = "sound")]
+    #[test]
+    fn test_hold_continues_release() {
+        let mut sound = SoundSystem::new();
+        sound
+            .envelope(1, &[1, 0, 0, 0, 0, 0, 0, 126, 0, 0, -1, 126, 126])
+            .unwrap();
+        sound.sound(1, 1, 53, 2).unwrap();
+        // &1001 holds for half a second, then a silent note follows
+        sound.sound(0x1001, 0, 0, 10).unwrap();
+        sound.sound(1, 0, 53, 2).unwrap();
+        let samples = sound.render();
+        assert_eq!(samples.len(), 14 * SAMPLES_PER_DURATION);
+        // The release is still sounding during the held note
+        let held = &samples[5 * SAMPLES_PER_DURATION..6 * SAMPLES_PER_DURATION];
+        assert!(held.iter().any(|&s| s != 0));
+        assert!(samples[12 * SAMPLES_PER_DURATION..].iter().all(|&s| s == 0));
+    }
+
+    #[cfg(feature = "sound")]
+    #[test]
+    fn test_typed_api_matches_statements() {
+        let mut typed = SoundSystem::new();
+        let params = [1, 0, 0, 0, 0, 0, 0, 126, 0, 0, -10, 126, 126];
+        let envelope = Envelope::from_params(&params).unwrap();
+        typed.define_envelope(2, envelope).unwrap();
+        let note = SoundCommand::note(1, Amplitude::Envelope(2), 53, Some(4));
+        typed.enqueue(note.with_sync(1)).unwrap();
+        let note = SoundCommand::note(2, Amplitude::Volume(15), 89, Some(4));
+        typed.enqueue(note.with_sync(1)).unwrap();
+
+        let mut statements = SoundSystem::new();
+        statements.envelope(2, &params).unwrap();
+        statements.sound(0x101, 2, 53, 4).unwrap();
+        statements.sound(0x102, -15, 89, 4).unwrap();
+        assert_eq!(typed.notes(1), statements.notes(1));
+        assert_eq!(typed.render(), statements.render());
+
+        // Rendering in chunks gives the same samples
+        let all = typed.render();
+        let mut chunk = vec![0; 1000];
+        assert_eq!(typed.render_into(500, &mut chunk), 1000);
+        assert_eq!(chunk, all[500..1500]);
+        assert_eq!(typed.render_into(all.len() - 10, &mut chunk), 10);
+
+        assert!(typed.define_envelope(17, envelope).is_err());
+        let note = SoundCommand::note(0, Amplitude::Envelope(0), 0, None);
+        assert!(typed.enqueue(note).is_err());
+        let note = SoundCommand::note(1, Amplitude::Volume(8), 0, None);
+        typed.enqueue(note.with_flush().with_hold()).unwrap();
+        assert_eq!(typed.notes(1).last().unwrap().amplitude, -8);
+    }
+
+    #[test]
+    fn test_osword_blocks() {
+        let command = SoundCommand::from_params(0x1213, -7, 200, -1).unwrap();
+        assert_eq!((command.hold, command.sync, command.flush), (true, 2, true));
+        assert_eq!(command.channel_word(), 0x1213);
+        let block = command.to_osword();
+        assert_eq!(block, [0x13, 0x12, 0xF9, 0xFF, 200, 0, 0xFF, 0xFF]);
+        assert_eq!(SoundCommand::from_osword(&block).unwrap(), command);
+
+        let mut sound = SoundSystem::new();
+        let note = SoundCommand::note(3, Amplitude::Volume(10), 100, Some(5));
+        sound.osword(7, &note.to_osword()).unwrap();
+        assert_eq!(sound.notes(3)[0].amplitude, -10);
+
+        // ENVELOPE 1,1,-1,0,0,4,0,0,126,-4,0,-126,126,100
+        let block = [1, 1, 0xFF, 0, 0, 4, 0, 0, 126, 0xFC, 0, 0x82, 126, 100];
+        sound.osword(8, &block).unwrap();
+        let envelope = sound.get_envelope(1).unwrap();
+        assert_eq!(envelope.pitch_changes, [-1, 0, 0]);
+        assert_eq!(envelope.rates, [126, -4, 0, -126]);
+        assert_eq!(envelope.decay_target, 100);
+        assert!(sound.osword(7, &[0; 3]).is_err());
+        assert!(sound.osword(9, &[]).is_err());
+    }
+
+    #[cfg(feature = "sound")]
+    #[test]
+    fn test_wav_header() {
+        let mut sound = SoundSystem::new();
+        sound.sound(1, -15, 53, 1).unwrap();
+        let wav = sound.to_wav();
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(&wav[8..12], b"WAVE");
+        assert_eq!(wav.len(), 44 + SAMPLES_PER_DURATION * 2);
+    }
+
+    #[cfg(feature = "sound")]
+    #[test]
+    fn test_tape_tones() {
+        // Ten bits a byte at 1200 bits a second, after the leader
+        let samples = tape_tones(&[0x00, 0xFF], 0.5);
+        let bit = SAMPLE_RATE as f64 / 1200.0;
+        assert_eq!(samples.len(), ((600 + 20) as f64 * bit).round() as usize);
+
+        // Count the times the wave goes from low to high across a bit
+        let cycles = |bit_index: usize| {
+            let start = (bit_index as f64 * bit).round() as usize;
+            let end = ((bit_index + 1) as f64 * bit).round() as usize;
+            samples[start..end]
+                .windows(2)
+                .filter(|pair| pair[0] < 0 && pair[1] > 0)
+                .count()
+        };
+        // A 0 data bit is one cycle of 1200 Hz, a 1 two cycles of 2400 Hz
+        assert_eq!(cycles(600 + 1), 1);
+        assert_eq!(cycles(600 + 11), 2);
+        assert!(tape_tones(&[], 0.0).is_empty());
+    }
+}
@@ -0,0 +1,233 @@
+//! Speech for BBC BASIC (*SAY), after the Acorn Speech System
+//!
+//! `*SAY text` queues a phrase to be spoken, as SOUND queues a note: the
+//! program carries on at once, and phrases are spoken one after another in
+//! the order they were queued. Speaking is left to a [`Speaker`], so front
+//! ends can plug in their own text-to-speech; the default is silent, and the
+//! `speech` feature adds `HostSpeaker`, which speaks through the host's
+//! own speech program (`say`, `espeak-ng`, `espeak` or `spd-say`).
+//!
+//! Every phrase is also kept in a transcript, so programs that talk can be
+//! tested without listening to them.
+
+use std::fmt;
+
+/// Somewhere phrases are spoken
+pub trait Speaker: Send + fmt::Debug {
+    /// Queue a phrase to be spoken after any already queued, without
+    /// waiting for it
+    fn say(&mut self, phrase: &str) -> Result<(), String>;
+
+    /// Stop speaking and forget any phrases still queued
+    fn flush(&mut self) {}
+}
+
+/// A speaker that says nothing (the default)
+#[derive(Debug, Default)]
+pub struct SilentSpeaker;
+
+impl Speaker for SilentSpeaker {
+    fn say(&mut self, _phrase: &str) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// The phrases spoken by a program, and the speaker that speaks them
+#[derive(Debug)]
+pub struct Speech {
+    speaker: Box<dyn Speaker>,
+    transcript: Vec<String>,
+}
+
+impl Speech {
+    /// Speech with the silent speaker
+    pub fn new() -> Self {
+        Self {
+            speaker: Box::new(SilentSpeaker),
+            transcript: Vec::new(),
+        }
+    }
+
+    /// Speak through `speaker` from now on
+    pub fn set_speaker(&mut self, speaker: Box<dyn Speaker>) {
+        self.speaker = speaker;
+    }
+
+    /// Queue a phrase (*SAY), given with or without quotes
+    pub fn say(&mut self, text: &str) -> Result<(), String> {
+        let text = text.trim();
+        let phrase = text
+            .strip_prefix('"')
+            .and_then(|text| text.strip_suffix('"'))
+            .unwrap_or(text);
+        if phrase.is_empty() {
+            return Ok(());
+        }
+        self.speaker.say(phrase)?;
+        self.transcript.push(phrase.to_string());
+        Ok(())
+    }
+
+    /// Stop speaking and forget the phrases still queued
+    pub fn flush(&mut self) {
+        self.speaker.flush();
+    }
+
+    /// Every phrase queued so far, in order
+    pub fn transcript(&self) -> &[String] {
+        &self.transcript
+    }
+}
+
+impl Default for Speech {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "speech")]
+pub use host::HostSpeaker;
+
+#[cfg(feature = "speech")]
+mod host {
+    use super::Speaker;
+    use std::process::{Child, Command, Stdio};
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// Host speech programs, tried in turn, each taking the phrase as its
+    /// last argument
+    const PROGRAMS: &[&str] = &["say", "espeak-ng", "espeak", "spd-say"];
+
+    /// Speaks through a host speech program, one phrase at a time on a
+    /// thread of its own
+    #[derive(Debug)]
+    pub struct HostSpeaker {
+        phrases: Sender<String>,
+        /// The program speaking now, so a flush can stop it
+        speaking: Arc<Mutex<Option<Child>>>,
+        /// Phrases queued behind it, forgotten by a flush
+        queued: Arc<Mutex<usize>>,
+    }
+
+    impl HostSpeaker {
+        /// Speak through the first of the known speech programs found on
+        /// the PATH, or None if there is none
+        pub fn detect() -> Option<Self> {
+            let path = std::env::var_os("PATH")?;
+            let found = |program: &&&str| {
+                std::env::split_paths(&path).any(|dir| dir.join(program).is_file())
+            };
+            PROGRAMS
+                .iter()
+                .find(found)
+                .map(|program| Self::new(program))
+        }
+
+        /// Speak through `program`, which is given each phrase as its last
+        /// argument
+        pub fn new(program: &str) -> Self {
+            let (phrases, received) = mpsc::channel();
+            let speaking = Arc::new(Mutex::new(None));
+            let queued = Arc::new(Mutex::new(0));
+            let program = program.to_string();
+            let (worker_speaking, worker_queued) = (speaking.clone(), queued.clone());
+            thread::spawn(move || speak(&program, received, &worker_speaking, &worker_queued));
+            Self {
+                phrases,
+                speaking,
+                queued,
+            }
+        }
+    }
+
+    impl Speaker for HostSpeaker {
+        fn say(&mut self, phrase: &str) -> Result<(), String> {
+            *self.queued.lock().unwrap() += 1;
+            self.phrases
+                .send(phrase.to_string())
+                .map_err(|_| "Speech has stopped".to_string())
+        }
+
+        fn flush(&mut self) {
+            *self.queued.lock().unwrap() = 0;
+            if let Some(child) = self.speaking.lock().unwrap().as_mut() {
+                let _ = child.kill();
+            }
+        }
+    }
+
+    /// Speak each phrase received in turn, skipping those flushed
+    fn speak(
+        program: &str,
+        phrases: Receiver<String>,
+        speaking: &Mutex<Option<Child>>,
+        queued: &Mutex<usize>,
+    ) {
+        for phrase in phrases {
+            {
+                let mut queued = queued.lock().unwrap();
+                if *queued == 0 {
+                    continue;
+                }
+                *queued -= 1;
+            }
+            let child = Command::new(program)
+                .arg(&phrase)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn();
+            let Ok(child) = child else {
+                continue;
+            };
+            *speaking.lock().unwrap() = Some(child);
+            // Poll, so that a flush can kill the program while it speaks
+            loop {
+                let mut speaking = speaking.lock().unwrap();
+                match speaking.as_mut().map(Child::try_wait) {
+                    Some(Ok(None)) => {}
+                    _ => {
+                        *speaking = None;
+                        break;
+                    }
+                }
+                drop(speaking);
+                thread::sleep(std::time::Duration::from_millis(20));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// A speaker that writes down what it is asked to say
+    #[derive(Debug, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Speaker for Recorder {
+        fn say(&mut self, phrase: &str) -> Result<(), String> {
+            self.0.lock().unwrap().push(phrase.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_speech_queue() {
+        let mut speech = Speech::new();
+        speech.say("\"HELLO THERE\"").unwrap();
+        speech.say("  GOODBYE ").unwrap();
+        speech.say("").unwrap();
+        assert_eq!(speech.transcript(), ["HELLO THERE", "GOODBYE"]);
+
+        let said = Arc::new(Mutex::new(Vec::new()));
+        speech.set_speaker(Box::new(Recorder(said.clone())));
+        speech.say("ONE").unwrap();
+        speech.say("TWO").unwrap();
+        assert_eq!(*said.lock().unwrap(), ["ONE", "TWO"]);
+        assert_eq!(speech.transcript().len(), 4);
+    }
+}
@@ -0,0 +1,646 @@
+//! Structured program transformation for BBC BASIC
+//!
+//! Rewrites simple GOTO and GOSUB patterns in type-in listings into
+//! REPEAT...UNTIL, WHILE...ENDWHILE and PROC structures. Each rewrite is only
+//! made when the analysis of the parsed program shows it cannot change what
+//! the program does: the region has a single entry, nothing inside it jumps
+//! out, and any loops inside it are balanced.
+
+use crate::error::{BBCBasicError, Result};
+use crate::parser::{parse_statement, Dialect, Statement};
+use crate::program::ProgramStore;
+use crate::tokenizer::{Token, TokenizedLine};
+use std::fmt;
+
+/// A rewrite made by `structure_program`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rewrite {
+    /// A backward conditional GOTO became REPEAT...UNTIL
+    RepeatUntil { start: u16, end: u16 },
+    /// A test-at-the-top GOTO loop became WHILE...ENDWHILE
+    WhileEndWhile { start: u16, end: u16 },
+    /// A GOSUB subroutine became a procedure
+    Procedure { start: u16, end: u16, name: String },
+}
+
+impl fmt::Display for Rewrite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rewrite::RepeatUntil { start, end } => {
+                write!(f, "Lines {}-{}: GOTO loop -> REPEAT...UNTIL", start, end)
+            }
+            Rewrite::WhileEndWhile { start, end } => {
+                write!(f, "Lines {}-{}: GOTO loop -> WHILE...ENDWHILE", start, end)
+            }
+            Rewrite::Procedure { start, end, name } => {
+                write!(
+                    f,
+                    "Lines {}-{}: GOSUB {} -> PROC{}",
+                    start, end, start, name
+                )
+            }
+        }
+    }
+}
+
+/// How a statement refers to a line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Jump {
+    Goto,
+    Gosub,
+    /// ON GOTO, ON GOSUB and ON ERROR, which cannot be rewritten
+    Computed,
+}
+
+/// Rewrite GOTO/GOSUB patterns in a program into structured statements
+///
+/// Returns the new program and the rewrites made. WHILE loops are only
+/// introduced for BASIC V. Fails if any line does not parse, since its
+/// jumps could not be accounted for.
+pub fn structure_program(
+    program: &ProgramStore,
+    dialect: Dialect,
+) -> Result<(ProgramStore, Vec<Rewrite>)> {
+    let mut program = program.clone();
+    let mut rewrites = Vec::new();
+    // UNDO takes back all the rewrites at once
+    program.begin_edit();
+
+    // Each rewrite changes the jumps in the program, so re-analyse after each
+    loop {
+        let analysis = Analysis::new(&program)?;
+        let rewrite = analysis
+            .find_while(dialect)
+            .or_else(|| analysis.find_repeat());
+        let rewrite = rewrite.or_else(|| analysis.find_procedure());
+        match rewrite {
+            Some((rewrite, edits)) => {
+                for edit in edits {
+                    program.store_line(edit);
+                }
+                rewrites.push(rewrite);
+            }
+            None => break,
+        }
+    }
+    program.end_edit();
+
+    Ok((program, rewrites))
+}
+
+/// Parsed program with its jumps
+struct Analysis<'a> {
+    program: &'a ProgramStore,
+    /// Line numbers in order
+    numbers: Vec<u16>,
+    /// Statement on each line
+    statements: Vec<Statement>,
+    /// (source line, target line, kind) for every jump
+    jumps: Vec<(u16, u16, Jump)>,
+}
+
+/// A rewrite and the lines it stores
+type Edit = (Rewrite, Vec<TokenizedLine>);
+
+impl<'a> Analysis<'a> {
+    fn new(program: &'a ProgramStore) -> Result<Self> {
+        let mut numbers = Vec::new();
+        let mut statements = Vec::new();
+        let mut jumps = Vec::new();
+
+        for (number, line) in program.list() {
+            let statement = parse_statement(line).map_err(|e| match e {
+                BBCBasicError::SyntaxError { message, .. } => BBCBasicError::SyntaxError {
+                    message,
+                    line: Some(number),
+                },
+                e => e,
+            })?;
+            let mut targets = Vec::new();
+            collect_jumps(&statement, &mut targets);
+            jumps.extend(
+                targets
+                    .into_iter()
+                    .map(|(target, kind)| (number, target, kind)),
+            );
+            numbers.push(number);
+            statements.push(statement);
+        }
+
+        Ok(Self {
+            program,
+            numbers,
+            statements,
+            jumps,
+        })
+    }
+
+    /// Tokens of a line
+    fn tokens(&self, index: usize) -> &[Token] {
+        self.program
+            .get_line(self.numbers[index])
+            .map_or(&[], |line| line.tokens.as_slice())
+    }
+
+    /// Jumps to a line
+    fn jumps_to(&self, line: u16) -> impl Iterator<Item = &(u16, u16, Jump)> {
+        self.jumps
+            .iter()
+            .filter(move |(_, target, _)| *target == line)
+    }
+
+    /// Whether any jump lands strictly inside `start..=end` other than at `start`
+    fn entered_midway(&self, start: u16, end: u16) -> bool {
+        self.jumps
+            .iter()
+            .any(|(_, target, _)| *target > start && *target <= end)
+    }
+
+    /// Whether lines `from..to` (by index) only ever fall through to the next line
+    fn straight_line(&self, from: usize, to: usize) -> bool {
+        let body = &self.statements[from..to];
+        body.iter().all(|statement| !transfers_control(statement)) && balanced(body)
+    }
+
+    /// A free line number just before the line at `index`, for inserting a statement
+    fn free_line_before(&self, index: usize) -> Option<u16> {
+        let number = self.numbers[index].checked_sub(1)?;
+        match index.checked_sub(1) {
+            Some(previous) if self.numbers[previous] >= number => None,
+            _ => Some(number),
+        }
+    }
+
+    /// `IF cond THEN GOTO back` at the foot of a loop -> REPEAT ... UNTIL NOT cond
+    fn find_repeat(&self) -> Option<Edit> {
+        for (end, statement) in self.statements.iter().enumerate() {
+            let Some(target) = conditional_goto(statement) else {
+                continue;
+            };
+            let end_line = self.numbers[end];
+            let Some(start) = self.numbers.iter().position(|&n| n == target) else {
+                continue;
+            };
+            if target >= end_line
+                || self.jumps_to(target).count() != 1
+                || self.entered_midway(target, end_line)
+                || !self.straight_line(start, end)
+            {
+                continue;
+            }
+            let Some(repeat_line) = self.free_line_before(start) else {
+                continue;
+            };
+
+            let mut until = vec![Token::Keyword(0xFD)];
+            until.extend(negate(condition_tokens(self.tokens(end))));
+            let edits = vec![
+                TokenizedLine::new(Some(repeat_line), vec![Token::Keyword(0xF5)]),
+                TokenizedLine::new(Some(end_line), until),
+            ];
+            let rewrite = Rewrite::RepeatUntil {
+                start: target,
+                end: end_line,
+            };
+            return Some((rewrite, edits));
+        }
+        None
+    }
+
+    /// `IF cond THEN GOTO exit` at the head of a loop closed by `GOTO head`
+    /// -> WHILE NOT cond ... ENDWHILE
+    fn find_while(&self, dialect: Dialect) -> Option<Edit> {
+        if dialect == Dialect::BasicII {
+            return None;
+        }
+
+        for (start, statement) in self.statements.iter().enumerate() {
+            let Some(exit) = conditional_goto(statement) else {
+                continue;
+            };
+            let start_line = self.numbers[start];
+            let Some(end) = (start + 1..self.statements.len()).find(|&i| {
+                matches!(self.statements[i], Statement::Goto { line_number } if line_number == start_line)
+            }) else {
+                continue;
+            };
+            let end_line = self.numbers[end];
+            if self.numbers.get(end + 1) != Some(&exit)
+                || self.jumps_to(start_line).count() != 1
+                || self.entered_midway(start_line, end_line)
+                || !self.straight_line(start + 1, end)
+            {
+                continue;
+            }
+
+            let mut head = vec![Token::ExtendedKeyword(0xC8, 0x95)];
+            head.extend(negate(condition_tokens(self.tokens(start))));
+            let edits = vec![
+                TokenizedLine::new(Some(start_line), head),
+                TokenizedLine::new(Some(end_line), vec![Token::ExtendedKeyword(0xC8, 0xA4)]),
+            ];
+            let rewrite = Rewrite::WhileEndWhile {
+                start: start_line,
+                end: end_line,
+            };
+            return Some((rewrite, edits));
+        }
+        None
+    }
+
+    /// A subroutine only reached by GOSUB, ending at its first RETURN -> DEF PROC
+    fn find_procedure(&self) -> Option<Edit> {
+        let mut targets: Vec<u16> = self
+            .jumps
+            .iter()
+            .filter(|(_, _, kind)| *kind == Jump::Gosub)
+            .map(|(_, target, _)| *target)
+            .collect();
+        targets.sort_unstable();
+        targets.dedup();
+
+        for target in targets {
+            if self
+                .jumps_to(target)
+                .any(|(_, _, kind)| *kind != Jump::Gosub)
+            {
+                continue;
+            }
+            let Some(start) = self.numbers.iter().position(|&n| n == target) else {
+                continue;
+            };
+            // Execution must not be able to fall into the subroutine
+            let Some(previous) = start.checked_sub(1) else {
+                continue;
+            };
+            if !ends_flow(&self.statements[previous]) {
+                continue;
+            }
+            let Some(end) = (start..self.statements.len())
+                .find(|&i| matches!(self.statements[i], Statement::Return { value: None }))
+            else {
+                continue;
+            };
+            let end_line = self.numbers[end];
+            let name = format!("sub{}", target);
+            let name_taken = self
+                .statements
+                .iter()
+                .any(|s| matches!(s, Statement::DefProc { name: n, .. } if *n == name));
+            if name_taken
+                || self.entered_midway(target, end_line)
+                || !self.straight_line(start, end)
+            {
+                continue;
+            }
+            let Some(def_line) = self.free_line_before(start) else {
+                continue;
+            };
+
+            let mut edits = vec![
+                TokenizedLine::new(
+                    Some(def_line),
+                    vec![
+                        Token::Keyword(0xDD),
+                        Token::Keyword(0xF2),
+                        Token::Identifier(name.clone()),
+                    ],
+                ),
+                TokenizedLine::new(Some(end_line), vec![Token::Keyword(0xE1)]),
+            ];
+            // Replace every GOSUB to the subroutine with a PROC call
+            for (index, &number) in self.numbers.iter().enumerate() {
+                if !self
+                    .jumps
+                    .iter()
+                    .any(|&(from, to, _)| from == number && to == target)
+                {
+                    continue;
+                }
+                let mut tokens = Vec::new();
+                let mut iter = self.tokens(index).iter().peekable();
+                while let Some(token) = iter.next() {
+                    if *token == Token::Keyword(0xE4)
+                        && iter.peek() == Some(&&Token::Integer(target as i32))
+                    {
+                        iter.next();
+                        tokens.push(Token::Keyword(0xF2));
+                        tokens.push(Token::Identifier(name.clone()));
+                    } else {
+                        tokens.push(token.clone());
+                    }
+                }
+                edits.push(TokenizedLine::new(Some(number), tokens));
+            }
+
+            let rewrite = Rewrite::Procedure {
+                start: target,
+                end: end_line,
+                name,
+            };
+            return Some((rewrite, edits));
+        }
+        None
+    }
+}
+
+/// Collect the lines a statement can jump to
+fn collect_jumps(statement: &Statement, targets: &mut Vec<(u16, Jump)>) {
+    match statement {
+        Statement::Goto { line_number } => targets.push((*line_number, Jump::Goto)),
+        Statement::Gosub { line_number } => targets.push((*line_number, Jump::Gosub)),
+        Statement::OnError { line_number } => targets.push((*line_number, Jump::Computed)),
+        Statement::OnGoto { targets: lines, .. } | Statement::OnGosub { targets: lines, .. } => {
+            targets.extend(lines.iter().map(|&line| (line, Jump::Computed)))
+        }
+        Statement::If {
+            then_part,
+            else_part,
+            ..
+        } => {
+            for statement in then_part.iter().chain(else_part.iter().flatten()) {
+                collect_jumps(statement, targets);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Whether a statement can pass control anywhere but the next line (GOSUB
+/// and PROC calls return, so they do not count)
+fn transfers_control(statement: &Statement) -> bool {
+    match statement {
+        Statement::Goto { .. }
+        | Statement::OnGoto { .. }
+        | Statement::OnGosub { .. }
+        | Statement::OnError { .. }
+        | Statement::Return { .. }
+        | Statement::End
+        | Statement::Stop
+        | Statement::Quit
+        | Statement::EndProc
+        | Statement::DefProc { .. }
+        | Statement::DefFn { .. }
+        | Statement::IfBlock { .. }
+        | Statement::Else
+        | Statement::EndIf => true,
+        Statement::If {
+            then_part,
+            else_part,
+            ..
+        } => then_part
+            .iter()
+            .chain(else_part.iter().flatten())
+            .any(transfers_control),
+        _ => false,
+    }
+}
+
+/// Whether a statement never falls through to the next line
+fn ends_flow(statement: &Statement) -> bool {
+    matches!(
+        statement,
+        Statement::Goto { .. }
+            | Statement::Return { .. }
+            | Statement::End
+            | Statement::Stop
+            | Statement::Quit
+            | Statement::EndProc
+    )
+}
+
+/// Whether every FOR, REPEAT and WHILE in a run of statements is closed within it
+fn balanced(statements: &[Statement]) -> bool {
+    let mut depths = [0i32; 3];
+    for statement in statements {
+        let (kind, change) = match statement {
+            Statement::For { .. } => (0, 1),
+            Statement::Next { .. } => (0, -1),
+            Statement::Repeat => (1, 1),
+            Statement::Until { .. } => (1, -1),
+            Statement::While { .. } => (2, 1),
+            Statement::EndWhile => (2, -1),
+            _ => continue,
+        };
+        depths[kind] += change;
+        if depths[kind] < 0 {
+            return false;
+        }
+    }
+    depths == [0; 3]
+}
+
+/// The target of `IF cond THEN GOTO line` with no ELSE
+fn conditional_goto(statement: &Statement) -> Option<u16> {
+    match statement {
+        Statement::If {
+            then_part,
+            else_part: None,
+            ..
+        } => match then_part.as_slice() {
+            [Statement::Goto { line_number }] => Some(*line_number),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The condition tokens of an IF line (between IF and THEN)
+fn condition_tokens(tokens: &[Token]) -> &[Token] {
+    let then = tokens
+        .iter()
+        .position(|t| *t == Token::Keyword(0x8C))
+        .unwrap_or(tokens.len());
+    &tokens[1.min(then)..then]
+}
+
+/// Tokens for the logical inverse of a condition
+///
+/// A single comparison is inverted in place (A<B becomes A>=B); anything
+/// else becomes (cond)=0, which is true exactly when IF would skip.
+fn negate(condition: &[Token]) -> Vec<Token> {
+    let mut depth = 0;
+    let mut comparisons = Vec::new();
+    let mut logical = false;
+    for (i, token) in condition.iter().enumerate() {
+        match token {
+            Token::Separator('(') => depth += 1,
+            Token::Separator(')') => depth -= 1,
+            Token::Operator('<' | '>' | '=') if depth == 0 => comparisons.push(i),
+            // AND, OR, EOR
+            Token::Keyword(0x80 | 0x82 | 0x84) if depth == 0 => logical = true,
+            _ => {}
+        }
+    }
+
+    let operator: String = comparisons
+        .iter()
+        .filter_map(|&i| match condition[i] {
+            Token::Operator(op) => Some(op),
+            _ => None,
+        })
+        .collect();
+    let adjacent = comparisons.windows(2).all(|pair| pair[1] == pair[0] + 1);
+    let inverse = match operator.as_str() {
+        "<" => Some(">="),
+        ">" => Some("<="),
+        "<=" => Some(">"),
+        ">=" => Some("<"),
+        _ => None,
+    };
+
+    match inverse {
+        Some(inverse) if adjacent && !logical => {
+            let first = comparisons[0];
+            let last = comparisons[comparisons.len() - 1];
+            let mut tokens = condition[..first].to_vec();
+            tokens.extend(inverse.chars().map(Token::Operator));
+            tokens.extend_from_slice(&condition[last + 1..]);
+            tokens
+        }
+        _ => {
+            let mut tokens = vec![Token::Separator('(')];
+            tokens.extend_from_slice(condition);
+            tokens.push(Token::Separator(')'));
+            tokens.push(Token::Operator('='));
+            tokens.push(Token::Integer(0));
+            tokens
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::{detokenize, tokenize};
+
+    fn program(lines: &[&str]) -> ProgramStore {
+        let mut program = ProgramStore::new();
+        for line in lines {
+            program.store_line(tokenize(line).unwrap());
+        }
+        program
+    }
+
+    fn listing(program: &ProgramStore) -> Vec<String> {
+        program
+            .list()
+            .into_iter()
+            .map(|(_, line)| detokenize(line).unwrap())
+            .collect()
+    }
+
+    /// Expected listing, spaced the way detokenize spaces it
+    fn expected(lines: &[&str]) -> Vec<String> {
+        listing(&program(lines))
+    }
+
+    #[test]
+    fn test_repeat_until() {
+        let source = program(&[
+            "10 I% = 0",
+            "20 I% = I% + 1",
+            "30 PRINT I%",
+            "40 IF I% < 10 THEN GOTO 20",
+            "50 END",
+        ]);
+        let (result, rewrites) = structure_program(&source, Dialect::BasicV).unwrap();
+        assert_eq!(rewrites, vec![Rewrite::RepeatUntil { start: 20, end: 40 }]);
+        assert_eq!(
+            listing(&result),
+            expected(&[
+                "10 I% = 0",
+                "19 REPEAT",
+                "20 I% = I% + 1",
+                "30 PRINT I%",
+                "40 UNTIL I% >= 10",
+                "50 END",
+            ])
+        );
+
+        // The structured program runs the loop ten times
+        let mut interpreter = crate::Interpreter::new();
+        *interpreter.program_mut() = result;
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.executor().get_variable_int("I%").unwrap(), 10);
+    }
+
+    #[test]
+    fn test_while_endwhile() {
+        let source = program(&[
+            "10 IF X% = 5 THEN GOTO 50",
+            "20 X% = X% + 1",
+            "30 GOTO 10",
+            "50 PRINT X%",
+        ]);
+        let (result, rewrites) = structure_program(&source, Dialect::BasicV).unwrap();
+        assert_eq!(
+            rewrites,
+            vec![Rewrite::WhileEndWhile { start: 10, end: 30 }]
+        );
+        assert_eq!(
+            listing(&result),
+            expected(&[
+                "10 WHILE (X% = 5) = 0",
+                "20 X% = X% + 1",
+                "30 ENDWHILE",
+                "50 PRINT X%"
+            ])
+        );
+
+        // BASIC II has no WHILE, so the loop is left alone
+        let (_, rewrites) = structure_program(&source, Dialect::BasicII).unwrap();
+        assert!(rewrites.is_empty());
+    }
+
+    #[test]
+    fn test_gosub_to_procedure() {
+        let source = program(&[
+            "10 GOSUB 100",
+            "20 IF A% > 1 THEN GOSUB 100",
+            "30 END",
+            "100 A% = A% + 1",
+            "110 RETURN",
+        ]);
+        let (result, rewrites) = structure_program(&source, Dialect::BasicII).unwrap();
+        assert_eq!(
+            rewrites,
+            vec![Rewrite::Procedure {
+                start: 100,
+                end: 110,
+                name: "sub100".to_string()
+            }]
+        );
+        assert_eq!(
+            listing(&result),
+            expected(&[
+                "10 PROCsub100",
+                "20 IF A% > 1 THEN PROCsub100",
+                "30 END",
+                "99 DEF PROCsub100",
+                "100 A% = A% + 1",
+                "110 ENDPROC",
+            ])
+        );
+    }
+
+    #[test]
+    fn test_unsafe_patterns_left_alone() {
+        // Line 30 is also reached by GOTO, and the loop body is entered midway
+        let source = program(&[
+            "10 GOSUB 30",
+            "20 GOTO 30",
+            "30 X% = X% + 1",
+            "40 IF X% < 3 THEN GOTO 20",
+            "50 RETURN",
+        ]);
+        let (result, rewrites) = structure_program(&source, Dialect::BasicV).unwrap();
+        assert!(rewrites.is_empty());
+        assert_eq!(listing(&result), listing(&source));
+
+        // A subroutine that can be fallen into is not a procedure
+        let source = program(&["10 GOSUB 20", "20 PRINT", "30 RETURN"]);
+        let (_, rewrites) = structure_program(&source, Dialect::BasicV).unwrap();
+        assert!(rewrites.is_empty());
+    }
+}
@@ -3,8 +3,10 @@
 //! Converts BBC BASIC source code into internal token representation compatible
 //! with the original BBC Micro tokenized format.
 
-use crate::error::Result;
+use crate::error::{BBCBasicError, Result};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::ops::Range;
 
 /// Represents a single token in BBC BASIC
 #[derive(Debug, Clone, PartialEq)]
@@ -23,6 +25,8 @@ pub enum Token {
     String(String),
     /// Variable or procedure name
     Identifier(String),
+    /// The untokenized text of DATA, REM or a star command
+    Text(String),
     /// Operators (+, -, *, etc.)
     Operator(char),
     /// Separators (,, ;, :)
@@ -54,13 +58,83 @@ impl TokenizedLine {
             tokens: Vec::new(),
         }
     }
+
+    /// Bytes the line takes in a BBC Micro program: a carriage return, the
+    /// line number and a length byte, then the tokenized text
+    ///
+    /// Spaces are not kept in tokens, so this can be a little less than the
+    /// same line typed on a Model B.
+    pub fn size(&self) -> usize {
+        let text: usize = self
+            .tokens
+            .iter()
+            .map(|token| match token {
+                Token::Keyword(_) | Token::Operator(_) | Token::Separator(_) => 1,
+                Token::ExtendedKeyword(..) => 2,
+                // &8D and the line number in three bytes
+                Token::LineNumber(_) => 4,
+                // Numbers are kept as their text
+                Token::Integer(value) => value.to_string().len(),
+                Token::Real(value) => real_text(*value).len(),
+                Token::String(text) => text.chars().count() + 2,
+                Token::Identifier(name) => name.len(),
+                Token::Text(text) => text.chars().count(),
+                Token::EndOfLine => 0,
+            })
+            .sum();
+        4 + text
+    }
+}
+
+/// Case used for keywords when a program is listed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeywordCase {
+    /// PRINT, GOTO (as on the BBC Micro)
+    #[default]
+    Upper,
+    /// print, goto
+    Lower,
+}
+
+/// Options controlling how source text is tokenized and listed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenizerOptions {
+    /// Accept keywords in lowercase or mixed case (real BBC BASIC only
+    /// recognises uppercase, so `print` would be a variable name)
+    pub case_insensitive_keywords: bool,
+    /// Case used for keywords by detokenize
+    pub keyword_case: KeywordCase,
+}
+
+impl Default for TokenizerOptions {
+    fn default() -> Self {
+        Self {
+            case_insensitive_keywords: true,
+            keyword_case: KeywordCase::Upper,
+        }
+    }
 }
 
 /// Tokenize a BBC BASIC source line
 pub fn tokenize(source_line: &str) -> Result<TokenizedLine> {
+    tokenize_with_options(source_line, &TokenizerOptions::default())
+}
+
+/// Tokenize a BBC BASIC source line with the given options
+pub fn tokenize_with_options(
+    source_line: &str,
+    options: &TokenizerOptions,
+) -> Result<TokenizedLine> {
     let mut tokens = Vec::new();
     let mut line_number = None;
     let (keyword_map, extended_map) = create_keyword_maps();
+    let longest_keyword = keyword_map
+        .keys()
+        .chain(extended_map.keys())
+        .map(String::len)
+        .max()
+        .unwrap_or(0);
 
     // Trim the input
     let line = source_line.trim();
@@ -101,10 +175,12 @@ pub fn tokenize(source_line: &str) -> Result<TokenizedLine> {
                 {
                     temp_chars.next();
                 }
-                // Check if what follows looks like a statement (keyword or identifier, not operator)
+                // Check if what follows looks like a statement (keyword or
+                // identifier, an indirection operator assigned to, or a star
+                // command)
                 let next_is_statement = temp_chars
                     .peek()
-                    .map(|c| c.is_alphabetic() || *c == '_')
+                    .map(|c| c.is_alphabetic() || matches!(c, '_' | '?' | '!' | '$' | '@' | '*'))
                     .unwrap_or(false);
 
                 if next_is_statement {
@@ -148,7 +224,7 @@ pub fn tokenize(source_line: &str) -> Result<TokenizedLine> {
             chars.next(); // consume opening quote
             let mut string_content = String::new();
 
-            while let Some(ch) = chars.next() {
+            for ch in chars.by_ref() {
                 if ch == '"' {
                     break; // found closing quote
                 }
@@ -159,14 +235,12 @@ pub fn tokenize(source_line: &str) -> Result<TokenizedLine> {
             continue;
         }
 
-        // Numbers (integer or real, including negative)
+        // Numbers (integer or real, with an exponent, and negative where a
+        // minus cannot be subtracting from what comes before it)
+        let starts_number = |c: Option<char>| c.is_some_and(|c| c.is_ascii_digit() || c == '.');
         if ch.is_ascii_digit()
-            || (ch == '-'
-                && chars
-                    .clone()
-                    .nth(1)
-                    .map(|c| c.is_ascii_digit())
-                    .unwrap_or(false))
+            || (ch == '.' && chars.clone().nth(1).is_some_and(|c| c.is_ascii_digit()))
+            || (ch == '-' && starts_number(chars.clone().nth(1)) && !ends_operand(tokens.last()))
         {
             let mut num_str = String::new();
             let mut is_real = false;
@@ -177,83 +251,152 @@ pub fn tokenize(source_line: &str) -> Result<TokenizedLine> {
                 chars.next();
             }
 
-            // Collect digits before decimal point
+            // Digits, with a decimal point among them (2.5, .5 and 2.)
             while let Some(&ch) = chars.peek() {
                 if ch.is_ascii_digit() {
                     num_str.push(ch);
                     chars.next();
-                } else if ch == '.' {
-                    // Check if the next character is a digit (to distinguish from range operator)
-                    let mut temp_chars = chars.clone();
-                    temp_chars.next(); // skip the '.'
-                    if temp_chars
-                        .peek()
-                        .map(|c| c.is_ascii_digit())
-                        .unwrap_or(false)
-                    {
-                        is_real = true;
-                        num_str.push(ch);
-                        chars.next(); // consume the '.'
-                    } else {
-                        break;
-                    }
+                } else if ch == '.' && !is_real {
+                    is_real = true;
+                    num_str.push(ch);
+                    chars.next();
                 } else {
                     break;
                 }
             }
 
-            // Parse the number
+            // An exponent (1E10, 1.5E-3) when E is followed by digits
+            if chars.peek() == Some(&'E') {
+                let mut ahead = chars.clone();
+                let mut exponent: String = ahead.next().into_iter().collect();
+                exponent.extend(ahead.next_if(|c| matches!(c, '+' | '-')));
+                if ahead.peek().is_some_and(char::is_ascii_digit) {
+                    while let Some(digit) = ahead.next_if(char::is_ascii_digit) {
+                        exponent.push(digit);
+                    }
+                    is_real = true;
+                    num_str.push_str(&exponent);
+                    chars = ahead;
+                }
+            }
+
+            // Parse the number: "Too big" beyond the range of its type
             if is_real {
-                if let Ok(val) = num_str.parse::<f64>() {
-                    tokens.push(Token::Real(val));
+                let num_str = num_str.trim_end_matches('.');
+                match num_str.parse::<f64>() {
+                    Ok(val) if val.is_finite() => tokens.push(Token::Real(val)),
+                    _ => return Err(BBCBasicError::TooBig),
                 }
             } else {
-                if let Ok(val) = num_str.parse::<i32>() {
-                    tokens.push(Token::Integer(val));
-                }
+                let val = num_str.parse::<i32>().map_err(|_| BBCBasicError::TooBig)?;
+                tokens.push(Token::Integer(val));
             }
             continue;
         }
 
-        // Keywords and identifiers
-        if ch.is_alphabetic() || ch == '_' {
-            let mut word = String::new();
+        // @%, the PRINT format variable
+        if ch == '@' && chars.clone().nth(1) == Some('%') {
+            chars.nth(1);
+            tokens.push(Token::Identifier("@%".to_string()));
+            continue;
+        }
 
-            // Collect alphanumeric characters, underscores, % (integer), $ (string)
-            while let Some(&ch) = chars.peek() {
-                if ch.is_alphanumeric() || ch == '_' || ch == '%' || ch == '$' {
-                    word.push(ch);
-                    chars.next();
-                } else {
-                    break;
+        // Keywords and names
+        if ch.is_ascii_alphabetic() || ch == '_' {
+            // Enough of the line for the longest keyword and the character
+            // after it, without copying the rest of a long line each time
+            let start: String = chars.clone().take(longest_keyword + 1).collect();
+
+            // Keywords may run straight into names and numbers (FORI=1TO10),
+            // so match the longest keyword at the start of the remaining text
+            let (token, length) = match match_keyword_prefix(&start, &keyword_map, &extended_map) {
+                Some(matched) => matched,
+                None => {
+                    let mut word: String = chars.clone().take_while(|&c| is_name_char(c)).collect();
+                    if let Some(suffix @ ('%' | '$')) = chars.clone().nth(word.len()) {
+                        word.push(suffix);
+                    }
+                    // Lowercase or mixed-case keywords are only recognised
+                    // as whole words, so `fori` stays a variable name
+                    let upper_word = word.to_ascii_uppercase();
+                    let keyword = if options.case_insensitive_keywords {
+                        lookup_keyword(&upper_word, &keyword_map, &extended_map)
+                    } else {
+                        None
+                    };
+                    match keyword {
+                        Some(token) => (token, word.len()),
+                        None => (Token::Identifier(word.to_string()), word.len()),
+                    }
                 }
+            };
+            for _ in 0..length {
+                chars.next();
             }
 
-            // Convert to uppercase for keyword matching
-            let upper_word = word.to_uppercase();
-
-            // Check if it's a keyword
-            if let Some(&token_byte) = keyword_map.get(&upper_word) {
-                tokens.push(Token::Keyword(token_byte));
-            } else if let Some(&(prefix, token_byte)) = extended_map.get(&upper_word) {
-                tokens.push(Token::ExtendedKeyword(prefix, token_byte));
-            } else {
-                // It's an identifier (variable, procedure, or function name)
-                tokens.push(Token::Identifier(word));
+            // DATA starting a statement keeps the rest of the line as typed,
+            // and so does REM wherever it is
+            let data = (token == Token::Keyword(0xDC)
+                && matches!(tokens.last(), None | Some(Token::Separator(':'))))
+                || token == Token::Keyword(0xF4);
+            // The name after PROC or FN is never tokenized (PROCend, FNto)
+            let takes_name = matches!(token, Token::Keyword(0xF2) | Token::Keyword(0xA4));
+            tokens.push(token);
+            if data {
+                let text: String = chars.by_ref().collect();
+                if !text.is_empty() || tokens.last() == Some(&Token::Keyword(0xDC)) {
+                    tokens.push(Token::Text(text));
+                }
+                break;
+            }
+            if takes_name {
+                while chars.peek().is_some_and(|c| *c == ' ') {
+                    chars.next();
+                }
+                let mut name = String::new();
+                while let Some(&ch) = chars.peek() {
+                    if is_name_char(ch) {
+                        name.push(ch);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                // A type suffix is part of the name (FNname$)
+                if !name.is_empty() && matches!(chars.peek(), Some('%') | Some('$')) {
+                    name.extend(chars.next());
+                }
+                if !name.is_empty() {
+                    tokens.push(Token::Identifier(name));
+                }
             }
             continue;
         }
 
         // Operators and separators
         match ch {
+            // In PRINT, an apostrophe starts a new line
+            '\'' if in_print(&tokens) => {
+                chars.next();
+                tokens.push(Token::Separator(ch));
+            }
+            // A star command starting a statement gives the rest of the
+            // line to the OS as typed
+            '*' if matches!(tokens.last(), None | Some(Token::Separator(':'))) => {
+                chars.next();
+                tokens.push(Token::Operator(ch));
+                tokens.push(Token::Text(chars.by_ref().collect()));
+            }
             '\'' => {
-                // Apostrophe is shorthand for REM - rest of line is a comment
+                // Elsewhere it is shorthand for REM - rest of line is a comment
                 chars.next(); // consume apostrophe
                 tokens.push(Token::Keyword(0xF4)); // REM token
                 // Consume rest of line (don't tokenize comment text)
                 while chars.next().is_some() {}
             }
-            '+' | '*' | '/' | '^' | '<' | '>' | '=' => {
+            // # marks a file channel (PRINT#, BGET# and so on), ~ asks
+            // PRINT for hex, and ?, ! and $ read and write memory
+            '+' | '*' | '/' | '^' | '<' | '>' | '=' | '#' | '~' | '?' | '!' | '$' => {
                 chars.next();
                 tokens.push(Token::Operator(ch));
             }
@@ -262,13 +405,40 @@ pub fn tokenize(source_line: &str) -> Result<TokenizedLine> {
                 chars.next();
                 tokens.push(Token::Operator(ch));
             }
-            ',' | ';' | ':' | '(' | ')' => {
+            // | ends a VDU statement with nine zero bytes
+            ',' | ';' | ':' | '(' | ')' | '|' => {
                 chars.next();
                 tokens.push(Token::Separator(ch));
             }
-            _ => {
-                // Unknown character, skip it
+            '&' => {
+                // Hexadecimal constant (&FF); values above &7FFFFFFF wrap
+                // to negative integers, as in BBC BASIC
                 chars.next();
+                let mut hex = String::new();
+                while let Some(&ch) = chars.peek() {
+                    if ch.is_ascii_hexdigit() {
+                        hex.push(ch);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                match u32::from_str_radix(&hex, 16) {
+                    Ok(val) => tokens.push(Token::Integer(val as i32)),
+                    Err(_) if hex.is_empty() => {
+                        return Err(BBCBasicError::SyntaxError {
+                            message: "Bad HEX".to_string(),
+                            line: line_number,
+                        })
+                    }
+                    Err(_) => return Err(BBCBasicError::TooBig),
+                }
+            }
+            _ => {
+                return Err(BBCBasicError::SyntaxError {
+                    message: format!("Unexpected character {:?}", ch),
+                    line: line_number,
+                })
             }
         }
     }
@@ -276,8 +446,273 @@ pub fn tokenize(source_line: &str) -> Result<TokenizedLine> {
     Ok(TokenizedLine::new(line_number, tokens))
 }
 
+/// What a span of source text is, for syntax highlighting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenClass {
+    Keyword,
+    /// A string literal, with its quotes
+    String,
+    /// A number, including line numbers and &hex constants
+    Number,
+    /// A variable, procedure or function name
+    Identifier,
+    /// REM or ' and the rest of the line
+    Comment,
+    /// Operators and punctuation
+    Operator,
+}
+
+/// Label the keyword, string, number, name, comment and operator spans of a
+/// source line
+pub fn classify(source_line: &str) -> Vec<(Range<usize>, TokenClass)> {
+    classify_with_options(source_line, &TokenizerOptions::default())
+}
+
+/// Whether the statement tokenized so far has a PRINT to the screen in it
+fn in_print(tokens: &[Token]) -> bool {
+    let start = tokens
+        .iter()
+        .rposition(|token| {
+            matches!(
+                token,
+                Token::Separator(':') | Token::Keyword(0x8B) | Token::Keyword(0x8C)
+            )
+        })
+        .map_or(0, |end| end + 1);
+    let statement = &tokens[start..];
+    statement.iter().enumerate().any(|(n, token)| {
+        *token == Token::Keyword(0xF1) && statement.get(n + 1) != Some(&Token::Operator('#'))
+    })
+}
+
+/// Label the spans of a source line, matching keywords as
+/// [`tokenize_with_options`] would
+///
+/// Spans are byte ranges of `source_line`, in order. Spaces and characters
+/// BBC BASIC has no use for are left out. Unlike tokenizing, classifying
+/// keeps every span's position and never fails, so it can follow text as it
+/// is typed.
+pub fn classify_with_options(
+    source_line: &str,
+    options: &TokenizerOptions,
+) -> Vec<(Range<usize>, TokenClass)> {
+    let (keyword_map, extended_map) = create_keyword_maps();
+    let mut spans = Vec::new();
+    let mut position = 0;
+    // Whether the statement so far is a PRINT, where ' starts a new line
+    let mut print = false;
+
+    while let Some(ch) = source_line[position..].chars().next() {
+        let rest = &source_line[position..];
+        let (length, class) = if ch.is_whitespace() {
+            position += ch.len_utf8();
+            continue;
+        } else if ch == '"' {
+            let length = rest[1..].find('"').map_or(rest.len(), |end| end + 2);
+            (length, TokenClass::String)
+        } else if ch == '\'' && !print {
+            (rest.len(), TokenClass::Comment)
+        } else if ch.is_ascii_digit()
+            || (ch == '.' && rest[1..].starts_with(|c: char| c.is_ascii_digit()))
+        {
+            let length = rest
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(rest.len());
+            (length, TokenClass::Number)
+        } else if ch == '&' && rest[1..].starts_with(|c: char| c.is_ascii_hexdigit()) {
+            let length = rest[1..]
+                .find(|c: char| !c.is_ascii_hexdigit())
+                .map_or(rest.len(), |end| end + 1);
+            (length, TokenClass::Number)
+        } else if ch.is_ascii_alphabetic() || ch == '_' {
+            let matched = match_keyword_prefix(rest, &keyword_map, &extended_map).or_else(|| {
+                let word = take_name(rest);
+                options
+                    .case_insensitive_keywords
+                    .then(|| {
+                        lookup_keyword(&word.to_ascii_uppercase(), &keyword_map, &extended_map)
+                    })
+                    .flatten()
+                    .map(|token| (token, word.len()))
+            });
+            match matched {
+                Some((token, length)) => {
+                    spans.push((position..position + length, TokenClass::Keyword));
+                    position += length;
+                    print = match token {
+                        Token::Keyword(0xF1) => {
+                            !source_line[position..].trim_start().starts_with('#')
+                        }
+                        Token::Keyword(0x8B) | Token::Keyword(0x8C) => false,
+                        _ => print,
+                    };
+                    match token {
+                        // REM's text is not tokenized
+                        Token::Keyword(0xF4) => {
+                            let rest = &source_line[position..];
+                            let comment = rest.trim();
+                            if !comment.is_empty() {
+                                let start = position + rest.len() - rest.trim_start().len();
+                                spans.push((start..start + comment.len(), TokenClass::Comment));
+                            }
+                            break;
+                        }
+                        // Nor is the name after PROC or FN
+                        Token::Keyword(0xF2) | Token::Keyword(0xA4) => {
+                            let rest = &source_line[position..];
+                            let start = position + rest.len() - rest.trim_start_matches(' ').len();
+                            let name = source_line[start..]
+                                .find(|c: char| !is_name_char(c))
+                                .unwrap_or(source_line.len() - start);
+                            if name > 0 {
+                                spans.push((start..start + name, TokenClass::Identifier));
+                                position = start + name;
+                            }
+                        }
+                        _ => {}
+                    }
+                    continue;
+                }
+                None => (take_name(rest).len(), TokenClass::Identifier),
+            }
+        } else if rest.starts_with("@%") {
+            (2, TokenClass::Identifier)
+        } else if ch.is_ascii_punctuation() {
+            print &= ch != ':';
+            (1, TokenClass::Operator)
+        } else {
+            position += ch.len_utf8();
+            continue;
+        };
+        spans.push((position..position + length, class));
+        position += length;
+    }
+
+    spans
+}
+
+/// Keywords that are left as part of a name when followed by a letter, digit
+/// or underscore, so that COUNTER, TIMER and ENDING can be variables (the
+/// "conditional" flag in the BASIC II token table)
+const CONDITIONAL_KEYWORDS: &[&str] = &[
+    "CLEAR", "CLG", "CLS", "COUNT", "END", "ENDIF", "ENDPROC", "ENDWHILE", "ERL", "ERR", "FALSE",
+    "HIMEM", "LOMEM", "NEW", "OLD", "PAGE", "PI", "POS", "PTR", "QUIT", "REPEAT", "REPORT",
+    "RETURN", "RUN", "STOP", "TIME", "TRUE", "VPOS",
+];
+
+/// Keywords that are values on their own (PAGE, TIME, LOMEM, HIMEM, COUNT,
+/// ERL, ERR, FALSE, PI, POS, TRUE and VPOS, in both forms where there are
+/// two), so that a minus after one subtracts
+const VALUE_KEYWORDS: &[u8] = &[
+    0x90, 0x91, 0x92, 0x93, 0x9C, 0x9E, 0x9F, 0xA3, 0xAF, 0xB1, 0xB9, 0xBC, 0xD0, 0xD1, 0xD2, 0xD3,
+];
+
+/// The text of a real number literal, in E form where the plain digits
+/// would read back as an integer too big for 32 bits (1E10)
+fn real_text(value: f64) -> String {
+    let plain = value.to_string();
+    if plain.contains('.') || plain.parse::<i32>().is_ok() {
+        plain
+    } else {
+        format!("{:E}", value)
+    }
+}
+
+/// Whether a token can end an operand, so that a minus after it is taken
+/// as subtraction (4-5) rather than the sign of a number (PRINT -5)
+fn ends_operand(token: Option<&Token>) -> bool {
+    match token {
+        Some(Token::Integer(_) | Token::Real(_) | Token::String(_) | Token::Identifier(_)) => true,
+        Some(Token::Separator(')')) => true,
+        Some(Token::Keyword(byte)) => VALUE_KEYWORDS.contains(byte),
+        _ => false,
+    }
+}
+
+/// Whether a character can appear in a variable, procedure or function name
+///
+/// Names are letters, digits and underscores, must not begin with a digit,
+/// are case-sensitive and may be any length.
+pub fn is_name_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_'
+}
+
+/// Take a name from the start of `text`, including any % or $ type suffix
+fn take_name(text: &str) -> &str {
+    let end = text.find(|c: char| !is_name_char(c)).unwrap_or(text.len());
+    match text[end..].chars().next() {
+        Some('%') | Some('$') => &text[..end + 1],
+        _ => &text[..end],
+    }
+}
+
+/// Look up a whole word in the keyword tables
+fn lookup_keyword(
+    word: &str,
+    keyword_map: &HashMap<String, u8>,
+    extended_map: &HashMap<String, (u8, u8)>,
+) -> Option<Token> {
+    if let Some(&token_byte) = keyword_map.get(word) {
+        Some(Token::Keyword(token_byte))
+    } else {
+        extended_map
+            .get(word)
+            .map(|&(prefix, token_byte)| Token::ExtendedKeyword(prefix, token_byte))
+    }
+}
+
+/// Find the longest keyword at the start of `text`, returning the token and
+/// the number of characters it covers
+///
+/// Keywords must be in uppercase to match. A conditional keyword is skipped
+/// when it runs into a name, in which case a shorter keyword may still match.
+fn match_keyword_prefix(
+    text: &str,
+    keyword_map: &HashMap<String, u8>,
+    extended_map: &HashMap<String, (u8, u8)>,
+) -> Option<(Token, usize)> {
+    let accepts = |keyword: &str| {
+        text.starts_with(keyword)
+            && !(CONDITIONAL_KEYWORDS.contains(&keyword)
+                && text[keyword.len()..]
+                    .chars()
+                    .next()
+                    .is_some_and(is_name_char))
+    };
+
+    let mut best: Option<(Token, usize)> = None;
+    let main = keyword_map
+        .iter()
+        .map(|(keyword, &byte)| (keyword, Token::Keyword(byte)));
+    let extended = extended_map
+        .iter()
+        .map(|(keyword, &(prefix, byte))| (keyword, Token::ExtendedKeyword(prefix, byte)));
+    // Main keywords win ties, as they do for whole-word lookups
+    for (keyword, token) in main.chain(extended) {
+        let longer = best
+            .as_ref()
+            .is_none_or(|(_, length)| keyword.len() > *length);
+        if longer && accepts(keyword) {
+            best = Some((token, keyword.len()));
+        }
+    }
+    best
+}
+
 /// Convert tokens back to BBC BASIC source
 pub fn detokenize(tokenized_line: &TokenizedLine) -> Result<String> {
+    detokenize_with_options(tokenized_line, &TokenizerOptions::default())
+}
+
+/// Convert tokens back to BBC BASIC source, listing keywords in the configured case
+pub fn detokenize_with_options(
+    tokenized_line: &TokenizedLine,
+    options: &TokenizerOptions,
+) -> Result<String> {
+    let push_keyword = |result: &mut String, keyword: &str| match options.keyword_case {
+        KeywordCase::Upper => result.push_str(keyword),
+        KeywordCase::Lower => result.push_str(&keyword.to_lowercase()),
+    };
     let (main_reverse, extended_reverse) = create_reverse_keyword_maps();
     let mut result = String::new();
 
@@ -299,6 +734,8 @@ pub fn detokenize(tokenized_line: &TokenizedLine) -> Result<String> {
                 (_, Token::Separator(',')) => false,
                 (_, Token::Separator(';')) => false,
                 (_, Token::Separator(':')) => false,
+                // DATA text keeps its own spacing
+                (_, Token::Text(_)) => false,
                 // Need space between most tokens
                 _ => true,
             };
@@ -311,12 +748,12 @@ pub fn detokenize(tokenized_line: &TokenizedLine) -> Result<String> {
         match token {
             Token::Keyword(byte) => {
                 if let Some(keyword) = main_reverse.get(byte) {
-                    result.push_str(keyword);
+                    push_keyword(&mut result, keyword);
                 }
             }
             Token::ExtendedKeyword(prefix, byte) => {
                 if let Some(keyword) = extended_reverse.get(&(*prefix, *byte)) {
-                    result.push_str(keyword);
+                    push_keyword(&mut result, keyword);
                 }
             }
             Token::LineNumber(num) => {
@@ -326,7 +763,7 @@ pub fn detokenize(tokenized_line: &TokenizedLine) -> Result<String> {
                 result.push_str(&val.to_string());
             }
             Token::Real(val) => {
-                result.push_str(&val.to_string());
+                result.push_str(&real_text(*val));
             }
             Token::String(s) => {
                 result.push('"');
@@ -336,6 +773,9 @@ pub fn detokenize(tokenized_line: &TokenizedLine) -> Result<String> {
             Token::Identifier(name) => {
                 result.push_str(name);
             }
+            Token::Text(text) => {
+                result.push_str(text);
+            }
             Token::Operator(op) => {
                 result.push(*op);
             }
@@ -351,6 +791,59 @@ pub fn detokenize(tokenized_line: &TokenizedLine) -> Result<String> {
     Ok(result)
 }
 
+/// List a line without the spaces BBC BASIC can do without (*PACK)
+///
+/// Spaces are kept between names and numbers that would otherwise run
+/// together, and the whole line is spaced as usual if the packed text would
+/// not tokenize back to the same line.
+pub fn detokenize_compact(
+    tokenized_line: &TokenizedLine,
+    options: &TokenizerOptions,
+) -> Result<String> {
+    for spaced_keywords in [false, true] {
+        let packed = pack_tokens(tokenized_line, options, spaced_keywords)?;
+        if tokenize_with_options(&packed, options).is_ok_and(|line| line == *tokenized_line) {
+            return Ok(packed);
+        }
+    }
+    detokenize_with_options(tokenized_line, options)
+}
+
+/// Join a line's tokens, spacing only between word characters (and, unless
+/// `spaced_keywords`, not after a keyword, since keywords may run into names)
+fn pack_tokens(
+    tokenized_line: &TokenizedLine,
+    options: &TokenizerOptions,
+    spaced_keywords: bool,
+) -> Result<String> {
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.';
+    let mut result = match tokenized_line.line_number {
+        Some(line_number) => format!("{} ", line_number),
+        None => String::new(),
+    };
+    let mut previous: Option<&Token> = None;
+    for token in &tokenized_line.tokens {
+        let text =
+            detokenize_with_options(&TokenizedLine::new(None, vec![token.clone()]), options)?;
+        let after_keyword = matches!(
+            previous,
+            Some(Token::Keyword(_) | Token::ExtendedKeyword(..))
+        );
+        let joins_word = result.ends_with(is_word)
+            && text.starts_with(is_word)
+            && (spaced_keywords || !after_keyword);
+        // A minus straight before a digit would read as a negative number
+        let joins_number = previous == Some(&Token::Operator('-'))
+            && text.starts_with(|c: char| c.is_ascii_digit());
+        if joins_word || joins_number {
+            result.push(' ');
+        }
+        result.push_str(&text);
+        previous = Some(token);
+    }
+    Ok(result)
+}
+
 // BBC BASIC keyword to token mappings
 // Main keywords (0x80-0xFF) - corrected to match BBC BASIC specification
 const MAIN_KEYWORDS: &[(&str, u8)] = &[
@@ -537,8 +1030,43 @@ const EXTENDED_STATEMENTS: &[(&str, u8)] = &[
     ("STEREO", 0xA2),
     ("OVERLAY", 0xA3),
     ("ENDWHILE", 0xA4),
+    ("ENDIF", 0xA5),
+    ("CONST", 0xA6),
+    ("ASSERT", 0xA7),
+    ("OUTPUT", 0xA8),
+    #[cfg(feature = "turtle")]
+    ("FORWARD", 0xA9),
+    #[cfg(feature = "turtle")]
+    ("TURN", 0xAA),
+    #[cfg(feature = "turtle")]
+    ("PENUP", 0xAB),
+    #[cfg(feature = "turtle")]
+    ("PENDOWN", 0xAC),
 ];
 
+/// Every keyword with its token, main keywords first and then the BASIC 4
+/// extended ones
+///
+/// A few keywords have more than one token (PAGE, TIME and the like as a
+/// function and as a statement); each is listed once per token.
+pub fn keyword_tokens() -> Vec<(&'static str, Token)> {
+    let main = MAIN_KEYWORDS
+        .iter()
+        .map(|&(keyword, token)| (keyword, Token::Keyword(token)));
+    let extended = [
+        (0xC6, EXTENDED_FUNCTIONS),
+        (0xC7, EXTENDED_COMMANDS),
+        (0xC8, EXTENDED_STATEMENTS),
+    ]
+    .into_iter()
+    .flat_map(|(prefix, keywords)| {
+        keywords
+            .iter()
+            .map(move |&(keyword, token)| (keyword, Token::ExtendedKeyword(prefix, token)))
+    });
+    main.chain(extended).collect()
+}
+
 /// Create keyword lookup tables for tokenization
 pub fn create_keyword_maps() -> (HashMap<String, u8>, HashMap<String, (u8, u8)>) {
     let mut main_keywords = HashMap::new();
@@ -596,6 +1124,7 @@ pub fn create_reverse_keyword_maps() -> (HashMap<u8, String>, HashMap<(u8, u8),
 }
 
 #[cfg(test)]
+#[allow(clippy::approx_constant)]
 mod tests {
     use super::*;
 
@@ -678,6 +1207,48 @@ mod tests {
         assert_eq!(result.tokens[0], Token::Real(3.14159));
     }
 
+    #[test]
+    fn test_tokenize_exponent() {
+        let result = tokenize("A = 1E38 * 1.5E-3 + .5 - 2.").unwrap();
+        assert_eq!(
+            result.tokens,
+            vec![
+                Token::Identifier("A".to_string()),
+                Token::Operator('='),
+                Token::Real(1E38),
+                Token::Operator('*'),
+                Token::Real(1.5E-3),
+                Token::Operator('+'),
+                Token::Real(0.5),
+                Token::Operator('-'),
+                Token::Real(2.0),
+            ]
+        );
+        // A long real lists in E form, so that it reads back as a real
+        assert_eq!(detokenize(&tokenize("PRINT 1E10").unwrap()).unwrap(), "PRINT 1E10");
+    }
+
+    #[test]
+    fn test_tokenize_bad_literals() {
+        for text in ["A% = 99999999999", "A = 1E999", "A% = &1FFFFFFFF"] {
+            assert_eq!(tokenize(text).unwrap_err(), BBCBasicError::TooBig, "{}", text);
+        }
+        for text in ["A = &", "PRINT [1]", "A = 1 ` 2"] {
+            assert!(
+                matches!(tokenize(text), Err(BBCBasicError::SyntaxError { .. })),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_tokenize_hex_constant() {
+        let result = tokenize("SOUND &1011,&ffffffff").unwrap();
+        assert_eq!(result.tokens[1], Token::Integer(0x1011));
+        assert_eq!(result.tokens[3], Token::Integer(-1));
+    }
+
     #[test]
     fn test_tokenize_print_keyword() {
         // RED: Test tokenizing PRINT keyword
@@ -740,6 +1311,23 @@ mod tests {
         assert_eq!(result.tokens[1], Token::Integer(42));
     }
 
+    #[test]
+    fn test_tokenize_star_command() {
+        let result = tokenize("10 *FX 19").unwrap();
+        assert_eq!(result.line_number, Some(10));
+        assert_eq!(
+            result.tokens,
+            vec![Token::Operator('*'), Token::Text("FX 19".to_string())]
+        );
+
+        // After a colon too, with colons in the command kept
+        let result = tokenize("20 A% = 2 * 3: *SAVE A:B").unwrap();
+        assert_eq!(result.tokens[3], Token::Operator('*'));
+        assert_eq!(result.tokens[6], Token::Operator('*'));
+        assert_eq!(result.tokens[7], Token::Text("SAVE A:B".to_string()));
+        assert_eq!(detokenize(&result).unwrap(), "20 A% = 2 * 3:*SAVE A:B");
+    }
+
     #[test]
     fn test_tokenize_expression_with_operators() {
         // RED: Test tokenizing "2 + 3 * 4"
@@ -795,15 +1383,230 @@ mod tests {
     #[test]
     fn test_apostrophe_comment() {
         // RED: Test that apostrophe (') is tokenized as REM
-        let line = tokenize("10 PRINT 42 ' This is a comment").unwrap();
+        let line = tokenize("10 A% = 42 ' This is a comment").unwrap();
 
-        // Should tokenize as: LineNumber(10), Keyword(PRINT), Integer(42), Keyword(REM)
+        // Should tokenize as: LineNumber(10), A%, =, Integer(42), Keyword(REM)
         assert_eq!(line.line_number, Some(10));
-        assert_eq!(line.tokens.len(), 3); // PRINT, 42, REM
+        assert_eq!(line.tokens.len(), 4); // A%, =, 42, REM
 
         // Check tokens
-        assert!(matches!(line.tokens[0], Token::Keyword(0xF1))); // PRINT
-        assert!(matches!(line.tokens[1], Token::Integer(42)));
-        assert!(matches!(line.tokens[2], Token::Keyword(0xF4))); // REM
+        assert!(matches!(line.tokens[2], Token::Integer(42)));
+        assert!(matches!(line.tokens[3], Token::Keyword(0xF4))); // REM
+
+        // In PRINT it starts a new line, up to the end of the statement
+        let line = tokenize("PRINT 42'\"A\": A% = 1 ' comment").unwrap();
+        assert_eq!(line.tokens[2], Token::Separator('\''));
+        assert_eq!(line.tokens.last(), Some(&Token::Keyword(0xF4)));
+        let line = tokenize("IF X PRINT ' ELSE PRINT#C, 1 ' comment").unwrap();
+        assert_eq!(line.tokens[3], Token::Separator('\''));
+        assert_eq!(line.tokens.last(), Some(&Token::Keyword(0xF4)));
+    }
+
+    #[test]
+    fn test_lowercase_keywords_accepted_by_default() {
+        let result = tokenize("10 print count").unwrap();
+        assert_eq!(result.tokens[0], Token::Keyword(0xF1));
+        assert_eq!(result.tokens[1], Token::Keyword(0x9C)); // COUNT
+    }
+
+    #[test]
+    fn test_strict_keyword_case() {
+        let options = TokenizerOptions {
+            case_insensitive_keywords: false,
+            ..Default::default()
+        };
+        let result = tokenize_with_options("10 PRINT count, Total", &options).unwrap();
+        assert_eq!(result.tokens[0], Token::Keyword(0xF1));
+        // Lowercase keywords are ordinary names, and identifier case is kept
+        assert_eq!(result.tokens[1], Token::Identifier("count".to_string()));
+        assert_eq!(result.tokens[3], Token::Identifier("Total".to_string()));
+    }
+
+    #[test]
+    fn test_detokenize_keyword_case() {
+        let line = tokenize("10 print \"Hi\";Name$").unwrap();
+        let options = TokenizerOptions {
+            keyword_case: KeywordCase::Lower,
+            ..Default::default()
+        };
+        assert_eq!(detokenize(&line).unwrap(), "10 PRINT \"Hi\";Name$");
+        assert_eq!(
+            detokenize_with_options(&line, &options).unwrap(),
+            "10 print \"Hi\";Name$"
+        );
+    }
+
+    #[test]
+    fn test_data_text_is_kept() {
+        let line = tokenize("10 DATA  Fred , \"a,b\":PRINT").unwrap();
+        assert_eq!(
+            line.tokens,
+            vec![
+                Token::Keyword(0xDC),
+                Token::Text("  Fred , \"a,b\":PRINT".to_string()),
+            ]
+        );
+        assert_eq!(detokenize(&line).unwrap(), "10 DATA  Fred , \"a,b\":PRINT");
+        // RESTORE DATA and LOCAL DATA are not DATA statements
+        let line = tokenize("RESTORE DATA").unwrap();
+        assert_eq!(
+            line.tokens,
+            vec![Token::Keyword(0xF7), Token::Keyword(0xDC)]
+        );
+    }
+
+    #[test]
+    fn test_keywords_run_into_names() {
+        let result = tokenize("10 FORI=1TO10STEP2").unwrap();
+        assert_eq!(
+            result.tokens,
+            vec![
+                Token::Keyword(0xE3), // FOR
+                Token::Identifier("I".to_string()),
+                Token::Operator('='),
+                Token::Integer(1),
+                Token::Keyword(0xB8), // TO
+                Token::Integer(10),
+                Token::Keyword(0x88), // STEP
+                Token::Integer(2),
+            ]
+        );
+
+        // Longest keyword wins, then scanning starts afresh
+        let result = tokenize("PRINTTAB(5)CHR$65").unwrap();
+        assert_eq!(result.tokens[0], Token::Keyword(0xF1)); // PRINT
+        assert_eq!(result.tokens[1], Token::Keyword(0x8A)); // TAB
+        assert_eq!(result.tokens[5], Token::Keyword(0xBD)); // CHR$
+        assert_eq!(result.tokens[6], Token::Integer(65));
+    }
+
+    #[test]
+    fn test_names_absorb_following_keywords() {
+        // Keywords are only recognised at the start of a name
+        let result = tokenize("BAND=XOR%+ITO").unwrap();
+        assert_eq!(result.tokens[0], Token::Identifier("BAND".to_string()));
+        assert_eq!(result.tokens[2], Token::Identifier("XOR%".to_string()));
+        assert_eq!(result.tokens[4], Token::Identifier("ITO".to_string()));
+
+        // A type suffix ends the name
+        let result = tokenize("A%AND3").unwrap();
+        assert_eq!(result.tokens[0], Token::Identifier("A%".to_string()));
+        assert_eq!(result.tokens[1], Token::Keyword(0x80)); // AND
+
+        // Names may be long, use underscores and digits, and keep their case
+        let result = tokenize("my_long_name_2$=Name$").unwrap();
+        assert_eq!(
+            result.tokens[0],
+            Token::Identifier("my_long_name_2$".to_string())
+        );
+        assert_eq!(result.tokens[2], Token::Identifier("Name$".to_string()));
+
+        // A leading digit starts a number, not a name
+        let result = tokenize("PRINT 2X").unwrap();
+        assert_eq!(result.tokens[1], Token::Integer(2));
+        assert_eq!(result.tokens[2], Token::Identifier("X".to_string()));
+    }
+
+    #[test]
+    fn test_conditional_keywords() {
+        // Conditional keywords followed by a name character are left alone
+        let result = tokenize("COUNTER=TIMER+ENDING").unwrap();
+        assert_eq!(result.tokens[0], Token::Identifier("COUNTER".to_string()));
+        assert_eq!(result.tokens[2], Token::Identifier("TIMER".to_string()));
+        assert_eq!(result.tokens[4], Token::Identifier("ENDING".to_string()));
+
+        let result = tokenize("PRINTCOUNT:END").unwrap();
+        assert_eq!(result.tokens[1], Token::Keyword(0x9C)); // COUNT
+        assert_eq!(result.tokens[3], Token::Keyword(0xE0)); // END
+
+        // Other keywords split a name as they do on the Model B
+        let result = tokenize("TOTAL").unwrap();
+        assert_eq!(result.tokens[0], Token::Keyword(0xB8)); // TO
+        assert_eq!(result.tokens[1], Token::Identifier("TAL".to_string()));
+    }
+
+    #[test]
+    fn test_proc_and_fn_names_not_tokenized() {
+        let result = tokenize("DEFPROCend").unwrap();
+        assert_eq!(
+            result.tokens,
+            vec![
+                Token::Keyword(0xDD),
+                Token::Keyword(0xF2),
+                Token::Identifier("end".to_string()),
+            ]
+        );
+
+        let result = tokenize("PROCTOTAL(1)").unwrap();
+        assert_eq!(result.tokens[1], Token::Identifier("TOTAL".to_string()));
+
+        let result = tokenize("X=FNsquare(3)").unwrap();
+        assert_eq!(result.tokens[2], Token::Keyword(0xA4));
+        assert_eq!(result.tokens[3], Token::Identifier("square".to_string()));
+
+        let result = tokenize("A$=FNname$").unwrap();
+        assert_eq!(result.tokens[3], Token::Identifier("name$".to_string()));
+    }
+
+    #[test]
+    fn test_lowercase_keywords_match_whole_words_only() {
+        let result = tokenize("fori=1to10").unwrap();
+        assert_eq!(result.tokens[0], Token::Identifier("fori".to_string()));
+        assert_eq!(result.tokens[3], Token::Identifier("to10".to_string()));
+    }
+
+    #[test]
+    fn test_classify() {
+        let line = "10 IF A%>&1F PRINT \"HI\";PROCgo : REM done";
+        let spans: Vec<(&str, TokenClass)> = classify(line)
+            .into_iter()
+            .map(|(range, class)| (&line[range], class))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                ("10", TokenClass::Number),
+                ("IF", TokenClass::Keyword),
+                ("A%", TokenClass::Identifier),
+                (">", TokenClass::Operator),
+                ("&1F", TokenClass::Number),
+                ("PRINT", TokenClass::Keyword),
+                ("\"HI\"", TokenClass::String),
+                (";", TokenClass::Operator),
+                ("PROC", TokenClass::Keyword),
+                ("go", TokenClass::Identifier),
+                (":", TokenClass::Operator),
+                ("REM", TokenClass::Keyword),
+                ("done", TokenClass::Comment),
+            ]
+        );
+
+        // Unfinished text still classifies, and keywords run into names
+        let line = "FORI=1TO2.5:PRINT \"open 'x";
+        let classes: Vec<TokenClass> = classify(line).into_iter().map(|(_, class)| class).collect();
+        assert_eq!(
+            classes,
+            vec![
+                TokenClass::Keyword,
+                TokenClass::Identifier,
+                TokenClass::Operator,
+                TokenClass::Number,
+                TokenClass::Keyword,
+                TokenClass::Number,
+                TokenClass::Operator,
+                TokenClass::Keyword,
+                TokenClass::String,
+            ]
+        );
+        assert_eq!(classify("  ' note")[0], (2..8, TokenClass::Comment));
+
+        // In PRINT, ' is a separator up to the end of the statement
+        let line = "IF X PRINT 'A% ELSE PRINT#C, 1 ' note";
+        let comments: Vec<&str> = classify(line)
+            .into_iter()
+            .filter(|(_, class)| *class == TokenClass::Comment)
+            .map(|(range, _)| &line[range])
+            .collect();
+        assert_eq!(comments, ["' note"]);
     }
 }
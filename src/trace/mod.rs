@@ -0,0 +1,205 @@
+//! Instrumentation of running programs with the `tracing` crate
+//!
+//! With the `tracing` feature built in and the `trace` option on, a running
+//! program reports what it does under the `bbc_basic` target, so embedders
+//! can follow a misbehaving program with any `tracing` subscriber:
+//!
+//! - a `run` span around each stretch of running, with the backend
+//! - a TRACE event for each statement, with its line number
+//! - DEBUG events as PROCs and FNs are entered and left, with the depth
+//! - INFO events for files opened and closed
+//! - WARN events for errors trapped by ON ERROR, and ERROR events for errors
+//!   that stop the program
+//!
+//! Without the feature these functions do nothing, so callers only need to
+//! check the option.
+
+/// Target that all spans and events are reported under
+#[cfg(feature = "tracing")]
+const TARGET: &str = "bbc_basic";
+
+/// The `run` span, entered until this is dropped
+pub struct Run {
+    #[cfg(feature = "tracing")]
+    _span: tracing::span::EnteredSpan,
+}
+
+/// Enter a span for a stretch of running on the named backend
+#[cfg(feature = "tracing")]
+pub fn run(backend: &str) -> Run {
+    Run {
+        _span: tracing::info_span!(target: TARGET, "run", backend).entered(),
+    }
+}
+
+/// A statement is about to run
+#[cfg(feature = "tracing")]
+pub fn statement(line_number: u16) {
+    tracing::trace!(target: TARGET, line = line_number, "statement");
+}
+
+/// A PROC or FN (`kind`) was entered, leaving `depth` calls in progress
+#[cfg(feature = "tracing")]
+pub fn enter(kind: &str, name: &str, depth: usize) {
+    tracing::debug!(target: TARGET, kind, name, depth, "enter");
+}
+
+/// A PROC or FN returned, leaving `depth` calls in progress
+#[cfg(feature = "tracing")]
+pub fn leave(kind: &str, depth: usize) {
+    tracing::debug!(target: TARGET, kind, depth, "leave");
+}
+
+/// A file was opened (OPENIN, OPENOUT or OPENUP) on a channel
+#[cfg(feature = "tracing")]
+pub fn open(operation: &str, name: &str, handle: i32) {
+    tracing::info!(target: TARGET, operation, name, handle, "open");
+}
+
+/// A channel was closed
+#[cfg(feature = "tracing")]
+pub fn close(handle: i32) {
+    tracing::info!(target: TARGET, handle, "close");
+}
+
+/// An error was trapped by ON ERROR
+#[cfg(feature = "tracing")]
+pub fn trapped(number: i32, report: &str, line_number: u16) {
+    tracing::warn!(target: TARGET, number, report, line = line_number, "trapped");
+}
+
+/// An error stopped the program
+#[cfg(feature = "tracing")]
+pub fn failed(error: &str, line_number: Option<u16>) {
+    tracing::error!(target: TARGET, error, line = line_number, "failed");
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn run(_backend: &str) -> Run {
+    Run {}
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn statement(_line_number: u16) {}
+
+#[cfg(not(feature = "tracing"))]
+pub fn enter(_kind: &str, _name: &str, _depth: usize) {}
+
+#[cfg(not(feature = "tracing"))]
+pub fn leave(_kind: &str, _depth: usize) {}
+
+#[cfg(not(feature = "tracing"))]
+pub fn open(_operation: &str, _name: &str, _handle: i32) {}
+
+#[cfg(not(feature = "tracing"))]
+pub fn close(_handle: i32) {}
+
+#[cfg(not(feature = "tracing"))]
+pub fn trapped(_number: i32, _report: &str, _line_number: u16) {}
+
+#[cfg(not(feature = "tracing"))]
+pub fn failed(_error: &str, _line_number: Option<u16>) {}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use crate::config::{Backend, Config};
+    use crate::Interpreter;
+    use std::fmt;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// Keeps each event's fields as "name=value" text
+    struct Collector(Arc<Mutex<Vec<String>>>);
+
+    struct Fields(Vec<String>);
+
+    impl Visit for Fields {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.push(format!("{}={}", field.name(), value));
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0.push(format!("{}={:?}", field.name(), value));
+        }
+    }
+
+    impl Subscriber for Collector {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _span: &Attributes<'_>) -> Id {
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut fields = Fields(Vec::new());
+            event.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0.join(" "));
+        }
+
+        fn enter(&self, _span: &Id) {}
+
+        fn exit(&self, _span: &Id) {}
+    }
+
+    /// Run a program and return the events it reported
+    fn traced(backend: Backend, trace: bool, lines: &[&str]) -> Vec<String> {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let collector = Collector(Arc::clone(&seen));
+        tracing::subscriber::with_default(collector, || {
+            let mut interpreter = Interpreter::with_config(Config {
+                backend,
+                trace,
+                ..Default::default()
+            });
+            for line in lines {
+                interpreter.process_line(line).unwrap();
+            }
+            let _ = interpreter.run();
+        });
+        let events = seen.lock().unwrap().clone();
+        events
+    }
+
+    #[test]
+    fn test_running_program_is_traced() {
+        let program = [
+            "10 PROCgreet",
+            "20 X = FNtwice(2)",
+            "30 Y = 1 / 0",
+            "40 DEF PROCgreet",
+            "50 ENDPROC",
+            "60 DEF FNtwice(N) = N * 2",
+        ];
+        for backend in [Backend::Tree, Backend::Bytecode] {
+            let events = traced(backend, true, &program);
+            let has = |event: &str| events.iter().any(|e| e == event);
+            assert!(has("message=statement line=10"), "{:?}", events);
+            assert!(
+                has("message=enter kind=PROC name=greet depth=1"),
+                "{:?}",
+                events
+            );
+            assert!(has("message=leave kind=PROC depth=0"), "{:?}", events);
+            assert!(
+                has("message=enter kind=FN name=twice depth=1"),
+                "{:?}",
+                events
+            );
+            assert_eq!(
+                events.last().unwrap(),
+                "message=failed error=Division by zero at line 30 line=30"
+            );
+
+            // Nothing is reported with the option off
+            assert!(traced(backend, false, &program).is_empty());
+        }
+    }
+}
@@ -0,0 +1,1414 @@
+//! BASIC-to-Rust transpiler
+//!
+//! Lowers the parsed statements of a stored program to a standalone Rust
+//! source file, so compute-heavy programs can be built as native binaries.
+//! The generated program keeps BASIC's line-by-line control flow as a state
+//! machine, which lets GOTO, GOSUB and the loop statements keep their
+//! dynamic behaviour. Assignments, loops, IF, PROC/FN, arrays and PRINT are
+//! covered; any other statement is left out with a diagnostic.
+
+use crate::error::{BBCBasicError, Result};
+use crate::parser::{
+    parse_statement, BinaryOperator, Expression, PrintItem, Statement, UnaryOperator,
+};
+use crate::program::ProgramStore;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::fmt::Write as _;
+
+/// Result of transpiling a program
+#[derive(Debug, Clone)]
+pub struct Transpiled {
+    /// Rust source for a standalone program
+    pub source: String,
+    /// Statements that could not be translated (they are left out)
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// A statement the transpiler could not translate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// BASIC line number
+    pub line: u16,
+    /// What was not supported
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Line {}: {}", self.line, self.message)
+    }
+}
+
+/// Translate a stored program to Rust source
+///
+/// Fails only if a line does not parse; untranslatable statements are
+/// reported as diagnostics instead.
+pub fn transpile(program: &ProgramStore) -> Result<Transpiled> {
+    let mut lines = Vec::new();
+    for (number, line) in program.list() {
+        let statement = parse_statement(line).map_err(|e| match e {
+            BBCBasicError::SyntaxError { message, .. } => BBCBasicError::SyntaxError {
+                message,
+                line: Some(number),
+            },
+            e => e,
+        })?;
+        lines.push((number, statement));
+    }
+
+    let mut transpiler = Transpiler::new(lines);
+    let source = transpiler.generate();
+    Ok(Transpiled {
+        source,
+        diagnostics: transpiler.diagnostics,
+    })
+}
+
+/// Static type of a BASIC value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Type {
+    Int,
+    Real,
+    Str,
+}
+
+impl Type {
+    /// Type of a variable from its suffix
+    fn of_name(name: &str) -> Type {
+        if name.ends_with('%') {
+            Type::Int
+        } else if name.ends_with('$') {
+            Type::Str
+        } else {
+            Type::Real
+        }
+    }
+
+    /// Rust type used for values of this type
+    fn rust(self) -> &'static str {
+        match self {
+            Type::Int => "i32",
+            Type::Real => "f64",
+            Type::Str => "String",
+        }
+    }
+}
+
+/// Rust field holding a scalar variable (A% -> int_A)
+fn field(name: &str) -> String {
+    let base = name.trim_end_matches(['%', '$']);
+    match Type::of_name(name) {
+        Type::Int => format!("int_{}", base),
+        Type::Real => format!("real_{}", base),
+        Type::Str => format!("str_{}", base),
+    }
+}
+
+/// Rust field holding an array (A%() -> arr_int_A)
+fn array_field(name: &str) -> String {
+    format!("arr_{}", field(name))
+}
+
+/// A translated expression
+type Code = std::result::Result<(String, Type), String>;
+
+struct Transpiler {
+    /// Parsed program lines in order
+    lines: Vec<(u16, Statement)>,
+    /// DEF FN definitions: name -> (parameters, expression)
+    functions: HashMap<String, (Vec<String>, Expression)>,
+    /// DEF PROC definitions: name -> (line index, parameters)
+    procedures: HashMap<String, (usize, Vec<String>)>,
+    /// Return types of functions, once known
+    function_types: HashMap<String, Type>,
+    /// Functions whose return type is being worked out (for recursion)
+    inferring: BTreeSet<String>,
+    /// Scalar variables used
+    scalars: BTreeSet<String>,
+    /// Arrays used
+    arrays: BTreeSet<String>,
+    /// Variables used as FOR loop counters (index = id)
+    loop_variables: Vec<String>,
+    /// Variables saved on PROC entry (parameters and LOCALs) (index = id)
+    saved_variables: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Transpiler {
+    fn new(lines: Vec<(u16, Statement)>) -> Self {
+        let mut functions = HashMap::new();
+        let mut procedures = HashMap::new();
+        for (index, (_, statement)) in lines.iter().enumerate() {
+            match statement {
+                Statement::DefFn {
+                    name,
+                    params,
+                    expression: Some(expression),
+                } => {
+                    functions.insert(name.clone(), (params.clone(), expression.clone()));
+                }
+                Statement::DefProc { name, params } => {
+                    procedures.insert(name.clone(), (index, params.clone()));
+                }
+                _ => {}
+            }
+        }
+
+        Self {
+            lines,
+            functions,
+            procedures,
+            function_types: HashMap::new(),
+            inferring: BTreeSet::new(),
+            scalars: BTreeSet::new(),
+            arrays: BTreeSet::new(),
+            loop_variables: Vec::new(),
+            saved_variables: Vec::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// Generate the complete Rust source
+    fn generate(&mut self) -> String {
+        let mut arms = String::new();
+        for index in 0..self.lines.len() {
+            let number = self.lines[index].0;
+            let statement = self.lines[index].1.clone();
+            let code = match self.statement(index, &statement) {
+                Ok(code) => code,
+                Err(message) => {
+                    self.diagnostics.push(Diagnostic {
+                        line: number,
+                        message: message.clone(),
+                    });
+                    format!("// Not translated: {}\n", message)
+                }
+            };
+            let _ = writeln!(arms, "                {} => {{", number);
+            for line in code.lines() {
+                let _ = writeln!(arms, "                    {}", line);
+            }
+            let _ = writeln!(
+                arms,
+                "                    line = {};",
+                self.next_line(index)
+            );
+            let _ = writeln!(arms, "                }}");
+        }
+
+        let mut functions = String::new();
+        let mut names: Vec<String> = self.functions.keys().cloned().collect();
+        names.sort();
+        for name in names {
+            match self.function(&name) {
+                Ok(code) => functions.push_str(&code),
+                Err(message) => {
+                    let line = self
+                        .lines
+                        .iter()
+                        .find(|(_, s)| matches!(s, Statement::DefFn { name: n, .. } if *n == name))
+                        .map_or(0, |(number, _)| *number);
+                    self.diagnostics.push(Diagnostic {
+                        line,
+                        message: format!("FN{}: {}", name, message),
+                    });
+                }
+            }
+        }
+
+        let mut fields = String::new();
+        for name in &self.scalars {
+            let _ = writeln!(
+                fields,
+                "    {}: {},",
+                field(name),
+                Type::of_name(name).rust()
+            );
+        }
+        for name in &self.arrays {
+            let _ = writeln!(
+                fields,
+                "    {}: Array<{}>,",
+                array_field(name),
+                Type::of_name(name).rust()
+            );
+        }
+
+        let first = self
+            .lines
+            .first()
+            .map_or("END".to_string(), |(n, _)| n.to_string());
+        let mut source = String::new();
+        source.push_str(PRELUDE);
+        let _ = write!(
+            source,
+            "\n#[derive(Default)]\nstruct Program {{\n    col: usize,\n    fors: Vec<ForFrame>,\n    \
+             repeats: Vec<u32>,\n    whiles: Vec<u32>,\n    gosubs: Vec<u32>,\n    \
+             procs: Vec<ProcFrame>,\n{}}}\n\nimpl Program {{\n",
+            fields
+        );
+        source.push_str(PROGRAM_METHODS);
+        source.push_str(&self.loop_methods());
+        source.push_str(&self.save_methods());
+        source.push_str(&functions);
+        let _ = write!(
+            source,
+            "    fn run(&mut self) {{\n        let mut line: u32 = {};\n        loop {{\n            \
+             match line {{\n{}                _ => return,\n            }}\n        }}\n    }}\n}}\n",
+            first, arms
+        );
+        source.push_str(MAIN);
+        source
+    }
+
+    /// Line number following the line at `index` (END after the last line)
+    fn next_line(&self, index: usize) -> String {
+        self.lines
+            .get(index + 1)
+            .map_or("END".to_string(), |(n, _)| n.to_string())
+    }
+
+    /// Code to jump to a line
+    fn jump(&self, target: u16) -> std::result::Result<String, String> {
+        if self.lines.iter().any(|(n, _)| *n == target) {
+            Ok(format!("line = {};\ncontinue;\n", target))
+        } else {
+            Err(format!("No such line: {}", target))
+        }
+    }
+
+    /// Translate one statement to a block of Rust statements
+    fn statement(
+        &mut self,
+        index: usize,
+        statement: &Statement,
+    ) -> std::result::Result<String, String> {
+        let next = self.next_line(index);
+        Ok(match statement {
+            // A multi-line DEF FN is not supported, and is reported below
+            Statement::Empty
+            | Statement::Rem { .. }
+            | Statement::DefFn {
+                expression: Some(_),
+                ..
+            } => String::new(),
+            // Falling into a DEF PROC line does nothing, as in the interpreter
+            Statement::DefProc { .. } | Statement::EndIf => String::new(),
+            Statement::End | Statement::Stop | Statement::Quit => "return;\n".to_string(),
+            Statement::Assignment { target, expression } => {
+                let (code, ty) = self.expression(expression)?;
+                self.scalars.insert(target.clone());
+                let value = convert(code, ty, Type::of_name(target))?;
+                format!("self.{} = {};\n", field(target), value)
+            }
+            Statement::ArrayAssignment {
+                name,
+                indices,
+                expression,
+            } => {
+                let indices = self.indices(indices)?;
+                let (code, ty) = self.expression(expression)?;
+                let value = convert(code, ty, Type::of_name(name))?;
+                self.arrays.insert(name.clone());
+                format!(
+                    "let indices = [{}];\nlet value = {};\nself.{}.set(&indices, value);\n",
+                    indices,
+                    value,
+                    array_field(name)
+                )
+            }
+            Statement::Dim { arrays } => {
+                let mut code = String::new();
+                for (name, dimensions) in arrays {
+                    let dimensions = self.indices(dimensions)?;
+                    self.arrays.insert(name.clone());
+                    let _ = writeln!(code, "self.{} = Array::new(&[{}]);", array_field(name), dimensions);
+                }
+                code
+            }
+            Statement::Print { items } => self.print(items)?,
+            Statement::Input { variables } => {
+                let mut code = String::new();
+                for variable in variables {
+                    let read = |name| match Type::of_name(name) {
+                        Type::Int => "self.input_number() as i32",
+                        Type::Real => "self.input_number()",
+                        Type::Str => "self.input_line()",
+                    };
+                    match variable {
+                        Expression::Variable(name) => {
+                            self.scalars.insert(name.clone());
+                            let _ = writeln!(code, "self.{} = {};", field(name), read(name));
+                        }
+                        Expression::ArrayAccess { name, indices } => {
+                            let indices = self.indices(indices)?;
+                            self.arrays.insert(name.clone());
+                            let _ = writeln!(
+                                code,
+                                "let indices = [{}];\nlet value = {};\nself.{}.set(&indices, value);",
+                                indices,
+                                read(name),
+                                array_field(name)
+                            );
+                        }
+                        _ => return Err("Expected variable name".to_string()),
+                    }
+                }
+                code
+            }
+            Statement::If {
+                condition,
+                then_part,
+                else_part,
+            } => {
+                let condition = self.condition(condition)?;
+                let mut code = format!("if {} {{\n", condition);
+                for statement in then_part {
+                    code.push_str(&indent(&self.statement(index, statement)?));
+                }
+                code.push('}');
+                if let Some(else_part) = else_part {
+                    code.push_str(" else {\n");
+                    for statement in else_part {
+                        code.push_str(&indent(&self.statement(index, statement)?));
+                    }
+                    code.push('}');
+                }
+                code.push('\n');
+                code
+            }
+            Statement::IfBlock { condition } => {
+                let condition = self.condition(condition)?;
+                let target = self.skip_block(index, true).ok_or("Missing ENDIF")?;
+                format!("if !({}) {{\n    line = {};\n    continue;\n}}\n", condition, target)
+            }
+            Statement::Else => {
+                let target = self.skip_block(index, false).ok_or("Missing ENDIF")?;
+                format!("line = {};\ncontinue;\n", target)
+            }
+            Statement::Goto { line_number } => self.jump(*line_number)?,
+            Statement::Gosub { line_number } => {
+                format!("self.gosubs.push({});\n{}", next, self.jump(*line_number)?)
+            }
+            Statement::Return { value: None } => {
+                "line = self.gosubs.pop().expect(\"No GOSUB\");\ncontinue;\n".to_string()
+            }
+            Statement::OnGoto {
+                expression,
+                targets,
+            }
+            | Statement::OnGosub {
+                expression,
+                targets,
+            } => {
+                let (code, ty) = self.expression(expression)?;
+                let selector = convert(code, ty, Type::Int)?;
+                let mut arms = String::new();
+                for (i, target) in targets.iter().enumerate() {
+                    self.jump(*target)?;
+                    let _ = writeln!(arms, "    {} => {},", i + 1, target);
+                }
+                let push = if matches!(statement, Statement::OnGosub { .. }) {
+                    format!("self.gosubs.push({});\n", next)
+                } else {
+                    String::new()
+                };
+                format!(
+                    "{}line = match {} {{\n{}    _ => panic!(\"ON range\"),\n}};\ncontinue;\n",
+                    push, selector, arms
+                )
+            }
+            Statement::For {
+                variable,
+                start,
+                end,
+                step,
+            } => {
+                let Expression::Variable(variable) = variable else {
+                    return Err("FOR with an array element is not supported".to_string());
+                };
+                if Type::of_name(variable) == Type::Str {
+                    return Err("Type mismatch".to_string());
+                }
+                let (code, ty) = self.expression(start)?;
+                let start = convert(code, ty, Type::of_name(variable))?;
+                let (code, ty) = self.expression(end)?;
+                let limit = convert(code, ty, Type::Real)?;
+                let step = match step {
+                    Some(step) => {
+                        let (code, ty) = self.expression(step)?;
+                        convert(code, ty, Type::Real)?
+                    }
+                    None => "1.0".to_string(),
+                };
+                self.scalars.insert(variable.clone());
+                let id = self.loop_variable(variable);
+                format!(
+                    "self.{} = {};\nself.fors.push(ForFrame {{ var: {}, limit: {}, step: {}, body: {} }});\n",
+                    field(variable),
+                    start,
+                    id,
+                    limit,
+                    step,
+                    next
+                )
+            }
+            Statement::Next { variables } => {
+                if variables.is_empty() {
+                    "if let Some(body) = self.next_for(None) {\n    line = body;\n    continue;\n}\n"
+                        .to_string()
+                } else {
+                    let mut code = String::new();
+                    for variable in variables {
+                        let Expression::Variable(variable) = variable else {
+                            return Err("NEXT with an array element is not supported".to_string());
+                        };
+                        let id = self.loop_variable(variable);
+                        let _ = write!(
+                            code,
+                            "if let Some(body) = self.next_for(Some({})) {{\n    line = body;\n    continue;\n}}\n",
+                            id
+                        );
+                    }
+                    code
+                }
+            }
+            Statement::Repeat => format!("self.repeats.push({});\n", next),
+            Statement::Until { condition } => {
+                let condition = self.condition(condition)?;
+                format!(
+                    "if !({}) {{\n    line = *self.repeats.last().expect(\"No REPEAT\");\n    continue;\n}}\nself.repeats.pop();\n",
+                    condition
+                )
+            }
+            Statement::While { condition } => {
+                let condition = self.condition(condition)?;
+                let target = self.skip_while(index).ok_or("WHILE without matching ENDWHILE")?;
+                format!(
+                    "if {} {{\n    self.whiles.push({});\n}} else {{\n    line = {};\n    continue;\n}}\n",
+                    condition, self.lines[index].0, target
+                )
+            }
+            Statement::EndWhile => {
+                "line = self.whiles.pop().expect(\"No WHILE\");\ncontinue;\n".to_string()
+            }
+            Statement::ProcCall { name, args } => {
+                let (def_index, params) = self
+                    .procedures
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| format!("No such PROC: {}", name))?;
+                if params.len() != args.len() {
+                    return Err(format!("Arguments: PROC{} takes {}", name, params.len()));
+                }
+                if params.iter().any(|param| param.ends_with("()")) {
+                    return Err("Array parameter is not supported".to_string());
+                }
+                let body = self.next_line(def_index);
+                let mut code = String::new();
+                // Evaluate every argument before any parameter changes
+                for (i, (param, arg)) in params.iter().zip(args).enumerate() {
+                    let (value, ty) = self.expression(arg)?;
+                    let value = convert(value, ty, Type::of_name(param))?;
+                    let _ = writeln!(code, "let arg{} = {};", i, value);
+                }
+                code.push_str("let mut saved = Vec::new();\n");
+                for (i, param) in params.iter().enumerate() {
+                    self.scalars.insert(param.clone());
+                    let id = self.saved_variable(param);
+                    let _ = writeln!(code, "saved.push(({}, self.save({})));", id, id);
+                    let _ = writeln!(code, "self.{} = arg{};", field(param), i);
+                }
+                let _ = write!(
+                    code,
+                    "self.procs.push(ProcFrame {{ ret: {}, saved }});\nline = {};\ncontinue;\n",
+                    next, body
+                );
+                code
+            }
+            Statement::Local { variables } => {
+                let mut code = String::new();
+                for name in variables {
+                    if name.ends_with("()") {
+                        return Err("LOCAL array is not supported".to_string());
+                    }
+                    self.scalars.insert(name.clone());
+                    let id = self.saved_variable(name);
+                    let _ = writeln!(
+                        code,
+                        "let value = self.save({});\nself.procs.last_mut().expect(\"Not LOCAL\").saved.push(({}, value));\nself.{} = Default::default();",
+                        id,
+                        id,
+                        field(name)
+                    );
+                }
+                code
+            }
+            Statement::EndProc => "let frame = self.procs.pop().expect(\"No PROC\");\n\
+                 for (id, value) in frame.saved.into_iter().rev() {\n    self.restore(id, value);\n}\n\
+                 line = frame.ret;\ncontinue;\n"
+                .to_string(),
+            other => {
+                let debug = format!("{:?}", other);
+                let kind = debug
+                    .split(|c: char| !c.is_alphanumeric())
+                    .next()
+                    .unwrap_or_default()
+                    .to_string();
+                return Err(format!("{} is not supported", kind));
+            }
+        })
+    }
+
+    /// Translate a PRINT statement
+    fn print(&mut self, items: &[PrintItem]) -> std::result::Result<String, String> {
+        let mut code = String::new();
+        // Numbers are right-justified in ten column fields, as with the
+        // default @%, except from a semicolon up to the next comma
+        let mut justify = true;
+        for item in items {
+            match item {
+                PrintItem::Expression(expression) => {
+                    let (value, ty) = self.expression(expression)?;
+                    let text = match ty {
+                        Type::Str => value,
+                        _ if justify => format!("format!(\"{{:>10}}\", {})", wrap(&value)),
+                        _ => format!("{}.to_string()", wrap(&value)),
+                    };
+                    let _ = writeln!(code, "let text = {};\nself.print_str(&text);", text);
+                }
+                PrintItem::Hex(expression) => {
+                    let (value, ty) = self.expression(expression)?;
+                    let value = convert(value, ty, Type::Int)?;
+                    let width = if justify { ":>10X" } else { ":X" };
+                    let _ = writeln!(
+                        code,
+                        "let text = format!(\"{{{}}}\", {} as u32);\nself.print_str(&text);",
+                        width,
+                        wrap(&value)
+                    );
+                }
+                PrintItem::Tab(expression) | PrintItem::Spc(expression) => {
+                    let (value, ty) = self.expression(expression)?;
+                    let value = convert(value, ty, Type::Int)?;
+                    let method = if matches!(item, PrintItem::Tab(_)) {
+                        "print_tab"
+                    } else {
+                        "print_spc"
+                    };
+                    let _ = writeln!(code, "let count = {};\nself.{}(count);", value, method);
+                }
+                PrintItem::Comma => {
+                    code.push_str("self.print_comma();\n");
+                    justify = true;
+                }
+                PrintItem::Apostrophe => code.push_str("self.print_newline();\n"),
+                PrintItem::Semicolon => justify = false,
+            }
+        }
+        if !matches!(items.last(), Some(PrintItem::Semicolon | PrintItem::Comma)) {
+            code.push_str("self.print_newline();\n");
+        }
+        Ok(code)
+    }
+
+    /// Translate a condition to a Rust bool
+    fn condition(&mut self, expression: &Expression) -> std::result::Result<String, String> {
+        let (code, ty) = self.expression(expression)?;
+        match ty {
+            Type::Int => Ok(format!("{} != 0", wrap(&code))),
+            Type::Real => Ok(format!("{} != 0.0", wrap(&code))),
+            Type::Str => Err("Type mismatch".to_string()),
+        }
+    }
+
+    /// Translate array subscripts or dimensions to a list of i32 expressions
+    fn indices(&mut self, indices: &[Expression]) -> std::result::Result<String, String> {
+        let mut codes = Vec::new();
+        for index in indices {
+            let (code, ty) = self.expression(index)?;
+            codes.push(convert(code, ty, Type::Int)?);
+        }
+        Ok(codes.join(", "))
+    }
+
+    /// Translate an expression
+    fn expression(&mut self, expression: &Expression) -> Code {
+        match expression {
+            Expression::Integer(value) => Ok((format!("({}_i32)", value), Type::Int)),
+            Expression::Real(value) => Ok((format!("({:?}_f64)", value), Type::Real)),
+            Expression::String(value) => Ok((format!("String::from({:?})", value), Type::Str)),
+            Expression::Variable(name) => match name.as_str() {
+                "PI" => Ok(("std::f64::consts::PI".to_string(), Type::Real)),
+                "TRUE" => Ok(("(-1_i32)".to_string(), Type::Int)),
+                "FALSE" => Ok(("(0_i32)".to_string(), Type::Int)),
+                _ => {
+                    if !name
+                        .trim_end_matches(['%', '$'])
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_')
+                    {
+                        return Err(format!("{} is not supported", name));
+                    }
+                    self.scalars.insert(name.clone());
+                    let ty = Type::of_name(name);
+                    let code = match ty {
+                        Type::Str => format!("self.{}.clone()", field(name)),
+                        _ => format!("self.{}", field(name)),
+                    };
+                    Ok((code, ty))
+                }
+            },
+            Expression::ArrayAccess { name, indices } => {
+                let indices = self.indices(indices)?;
+                self.arrays.insert(name.clone());
+                Ok((
+                    format!("self.{}.get(&[{}]).clone()", array_field(name), indices),
+                    Type::of_name(name),
+                ))
+            }
+            Expression::FunctionCall { name, args } => self.call(name, args),
+            Expression::UnaryOp { op, operand } => {
+                let (code, ty) = self.expression(operand)?;
+                match op {
+                    UnaryOperator::Plus => Ok((code, ty)),
+                    UnaryOperator::Minus => match ty {
+                        Type::Int => Ok((format!("{}.wrapping_neg()", wrap(&code)), Type::Int)),
+                        Type::Real => Ok((format!("(-{})", wrap(&code)), Type::Real)),
+                        Type::Str => Err("Type mismatch".to_string()),
+                    },
+                    UnaryOperator::Not => {
+                        Ok((format!("(!{})", convert(code, ty, Type::Int)?), Type::Int))
+                    }
+                }
+            }
+            Expression::BinaryOp { left, op, right } => {
+                let left = self.expression(left)?;
+                let right = self.expression(right)?;
+                binary(op, left, right)
+            }
+            // There is no BBC memory in a compiled program
+            Expression::Indirect { op, .. } => Err(format!("{} is not supported", op.symbol())),
+        }
+    }
+
+    /// Translate a call to a built-in function or FN
+    fn call(&mut self, name: &str, args: &[Expression]) -> Code {
+        if self.functions.contains_key(name) {
+            return self.call_fn(name, args);
+        }
+
+        let mut values = Vec::new();
+        for arg in args {
+            values.push(self.expression(arg)?);
+        }
+        let arity = |n: usize| {
+            if values.len() == n {
+                Ok(())
+            } else {
+                Err(format!("Wrong number of arguments to {}", name))
+            }
+        };
+        let real = |i: usize| convert(values[i].0.clone(), values[i].1, Type::Real);
+        let int = |i: usize| convert(values[i].0.clone(), values[i].1, Type::Int);
+        let string = |i: usize| convert(values[i].0.clone(), values[i].1, Type::Str);
+
+        let math = |method: &str| -> Code {
+            arity(1)?;
+            Ok((format!("{}.{}()", wrap(&real(0)?), method), Type::Real))
+        };
+        match name {
+            "SQR" => math("sqrt"),
+            "SIN" => math("sin"),
+            "COS" => math("cos"),
+            "TAN" => math("tan"),
+            "ATN" => math("atan"),
+            "ACS" => math("acos"),
+            "ASN" => math("asin"),
+            "EXP" => math("exp"),
+            "LN" => math("ln"),
+            "LOG" => math("log10"),
+            "DEG" => math("to_degrees"),
+            "RAD" => math("to_radians"),
+            "ABS" => {
+                arity(1)?;
+                match values[0].1 {
+                    Type::Int => Ok((format!("{}.wrapping_abs()", wrap(&values[0].0)), Type::Int)),
+                    _ => Ok((format!("{}.abs()", wrap(&real(0)?)), Type::Real)),
+                }
+            }
+            "INT" => {
+                arity(1)?;
+                Ok((format!("({}.floor() as i32)", wrap(&real(0)?)), Type::Int))
+            }
+            "SGN" => {
+                arity(1)?;
+                Ok((format!("bbc_sgn({})", real(0)?), Type::Int))
+            }
+            "NOT" => {
+                arity(1)?;
+                Ok((format!("(!{})", int(0)?), Type::Int))
+            }
+            "LEN" => {
+                arity(1)?;
+                Ok((
+                    format!("({}.chars().count() as i32)", wrap(&string(0)?)),
+                    Type::Int,
+                ))
+            }
+            "ASC" => {
+                arity(1)?;
+                Ok((format!("bbc_asc(&{})", string(0)?), Type::Int))
+            }
+            "VAL" => {
+                arity(1)?;
+                Ok((format!("bbc_val(&{})", string(0)?), Type::Real))
+            }
+            "CHR$" => {
+                arity(1)?;
+                Ok((format!("bbc_chr({})", int(0)?), Type::Str))
+            }
+            "STR$" => {
+                arity(1)?;
+                Ok((format!("{}.to_string()", wrap(&values[0].0)), Type::Str))
+            }
+            "LEFT$" => {
+                arity(2)?;
+                Ok((format!("bbc_left(&{}, {})", string(0)?, int(1)?), Type::Str))
+            }
+            "RIGHT$" => {
+                arity(2)?;
+                Ok((
+                    format!("bbc_right(&{}, {})", string(0)?, int(1)?),
+                    Type::Str,
+                ))
+            }
+            "MID$" => {
+                let length = match values.len() {
+                    2 => "255".to_string(),
+                    3 => int(2)?,
+                    _ => return Err("Wrong number of arguments to MID$".to_string()),
+                };
+                Ok((
+                    format!("bbc_mid(&{}, {}, {})", string(0)?, int(1)?, length),
+                    Type::Str,
+                ))
+            }
+            "STRING$" => {
+                arity(2)?;
+                Ok((
+                    format!(
+                        "{}.repeat({}.max(0) as usize)",
+                        wrap(&string(1)?),
+                        wrap(&int(0)?)
+                    ),
+                    Type::Str,
+                ))
+            }
+            "INSTR" => {
+                let start = match values.len() {
+                    2 => "1".to_string(),
+                    3 => int(2)?,
+                    _ => return Err("Wrong number of arguments to INSTR".to_string()),
+                };
+                Ok((
+                    format!("bbc_instr(&{}, &{}, {})", string(0)?, string(1)?, start),
+                    Type::Int,
+                ))
+            }
+            _ => Err(format!("{} is not supported", name)),
+        }
+    }
+
+    /// Translate a call to a DEF FN function
+    fn call_fn(&mut self, name: &str, args: &[Expression]) -> Code {
+        let (params, _) = self.functions[name].clone();
+        if params.len() != args.len() {
+            return Err(format!("Arguments: FN{} takes {}", name, params.len()));
+        }
+        if params.iter().any(|param| param.ends_with("()")) {
+            return Err("Array parameter is not supported".to_string());
+        }
+        let mut values = Vec::new();
+        for (param, arg) in params.iter().zip(args) {
+            let (code, ty) = self.expression(arg)?;
+            values.push(convert(code, ty, Type::of_name(param))?);
+        }
+        let ty = self.function_type(name)?;
+        Ok((format!("self.fn_{}({})", name, values.join(", ")), ty))
+    }
+
+    /// Return type of a DEF FN function
+    fn function_type(&mut self, name: &str) -> std::result::Result<Type, String> {
+        if let Some(ty) = self.function_types.get(name) {
+            return Ok(*ty);
+        }
+        // A recursive call while the type is being worked out: assume a number
+        if !self.inferring.insert(name.to_string()) {
+            return Ok(Type::Real);
+        }
+        let (_, expression) = self.functions[name].clone();
+        let result = self.expression(&expression).map(|(_, ty)| ty);
+        self.inferring.remove(name);
+        let ty = result?;
+        self.function_types.insert(name.to_string(), ty);
+        Ok(ty)
+    }
+
+    /// Generate the method for a DEF FN function
+    fn function(&mut self, name: &str) -> std::result::Result<String, String> {
+        let (params, expression) = self.functions[name].clone();
+        let ty = self.function_type(name)?;
+        let (body, body_type) = self.expression(&expression)?;
+        let body = convert(body, body_type, ty)?;
+
+        let arguments: Vec<String> = params
+            .iter()
+            .enumerate()
+            .map(|(i, p)| format!("arg{}: {}", i, Type::of_name(p).rust()))
+            .collect();
+        let mut code = format!(
+            "    fn fn_{}(&mut self, {}) -> {} {{\n",
+            name,
+            arguments.join(", "),
+            ty.rust()
+        );
+        // Parameters are local to the function
+        for (i, param) in params.iter().enumerate() {
+            self.scalars.insert(param.clone());
+            let _ = writeln!(
+                code,
+                "        let saved{} = std::mem::replace(&mut self.{}, arg{});",
+                i,
+                field(param),
+                i
+            );
+        }
+        let _ = writeln!(code, "        let result = {};", body);
+        for (i, param) in params.iter().enumerate() {
+            let _ = writeln!(code, "        self.{} = saved{};", field(param), i);
+        }
+        code.push_str("        result\n    }\n\n");
+        Ok(code)
+    }
+
+    /// Id of a FOR loop variable
+    fn loop_variable(&mut self, name: &str) -> usize {
+        match self.loop_variables.iter().position(|v| v == name) {
+            Some(id) => id,
+            None => {
+                self.loop_variables.push(name.to_string());
+                self.loop_variables.len() - 1
+            }
+        }
+    }
+
+    /// Id of a variable saved by PROC entry or LOCAL
+    fn saved_variable(&mut self, name: &str) -> usize {
+        match self.saved_variables.iter().position(|v| v == name) {
+            Some(id) => id,
+            None => {
+                self.saved_variables.push(name.to_string());
+                self.saved_variables.len() - 1
+            }
+        }
+    }
+
+    /// Line after the ENDWHILE matching the WHILE at `index`
+    fn skip_while(&self, index: usize) -> Option<String> {
+        let mut depth = 0;
+        for i in index + 1..self.lines.len() {
+            match self.lines[i].1 {
+                Statement::While { .. } => depth += 1,
+                Statement::EndWhile if depth == 0 => return Some(self.next_line(i)),
+                Statement::EndWhile => depth -= 1,
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Line after the ELSE (if `stop_at_else`) or ENDIF ending a block IF branch
+    fn skip_block(&self, index: usize, stop_at_else: bool) -> Option<String> {
+        let mut depth = 0;
+        for i in index + 1..self.lines.len() {
+            match self.lines[i].1 {
+                Statement::IfBlock { .. } => depth += 1,
+                Statement::Else if depth == 0 && stop_at_else => return Some(self.next_line(i)),
+                Statement::EndIf if depth == 0 => return Some(self.next_line(i)),
+                Statement::EndIf => depth -= 1,
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Generate the FOR/NEXT support methods
+    fn loop_methods(&self) -> String {
+        let mut arms = String::new();
+        for (id, name) in self.loop_variables.iter().enumerate() {
+            let step = match Type::of_name(name) {
+                Type::Int => format!(
+                    "self.{0} = self.{0}.wrapping_add(step as i32);\n                self.{0} as f64",
+                    field(name)
+                ),
+                _ => format!("self.{0} += step;\n                self.{0}", field(name)),
+            };
+            let _ = writeln!(
+                arms,
+                "            {} => {{\n                {}\n            }}",
+                id, step
+            );
+        }
+        format!(
+            "    /// Add the step to a loop variable, returning its new value\n    \
+             fn step_var(&mut self, var: usize, step: f64) -> f64 {{\n        \
+             match var {{\n{}            _ => unreachable!(),\n        }}\n    }}\n\n{}",
+            arms, NEXT_FOR
+        )
+    }
+
+    /// Generate the methods that save and restore PROC parameters and LOCALs
+    fn save_methods(&self) -> String {
+        let mut save = String::new();
+        let mut restore = String::new();
+        for (id, name) in self.saved_variables.iter().enumerate() {
+            let (variant, value) = match Type::of_name(name) {
+                Type::Int => ("Int", format!("self.{}", field(name))),
+                Type::Real => ("Real", format!("self.{}", field(name))),
+                Type::Str => ("Str", format!("self.{}.clone()", field(name))),
+            };
+            let _ = writeln!(save, "            {} => Value::{}({}),", id, variant, value);
+            let _ = writeln!(
+                restore,
+                "            ({}, Value::{}(value)) => self.{} = value,",
+                id,
+                variant,
+                field(name)
+            );
+        }
+        format!(
+            "    fn save(&self, var: usize) -> Value {{\n        match var {{\n{}            \
+             _ => unreachable!(),\n        }}\n    }}\n\n    \
+             fn restore(&mut self, var: usize, value: Value) {{\n        match (var, value) {{\n{}            \
+             _ => unreachable!(),\n        }}\n    }}\n\n",
+            save, restore
+        )
+    }
+}
+
+/// Translate a binary operation
+fn binary(op: &BinaryOperator, left: (String, Type), right: (String, Type)) -> Code {
+    let (l, lt) = left;
+    let (r, rt) = right;
+    let strings = lt == Type::Str || rt == Type::Str;
+    let ints = lt == Type::Int && rt == Type::Int;
+    let real = |code: String, ty: Type| convert(code, ty, Type::Real).map(|c| wrap(&c));
+    let int = |code: String, ty: Type| convert(code, ty, Type::Int).map(|c| wrap(&c));
+
+    let comparison = |rust_op: &str| -> Code {
+        let code = if strings {
+            if lt != rt {
+                return Err("Type mismatch".to_string());
+            }
+            format!("bbc_bool({} {} {})", l, rust_op, r)
+        } else if ints {
+            format!("bbc_bool({} {} {})", wrap(&l), rust_op, wrap(&r))
+        } else {
+            format!(
+                "bbc_bool({} {} {})",
+                real(l.clone(), lt)?,
+                rust_op,
+                real(r.clone(), rt)?
+            )
+        };
+        Ok((code, Type::Int))
+    };
+    let arithmetic = |int_method: &str, rust_op: &str| -> Code {
+        if strings {
+            Err("Type mismatch".to_string())
+        } else if ints {
+            Ok((format!("{}.{}({})", wrap(&l), int_method, r), Type::Int))
+        } else {
+            Ok((
+                format!(
+                    "({} {} {})",
+                    real(l.clone(), lt)?,
+                    rust_op,
+                    real(r.clone(), rt)?
+                ),
+                Type::Real,
+            ))
+        }
+    };
+
+    match op {
+        BinaryOperator::Add | BinaryOperator::StringConcat if strings => {
+            if lt != rt {
+                return Err("Type mismatch".to_string());
+            }
+            Ok((format!("format!(\"{{}}{{}}\", {}, {})", l, r), Type::Str))
+        }
+        BinaryOperator::Add | BinaryOperator::StringConcat => arithmetic("wrapping_add", "+"),
+        BinaryOperator::Subtract => arithmetic("wrapping_sub", "-"),
+        BinaryOperator::Multiply => arithmetic("wrapping_mul", "*"),
+        BinaryOperator::Divide => {
+            if strings {
+                return Err("Type mismatch".to_string());
+            }
+            Ok((format!("({} / {})", real(l, lt)?, real(r, rt)?), Type::Real))
+        }
+        BinaryOperator::Power => {
+            if strings {
+                return Err("Type mismatch".to_string());
+            }
+            Ok((
+                format!("{}.powf({})", real(l, lt)?, real(r, rt)?),
+                Type::Real,
+            ))
+        }
+        BinaryOperator::IntegerDivide => Ok((
+            format!("bbc_div({}, {})", int(l, lt)?, int(r, rt)?),
+            Type::Int,
+        )),
+        BinaryOperator::Modulo => Ok((
+            format!("bbc_mod({}, {})", int(l, lt)?, int(r, rt)?),
+            Type::Int,
+        )),
+        BinaryOperator::And => Ok((format!("({} & {})", int(l, lt)?, int(r, rt)?), Type::Int)),
+        BinaryOperator::Or => Ok((format!("({} | {})", int(l, lt)?, int(r, rt)?), Type::Int)),
+        BinaryOperator::Eor => Ok((format!("({} ^ {})", int(l, lt)?, int(r, rt)?), Type::Int)),
+        BinaryOperator::LeftShift => Ok((
+            format!("{}.wrapping_shl({} as u32)", int(l, lt)?, int(r, rt)?),
+            Type::Int,
+        )),
+        BinaryOperator::RightShift => Ok((
+            format!("{}.wrapping_shr({} as u32)", int(l, lt)?, int(r, rt)?),
+            Type::Int,
+        )),
+        BinaryOperator::Equal => comparison("=="),
+        BinaryOperator::NotEqual => comparison("!="),
+        BinaryOperator::LessThan => comparison("<"),
+        BinaryOperator::LessThanOrEqual => comparison("<="),
+        BinaryOperator::GreaterThan => comparison(">"),
+        BinaryOperator::GreaterThanOrEqual => comparison(">="),
+    }
+}
+
+/// Convert a value between types (numbers convert freely, strings do not)
+fn convert(code: String, from: Type, to: Type) -> std::result::Result<String, String> {
+    match (from, to) {
+        (a, b) if a == b => Ok(code),
+        (Type::Int, Type::Real) => Ok(format!("({} as f64)", wrap(&code))),
+        (Type::Real, Type::Int) => Ok(format!("({} as i32)", wrap(&code))),
+        _ => Err("Type mismatch".to_string()),
+    }
+}
+
+/// Parenthesise an expression unless it is already a single term
+fn wrap(code: &str) -> String {
+    let simple =
+        code.starts_with('(') && code.ends_with(')') && balanced_parens(&code[1..code.len() - 1])
+            || code
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    if simple {
+        code.to_string()
+    } else {
+        format!("({})", code)
+    }
+}
+
+/// Whether parentheses in `code` never close more than they open
+fn balanced_parens(code: &str) -> bool {
+    let mut depth = 0i32;
+    for c in code.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        if depth < 0 {
+            return false;
+        }
+    }
+    depth == 0
+}
+
+/// Indent a block of generated code by one level
+fn indent(code: &str) -> String {
+    code.lines().map(|line| format!("    {}\n", line)).collect()
+}
+
+/// Runtime support included in every generated program
+const PRELUDE: &str = r#"//! Generated from a BBC BASIC program by *COMPILE
+#![allow(non_snake_case, unused_parens, unused_assignments, unreachable_code, dead_code)]
+
+use std::io::{self, BufRead, Write};
+
+/// Line number that ends the program
+const END: u32 = u32::MAX;
+
+/// A DIMensioned array
+#[derive(Debug, Clone, Default)]
+struct Array<T> {
+    dims: Vec<usize>,
+    data: Vec<T>,
+}
+
+impl<T: Clone + Default> Array<T> {
+    fn new(dims: &[i32]) -> Self {
+        let dims: Vec<usize> = dims.iter().map(|&d| d.max(0) as usize + 1).collect();
+        let size = dims.iter().product();
+        Self { dims, data: vec![T::default(); size] }
+    }
+
+    fn offset(&self, indices: &[i32]) -> usize {
+        assert!(!self.dims.is_empty(), "Array not dimensioned");
+        assert_eq!(indices.len(), self.dims.len(), "Subscript out of range");
+        indices.iter().zip(&self.dims).fold(0, |offset, (&i, &dim)| {
+            assert!(i >= 0 && (i as usize) < dim, "Subscript out of range");
+            offset * dim + i as usize
+        })
+    }
+
+    fn get(&self, indices: &[i32]) -> &T {
+        &self.data[self.offset(indices)]
+    }
+
+    fn set(&mut self, indices: &[i32], value: T) {
+        let offset = self.offset(indices);
+        self.data[offset] = value;
+    }
+}
+
+/// A saved variable value
+#[derive(Debug, Clone)]
+enum Value {
+    Int(i32),
+    Real(f64),
+    Str(String),
+}
+
+/// An active FOR loop
+#[derive(Debug)]
+struct ForFrame {
+    var: usize,
+    limit: f64,
+    step: f64,
+    body: u32,
+}
+
+/// An active PROC call
+#[derive(Debug)]
+struct ProcFrame {
+    ret: u32,
+    saved: Vec<(usize, Value)>,
+}
+
+fn bbc_bool(value: bool) -> i32 {
+    if value {
+        -1
+    } else {
+        0
+    }
+}
+
+fn bbc_div(a: i32, b: i32) -> i32 {
+    assert!(b != 0, "Division by zero");
+    a.wrapping_div(b)
+}
+
+fn bbc_mod(a: i32, b: i32) -> i32 {
+    assert!(b != 0, "Division by zero");
+    a.wrapping_rem(b)
+}
+
+fn bbc_sgn(value: f64) -> i32 {
+    if value > 0.0 {
+        1
+    } else if value < 0.0 {
+        -1
+    } else {
+        0
+    }
+}
+
+fn bbc_asc(s: &str) -> i32 {
+    s.chars().next().map_or(-1, |c| c as i32)
+}
+
+fn bbc_chr(code: i32) -> String {
+    char::from_u32((code & 0xFF) as u32).unwrap_or('?').to_string()
+}
+
+fn bbc_val(s: &str) -> f64 {
+    let s = s.trim_start();
+    let end = s
+        .char_indices()
+        .find(|&(i, c)| !(c.is_ascii_digit() || c == '.' || (i == 0 && (c == '-' || c == '+'))))
+        .map_or(s.len(), |(i, _)| i);
+    s[..end].parse().unwrap_or(0.0)
+}
+
+fn bbc_left(s: &str, n: i32) -> String {
+    s.chars().take(n.max(0) as usize).collect()
+}
+
+fn bbc_right(s: &str, n: i32) -> String {
+    let len = s.chars().count();
+    s.chars().skip(len.saturating_sub(n.max(0) as usize)).collect()
+}
+
+fn bbc_mid(s: &str, start: i32, n: i32) -> String {
+    s.chars().skip((start.max(1) - 1) as usize).take(n.max(0) as usize).collect()
+}
+
+fn bbc_instr(haystack: &str, needle: &str, start: i32) -> i32 {
+    let skip = (start.max(1) - 1) as usize;
+    let rest: String = haystack.chars().skip(skip).collect();
+    match rest.find(needle) {
+        Some(byte) => (rest[..byte].chars().count() + skip + 1) as i32,
+        None => 0,
+    }
+}
+"#;
+
+/// Output and input methods shared by every generated program
+const PROGRAM_METHODS: &str = r#"    fn print_str(&mut self, text: &str) {
+        print!("{}", text);
+        match text.rfind('\n') {
+            Some(newline) => self.col = text[newline + 1..].chars().count(),
+            None => self.col += text.chars().count(),
+        }
+    }
+
+    fn print_newline(&mut self) {
+        println!();
+        self.col = 0;
+    }
+
+    fn print_comma(&mut self) {
+        self.print_str(&" ".repeat((10 - self.col % 10) % 10));
+    }
+
+    fn print_tab(&mut self, column: i32) {
+        let column = column.max(0) as usize;
+        if column > self.col {
+            self.print_str(&" ".repeat(column - self.col));
+        }
+    }
+
+    fn print_spc(&mut self, count: i32) {
+        self.print_str(&" ".repeat(count.max(0) as usize));
+    }
+
+    fn input_line(&mut self) -> String {
+        self.print_str("?");
+        io::stdout().flush().ok();
+        let mut line = String::new();
+        io::stdin().lock().read_line(&mut line).ok();
+        self.col = 0;
+        line.trim_end_matches(['\r', '\n']).to_string()
+    }
+
+    fn input_number(&mut self) -> f64 {
+        bbc_val(&self.input_line())
+    }
+
+"#;
+
+/// NEXT handling shared by every generated program
+const NEXT_FOR: &str = r#"    /// NEXT: returns the loop body line if the loop continues
+    fn next_for(&mut self, var: Option<usize>) -> Option<u32> {
+        if let Some(var) = var {
+            while self.fors.last().is_some_and(|frame| frame.var != var) {
+                self.fors.pop();
+            }
+        }
+        let frame = self.fors.last().expect("No FOR");
+        let (var, limit, step, body) = (frame.var, frame.limit, frame.step, frame.body);
+        let value = self.step_var(var, step);
+        if (step >= 0.0 && value <= limit) || (step < 0.0 && value >= limit) {
+            Some(body)
+        } else {
+            self.fors.pop();
+            None
+        }
+    }
+
+"#;
+
+/// Entry point of every generated program
+const MAIN: &str = r#"
+fn main() {
+    let mut program = Program::default();
+    program.run();
+    io::stdout().flush().ok();
+}
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::tokenize;
+
+    fn program(lines: &[&str]) -> ProgramStore {
+        let mut program = ProgramStore::new();
+        for line in lines {
+            program.store_line(tokenize(line).unwrap());
+        }
+        program
+    }
+
+    #[test]
+    fn test_expression_types() {
+        let mut transpiler = Transpiler::new(Vec::new());
+        let (code, ty) = transpiler
+            .expression(&Expression::BinaryOp {
+                left: Box::new(Expression::Variable("A%".to_string())),
+                op: BinaryOperator::Add,
+                right: Box::new(Expression::Real(1.5)),
+            })
+            .unwrap();
+        assert_eq!(ty, Type::Real);
+        assert_eq!(code, "((self.int_A as f64) + (1.5_f64))");
+
+        let (_, ty) = transpiler
+            .expression(&Expression::FunctionCall {
+                name: "LEFT$".to_string(),
+                args: vec![
+                    Expression::Variable("N$".to_string()),
+                    Expression::Integer(2),
+                ],
+            })
+            .unwrap();
+        assert_eq!(ty, Type::Str);
+        assert!(transpiler.scalars.contains("N$"));
+    }
+
+    #[test]
+    fn test_unsupported_statements_reported() {
+        let result = transpile(&program(&[
+            "10 MODE 1",
+            "20 X = 1",
+            "30 SOUND 1, -15, 53, 20",
+            "40 GOTO 99",
+        ]))
+        .unwrap();
+        let lines: Vec<u16> = result.diagnostics.iter().map(|d| d.line).collect();
+        assert_eq!(lines, vec![10, 30, 40]);
+        assert_eq!(
+            result.diagnostics[1].to_string(),
+            "Line 30: Sound is not supported"
+        );
+        assert!(result.source.contains("self.real_X = ((1_i32) as f64);"));
+    }
+
+    #[test]
+    fn test_type_mismatch_reported() {
+        let result = transpile(&program(&["10 A% = \"text\""])).unwrap();
+        assert_eq!(result.diagnostics[0].message, "Type mismatch");
+    }
+}
@@ -0,0 +1,98 @@
+//! Turtle graphics for teaching (FORWARD, TURN, PENUP, PENDOWN)
+//!
+//! With the `turtle` feature, a LOGO-style turtle walks the graphics
+//! screen: FORWARD moves it, drawing a line while its pen is down, TURN
+//! turns it clockwise by a number of degrees, and PENUP and PENDOWN lift
+//! and lower its pen (PENDOWN colour also picks the colour, as GCOL 0
+//! does). It starts facing up the screen with its pen down.
+//!
+//! The turtle walks with MOVE and DRAW, so it sets off from wherever the
+//! graphics cursor is, and a MOVE in between moves the turtle too. It keeps
+//! its position to a fraction of a unit, so a hundred short steps end up
+//! where one long one would. MODE puts it back facing up with its pen down.
+
+/// The turtle's heading and pen
+#[derive(Debug, Clone, PartialEq)]
+pub struct Turtle {
+    /// Degrees clockwise from straight up
+    heading: f64,
+    pen_down: bool,
+    /// Where the turtle is exactly, and the point that was rounded to for
+    /// the graphics cursor
+    position: Option<((f64, f64), (i32, i32))>,
+}
+
+impl Default for Turtle {
+    fn default() -> Self {
+        Self {
+            heading: 0.0,
+            pen_down: true,
+            position: None,
+        }
+    }
+}
+
+impl Turtle {
+    /// Degrees clockwise from straight up, from 0 to under 360
+    pub fn heading(&self) -> f64 {
+        self.heading
+    }
+
+    /// Whether FORWARD draws
+    pub fn is_pen_down(&self) -> bool {
+        self.pen_down
+    }
+
+    /// Lift (false) or lower (true) the pen
+    pub fn set_pen(&mut self, down: bool) {
+        self.pen_down = down;
+    }
+
+    /// Turn clockwise by `degrees` (anticlockwise if negative)
+    pub fn turn(&mut self, degrees: f64) {
+        self.heading = (self.heading + degrees).rem_euclid(360.0);
+    }
+
+    /// Walk `distance` graphics units (backwards if negative) from the
+    /// graphics cursor at `cursor`, returning the point to move or draw to
+    pub fn forward(&mut self, distance: f64, cursor: (i32, i32)) -> (i32, i32) {
+        // The cursor lost the fraction, unless something else has moved it
+        let (x, y) = match self.position {
+            Some((exact, rounded)) if rounded == cursor => exact,
+            _ => (f64::from(cursor.0), f64::from(cursor.1)),
+        };
+        let (sin, cos) = self.heading.to_radians().sin_cos();
+        let exact = (x + distance * sin, y + distance * cos);
+        let rounded = (exact.0.round() as i32, exact.1.round() as i32);
+        self.position = Some((exact, rounded));
+        rounded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_turtle() {
+        let mut turtle = Turtle::default();
+        assert!(turtle.is_pen_down());
+        assert_eq!(turtle.forward(100.0, (640, 512)), (640, 612));
+        turtle.turn(90.0);
+        assert_eq!(turtle.forward(50.0, (640, 612)), (690, 612));
+        turtle.turn(-450.0);
+        assert_eq!(turtle.heading(), 0.0);
+        assert_eq!(turtle.forward(-12.0, (690, 612)), (690, 600));
+
+        // Fractions add up over many steps
+        turtle.turn(30.0);
+        let mut cursor = (0, 0);
+        for _ in 0..100 {
+            cursor = turtle.forward(1.0, cursor);
+        }
+        assert_eq!(cursor, (50, 87));
+
+        // A cursor moved by something else is where the turtle starts from
+        assert_eq!(turtle.forward(0.0, (7, 8)), (7, 8));
+    }
+}
@@ -4,7 +4,10 @@
 //! with proper type handling and memory allocation.
 
 use crate::error::{BBCBasicError, Result};
+use crate::history::ChangeLog;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
 
 /// Variable types supported by BBC BASIC
 #[derive(Debug, Clone, PartialEq)]
@@ -15,7 +18,7 @@ pub enum VarType {
 }
 
 /// Represents a BBC BASIC variable value
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Variable {
     /// 32-bit signed integer (A%, B%, etc.)
     Integer(i32),
@@ -100,6 +103,26 @@ impl Variable {
         }
     }
 
+    /// Bytes the value takes on the BBC BASIC heap: 4 for an integer, 5 for
+    /// a real, and a 4 byte descriptor plus the text for a string. Arrays add
+    /// a header giving their dimensions.
+    pub fn heap_size(&self) -> usize {
+        match self {
+            Variable::Integer(_) => 4,
+            Variable::Real(_) => 5,
+            Variable::String(value) => 4 + value.len(),
+            Variable::IntegerArray { values, dimensions } => {
+                1 + 2 * dimensions.len() + 4 * values.len()
+            }
+            Variable::RealArray { values, dimensions } => {
+                1 + 2 * dimensions.len() + 5 * values.len()
+            }
+            Variable::StringArray { values, dimensions } => {
+                1 + 2 * dimensions.len() + values.iter().map(|v| 4 + v.len()).sum::<usize>()
+            }
+        }
+    }
+
     /// Calculate linear index from multi-dimensional indices
     pub fn calculate_index(&self, indices: &[usize]) -> Result<usize> {
         let dimensions = self.dimensions().ok_or(BBCBasicError::TypeMismatch)?;
@@ -123,10 +146,45 @@ impl Variable {
     }
 }
 
+/// A variable, or an array element with its subscripts worked out, as
+/// READ, INPUT and FOR give values to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Variable(String),
+    Element { name: String, indices: Vec<usize> },
+}
+
+impl Target {
+    /// Name of the variable or array
+    pub fn name(&self) -> &str {
+        match self {
+            Target::Variable(name) | Target::Element { name, .. } => name,
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Variable(name) => write!(f, "{}", name),
+            Target::Element { name, indices } => {
+                let indices: Vec<String> = indices.iter().map(usize::to_string).collect();
+                write!(f, "{}({})", name, indices.join(","))
+            }
+        }
+    }
+}
+
 /// Variable storage system
 #[derive(Debug, Clone)]
 pub struct VariableStore {
     variables: HashMap<String, Variable>,
+    /// Enforce the 255 character string limit
+    string_limit: bool,
+    /// Times a variable has been given a new value, or created or removed
+    changes: u64,
+    /// The latest changes, for *HISTORY
+    history: ChangeLog,
 }
 
 impl VariableStore {
@@ -134,25 +192,63 @@ impl VariableStore {
     pub fn new() -> Self {
         Self {
             variables: HashMap::new(),
+            string_limit: true,
+            changes: 0,
+            history: ChangeLog::default(),
         }
     }
 
+    /// Store a variable, counting (and recording) a change if its value is
+    /// new
+    fn store(&mut self, name: String, variable: Variable) {
+        let old = self.variables.get(&name);
+        if old != Some(&variable) {
+            self.changes += 1;
+            if self.history.is_recording() && !variable.is_array() {
+                self.history
+                    .record(name.clone(), old.cloned(), Some(variable.clone()));
+            }
+        }
+        self.variables.insert(name, variable);
+    }
+
+    /// How many times variables have changed, for telling whether a
+    /// program is getting anywhere
+    pub fn changes(&self) -> u64 {
+        self.changes
+    }
+
+    /// The latest changes to variables
+    pub fn history(&self) -> &ChangeLog {
+        &self.history
+    }
+
+    /// The latest changes to variables, for recording them
+    pub fn history_mut(&mut self) -> &mut ChangeLog {
+        &mut self.history
+    }
+
     /// Set an integer variable
     pub fn set_integer_var(&mut self, name: String, value: i32) {
-        self.variables.insert(name, Variable::Integer(value));
+        self.store(name, Variable::Integer(value));
     }
 
     /// Get an integer variable
+    ///
+    /// The resident integers always have a value: 0, or for @%
+    /// [`DEFAULT_PRINT_FORMAT`], until they are first set.
     pub fn get_integer_var(&self, name: &str) -> Option<i32> {
         match self.variables.get(name) {
             Some(Variable::Integer(value)) => Some(*value),
+            None if name == "@%" => Some(DEFAULT_PRINT_FORMAT),
+            None if is_resident_integer(name) => Some(0),
             _ => None,
         }
     }
 
     /// Set a real variable
     pub fn set_real_var(&mut self, name: String, value: f64) {
-        self.variables.insert(name, Variable::Real(value));
+        self.store(name, Variable::Real(value));
     }
 
     /// Get a real variable
@@ -165,13 +261,18 @@ impl VariableStore {
 
     /// Set a string variable
     pub fn set_string_var(&mut self, name: String, value: String) -> Result<()> {
-        if value.len() > 255 {
+        if self.string_limit && value.chars().count() > 255 {
             return Err(BBCBasicError::StringTooLong);
         }
-        self.variables.insert(name, Variable::String(value));
+        self.store(name, Variable::String(value));
         Ok(())
     }
 
+    /// Enable or disable the 255 character string limit
+    pub fn set_string_limit(&mut self, enabled: bool) {
+        self.string_limit = enabled;
+    }
+
     /// Get a string variable
     pub fn get_string_var(&self, name: &str) -> Option<&str> {
         match self.variables.get(name) {
@@ -200,10 +301,26 @@ impl VariableStore {
             VarType::String => Variable::new_string_array(dimensions),
         };
 
+        self.changes += 1;
         self.variables.insert(name, variable);
         Ok(())
     }
 
+    /// Store a whole variable or array, replacing any existing one
+    pub fn insert_variable(&mut self, name: String, variable: Variable) {
+        self.store(name, variable);
+    }
+
+    /// Remove a variable or array
+    pub fn remove_variable(&mut self, name: &str) {
+        if let Some(old) = self.variables.remove(name) {
+            self.changes += 1;
+            if !old.is_array() {
+                self.history.record(name.to_string(), Some(old), None);
+            }
+        }
+    }
+
     /// Get a variable by name (immutable)
     pub fn get_variable(&self, name: &str) -> Option<&Variable> {
         self.variables.get(name)
@@ -211,6 +328,7 @@ impl VariableStore {
 
     /// Get a mutable reference to a variable
     pub fn get_variable_mut(&mut self, name: &str) -> Option<&mut Variable> {
+        self.changes += 1;
         self.variables.get_mut(name)
     }
 
@@ -239,6 +357,11 @@ impl VariableStore {
         indices: &[usize],
         value: Variable,
     ) -> Result<()> {
+        let old = self.get_array_element(name, indices)?;
+        if old == value {
+            return Ok(());
+        }
+        let recorded = self.history.is_recording().then(|| value.clone());
         let variable = self
             .get_variable_mut(name)
             .ok_or(BBCBasicError::NoSuchVariable(name.to_string()))?;
@@ -256,20 +379,99 @@ impl VariableStore {
             _ => return Err(BBCBasicError::TypeMismatch),
         }
 
+        if let Some(new) = recorded {
+            let element = Target::Element {
+                name: name.to_string(),
+                indices: indices.to_vec(),
+            };
+            self.history
+                .record(element.to_string(), Some(old), Some(new));
+        }
         Ok(())
     }
 
+    /// Value of a variable or array element
+    pub fn get_target(&self, target: &Target) -> Result<Variable> {
+        match target {
+            Target::Element { name, indices } => self.get_array_element(name, indices),
+            Target::Variable(name) => match self.get_integer_var(name) {
+                Some(value) => Ok(Variable::Integer(value)),
+                None => self
+                    .get_variable(name)
+                    .cloned()
+                    .ok_or_else(|| BBCBasicError::NoSuchVariable(name.clone())),
+            },
+        }
+    }
+
+    /// Give a variable or array element a value of its type
+    pub fn set_target(&mut self, target: &Target, value: Variable) -> Result<()> {
+        match (target, value) {
+            (Target::Element { name, indices }, value) => {
+                self.set_array_element(name, indices, value)
+            }
+            (Target::Variable(name), Variable::String(text)) => {
+                self.set_string_var(name.clone(), text)
+            }
+            (Target::Variable(name), value) => {
+                self.insert_variable(name.clone(), value);
+                Ok(())
+            }
+        }
+    }
+
     /// Check if a variable exists
     pub fn has_variable(&self, name: &str) -> bool {
         self.variables.contains_key(name)
     }
 
-    /// Clear all variables
+    /// All variables and arrays, by name
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Variable)> {
+        self.variables.iter()
+    }
+
+    /// Bytes the variables take on the heap between LOMEM and HIMEM
+    ///
+    /// Each entry has a two byte link and its name as well as its value. The
+    /// resident integers @% and A% to Z% live in fixed workspace below PAGE,
+    /// so they take no heap space.
+    pub fn heap_size(&self) -> usize {
+        self.variables
+            .iter()
+            .filter(|(name, variable)| variable.is_array() || !is_resident_integer(name))
+            .map(|(name, variable)| 2 + name.len() + variable.heap_size())
+            .sum()
+    }
+
+    /// Clear all variables except the resident integers, which keep their
+    /// values through NEW, RUN, CHAIN and CLEAR, and start the history
+    /// afresh
     pub fn clear(&mut self) {
-        self.variables.clear();
+        self.changes += 1;
+        self.history.clear();
+        self.variables.retain(|name, _| is_resident_integer(name));
     }
 }
 
+/// Value of @% until a program sets it: numbers printed in general format
+/// to nine figures, in fields ten characters wide
+pub const DEFAULT_PRINT_FORMAT: i32 = 0x090A;
+
+/// How many resident integers there are: @% and A% to Z%
+pub const RESIDENT_INTEGERS: usize = 27;
+
+/// Name of a resident integer by its place in the workspace: 0 for @%, 1
+/// for A% and so on
+pub fn resident_integer_name(index: usize) -> String {
+    format!("{}%", char::from(b'@' + index as u8))
+}
+
+/// Whether a name is one of the resident integer variables @% and A% to Z%
+pub fn is_resident_integer(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    bytes.len() == 2 && bytes[1] == b'%' && (bytes[0] == b'@' || bytes[0].is_ascii_uppercase())
+}
+
 impl Default for VariableStore {
     fn default() -> Self {
         Self::new()
@@ -277,6 +479,7 @@ impl Default for VariableStore {
 }
 
 #[cfg(test)]
+#[allow(clippy::approx_constant)]
 mod tests {
     use super::*;
     use quickcheck::TestResult;
@@ -350,6 +553,23 @@ mod tests {
         assert_eq!(store.get_string_var("C$"), Some("hello"));
     }
 
+    #[test]
+    fn test_names_are_case_sensitive() {
+        let mut store = VariableStore::new();
+
+        store.set_real_var("score".to_string(), 1.0);
+        store.set_real_var("Score".to_string(), 2.0);
+        store.set_integer_var("a_very_long_variable_name_indeed%".to_string(), 3);
+
+        assert_eq!(store.get_real_var("score"), Some(1.0));
+        assert_eq!(store.get_real_var("Score"), Some(2.0));
+        assert_eq!(store.get_real_var("SCORE"), None);
+        assert_eq!(
+            store.get_integer_var("a_very_long_variable_name_indeed%"),
+            Some(3)
+        );
+    }
+
     #[test]
     fn test_string_too_long() {
         let mut store = VariableStore::new();
@@ -359,6 +579,45 @@ mod tests {
         assert!(matches!(result, Err(BBCBasicError::StringTooLong)));
     }
 
+    #[test]
+    fn test_resident_integers_survive_clear() {
+        let mut store = VariableStore::new();
+        assert_eq!(store.get_integer_var("Z%"), Some(0));
+        assert_eq!(store.get_integer_var("@%"), Some(DEFAULT_PRINT_FORMAT));
+        assert_eq!(store.get_integer_var("ZZ%"), None);
+        store.set_integer_var("A%".to_string(), 7);
+        store.set_integer_var("AB%".to_string(), 8);
+        store
+            .dim_array("B%(".to_string(), vec![3], VarType::Integer)
+            .unwrap();
+        store.clear();
+        assert_eq!(store.get_integer_var("A%"), Some(7));
+        assert_eq!(store.get_integer_var("AB%"), None);
+        assert!(!store.has_variable("B%("));
+        assert_eq!(resident_integer_name(0), "@%");
+        assert_eq!(resident_integer_name(26), "Z%");
+    }
+
+    #[test]
+    fn test_heap_size() {
+        let mut store = VariableStore::new();
+        store.set_integer_var("A%".to_string(), 1);
+        store.set_integer_var("@%".to_string(), 10);
+        assert_eq!(store.heap_size(), 0);
+
+        store.set_integer_var("COUNT%".to_string(), 1);
+        store.set_real_var("X".to_string(), 1.5);
+        store
+            .set_string_var("N$".to_string(), "ABC".to_string())
+            .unwrap();
+        assert_eq!(store.heap_size(), (2 + 6 + 4) + (2 + 1 + 5) + (2 + 2 + 7));
+
+        store
+            .dim_array("B%".to_string(), vec![11], VarType::Integer)
+            .unwrap();
+        assert_eq!(store.heap_size(), 31 + (2 + 2 + 3 + 44));
+    }
+
     // Property-Based Tests
 
     /// **Feature: bbc-basic-interpreter, Property 1: Variable Storage and Type Safety**
@@ -0,0 +1,790 @@
+//! Bytecode execution backend
+//!
+//! Compiles the stored program once into a flat list of instructions, one
+//! for each statement: line numbers are resolved to instruction indices, the
+//! ends of WHILE loops, block IFs and one-line IF parts are found ahead of
+//! time, and numeric expressions are lowered to stack bytecode. RUN then
+//! steps through the instructions without parsing or scanning lines again.
+//! Statements the VM does not lower are handed to the executor, so both
+//! backends share variables, I/O and error behaviour.
+
+use crate::error::{BBCBasicError, Result};
+use crate::executor::{
+    integer_binary_op, integer_unary_op, real_binary_op, real_to_integer, real_unary_op, Executor,
+    PSEUDO_VARIABLES,
+};
+use crate::interpreter::{error_message, no_such_line, Throttle};
+use crate::parser::{
+    parse_statements_with_dialect, run_order, BinaryOperator, Dialect, Expression, ExpressionType,
+    Statement, UnaryOperator,
+};
+use crate::program::{Position, ProgramStore};
+use crate::trace;
+use std::collections::HashMap;
+
+/// Bytecode for an expression evaluated as an integer
+#[derive(Debug, Clone, PartialEq)]
+pub enum IntOp {
+    /// Push a constant
+    Const(i32),
+    /// Push a variable
+    Var(String),
+    /// Push a value the executor evaluates (arrays, function calls, pseudo-variables)
+    Eval(Expression),
+    /// Pop two values and push the result
+    Binary(BinaryOperator),
+    /// Pop a value and push the result
+    Unary(UnaryOperator),
+}
+
+/// Bytecode for an expression evaluated as a real
+#[derive(Debug, Clone, PartialEq)]
+pub enum RealOp {
+    /// Push a constant
+    Const(f64),
+    /// Push a variable
+    Var(String),
+    /// Push a value the executor evaluates (arrays, function calls)
+    Eval(Expression),
+    /// Pop two values and push the result
+    Binary(BinaryOperator),
+    /// Pop a value and push the result
+    Unary(UnaryOperator),
+}
+
+/// Lower an expression evaluated as an integer to bytecode
+pub fn compile_integer(expr: &Expression) -> Vec<IntOp> {
+    let mut code = Vec::new();
+    emit_integer(expr, &mut code);
+    code
+}
+
+fn emit_integer(expr: &Expression, code: &mut Vec<IntOp>) {
+    match expr {
+        Expression::Integer(val) => code.push(IntOp::Const(*val)),
+        // Reals out of range are left to fail when they are reached
+        Expression::Real(val) => match real_to_integer(*val) {
+            Ok(val) => code.push(IntOp::Const(val)),
+            Err(_) => code.push(IntOp::Eval(expr.clone())),
+        },
+        Expression::Variable(name) if !PSEUDO_VARIABLES.contains(&name.as_str()) => {
+            code.push(IntOp::Var(name.clone()))
+        }
+        // Comparing strings is left to the executor
+        Expression::BinaryOp { left, right, .. }
+            if left.expression_type() == ExpressionType::String
+                || right.expression_type() == ExpressionType::String =>
+        {
+            code.push(IntOp::Eval(expr.clone()))
+        }
+        Expression::BinaryOp { left, op, right } => {
+            emit_integer(left, code);
+            emit_integer(right, code);
+            code.push(IntOp::Binary(op.clone()));
+        }
+        Expression::UnaryOp { op, operand } => {
+            emit_integer(operand, code);
+            code.push(IntOp::Unary(op.clone()));
+        }
+        _ => code.push(IntOp::Eval(expr.clone())),
+    }
+}
+
+/// Lower an expression evaluated as a real to bytecode
+pub fn compile_real(expr: &Expression) -> Vec<RealOp> {
+    let mut code = Vec::new();
+    emit_real(expr, &mut code);
+    code
+}
+
+fn emit_real(expr: &Expression, code: &mut Vec<RealOp>) {
+    match expr {
+        Expression::Integer(val) => code.push(RealOp::Const(*val as f64)),
+        Expression::Real(val) => code.push(RealOp::Const(*val)),
+        Expression::Variable(name) if !PSEUDO_VARIABLES.contains(&name.as_str()) => {
+            code.push(RealOp::Var(name.clone()))
+        }
+        Expression::BinaryOp { left, op, right } => {
+            emit_real(left, code);
+            emit_real(right, code);
+            code.push(RealOp::Binary(op.clone()));
+        }
+        Expression::UnaryOp { op, operand } => {
+            emit_real(operand, code);
+            code.push(RealOp::Unary(op.clone()));
+        }
+        _ => code.push(RealOp::Eval(expr.clone())),
+    }
+}
+
+/// Run integer bytecode
+fn eval_integer(code: &[IntOp], executor: &mut Executor, stack: &mut Vec<i32>) -> Result<i32> {
+    stack.clear();
+    for op in code {
+        let value = match op {
+            IntOp::Const(val) => *val,
+            IntOp::Var(name) => executor.integer_variable(name)?,
+            IntOp::Eval(expr) => executor.eval_integer(expr)?,
+            IntOp::Binary(op) => {
+                let right = stack.pop().expect("bytecode stack underflow");
+                let left = stack.pop().expect("bytecode stack underflow");
+                integer_binary_op(op, left, right)?
+            }
+            IntOp::Unary(op) => {
+                let val = stack.pop().expect("bytecode stack underflow");
+                integer_unary_op(op, val)
+            }
+        };
+        stack.push(value);
+    }
+    Ok(stack.pop().expect("bytecode stack underflow"))
+}
+
+/// Run real bytecode
+fn eval_real(code: &[RealOp], executor: &mut Executor, stack: &mut Vec<f64>) -> Result<f64> {
+    stack.clear();
+    for op in code {
+        let value = match op {
+            RealOp::Const(val) => *val,
+            RealOp::Var(name) => executor.real_variable(name)?,
+            RealOp::Eval(expr) => executor.eval_real(expr)?,
+            RealOp::Binary(op) => {
+                let right = stack.pop().expect("bytecode stack underflow");
+                let left = stack.pop().expect("bytecode stack underflow");
+                real_binary_op(op, left, right)?
+            }
+            RealOp::Unary(op) => {
+                let val = stack.pop().expect("bytecode stack underflow");
+                real_unary_op(op, val)
+            }
+        };
+        stack.push(executor.round_real(value)?);
+    }
+    Ok(stack.pop().expect("bytecode stack underflow"))
+}
+
+/// One compiled statement
+#[derive(Debug)]
+struct Instruction {
+    position: Position,
+    statement: Statement,
+    op: Op,
+}
+
+/// What the VM does for an instruction
+#[derive(Debug)]
+enum Op {
+    /// Hand the statement to the executor and go on to the next statement
+    Execute,
+    /// DATA, collected before the run and passed over
+    Data,
+    /// Integer variable assignment
+    AssignInteger(String, Vec<IntOp>),
+    /// Real variable assignment
+    AssignReal(String, Vec<RealOp>),
+    /// END or STOP
+    End,
+    Goto(u16),
+    Gosub(u16),
+    /// ON GOTO, ON GOSUB or ON PROC, following the statement's choice
+    /// (see [`Statement::on_choice`])
+    On(Vec<IntOp>),
+    Return,
+    /// RETURN with a value, ending a multi-line function's body
+    Result,
+    ProcCall,
+    EndProc,
+    /// FOR (set up by the executor)
+    For,
+    /// NEXT (stepped by the executor)
+    Next,
+    Repeat,
+    Until(Vec<IntOp>),
+    /// WHILE, with the instruction after its ENDWHILE
+    While(Vec<IntOp>, Option<usize>),
+    EndWhile,
+    /// Block IF, with the instruction after its ELSE or ENDIF
+    IfBlock(Vec<IntOp>, Option<usize>),
+    /// ELSE, with the instruction to go on to: the one after its ENDIF, or
+    /// for a one-line IF the start of the next line
+    Else(Option<usize>),
+    /// One-line IF, with the instruction to go on to if its condition is
+    /// false: the one after its ELSE, or the start of the next line
+    If(Vec<IntOp>, usize),
+    /// DEF PROC or DEF FN reached in the program's flow, with the start of
+    /// the next line: the rest of the line only runs when it is called
+    Def(usize),
+    /// LIBRARY or INSTALL (loaded by the interpreter)
+    Library,
+    /// CHAIN (loaded and run by the interpreter)
+    Chain,
+}
+
+/// A program compiled for the VM
+#[derive(Debug)]
+pub struct CompiledProgram {
+    instructions: Vec<Instruction>,
+    index: HashMap<u16, usize>,
+    /// First instruction of the loaded libraries, which follow the program
+    library_start: usize,
+}
+
+/// A LIBRARY or INSTALL statement reached by the VM
+///
+/// The VM cannot load a library itself, so it stops and hands the statement
+/// back; the interpreter loads the library, compiles the program again and
+/// carries on from `resume`. Libraries come after the program, so program
+/// instructions keep their indices.
+#[derive(Debug)]
+pub struct LibraryRequest {
+    /// Line of the statement
+    pub line_number: u16,
+    /// File name expression
+    pub filename: Expression,
+    /// INSTALL rather than LIBRARY
+    pub permanent: bool,
+    /// Instruction to continue from once the library is loaded
+    pub resume: usize,
+}
+
+/// A statement the VM hands back to the interpreter to carry out
+#[derive(Debug)]
+pub enum Request {
+    /// Load a library and carry on
+    Library(LibraryRequest),
+    /// Replace the program with the one named, and run it from the start
+    Chain {
+        /// Line of the statement
+        line_number: u16,
+        /// Program name expression
+        filename: Expression,
+    },
+    /// End a multi-line function's body with its result (see
+    /// [`CompiledProgram::run_function`])
+    Result {
+        /// Line of the statement
+        line_number: u16,
+        /// Result expression, evaluated by the caller
+        value: Expression,
+    },
+}
+
+/// Compile the stored program, and any loaded libraries, for the VM
+pub fn compile(
+    program: &ProgramStore,
+    dialect: Dialect,
+) -> std::result::Result<CompiledProgram, String> {
+    let mut instructions = Vec::new();
+    let mut index = HashMap::new();
+    let mut library_start = None;
+    let lines = program.list().into_iter().chain(program.library_lines());
+    for (n, (line_number, line)) in lines.enumerate() {
+        let statements = parse_statements_with_dialect(line, dialect)
+            .map_err(|e| error_message(&e, Some(line_number)))?;
+        if n == program.len() {
+            library_start = Some(instructions.len());
+        }
+        index.insert(line_number, instructions.len());
+        for (i, statement) in run_order(statements).into_iter().enumerate() {
+            instructions.push(Instruction {
+                position: Position::new(line_number, i),
+                op: Op::Execute,
+                statement,
+            });
+        }
+    }
+    let library_start = library_start.unwrap_or(instructions.len());
+
+    for i in 0..instructions.len() {
+        let position = instructions[i].position;
+        // Where the next line starts
+        let line_end = (i + 1..instructions.len())
+            .find(|&j| instructions[j].position.statement == 0)
+            .unwrap_or(instructions.len());
+        let op = match &instructions[i].statement {
+            Statement::Assignment { target, expression } if target.ends_with('%') => {
+                Op::AssignInteger(target.clone(), compile_integer(expression))
+            }
+            Statement::Assignment { target, expression } if !target.ends_with('$') => {
+                Op::AssignReal(target.clone(), compile_real(expression))
+            }
+            Statement::End | Statement::Stop => Op::End,
+            Statement::Goto { line_number } => Op::Goto(*line_number),
+            Statement::Gosub { line_number } => Op::Gosub(*line_number),
+            Statement::OnGoto { expression, .. }
+            | Statement::OnGosub { expression, .. }
+            | Statement::OnProc { expression, .. } => Op::On(compile_integer(expression)),
+            Statement::Return { value: Some(_) } => Op::Result,
+            Statement::Return { value: None } => Op::Return,
+            Statement::ProcCall { .. } => Op::ProcCall,
+            Statement::EndProc => Op::EndProc,
+            Statement::For { .. } => Op::For,
+            Statement::Next { .. } => Op::Next,
+            Statement::Repeat => Op::Repeat,
+            Statement::Until { condition } => Op::Until(compile_integer(condition)),
+            Statement::While { condition } => {
+                Op::While(compile_integer(condition), find_endwhile(&instructions, i))
+            }
+            Statement::EndWhile => Op::EndWhile,
+            Statement::IfBlock { condition } => Op::IfBlock(
+                compile_integer(condition),
+                find_if_end(&instructions, i, true),
+            ),
+            Statement::If { condition, .. } => {
+                let statement = &instructions[i].statement;
+                let otherwise = statement
+                    .else_index(position.statement)
+                    .map_or(line_end, |index| i - position.statement + index);
+                Op::If(compile_integer(condition), otherwise)
+            }
+            // Reaching a one-line IF's ELSE ends its line
+            Statement::Else if position.statement > 0 => Op::Else(Some(line_end)),
+            Statement::Else => Op::Else(find_if_end(&instructions, i, false)),
+            Statement::DefProc { .. } | Statement::DefFn { .. } if position.statement == 0 => {
+                Op::Def(line_end)
+            }
+            Statement::Library { .. } => Op::Library,
+            Statement::Chain { .. } => Op::Chain,
+            Statement::Data { .. } => Op::Data,
+            _ => Op::Execute,
+        };
+        instructions[i].op = op;
+    }
+
+    Ok(CompiledProgram {
+        instructions,
+        index,
+        library_start,
+    })
+}
+
+/// Instruction after the ENDWHILE matching the WHILE at `start`
+fn find_endwhile(instructions: &[Instruction], start: usize) -> Option<usize> {
+    let mut depth = 1;
+    for (i, instruction) in instructions.iter().enumerate().skip(start + 1) {
+        match instruction.statement {
+            Statement::While { .. } => depth += 1,
+            Statement::EndWhile => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Instruction after the ELSE (if `stop_at_else`) or ENDIF ending the block
+/// IF branch that starts at `start`; nested blocks are skipped
+fn find_if_end(instructions: &[Instruction], start: usize, stop_at_else: bool) -> Option<usize> {
+    let mut depth = 0;
+    for (i, instruction) in instructions.iter().enumerate().skip(start + 1) {
+        match instruction.statement {
+            Statement::IfBlock { .. } => depth += 1,
+            // The ELSE of a block IF starts its line
+            Statement::Else if instruction.position.statement > 0 => {}
+            Statement::Else if depth == 0 && stop_at_else => return Some(i + 1),
+            Statement::EndIf if depth == 0 => return Some(i + 1),
+            Statement::EndIf => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+impl CompiledProgram {
+    /// Number of compiled statements
+    pub fn len(&self) -> usize {
+        self.instructions.len()
+    }
+
+    /// Whether the program has no lines
+    pub fn is_empty(&self) -> bool {
+        self.instructions.is_empty()
+    }
+
+    /// Instruction index of a line
+    pub(crate) fn find(&self, line_number: u16) -> Option<usize> {
+        self.index.get(&line_number).copied()
+    }
+
+    /// Instruction index of a statement
+    fn find_position(&self, position: Position) -> Option<usize> {
+        self.find(position.line_number)
+            .map(|start| start + position.statement)
+    }
+
+    /// Run the program from instruction `start` on an executor whose DATA
+    /// and procedures have been collected, at up to `speed` statements per
+    /// second (0 = unthrottled)
+    ///
+    /// Returns a request if the program reaches LIBRARY, INSTALL, CHAIN or
+    /// RETURN with a value, and None when it ends.
+    pub fn run(
+        &self,
+        executor: &mut Executor,
+        speed: u32,
+        start: usize,
+    ) -> std::result::Result<Option<Request>, String> {
+        let mut throttle = Throttle::new(speed);
+        let mut int_stack = Vec::new();
+        let mut real_stack = Vec::new();
+        let mut pc = start;
+
+        while let Some(instruction) = self.instructions.get(pc) {
+            let position = instruction.position;
+            let line_number = position.line_number;
+            let mut taken = false;
+
+            // Execute the statement (pausing first if a speed limit is set)
+            executor.set_line_number(Some(line_number));
+            throttle.tick();
+            // The hooks may skip it, put another statement in its place (run
+            // by the executor, so any jump in it is not followed) or refuse it
+            let hooked = executor.hook_before(line_number, &instruction.statement);
+            let execution_result = executor.watch().and_then(|()| match &hooked {
+                Err(e) => Err(e.clone()),
+                Ok(Some(replacement)) => executor.execute_statement(replacement),
+                Ok(None) => match &instruction.op {
+                    Op::Execute | Op::For | Op::Next => {
+                        executor.execute_statement(&instruction.statement)
+                    }
+                    Op::AssignInteger(target, code) => executor
+                        .check_constant(target)
+                        .and_then(|_| eval_integer(code, executor, &mut int_stack))
+                        .map(|value| executor.set_variable_int(target, value)),
+                    Op::AssignReal(target, code) => executor
+                        .check_constant(target)
+                        .and_then(|_| eval_real(code, executor, &mut real_stack))
+                        .map(|value| executor.set_variable_real(target, value)),
+                    Op::If(condition, _) => eval_integer(condition, executor, &mut int_stack)
+                        .map(|value| taken = value != 0),
+                    // Control flow is handled below; the executor does nothing for these
+                    _ => Ok(()),
+                },
+            });
+            let replacement = hooked.ok().flatten();
+            let statement = replacement.as_ref().unwrap_or(&instruction.statement);
+            executor.hook_after(line_number, statement, &execution_result);
+
+            // Handle errors with ON ERROR handler if set
+            if let Err(e) = execution_result {
+                if let Some(handler_line) = executor.error_handler_for(&e) {
+                    executor.set_last_error(e.number(), line_number, e.report());
+                    pc = self.find(handler_line).ok_or_else(|| {
+                        format!(
+                            "Error handler line {} not found (from error at line {})",
+                            handler_line, line_number
+                        )
+                    })?;
+                    continue;
+                } else {
+                    return Err(error_message(&e, Some(line_number)));
+                }
+            }
+
+            let next = match &instruction.op {
+                // A statement put in place of a one-line IF takes its THEN
+                // and ELSE parts with it
+                Op::If(..) if replacement.is_some() => (pc + 1..self.instructions.len())
+                    .find(|&next| self.instructions[next].position.statement == 0)
+                    .unwrap_or(self.instructions.len()),
+                _ if replacement.is_some() => pc + 1,
+                Op::Execute | Op::Data | Op::AssignInteger(..) | Op::AssignReal(..) => pc + 1,
+                Op::End => break,
+                Op::Goto(target) => self
+                    .find(*target)
+                    .ok_or_else(|| no_such_line(*target, line_number))?,
+                Op::Gosub(target) => {
+                    executor.push_gosub_return(position);
+                    self.find(*target)
+                        .ok_or_else(|| no_such_line(*target, line_number))?
+                }
+                Op::On(selector) => {
+                    let index = eval_integer(selector, executor, &mut int_stack)
+                        .map_err(|e| error_message(&e, Some(line_number)))?;
+                    match instruction.statement.on_choice(index) {
+                        Statement::Goto {
+                            line_number: target,
+                        } => self
+                            .find(target)
+                            .ok_or_else(|| no_such_line(target, line_number))?,
+                        Statement::Gosub {
+                            line_number: target,
+                        } => {
+                            executor.push_gosub_return(position);
+                            self.find(target)
+                                .ok_or_else(|| no_such_line(target, line_number))?
+                        }
+                        Statement::ProcCall { name, args } => {
+                            self.call_procedure(executor, &name, &args, position)?
+                        }
+                        // Out of range: fall through to the next statement
+                        _ => pc + 1,
+                    }
+                }
+                Op::Return => {
+                    let gosub = executor
+                        .pop_gosub_return()
+                        .map_err(|_| "RETURN without GOSUB".to_string())?;
+                    self.find_position(gosub)
+                        .ok_or_else(|| format!("Return line {} not found", gosub.line_number))?
+                        + 1
+                }
+                Op::ProcCall => {
+                    let Statement::ProcCall { name, args } = &instruction.statement else {
+                        unreachable!("PROC instruction without a PROC statement");
+                    };
+                    self.call_procedure(executor, name, args, position)?
+                }
+                Op::EndProc => {
+                    executor
+                        .exit_local_scope()
+                        .map_err(|e| error_message(&e, Some(line_number)))?;
+                    if executor.tracing() {
+                        trace::leave("PROC", executor.call_depth());
+                    }
+                    let call = executor
+                        .pop_gosub_return()
+                        .map_err(|_| "ENDPROC without PROC call".to_string())?;
+                    self.find_position(call)
+                        .ok_or_else(|| format!("Return line {} not found", call.line_number))?
+                        + 1
+                }
+                Op::For => {
+                    executor.set_for_loop_position(position);
+                    pc + 1
+                }
+                Op::Next => match executor.should_loop_back() {
+                    Some(start) => {
+                        self.find_position(start).ok_or_else(|| {
+                            format!("FOR loop line {} not found", start.line_number)
+                        })? + 1
+                    }
+                    None => pc + 1,
+                },
+                Op::Repeat => {
+                    executor.push_repeat(position);
+                    pc + 1
+                }
+                Op::Until(condition) => {
+                    let result = eval_integer(condition, executor, &mut int_stack)
+                        .map_err(|e| error_message(&e, Some(line_number)))?;
+                    match executor.check_until_value(result) {
+                        Some(repeat) => {
+                            self.find_position(repeat).ok_or_else(|| {
+                                format!("REPEAT line {} not found", repeat.line_number)
+                            })? + 1
+                        }
+                        None => pc + 1,
+                    }
+                }
+                Op::While(condition, exit) => {
+                    let result = eval_integer(condition, executor, &mut int_stack)
+                        .map_err(|e| error_message(&e, Some(line_number)))?;
+                    match executor.push_while_value(position, result) {
+                        Some(_) => pc + 1,
+                        None => exit.ok_or("WHILE without matching ENDWHILE")?,
+                    }
+                }
+                Op::EndWhile => {
+                    let start = executor
+                        .check_endwhile_get_while_position()
+                        .ok_or("ENDWHILE without matching WHILE")?;
+                    let while_line = start.line_number;
+                    let while_pc = self
+                        .find_position(start)
+                        .ok_or_else(|| format!("WHILE line {} not found", while_line))?;
+                    let Op::While(condition, _) = &self.instructions[while_pc].op else {
+                        return Err(format!(
+                            "Could not parse WHILE statement at line {}",
+                            while_line
+                        ));
+                    };
+                    let result = eval_integer(condition, executor, &mut int_stack)
+                        .map_err(|e| error_message(&e, Some(line_number)))?;
+                    match executor.check_endwhile_value(result) {
+                        Some(_) => while_pc + 1,
+                        None => pc + 1,
+                    }
+                }
+                Op::IfBlock(condition, skip) => {
+                    match eval_integer(condition, executor, &mut int_stack) {
+                        Ok(0) => skip.ok_or("Missing ENDIF")?,
+                        Ok(_) => pc + 1,
+                        Err(e) => return Err(error_message(&e, Some(line_number))),
+                    }
+                }
+                // One-line IF: its THEN part follows it
+                Op::If(_, otherwise) => {
+                    if taken {
+                        pc + 1
+                    } else {
+                        *otherwise
+                    }
+                }
+                // Reaching ELSE means the THEN branch ran, so skip the ELSE branch
+                Op::Else(skip) => skip.ok_or("Missing ENDIF")?,
+                Op::Def(next_line) => *next_line,
+                Op::Library => {
+                    let Statement::Library {
+                        filename,
+                        permanent,
+                    } = &instruction.statement
+                    else {
+                        unreachable!("LIBRARY instruction without a LIBRARY statement");
+                    };
+                    return Ok(Some(Request::Library(LibraryRequest {
+                        line_number,
+                        filename: filename.clone(),
+                        permanent: *permanent,
+                        resume: pc + 1,
+                    })));
+                }
+                Op::Chain => {
+                    let Statement::Chain { filename } = &instruction.statement else {
+                        unreachable!("CHAIN instruction without a CHAIN statement");
+                    };
+                    return Ok(Some(Request::Chain {
+                        line_number,
+                        filename: filename.clone(),
+                    }));
+                }
+                Op::Result => {
+                    let Statement::Return { value: Some(value) } = &instruction.statement else {
+                        unreachable!("RETURN value instruction without a value");
+                    };
+                    return Ok(Some(Request::Result {
+                        line_number,
+                        value: value.clone(),
+                    }));
+                }
+            };
+
+            // Running off the end of the program stops it rather than
+            // going on into the libraries
+            if next == self.library_start && pc < next {
+                break;
+            }
+            pc = next;
+        }
+
+        Ok(None)
+    }
+
+    /// Run a multi-line function's body from instruction `start`, whose
+    /// arguments are bound, returning the value its RETURN gives
+    ///
+    /// The body runs unthrottled to the end, whatever the caller is doing;
+    /// an error that stops it is reported with the line it was on.
+    pub(crate) fn run_function(&self, executor: &mut Executor, start: usize) -> Result<Expression> {
+        match self.run(executor, 0, start) {
+            Ok(Some(Request::Result { value, .. })) => Ok(value),
+            Ok(Some(Request::Library(LibraryRequest { line_number, .. })))
+            | Ok(Some(Request::Chain { line_number, .. })) => {
+                Err(BBCBasicError::InFunction(format!(
+                    "Cannot load a library or program in a function at line {}",
+                    line_number
+                )))
+            }
+            Ok(None) => Err(BBCBasicError::InFunction(
+                "Function ended without RETURN".to_string(),
+            )),
+            Err(message) => Err(BBCBasicError::InFunction(message)),
+        }
+    }
+
+    /// Bind a PROC call's arguments and return the instruction to jump to
+    fn call_procedure(
+        &self,
+        executor: &mut Executor,
+        name: &str,
+        args: &[Expression],
+        position: Position,
+    ) -> std::result::Result<usize, String> {
+        let line_number = position.line_number;
+        let proc = executor
+            .get_procedure(name)
+            .ok_or_else(|| format!("Procedure {} not defined", name))?;
+        let proc_line = proc.line_number;
+        let params = proc.params.clone();
+
+        // Enter local scope and bind arguments to parameters
+        executor
+            .enter_procedure(&params, args)
+            .map_err(|e| error_message(&e, Some(line_number)))?;
+        if executor.tracing() {
+            trace::enter("PROC", name, executor.call_depth());
+        }
+        executor.push_gosub_return(position);
+
+        // Continue after the DEF PROC
+        let def = self
+            .find(proc_line)
+            .ok_or_else(|| format!("Procedure {} line {} not found", name, proc_line))?;
+        Ok(def + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::tokenize;
+
+    fn parse(source: &str) -> Expression {
+        match crate::parser::parse_statement(&tokenize(&format!("X={}", source)).unwrap()) {
+            Ok(Statement::Assignment { expression, .. }) => expression,
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_compile_integer() {
+        let code = compile_integer(&parse("A% * 2 + TIME"));
+        assert_eq!(
+            code,
+            vec![
+                IntOp::Var("A%".to_string()),
+                IntOp::Const(2),
+                IntOp::Binary(BinaryOperator::Multiply),
+                IntOp::Eval(Expression::Variable("TIME".to_string())),
+                IntOp::Binary(BinaryOperator::Add),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bytecode_matches_tree_evaluation() {
+        let mut executor = Executor::new();
+        executor.set_variable_int("A%", 7);
+        executor.set_variable_real("B", 2.5);
+        let mut int_stack = Vec::new();
+        let mut real_stack = Vec::new();
+        for source in [
+            "A% * 3 - 1",
+            "A% DIV 2 + A% MOD 3",
+            "-(A% + B)",
+            "ABS(-A%) = 7",
+            "A% AND 3 OR 8",
+        ] {
+            let expr = parse(source);
+            assert_eq!(
+                eval_integer(&compile_integer(&expr), &mut executor, &mut int_stack).unwrap(),
+                executor.eval_integer(&expr).unwrap(),
+                "{}",
+                source
+            );
+        }
+        for source in ["B * 2 + A%", "A% / 2", "2 ^ B", "SQR(16) + B"] {
+            let expr = parse(source);
+            assert_eq!(
+                eval_real(&compile_real(&expr), &mut executor, &mut real_stack).unwrap(),
+                executor.eval_real(&expr).unwrap(),
+                "{}",
+                source
+            );
+        }
+    }
+}
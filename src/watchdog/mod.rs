@@ -0,0 +1,194 @@
+//! Watchdog for programs stuck in a loop
+//!
+//! A program going round the same few lines without printing, reading the
+//! keyboard, clock or files, or changing a variable is never going to stop.
+//! The watchdog notices when that has gone on for the configured interval
+//! (the `watchdog` option, in seconds) and asks a [`StuckHandler`] what to
+//! do: break out with Escape, trace the lines as they run, or carry on. The
+//! command line asks at the terminal; embedders plug in their own handler.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Most lines a loop can take in and still count as stuck
+const MAX_LINES: usize = 16;
+
+/// Statements run between looks at the clock
+const CHECK_EVERY: u32 = 1024;
+
+/// What to do about a stuck program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchdogAction {
+    /// Stop with Escape, which ON ERROR can trap
+    Break,
+    /// Print each line number in square brackets as it runs, and carry on
+    Trace,
+    /// Carry on, asking again after another interval
+    Continue,
+}
+
+/// A program that has got nowhere for a while
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stuck {
+    /// The lines it has been going round, in order
+    pub lines: Vec<u16>,
+    /// How long it has been going round them
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for Stuck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines: Vec<String> = self.lines.iter().map(u16::to_string).collect();
+        write!(
+            f,
+            "No progress for {} seconds in line{} {}",
+            self.elapsed.as_secs(),
+            if lines.len() == 1 { "" } else { "s" },
+            lines.join(", ")
+        )
+    }
+}
+
+/// Something that decides what to do about a stuck program
+pub trait StuckHandler: Send {
+    /// Choose what to do about a program that has got nowhere
+    fn stuck(&mut self, stuck: &Stuck) -> WatchdogAction;
+}
+
+impl<F: FnMut(&Stuck) -> WatchdogAction + Send> StuckHandler for F {
+    fn stuck(&mut self, stuck: &Stuck) -> WatchdogAction {
+        self(stuck)
+    }
+}
+
+/// Watches the lines a program runs for it getting nowhere
+pub struct Watchdog {
+    interval: Duration,
+    handler: Box<dyn StuckHandler>,
+    /// When the lines being watched started going round without progress
+    since: Instant,
+    /// Progress made up to then, from [`crate::executor::Executor::activity`]
+    activity: u64,
+    lines: BTreeSet<u16>,
+    statements: u32,
+}
+
+impl Watchdog {
+    /// Ask `handler` once a program has got nowhere for `interval` (zero
+    /// never asks)
+    pub fn new(interval: Duration, handler: Box<dyn StuckHandler>) -> Self {
+        Self {
+            interval,
+            handler,
+            since: Instant::now(),
+            activity: 0,
+            lines: BTreeSet::new(),
+            statements: 0,
+        }
+    }
+
+    /// Change how long a program may get nowhere before the handler is
+    /// asked (zero never asks)
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// Note a line about to run, given the progress made so far, and say
+    /// what to do about it
+    pub fn check(&mut self, line_number: u16, activity: u64) -> WatchdogAction {
+        if self.interval.is_zero() {
+            return WatchdogAction::Continue;
+        }
+        if activity != self.activity || self.lines.len() > MAX_LINES {
+            self.restart(activity);
+        }
+        self.lines.insert(line_number);
+        self.statements = self.statements.wrapping_add(1);
+        if !self.statements.is_multiple_of(CHECK_EVERY) || self.since.elapsed() < self.interval {
+            return WatchdogAction::Continue;
+        }
+        let stuck = Stuck {
+            lines: self.lines.iter().copied().collect(),
+            elapsed: self.since.elapsed(),
+        };
+        let action = self.handler.stuck(&stuck);
+        self.restart(activity);
+        action
+    }
+
+    /// Start watching afresh
+    fn restart(&mut self, activity: u64) {
+        self.since = Instant::now();
+        self.activity = activity;
+        self.lines.clear();
+    }
+}
+
+impl fmt::Debug for Watchdog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Watchdog")
+            .field("interval", &self.interval)
+            .field("lines", &self.lines)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    const INTERVAL: Duration = Duration::from_millis(10);
+
+    /// A watchdog that records what it reports and answers Break
+    fn recording() -> (Watchdog, Arc<Mutex<Vec<Stuck>>>) {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let seen = reports.clone();
+        let handler = move |stuck: &Stuck| {
+            seen.lock().unwrap().push(stuck.clone());
+            WatchdogAction::Break
+        };
+        (Watchdog::new(INTERVAL, Box::new(handler)), reports)
+    }
+
+    #[test]
+    fn test_stuck_loop() {
+        let (mut watchdog, reports) = recording();
+        watchdog.check(10, 0);
+        thread::sleep(INTERVAL * 2);
+        let broke = (0..CHECK_EVERY)
+            .map(|i| watchdog.check([20, 10][i as usize % 2], 0))
+            .position(|action| action == WatchdogAction::Break);
+        assert!(broke.is_some());
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports[0].lines, [10, 20]);
+        assert!(reports[0]
+            .to_string()
+            .starts_with("No progress for 0 seconds in lines 10, 20"));
+    }
+
+    #[test]
+    fn test_progress_is_not_stuck() {
+        let (mut watchdog, reports) = recording();
+        for i in 0..CHECK_EVERY * 2 {
+            if i % CHECK_EVERY == 1000 {
+                thread::sleep(INTERVAL * 2);
+            }
+            // A variable changing every time round
+            assert_eq!(watchdog.check(10, u64::from(i)), WatchdogAction::Continue);
+        }
+        for i in 0..CHECK_EVERY * 2 {
+            if i % CHECK_EVERY == 1000 {
+                thread::sleep(INTERVAL * 2);
+            }
+            // Too many lines to be a small loop
+            assert_eq!(
+                watchdog.check((i % 100) as u16, 0),
+                WatchdogAction::Continue
+            );
+        }
+        assert!(reports.lock().unwrap().is_empty());
+    }
+}
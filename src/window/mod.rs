@@ -0,0 +1,123 @@
+//! The prompt's graphics window (*CLOSEWIN)
+//!
+//! The terminal has no pixels, so the window is the graphics canvas drawn
+//! in block characters, 80 columns by 32 rows. A MOVE, DRAW, PLOT or other
+//! drawing statement typed at the prompt opens it, and from then on it is
+//! drawn again whenever the picture has changed, after each command and
+//! each RUN, until *CLOSEWIN. The picture itself outlasts the window: only
+//! CLG and MODE clear it, so MOVE and DRAW at the prompt plot on top of
+//! what the last program drew.
+//!
+//! With the `font` option set to `system`, text drawn at the graphics cursor
+//! (VDU 5) is shown in the terminal's own font rather than as blocks.
+
+use crate::config::Font;
+use crate::graphics::GraphicsSystem;
+use crate::parser::{parse_statement, Statement};
+use crate::tokenizer::tokenize;
+
+/// Canvas pixels to a character, across and down
+const SCALE: (usize, usize) = (16, 32);
+
+/// Whether the window is open, and what it last showed
+#[derive(Debug, Clone, Default)]
+pub struct GraphicsWindow {
+    open: bool,
+    /// Checksum of the picture last drawn, and the font it was drawn in
+    shown: Option<(u64, Font)>,
+}
+
+impl GraphicsWindow {
+    /// Open the window (or keep it open), drawing it at the next update
+    pub fn open(&mut self) {
+        self.open = true;
+        self.shown = None;
+    }
+
+    /// Close the window until a drawing statement opens it again
+    pub fn close(&mut self) {
+        self.open = false;
+    }
+
+    /// Whether the window is open
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// The window to draw, if it is open and the picture or the font has
+    /// changed since it was last drawn
+    pub fn update(&mut self, graphics: &GraphicsSystem, font: Font) -> Option<String> {
+        let shown = (graphics.checksum(), font);
+        if !self.open || self.shown == Some(shown) {
+            return None;
+        }
+        self.shown = Some(shown);
+        Some(match font {
+            Font::Beeb => graphics.render_scaled(SCALE.0, SCALE.1),
+            Font::System => graphics.render_scaled_with_text(SCALE.0, SCALE.1),
+        })
+    }
+}
+
+/// Whether a line typed at the prompt draws, and so opens the window
+pub fn draws(line: &str) -> bool {
+    let Ok(line) = tokenize(line) else {
+        return false;
+    };
+    line.line_number.is_none()
+        && matches!(
+            parse_statement(&line),
+            Ok(Statement::Plot { .. }
+                | Statement::Move { .. }
+                | Statement::Draw { .. }
+                | Statement::Circle { .. }
+                | Statement::Ellipse { .. }
+                | Statement::Rectangle { .. }
+                | Statement::Fill { .. })
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::Canvas;
+
+    #[test]
+    fn test_window() {
+        assert!(draws("MOVE 0, 0"));
+        assert!(draws("PLOT 69, 10, 10"));
+        assert!(!draws("10 DRAW 100, 100"));
+        assert!(!draws("PRINT \"DRAW\""));
+
+        let mut graphics = GraphicsSystem::new();
+        let mut window = GraphicsWindow::default();
+        assert_eq!(window.update(&graphics, Font::Beeb), None);
+
+        window.open();
+        let blank = window.update(&graphics, Font::Beeb).unwrap();
+        assert_eq!(blank.lines().count(), 34);
+        assert!(blank.lines().all(|line| line.chars().count() == 82));
+        // Nothing has changed, so nothing is drawn
+        assert_eq!(window.update(&graphics, Font::Beeb), None);
+
+        graphics.move_to(0, 0);
+        graphics.draw_line_to(1279, 0);
+        let line = window.update(&graphics, Font::Beeb).unwrap();
+        assert_ne!(line, blank);
+        assert!(line.lines().nth(32).unwrap().contains('░'));
+
+        // Text drawn at the graphics cursor shows as itself in the system font
+        graphics.move_to(0, 1023);
+        graphics.draw_char(b'H', 32, 32);
+        let blocks = window.update(&graphics, Font::Beeb).unwrap();
+        assert!(!blocks.lines().nth(1).unwrap().contains('H'));
+        let text = window.update(&graphics, Font::System).unwrap();
+        assert!(text.lines().nth(1).unwrap().starts_with("|H "), "{}", text);
+        assert_eq!(window.update(&graphics, Font::System), None);
+
+        window.close();
+        graphics.draw_line_to(1279, 1023);
+        assert_eq!(window.update(&graphics, Font::Beeb), None);
+        assert!(!window.is_open());
+    }
+}
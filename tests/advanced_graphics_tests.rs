@@ -1,4 +1,7 @@
+#![cfg(feature = "graphics")]
+
 use bbc_basic_interpreter::executor::Executor;
+use bbc_basic_interpreter::graphics::Canvas;
 use bbc_basic_interpreter::parser::parse_statement;
 use bbc_basic_interpreter::tokenizer::tokenize;
 
@@ -25,11 +28,11 @@ fn test_ellipse_command() {
 fn test_rectangle_command() {
     let mut executor = Executor::new();
 
-    // Draw a filled rectangle
+    // Draw a rectangle outline
     execute_line(&mut executor, "10 RECTANGLE 300, 300, 200, 150");
 
     let output = executor.get_graphics_output();
-    // Should have drawn a filled rectangle
+    // Should have drawn the rectangle
     assert!(output.contains('█') || output.contains('▓') || output.contains('▒') || output.contains('░'));
 }
 
@@ -186,3 +189,53 @@ fn test_combined_shapes() {
     // Complex scene should render
     assert!(output.contains('█') || output.contains('▓') || output.contains('▒') || output.contains('░'));
 }
+
+#[test]
+fn test_relative_move_and_draw() {
+    let mut executor = Executor::new();
+
+    execute_line(&mut executor, "10 MOVE 100, 100");
+    execute_line(&mut executor, "20 MOVE BY 50, 20");
+    execute_line(&mut executor, "30 DRAW BY -30, 0");
+
+    let graphics = executor.graphics();
+    assert_eq!(graphics.get_position(), (120, 120));
+    assert_eq!(graphics.get_pixel(135, 120), Some(true));
+    assert_eq!(graphics.get_pixel(135, 100), Some(false));
+}
+
+#[test]
+fn test_filled_shapes() {
+    let mut executor = Executor::new();
+
+    execute_line(&mut executor, "10 CIRCLE FILL 300, 300, 50");
+    execute_line(&mut executor, "20 ELLIPSE FILL 600, 300, 80, 40");
+    execute_line(&mut executor, "30 RECTANGLE 100, 600, 100, 100");
+    execute_line(&mut executor, "40 RECTANGLE FILL 400, 600, 100, 100");
+
+    let graphics = executor.graphics();
+    assert_eq!(graphics.get_pixel(300, 300), Some(true));
+    assert_eq!(graphics.get_pixel(600, 300), Some(true));
+    // Plain RECTANGLE is an outline; RECTANGLE FILL is solid
+    assert_eq!(graphics.get_pixel(100, 650), Some(true));
+    assert_eq!(graphics.get_pixel(150, 650), Some(false));
+    assert_eq!(graphics.get_pixel(450, 650), Some(true));
+}
+
+#[test]
+fn test_rectangle_copy_and_move() {
+    let mut executor = Executor::new();
+
+    execute_line(&mut executor, "10 CIRCLE FILL 100, 100, 20");
+    execute_line(&mut executor, "20 RECTANGLE 80, 80, 40, 40 TO 500, 500");
+    execute_line(
+        &mut executor,
+        "30 RECTANGLE FILL 80, 80, 40, 40 TO 800, 100",
+    );
+
+    let graphics = executor.graphics();
+    assert_eq!(graphics.get_pixel(520, 520), Some(true));
+    assert_eq!(graphics.get_pixel(820, 120), Some(true));
+    // Moving the block cleared where it came from
+    assert_eq!(graphics.get_pixel(100, 100), Some(false));
+}
@@ -0,0 +1,63 @@
+//! Checks that the benchmark programs in benches/programs run, and give the
+//! same output on both execution backends
+
+use bbc_basic_interpreter::config::{Backend, Config};
+use bbc_basic_interpreter::Interpreter;
+use std::path::Path;
+
+/// Run a program's source on a backend and return what it printed
+fn run(source: &str, backend: Backend) -> Result<String, String> {
+    let mut interpreter = Interpreter::with_config(Config {
+        backend,
+        deterministic: true,
+        ..Default::default()
+    });
+    for line in source.lines().filter(|line| !line.trim().is_empty()) {
+        interpreter.process_line(line)?;
+    }
+    interpreter.run()?;
+    Ok(interpreter.executor().get_output().to_string())
+}
+
+#[test]
+fn test_benchmark_programs_run() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/programs");
+    let mut count = 0;
+    for entry in std::fs::read_dir(directory).unwrap() {
+        let path = entry.unwrap().path();
+        let source = std::fs::read_to_string(&path).unwrap();
+        let tree =
+            run(&source, Backend::Tree).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        let bytecode = run(&source, Backend::Bytecode).unwrap();
+        assert_eq!(tree, bytecode, "{}", path.display());
+        count += 1;
+    }
+    assert_eq!(count, 10);
+}
+
+#[test]
+fn test_pcw_loops_count_to_1000() {
+    for n in 2..=8 {
+        let path = format!(
+            "{}/benches/programs/pcw{}.bas",
+            env!("CARGO_MANIFEST_DIR"),
+            n
+        );
+        let source = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            run(&source, Backend::Bytecode).unwrap(),
+            "S\nE1000\n",
+            "{}",
+            path
+        );
+    }
+}
+
+#[test]
+fn test_sieve_finds_primes() {
+    let source = include_str!("../benches/programs/sieve.bas");
+    assert_eq!(
+        run(source, Backend::Bytecode).unwrap().trim(),
+        "1027 primes"
+    );
+}
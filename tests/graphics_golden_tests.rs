@@ -0,0 +1,218 @@
+//! Golden-image tests for the headless graphics framebuffer
+//!
+//! Each test draws with BBC BASIC statements and compares the framebuffer
+//! checksum against a known-good value. Set BBC_GOLDEN_DIR to write the
+//! images out as PPM files when a checksum needs to be re-inspected.
+
+#![cfg(feature = "graphics")]
+
+use bbc_basic_interpreter::executor::Executor;
+use bbc_basic_interpreter::graphics::Canvas;
+use bbc_basic_interpreter::parser::parse_statement;
+use bbc_basic_interpreter::tokenizer::tokenize;
+
+/// Checksum of an empty 1280x1024 framebuffer
+const BLANK: u64 = 0xa555_a11a_193b_162a;
+
+/// Helper to execute a BBC BASIC line
+fn execute_line(executor: &mut Executor, line: &str) {
+    let tokens = tokenize(line).unwrap();
+    let statement = parse_statement(&tokens).unwrap();
+    executor.execute_statement(&statement).unwrap();
+}
+
+/// Run a sequence of lines on a fresh executor
+fn draw(lines: &[&str]) -> Executor {
+    let mut executor = Executor::new();
+    for line in lines {
+        execute_line(&mut executor, line);
+    }
+    executor
+}
+
+/// Compare the framebuffer against a golden checksum
+fn assert_golden(executor: &Executor, name: &str, expected: u64) {
+    let graphics = executor.graphics();
+    if let Ok(dir) = std::env::var("BBC_GOLDEN_DIR") {
+        let path = std::path::Path::new(&dir).join(format!("{}.ppm", name));
+        graphics.save_ppm(path).unwrap();
+    }
+    assert_eq!(
+        graphics.checksum(),
+        expected,
+        "framebuffer for '{}' differs from golden image (got {:#018x})",
+        name,
+        graphics.checksum()
+    );
+}
+
+/// Count set pixels inside a rectangle (inclusive bounds)
+fn count_pixels(executor: &Executor, x0: i32, y0: i32, x1: i32, y1: i32) -> usize {
+    let graphics = executor.graphics();
+    let mut count = 0;
+    for y in y0..=y1 {
+        for x in x0..=x1 {
+            if graphics.get_pixel(x, y) == Some(true) {
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+#[test]
+fn test_golden_blank() {
+    let executor = draw(&["10 CLG"]);
+    assert_golden(&executor, "blank", BLANK);
+}
+
+#[test]
+fn test_golden_plot_0_relative_move() {
+    let executor = draw(&["10 MOVE 100, 100", "20 PLOT 0, 50, 25"]);
+    assert_eq!(executor.graphics().get_position(), (150, 125));
+    assert_golden(&executor, "plot_0", BLANK);
+}
+
+#[test]
+fn test_golden_plot_1_relative_line() {
+    let executor = draw(&["10 MOVE 100, 100", "20 PLOT 1, 100, 0"]);
+    assert_eq!(count_pixels(&executor, 0, 0, 1279, 1023), 101);
+    assert_eq!(count_pixels(&executor, 100, 100, 200, 100), 101);
+    assert_golden(&executor, "plot_1", 0x2932_687a_ec47_0671);
+}
+
+#[test]
+fn test_golden_plot_2_relative_inverse() {
+    // Inverting across an existing line leaves the crossing point clear
+    let executor = draw(&[
+        "10 MOVE 100, 150",
+        "20 DRAW 300, 150",
+        "30 MOVE 200, 100",
+        "40 PLOT 2, 0, 100",
+    ]);
+    assert_eq!(executor.graphics().get_pixel(200, 150), Some(false));
+    assert_eq!(executor.graphics().get_pixel(200, 149), Some(true));
+    assert_golden(&executor, "plot_2", 0x5345_589e_086f_d0b5);
+}
+
+#[test]
+fn test_golden_plot_3_relative_background() {
+    // Drawing in the background colour erases part of a line
+    let executor = draw(&[
+        "10 MOVE 100, 100",
+        "20 DRAW 300, 100",
+        "30 MOVE 150, 100",
+        "40 PLOT 3, 50, 0",
+    ]);
+    assert_eq!(count_pixels(&executor, 150, 100, 200, 100), 0);
+    assert_eq!(count_pixels(&executor, 100, 100, 300, 100), 150);
+    assert_golden(&executor, "plot_3", 0x54a1_29bc_5f7b_88c4);
+}
+
+#[test]
+fn test_golden_plot_4_absolute_move() {
+    let executor = draw(&["10 MOVE 100, 100", "20 PLOT 4, 50, 25"]);
+    assert_eq!(executor.graphics().get_position(), (50, 25));
+    assert_golden(&executor, "plot_4", BLANK);
+}
+
+#[test]
+fn test_golden_plot_5_absolute_line() {
+    let executor = draw(&["10 PLOT 4, 100, 100", "20 PLOT 5, 200, 200"]);
+    assert_eq!(count_pixels(&executor, 0, 0, 1279, 1023), 101);
+    assert_eq!(executor.graphics().get_pixel(150, 150), Some(true));
+    assert_golden(&executor, "plot_5", 0x0458_e492_2037_9ab1);
+}
+
+#[test]
+fn test_golden_plot_6_absolute_inverse() {
+    let executor = draw(&[
+        "10 MOVE 100, 150",
+        "20 DRAW 300, 150",
+        "30 MOVE 200, 100",
+        "40 PLOT 6, 200, 200",
+    ]);
+    assert_eq!(executor.graphics().get_pixel(200, 150), Some(false));
+    assert_golden(&executor, "plot_6", 0x5345_589e_086f_d0b5);
+}
+
+#[test]
+fn test_golden_plot_7_absolute_background() {
+    let executor = draw(&[
+        "10 MOVE 100, 100",
+        "20 DRAW 300, 100",
+        "30 MOVE 150, 100",
+        "40 PLOT 7, 200, 100",
+    ]);
+    assert_eq!(count_pixels(&executor, 150, 100, 200, 100), 0);
+    assert_golden(&executor, "plot_7", 0x54a1_29bc_5f7b_88c4);
+}
+
+#[test]
+fn test_golden_plot_point_modes() {
+    // PLOT 64-67 are relative, 68-71 absolute; 64/68 only move
+    let executor = draw(&[
+        "10 MOVE 10, 10",
+        "20 PLOT 64, 10, 0",
+        "30 PLOT 65, 10, 0",
+        "40 PLOT 69, 40, 10",
+        "50 PLOT 69, 50, 10",
+        "60 PLOT 68, 60, 10",
+    ]);
+    assert_eq!(executor.graphics().get_pixel(20, 10), Some(false));
+    assert_eq!(executor.graphics().get_pixel(30, 10), Some(true));
+    assert_eq!(executor.graphics().get_pixel(40, 10), Some(true));
+    assert_eq!(executor.graphics().get_pixel(50, 10), Some(true));
+    assert_eq!(executor.graphics().get_pixel(60, 10), Some(false));
+    assert_eq!(count_pixels(&executor, 0, 0, 1279, 1023), 3);
+    assert_golden(&executor, "plot_64_69", 0x3fd6_b90d_acc1_64c4);
+}
+
+#[test]
+fn test_golden_plot_point_inverse_and_background() {
+    let executor = draw(&[
+        "10 PLOT 69, 10, 10",
+        "20 PLOT 69, 20, 10",
+        "30 PLOT 70, 10, 10",
+        "40 PLOT 71, 20, 10",
+        "50 PLOT 70, 30, 10",
+        "60 PLOT 66, 10, 0",
+        "70 PLOT 67, 10, 0",
+    ]);
+    assert_eq!(executor.graphics().get_pixel(10, 10), Some(false));
+    assert_eq!(executor.graphics().get_pixel(20, 10), Some(false));
+    assert_eq!(executor.graphics().get_pixel(30, 10), Some(true));
+    assert_eq!(executor.graphics().get_pixel(40, 10), Some(true));
+    assert_eq!(executor.graphics().get_pixel(50, 10), Some(false));
+    assert_golden(&executor, "plot_70_71", 0x8f94_2fe7_02b9_73a4);
+}
+
+#[test]
+fn test_golden_plot_triangle() {
+    let executor = draw(&[
+        "10 MOVE 100, 100",
+        "20 PLOT 132, 300, 100",
+        "30 PLOT 132, 200, 300",
+    ]);
+    assert_eq!(executor.graphics().get_pixel(200, 150), Some(true));
+    assert_eq!(executor.graphics().get_pixel(100, 300), Some(false));
+    assert_golden(&executor, "plot_132", 0xabc5_3cfd_ca11_24bd);
+}
+
+#[test]
+fn test_golden_move_draw_circle() {
+    let executor = draw(&[
+        "10 CLG",
+        "20 GCOL 0, 255",
+        "30 MOVE 400, 400",
+        "40 DRAW 600, 400",
+        "50 DRAW 600, 600",
+        "60 DRAW 400, 600",
+        "70 DRAW 400, 400",
+        "80 CIRCLE 500, 500, 100",
+    ]);
+    assert_eq!(executor.graphics().get_pixel(600, 500), Some(true));
+    assert_eq!(executor.graphics().get_pixel(500, 600), Some(true));
+    assert_eq!(executor.graphics().get_pixel(500, 500), Some(false));
+    assert_golden(&executor, "move_draw_circle", 0x29b4_fbdd_590c_eaf2);
+}
@@ -1,3 +1,5 @@
+#![cfg(feature = "graphics")]
+
 use bbc_basic_interpreter::executor::Executor;
 use bbc_basic_interpreter::parser::parse_statement;
 use bbc_basic_interpreter::tokenizer::tokenize;
@@ -0,0 +1,219 @@
+//! Conformance tests for 32 bit integer arithmetic, after BBC BASIC II
+
+use bbc_basic_interpreter::interpreter::Interpreter;
+
+/// Run a program on both backends, check they agree and return the output
+fn run(lines: &[&str]) -> Result<String, String> {
+    let mut outputs = Vec::new();
+    for backend in ["tree", "vm"] {
+        let mut interpreter = Interpreter::new();
+        interpreter.configure("backend", backend).unwrap();
+        for line in lines {
+            interpreter.process_line(line)?;
+        }
+        let result = interpreter.run();
+        outputs.push(result.map(|_| interpreter.executor().get_output().to_string()));
+    }
+    assert_eq!(outputs[0], outputs[1], "backends disagree");
+    outputs.remove(0)
+}
+
+/// What a program prints for each of a list of expressions, one per line,
+/// without the spaces that right-justify them in their fields
+fn values(expressions: &[&str]) -> Vec<String> {
+    let lines: Vec<String> = expressions
+        .iter()
+        .enumerate()
+        .map(|(i, expression)| format!("{} PRINT {}", (i + 1) * 10, expression))
+        .collect();
+    let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+    let output = run(&lines).unwrap();
+    output
+        .lines()
+        .map(|line| line.trim_start().to_string())
+        .collect()
+}
+
+#[test]
+fn test_addition_wraps() {
+    let output = run(&[
+        "10 A% = &7FFFFFFF",
+        "20 A% = A% + 1",
+        "30 PRINT A%",
+        "40 A% = A% - 1",
+        "50 PRINT A%",
+        "60 B% = -A%",
+        "70 PRINT B%",
+        "80 B% = B% - 1",
+        "90 PRINT -B%",
+    ])
+    .unwrap();
+    assert_eq!(
+        output,
+        "-2147483648\n2147483647\n-2147483647\n-2147483648\n"
+    );
+}
+
+#[test]
+fn test_hex_constants_and_printing() {
+    assert_eq!(
+        values(&[
+            "&FFFFFFFF",
+            "&80000000",
+            "~255",
+            "~-1",
+            "~&80000000",
+            "~0",
+            "~&7FFFFFFF + 1",
+            "~2.75",
+        ]),
+        [
+            "-1",
+            "-2147483648",
+            "FF",
+            "FFFFFFFF",
+            "80000000",
+            "0",
+            "80000000",
+            "2"
+        ]
+    );
+    let output = run(&["10 A% = 4096", "20 PRINT \"&\";~A%;\" \";A%"]).unwrap();
+    assert_eq!(output, "&1000 4096\n");
+}
+
+#[test]
+fn test_truth_values_are_bitwise() {
+    assert_eq!(
+        values(&[
+            "TRUE",
+            "FALSE",
+            "NOT 0",
+            "NOT TRUE",
+            "NOT 5",
+            "NOT &7FFFFFFF",
+            "TRUE AND 7",
+            "FALSE OR 7",
+        ]),
+        ["-1", "0", "-1", "0", "-6", "-2147483648", "7", "7"]
+    );
+}
+
+#[test]
+fn test_logical_operators() {
+    assert_eq!(
+        values(&[
+            "5 AND 3",
+            "5 OR 3",
+            "5 EOR 3",
+            "-1 AND &FF",
+            "&F0 OR &0F",
+            "&FFFFFFFF EOR &0F0F0F0F",
+            "&80000000 AND &80000001",
+            "(1 < 2) AND (3 = 3)",
+            "(1 < 2) AND (3 = 4)",
+            "(1 > 2) OR (3 <> 4)",
+        ]),
+        [
+            "1",
+            "7",
+            "6",
+            "255",
+            "255",
+            "-252645136",
+            "-2147483648",
+            "-1",
+            "0",
+            "-1"
+        ]
+    );
+}
+
+#[test]
+fn test_comparisons_are_signed() {
+    assert_eq!(
+        values(&[
+            "1 < 2",
+            "2 < 1",
+            "&80000000 < 0",
+            "&7FFFFFFF > &80000000",
+            "&FFFFFFFF = -1",
+            "3 <= 3",
+            "3 >= 4",
+        ]),
+        ["-1", "0", "-1", "-1", "-1", "-1", "0"]
+    );
+}
+
+#[test]
+fn test_string_comparisons() {
+    // Strings compare character code by character code, and a string is
+    // less than any longer one it starts
+    assert_eq!(
+        values(&[
+            "\"APPLE\" < \"BANANA\"",
+            "\"ab\" <= \"a\"",
+            "\"a\" > \"Z\"",
+            "\"\" < \"A\"",
+            "CHR$(200) > \"z\"",
+            "CHR$(96) < \"a\"",
+            "\"APPLE\" = \"APPLE\"",
+            "\"APPLE\" <> \"apple\"",
+        ]),
+        ["-1", "0", "-1", "-1", "-1", "-1", "-1", "-1"]
+    );
+    let output = run(&[
+        "10 A$ = \"APPLE\"",
+        "20 N% = 0",
+        "30 WHILE A$ < \"BANANA\" AND N% < 5",
+        "40 N% = N% + 1",
+        "50 ENDWHILE",
+        "60 E% = A$ = \"APPLE\"",
+        "70 IF \"Z\" > A$ THEN PRINT N%: PRINT E%",
+    ])
+    .unwrap();
+    assert_eq!(output, "         5\n        -1\n");
+    // A string and a number do not compare
+    let error = run(&["10 PRINT \"A\" < 1"]).unwrap_err();
+    assert!(error.contains("Type mismatch"), "{}", error);
+}
+
+#[test]
+fn test_division_and_truncation() {
+    assert_eq!(
+        values(&[
+            "7 DIV 2",
+            "-7 DIV 2",
+            "7 MOD -2",
+            "-7 MOD 2",
+            "&80000000 DIV -1"
+        ]),
+        ["3", "-3", "1", "-1", "-2147483648"]
+    );
+    let output = run(&[
+        "10 A% = 2.7",
+        "20 PRINT A%",
+        "30 A% = -2.7",
+        "40 PRINT A%",
+        "50 X = 2147483647.5",
+        "60 A% = X",
+        "70 PRINT A%",
+    ])
+    .unwrap();
+    assert_eq!(output, "         2\n        -2\n2147483647\n");
+}
+
+#[test]
+fn test_out_of_range_is_too_big() {
+    for program in [
+        &["10 A% = 3000000000.0"][..],
+        &["10 X = -2147483649.0", "20 A% = X"],
+        &["10 A% = 65536 * 65536"],
+        &["10 A% = 2 ^ 31"],
+    ] {
+        let error = run(program).unwrap_err();
+        assert!(error.contains("Too big"), "{:?}: {}", program, error);
+    }
+    // Products too big for an integer are worked out as reals
+    assert_eq!(values(&["65536 * 65536"]), ["4.2949673E9"]);
+}
@@ -0,0 +1,166 @@
+//! Tests that malformed lines come back from the tokenizer and parser as
+//! errors, never as panics, the same checks the fuzz target makes
+
+use bbc_basic_interpreter::error::BBCBasicError;
+use bbc_basic_interpreter::interpreter::Interpreter;
+use bbc_basic_interpreter::parser::{
+    parse_expression, parse_statement, parse_statement_with_dialect, unparse, Dialect, MAX_NESTING,
+};
+use bbc_basic_interpreter::tokenizer::{
+    classify, classify_with_options, detokenize, detokenize_compact, tokenize,
+    tokenize_with_options, KeywordCase, TokenizerOptions,
+};
+use quickcheck::QuickCheck;
+use std::path::Path;
+
+/// Pieces of BASIC that arbitrary lines are made of
+const FRAGMENTS: &[&str] = &[
+    "PRINT", "IF", "THEN", "ELSE", "FOR", "TO", "STEP", "NEXT", "DIM", "DEF", "FN", "PROC", "GOTO",
+    "ON", "ERROR", "LOCAL", "DATA", "READ", "REM", "VDU", "PLOT", "SOUND", "CASE", "OF", "WHEN",
+    "WHILE", "MID$", "LEFT$(", "TAB(", "NOT", "AND", "MOD", "PTR", "#", "(", ")", ",", ";", ":",
+    "'", "\"", "\"x\"", "=", "+", "-", "*", "^", "<", ">", "?", "!", "$", "|", "~", "@%", "&",
+    "&FF", "%", ".", "1", "1E", "2.5", "65535", "99999 ", "A", "B%", "C$", " ", "é",
+];
+
+/// Put a line through everything the tokenizer and parser offer
+fn exercise(text: &str) {
+    let strict = TokenizerOptions {
+        case_insensitive_keywords: false,
+        keyword_case: KeywordCase::Lower,
+    };
+    classify(text);
+    classify_with_options(text, &strict);
+    let _ = tokenize_with_options(text, &strict);
+
+    let Ok(line) = tokenize(text) else {
+        return;
+    };
+    let _ = detokenize(&line);
+    let _ = detokenize_compact(&line, &strict);
+    let _ = parse_expression(&line.tokens);
+    for dialect in [Dialect::BasicII, Dialect::BasicV] {
+        if let Ok(statement) = parse_statement_with_dialect(&line, dialect) {
+            if let Ok(again) = tokenize(&unparse(&statement)) {
+                let _ = parse_statement_with_dialect(&again, dialect);
+            }
+        }
+    }
+}
+
+/// The error a line gives when tokenized and parsed
+fn parse_error(text: &str) -> Option<BBCBasicError> {
+    parse_statement(&tokenize(text).unwrap()).err()
+}
+
+#[test]
+fn test_fuzz_corpus() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/corpus/parse_line");
+    let mut seeds = 0;
+    for entry in std::fs::read_dir(corpus).unwrap() {
+        let bytes = std::fs::read(entry.unwrap().path()).unwrap();
+        exercise(&String::from_utf8_lossy(&bytes));
+        seeds += 1;
+    }
+    assert!(seeds > 0);
+}
+
+#[test]
+fn test_arbitrary_lines() {
+    fn property(text: String) -> bool {
+        exercise(&text);
+        true
+    }
+    QuickCheck::new()
+        .tests(500)
+        .quickcheck(property as fn(String) -> bool);
+}
+
+#[test]
+fn test_lines_of_fragments() {
+    fn property(pieces: Vec<u8>) -> bool {
+        let text: String = pieces
+            .iter()
+            .map(|&piece| FRAGMENTS[usize::from(piece) % FRAGMENTS.len()])
+            .collect();
+        exercise(&text);
+        true
+    }
+    QuickCheck::new()
+        .tests(1000)
+        .quickcheck(property as fn(Vec<u8>) -> bool);
+}
+
+#[test]
+fn test_nesting_is_limited() {
+    let deep = MAX_NESTING * 10;
+    for text in [
+        format!("A = {}1{}", "(".repeat(deep), ")".repeat(deep)),
+        format!("A = {}1{}", "ABS(".repeat(deep), ")".repeat(deep)),
+        format!("A = {}1", "-".repeat(deep)),
+        format!("A = {}1", "NOT ".repeat(deep)),
+        format!("A = {}1", "1 + ".repeat(deep)),
+        format!("{}PRINT", "IF 1 THEN ".repeat(deep)),
+    ] {
+        match parse_error(&text) {
+            Some(BBCBasicError::SyntaxError { message, .. }) => assert_eq!(message, "Too complex"),
+            other => panic!("{:?} for {}", other, &text[..20]),
+        }
+    }
+
+    // Anything a BBC Micro line could hold still parses, and the limit
+    // applies afresh to each line
+    for text in [
+        format!("A = {}1{}", "(".repeat(100), ")".repeat(100)),
+        format!("A = {}1", "1 + ".repeat(60)),
+        format!("{}PRINT", "IF 1 THEN ".repeat(25)),
+    ] {
+        assert_eq!(parse_error(&text), None);
+    }
+
+    let mut interpreter = Interpreter::new();
+    let line = format!("10 A = {}1", "-".repeat(deep));
+    interpreter.process_line(&line).unwrap();
+    let error = interpreter.run().unwrap_err();
+    assert!(error.contains("Too complex"), "{}", error);
+}
+
+#[test]
+fn test_trailing_text_is_an_error() {
+    for text in [
+        "A=1 GARBAGE",
+        "PRINT 1 2",
+        "PRINT \"A\" \"B\"",
+        "RESTORE ERROR DATA",
+        "GOTO 10 20",
+        "GOSUB 10 X",
+        "ON X GOTO 10 20",
+        "ON ERROR OFF X",
+        "DIM A(3) B",
+        "READ A B",
+        "INPUT A B",
+        "NEXT I J",
+        "LOCAL A B",
+        "PROCa(1) X",
+        "DEF PROCa(X) Y",
+        "END X",
+        "CLS 1",
+    ] {
+        assert!(
+            matches!(parse_error(text), Some(BBCBasicError::SyntaxError { .. })),
+            "{}",
+            text
+        );
+    }
+
+    // Lists, colons and comments still end statements
+    for text in [
+        "NEXT I,J",
+        "READ A,B",
+        "DIM A(3),B(2)",
+        "ON X GOTO 10,20",
+        "X%=1:REM start",
+        "GOSUB 90 ' done",
+    ] {
+        assert_eq!(parse_error(text), None, "{}", text);
+    }
+}
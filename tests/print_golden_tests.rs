@@ -0,0 +1,113 @@
+//! Golden-output tests for PRINT's separators
+//!
+//! Each test runs a program on both backends and compares what it printed
+//! against a transcript of the same program on a BBC Micro: `;` joins items
+//! and keeps the cursor on the line, `,` moves to the next field as wide as
+//! the low byte of @%, and `'` starts a new line. Only a trailing `;` or
+//! `,` leaves out the new line PRINT ends with. Numbers are right-justified
+//! in the field, except from a `;` up to the next `,`.
+
+use bbc_basic_interpreter::config::{Backend, Config};
+use bbc_basic_interpreter::Interpreter;
+
+/// Run a program on each backend, checking it prints the transcript
+fn assert_transcript(lines: &[&str], transcript: &str) {
+    for backend in [Backend::Tree, Backend::Bytecode] {
+        let mut interpreter = Interpreter::with_config(Config {
+            backend,
+            ..Default::default()
+        });
+        for line in lines {
+            interpreter.process_line(line).unwrap();
+        }
+        interpreter.run().unwrap();
+        assert_eq!(
+            interpreter.executor().get_output(),
+            transcript,
+            "{:?} backend",
+            backend
+        );
+    }
+}
+
+#[test]
+fn test_golden_semicolons() {
+    assert_transcript(
+        &[
+            "10 PRINT \"ONE\";\"TWO\"",
+            "20 PRINT \"THREE\";",
+            "30 PRINT \"FOUR\"",
+            "40 PRINT \"FIVE\";'\"SIX\"",
+        ],
+        "ONETWO\nTHREEFOUR\nFIVE\nSIX\n",
+    );
+}
+
+#[test]
+fn test_golden_apostrophes() {
+    assert_transcript(
+        &[
+            "10 PRINT \"A\"'\"B\"",
+            "20 PRINT \"C\"''\"D\"",
+            "30 PRINT'\"E\"",
+            "40 PRINT \"F\"'",
+            "50 PRINT \"G\": PRINT '\"H\"",
+            "60 IF TRUE THEN PRINT \"I\"'\"J\" ELSE PRINT \"K\"",
+        ],
+        "A\nB\nC\n\nD\n\nE\nF\n\nG\n\nH\nI\nJ\n",
+    );
+}
+
+#[test]
+fn test_golden_commas() {
+    assert_transcript(
+        &[
+            "10 PRINT \"A\",\"B\",\"C\"",
+            "20 PRINT \"LONGER\",",
+            "30 PRINT \"JOINED\"",
+            "40 PRINT \"X\",'\"Y\"",
+            "50 @% = 5",
+            "60 PRINT \"AB\",\"C\",",
+            "70 PRINT \"D\"",
+        ],
+        concat!(
+            "A         B         C\n",
+            "LONGER    JOINED\n",
+            "X         \n",
+            "Y\n",
+            "AB   C    D\n",
+        ),
+    );
+}
+
+#[test]
+fn test_golden_numbers() {
+    assert_transcript(
+        &[
+            "10 PRINT 1",
+            "20 PRINT 1;2",
+            "30 PRINT 1,2'3",
+            "40 PRINT -5;\"X\";1.5",
+            "50 PRINT \"AB\",3",
+            "60 PRINT ;7",
+            "62 PRINT SQR(2), SQR(10000)",
+            "64 PRINT ~@%",
+            "66 PRINT 1E10;\" \";1.5E-3",
+            "70 @% = &20205",
+            "80 PRINT 3.14159",
+        ],
+        concat!(
+            "         1\n",
+            "         12\n",
+            "         1         2\n",
+            "         3\n",
+            "        -5X1.5\n",
+            "AB                 3\n",
+            "7\n",
+            "1.41421356       100\n",
+            "       90A\n",
+            "      1E10 1.5E-3\n",
+            " 3.14\n",
+        ),
+    );
+}
@@ -0,0 +1,114 @@
+//! Tests for SOUND/ENVELOPE capture through the offline audio backend
+
+#![cfg(feature = "sound")]
+
+use bbc_basic_interpreter::executor::Executor;
+use bbc_basic_interpreter::parser::parse_statement;
+use bbc_basic_interpreter::sound::{Amplitude, SoundCommand, SAMPLE_RATE};
+use bbc_basic_interpreter::tokenizer::tokenize;
+
+/// Helper to execute a BBC BASIC line
+fn execute_line(executor: &mut Executor, line: &str) {
+    let tokens = tokenize(line).unwrap();
+    let statement = parse_statement(&tokens).unwrap();
+    executor.execute_statement(&statement).unwrap();
+}
+
+#[test]
+fn test_sound_statements_are_captured() {
+    let mut executor = Executor::new();
+
+    execute_line(&mut executor, "10 SOUND 1, -15, 53, 20");
+    execute_line(&mut executor, "20 SOUND 1, -15, 69, 10");
+    execute_line(&mut executor, "30 SOUND 2, -10, 89, 20");
+
+    let sound = executor.sound();
+    assert_eq!(sound.notes(1).len(), 2);
+    assert_eq!(sound.notes(2).len(), 1);
+    assert_eq!(sound.notes(1)[1].pitch, 69);
+
+    // Channel 1 plays for 1.5 seconds, which sets the overall length
+    let samples = sound.render();
+    assert_eq!(samples.len(), SAMPLE_RATE as usize * 3 / 2);
+}
+
+#[test]
+fn test_sound_capture_is_deterministic() {
+    let program = [
+        "10 ENVELOPE 1, 2, 1, -1, 0, 4, 4, 0, 60, -2, 0, -8, 126, 80",
+        "20 SOUND 1, 1, 53, 10",
+        "30 SOUND 0, -12, 6, 5",
+    ];
+
+    let render = || {
+        let mut executor = Executor::new();
+        for line in program {
+            execute_line(&mut executor, line);
+        }
+        executor.sound().to_wav()
+    };
+
+    let first = render();
+    assert_eq!(first, render());
+    assert!(first.len() > 44);
+}
+
+/// Evaluate an expression with PRINT and return what was printed
+fn print_value(executor: &mut Executor, expression: &str) -> String {
+    executor.clear_output();
+    execute_line(executor, &format!("PRINT {}", expression));
+    executor.get_output().trim().to_string()
+}
+
+#[test]
+fn test_adval_reports_free_queue_places() {
+    let mut executor = Executor::new();
+    assert_eq!(print_value(&mut executor, "ADVAL(-6)"), "4");
+
+    execute_line(&mut executor, "20 SOUND 1, -15, 53, 100");
+    execute_line(&mut executor, "30 SOUND 1, -15, 69, 100");
+    execute_line(&mut executor, "40 SOUND 1, -15, 89, 100");
+    assert_eq!(print_value(&mut executor, "ADVAL(-6)"), "2");
+    assert_eq!(print_value(&mut executor, "ADVAL(-5)"), "4");
+
+    // Flushing channel 1 leaves just the new note waiting
+    execute_line(&mut executor, "60 SOUND &11, -15, 101, 100");
+    assert_eq!(print_value(&mut executor, "ADVAL(-6)"), "4");
+    assert_eq!(executor.sound().notes(1).last().unwrap().pitch, 101);
+}
+
+#[test]
+fn test_embedder_sound_api() {
+    let mut executor = Executor::new();
+    execute_line(&mut executor, "10 SOUND 1, -15, 53, 20");
+    // Notes queued through the API join those from SOUND statements
+    let note = SoundCommand::note(1, Amplitude::Volume(15), 69, Some(10));
+    executor.sound_mut().enqueue(note).unwrap();
+    assert_eq!(executor.sound().notes(1).len(), 2);
+    assert_eq!(
+        executor.sound().render().len(),
+        SAMPLE_RATE as usize * 3 / 2
+    );
+}
+
+#[test]
+fn test_sound_bad_amplitude() {
+    let mut executor = Executor::new();
+    let tokens = tokenize("10 SOUND 1, -20, 53, 20").unwrap();
+    let statement = parse_statement(&tokens).unwrap();
+    assert!(executor.execute_statement(&statement).is_err());
+}
+
+#[test]
+fn test_save_wav() {
+    let mut executor = Executor::new();
+    execute_line(&mut executor, "10 SOUND 3, -15, 101, 4");
+
+    let path = std::env::temp_dir().join("bbc_basic_sound_test.wav");
+    executor.sound().save_wav(&path).unwrap();
+    let data = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).ok();
+
+    assert_eq!(&data[0..4], b"RIFF");
+    assert_eq!(data, executor.sound().to_wav());
+}
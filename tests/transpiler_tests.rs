@@ -0,0 +1,130 @@
+//! Tests that *COMPILE output builds with rustc and behaves like the program
+
+use bbc_basic_interpreter::program::ProgramStore;
+use bbc_basic_interpreter::tokenizer::tokenize;
+use bbc_basic_interpreter::transpiler::transpile;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Build a program from numbered source lines
+fn program(lines: &[&str]) -> ProgramStore {
+    let mut program = ProgramStore::new();
+    for line in lines {
+        program.store_line(tokenize(line).unwrap());
+    }
+    program
+}
+
+/// Transpile, compile with rustc and run, returning standard output
+fn compile_and_run(name: &str, lines: &[&str]) -> String {
+    let result = transpile(&program(lines)).unwrap();
+    assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+
+    let dir: PathBuf = std::env::temp_dir().join(format!("bbc_transpiler_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let source = dir.join(format!("{}.rs", name));
+    let binary = dir.join(name);
+    std::fs::write(&source, &result.source).unwrap();
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let status = Command::new(rustc)
+        .args(["--edition", "2021", "-O", "-o"])
+        .arg(&binary)
+        .arg(&source)
+        .status()
+        .expect("failed to run rustc");
+    assert!(
+        status.success(),
+        "generated source did not compile:\n{}",
+        result.source
+    );
+
+    let output = Command::new(&binary).output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_loops_and_conditions() {
+    let output = compile_and_run(
+        "loops",
+        &[
+            "10 T% = 0",
+            "20 FOR I% = 1 TO 10",
+            "30 T% = T% + I%",
+            "40 NEXT I%",
+            "50 PRINT \"Total \";T%",
+            "60 N = 1",
+            "70 REPEAT",
+            "80 N = N * 2",
+            "90 UNTIL N > 100",
+            "100 PRINT N",
+            "110 WHILE N > 1",
+            "120 N = N DIV 4",
+            "130 ENDWHILE",
+            "140 IF N = 0 THEN PRINT \"zero\" ELSE PRINT \"not zero\"",
+            "150 IF N < 5 THEN",
+            "160 PRINT \"small\"",
+            "170 ELSE",
+            "180 PRINT \"large\"",
+            "190 ENDIF",
+            "200 GOSUB 230",
+            "210 PRINT \"back\"",
+            "220 END",
+            "230 PRINT \"sub\"",
+            "240 RETURN",
+        ],
+    );
+    assert_eq!(output, "Total 55\n       128\nzero\nsmall\nsub\nback\n");
+}
+
+#[test]
+fn test_procedures_functions_and_arrays() {
+    let output = compile_and_run(
+        "procs",
+        &[
+            "10 DIM A%(5)",
+            "20 FOR I% = 0 TO 5",
+            "30 A%(I%) = FNsquare(I%)",
+            "40 NEXT",
+            "50 X = 7",
+            "60 PROCshow(\"Squares\", A%(5))",
+            "70 PRINT X",
+            "80 N$ = \"HELLO WORLD\"",
+            "90 PRINT LEFT$(N$, 5);\"-\";MID$(N$, 7, 3);\"-\";LEN(N$)",
+            "100 PRINT 1,2'\"A\",",
+            "105 PRINT \"B\"",
+            "110 END",
+            "120 DEF FNsquare(N%) = N% * N%",
+            "130 DEF PROCshow(T$, V%)",
+            "140 LOCAL X",
+            "150 X = 99",
+            "160 PRINT T$;\": \";V%",
+            "170 ENDPROC",
+        ],
+    );
+    assert_eq!(
+        output,
+        "Squares: 25\n         7\nHELLO-WOR-11\n         1         2\nA         B\n"
+    );
+}
+
+#[test]
+fn test_integer_arithmetic() {
+    let output = compile_and_run(
+        "integers",
+        &[
+            "10 A% = &7FFFFFFF",
+            "20 A% = A% + 1",
+            "30 PRINT A%",
+            "40 PRINT ~A%",
+            "50 PRINT ~-1",
+            "60 PRINT NOT 5",
+            "70 PRINT 5 EOR 3",
+        ],
+    );
+    assert_eq!(
+        output,
+        "-2147483648\n  80000000\n  FFFFFFFF\n        -6\n         6\n"
+    );
+}